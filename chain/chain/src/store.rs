@@ -2528,6 +2528,11 @@ impl<'a> ChainStoreUpdate<'a> {
             | DBCol::ColPeerComponent
             | DBCol::ColLastComponentNonce
             | DBCol::ColComponentEdges
+            | DBCol::ColEdgeStats
+            | DBCol::ColPeerCache
+            | DBCol::ColRouteBack
+            | DBCol::ColPeerQuality
+            | DBCol::ColRoutingAuditLog
             | DBCol::ColEpochInfo
             | DBCol::ColEpochStart
             | DBCol::ColEpochValidatorInfo
@@ -89,6 +89,10 @@ pub struct ClientActor {
     node_id: PeerId,
     /// Last time we announced our accounts as validators.
     last_validator_announce_time: Option<Instant>,
+    /// Epoch of our last announcement.
+    last_validator_announce_epoch: Option<EpochId>,
+    /// Last epoch for which validators were sent to the network.
+    last_epoch_validators_sent: Option<EpochId>,
     /// Info helper.
     info_helper: InfoHelper,
 
@@ -177,8 +181,11 @@ impl ClientActor {
                 sent_bytes_per_sec: 0,
                 known_producers: vec![],
                 peer_counter: 0,
+                routing_recalculation: Default::default(),
             },
             last_validator_announce_time: None,
+            last_validator_announce_epoch: None,
+            last_epoch_validators_sent: None,
             info_helper,
             block_production_next_attempt: now,
             block_production_started: false,
@@ -591,6 +598,10 @@ impl Handler<NetworkClientMessages> for ClientActor {
                 NetworkClientResponses::NoResponse
             }
             NetworkClientMessages::NetworkInfo(network_info) => {
+                self.client.state_sync.update_routes(&network_info.known_producers);
+                for (state_sync, _, _) in self.client.catchup_state_syncs.values_mut() {
+                    state_sync.update_routes(&network_info.known_producers);
+                }
                 self.network_info = network_info;
                 NetworkClientResponses::NoResponse
             }
@@ -677,6 +688,7 @@ impl Handler<Status> for ClientActor {
                 earliest_block_time,
             },
             validator_account_id,
+            routing_recalculation: self.network_info.routing_recalculation.clone(),
         })
     }
 }
@@ -736,27 +748,32 @@ impl ClientActor {
         };
 
         let now = Instant::now();
-        // Check that we haven't announced it too recently
+        // Announce AccountId if client is becoming a validator soon.
+        let next_epoch_id = unwrap_or_return!(self
+            .client
+            .runtime_adapter
+            .get_next_epoch_id_from_prev_block(&prev_block_hash));
+
+        // Check that we haven't announced it too recently. Announce right away once the epoch
+        // changes though, since peers expire announcements from past epochs.
         if let Some(last_validator_announce_time) = self.last_validator_announce_time {
             // Don't make announcement if have passed less than half of the time in which other peers
             // should remove our Account Id from their Routing Tables.
-            if 2 * (now - last_validator_announce_time) < self.client.config.ttl_account_id_router {
+            if self.last_validator_announce_epoch.as_ref() == Some(&next_epoch_id)
+                && 2 * (now - last_validator_announce_time)
+                    < self.client.config.ttl_account_id_router
+            {
                 return;
             }
         }
 
         debug!(target: "client", "Check announce account for {}, last announce time {:?}", validator_signer.validator_id(), self.last_validator_announce_time);
 
-        // Announce AccountId if client is becoming a validator soon.
-        let next_epoch_id = unwrap_or_return!(self
-            .client
-            .runtime_adapter
-            .get_next_epoch_id_from_prev_block(&prev_block_hash));
-
         // Check client is part of the futures validators
         if self.client.is_validator(&next_epoch_id, &prev_block_hash) {
             debug!(target: "client", "Sending announce account for {}", validator_signer.validator_id());
             self.last_validator_announce_time = Some(now);
+            self.last_validator_announce_epoch = Some(next_epoch_id.clone());
             let signature = self.sign_announce_account(&next_epoch_id).unwrap();
 
             self.network_adapter.do_send(NetworkRequests::AnnounceAccount(AnnounceAccount {
@@ -768,6 +785,65 @@ impl ClientActor {
         }
     }
 
+    /// Send validators of the current and next epoch to the network once per epoch, so that
+    /// announcements of accounts that are no longer validators get low priority, with the
+    /// validators of each shard in the current epoch.
+    fn check_send_epoch_validators(&mut self, prev_block_hash: CryptoHash) {
+        let epoch_id = unwrap_or_return!(self
+            .client
+            .runtime_adapter
+            .get_epoch_id_from_prev_block(&prev_block_hash));
+        if self.last_epoch_validators_sent.as_ref() == Some(&epoch_id) {
+            return;
+        }
+        let next_epoch_id = unwrap_or_return!(self
+            .client
+            .runtime_adapter
+            .get_next_epoch_id_from_prev_block(&prev_block_hash));
+
+        let mut epoch_validators = vec![];
+        for epoch_id in [&epoch_id, &next_epoch_id] {
+            let block_producers = unwrap_or_return!(self
+                .client
+                .runtime_adapter
+                .get_epoch_block_producers_ordered(epoch_id, &prev_block_hash));
+            epoch_validators.push(
+                block_producers
+                    .into_iter()
+                    .filter(|(_, is_slashed)| !is_slashed)
+                    .map(|(validator_stake, _)| validator_stake.take_account_id())
+                    .collect::<Vec<_>>(),
+            );
+        }
+        let num_shards = unwrap_or_return!(self.client.runtime_adapter.num_shards(&epoch_id));
+        let shard_validators = (0..num_shards)
+            .map(|shard_id| {
+                let shard_validators = epoch_validators[0]
+                    .iter()
+                    .filter(|account_id| {
+                        self.client.runtime_adapter.cares_about_shard(
+                            Some(account_id),
+                            &prev_block_hash,
+                            shard_id,
+                            false,
+                        )
+                    })
+                    .cloned()
+                    .collect();
+                (shard_id, shard_validators)
+            })
+            .collect();
+        let validators = epoch_validators.concat();
+
+        self.last_epoch_validators_sent = Some(epoch_id.clone());
+        self.network_adapter.do_send(NetworkRequests::SetEpochValidators {
+            epoch_id,
+            next_epoch_id,
+            validators,
+            shard_validators,
+        });
+    }
+
     /// Retrieves latest height, and checks if must produce next block.
     /// Otherwise wait for block arrival or suggest to skip after timeout.
     fn handle_block_production(&mut self) -> Result<(), Error> {
@@ -995,6 +1071,7 @@ impl ClientActor {
 
             self.info_helper.block_processed(gas_used);
             self.check_send_announce_account(last_final_hash);
+            self.check_send_epoch_validators(last_final_hash);
         }
     }
 
@@ -13,7 +13,8 @@ use rand::seq::{IteratorRandom, SliceRandom};
 use rand::{thread_rng, Rng};
 
 use near_chain::{Chain, RuntimeAdapter};
-use near_network::types::{AccountOrPeerIdOrHash, NetworkResponses, ReasonForBan};
+use near_network::routed_retrier::{RetryCandidate, RetryPolicy, RoutedRequestRetrier};
+use near_network::types::{AccountOrPeerIdOrHash, KnownProducer, NetworkResponses, ReasonForBan};
 use near_network::{FullPeerInfo, NetworkAdapter, NetworkRequests};
 use near_primitives::block::Tip;
 use near_primitives::hash::CryptoHash;
@@ -605,6 +606,11 @@ pub struct StateSync {
     last_part_id_requested: HashMap<(AccountOrPeerIdOrHash, ShardId), PendingRequestStatus>,
     /// Map from which part we requested to whom.
     requested_target: SizedCache<(u64, CryptoHash), AccountOrPeerIdOrHash>,
+    /// Retries of the state requests, keyed by shard, sync hash and part (`None` for the header).
+    request_retriers:
+        HashMap<(ShardId, CryptoHash, Option<u64>), RoutedRequestRetrier<AccountOrPeerIdOrHash>>,
+    /// Next hops towards the accounts state is requested from, as last reported by the network.
+    account_next_hops: Option<HashMap<AccountId, Vec<PeerId>>>,
 
     timeout: Duration,
 
@@ -623,12 +629,79 @@ impl StateSync {
             last_time_block_requested: None,
             last_part_id_requested: Default::default(),
             requested_target: SizedCache::with_size(MAX_PENDING_PART as usize),
+            request_retriers: Default::default(),
+            account_next_hops: None,
             timeout: Duration::from_std(timeout).unwrap(),
             state_parts_apply_results: HashMap::new(),
             split_state_roots: HashMap::new(),
         }
     }
 
+    /// Update the routes to the accounts state is requested from.
+    pub fn update_routes(&mut self, known_producers: &[KnownProducer]) {
+        self.account_next_hops = Some(
+            known_producers
+                .iter()
+                .filter_map(|producer| {
+                    Some((producer.account_id.clone(), producer.next_hops.clone()?))
+                })
+                .collect(),
+        );
+    }
+
+    /// Route to a state sync target, as far as known to the client. Peers with the highest
+    /// height are connected directly, accounts not announced to the network are unreachable.
+    fn next_hops(&self, target: &AccountOrPeerIdOrHash) -> Option<Vec<PeerId>> {
+        match target {
+            AccountOrPeerIdOrHash::PeerId(peer_id) => Some(vec![peer_id.clone()]),
+            AccountOrPeerIdOrHash::AccountId(account_id) => self
+                .account_next_hops
+                .as_ref()
+                .map(|routes| routes.get(account_id).cloned().unwrap_or_default()),
+            AccountOrPeerIdOrHash::Hash(_) | AccountOrPeerIdOrHash::ShardValidator(_) => None,
+        }
+    }
+
+    /// Retrier of the request of a part, or of the header if `part_id` is `None`.
+    fn request_retrier(
+        &mut self,
+        shard_id: ShardId,
+        sync_hash: CryptoHash,
+        part_id: Option<u64>,
+    ) -> &mut RoutedRequestRetrier<AccountOrPeerIdOrHash> {
+        let key = (shard_id, sync_hash, part_id);
+        // State sync never gives up, start over once the retries are exhausted.
+        if self.request_retriers.get(&key).map_or(false, |retrier| retrier.is_exhausted()) {
+            self.request_retriers.remove(&key).unwrap().failed();
+        }
+        self.request_retriers.entry(key).or_insert_with(|| {
+            let kind = if part_id.is_some() { "state_part" } else { "state_header" };
+            RoutedRequestRetrier::new(kind, RetryPolicy::default())
+        })
+    }
+
+    fn request_succeeded(
+        &mut self,
+        shard_id: ShardId,
+        sync_hash: CryptoHash,
+        part_id: Option<u64>,
+    ) {
+        if let Some(retrier) = self.request_retriers.remove(&(shard_id, sync_hash, part_id)) {
+            retrier.succeeded();
+        }
+    }
+
+    fn request_timed_out(
+        &mut self,
+        shard_id: ShardId,
+        sync_hash: CryptoHash,
+        part_id: Option<u64>,
+    ) {
+        if let Some(retrier) = self.request_retriers.get_mut(&(shard_id, sync_hash, part_id)) {
+            retrier.timed_out();
+        }
+    }
+
     pub fn sync_block_status(
         &mut self,
         prev_hash: &CryptoHash,
@@ -670,6 +743,17 @@ impl StateSync {
         state_parts_task_scheduler: &dyn Fn(ApplyStatePartsRequest),
         state_split_scheduler: &dyn Fn(StateSplitRequest),
     ) -> Result<(bool, bool), near_chain::Error> {
+        // Requests for another sync hash are not retried anymore.
+        let stale_retriers = self
+            .request_retriers
+            .keys()
+            .filter(|(_, hash, _)| hash != &sync_hash)
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in stale_retriers {
+            self.request_retriers.remove(&key).unwrap().failed();
+        }
+
         let mut all_done = true;
         let mut update_sync_status = false;
         let init_sync_download = ShardSyncDownload {
@@ -710,6 +794,7 @@ impl StateSync {
             match shard_sync_download.status {
                 ShardSyncStatus::StateDownloadHeader => {
                     if shard_sync_download.downloads[0].done {
+                        self.request_succeeded(shard_id, sync_hash, None);
                         let shard_state_header = chain.get_state_header(shard_id, sync_hash)?;
                         let state_num_parts =
                             get_num_state_parts(shard_state_header.state_root_node().memory_usage);
@@ -733,6 +818,9 @@ impl StateSync {
                         let prev = shard_sync_download.downloads[0].prev_update_time;
                         let error = shard_sync_download.downloads[0].error;
                         download_timeout = now - prev > self.timeout;
+                        if download_timeout {
+                            self.request_timed_out(shard_id, sync_hash, None);
+                        }
                         if download_timeout || error {
                             shard_sync_download.downloads[0].run_me.store(true, Ordering::SeqCst);
                             shard_sync_download.downloads[0].error = false;
@@ -745,12 +833,19 @@ impl StateSync {
                 }
                 ShardSyncStatus::StateDownloadParts => {
                     let mut parts_done = true;
-                    for part_download in shard_sync_download.downloads.iter_mut() {
-                        if !part_download.done {
+                    for (part_id, part_download) in
+                        shard_sync_download.downloads.iter_mut().enumerate()
+                    {
+                        if part_download.done {
+                            self.request_succeeded(shard_id, sync_hash, Some(part_id as u64));
+                        } else {
                             parts_done = false;
                             let prev = part_download.prev_update_time;
                             let error = part_download.error;
                             let part_timeout = now - prev > self.timeout;
+                            if part_timeout {
+                                self.request_timed_out(shard_id, sync_hash, Some(part_id as u64));
+                            }
                             if part_timeout || error {
                                 download_timeout |= part_timeout;
                                 part_download.run_me.store(true, Ordering::SeqCst);
@@ -1077,7 +1172,16 @@ impl StateSync {
 
         match shard_sync_download.status {
             ShardSyncStatus::StateDownloadHeader => {
-                let target = possible_targets.choose(&mut thread_rng()).cloned().unwrap();
+                let candidates = possible_targets
+                    .iter()
+                    .map(|target| RetryCandidate::new(target.clone(), self.next_hops(target)))
+                    .collect::<Vec<_>>();
+                let retrier = self.request_retrier(shard_id, sync_hash, None);
+                let target = match retrier.next_target(candidates) {
+                    Some(target) => target,
+                    // Wait for the backoff, `run_me` stays set so the request is retried.
+                    None => return Ok(shard_sync_download),
+                };
                 assert!(new_shard_sync_download.downloads[0].run_me.load(Ordering::SeqCst));
                 new_shard_sync_download.downloads[0].run_me.store(false, Ordering::SeqCst);
                 new_shard_sync_download.downloads[0].state_requests_count += 1;
@@ -1097,20 +1201,46 @@ impl StateSync {
                 );
             }
             ShardSyncStatus::StateDownloadParts => {
-                let possible_targets_sampler =
+                let mut possible_targets_sampler =
                     SamplerLimited::new(possible_targets, MAX_STATE_PART_REQUEST);
 
                 // Iterate over all parts that needs to be requested (i.e. download.run_me is true).
                 // Parts are ordered such that its index match its part_id.
                 // Finally, for every part that needs to be requested it is selected one peer (target) randomly
-                // to request the part from
-                for ((part_id, download), target) in new_shard_sync_download
+                // to request the part from. Retries are chosen by the retrier of the part instead,
+                // which prefers targets and routes not tried yet.
+                for (part_id, download) in new_shard_sync_download
                     .downloads
                     .iter_mut()
                     .enumerate()
                     .filter(|(_, download)| download.run_me.load(Ordering::SeqCst))
-                    .zip(possible_targets_sampler)
                 {
+                    let is_retry = download.state_requests_count > 0;
+                    let targets = if is_retry {
+                        possible_targets_sampler.available().to_vec()
+                    } else {
+                        match possible_targets_sampler.next() {
+                            Some(target) => vec![target],
+                            None => break,
+                        }
+                    };
+                    let candidates = targets
+                        .into_iter()
+                        .map(|target| {
+                            let next_hops = self.next_hops(&target);
+                            RetryCandidate::new(target, next_hops)
+                        })
+                        .collect::<Vec<_>>();
+                    let retrier = self.request_retrier(shard_id, sync_hash, Some(part_id as u64));
+                    let target = match retrier.next_target(candidates) {
+                        Some(target) => target,
+                        // Wait for the backoff or a reachable target, `run_me` stays set so the
+                        // part is retried.
+                        None => continue,
+                    };
+                    if is_retry {
+                        possible_targets_sampler.take(&target);
+                    }
                     self.sent_request_part(target.clone(), part_id as u64, shard_id, sync_hash);
                     download.run_me.store(false, Ordering::SeqCst);
                     download.state_requests_count += 1;
@@ -1232,6 +1362,24 @@ impl<T> SamplerLimited<T> {
     }
 }
 
+impl<T: PartialEq> SamplerLimited<T> {
+    /// Items which may still be sampled.
+    fn available(&self) -> &[T] {
+        &self.data
+    }
+
+    /// Use up one sample of an item chosen by the caller.
+    fn take(&mut self, item: &T) {
+        if let Some(ix) = self.data.iter().position(|x| x == item) {
+            self.limit[ix] -= 1;
+            if self.limit[ix] == 0 {
+                self.limit.swap_remove(ix);
+                self.data.swap_remove(ix);
+            }
+        }
+    }
+}
+
 impl<T: Clone> Iterator for SamplerLimited<T> {
     type Item = T;
 
@@ -1621,4 +1769,15 @@ mod test {
             blocks.iter().take(1).map(|b| *b.hash()).collect::<HashSet<_>>()
         );
     }
+
+    #[test]
+    fn test_sampler_limited_take() {
+        let mut sampler = SamplerLimited::new(vec![1, 2], 2);
+        sampler.take(&1);
+        assert_eq!(sampler.available(), &[1, 2]);
+        sampler.take(&1);
+        assert_eq!(sampler.available(), &[2]);
+        assert_eq!(sampler.by_ref().collect::<Vec<_>>(), vec![2, 2]);
+        assert!(sampler.available().is_empty());
+    }
 }
@@ -574,6 +574,7 @@ pub fn setup_mock_all_validators(
                             received_bytes_per_sec: 0,
                             known_producers: vec![],
                             peer_counter: 0,
+                            routing_recalculation: Default::default(),
                         };
                         client_addr.do_send(NetworkClientMessages::NetworkInfo(info));
                     }
@@ -962,15 +963,22 @@ pub fn setup_mock_all_validators(
                         | NetworkRequests::FetchRoutingTable
                         | NetworkRequests::PingTo(_, _)
                         | NetworkRequests::FetchPingPongInfo
+                        | NetworkRequests::SetEpochValidators { .. }
                         | NetworkRequests::BanPeer { .. }
                         | NetworkRequests::TxStatus(_, _, _)
                         | NetworkRequests::Query { .. }
                         | NetworkRequests::Challenge(_)
                         | NetworkRequests::RequestUpdateNonce(_, _)
                         | NetworkRequests::ResponseUpdateNonce(_)
-                        | NetworkRequests::ReceiptOutComeRequest(_, _) => {}
-                        #[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
-                        | NetworkRequests::IbfMessage { .. } => {}
+                        | NetworkRequests::ReceiptOutComeRequest(_, _)
+                        | NetworkRequests::IbfMessage { .. }
+                        | NetworkRequests::DeltaSyncRequest { .. }
+                        | NetworkRequests::DeltaSync { .. }
+                        | NetworkRequests::EdgeDigest { .. }
+                        | NetworkRequests::EdgePull { .. }
+                        | NetworkRequests::AnnounceAccountsWithHops { .. }
+                        | NetworkRequests::ProxyAnnouncement { .. }
+                        | NetworkRequests::SubKeyDelegations { .. } => {}
                     };
                 }
                 Box::new(Some(resp))
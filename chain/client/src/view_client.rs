@@ -67,6 +67,11 @@ const QUERY_REQUEST_LIMIT: usize = 500;
 const REQUEST_WAIT_TIME: u64 = 1000;
 
 const POISONED_LOCK_ERR: &str = "The lock was poisoned.";
+/// Max number of rejected account announcements that we keep.
+const REJECTED_ANNOUNCE_ACCOUNT_LIMIT: usize = 1_000;
+/// Time after which announcements of a rejected account are checked again, in case the account
+/// was created since.
+const REJECTED_ANNOUNCE_ACCOUNT_TTL: Duration = Duration::from_secs(600);
 
 /// Request and response manager across all instances of ViewClientActor.
 pub struct ViewClientRequestManager {
@@ -80,6 +85,9 @@ pub struct ViewClientRequestManager {
     pub query_responses: SizedCache<String, Result<QueryResponse, String>>,
     /// Receipt outcome requests
     pub receipt_outcome_requests: SizedCache<CryptoHash, Instant>,
+    /// Accounts and epochs of announcements rejected because the account doesn't exist or isn't
+    /// a validator of the epoch, with the time they were rejected.
+    pub rejected_announce_accounts: SizedCache<(AccountId, EpochId), Instant>,
 }
 
 #[cfg(feature = "test_features")]
@@ -113,6 +121,7 @@ impl ViewClientRequestManager {
             query_requests: SizedCache::with_size(QUERY_REQUEST_LIMIT),
             query_responses: SizedCache::with_size(QUERY_REQUEST_LIMIT),
             receipt_outcome_requests: SizedCache::with_size(QUERY_REQUEST_LIMIT),
+            rejected_announce_accounts: SizedCache::with_size(REJECTED_ANNOUNCE_ACCOUNT_LIMIT),
         }
     }
 }
@@ -498,6 +507,30 @@ impl ViewClientActor {
             .map_err(|e| e.into())
     }
 
+    /// Whether an announcement of `key` was rejected recently.
+    fn is_rejected_announce_account(&self, key: &(AccountId, EpochId)) -> bool {
+        let mut request_manager = self.request_manager.write().expect(POISONED_LOCK_ERR);
+        match request_manager.rejected_announce_accounts.cache_get(key) {
+            Some(rejected) => rejected.elapsed() < REJECTED_ANNOUNCE_ACCOUNT_TTL,
+            None => false,
+        }
+    }
+
+    fn reject_announce_account(&self, key: (AccountId, EpochId)) {
+        let mut request_manager = self.request_manager.write().expect(POISONED_LOCK_ERR);
+        request_manager.rejected_announce_accounts.cache_set(key, Instant::now());
+    }
+
+    /// Whether `account_id` exists at the head. Accounts of shards we don't track are assumed to
+    /// exist.
+    fn account_exists(&mut self, account_id: &AccountId) -> bool {
+        let query = Query::new(
+            BlockReference::latest(),
+            QueryRequest::ViewAccount { account_id: account_id.clone() },
+        );
+        !matches!(self.handle_query(query), Err(QueryError::UnknownAccount { .. }))
+    }
+
     fn get_height(&self, head: &Tip) -> BlockHeight {
         #[cfg(feature = "test_features")]
         {
@@ -1265,21 +1298,44 @@ impl Handler<NetworkViewClientMessages> for ViewClientActor {
             }
             NetworkViewClientMessages::AnnounceAccount(announce_accounts) => {
                 let mut filtered_announce_accounts = Vec::new();
+                let mut stale_announce_accounts = Vec::new();
 
                 for (announce_account, last_epoch) in announce_accounts {
                     // Keep the announcement if it is newer than the last announcement from
                     // the same account.
+                    let mut stale = false;
                     if let Some(last_epoch) = last_epoch {
                         match self
                             .runtime_adapter
                             .compare_epoch_id(&announce_account.epoch_id, &last_epoch)
                         {
                             Ok(Ordering::Greater) => {}
-                            _ => continue,
+                            Ok(Ordering::Less) | Ok(Ordering::Equal) => stale = true,
+                            // Unknown epochs may be ahead of our head.
+                            Err(_) => continue,
+                        }
+                    }
+
+                    let check_existence = self.config.check_announce_account_existence;
+                    let key =
+                        (announce_account.account_id.clone(), announce_account.epoch_id.clone());
+                    if check_existence {
+                        if self.is_rejected_announce_account(&key) {
+                            continue;
+                        }
+                        if !self.account_exists(&announce_account.account_id) {
+                            debug!(target: "view_client", "Dropping announcement of unknown account {}", announce_account.account_id);
+                            self.reject_announce_account(key);
+                            continue;
                         }
                     }
 
                     match self.check_signature_account_announce(&announce_account) {
+                        // Only signed stale announcements count against the peer, anyone can
+                        // forge an unsigned one.
+                        Ok(true) if stale => {
+                            stale_announce_accounts.push(announce_account);
+                        }
                         Ok(true) => {
                             filtered_announce_accounts.push(announce_account);
                         }
@@ -1290,12 +1346,21 @@ impl Handler<NetworkViewClientMessages> for ViewClientActor {
                         }
                         // Filter this account
                         Err(e) => {
+                            if let Error::Chain(err) = &e {
+                                if check_existence && matches!(err.kind(), ErrorKind::NotAValidator)
+                                {
+                                    self.reject_announce_account(key);
+                                }
+                            }
                             debug!(target: "view_client", "Failed to validate account announce signature: {}", e);
                         }
                     }
                 }
 
-                NetworkViewClientResponses::AnnounceAccount(filtered_announce_accounts)
+                NetworkViewClientResponses::AnnounceAccount {
+                    accounts: filtered_announce_accounts,
+                    stale: stale_announce_accounts,
+                }
             }
             NetworkViewClientMessages::EpochSyncRequest { epoch_id: _epoch_id } => {
                 // TODO #3488
@@ -1,5 +1,9 @@
+use std::path::PathBuf;
+
 #[cfg(feature = "ser_de")]
 use near_jsonrpc_primitives::errors::RpcError;
+#[cfg(feature = "ser_de")]
+use near_network::routing::RoutingTableSnapshot;
 use near_network::routing::{Edge, SimpleEdge};
 use near_primitives::network::PeerId;
 #[cfg(feature = "ser_de")]
@@ -37,3 +41,35 @@ pub struct SetAdvOptionsRequest {
 pub struct StartRoutingTableSyncRequest {
     pub peer_id: PeerId,
 }
+
+#[cfg_attr(feature = "ser_de", derive(Deserialize))]
+pub struct ExplainRouteRequest {
+    pub target: PeerId,
+}
+
+#[cfg_attr(feature = "ser_de", derive(Deserialize))]
+pub struct GetEdgeGossipStatsRequest {
+    pub limit: usize,
+}
+
+#[cfg_attr(feature = "ser_de", derive(Deserialize))]
+pub struct GetPeerScoresRequest {
+    pub limit: usize,
+}
+
+#[cfg_attr(feature = "ser_de", derive(Deserialize))]
+pub struct GetRoutingAuditLogRequest {
+    pub limit: usize,
+}
+
+#[cfg_attr(feature = "ser_de", derive(Deserialize))]
+pub struct DumpRoutingTableRequest {
+    pub path: PathBuf,
+}
+
+/// `RoutingTableSnapshot` only exists with `near-network/test_features`, enabled by `ser_de`.
+#[cfg(feature = "ser_de")]
+#[derive(Deserialize)]
+pub struct RestoreRoutingTableSnapshotRequest {
+    pub snapshot: RoutingTableSnapshot,
+}
@@ -1,4 +1,18 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use near_primitives::network::PeerId;
+use near_primitives::types::AccountId;
+
+/// Maximum number of pings sent by a single `EXPERIMENTAL_ping` request.
+pub const MAX_PING_COUNT: usize = 100;
+/// Maximum duration of a single `EXPERIMENTAL_ping` request in milliseconds, so it's answered
+/// before clients time out.
+pub const MAX_PING_DURATION_MS: u64 = 20_000;
 
 #[derive(Serialize, Debug)]
 pub struct RpcNetworkInfoResponse {
@@ -6,6 +20,51 @@ pub struct RpcNetworkInfoResponse {
     pub network_info_response: near_client_primitives::types::NetworkInfoResponse,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RpcRoutingDiagnosticsRequest {
+    /// For how long to log verbose routing diagnostics, zero turns them off.
+    pub duration_secs: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RpcRoutingDiagnosticsResponse {
+    /// Seconds until routing diagnostics are turned off again, zero if they are off.
+    pub remaining_secs: u64,
+}
+
+/// Write the routing table to a file on the node, to debug routing problems offline.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RpcDumpRoutingTableRequest {
+    /// Path of the dump on the node, Borsh encoded, or JSON with a `.json` extension on nodes
+    /// built with `test_features`.
+    pub path: PathBuf,
+}
+
+/// Pings sent to a peer through the routed network. The peer is either given directly, or is the
+/// peer which announced the account.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RpcPingRequest {
+    pub account_id: Option<AccountId>,
+    pub peer_id: Option<PeerId>,
+    pub count: usize,
+    /// Time between consecutive pings.
+    pub interval_ms: u64,
+    /// Time waited for pongs after the last ping was sent.
+    pub timeout_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RpcPingResponse {
+    pub peer_id: PeerId,
+    pub sent: usize,
+    pub received: usize,
+    pub min_rtt_ms: Option<f64>,
+    pub avg_rtt_ms: Option<f64>,
+    pub max_rtt_ms: Option<f64>,
+    /// Number of pings sent through each next hop. Pings which couldn't be routed aren't counted.
+    pub next_hops: HashMap<PeerId, usize>,
+}
+
 #[derive(thiserror::Error, Debug, Serialize, Deserialize)]
 #[serde(tag = "name", content = "info", rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum RpcNetworkInfoError {
@@ -19,6 +78,106 @@ impl From<near_client_primitives::types::NetworkInfoResponse> for RpcNetworkInfo
     }
 }
 
+impl RpcRoutingDiagnosticsRequest {
+    pub fn parse(value: Option<Value>) -> Result<Self, crate::errors::RpcParseError> {
+        crate::utils::parse_params::<Self>(value)
+    }
+}
+
+impl RpcDumpRoutingTableRequest {
+    pub fn parse(value: Option<Value>) -> Result<Self, crate::errors::RpcParseError> {
+        crate::utils::parse_params::<Self>(value)
+    }
+}
+
+impl RpcPingRequest {
+    pub fn parse(value: Option<Value>) -> Result<Self, crate::errors::RpcParseError> {
+        let request = crate::utils::parse_params::<Self>(value)?;
+        if request.account_id.is_some() == request.peer_id.is_some() {
+            return Err(crate::errors::RpcParseError(
+                "Exactly one of account_id and peer_id must be given".to_string(),
+            ));
+        }
+        if request.count == 0 || request.count > MAX_PING_COUNT {
+            return Err(crate::errors::RpcParseError(format!(
+                "count must be between 1 and {}",
+                MAX_PING_COUNT
+            )));
+        }
+        let duration_ms = (request.count as u64 - 1)
+            .saturating_mul(request.interval_ms)
+            .saturating_add(request.timeout_ms);
+        if duration_ms > MAX_PING_DURATION_MS {
+            return Err(crate::errors::RpcParseError(format!(
+                "Pings would take {} ms, more than {} ms",
+                duration_ms, MAX_PING_DURATION_MS
+            )));
+        }
+        Ok(request)
+    }
+}
+
+impl RpcPingResponse {
+    /// Summarize pings to `peer_id`: `next_hops` has the next hop of every ping sent, none if it
+    /// couldn't be routed, and `rtts` the round trip times of the pongs received.
+    pub fn new(peer_id: PeerId, next_hops: Vec<Option<PeerId>>, rtts: Vec<f64>) -> Self {
+        let mut next_hop_counts = HashMap::new();
+        for next_hop in next_hops.iter().flatten() {
+            *next_hop_counts.entry(next_hop.clone()).or_insert(0) += 1;
+        }
+        let min_rtt_ms = rtts
+            .iter()
+            .cloned()
+            .fold(None, |min: Option<f64>, rtt| Some(min.map_or(rtt, |min| min.min(rtt))));
+        let max_rtt_ms = rtts
+            .iter()
+            .cloned()
+            .fold(None, |max: Option<f64>, rtt| Some(max.map_or(rtt, |max| max.max(rtt))));
+        let avg_rtt_ms =
+            if rtts.is_empty() { None } else { Some(rtts.iter().sum::<f64>() / rtts.len() as f64) };
+        Self {
+            peer_id,
+            sent: next_hops.len(),
+            received: rtts.len(),
+            min_rtt_ms,
+            avg_rtt_ms,
+            max_rtt_ms,
+            next_hops: next_hop_counts,
+        }
+    }
+
+    /// Percentage of pings sent which weren't answered.
+    pub fn loss_percent(&self) -> f64 {
+        if self.sent == 0 {
+            return 0.0;
+        }
+        (self.sent - self.received) as f64 * 100.0 / self.sent as f64
+    }
+}
+
+impl fmt::Display for RpcPingResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} pings sent, {} received, {:.1}% loss",
+            self.sent,
+            self.received,
+            self.loss_percent()
+        )?;
+        if let (Some(min), Some(avg), Some(max)) =
+            (self.min_rtt_ms, self.avg_rtt_ms, self.max_rtt_ms)
+        {
+            writeln!(f, "rtt min/avg/max = {:.3}/{:.3}/{:.3} ms", min, avg, max)?;
+        }
+        let mut next_hops: Vec<_> = self.next_hops.iter().collect();
+        next_hops.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+        for (next_hop, count) in next_hops {
+            writeln!(f, "  next hop {}: {} pings", next_hop, count)?;
+        }
+        Ok(())
+    }
+}
+
 impl From<actix::MailboxError> for RpcNetworkInfoError {
     fn from(error: actix::MailboxError) -> Self {
         Self::InternalError { error_message: error.to_string() }
@@ -45,3 +204,29 @@ impl From<RpcNetworkInfoError> for crate::errors::RpcError {
         Self::new_internal_or_handler_error(Some(error_data.clone()), error_data)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use near_crypto::{KeyType, SecretKey};
+    use near_primitives::network::PeerId;
+
+    use super::RpcPingResponse;
+
+    fn peer_id(seed: &str) -> PeerId {
+        PeerId::new(SecretKey::from_seed(KeyType::ED25519, seed).public_key())
+    }
+
+    #[test]
+    fn ping_response_summary() {
+        let (target, hop0, hop1) = (peer_id("target"), peer_id("hop0"), peer_id("hop1"));
+        let next_hops = vec![Some(hop0.clone()), None, Some(hop1.clone()), Some(hop0.clone())];
+        let response = RpcPingResponse::new(target, next_hops, vec![30.0, 10.0, 20.0]);
+        assert_eq!((response.sent, response.received), (4, 3));
+        assert_eq!(response.loss_percent(), 25.0);
+        assert_eq!(response.min_rtt_ms, Some(10.0));
+        assert_eq!(response.avg_rtt_ms, Some(20.0));
+        assert_eq!(response.max_rtt_ms, Some(30.0));
+        assert_eq!(response.next_hops.get(&hop0), Some(&2));
+        assert_eq!(response.next_hops.get(&hop1), Some(&1));
+    }
+}
@@ -7,7 +7,9 @@ pub struct RpcStatusResponse {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct RpcHealthResponse;
+pub struct RpcHealthResponse {
+    pub routing_recalculation: near_primitives::views::RoutingRecalculationView,
+}
 
 #[derive(thiserror::Error, Debug, Serialize, Deserialize)]
 #[serde(tag = "name", content = "info", rename_all = "SCREAMING_SNAKE_CASE")]
@@ -29,8 +31,8 @@ impl From<near_primitives::views::StatusResponse> for RpcStatusResponse {
 }
 
 impl From<near_primitives::views::StatusResponse> for RpcHealthResponse {
-    fn from(_status_response: near_primitives::views::StatusResponse) -> Self {
-        Self {}
+    fn from(status_response: near_primitives::views::StatusResponse) -> Self {
+        Self { routing_recalculation: status_response.routing_recalculation }
     }
 }
 
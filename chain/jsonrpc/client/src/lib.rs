@@ -258,6 +258,14 @@ impl JsonRpcClient {
     ) -> RpcRequest<near_jsonrpc_primitives::types::config::RpcProtocolConfigResponse> {
         call_method(&self.client, &self.server_addr, "EXPERIMENTAL_protocol_config", request)
     }
+
+    #[allow(non_snake_case)]
+    pub fn EXPERIMENTAL_ping(
+        &self,
+        request: near_jsonrpc_primitives::types::network_info::RpcPingRequest,
+    ) -> RpcRequest<near_jsonrpc_primitives::types::network_info::RpcPingResponse> {
+        call_method(&self.client, &self.server_addr, "EXPERIMENTAL_ping", request)
+    }
 }
 
 fn create_client() -> Client {
@@ -1,4 +1,3 @@
-#[cfg(feature = "test_features")]
 use actix::Actor;
 use actix::Addr;
 use futures::{future, future::LocalBoxFuture, FutureExt, TryFutureExt};
@@ -9,9 +8,7 @@ use near_client::test_utils::setup_no_network_with_validity_period_and_no_epoch_
 use near_client::ViewClientActor;
 use near_jsonrpc::{start_http, RpcConfig};
 use near_jsonrpc_primitives::message::{from_slice, Message};
-use near_network::test_utils::open_port;
-#[cfg(feature = "test_features")]
-use near_network::test_utils::{make_ibf_routing_pool, make_peer_manager};
+use near_network::test_utils::{make_ibf_routing_pool, make_peer_manager, open_port};
 use near_primitives::types::NumBlocks;
 
 lazy_static::lazy_static! {
@@ -47,9 +44,7 @@ pub fn start_all_with_validity_period_and_no_epoch_sync(
 
     let addr = format!("127.0.0.1:{}", open_port());
 
-    #[cfg(feature = "test_features")]
     let ibf_routing_pool = make_ibf_routing_pool();
-    #[cfg(feature = "test_features")]
     let peer_manager_addr = make_peer_manager(
         "test2",
         open_port(),
@@ -65,7 +60,6 @@ pub fn start_all_with_validity_period_and_no_epoch_sync(
         TEST_GENESIS_CONFIG.clone(),
         client_addr.clone(),
         view_client_addr.clone(),
-        #[cfg(feature = "test_features")]
         peer_manager_addr,
         #[cfg(feature = "test_features")]
         ibf_routing_pool,
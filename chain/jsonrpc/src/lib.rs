@@ -4,7 +4,9 @@ use std::time::Duration;
 
 use actix::Addr;
 use actix_cors::Cors;
-use actix_web::{http, middleware, web, App, Error as HttpError, HttpResponse, HttpServer};
+use actix_web::{
+    http, middleware, web, App, Error as HttpError, HttpRequest, HttpResponse, HttpServer,
+};
 use futures::Future;
 use futures::FutureExt;
 use prometheus;
@@ -21,17 +23,11 @@ use near_client::{
     TxStatusError, ViewClientActor,
 };
 #[cfg(feature = "test_features")]
-use near_jsonrpc_adversarial_primitives::SetAdvOptionsRequest;
-#[cfg(all(
-    feature = "test_features",
-    feature = "protocol_feature_routing_exchange_algorithm"
-))]
-use near_jsonrpc_adversarial_primitives::SetRoutingTableRequest;
-#[cfg(all(
-    feature = "test_features",
-    feature = "protocol_feature_routing_exchange_algorithm"
-))]
-use near_jsonrpc_adversarial_primitives::StartRoutingTableSyncRequest;
+use near_jsonrpc_adversarial_primitives::{
+    DumpRoutingTableRequest, ExplainRouteRequest, GetEdgeGossipStatsRequest, GetPeerScoresRequest,
+    GetRoutingAuditLogRequest, RestoreRoutingTableSnapshotRequest, SetAdvOptionsRequest,
+    SetRoutingTableRequest, StartRoutingTableSyncRequest,
+};
 pub use near_jsonrpc_client as client;
 use near_jsonrpc_primitives::errors::RpcError;
 use near_jsonrpc_primitives::message::{Message, Request};
@@ -39,21 +35,24 @@ use near_jsonrpc_primitives::types::config::RpcProtocolConfigResponse;
 use near_metrics::{Encoder, TextEncoder};
 #[cfg(feature = "test_features")]
 use near_network::routing::GetRoutingTableResult;
+use near_network::types::{
+    AccountOrPeerIdOrHash, DumpRoutingTable, ExplainAccountRoute, GetNetworkDebugInfo, GetPingRtts,
+    GetRoutingProfile, SendPing,
+};
 #[cfg(feature = "test_features")]
 use near_network::types::{
-    GetPeerId, GetRoutingTable, NetworkAdversarialMessage, NetworkViewClientMessages, SetAdvOptions,
+    ExplainRoute, GetEdgeGossipStats, GetPeerId, GetPeerScores, GetReachabilityProbes,
+    GetRoutingAuditLog, GetRoutingTable, GetRoutingTableSnapshot, NetworkAdversarialMessage,
+    NetworkViewClientMessages, RestoreRoutingTableSnapshot, SetAdvOptions,
 };
 #[cfg(feature = "sandbox")]
 use near_network::types::{NetworkSandboxMessage, SandboxResponse};
-#[cfg(all(
-    feature = "test_features",
-    feature = "protocol_feature_routing_exchange_algorithm"
-))]
+#[cfg(feature = "test_features")]
 use near_network::types::{SetRoutingTable, StartRoutingTableSync};
-use near_network::{NetworkClientMessages, NetworkClientResponses};
+use near_network::{NetworkClientMessages, NetworkClientResponses, PeerManagerActor};
 #[cfg(feature = "test_features")]
 use near_network::{
-    PeerManagerActor, RoutingTableActor, RoutingTableMessages, RoutingTableMessagesResponse,
+    RoutingTableActor, RoutingTableMessages, RoutingTableMessagesResponse, ROUTING_TABLE_PAGE_SIZE,
 };
 use near_primitives::hash::CryptoHash;
 use near_primitives::serialize::BaseEncode;
@@ -99,6 +98,13 @@ pub struct RpcConfig {
     pub polling_config: RpcPollingConfig,
     #[serde(default)]
     pub limits_config: RpcLimitsConfig,
+    /// Whether methods changing the behavior of the node, like turning on routing diagnostics or
+    /// writing a dump of the routing table, are served. They should only be enabled on an address reachable by the operator.
+    #[serde(default)]
+    pub enable_admin_methods: bool,
+    /// Whether the state of the node is served as JSON under `/debug/api`.
+    #[serde(default)]
+    pub enable_debug_rpc: bool,
 }
 
 impl Default for RpcConfig {
@@ -109,6 +115,8 @@ impl Default for RpcConfig {
             cors_allowed_origins: vec!["*".to_owned()],
             polling_config: Default::default(),
             limits_config: Default::default(),
+            enable_admin_methods: false,
+            enable_debug_rpc: false,
         }
     }
 }
@@ -228,18 +236,21 @@ struct JsonRpcHandler {
     view_client_addr: Addr<ViewClientActor>,
     polling_config: RpcPollingConfig,
     genesis_config: GenesisConfig,
-    #[cfg(feature = "test_features")]
+    enable_admin_methods: bool,
+    enable_debug_rpc: bool,
     peer_manager_addr: Addr<PeerManagerActor>,
     #[cfg(feature = "test_features")]
     ibf_routing_pool: Addr<RoutingTableActor>,
 }
 
 impl JsonRpcHandler {
-    pub async fn process(&self, message: Message) -> Result<Message, HttpError> {
+    /// Process a JSON RPC message from `caller`, recorded in the audit log of the requests which
+    /// mutate the routing state.
+    pub async fn process(&self, message: Message, caller: &str) -> Result<Message, HttpError> {
         let id = message.id();
         match message {
             Message::Request(request) => {
-                Ok(Message::response(id, self.process_request(request).await))
+                Ok(Message::response(id, self.process_request(request, caller).await))
             }
             _ => Ok(Message::error(RpcError::parse_error(
                 "JSON RPC Request format was expected".to_owned(),
@@ -247,7 +258,8 @@ impl JsonRpcHandler {
         }
     }
 
-    async fn process_request(&self, request: Request) -> Result<Value, RpcError> {
+    #[cfg_attr(not(feature = "test_features"), allow(unused_variables))]
+    async fn process_request(&self, request: Request, caller: &str) -> Result<Value, RpcError> {
         near_metrics::inc_counter_vec(&metrics::HTTP_RPC_REQUEST_COUNT, &[request.method.as_ref()]);
         let _rpc_processing_time = near_metrics::start_timer_vec(
             &metrics::RPC_PROCESSING_TIME,
@@ -277,6 +289,7 @@ impl JsonRpcHandler {
                             disable_edge_propagation: params.disable_edge_propagation,
                             disable_edge_pruning: params.disable_edge_pruning,
                             set_max_peers: None,
+                            caller: caller.to_string(),
                         })
                         .await?;
                     Some(
@@ -284,7 +297,6 @@ impl JsonRpcHandler {
                             .map_err(|err| RpcError::serialization_error(err.to_string())),
                     )
                 }
-                #[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
                 "adv_set_routing_table" => {
                     let request = SetRoutingTableRequest::parse(params)?;
                     let result = self
@@ -293,6 +305,7 @@ impl JsonRpcHandler {
                             add_edges: request.add_edges,
                             remove_edges: request.remove_edges,
                             prune_edges: request.prune_edges,
+                            caller: caller.to_string(),
                         })
                         .await?;
                     Some(
@@ -300,13 +313,15 @@ impl JsonRpcHandler {
                             .map_err(|err| RpcError::serialization_error(err.to_string())),
                     )
                 }
-                #[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
                 "adv_start_routing_table_syncv2" => {
                     let params = parse_params::<StartRoutingTableSyncRequest>(params)?;
 
                     let result = self
                         .peer_manager_addr
-                        .send(StartRoutingTableSync { peer_id: params.peer_id })
+                        .send(StartRoutingTableSync {
+                            peer_id: params.peer_id,
+                            caller: caller.to_string(),
+                        })
                         .await?;
                     Some(
                         serde_json::to_value(result)
@@ -320,6 +335,55 @@ impl JsonRpcHandler {
                             .map_err(|err| RpcError::serialization_error(err.to_string())),
                     )
                 }
+                "adv_get_edge_gossip_stats" => {
+                    let params = parse_params::<GetEdgeGossipStatsRequest>(params)?;
+                    let result = self
+                        .peer_manager_addr
+                        .send(GetEdgeGossipStats { limit: params.limit })
+                        .await?;
+                    Some(
+                        serde_json::to_value(result)
+                            .map_err(|err| RpcError::serialization_error(err.to_string())),
+                    )
+                }
+                "adv_get_peer_scores" => {
+                    let params = parse_params::<GetPeerScoresRequest>(params)?;
+                    let result =
+                        self.peer_manager_addr.send(GetPeerScores { limit: params.limit }).await?;
+                    Some(
+                        serde_json::to_value(result)
+                            .map_err(|err| RpcError::serialization_error(err.to_string())),
+                    )
+                }
+                "adv_get_reachability_probes" => {
+                    let result = self.peer_manager_addr.send(GetReachabilityProbes {}).await?;
+                    Some(
+                        serde_json::to_value(result)
+                            .map_err(|err| RpcError::serialization_error(err.to_string())),
+                    )
+                }
+                "adv_explain_route" => {
+                    let params = parse_params::<ExplainRouteRequest>(params)?;
+                    let result =
+                        self.peer_manager_addr.send(ExplainRoute { target: params.target }).await?;
+                    Some(result.map_err(|err| RpcError::server_error(Some(err))).and_then(
+                        |explanation| {
+                            serde_json::to_value(explanation)
+                                .map_err(|err| RpcError::serialization_error(err.to_string()))
+                        },
+                    ))
+                }
+                "adv_get_routing_audit_log" => {
+                    let params = parse_params::<GetRoutingAuditLogRequest>(params)?;
+                    let result = self
+                        .peer_manager_addr
+                        .send(GetRoutingAuditLog { limit: params.limit })
+                        .await?;
+                    Some(
+                        serde_json::to_value(result)
+                            .map_err(|err| RpcError::serialization_error(err.to_string())),
+                    )
+                }
                 "adv_get_routing_table" => {
                     let result = self.peer_manager_addr.send(GetRoutingTable {}).await?;
                     Some(
@@ -327,30 +391,70 @@ impl JsonRpcHandler {
                             .map_err(|err| RpcError::serialization_error(err.to_string())),
                     )
                 }
-                "adv_get_routing_table_new" => {
+                "adv_get_routing_table_snapshot" => {
+                    let result = self.peer_manager_addr.send(GetRoutingTableSnapshot {}).await?;
+                    Some(
+                        serde_json::to_value(result)
+                            .map_err(|err| RpcError::serialization_error(err.to_string())),
+                    )
+                }
+                "adv_dump_routing_table" => {
+                    let params = parse_params::<DumpRoutingTableRequest>(params)?;
+                    let result =
+                        self.peer_manager_addr.send(DumpRoutingTable { path: params.path }).await?;
+                    Some(
+                        result
+                            .map(|()| Value::Null)
+                            .map_err(|err| RpcError::server_error(Some(err))),
+                    )
+                }
+                "adv_restore_routing_table_snapshot" => {
+                    let params = parse_params::<RestoreRoutingTableSnapshotRequest>(params)?;
                     let result = self
-                        .ibf_routing_pool
-                        .send(RoutingTableMessages::RequestRoutingTable)
+                        .peer_manager_addr
+                        .send(RestoreRoutingTableSnapshot {
+                            snapshot: params.snapshot,
+                            caller: caller.to_string(),
+                        })
                         .await?;
-
-                    match result {
-                        RoutingTableMessagesResponse::RequestRoutingTableResponse {
-                            edges_info: routing_table,
-                        } => {
-                            let response = {
-                                GetRoutingTableResult {
-                                    edges_info: routing_table
-                                        .iter()
-                                        .map(|x| x.to_simple_edge())
-                                        .collect(),
+                    Some(
+                        serde_json::to_value(result)
+                            .map_err(|err| RpcError::serialization_error(err.to_string())),
+                    )
+                }
+                "adv_get_routing_table_new" => {
+                    // Read the table in pages, so only one page of full edges is in memory.
+                    let mut edges_info = vec![];
+                    let mut after = None;
+                    let complete = loop {
+                        let result = self
+                            .ibf_routing_pool
+                            .send(RoutingTableMessages::RequestRoutingTablePage {
+                                after,
+                                limit: ROUTING_TABLE_PAGE_SIZE,
+                            })
+                            .await?;
+                        match result {
+                            RoutingTableMessagesResponse::RequestRoutingTablePageResponse {
+                                edges,
+                                next,
+                            } => {
+                                edges_info.extend(edges.iter().map(|x| x.to_simple_edge()));
+                                match next {
+                                    Some(next) => after = Some(next),
+                                    None => break true,
                                 }
-                            };
-                            Some(
-                                serde_json::to_value(response)
-                                    .map_err(|err| RpcError::serialization_error(err.to_string())),
-                            )
+                            }
+                            _ => break false,
                         }
-                        _ => None,
+                    };
+                    if complete {
+                        Some(
+                            serde_json::to_value(GetRoutingTableResult { edges_info })
+                                .map_err(|err| RpcError::serialization_error(err.to_string())),
+                        )
+                    } else {
+                        None
                     }
                 }
                 _ => None,
@@ -525,6 +629,33 @@ impl JsonRpcHandler {
                 serde_json::to_value(receipt)
                     .map_err(|err| RpcError::serialization_error(err.to_string()))
             }
+            "EXPERIMENTAL_routing_diagnostics" if self.enable_admin_methods => {
+                let rpc_routing_diagnostics_request =
+                    near_jsonrpc_primitives::types::network_info::RpcRoutingDiagnosticsRequest::parse(
+                        request.params,
+                    )?;
+                let routing_diagnostics_response =
+                    self.routing_diagnostics(rpc_routing_diagnostics_request);
+                serde_json::to_value(routing_diagnostics_response)
+                    .map_err(|err| RpcError::serialization_error(err.to_string()))
+            }
+            "EXPERIMENTAL_dump_routing_table" if self.enable_admin_methods => {
+                let rpc_dump_routing_table_request =
+                    near_jsonrpc_primitives::types::network_info::RpcDumpRoutingTableRequest::parse(
+                        request.params,
+                    )?;
+                self.dump_routing_table(rpc_dump_routing_table_request).await?;
+                Ok(Value::Null)
+            }
+            "EXPERIMENTAL_ping" if self.enable_admin_methods => {
+                let rpc_ping_request =
+                    near_jsonrpc_primitives::types::network_info::RpcPingRequest::parse(
+                        request.params,
+                    )?;
+                let ping_response = self.ping(rpc_ping_request).await?;
+                serde_json::to_value(ping_response)
+                    .map_err(|err| RpcError::serialization_error(err.to_string()))
+            }
             "EXPERIMENTAL_tx_status" => {
                 let rpc_transaction_status_common_request = near_jsonrpc_primitives::types::transactions::RpcTransactionStatusCommonRequest::parse(request.params)?;
                 let rpc_transaction_response =
@@ -1051,6 +1182,64 @@ impl JsonRpcHandler {
         Ok(self.client_addr.send(GetNetworkInfo {}).await??.into())
     }
 
+    /// Writes the routing table to a file on the node, see `RoutingTableDump`.
+    async fn dump_routing_table(
+        &self,
+        request_data: near_jsonrpc_primitives::types::network_info::RpcDumpRoutingTableRequest,
+    ) -> Result<(), near_jsonrpc_primitives::types::network_info::RpcNetworkInfoError> {
+        Ok(self.peer_manager_addr.send(DumpRoutingTable { path: request_data.path }).await??)
+    }
+
+    /// Turns on verbose routing diagnostics for the requested duration, after which they are
+    /// turned off automatically.
+    fn routing_diagnostics(
+        &self,
+        request_data: near_jsonrpc_primitives::types::network_info::RpcRoutingDiagnosticsRequest,
+    ) -> near_jsonrpc_primitives::types::network_info::RpcRoutingDiagnosticsResponse {
+        let duration = near_network::diagnostics::enable_routing_diagnostics(
+            Duration::from_secs(request_data.duration_secs),
+            &near_network::time::Clock::real(),
+        );
+        near_jsonrpc_primitives::types::network_info::RpcRoutingDiagnosticsResponse {
+            remaining_secs: duration.as_secs(),
+        }
+    }
+
+    /// Pings a peer through the routed network: sends `count` pings `interval_ms` apart, waits
+    /// `timeout_ms` for the last pongs and summarizes the round trip times.
+    async fn ping(
+        &self,
+        request_data: near_jsonrpc_primitives::types::network_info::RpcPingRequest,
+    ) -> Result<
+        near_jsonrpc_primitives::types::network_info::RpcPingResponse,
+        near_jsonrpc_primitives::types::network_info::RpcNetworkInfoError,
+    > {
+        let target = match request_data.peer_id {
+            Some(peer_id) => AccountOrPeerIdOrHash::PeerId(peer_id),
+            None => AccountOrPeerIdOrHash::AccountId(
+                request_data.account_id.ok_or_else(|| "Missing ping target".to_string())?,
+            ),
+        };
+        // Pings to an account all go to the peer which owned it when the first one was sent.
+        let first = self.peer_manager_addr.send(SendPing { target }).await??;
+        let peer_id = first.target;
+        let mut nonces = vec![first.nonce];
+        let mut next_hops = vec![first.next_hop];
+        for _ in 1..request_data.count {
+            sleep(Duration::from_millis(request_data.interval_ms)).await;
+            let target = AccountOrPeerIdOrHash::PeerId(peer_id.clone());
+            let sent = self.peer_manager_addr.send(SendPing { target }).await??;
+            nonces.push(sent.nonce);
+            next_hops.push(sent.next_hop);
+        }
+        sleep(Duration::from_millis(request_data.timeout_ms)).await;
+        let rtts =
+            self.peer_manager_addr.send(GetPingRtts { target: peer_id.clone(), nonces }).await?;
+        Ok(near_jsonrpc_primitives::types::network_info::RpcPingResponse::new(
+            peer_id, next_hops, rtts,
+        ))
+    }
+
     async fn gas_price(
         &self,
         request_data: near_jsonrpc_primitives::types::gas_price::RpcGasPriceRequest,
@@ -1245,11 +1434,13 @@ impl JsonRpcHandler {
 }
 
 fn rpc_handler(
+    request: HttpRequest,
     message: web::Json<Message>,
     handler: web::Data<JsonRpcHandler>,
 ) -> impl Future<Output = Result<HttpResponse, HttpError>> {
+    let caller = request.peer_addr().map_or_else(|| "unknown".to_string(), |addr| addr.to_string());
     let response = async move {
-        let message = handler.process(message.0).await?;
+        let message = handler.process(message.0, &caller).await?;
         Ok(HttpResponse::Ok().json(&message))
     };
     response.boxed()
@@ -1293,6 +1484,53 @@ fn network_info_handler(
     response.boxed()
 }
 
+fn debug_network_info_handler(
+    handler: web::Data<JsonRpcHandler>,
+) -> impl Future<Output = Result<HttpResponse, HttpError>> {
+    let response = async move {
+        if !handler.enable_debug_rpc {
+            return Ok(HttpResponse::NotFound().finish());
+        }
+        match handler.peer_manager_addr.send(GetNetworkDebugInfo {}).await {
+            Ok(Ok(value)) => Ok(HttpResponse::Ok().json(&value)),
+            _ => Ok(HttpResponse::ServiceUnavailable().finish()),
+        }
+    };
+    response.boxed()
+}
+
+fn debug_routing_profile_handler(
+    handler: web::Data<JsonRpcHandler>,
+) -> impl Future<Output = Result<HttpResponse, HttpError>> {
+    let response = async move {
+        if !handler.enable_debug_rpc {
+            return Ok(HttpResponse::NotFound().finish());
+        }
+        match handler.peer_manager_addr.send(GetRoutingProfile {}).await {
+            Ok(Ok(value)) => Ok(HttpResponse::Ok().json(&value)),
+            _ => Ok(HttpResponse::ServiceUnavailable().finish()),
+        }
+    };
+    response.boxed()
+}
+
+fn debug_account_route_handler(
+    account_id: web::Path<AccountId>,
+    handler: web::Data<JsonRpcHandler>,
+) -> impl Future<Output = Result<HttpResponse, HttpError>> {
+    let response = async move {
+        if !handler.enable_debug_rpc {
+            return Ok(HttpResponse::NotFound().finish());
+        }
+        let msg = ExplainAccountRoute { account_id: account_id.into_inner() };
+        match handler.peer_manager_addr.send(msg).await {
+            Ok(value) => Ok(HttpResponse::Ok().json(&value)),
+            _ => Ok(HttpResponse::ServiceUnavailable().finish()),
+        }
+    };
+    response.boxed()
+}
+
 pub async fn prometheus_handler() -> Result<HttpResponse, HttpError> {
     near_metrics::inc_counter(&metrics::PROMETHEUS_REQUEST_COUNT);
 
@@ -1335,11 +1573,18 @@ pub fn start_http(
     genesis_config: GenesisConfig,
     client_addr: Addr<ClientActor>,
     view_client_addr: Addr<ViewClientActor>,
-    #[cfg(feature = "test_features")] peer_manager_addr: Addr<PeerManagerActor>,
+    peer_manager_addr: Addr<PeerManagerActor>,
     #[cfg(feature = "test_features")] ibf_routing_pool: Addr<RoutingTableActor>,
 ) -> Vec<(&'static str, actix_web::dev::Server)> {
-    let RpcConfig { addr, prometheus_addr, cors_allowed_origins, polling_config, limits_config } =
-        config;
+    let RpcConfig {
+        addr,
+        prometheus_addr,
+        cors_allowed_origins,
+        polling_config,
+        limits_config,
+        enable_admin_methods,
+        enable_debug_rpc,
+    } = config;
     let prometheus_addr = prometheus_addr.filter(|it| it != &addr);
     let cors_allowed_origins_clone = cors_allowed_origins.clone();
     info!(target:"network", "Starting http server at {}", addr);
@@ -1352,7 +1597,8 @@ pub fn start_http(
                 view_client_addr: view_client_addr.clone(),
                 polling_config,
                 genesis_config: genesis_config.clone(),
-                #[cfg(feature = "test_features")]
+                enable_admin_methods,
+                enable_debug_rpc,
                 peer_manager_addr: peer_manager_addr.clone(),
                 #[cfg(feature = "test_features")]
                 ibf_routing_pool: ibf_routing_pool.clone(),
@@ -1371,6 +1617,18 @@ pub fn start_http(
                     .route(web::head().to(health_handler)),
             )
             .service(web::resource("/network_info").route(web::get().to(network_info_handler)))
+            .service(
+                web::resource("/debug/api/network_info")
+                    .route(web::get().to(debug_network_info_handler)),
+            )
+            .service(
+                web::resource("/debug/api/routing_profile")
+                    .route(web::get().to(debug_routing_profile_handler)),
+            )
+            .service(
+                web::resource("/debug/api/account_route/{account_id}")
+                    .route(web::get().to(debug_account_route_handler)),
+            )
             .service(web::resource("/metrics").route(web::get().to(prometheus_handler)))
     })
     .bind(addr)
@@ -2,7 +2,8 @@ use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::fmt::{Debug, Error, Formatter};
 use std::hash::Hash;
-use std::net::{AddrParseError, IpAddr, SocketAddr};
+use std::net::{AddrParseError, IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::time::Duration;
 
@@ -13,10 +14,11 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use strum::AsStaticStr;
 use tokio::net::TcpStream;
-use tracing::{error, warn};
+use tracing::warn;
 
 use near_crypto::{KeyType, PublicKey, SecretKey, Signature};
 use near_primitives::block::{Approval, Block, BlockHeader, GenesisId};
+use near_primitives::checked_feature;
 use near_primitives::hash::{hash, CryptoHash};
 use near_primitives::merkle::combine_hash;
 use near_primitives::network::{AnnounceAccount, PeerId};
@@ -33,6 +35,7 @@ use near_primitives::syncing::{
 use near_primitives::transaction::{ExecutionOutcomeWithIdAndProof, SignedTransaction};
 use near_primitives::types::{AccountId, BlockHeight, BlockReference, EpochId, ShardId};
 use near_primitives::utils::{from_timestamp, to_timestamp};
+use near_primitives::version::PROTOCOL_VERSION;
 use near_primitives::views::{FinalExecutionOutcomeView, QueryRequest, QueryResponse};
 
 /// Number of hops a message is allowed to travel before being dropped.
@@ -43,6 +46,216 @@ pub const ROUTED_MESSAGE_TTL: u8 = 100;
 /// but wait some "small" timeout between updates to avoid a lot of messages between
 /// Peer and PeerManager.
 pub const UPDATE_INTERVAL_LAST_TIME_RECEIVED_MESSAGE: Duration = Duration::from_secs(60);
+/// Routing table will clean edges if there is at least one node that is not reachable
+/// since `SAVE_PEERS_MAX_TIME` seconds. All peers disconnected since `SAVE_PEERS_AFTER_TIME`
+/// seconds will be removed from cache and persisted in disk.
+pub const SAVE_PEERS_MAX_TIME: Duration = Duration::from_secs(7_200);
+pub const SAVE_PEERS_AFTER_TIME: Duration = Duration::from_secs(3_600);
+/// Maximum number of active peers supported by `PeerManagerActor`. The graph itself has no limit
+/// on the number of direct peers of the source.
+pub const MAX_NUM_PEERS: usize = 1024;
+/// Bounds enforced by `RoutingTableConfig::verify`.
+const MAX_ANNOUNCE_ACCOUNT_CACHE_SIZE: usize = 1_000_000;
+const MAX_ROUTE_BACK_CACHE_SIZE: u64 = 10_000_000;
+const MIN_ROUTE_BACK_CACHE_EVICT_TIMEOUT: Duration = Duration::from_secs(1);
+const MIN_SAVE_PEERS_AFTER_TIME: Duration = Duration::from_secs(60);
+
+/// How the next hop is chosen among the peers on shortest paths to the target.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoutingStrategy {
+    /// Round robin among all next hops, ignoring their latency.
+    RoundRobin,
+    /// Round robin among the next hops with the lowest latency.
+    LatencyAware,
+}
+
+impl Default for RoutingStrategy {
+    fn default() -> Self {
+        RoutingStrategy::LatencyAware
+    }
+}
+
+/// Sizes of caches and timeouts used by the routing table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RoutingTableConfig {
+    /// Maximum number of accounts we keep announcements of.
+    pub announce_account_cache_size: usize,
+    /// Maximum number of routed messages we remember the previous hop of, to route responses back.
+    pub route_back_cache_size: u64,
+    /// Entries of the route back cache older than this are evicted first when it's full.
+    pub route_back_cache_evict_timeout: Duration,
+    /// Number of entries evicted from the route back cache at once when it's full.
+    pub route_back_cache_remove_batch: u64,
+    /// Number of entries of the route back cache each peer can hold even if many peers use it.
+    /// Peers above both this and their fair share of the cache have their oldest entries evicted
+    /// first, so a noisy peer can't evict the entries of other peers.
+    pub route_back_cache_peer_quota: u64,
+    /// Save the route back cache on shutdown and restore the entries which didn't expire on
+    /// startup, so responses to requests routed before a restart can still be routed back.
+    pub persist_route_back: bool,
+    /// Edges are only pruned if some peer is unreachable for at least this long.
+    pub save_peers_max_time: Duration,
+    /// Peers unreachable for this long are removed from memory and saved to disk when pruning.
+    pub save_peers_after_time: Duration,
+    /// Maximum number of unreachable peers kept in the routing graph. Above it, the ones
+    /// unreachable for the longest time are saved to disk and removed from memory, which bounds
+    /// the memory used by disconnected parts of the network.
+    pub max_unreachable_graph_nodes: usize,
+    /// Maximum number of edges kept in memory. Above it, unreachable peers are saved to disk
+    /// starting from the ones unreachable for the longest time, regardless of
+    /// `save_peers_max_time`, which bounds the memory used on nodes knowing many edges.
+    pub max_edges_in_memory: usize,
+    /// Number of components of unreachable peers kept on disk. Older components are removed,
+    /// see `RoutingTable::save_component`.
+    pub max_saved_components: u64,
+    /// Announcements which traveled more hops are verified by pinging the peer which announced
+    /// them.
+    pub max_announcement_hops: u32,
+    /// Strategy used to route messages.
+    pub strategy: RoutingStrategy,
+    /// Strategy whose decisions are only computed and compared with the ones of `strategy`, to
+    /// validate it before switching to it.
+    pub shadow_strategy: Option<RoutingStrategy>,
+}
+
+impl Default for RoutingTableConfig {
+    fn default() -> Self {
+        Self {
+            announce_account_cache_size: 10_000,
+            route_back_cache_size: 100_000,
+            route_back_cache_evict_timeout: Duration::from_millis(120_000),
+            route_back_cache_remove_batch: 100,
+            route_back_cache_peer_quota: 10_000,
+            persist_route_back: false,
+            save_peers_max_time: SAVE_PEERS_MAX_TIME,
+            save_peers_after_time: SAVE_PEERS_AFTER_TIME,
+            max_unreachable_graph_nodes: 1_000,
+            max_edges_in_memory: 1_000_000,
+            max_saved_components: 100_000,
+            max_announcement_hops: 10,
+            strategy: RoutingStrategy::default(),
+            shadow_strategy: None,
+        }
+    }
+}
+
+impl RoutingTableConfig {
+    /// Check that values are within sane bounds.
+    pub fn verify(&self) -> Result<(), String> {
+        if self.announce_account_cache_size == 0
+            || self.announce_account_cache_size > MAX_ANNOUNCE_ACCOUNT_CACHE_SIZE
+        {
+            return Err(format!(
+                "announce_account_cache_size({}) must be between 1 and {}",
+                self.announce_account_cache_size, MAX_ANNOUNCE_ACCOUNT_CACHE_SIZE
+            ));
+        }
+        if self.route_back_cache_size == 0 || self.route_back_cache_size > MAX_ROUTE_BACK_CACHE_SIZE
+        {
+            return Err(format!(
+                "route_back_cache_size({}) must be between 1 and {}",
+                self.route_back_cache_size, MAX_ROUTE_BACK_CACHE_SIZE
+            ));
+        }
+        if self.route_back_cache_remove_batch == 0
+            || self.route_back_cache_remove_batch > self.route_back_cache_size
+        {
+            return Err(format!(
+                "route_back_cache_remove_batch({}) must be between 1 and route_back_cache_size({})",
+                self.route_back_cache_remove_batch, self.route_back_cache_size
+            ));
+        }
+        if self.route_back_cache_peer_quota == 0 {
+            return Err("route_back_cache_peer_quota must be positive".to_string());
+        }
+        if self.route_back_cache_evict_timeout < MIN_ROUTE_BACK_CACHE_EVICT_TIMEOUT {
+            return Err(format!(
+                "route_back_cache_evict_timeout({:?}) must be at least {:?}",
+                self.route_back_cache_evict_timeout, MIN_ROUTE_BACK_CACHE_EVICT_TIMEOUT
+            ));
+        }
+        if self.save_peers_after_time < MIN_SAVE_PEERS_AFTER_TIME
+            || self.save_peers_after_time > self.save_peers_max_time
+        {
+            return Err(format!(
+                "save_peers_after_time({:?}) must be between {:?} and save_peers_max_time({:?})",
+                self.save_peers_after_time, MIN_SAVE_PEERS_AFTER_TIME, self.save_peers_max_time
+            ));
+        }
+        if self.max_edges_in_memory == 0 {
+            return Err("max_edges_in_memory must be positive".to_string());
+        }
+        if self.max_saved_components == 0 {
+            return Err("max_saved_components must be positive".to_string());
+        }
+        if self.shadow_strategy == Some(self.strategy) {
+            return Err(format!("shadow_strategy({:?}) must differ from strategy", self.strategy));
+        }
+        Ok(())
+    }
+
+    /// When unreachable peers are pruned from the routing table, see `PruneConfig`.
+    pub fn prune_config(&self) -> PruneConfig {
+        PruneConfig {
+            save_after: self.save_peers_after_time,
+            prune_after: self.save_peers_max_time,
+            force: false,
+        }
+    }
+}
+
+/// When a routing table update saves unreachable peers to disk and removes them from memory.
+///
+/// Peers fall in three tiers by how long they have been unreachable: below `save_after` they
+/// are kept, from `save_after` they are saved when pruning happens, and once any of them reaches
+/// `prune_after` pruning happens. Forced pruning saves the second tier right away.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PruneConfig {
+    save_after: Duration,
+    prune_after: Duration,
+    force: bool,
+}
+
+impl PruneConfig {
+    /// Fails unless `save_after` is at most `prune_after`.
+    pub fn new(save_after: Duration, prune_after: Duration, force: bool) -> Result<Self, String> {
+        if save_after > prune_after {
+            return Err(format!(
+                "save_after({:?}) must be at most prune_after({:?})",
+                save_after, prune_after
+            ));
+        }
+        Ok(Self { save_after, prune_after, force })
+    }
+
+    /// Prune right away the peers unreachable for at least `save_after`.
+    pub fn forced(save_after: Duration) -> Self {
+        Self { save_after, prune_after: save_after, force: true }
+    }
+
+    pub fn save_after(&self) -> Duration {
+        self.save_after
+    }
+
+    pub fn prune_after(&self) -> Duration {
+        self.prune_after
+    }
+
+    pub fn force(&self) -> bool {
+        self.force
+    }
+
+    /// Whether to prune, given the longest time any peer has been unreachable for.
+    pub fn should_prune(&self, longest_unreachable: Duration) -> bool {
+        self.force || longest_unreachable >= self.prune_after
+    }
+
+    /// Whether a peer unreachable for `unreachable` is saved when pruning.
+    pub fn should_save(&self, unreachable: Duration) -> bool {
+        unreachable >= self.save_after
+    }
+}
 
 /// Peer information.
 #[derive(BorshSerialize, BorshDeserialize, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
@@ -65,6 +278,48 @@ impl PeerInfo {
     pub fn addr_port(&self) -> Option<u16> {
         self.addr.map(|addr| addr.port())
     }
+
+    /// Parses `s` like `from_str`, but also accepts a host name instead of an IP address. The
+    /// host name is resolved to an address of `ip_family`, preferring IPv6 if `prefer_v6`.
+    pub fn resolve(
+        s: &str,
+        ip_family: IpFamily,
+        prefer_v6: bool,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut chunks: Vec<&str> = s.split('@').collect();
+        // Account ids never contain ':', so this can only be a host name with a port.
+        let host = match chunks.get(1) {
+            Some(host) if host.contains(':') && host.parse::<SocketAddr>().is_err() => *host,
+            _ => return Self::from_str(s),
+        };
+        let addr = ip_family.select_addr(host.to_socket_addrs()?, prefer_v6).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("No {:?} address found for {}", ip_family, host),
+            )
+        })?;
+        let addr = addr.to_string();
+        chunks[1] = &addr;
+        Self::from_str(&chunks.join("@"))
+    }
+
+    /// Parses `s` like `from_str`, but also accepts a host name instead of an IP address. The
+    /// host name isn't resolved: it is returned along the peer info, which has no address.
+    pub fn parse_with_host(s: &str) -> Result<(Self, Option<String>), Box<dyn std::error::Error>> {
+        let mut chunks: Vec<&str> = s.split('@').collect();
+        let host = match chunks.get(1) {
+            Some(host) if host.contains(':') && host.parse::<SocketAddr>().is_err() => {
+                host.to_string()
+            }
+            _ => return Ok((Self::from_str(s)?, None)),
+        };
+        chunks.remove(1);
+        let mut peer_info = Self::from_str(chunks[0])?;
+        if let Some(account_id) = chunks.get(1) {
+            peer_info.account_id = Some(account_id.parse()?);
+        }
+        Ok((peer_info, Some(host)))
+    }
 }
 
 // Note, `Display` automatically implements `ToString` which must be reciprocal to `FromStr`.
@@ -196,6 +451,17 @@ pub struct Pong {
     pub source: PeerId,
 }
 
+/// Nonce of the ping sent after the ping with `nonce`. Nonces wrap around.
+pub fn next_ping_nonce(nonce: u64) -> u64 {
+    nonce.wrapping_add(1)
+}
+
+/// Whether the ping with nonce `a` was sent before the ping with nonce `b`, assuming they were
+/// sent less than half the nonce space apart so nonces which wrapped around compare correctly.
+pub fn ping_nonce_precedes(a: u64, b: u64) -> bool {
+    a != b && b.wrapping_sub(a) < 1 << 63
+}
+
 // TODO(#1313): Use Box
 #[derive(
     BorshSerialize,
@@ -237,6 +503,127 @@ pub enum RoutedMessageBody {
     VersionedPartialEncodedChunk(PartialEncodedChunk),
     VersionedStateResponse(StateResponseInfo),
     PartialEncodedChunkForward(PartialEncodedChunkForwardMsg),
+    /// Sent back to the author of a request which couldn't be routed to its target.
+    RouteNotFound(RouteNotFound),
+}
+
+/// Why a message can't be routed.
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Eq, Clone, Copy, Debug, AsStaticStr)]
+pub enum FindRouteError {
+    Disconnected,
+    PeerNotFound,
+    AccountNotFound,
+    RouteBackNotFound,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Eq, Clone, Debug)]
+pub struct RouteNotFound {
+    /// Hash of the request which couldn't be routed.
+    pub request: CryptoHash,
+    /// Target of the request.
+    pub target: PeerIdOrHash,
+    pub reason: FindRouteError,
+}
+
+/// Maximum number of relays in a `ProxyAnnouncement`.
+pub const MAX_PROXY_RELAYS: usize = 16;
+
+/// Relays a node hiding its address is connected through, signed with the key of the node. Other
+/// nodes route messages to it through one of the relays when they don't know a route to it.
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Eq, Clone, Debug)]
+pub struct ProxyAnnouncement {
+    pub peer_id: PeerId,
+    pub relays: Vec<PeerId>,
+    /// When the announcement was signed, in nanoseconds since the Unix epoch. Newer
+    /// announcements of the same node replace older ones.
+    pub timestamp: u64,
+    pub signature: Signature,
+}
+
+#[derive(BorshSerialize)]
+struct ProxyAnnouncementNoSignature<'a> {
+    peer_id: &'a PeerId,
+    relays: &'a [PeerId],
+    timestamp: u64,
+}
+
+impl ProxyAnnouncement {
+    pub fn new(secret_key: &SecretKey, relays: Vec<PeerId>, timestamp: u64) -> Self {
+        let peer_id = PeerId::new(secret_key.public_key());
+        let hash = Self::build_hash(&peer_id, &relays, timestamp);
+        let signature = secret_key.sign(hash.as_ref());
+        Self { peer_id, relays, timestamp, signature }
+    }
+
+    fn build_hash(peer_id: &PeerId, relays: &[PeerId], timestamp: u64) -> CryptoHash {
+        hash(
+            &ProxyAnnouncementNoSignature { peer_id, relays, timestamp }
+                .try_to_vec()
+                .expect("Failed to serialize"),
+        )
+    }
+
+    /// Whether the announcement is signed by its node and lists at most `MAX_PROXY_RELAYS`
+    /// relays, none of them the node itself.
+    pub fn verify(&self) -> bool {
+        !self.relays.is_empty()
+            && self.relays.len() <= MAX_PROXY_RELAYS
+            && !self.relays.contains(&self.peer_id)
+            && self.signature.verify(
+                Self::build_hash(&self.peer_id, &self.relays, self.timestamp).as_ref(),
+                &self.peer_id.public_key(),
+            )
+    }
+}
+
+/// Delegation from the node key of `peer_id` to a "network sub-key", so that nodes which keep
+/// their node key in an HSM can sign edge updates with a hot key. Only exchanged with peers whose
+/// negotiated protocol version has `ProtocolFeature::NetworkSubKeys`.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct SubKeyDelegation {
+    pub peer_id: PeerId,
+    /// Key used to sign edges instead of the node key.
+    pub sub_key: PublicKey,
+    /// Signature of the node key over `SubKeyDelegation::build_hash`.
+    pub signature: Signature,
+}
+
+impl SubKeyDelegation {
+    pub fn new(node_secret_key: &SecretKey, sub_key: PublicKey) -> Self {
+        let peer_id = PeerId::new(node_secret_key.public_key());
+        let signature = node_secret_key.sign(Self::build_hash(&peer_id, &sub_key).as_ref());
+        Self { peer_id, sub_key, signature }
+    }
+
+    fn build_hash(peer_id: &PeerId, sub_key: &PublicKey) -> CryptoHash {
+        let mut buffer = b"network_sub_key".to_vec();
+        buffer.extend((peer_id, sub_key).try_to_vec().expect("Failed to serialize"));
+        hash(buffer.as_slice())
+    }
+
+    /// Whether the delegation is signed by the node key of `peer_id`.
+    pub fn verify(&self) -> bool {
+        self.signature.verify(
+            Self::build_hash(&self.peer_id, &self.sub_key).as_ref(),
+            &self.peer_id.public_key(),
+        )
+    }
+}
+
+/// Network sub-key of this node with the delegation signed by its node key.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct NetworkSubKey {
+    pub secret_key: SecretKey,
+    pub delegation: SubKeyDelegation,
+}
+
+impl NetworkSubKey {
+    /// Generate a random sub-key delegated by `node_secret_key`.
+    pub fn generate(node_secret_key: &SecretKey) -> Self {
+        let secret_key = SecretKey::from_random(KeyType::ED25519);
+        let delegation = SubKeyDelegation::new(node_secret_key, secret_key.public_key());
+        Self { secret_key, delegation }
+    }
 }
 
 impl From<PartialEncodedChunkWithArcReceipts> for RoutedMessageBody {
@@ -309,6 +696,9 @@ impl Debug for RoutedMessageBody {
             ),
             RoutedMessageBody::Ping(_) => write!(f, "Ping"),
             RoutedMessageBody::Pong(_) => write!(f, "Pong"),
+            RoutedMessageBody::RouteNotFound(response) => {
+                write!(f, "RouteNotFound({}, {:?})", response.request, response.reason)
+            }
             RoutedMessageBody::Unused => write!(f, "Unused"),
         }
     }
@@ -345,12 +735,14 @@ pub enum AccountOrPeerIdOrHash {
     AccountId(AccountId),
     PeerId(PeerId),
     Hash(CryptoHash),
+    /// Any reachable validator of the shard in the current epoch, chosen by the network.
+    ShardValidator(ShardId),
 }
 
 impl AccountOrPeerIdOrHash {
     fn peer_id_or_hash(&self) -> Option<PeerIdOrHash> {
         match self {
-            AccountOrPeerIdOrHash::AccountId(_) => None,
+            AccountOrPeerIdOrHash::AccountId(_) | AccountOrPeerIdOrHash::ShardValidator(_) => None,
             AccountOrPeerIdOrHash::PeerId(peer_id) => Some(PeerIdOrHash::PeerId(peer_id.clone())),
             AccountOrPeerIdOrHash::Hash(hash) => Some(PeerIdOrHash::Hash(hash.clone())),
         }
@@ -464,12 +856,77 @@ impl Message for RoutedMessageFrom {
     type Result = bool;
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum BlockedPorts {
     All,
     Some(HashSet<u16>),
 }
 
+/// Peer the node always tries to stay connected to, given by peer id, address or both, as
+/// `peer_id@ip:port`, `peer_id` or `ip:port`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct AnchorPeer {
+    pub id: Option<PeerId>,
+    pub addr: Option<SocketAddr>,
+}
+
+impl AnchorPeer {
+    /// Whether the peer `peer_info` is this anchor: same peer id if it is known, and same
+    /// address if it is known.
+    pub fn matches(&self, peer_info: &PeerInfo) -> bool {
+        self.id.as_ref().map_or(true, |id| id == &peer_info.id)
+            && self.addr.map_or(true, |addr| {
+                peer_info
+                    .addr
+                    .map_or(false, |peer_addr| canonical_addr(peer_addr) == canonical_addr(addr))
+            })
+    }
+}
+
+impl FromStr for AnchorPeer {
+    type Err = Box<dyn std::error::Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(addr) = s.parse::<SocketAddr>() {
+            return Ok(Self { id: None, addr: Some(addr) });
+        }
+        let peer_info = PeerInfo::from_str(s)?;
+        if peer_info.account_id.is_some() {
+            return Err(Box::new(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Anchor peer {:?} can't have an account id", s),
+            )));
+        }
+        Ok(Self { id: Some(peer_info.id), addr: peer_info.addr })
+    }
+}
+
+impl fmt::Display for AnchorPeer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.id, &self.addr) {
+            (Some(id), Some(addr)) => write!(f, "{}@{}", id, addr),
+            (Some(id), None) => write!(f, "{}", id),
+            (None, Some(addr)) => write!(f, "{}", addr),
+            (None, None) => write!(f, "?"),
+        }
+    }
+}
+
+/// Settings of `NetworkConfig` which can be changed while the node is running, without dropping
+/// existing connections.
+#[derive(Clone, Debug)]
+pub struct ReloadableNetworkConfig {
+    pub boot_nodes: Vec<PeerInfo>,
+    pub peer_hosts: HashMap<PeerId, String>,
+    pub max_num_peers: u32,
+    pub whitelist_nodes: Vec<PeerInfo>,
+    pub blacklist: HashMap<IpAddr, BlockedPorts>,
+    pub edge_rate_limit: u64,
+    pub edge_rate_limit_burst: u64,
+    pub bandwidth: BandwidthConfig,
+    pub anchor_peers: Vec<AnchorPeer>,
+}
+
 /// Configuration for the peer-to-peer manager.
 #[derive(Clone)]
 pub struct NetworkConfig {
@@ -522,6 +979,12 @@ pub struct NetworkConfig {
     /// Peers on blacklist by IP:Port.
     /// Nodes will not accept or try to establish connection to such peers.
     pub blacklist: HashMap<IpAddr, BlockedPorts>,
+    /// Nodes always accepted, even when at `max_num_peers` or when their subnet is full, by peer
+    /// id and IP address. Their connections are never stopped to make room for other peers.
+    pub whitelist_nodes: Vec<PeerInfo>,
+    /// Prefixes of peer ids, e.g. `ed25519:Abc`. Edges and account announcements referencing a
+    /// matching peer are rejected before their signatures are verified.
+    pub denylisted_key_prefixes: Vec<String>,
     /// Flag to disable outbound connections. When this flag is active, nodes will not try to
     /// establish connection with other nodes, but will accept incoming connection if other requirements
     /// are satisfied.
@@ -529,6 +992,100 @@ pub struct NetworkConfig {
     pub outbound_disabled: bool,
     /// Not clear old data, set `true` for archive nodes.
     pub archive: bool,
+    /// Don't send edges back to peers which mostly gossip edges we already know, if they already
+    /// sent us those edges.
+    pub filter_redundant_gossip: bool,
+    /// Period between pings sent to every active peer to measure the latency used to select
+    /// next hops. `None` disables latency measurement.
+    pub latency_ping_period: Option<Duration>,
+    /// Number of threads verifying edge signatures.
+    pub edge_verifier_threads: usize,
+    /// Number of threads computing the routes of big routing graphs. Zero computes them in the
+    /// peer manager.
+    pub routing_threads: usize,
+    /// Whether to answer requests we can't route with `RoutedMessageBody::RouteNotFound`, so
+    /// their authors don't wait for responses that never come. Peers running older versions
+    /// can't decode it.
+    pub send_route_not_found: bool,
+    /// Maximum number of edges verified together with batch signature verification.
+    pub edge_verifier_batch_size: usize,
+    /// Minimum time a peer needs to stay connected before we send it all edges we know.
+    pub heavy_sync_min_connection_age: Duration,
+    /// Maximum number of peers we send all edges we know to at the same time.
+    pub max_concurrent_heavy_syncs: usize,
+    /// Whether to exchange routing tables using IBF with peers whose negotiated protocol version
+    /// supports it. If disabled, we send all edges we know on connection, and answer peers
+    /// starting an IBF sync with all our edges.
+    pub ibf_sync_enabled: bool,
+    /// Peers supporting IBF sync exchange all their edges instead if either side knows fewer
+    /// edges than this, when the IBF overhead isn't worth it.
+    pub ibf_sync_min_edges: u64,
+    /// Edges per second each peer may send us, excess edges are dropped. Zero disables the limit.
+    pub edge_rate_limit: u64,
+    /// Edges each peer may send at once before being rate limited.
+    pub edge_rate_limit_burst: u64,
+    /// Whether validators keep direct connections to the other validators of the epoch, which
+    /// are used for messages routed to them.
+    pub tier1_enabled: bool,
+    /// IP families used to listen and to connect to peers.
+    pub ip_family: IpFamily,
+    /// Proxy outbound connections go through, if any.
+    pub proxy: Option<ProxyConfig>,
+    /// Relays which hide our address. If any, we only connect to them, only accept their
+    /// connections, and sign a `ProxyAnnouncement` so other nodes route messages to us through
+    /// them.
+    pub relays: Vec<PeerInfo>,
+    /// Peers we always try to stay connected to. They are redialed as soon as they disconnect,
+    /// never evicted, and slots are reserved for them while they aren't connected.
+    pub anchor_peers: Vec<AnchorPeer>,
+    /// How long an anchor peer can stay disconnected before it's reported as unreachable.
+    pub anchor_unreachable_timeout: Duration,
+    /// Number of active peers blocks are sent to, and transactions are gossiped to when their
+    /// validator can't be reached, sampled by reach and reliability. 0 sends blocks to every
+    /// active peer and never gossips transactions.
+    pub gossip_fanout: u32,
+    /// Addresses listened at in addition to `addr`, e.g. on other interfaces or ports, each with
+    /// its advertisement policy. `addr` is advertised to every peer. `PeerInfo` still carries a
+    /// single address, so each peer only learns one of them, see `advertised_addr`.
+    pub extra_listen_addrs: Vec<ListenAddr>,
+    /// Host names of peers, connected to through the proxy without resolving them locally.
+    pub peer_hosts: HashMap<PeerId, String>,
+    /// Mutual TLS with the peers whose certificate is pinned, if any.
+    pub tls: Option<TlsConfig>,
+    /// File mapping networks to regions, used to export the number of reachable peers and the
+    /// latency of active peers per region.
+    pub peer_regions_file: Option<PathBuf>,
+    /// Maximum number of inbound peers from the same /24 IPv4 or /48 IPv6 subnet, so one operator
+    /// can't take all our inbound slots. Zero disables the limit.
+    pub max_inbound_peers_per_subnet: u32,
+    /// Number of the healthiest peers saved on disk and dialed on startup before the boot nodes.
+    /// Zero disables the cache.
+    pub cold_start_peers: usize,
+    /// How long to wait for a connection to one of the cached peers before also connecting to
+    /// the boot nodes and other known peers.
+    pub cold_start_timeout: Duration,
+    /// Whether to map the listening port on the NAT gateway with NAT-PMP or UPnP, and advertise
+    /// the external address of the mapping to peers.
+    pub nat_port_mapping: bool,
+    /// Names whose TXT and SRV records advertise boot nodes, see `dns_seed`.
+    pub dns_seeds: Vec<String>,
+    /// How often DNS seeds are resolved again.
+    pub dns_seeds_period: Duration,
+    /// Upload and download rate limits, per peer and for all peers together.
+    pub bandwidth: BandwidthConfig,
+    /// Fanout and budget of edge broadcasts, and period of edge digests.
+    pub edge_gossip: EdgeGossipConfig,
+    /// Bounds of the messages queued for each peer while its connection is congested.
+    pub send_queue: SendQueueConfig,
+    /// Compression of large messages to peers which support it.
+    pub compression: CompressionConfig,
+    /// Streaming of the routing table to hot-standby replicas, or from the primary we replicate.
+    pub routing_replica: RoutingReplicaConfig,
+    /// Sizes of caches and timeouts used by the routing table.
+    pub routing_table: RoutingTableConfig,
+    /// Hot key signing our edges with peers supporting `ProtocolFeature::NetworkSubKeys`, so the
+    /// node key is only used for the handshakes of outbound connections and with older peers.
+    pub sub_key: Option<NetworkSubKey>,
 }
 
 impl NetworkConfig {
@@ -562,44 +1119,612 @@ impl NetworkConfig {
             highest_peer_horizon: 5,
             push_info_period: Duration::from_millis(100),
             blacklist: HashMap::new(),
+            whitelist_nodes: vec![],
+            denylisted_key_prefixes: vec![],
             outbound_disabled: false,
             archive: false,
+            filter_redundant_gossip: false,
+            latency_ping_period: None,
+            edge_verifier_threads: 4,
+            routing_threads: 2,
+            send_route_not_found: false,
+            edge_verifier_batch_size: 256,
+            heavy_sync_min_connection_age: Duration::from_secs(3),
+            max_concurrent_heavy_syncs: 8,
+            ibf_sync_enabled: true,
+            ibf_sync_min_edges: 100,
+            edge_rate_limit: 2_000,
+            edge_rate_limit_burst: 100_000,
+            tier1_enabled: false,
+            ip_family: IpFamily::Dual,
+            proxy: None,
+            relays: vec![],
+            anchor_peers: vec![],
+            anchor_unreachable_timeout: Duration::from_secs(300),
+            gossip_fanout: 0,
+            extra_listen_addrs: vec![],
+            peer_hosts: HashMap::new(),
+            tls: None,
+            peer_regions_file: None,
+            max_inbound_peers_per_subnet: 8,
+            cold_start_peers: 10,
+            cold_start_timeout: Duration::from_secs(5),
+            nat_port_mapping: false,
+            dns_seeds: vec![],
+            dns_seeds_period: Duration::from_secs(30 * 60),
+            bandwidth: BandwidthConfig::default(),
+            edge_gossip: EdgeGossipConfig::default(),
+            send_queue: SendQueueConfig::default(),
+            compression: CompressionConfig::default(),
+            routing_replica: RoutingReplicaConfig::default(),
+            routing_table: RoutingTableConfig::default(),
+            sub_key: None,
+        }
+    }
+
+    /// Replace the settings which can be changed while the node is running by `reloadable`.
+    /// Returns the names of the settings which changed.
+    pub fn apply_reloadable(&mut self, reloadable: ReloadableNetworkConfig) -> Vec<&'static str> {
+        let mut changed = vec![];
+        macro_rules! apply {
+            ($($field:ident),*) => {$(
+                if self.$field != reloadable.$field {
+                    self.$field = reloadable.$field;
+                    changed.push(stringify!($field));
+                }
+            )*};
         }
+        apply!(
+            boot_nodes,
+            peer_hosts,
+            max_num_peers,
+            whitelist_nodes,
+            blacklist,
+            edge_rate_limit,
+            edge_rate_limit_burst,
+            bandwidth,
+            anchor_peers
+        );
+        changed
+    }
+
+    /// Addresses to listen at: `addr`, advertised to every peer, then `extra_listen_addrs`.
+    pub fn listen_addrs(&self) -> Vec<ListenAddr> {
+        self.addr
+            .map(|addr| ListenAddr { addr, advertise: AdvertisePolicy::Public })
+            .into_iter()
+            .chain(self.extra_listen_addrs.iter().cloned())
+            .collect()
+    }
+
+    /// First address listened at with the `advertise` policy.
+    pub fn listen_addr(&self, advertise: AdvertisePolicy) -> Option<SocketAddr> {
+        self.listen_addrs()
+            .into_iter()
+            .find(|listen| listen.advertise == advertise)
+            .map(|listen| listen.addr)
     }
 
-    pub fn verify(&self) {
+    /// Address advertised to a peer, `whitelisted` or not, `external_addr` being the address
+    /// mapped on our NAT gateway if any. Whitelisted nodes get the address listened at for them
+    /// if any, other peers the public one. Hidden addresses are never advertised.
+    ///
+    /// Only one address is advertised: the handshake carries a single `PeerInfo`, and a peer
+    /// advertising several addresses would need a new version of it.
+    pub fn advertised_addr(
+        &self,
+        whitelisted: bool,
+        external_addr: Option<SocketAddr>,
+    ) -> Option<SocketAddr> {
+        if whitelisted {
+            if let Some(addr) = self.listen_addr(AdvertisePolicy::Whitelisted) {
+                return Some(addr);
+            }
+        }
+        external_addr.or_else(|| self.listen_addr(AdvertisePolicy::Public))
+    }
+
+    /// Check that values are consistent with each other and within supported bounds. Returns
+    /// every problem found. Settings which are valid but likely unintended are only logged.
+    pub fn verify(&self) -> Result<(), String> {
+        let mut errors = vec![];
+
+        if self.max_num_peers == 0 || self.max_num_peers as usize > MAX_NUM_PEERS {
+            errors.push(format!(
+                "max_num_peers({}) must be between 1 and {}",
+                self.max_num_peers, MAX_NUM_PEERS
+            ));
+        }
+
         if self.ideal_connections_lo + 1 >= self.ideal_connections_hi {
-            error!(target: "network",
-            "Invalid ideal_connections values. lo({}) > hi({}).",
-            self.ideal_connections_lo, self.ideal_connections_hi);
+            errors.push(format!(
+                "ideal_connections_lo({}) must be at least 2 below ideal_connections_hi({})",
+                self.ideal_connections_lo, self.ideal_connections_hi
+            ));
         }
 
         if self.ideal_connections_hi >= self.max_num_peers {
-            error!(target: "network",
+            warn!(target: "network",
                 "max_num_peers({}) is below ideal_connections_hi({}) which may lead to connection saturation and declining new connections.",
                 self.max_num_peers, self.ideal_connections_hi
             );
         }
 
-        if self.outbound_disabled {
-            warn!(target: "network", "Outbound connections are disabled.");
+        if self.safe_set_size <= self.minimum_outbound_peers {
+            errors.push(format!(
+                "safe_set_size({}) must be larger than minimum_outbound_peers({})",
+                self.safe_set_size, self.minimum_outbound_peers
+            ));
         }
 
-        if self.safe_set_size <= self.minimum_outbound_peers {
-            error!(target: "network",
-                "safe_set_size({}) must be larger than minimum_outbound_peers({}).",
-                self.safe_set_size,
-                self.minimum_outbound_peers
+        if UPDATE_INTERVAL_LAST_TIME_RECEIVED_MESSAGE * 2 > self.peer_recent_time_window {
+            errors.push(format!(
+                "peer_recent_time_window({:?}) must be at least twice the update interval of the last time a message was received ({:?})",
+                self.peer_recent_time_window, UPDATE_INTERVAL_LAST_TIME_RECEIVED_MESSAGE
+            ));
+        }
+
+        if self.handshake_timeout == Duration::ZERO {
+            errors.push("handshake_timeout must be positive".to_string());
+        }
+
+        if self.edge_verifier_batch_size == 0 {
+            errors.push("edge_verifier_batch_size must be positive".to_string());
+        }
+
+        if self.max_concurrent_heavy_syncs == 0 {
+            errors.push(
+                "max_concurrent_heavy_syncs must be positive, new peers would never get the routing table"
+                    .to_string(),
             );
         }
 
-        if UPDATE_INTERVAL_LAST_TIME_RECEIVED_MESSAGE * 2 > self.peer_recent_time_window {
-            error!(
-                target: "network",
-                "Very short peer_recent_time_window({}). it should be at least twice update_interval_last_time_received_message({}).",
-                self.peer_recent_time_window.as_secs(), UPDATE_INTERVAL_LAST_TIME_RECEIVED_MESSAGE.as_secs()
+        if self.send_queue.normal_limit == 0 || self.send_queue.bulk_limit == 0 {
+            errors.push("send_queue limits must be positive".to_string());
+        }
+
+        if !(1..=22).contains(&self.compression.zstd_level) {
+            errors.push("compression zstd_level must be between 1 and 22".to_string());
+        }
+
+        if let (Some(publish_socket), Some(primary_socket)) =
+            (&self.routing_replica.publish_socket, &self.routing_replica.primary_socket)
+        {
+            if publish_socket == primary_socket {
+                errors.push(format!(
+                    "routing_replica publish_socket and primary_socket are both {}",
+                    publish_socket.display()
+                ));
+            }
+        }
+
+        if self.edge_gossip.digest_interval == Duration::ZERO {
+            errors.push("edge_gossip.digest_interval must be positive".to_string());
+        }
+
+        if self.edge_rate_limit > 0 && self.edge_rate_limit_burst == 0 {
+            errors.push(format!(
+                "edge_rate_limit_burst must be positive when edge_rate_limit({}) is set, set edge_rate_limit to 0 to disable the limit",
+                self.edge_rate_limit
+            ));
+        }
+
+        if let Some(sub_key) = &self.sub_key {
+            if !checked_feature!(
+                "protocol_feature_network_sub_keys",
+                NetworkSubKeys,
+                PROTOCOL_VERSION
+            ) {
+                errors.push(
+                    "sub_key requires a build with protocol_feature_network_sub_keys".to_string(),
+                );
+            }
+            let delegation = &sub_key.delegation;
+            if delegation.peer_id.public_key() != self.public_key
+                || delegation.sub_key != sub_key.secret_key.public_key()
+                || !delegation.verify()
+            {
+                errors.push(
+                    "sub_key delegation must be signed by the node key for this sub-key"
+                        .to_string(),
+                );
+            }
+        }
+
+        let listen_addrs = self.listen_addrs();
+        for (i, listen) in listen_addrs.iter().enumerate() {
+            if !self.ip_family.allows(&listen.addr) && !listen.addr.ip().is_unspecified() {
+                errors.push(format!(
+                    "listening address {} is not allowed by ip_family({:?})",
+                    listen.addr, self.ip_family
+                ));
+            }
+            if listen_addrs[..i].iter().any(|other| other.addr == listen.addr) {
+                errors.push(format!("listening address {} is configured twice", listen.addr));
+            }
+        }
+
+        for node in self.whitelist_nodes.iter() {
+            if node.addr.is_none() {
+                errors.push(format!("whitelisted node {} has no address", node.id));
+            }
+        }
+
+        for relay in self.relays.iter() {
+            if relay.addr.is_none() {
+                errors.push(format!("relay {} has no address", relay.id));
+            }
+        }
+
+        if self.relays.len() > MAX_PROXY_RELAYS {
+            errors.push(format!("at most {} relays are supported", MAX_PROXY_RELAYS));
+        }
+
+        if !self.relays.is_empty() && (self.nat_port_mapping || self.tier1_enabled) {
+            errors.push(
+                "relays can't be used with nat_port_mapping or tier1_enabled, which expose our address"
+                    .to_string(),
             );
         }
+
+        if self.anchor_peers.len() > self.max_num_peers as usize {
+            errors.push(format!(
+                "{} anchor_peers don't fit in max_num_peers ({})",
+                self.anchor_peers.len(),
+                self.max_num_peers
+            ));
+        }
+
+        if !self.anchor_peers.is_empty() && !self.relays.is_empty() {
+            errors.push("anchor_peers can't be used with relays".to_string());
+        }
+
+        if self.denylisted_key_prefixes.iter().any(|prefix| prefix.is_empty()) {
+            errors.push("denylisted_key_prefixes can't contain an empty prefix".to_string());
+        }
+
+        if !self.dns_seeds.is_empty() && self.dns_seeds_period == Duration::ZERO {
+            errors.push("dns_seeds_period must be positive".to_string());
+        }
+
+        if self.nat_port_mapping && self.addr.is_none() {
+            errors.push("nat_port_mapping requires a listening address".to_string());
+        }
+
+        match &self.proxy {
+            Some(proxy) => {
+                if let Err(err) = proxy.verify() {
+                    errors.push(err);
+                }
+            }
+            None if !self.peer_hosts.is_empty() => {
+                errors.push("peers given by host name require a proxy".to_string());
+            }
+            None => {}
+        }
+
+        if let Some(Err(err)) = self.tls.as_ref().map(TlsConfig::fingerprints) {
+            errors.push(err);
+        }
+
+        if let Err(err) = self.routing_table.verify() {
+            errors.push(err);
+        }
+
+        if self.outbound_disabled {
+            warn!(target: "network", "Outbound connections are disabled.");
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("; "))
+        }
+    }
+}
+
+/// IP families the node listens on and connects to peers with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IpFamily {
+    /// Both IPv4 and IPv6. An unspecified listening address accepts connections of both.
+    Dual,
+    V4,
+    V6,
+}
+
+impl Default for IpFamily {
+    fn default() -> Self {
+        IpFamily::Dual
+    }
+}
+
+impl IpFamily {
+    pub fn allows(&self, addr: &SocketAddr) -> bool {
+        match self {
+            IpFamily::Dual => true,
+            IpFamily::V4 => canonical_addr(*addr).is_ipv4(),
+            IpFamily::V6 => canonical_addr(*addr).is_ipv6(),
+        }
+    }
+
+    /// Addresses to bind the listener of `addr` to, in order of preference. An unspecified
+    /// address is replaced by the unspecified address of the family, for dual stack `[::]`
+    /// first, which accepts IPv4 connections on most systems, then `0.0.0.0`.
+    pub fn listen_addrs(&self, addr: SocketAddr) -> Vec<SocketAddr> {
+        if !addr.ip().is_unspecified() {
+            return vec![addr];
+        }
+        let v4 = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), addr.port());
+        let v6 = SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), addr.port());
+        match self {
+            IpFamily::Dual => vec![v6, v4],
+            IpFamily::V4 => vec![v4],
+            IpFamily::V6 => vec![v6],
+        }
+    }
+
+    /// Address to connect to among the resolved `addrs`. IPv6 addresses are preferred over
+    /// IPv4 ones if `prefer_v6`, and the other way around otherwise.
+    pub fn select_addr(
+        &self,
+        addrs: impl IntoIterator<Item = SocketAddr>,
+        prefer_v6: bool,
+    ) -> Option<SocketAddr> {
+        addrs
+            .into_iter()
+            .filter(|addr| self.allows(addr))
+            .min_by_key(|addr| addr.is_ipv6() != prefer_v6)
+    }
+}
+
+/// Which peers the address of a listener is advertised to in the handshake.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdvertisePolicy {
+    /// Every peer.
+    Public,
+    /// Only whitelisted nodes, e.g. for an interface on a private network shared with them.
+    Whitelisted,
+    /// No peer. Connections are accepted but the address isn't advertised.
+    Hidden,
+}
+
+impl Default for AdvertisePolicy {
+    fn default() -> Self {
+        AdvertisePolicy::Public
+    }
+}
+
+/// Address listened at for connections of peers, and to whom it's advertised.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListenAddr {
+    pub addr: SocketAddr,
+    #[serde(default)]
+    pub advertise: AdvertisePolicy,
+}
+
+/// Protocol spoken with the proxy outbound connections go through.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProxyProtocol {
+    /// SOCKS5, host names of peers are resolved by the proxy.
+    Socks5,
+    /// HTTP `CONNECT` tunnels.
+    Http,
+}
+
+/// Upload and download rate limits in bytes per second. Zero disables a limit. Peers exceeding
+/// a limit are slowed down rather than disconnected.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BandwidthConfig {
+    /// Bytes per second sent to each peer.
+    pub peer_upload_bytes_per_sec: u64,
+    /// Bytes per second received from each peer.
+    pub peer_download_bytes_per_sec: u64,
+    /// Bytes per second sent to all peers together.
+    pub upload_bytes_per_sec: u64,
+    /// Bytes per second received from all peers together.
+    pub download_bytes_per_sec: u64,
+}
+
+/// Bounded broadcast of new edges to the peers which can pull the edges they miss from digests.
+/// Zero fanout or budget disables the bound.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EdgeGossipConfig {
+    /// Number of peers each new edge is pushed to. The other peers learn it from digests.
+    pub fanout: usize,
+    /// Number of times the same edge is pushed, whatever its nonce, before it is only advertised
+    /// in digests. Bounds the traffic caused by flapping connections.
+    pub broadcast_budget: u32,
+    /// Period between digests of the recently changed edges sent to each peer.
+    pub digest_interval: Duration,
+}
+
+impl Default for EdgeGossipConfig {
+    fn default() -> Self {
+        Self { fanout: 0, broadcast_budget: 0, digest_interval: Duration::from_secs(10) }
+    }
+}
+
+/// What to do when a message arrives at a full send queue.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DropPolicy {
+    /// Drop the oldest queued message of the destination with the most queued bytes, it is the
+    /// most likely to be outdated.
+    DropOldest,
+    /// Drop the arriving message, the sender will retry.
+    DropNewest,
+}
+
+/// Bounds of the messages held back for a peer while its connection is congested. Handshakes,
+/// block approvals and chunk parts are never queued nor dropped.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SendQueueConfig {
+    /// Maximum number of queued normal priority messages.
+    pub normal_limit: usize,
+    pub normal_drop_policy: DropPolicy,
+    /// Maximum number of queued bulk messages: transactions, state and epoch sync responses.
+    pub bulk_limit: usize,
+    pub bulk_drop_policy: DropPolicy,
+    /// Peers whose send queue stays saturated for this long are disconnected: the queue keeps
+    /// dropping messages and doesn't drain below half of its limit.
+    pub max_saturation: Duration,
+}
+
+impl Default for SendQueueConfig {
+    fn default() -> Self {
+        Self {
+            normal_limit: 1_000,
+            normal_drop_policy: DropPolicy::DropOldest,
+            bulk_limit: 100,
+            bulk_drop_policy: DropPolicy::DropOldest,
+            max_saturation: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Algorithm messages to peers are compressed with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompressionAlgorithm {
+    /// Fast, for links where CPU time matters more than bandwidth.
+    Lz4,
+    /// Better ratio at a higher CPU cost, depending on `zstd_level`.
+    Zstd,
+}
+
+/// Compression of large messages, e.g. routing table syncs, state parts and blocks. Only used
+/// with peers which also enabled it, negotiated in the handshake. Peers decompress messages
+/// compressed with either algorithm.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CompressionConfig {
+    pub enabled: bool,
+    pub algorithm: CompressionAlgorithm,
+    /// Messages smaller than this are sent uncompressed.
+    pub threshold_bytes: usize,
+    pub zstd_level: i32,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            algorithm: CompressionAlgorithm::Lz4,
+            threshold_bytes: 16 * 1024,
+            zstd_level: 3,
+        }
+    }
+}
+
+/// Unix sockets over which a primary node streams the edges and account announcements it learns,
+/// so hot-standby nodes on the same host keep an identical routing table and can take over right
+/// away. A node can be both the replica of a primary and the primary of other replicas.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RoutingReplicaConfig {
+    /// Socket replicas subscribe to our routing events on.
+    pub publish_socket: Option<PathBuf>,
+    /// Socket of the primary whose routing table we replicate.
+    pub primary_socket: Option<PathBuf>,
+}
+
+/// Proxy outbound connections to peers go through.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProxyConfig {
+    pub protocol: ProxyProtocol,
+    /// Address of the proxy, `host:port`.
+    pub addr: String,
+    /// Credentials to authenticate to the proxy with, if it requires them.
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+impl ProxyConfig {
+    pub fn verify(&self) -> Result<(), String> {
+        match self.addr.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {}
+            _ => return Err(format!("proxy address {:?} must be host:port", self.addr)),
+        }
+        if self.username.is_some() != self.password.is_some() {
+            return Err("proxy username and password must be set together".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Mutual TLS beneath the handshake. Connections with the peers whose certificate is pinned use
+/// TLS, the others don't.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// PEM file with our certificate chain.
+    pub cert_file: PathBuf,
+    /// PEM file with the PKCS#8 private key of our certificate.
+    pub key_file: PathBuf,
+    /// SHA-256 fingerprint of the certificate of each peer, in hex, optionally separated by
+    /// colons as printed by `openssl x509 -fingerprint -sha256`.
+    pub pinned_peers: HashMap<PeerId, String>,
+}
+
+impl TlsConfig {
+    /// Parsed fingerprints of the pinned certificates.
+    pub fn fingerprints(&self) -> Result<HashMap<PeerId, CryptoHash>, String> {
+        self.pinned_peers
+            .iter()
+            .map(|(peer_id, fingerprint)| {
+                let digits: Vec<u8> = fingerprint.bytes().filter(|digit| *digit != b':').collect();
+                let mut bytes = [0u8; 32];
+                if digits.len() != 2 * bytes.len() {
+                    return Err(format!("invalid certificate fingerprint of {}", peer_id));
+                }
+                for (byte, pair) in bytes.iter_mut().zip(digits.chunks(2)) {
+                    *byte = std::str::from_utf8(pair)
+                        .ok()
+                        .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                        .ok_or_else(|| format!("invalid certificate fingerprint of {}", peer_id))?;
+                }
+                Ok((peer_id.clone(), CryptoHash(bytes)))
+            })
+            .collect()
+    }
+}
+
+/// Replaces IPv4-mapped IPv6 addresses, which dual stack listeners see IPv4 peers with, by the
+/// IPv4 address, so the same peer has the same address whichever way it connected.
+pub fn canonical_addr(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) => match v6.ip().segments() {
+            [0, 0, 0, 0, 0, 0xffff, ..] => {
+                SocketAddr::new(v6.ip().to_ipv4().unwrap().into(), v6.port())
+            }
+            _ => addr,
+        },
+        SocketAddr::V4(_) => addr,
+    }
+}
+
+/// Whether a peer connected from `old` which connects again from `new` moved to another host
+/// address, e.g. because its DHCP lease changed, leaving the connection from `old` stale.
+pub fn is_address_change(old: Option<SocketAddr>, new: Option<SocketAddr>) -> bool {
+    match (old, new) {
+        (Some(old), Some(new)) => canonical_addr(old).ip() != canonical_addr(new).ip(),
+        _ => false,
+    }
+}
+
+/// Subnet of `ip` connection limits apply to: its /24 for IPv4 and its /48 for IPv6, which are
+/// typically allocated to a single operator.
+pub fn subnet_of(ip: IpAddr) -> IpAddr {
+    match canonical_addr((ip, 0).into()).ip() {
+        IpAddr::V4(ip) => Ipv4Addr::from(u32::from(ip) & !0xff).into(),
+        IpAddr::V6(ip) => Ipv6Addr::from(u128::from(ip) & !(u128::MAX >> 48)).into(),
     }
 }
 
@@ -781,6 +1906,9 @@ pub struct Ban {
 pub enum PeerManagerRequest {
     BanPeer(ReasonForBan),
     UnregisterPeer,
+    /// The peer reconnected from another address, see `is_address_change`. The connection is
+    /// closed without unregistering the peer, whose state moved to the new connection.
+    ConnectionMigrated,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -788,6 +1916,10 @@ pub struct KnownProducer {
     pub account_id: AccountId,
     pub addr: Option<SocketAddr>,
     pub peer_id: PeerId,
+    /// Next hops towards `peer_id` according to the routing table, empty if it's unreachable.
+    /// `None` if unknown.
+    #[serde(skip)]
+    pub next_hops: Option<Vec<PeerId>>,
 }
 
 #[derive(PartialEq, Eq, Clone, Debug, BorshSerialize, BorshDeserialize)]
@@ -906,8 +2038,9 @@ pub enum NetworkViewClientResponses {
     },
     /// Response to state request.
     StateResponse(Box<StateResponseInfo>),
-    /// Valid announce accounts.
-    AnnounceAccount(Vec<AnnounceAccount>),
+    /// Valid announce accounts, and the valid ones which were not newer than the last
+    /// announcement of their account. Announcements dropped for other reasons are in neither.
+    AnnounceAccount { accounts: Vec<AnnounceAccount>, stale: Vec<AnnounceAccount> },
     /// A response to a request for a light client block during Epoch Sync
     EpochSyncResponse(EpochSyncResponse),
     /// A response to a request for headers and proofs during Epoch Sync
@@ -1038,6 +2171,297 @@ mod tests {
         };
     }
 
+    #[test]
+    fn test_routing_table_config_verify() {
+        assert_eq!(RoutingTableConfig::default().verify(), Ok(()));
+        assert!(RoutingTableConfig { route_back_cache_size: 0, ..Default::default() }
+            .verify()
+            .is_err());
+        assert!(RoutingTableConfig {
+            route_back_cache_size: 1_000_000,
+            route_back_cache_remove_batch: 1_000,
+            ..Default::default()
+        }
+        .verify()
+        .is_ok());
+        assert!(RoutingTableConfig { route_back_cache_peer_quota: 0, ..Default::default() }
+            .verify()
+            .is_err());
+        assert!(RoutingTableConfig {
+            save_peers_after_time: SAVE_PEERS_MAX_TIME * 2,
+            ..Default::default()
+        }
+        .verify()
+        .is_err());
+        assert!(RoutingTableConfig {
+            strategy: RoutingStrategy::LatencyAware,
+            shadow_strategy: Some(RoutingStrategy::LatencyAware),
+            ..Default::default()
+        }
+        .verify()
+        .is_err());
+        assert!(RoutingTableConfig {
+            strategy: RoutingStrategy::LatencyAware,
+            shadow_strategy: Some(RoutingStrategy::RoundRobin),
+            ..Default::default()
+        }
+        .verify()
+        .is_ok());
+    }
+
+    #[test]
+    fn test_route_not_found_serialization() {
+        let body = RoutedMessageBody::RouteNotFound(RouteNotFound {
+            request: CryptoHash::default(),
+            target: PeerIdOrHash::Hash(CryptoHash::default()),
+            reason: FindRouteError::PeerNotFound,
+        });
+        let bytes = body.try_to_vec().unwrap();
+        // Appended after the existing variants, so older messages keep their encoding.
+        assert_eq!(bytes[0], 19);
+        assert_eq!(RoutedMessageBody::try_from_slice(&bytes).unwrap(), body);
+    }
+
+    #[test]
+    fn test_proxy_announcement_signature() {
+        let secret_key = SecretKey::from_seed(KeyType::ED25519, "validator");
+        let relays = vec![PeerId::random(), PeerId::random()];
+        let announcement = ProxyAnnouncement::new(&secret_key, relays.clone(), 7);
+        assert_eq!(announcement.peer_id, PeerId::new(secret_key.public_key()));
+        assert!(announcement.verify());
+
+        let mut tampered = announcement.clone();
+        tampered.relays.pop();
+        assert!(!tampered.verify());
+        let mut tampered = announcement.clone();
+        tampered.timestamp += 1;
+        assert!(!tampered.verify());
+
+        // A node can't be its own relay.
+        let own = ProxyAnnouncement::new(&secret_key, vec![announcement.peer_id.clone()], 7);
+        assert!(!own.verify());
+        assert!(!ProxyAnnouncement::new(&secret_key, vec![], 7).verify());
+    }
+
+    #[test]
+    fn test_ping_nonce_wraparound() {
+        assert_eq!(next_ping_nonce(7), 8);
+        assert_eq!(next_ping_nonce(u64::MAX), 0);
+        assert!(ping_nonce_precedes(7, 8));
+        assert!(!ping_nonce_precedes(8, 7));
+        assert!(!ping_nonce_precedes(8, 8));
+        assert!(ping_nonce_precedes(u64::MAX, 0));
+        assert!(ping_nonce_precedes(u64::MAX - 1, 3));
+        assert!(!ping_nonce_precedes(3, u64::MAX - 1));
+    }
+
+    #[test]
+    fn test_prune_config() {
+        let (save_after, prune_after) = (Duration::from_secs(10), Duration::from_secs(20));
+        assert!(PruneConfig::new(prune_after, save_after, false).is_err());
+        assert!(PruneConfig::new(save_after, save_after, false).is_ok());
+        let prune = PruneConfig::new(save_after, prune_after, false).unwrap();
+        let just_below = |duration: Duration| duration - Duration::from_millis(1);
+
+        // Kept.
+        assert!(!prune.should_save(just_below(save_after)));
+        // Saved, once pruning happens.
+        assert!(prune.should_save(save_after));
+        assert!(!prune.should_prune(just_below(prune_after)));
+        // Pruning happens.
+        assert!(prune.should_prune(prune_after));
+        assert!(prune.should_save(prune_after));
+
+        let forced = PruneConfig::forced(save_after);
+        assert!(forced.should_prune(Duration::from_secs(0)));
+        assert!(!forced.should_save(just_below(save_after)));
+        assert!(forced.should_save(save_after));
+
+        let config = RoutingTableConfig::default();
+        assert_eq!(
+            config.prune_config(),
+            PruneConfig::new(config.save_peers_after_time, config.save_peers_max_time, false)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_network_config_verify() {
+        assert_eq!(NetworkConfig::from_seed("test", 0).verify(), Ok(()));
+        let mut config = NetworkConfig::from_seed("test", 0);
+        config.max_num_peers = MAX_NUM_PEERS as u32 + 1;
+        config.safe_set_size = config.minimum_outbound_peers;
+        config.routing_table.route_back_cache_size = 0;
+        config.whitelist_nodes =
+            vec![PeerInfo { id: PeerId::random(), addr: None, account_id: None }];
+        config.max_concurrent_heavy_syncs = 0;
+        // Every problem is reported at once.
+        let err = config.verify().unwrap_err();
+        assert_eq!(err.split("; ").count(), 5, "{}", err);
+        assert!(err.contains("max_concurrent_heavy_syncs"));
+        assert!(err.contains("max_num_peers"));
+        assert!(err.contains("safe_set_size"));
+        assert!(err.contains("route_back_cache_size"));
+        assert!(err.contains("has no address"));
+    }
+
+    #[test]
+    fn test_listen_addrs() {
+        let mut config = NetworkConfig::from_seed("test", 0);
+        let public = config.addr.unwrap();
+        let private: SocketAddr = "10.0.0.1:24568".parse().unwrap();
+        config.extra_listen_addrs = vec![
+            ListenAddr { addr: private, advertise: AdvertisePolicy::Whitelisted },
+            ListenAddr { addr: "[::]:24569".parse().unwrap(), advertise: AdvertisePolicy::Hidden },
+        ];
+        assert_eq!(config.listen_addrs().len(), 3);
+        assert_eq!(config.listen_addr(AdvertisePolicy::Public), Some(public));
+        assert_eq!(config.listen_addr(AdvertisePolicy::Whitelisted), Some(private));
+        assert_eq!(config.verify(), Ok(()));
+
+        config
+            .extra_listen_addrs
+            .push(ListenAddr { addr: public, advertise: AdvertisePolicy::Hidden });
+        assert!(config.verify().unwrap_err().contains("configured twice"));
+    }
+
+    #[test]
+    fn test_advertised_addr() {
+        let mut config = NetworkConfig::from_seed("test", 0);
+        let public = config.addr.unwrap();
+        let private: SocketAddr = "10.0.0.1:24568".parse().unwrap();
+        let hidden: SocketAddr = "10.0.0.2:24569".parse().unwrap();
+        let external: SocketAddr = "1.2.3.4:24567".parse().unwrap();
+        config.extra_listen_addrs = vec![
+            ListenAddr { addr: hidden, advertise: AdvertisePolicy::Hidden },
+            ListenAddr { addr: private, advertise: AdvertisePolicy::Whitelisted },
+        ];
+        assert_eq!(config.advertised_addr(false, None), Some(public));
+        assert_eq!(config.advertised_addr(false, Some(external)), Some(external));
+        assert_eq!(config.advertised_addr(true, None), Some(private));
+        assert_eq!(config.advertised_addr(true, Some(external)), Some(private));
+
+        // Without a public address, only whitelisted nodes learn one.
+        config.addr = None;
+        assert_eq!(config.advertised_addr(false, None), None);
+        assert_eq!(config.advertised_addr(true, None), Some(private));
+        config.extra_listen_addrs.retain(|listen| listen.advertise == AdvertisePolicy::Hidden);
+        assert_eq!(config.advertised_addr(true, None), None);
+    }
+
+    #[test]
+    fn test_network_config_apply_reloadable() {
+        let mut config = NetworkConfig::from_seed("test", 0);
+        let mut reloadable = ReloadableNetworkConfig {
+            boot_nodes: config.boot_nodes.clone(),
+            peer_hosts: config.peer_hosts.clone(),
+            max_num_peers: config.max_num_peers,
+            whitelist_nodes: config.whitelist_nodes.clone(),
+            blacklist: config.blacklist.clone(),
+            edge_rate_limit: config.edge_rate_limit,
+            edge_rate_limit_burst: config.edge_rate_limit_burst,
+            bandwidth: config.bandwidth.clone(),
+            anchor_peers: config.anchor_peers.clone(),
+        };
+        assert!(config.apply_reloadable(reloadable.clone()).is_empty());
+
+        reloadable.max_num_peers += 1;
+        reloadable.bandwidth.upload_bytes_per_sec = 1_000_000;
+        assert_eq!(config.apply_reloadable(reloadable), vec!["max_num_peers", "bandwidth"]);
+        assert_eq!(config.max_num_peers, 11);
+        assert_eq!(config.bandwidth.upload_bytes_per_sec, 1_000_000);
+    }
+
+    #[test]
+    fn test_anchor_peer() {
+        let peer_id = PeerId::random();
+        let addr: SocketAddr = "1.2.3.4:24567".parse().unwrap();
+        for anchor in [
+            AnchorPeer { id: Some(peer_id.clone()), addr: Some(addr) },
+            AnchorPeer { id: Some(peer_id.clone()), addr: None },
+            AnchorPeer { id: None, addr: Some(addr) },
+        ] {
+            assert_eq!(anchor.to_string().parse::<AnchorPeer>().unwrap(), anchor);
+            assert!(anchor.matches(&PeerInfo::new(peer_id.clone(), addr)));
+        }
+        assert!(format!("{}@{}@test", peer_id, addr).parse::<AnchorPeer>().is_err());
+
+        let anchor = AnchorPeer { id: None, addr: Some(addr) };
+        let mapped: SocketAddr = "[::ffff:1.2.3.4]:24567".parse().unwrap();
+        assert!(anchor.matches(&PeerInfo::new(PeerId::random(), mapped)));
+        assert!(!anchor.matches(&PeerInfo::new(peer_id.clone(), "1.2.3.4:24568".parse().unwrap())));
+        assert!(!anchor.matches(&PeerInfo { id: peer_id, addr: None, account_id: None }));
+    }
+
+    #[test]
+    fn test_address_change() {
+        let old: SocketAddr = "1.2.3.4:24567".parse().unwrap();
+        let mapped: SocketAddr = "[::ffff:1.2.3.4]:24568".parse().unwrap();
+        let new: SocketAddr = "1.2.3.5:24567".parse().unwrap();
+        assert!(is_address_change(Some(old), Some(new)));
+        // Another port of the same host, or an unknown address, isn't a move.
+        assert!(!is_address_change(Some(old), Some(mapped)));
+        assert!(!is_address_change(None, Some(new)));
+        assert!(!is_address_change(Some(old), None));
+    }
+
+    #[test]
+    fn test_ip_family() {
+        let v4: SocketAddr = "1.2.3.4:24567".parse().unwrap();
+        let v6: SocketAddr = "[2001:db8::1]:24567".parse().unwrap();
+        let mapped: SocketAddr = "[::ffff:1.2.3.4]:24567".parse().unwrap();
+        assert_eq!(canonical_addr(mapped), v4);
+        assert_eq!(canonical_addr(v6), v6);
+        assert!(IpFamily::V4.allows(&mapped));
+        assert!(!IpFamily::V6.allows(&mapped));
+        assert!(IpFamily::V6.allows(&v6));
+        assert!(IpFamily::Dual.allows(&v4) && IpFamily::Dual.allows(&v6));
+
+        assert_eq!(IpFamily::Dual.select_addr(vec![v4, v6], true), Some(v6));
+        assert_eq!(IpFamily::Dual.select_addr(vec![v6, v4], false), Some(v4));
+        assert_eq!(IpFamily::V4.select_addr(vec![v6, v4], true), Some(v4));
+        assert_eq!(IpFamily::V6.select_addr(vec![v4], true), None);
+
+        let any: SocketAddr = "0.0.0.0:24567".parse().unwrap();
+        let any_v6: SocketAddr = "[::]:24567".parse().unwrap();
+        assert_eq!(IpFamily::Dual.listen_addrs(any), vec![any_v6, any]);
+        assert_eq!(IpFamily::V4.listen_addrs(any_v6), vec![any]);
+        assert_eq!(IpFamily::V6.listen_addrs(v6), vec![v6]);
+    }
+
+    #[test]
+    fn test_peer_info_ipv6() {
+        let peer_id = PeerId::random();
+        let s = format!("{}@[2001:db8::1]:24567@test.near", peer_id);
+        let peer_info = PeerInfo::resolve(&s, IpFamily::Dual, false).unwrap();
+        assert_eq!(peer_info.addr, Some("[2001:db8::1]:24567".parse().unwrap()));
+        assert_eq!(peer_info.to_string(), s);
+    }
+
+    #[test]
+    fn test_peer_info_with_host() {
+        let peer_id = PeerId::random();
+        let (peer_info, host) =
+            PeerInfo::parse_with_host(&format!("{}@boot.near.org:24567@test.near", peer_id))
+                .unwrap();
+        assert_eq!(host.as_deref(), Some("boot.near.org:24567"));
+        assert_eq!(peer_info.id, peer_id);
+        assert_eq!(peer_info.addr, None);
+        assert_eq!(peer_info.account_id, Some("test.near".parse().unwrap()));
+
+        let s = format!("{}@1.2.3.4:24567", peer_id);
+        let (peer_info, host) = PeerInfo::parse_with_host(&s).unwrap();
+        assert_eq!((peer_info.to_string(), host), (s, None));
+    }
+
+    #[test]
+    fn test_subnet_of() {
+        let subnet = |ip: &str| subnet_of(ip.parse().unwrap()).to_string();
+        assert_eq!(subnet("1.2.3.4"), "1.2.3.0");
+        assert_eq!(subnet("::ffff:1.2.3.4"), "1.2.3.0");
+        assert_eq!(subnet("2001:db8:1:2::1"), "2001:db8:1::");
+    }
+
     #[test]
     fn test_enum_size() {
         assert_size!(PeerType);
@@ -4,6 +4,7 @@ extern crate bencher;
 use bencher::Bencher;
 
 use near_network::routing::Graph;
+use near_network::simulation::Topology;
 use near_network::test_utils::random_peer_id;
 
 fn build_graph(depth: usize, size: usize) -> Graph {
@@ -51,6 +52,13 @@ fn calculate_distance_10_100(bench: &mut Bencher) {
     });
 }
 
+fn calculate_distance_3_500(bench: &mut Bencher) {
+    let graph = build_graph(3, 500);
+    bench.iter(|| {
+        let _ = graph.calculate_distance();
+    });
+}
+
 #[allow(dead_code)]
 fn calculate_distance_100_100(bench: &mut Bencher) {
     let graph = build_graph(100, 100);
@@ -59,12 +67,46 @@ fn calculate_distance_100_100(bench: &mut Bencher) {
     });
 }
 
+/// Edge updates of a hub connected to 10k peers.
+fn add_remove_edges_hub_10000(bench: &mut Bencher) {
+    let source = random_peer_id();
+    let hub = random_peer_id();
+    let nodes: Vec<_> = (0..10_000).map(|_| random_peer_id()).collect();
+    let mut graph = Graph::new(source.clone());
+    graph.add_edge(source, hub.clone());
+    for node in nodes.iter() {
+        graph.add_edge(hub.clone(), node.clone());
+    }
+    bench.iter(|| {
+        for node in nodes.iter().step_by(100) {
+            graph.remove_edge(&hub, node);
+            graph.add_edge(hub.clone(), node.clone());
+        }
+    });
+}
+
+/// Routes of a peer of a 10k nodes network with a power-law degree distribution.
+fn calculate_distance_barabasi_albert_10000(bench: &mut Bencher) {
+    let topology = Topology::barabasi_albert(10_000, 3, 0);
+    let nodes: Vec<_> = (0..topology.num_nodes()).map(|_| random_peer_id()).collect();
+    let mut graph = Graph::new(nodes[0].clone());
+    for &(u, v) in topology.edges() {
+        graph.add_edge(nodes[u].clone(), nodes[v].clone());
+    }
+    bench.iter(|| {
+        let _ = graph.calculate_distance();
+    });
+}
+
 benchmark_group!(
     benches,
     calculate_distance_3_3,
     calculate_distance_10_10,
     //    calculate_distance_100_100,
-    calculate_distance_10_100
+    calculate_distance_10_100,
+    calculate_distance_3_500,
+    add_remove_edges_hub_10000,
+    calculate_distance_barabasi_albert_10000
 );
 
 benchmark_main!(benches);
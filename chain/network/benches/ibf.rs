@@ -1,21 +1,84 @@
 #[macro_use]
 extern crate bencher;
 
-use bencher::Bencher;
+use bencher::{black_box, Bencher};
 
-use near_network::ibf_peer_set::SlotMapId;
+use near_network::ibf::{IbfHasher, IbfHasherKind, SipIbfHasher, XxIbfHasher};
+use near_network::ibf_peer_set::{IbfPeerSet, SlotMapId};
 use near_network::ibf_set::IbfSet;
+use near_network::routing::SimpleEdge;
+use near_primitives::network::PeerId;
 
-#[allow(dead_code)]
-fn test_measure_adding_edges_to_ibf(bench: &mut Bencher) {
+fn measure_adding_edges_to_ibf(bench: &mut Bencher, hasher_kind: IbfHasherKind) {
     bench.iter(|| {
-        let mut a = IbfSet::<u64>::new(12);
+        let mut a = IbfSet::<u64>::with_hasher(12, hasher_kind);
         for i in 0..40 * 8 * 3 {
             a.add_edge(&(i as u64), (i + 1000000) as SlotMapId);
         }
     });
 }
 
-benchmark_group!(benches, test_measure_adding_edges_to_ibf);
+#[allow(dead_code)]
+fn test_measure_adding_edges_to_ibf(bench: &mut Bencher) {
+    measure_adding_edges_to_ibf(bench, IbfHasherKind::Sip);
+}
+
+#[allow(dead_code)]
+fn test_measure_adding_edges_to_ibf_xx(bench: &mut Bencher) {
+    measure_adding_edges_to_ibf(bench, IbfHasherKind::Xx);
+}
+
+fn measure_hasher(bench: &mut Bencher, hasher: impl IbfHasher) {
+    bench.iter(|| {
+        let mut result = 0;
+        for i in 0..10000u64 {
+            result ^= hasher.hash(black_box(i));
+        }
+        result
+    });
+}
+
+#[allow(dead_code)]
+fn hash_sip(bench: &mut Bencher) {
+    measure_hasher(bench, SipIbfHasher::new(12));
+}
+
+#[allow(dead_code)]
+fn hash_xx(bench: &mut Bencher) {
+    measure_hasher(bench, XxIbfHasher::new(12));
+}
+
+/// Connect 200 peers to a node knowing 100k edges, then add and remove an edge, as happens
+/// while many connections are established at once.
+#[allow(dead_code)]
+fn add_peers_with_100k_edges(bench: &mut Bencher) {
+    let nodes: Vec<_> = (0..1_000).map(|_| PeerId::random()).collect();
+    let mut ibf_peer_set = IbfPeerSet::default();
+    for i in 0..100_000 {
+        let (peer0, peer1) = (&nodes[i % 1_000], &nodes[(i / 1_000 + i % 1_000 + 1) % 1_000]);
+        ibf_peer_set.add_edge(&SimpleEdge::new(peer0.clone(), peer1.clone(), i as u64));
+    }
+    let peers: Vec<_> = (0..200).map(|_| PeerId::random()).collect();
+    let edge = SimpleEdge::new(nodes[0].clone(), nodes[1].clone(), u64::MAX);
+    bench.iter(|| {
+        for peer_id in peers.iter() {
+            ibf_peer_set.add_peer(peer_id.clone(), None, IbfHasherKind::Sip);
+        }
+        ibf_peer_set.add_edge(&edge);
+        ibf_peer_set.remove_edge(&edge);
+        for peer_id in peers.iter() {
+            ibf_peer_set.remove_peer(peer_id);
+        }
+    });
+}
+
+benchmark_group!(
+    benches,
+    test_measure_adding_edges_to_ibf,
+    test_measure_adding_edges_to_ibf_xx,
+    hash_sip,
+    hash_xx,
+    add_peers_with_100k_edges
+);
 
 benchmark_main!(benches);
@@ -0,0 +1,25 @@
+#![no_main]
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use libfuzzer_sys::fuzz_target;
+use near_network::compact_peer_id::{CompactPeerMessage, PeerIdDecoder, PeerIdEncoder};
+
+fuzz_target!(|bytes: &[u8]| {
+    // Stream of compact messages received over a single connection.
+    let messages = match Vec::<CompactPeerMessage>::try_from_slice(bytes) {
+        Ok(messages) => messages,
+        Err(_) => return,
+    };
+    let mut decoder = PeerIdDecoder::default();
+    let mut encoder = PeerIdEncoder::default();
+    let mut roundtrip_decoder = PeerIdDecoder::default();
+    for compact in messages {
+        if let Ok(msg) = decoder.decode(compact) {
+            // Whatever we managed to decode must survive encoding and decoding again.
+            let compact = encoder.encode(&msg).unwrap();
+            let compact =
+                CompactPeerMessage::try_from_slice(&compact.try_to_vec().unwrap()).unwrap();
+            assert_eq!(roundtrip_decoder.decode(compact).unwrap(), msg);
+        }
+    }
+});
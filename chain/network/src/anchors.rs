@@ -0,0 +1,252 @@
+//! Anchor peers, which operators want the node to stay connected to at all times.
+//!
+//! Anchors are given by peer id, address or both. The ones which aren't connected are redialed
+//! every `ANCHOR_RETRY_INTERVAL`, whatever their backoff in the peer store, and a slot is
+//! reserved for each of them so other peers can't take all the connections meanwhile. Anchors
+//! only given by address are dialed once the peer store knows the peer at that address, since
+//! outbound handshakes need the peer id. Active connections with anchors are never evicted.
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use actix::dev::MessageResponse;
+use serde::Serialize;
+
+use near_network_primitives::types::{canonical_addr, AnchorPeer, PeerInfo};
+use near_primitives::network::PeerId;
+
+use crate::time::Time;
+
+/// Time between two connection attempts to an anchor which isn't connected.
+pub(crate) const ANCHOR_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+struct AnchorState {
+    anchor: AnchorPeer,
+    /// Peer id of the anchor, as configured or from its last connection.
+    peer_id: Option<PeerId>,
+    /// Since when the anchor isn't connected, `None` while it is.
+    disconnected_since: Option<Instant>,
+    last_attempt: Option<Instant>,
+    /// Whether the anchor was reported as unreachable since it disconnected.
+    reported: bool,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct AnchorPeerStatus {
+    pub anchor: String,
+    pub peer_id: Option<PeerId>,
+    pub connected: bool,
+    /// Seconds since the anchor disconnected, or since we started if it never connected.
+    pub disconnected_secs: Option<u64>,
+    /// Whether the anchor has been disconnected for longer than `anchor_unreachable_timeout`.
+    pub unreachable: bool,
+}
+
+#[derive(MessageResponse, Debug)]
+pub struct GetAnchorPeersResult {
+    pub anchors: Vec<AnchorPeerStatus>,
+}
+
+#[derive(Default)]
+pub(crate) struct AnchorPeers {
+    anchors: Vec<AnchorState>,
+}
+
+impl AnchorPeers {
+    pub fn new(anchors: Vec<AnchorPeer>, now: Instant) -> Self {
+        let mut result = Self::default();
+        result.set_anchors(anchors, now);
+        result
+    }
+
+    /// Replace the anchors, keeping the state of the ones which were already anchors.
+    pub fn set_anchors(&mut self, anchors: Vec<AnchorPeer>, now: Instant) {
+        let mut previous = std::mem::take(&mut self.anchors);
+        for anchor in anchors {
+            match previous.iter().position(|state| state.anchor == anchor) {
+                Some(pos) => self.anchors.push(previous.swap_remove(pos)),
+                None => self.anchors.push(AnchorState {
+                    peer_id: anchor.id.clone(),
+                    anchor,
+                    disconnected_since: Some(now),
+                    last_attempt: None,
+                    reported: false,
+                }),
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.anchors.is_empty()
+    }
+
+    /// Whether `peer_info` is one of the anchors.
+    pub fn is_anchor(&self, peer_info: &PeerInfo) -> bool {
+        self.anchors.iter().any(|state| state.anchor.matches(peer_info))
+    }
+
+    /// Whether an anchor has the IP address of `addr`. Used before the handshake, when the peer
+    /// id isn't known yet.
+    pub fn is_anchor_ip(&self, addr: &SocketAddr) -> bool {
+        let ip = canonical_addr(*addr).ip();
+        self.anchors
+            .iter()
+            .any(|state| state.anchor.addr.map_or(false, |addr| canonical_addr(addr).ip() == ip))
+    }
+
+    /// Number of anchors which aren't connected, for which slots are reserved.
+    pub fn num_disconnected(&self) -> usize {
+        self.anchors.iter().filter(|state| state.disconnected_since.is_some()).count()
+    }
+
+    /// Update which anchors are connected, `connected` returning the peer id of the active
+    /// connection with an anchor. Returns the anchors to dial now, with `resolve` finding the
+    /// address of the anchors given by peer id and the peer id of the ones given by address,
+    /// and skipping anchors `is_connecting` holds for.
+    pub fn peers_to_connect(
+        &mut self,
+        now: Instant,
+        connected: impl Fn(&AnchorPeer) -> Option<PeerId>,
+        is_connecting: impl Fn(&PeerId) -> bool,
+        resolve: impl Fn(&AnchorPeer) -> Option<PeerInfo>,
+    ) -> Vec<PeerInfo> {
+        let mut to_connect = vec![];
+        for state in self.anchors.iter_mut() {
+            if let Some(peer_id) = connected(&state.anchor) {
+                state.peer_id = Some(peer_id);
+                state.disconnected_since = None;
+                state.reported = false;
+                continue;
+            }
+            state.disconnected_since.get_or_insert(now);
+            if state.peer_id.as_ref().map_or(false, |peer_id| is_connecting(peer_id)) {
+                continue;
+            }
+            if state
+                .last_attempt
+                .map_or(false, |last| Time::duration_since(now, last) < ANCHOR_RETRY_INTERVAL)
+            {
+                continue;
+            }
+            let peer_info = match (&state.anchor.id, state.anchor.addr) {
+                (Some(id), Some(addr)) => Some(PeerInfo::new(id.clone(), addr)),
+                _ => resolve(&state.anchor),
+            };
+            if let Some(peer_info) = peer_info {
+                state.peer_id = Some(peer_info.id.clone());
+                state.last_attempt = Some(now);
+                to_connect.push(peer_info);
+            }
+        }
+        to_connect
+    }
+
+    /// Anchors disconnected for longer than `timeout` which weren't reported yet.
+    pub fn newly_unreachable(&mut self, now: Instant, timeout: Duration) -> Vec<AnchorPeer> {
+        let mut unreachable = vec![];
+        for state in self.anchors.iter_mut() {
+            let since = match state.disconnected_since {
+                Some(since) => since,
+                None => continue,
+            };
+            if !state.reported && Time::duration_since(now, since) >= timeout {
+                state.reported = true;
+                unreachable.push(state.anchor.clone());
+            }
+        }
+        unreachable
+    }
+
+    /// Number of anchors disconnected for longer than `timeout`.
+    pub fn num_unreachable(&self, now: Instant, timeout: Duration) -> usize {
+        self.anchors
+            .iter()
+            .filter(|state| {
+                state
+                    .disconnected_since
+                    .map_or(false, |since| Time::duration_since(now, since) >= timeout)
+            })
+            .count()
+    }
+
+    pub fn statuses(&self, now: Instant, timeout: Duration) -> Vec<AnchorPeerStatus> {
+        self.anchors
+            .iter()
+            .map(|state| {
+                let disconnected =
+                    state.disconnected_since.map(|since| Time::duration_since(now, since));
+                AnchorPeerStatus {
+                    anchor: state.anchor.to_string(),
+                    peer_id: state.peer_id.clone(),
+                    connected: disconnected.is_none(),
+                    disconnected_secs: disconnected.map(|duration| duration.as_secs()),
+                    unreachable: disconnected.map_or(false, |duration| duration >= timeout),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::time::{Duration, Instant};
+
+    use near_network_primitives::types::{AnchorPeer, PeerInfo};
+
+    use crate::test_utils::random_peer_id;
+
+    use super::{AnchorPeers, ANCHOR_RETRY_INTERVAL};
+
+    #[test]
+    fn maintain_anchor_peers() {
+        let now = Instant::now();
+        let timeout = Duration::from_secs(60);
+        let (a, b) = (random_peer_id(), random_peer_id());
+        let addr_a: SocketAddr = "1.2.3.4:24567".parse().unwrap();
+        let addr_b: SocketAddr = "1.2.3.5:24567".parse().unwrap();
+        let mut anchors = AnchorPeers::default();
+        anchors.set_anchors(
+            vec![
+                AnchorPeer { id: Some(a.clone()), addr: Some(addr_a) },
+                AnchorPeer { id: None, addr: Some(addr_b) },
+            ],
+            now,
+        );
+        assert!(anchors.is_anchor(&PeerInfo::new(a.clone(), addr_a)));
+        assert!(anchors.is_anchor(&PeerInfo::new(b.clone(), addr_b)));
+        assert!(!anchors.is_anchor(&PeerInfo::new(b.clone(), addr_a)));
+        assert!(anchors.is_anchor_ip(&"1.2.3.5:1234".parse().unwrap()));
+        assert_eq!(anchors.num_disconnected(), 2);
+
+        // The anchor given by address is dialed once its peer id is known.
+        let to_connect = anchors.peers_to_connect(now, |_| None, |_| false, |_| None);
+        assert_eq!(to_connect, vec![PeerInfo::new(a.clone(), addr_a)]);
+        let resolve = |_: &AnchorPeer| Some(PeerInfo::new(b.clone(), addr_b));
+        let to_connect = anchors.peers_to_connect(now, |_| None, |_| false, resolve);
+        assert_eq!(to_connect, vec![PeerInfo::new(b.clone(), addr_b)]);
+
+        // Anchors are retried after the retry interval, unless we are connecting to them.
+        let later = now + ANCHOR_RETRY_INTERVAL;
+        let connecting = |peer_id: &_| peer_id == &b;
+        let to_connect = anchors.peers_to_connect(later, |_| None, connecting, |_| None);
+        assert_eq!(to_connect, vec![PeerInfo::new(a.clone(), addr_a)]);
+
+        // Anchors disconnected for too long are reported once.
+        let later = now + timeout;
+        let connected = |anchor: &AnchorPeer| anchor.id.clone();
+        assert!(anchors.peers_to_connect(later, connected, |_| false, |_| None).is_empty());
+        assert_eq!(anchors.num_disconnected(), 1);
+        assert_eq!(anchors.num_unreachable(later, timeout), 1);
+        let unreachable = anchors.newly_unreachable(later, timeout);
+        assert_eq!(unreachable, vec![AnchorPeer { id: None, addr: Some(addr_b) }]);
+        assert!(anchors.newly_unreachable(later, timeout).is_empty());
+        let statuses = anchors.statuses(later, timeout);
+        assert!(statuses[0].connected);
+        assert_eq!(statuses[1].peer_id, Some(b));
+        assert!(statuses[1].unreachable);
+
+        // The state of the anchors which are kept survives a reload.
+        anchors.set_anchors(vec![AnchorPeer { id: Some(a), addr: Some(addr_a) }], later);
+        assert_eq!(anchors.num_disconnected(), 0);
+    }
+}
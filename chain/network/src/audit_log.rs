@@ -0,0 +1,97 @@
+//! Append-only log of the adversarial and admin requests which mutate the routing state, kept on
+//! disk so operators of shared test networks can tell who changed the routes of a node and when.
+//!
+//! Entries are keyed by their big-endian sequence number, and never modified nor removed.
+use std::convert::TryInto;
+use std::io;
+use std::sync::Arc;
+
+use actix::dev::MessageResponse;
+use borsh::{BorshDeserialize, BorshSerialize};
+use serde::Serialize;
+
+use near_store::{ColRoutingAuditLog, Store};
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct AuditLogEntry {
+    /// Sequence number of the entry, starting at zero.
+    pub index: u64,
+    /// Unix timestamp in nanoseconds of the request.
+    pub timestamp: u64,
+    /// Who sent the request, e.g. the address of the RPC client.
+    pub caller: String,
+    /// What the request changed.
+    pub action: String,
+}
+
+#[derive(MessageResponse, Debug)]
+#[cfg_attr(feature = "test_features", derive(Serialize))]
+pub struct GetRoutingAuditLogResult {
+    /// Most recent entries, oldest first.
+    pub entries: Vec<AuditLogEntry>,
+}
+
+pub struct AuditLog {
+    store: Arc<Store>,
+    next_index: u64,
+}
+
+impl AuditLog {
+    pub fn load(store: Arc<Store>) -> Self {
+        let next_index = store
+            .iter(ColRoutingAuditLog)
+            .filter_map(|(key, _)| key.as_ref().try_into().ok().map(u64::from_be_bytes))
+            .max()
+            .map_or(0, |index| index + 1);
+        Self { store, next_index }
+    }
+
+    /// Append an entry for `action` requested by `caller` at `timestamp`.
+    pub fn record(&mut self, timestamp: u64, caller: String, action: String) -> io::Result<()> {
+        let entry = AuditLogEntry { index: self.next_index, timestamp, caller, action };
+        let mut update = self.store.store_update();
+        update.set_ser(ColRoutingAuditLog, &entry.index.to_be_bytes(), &entry)?;
+        update.commit()?;
+        self.next_index += 1;
+        Ok(())
+    }
+
+    /// Up to `limit` most recent entries, oldest first.
+    pub fn entries(&self, limit: usize) -> Vec<AuditLogEntry> {
+        let oldest = self.next_index.saturating_sub(limit as u64);
+        let mut entries: Vec<AuditLogEntry> = self
+            .store
+            .iter(ColRoutingAuditLog)
+            .filter_map(|(_, value)| AuditLogEntry::try_from_slice(value.as_ref()).ok())
+            .filter(|entry| entry.index >= oldest)
+            .collect();
+        entries.sort_by_key(|entry| entry.index);
+        entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_store::test_utils::create_test_store;
+
+    use super::AuditLog;
+
+    #[test]
+    fn append_and_reload() {
+        let store = create_test_store();
+        let mut log = AuditLog::load(store.clone());
+        for i in 0..3 {
+            log.record(i, format!("caller{}", i), format!("action{}", i)).unwrap();
+        }
+        let entries = log.entries(2);
+        assert_eq!(entries.iter().map(|entry| entry.index).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(entries[1].caller, "caller2");
+        assert_eq!(entries[1].action, "action2");
+
+        // Entries are appended after the ones on disk.
+        let mut log = AuditLog::load(store);
+        log.record(3, "caller3".to_string(), "action3".to_string()).unwrap();
+        assert_eq!(log.entries(10).len(), 4);
+        assert_eq!(log.entries(1)[0].index, 3);
+    }
+}
@@ -0,0 +1,157 @@
+//! Upload and download rate limits, per peer and for all peers together.
+//!
+//! Limits are token buckets of bytes, refilled at the configured rate and holding at most one
+//! second worth of bytes. Messages are never split: a message larger than the tokens left puts
+//! the bucket in debt, and the connection waits until it is paid back. Reads are delayed, which
+//! slows down the peer through TCP flow control, and writes are held in the send queue, so
+//! throttled peers are slowed down instead of being disconnected.
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::time::{Clock, Time};
+
+#[derive(Debug)]
+struct ByteBucket {
+    /// Bytes per second, zero if unlimited.
+    rate: u64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl ByteBucket {
+    fn new(rate: u64, now: Instant) -> Self {
+        Self { rate, tokens: rate as f64, updated: now }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = Time::duration_since(now, self.updated);
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() * self.rate as f64).min(self.rate as f64);
+        self.updated = now;
+    }
+
+    fn set_rate(&mut self, rate: u64, now: Instant) {
+        self.refill(now);
+        self.rate = rate;
+        self.tokens = self.tokens.min(rate as f64);
+    }
+
+    /// Take `bytes`, possibly going in debt. Returns how long until the debt is paid back.
+    fn consume(&mut self, bytes: u64, now: Instant) -> Duration {
+        if self.rate == 0 {
+            return Duration::ZERO;
+        }
+        self.refill(now);
+        self.tokens -= bytes as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate as f64)
+        }
+    }
+
+    fn is_exhausted(&mut self, now: Instant) -> bool {
+        if self.rate == 0 {
+            return false;
+        }
+        self.refill(now);
+        self.tokens < 0.0
+    }
+}
+
+/// Rate limit of one direction of a connection: its own bucket, and the buckets shared with
+/// other connections.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Throttle {
+    buckets: Vec<Arc<Mutex<ByteBucket>>>,
+    clock: Clock,
+}
+
+impl Throttle {
+    /// Throttle limited to `rate` bytes per second, unlimited if `rate` is zero.
+    pub fn new(rate: u64, clock: Clock) -> Self {
+        let bucket = ByteBucket::new(rate, clock.now());
+        Self { buckets: vec![Arc::new(Mutex::new(bucket))], clock }
+    }
+
+    #[cfg(test)]
+    fn new_at(rate: u64, now: Instant) -> Self {
+        Self {
+            buckets: vec![Arc::new(Mutex::new(ByteBucket::new(rate, now)))],
+            clock: Clock::real(),
+        }
+    }
+
+    /// Change the limit of this throttle's own bucket, for every connection sharing it. Zero
+    /// removes the limit.
+    pub fn set_rate(&self, rate: u64) {
+        self.set_rate_at(rate, self.clock.now())
+    }
+
+    fn set_rate_at(&self, rate: u64, now: Instant) {
+        if let Some(bucket) = self.buckets.first() {
+            bucket.lock().unwrap().set_rate(rate, now);
+        }
+    }
+
+    /// This throttle, also limited by `shared`.
+    pub fn and(mut self, shared: &Throttle) -> Self {
+        self.buckets.extend(shared.buckets.iter().cloned());
+        self
+    }
+
+    /// Account for `bytes` transferred. Returns how long to wait before the next transfer.
+    pub fn consume(&self, bytes: usize) -> Duration {
+        self.consume_at(bytes, self.clock.now())
+    }
+
+    fn consume_at(&self, bytes: usize, now: Instant) -> Duration {
+        self.buckets
+            .iter()
+            .map(|bucket| bucket.lock().unwrap().consume(bytes as u64, now))
+            .max()
+            .unwrap_or(Duration::ZERO)
+    }
+
+    /// Whether a transfer now would exceed one of the limits.
+    pub fn is_exhausted(&self) -> bool {
+        self.is_exhausted_at(self.clock.now())
+    }
+
+    fn is_exhausted_at(&self, now: Instant) -> bool {
+        self.buckets.iter().any(|bucket| bucket.lock().unwrap().is_exhausted(now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::Throttle;
+
+    #[test]
+    fn shared_limits() {
+        let now = Instant::now();
+        let global = Throttle::new_at(1_000, now);
+        let peer0 = Throttle::new_at(500, now).and(&global);
+        let peer1 = Throttle::new_at(0, now).and(&global);
+
+        assert_eq!(peer0.consume_at(500, now), Duration::ZERO);
+        assert!(!peer0.is_exhausted_at(now));
+        // A message larger than the tokens left goes through, and the debt is paid back later.
+        assert_eq!(peer0.consume_at(1_000, now), Duration::from_secs(2));
+        // The global limit is in debt too.
+        assert!(peer1.is_exhausted_at(now));
+        assert!(!peer1.is_exhausted_at(now + Duration::from_millis(500)));
+
+        assert!(peer0.is_exhausted_at(now + Duration::from_secs(1)));
+        assert!(!peer0.is_exhausted_at(now + Duration::from_secs(2)));
+        assert!(!Throttle::new_at(0, now).is_exhausted_at(now));
+
+        // Changing the shared limit applies to every connection sharing it.
+        global.set_rate_at(0, now);
+        assert_eq!(peer1.consume_at(1_000_000, now), Duration::ZERO);
+        global.set_rate_at(100, now);
+        assert_eq!(peer1.consume_at(200, now), Duration::from_secs(2));
+    }
+}
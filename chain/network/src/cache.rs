@@ -1,12 +1,36 @@
 use std::collections::btree_map;
 use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::TryFrom;
+use std::io;
 use std::time::{Duration, Instant};
 
+use borsh::{BorshDeserialize, BorshSerialize};
 use near_primitives::hash::CryptoHash;
 use near_primitives::network::PeerId;
+use near_store::{ColRouteBack, Store};
+
+use crate::metrics;
+use crate::time::Clock;
 
 type Size = u64;
 
+/// Count entries of `target` evicted from the route back cache for `reason`.
+fn record_evicted(target: &PeerId, reason: &str, count: u64) {
+    near_metrics::inc_counter_vec_by(
+        &metrics::ROUTE_BACK_EVICTED,
+        &[&target.to_string(), reason],
+        count,
+    );
+}
+
+/// Entry of the route back cache saved on disk, keyed by the hash of the message.
+#[derive(BorshSerialize, BorshDeserialize)]
+struct SavedRouteBack {
+    target: PeerId,
+    /// Unix timestamp in nanoseconds after which the entry is expired.
+    expires_at: u64,
+}
+
 /// Cache to store route back messages.
 ///
 /// The interface of the cache is similar to a regular HashMap:
@@ -42,6 +66,12 @@ type Size = u64;
 /// - If the cache is not at full capacity, all new records will be stored.
 /// - If a peer try to abuse the system, it will be able to allocate at most
 ///     $capacity / number_of_active_connections$ entries.
+///
+/// Additionally, a peer holding more entries than its quota has its oldest entry removed for
+/// every new one, even if the cache is not full, so a noisy peer can't fill the cache and have
+/// the entries of quieter peers evicted with the old ones. The quota is the largest of
+/// `peer_quota` and the fair share of the capacity, $capacity / number_of_peers_with_entries$,
+/// so it adapts to the number of peers using the cache.
 pub struct RouteBackCache {
     /// Maximum number of records allowed in the cache.
     capacity: u64,
@@ -49,6 +79,8 @@ pub struct RouteBackCache {
     evict_timeout: Duration,
     /// Minimum number of records to delete from offending peer when the cache is full.
     remove_frequent_min_size: u64,
+    /// Number of records each peer can hold regardless of the number of peers.
+    peer_quota: u64,
     /// Main map from message hash to time where it was created + target peer
     /// Size: O(capacity)
     main: HashMap<CryptoHash, (Instant, PeerId)>,
@@ -61,6 +93,7 @@ pub struct RouteBackCache {
     /// are sorted by the time they arrived from older to newer.
     /// Size: O(capacity)
     record_per_target: BTreeMap<PeerId, BTreeSet<(Instant, CryptoHash)>>,
+    clock: Clock,
 }
 
 impl RouteBackCache {
@@ -102,6 +135,7 @@ impl RouteBackCache {
             }
         }
 
+        record_evicted(&target, "full", removed);
         self.size_per_target.remove(&(size, target.clone()));
         // Since self.size is equal to capacity - real_size, adding 1, is equivalent to subtracting 1 from the real size.
         size += removed;
@@ -115,7 +149,7 @@ impl RouteBackCache {
         if self.is_full() {
             self.remove_frequent();
 
-            let now = Instant::now();
+            let now = self.clock.now();
             let remove_until = now - self.evict_timeout;
 
             let mut remove_empty = vec![];
@@ -132,6 +166,7 @@ impl RouteBackCache {
                 let new_size = value.len();
 
                 if prev_size != new_size {
+                    record_evicted(key, "expired", (prev_size - new_size) as u64);
                     self.size_per_target.remove(&(self.capacity - prev_size as Size, key.clone()));
 
                     if new_size > 0 {
@@ -158,12 +193,34 @@ impl RouteBackCache {
             capacity,
             evict_timeout,
             remove_frequent_min_size,
+            peer_quota: capacity,
             main: HashMap::new(),
             size_per_target: BTreeSet::new(),
             record_per_target: BTreeMap::new(),
+            clock: Clock::real(),
         }
     }
 
+    /// Read the time from `clock` instead of the system clock.
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Limit the number of records of each peer to the largest of `peer_quota` and its fair share
+    /// of the capacity. By default a peer can fill the whole cache.
+    pub fn with_peer_quota(mut self, peer_quota: u64) -> Self {
+        assert!(peer_quota > 0);
+        self.peer_quota = peer_quota;
+        self
+    }
+
+    /// Maximum number of records of a peer which already holds some.
+    fn quota(&self) -> Size {
+        let peers = self.record_per_target.len().max(1) as Size;
+        self.peer_quota.max(self.capacity / peers)
+    }
+
     pub fn get(&self, hash: &CryptoHash) -> Option<&PeerId> {
         self.main.get(&hash).map(|(_, target)| target)
     }
@@ -201,13 +258,25 @@ impl RouteBackCache {
     }
 
     pub fn insert(&mut self, hash: CryptoHash, target: PeerId) {
+        self.insert_at(hash, target, self.clock.now());
+    }
+
+    fn insert_at(&mut self, hash: CryptoHash, target: PeerId, now: Instant) {
         if self.main.contains_key(&hash) {
             return;
         }
 
         self.remove_evicted();
 
-        let now = Instant::now();
+        let oldest = self
+            .record_per_target
+            .get(&target)
+            .filter(|records| records.len() as Size >= self.quota())
+            .and_then(|records| records.iter().next().map(|(_, hash)| hash.clone()));
+        if let Some(oldest) = oldest {
+            self.remove(&oldest);
+            record_evicted(&target, "quota", 1);
+        }
 
         self.main.insert(hash, (now, target.clone()));
 
@@ -222,6 +291,53 @@ impl RouteBackCache {
         size += 1;
         self.size_per_target.insert((self.capacity - size, target));
     }
+
+    /// Replace the entries saved on disk with the ones which are in the cache for less than
+    /// `evict_timeout`. `now` is the current unix timestamp in nanoseconds.
+    pub fn save(&self, store: &Store, now: u64) -> io::Result<()> {
+        let time = self.clock.now();
+        let mut update = store.store_update();
+        update.delete_all(ColRouteBack);
+        for (hash, (inserted, target)) in self.main.iter() {
+            let age = time.saturating_duration_since(*inserted);
+            if let Some(ttl) = self.evict_timeout.checked_sub(age) {
+                let saved = SavedRouteBack {
+                    target: target.clone(),
+                    expires_at: now + ttl.as_nanos() as u64,
+                };
+                update.set_ser(ColRouteBack, hash.as_ref(), &saved)?;
+            }
+        }
+        update.commit()
+    }
+
+    /// Insert the entries saved on disk which didn't expire yet, as if they were inserted
+    /// `evict_timeout` before they expire, and remove them from disk. They are saved again on
+    /// the next `save`. Returns the number of entries restored.
+    pub fn load(&mut self, store: &Store, now: u64) -> io::Result<usize> {
+        let time = self.clock.now();
+        let mut restored = 0;
+        for (key, value) in store.iter(ColRouteBack) {
+            let (hash, saved) = match (
+                CryptoHash::try_from(key.as_ref()),
+                SavedRouteBack::try_from_slice(value.as_ref()),
+            ) {
+                (Ok(hash), Ok(saved)) => (hash, saved),
+                _ => continue,
+            };
+            if saved.expires_at <= now {
+                continue;
+            }
+            let ttl = Duration::from_nanos(saved.expires_at - now).min(self.evict_timeout);
+            let inserted = time.checked_sub(self.evict_timeout - ttl).unwrap_or(time);
+            self.insert_at(hash, saved.target, inserted);
+            restored += 1;
+        }
+        let mut update = store.store_update();
+        update.delete_all(ColRouteBack);
+        update.commit()?;
+        Ok(restored)
+    }
 }
 
 #[cfg(test)]
@@ -440,4 +556,67 @@ mod test {
             }
         }
     }
+
+    /// A peer above its quota has its own entries evicted, even if the cache is not full.
+    #[test]
+    fn peer_quota() {
+        let mut cache =
+            RouteBackCache::new(10, Duration::from_millis(1000000000), 1).with_peer_quota(2);
+        let (quiet, hash0) = create_message(0);
+        let noisy = PeerId::random();
+
+        cache.insert(hash0, quiet.clone());
+        for ix in 1..=8 {
+            cache.insert(hash(&[ix]), noisy.clone());
+            check_consistency(&cache);
+        }
+        // Quota of each of the two peers is its fair share: 10 / 2.
+        assert_eq!(cache.get(&hash0), Some(&quiet));
+        assert_eq!((1..=8).filter(|ix| cache.get(&hash(&[*ix])).is_some()).count(), 5);
+
+        // With more peers the quota shrinks down to `peer_quota`.
+        let mut cache =
+            RouteBackCache::new(100, Duration::from_millis(1000000000), 1).with_peer_quota(2);
+        for ix in 0..60 {
+            cache.insert(hash(&[ix]), PeerId::random());
+        }
+        for ix in 60..65 {
+            cache.insert(hash(&[ix]), noisy.clone());
+        }
+        check_consistency(&cache);
+        assert_eq!(cache.main.len(), 62);
+        assert_eq!(cache.record_per_target[&noisy].len(), 2);
+    }
+
+    /// Entries are restored after a restart with the time they had left, expired ones are dropped.
+    #[test]
+    fn save_and_load() {
+        let store = near_store::test_utils::create_test_store();
+        let timeout = Duration::from_secs(100);
+        let mut cache = RouteBackCache::new(10, timeout, 1);
+        let (peer0, hash0) = create_message(0);
+        let (peer1, hash1) = create_message(1);
+        cache.insert(hash0, peer0.clone());
+        cache.insert(hash1, peer1.clone());
+        let now = 1_000_000_000_000;
+        cache.save(&store, now).unwrap();
+
+        let mut restored = RouteBackCache::new(10, timeout, 1);
+        assert_eq!(
+            restored.load(&store, now + Duration::from_secs(10).as_nanos() as u64).unwrap(),
+            2
+        );
+        check_consistency(&restored);
+        assert_eq!(restored.get(&hash0), Some(&peer0));
+        assert_eq!(restored.remove(&hash1), Some(peer1));
+        let (inserted, _) = restored.main[&hash0];
+        assert!(Instant::now().duration_since(inserted) >= Duration::from_secs(10));
+        // Loaded entries are removed from disk.
+        assert_eq!(RouteBackCache::new(10, timeout, 1).load(&store, now).unwrap(), 0);
+
+        restored.save(&store, now).unwrap();
+        let mut expired = RouteBackCache::new(10, timeout, 1);
+        assert_eq!(expired.load(&store, now + timeout.as_nanos() as u64).unwrap(), 0);
+        assert_eq!(expired.get(&hash0), None);
+    }
 }
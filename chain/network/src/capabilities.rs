@@ -0,0 +1,133 @@
+//! Optional features negotiated in the handshake, independently of the protocol version.
+//!
+//! Both sides advertise the features they support and have enabled, and the connection uses the
+//! features advertised by both. Peers whose protocol version predates the negotiation are assumed
+//! to support the features implied by their version. Unknown features advertised by newer peers
+//! are ignored.
+use std::fmt;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use serde::Serialize;
+
+use near_primitives::checked_feature;
+use near_primitives::version::ProtocolVersion;
+
+/// Set of optional features of a connection.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct PeerCapabilities(u64);
+
+impl PeerCapabilities {
+    /// Exchange of routing tables using IBF (`RoutingTableSyncV2`).
+    pub const IBF_SYNC: Self = Self(1 << 0);
+    /// Compression of messages.
+    pub const COMPRESSION: Self = Self(1 << 1);
+    /// Connections over QUIC.
+    pub const QUIC: Self = Self(1 << 2);
+    /// Direct connections between the validators of the epoch.
+    pub const TIER1: Self = Self(1 << 3);
+    /// Account announcements with the number of hops they traveled (`AnnounceAccounts`).
+    pub const ANNOUNCEMENT_HOPS: Self = Self(1 << 4);
+    /// Announcements of the relays of nodes hiding their address (`ProxyAnnouncement`).
+    pub const PROXY_ANNOUNCEMENT: Self = Self(1 << 5);
+    /// Not a feature: declares that the sender is a light client, which only requests data and
+    /// sends transactions. It isn't negotiated, the peer's role is read from what it advertised,
+    /// see `PeerRole::negotiate`.
+    pub const LIGHT_CLIENT: Self = Self(1 << 6);
+
+    const NAMES: [(Self, &'static str); 7] = [
+        (Self::IBF_SYNC, "ibf_sync"),
+        (Self::COMPRESSION, "compression"),
+        (Self::QUIC, "quic"),
+        (Self::TIER1, "tier1"),
+        (Self::ANNOUNCEMENT_HOPS, "announcement_hops"),
+        (Self::PROXY_ANNOUNCEMENT, "proxy_announcement"),
+        (Self::LIGHT_CLIENT, "light_client"),
+    ];
+
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn with(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    /// Features of a peer speaking `protocol_version` which didn't advertise any, because its
+    /// version predates the negotiation.
+    pub fn implied_by_version(protocol_version: ProtocolVersion) -> Self {
+        if checked_feature!("stable", RoutingExchangeAlgorithm, protocol_version) {
+            Self::IBF_SYNC
+        } else {
+            Self::empty()
+        }
+    }
+
+    /// Features of a connection on which we advertised `ours` and the peer `theirs`, the latter
+    /// only read from the handshake if it supports negotiation at `protocol_version`.
+    pub fn negotiate(self, theirs: Self, protocol_version: ProtocolVersion) -> Self {
+        if checked_feature!(
+            "protocol_feature_handshake_capabilities",
+            HandshakeCapabilities,
+            protocol_version
+        ) {
+            self.intersection(theirs).intersection(Self(!Self::LIGHT_CLIENT.0))
+        } else {
+            self.intersection(Self::implied_by_version(protocol_version))
+        }
+    }
+
+    /// Names of the known features in the set.
+    pub fn names(self) -> Vec<&'static str> {
+        Self::NAMES
+            .iter()
+            .filter(|(capability, _)| self.contains(*capability))
+            .map(|(_, name)| *name)
+            .collect()
+    }
+}
+
+impl fmt::Debug for PeerCapabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.names()).finish()
+    }
+}
+
+impl Serialize for PeerCapabilities {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.names().serialize(serializer)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "protocol_feature_handshake_capabilities")]
+mod tests {
+    use near_primitives::version::ProtocolFeature;
+
+    use super::PeerCapabilities;
+
+    #[test]
+    fn negotiate_capabilities() {
+        let ours = PeerCapabilities::IBF_SYNC.with(PeerCapabilities::TIER1);
+        let theirs =
+            PeerCapabilities::TIER1.with(PeerCapabilities::QUIC).with(PeerCapabilities(1 << 40));
+        let version = ProtocolFeature::HandshakeCapabilities.protocol_version();
+        assert_eq!(ours.negotiate(theirs, version), PeerCapabilities::TIER1);
+        assert_eq!(format!("{:?}", ours), r#"{"ibf_sync", "tier1"}"#);
+
+        // Older peers advertise nothing, the features implied by their version are used.
+        assert_eq!(
+            ours.negotiate(PeerCapabilities::empty(), version - 1),
+            PeerCapabilities::IBF_SYNC
+        );
+        let legacy = ProtocolFeature::RoutingExchangeAlgorithm.protocol_version() - 1;
+        assert_eq!(ours.negotiate(theirs, legacy), PeerCapabilities::empty());
+    }
+}
@@ -0,0 +1,192 @@
+//! Fault injection in the send and receive paths of peers, so tests can reproduce flaky networks.
+//!
+//! Each direction of a connection has its own `FaultInjector`, which drops messages or holds them
+//! until their delivery time: the configured latency plus a random jitter, plus the time the
+//! messages before them take to go through the bandwidth cap. Messages are delivered in order
+//! unless picked for reordering, which delays them by one more latency and jitter so the next
+//! messages overtake them. Faults are drawn from a generator seeded from the config, so the same
+//! messages pushed at the same instants get the same faults.
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use actix::Message;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::time::Time;
+
+/// Faults of one direction of a connection.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FaultConfig {
+    /// Delay of every message.
+    pub latency: Duration,
+    /// Maximum random delay added to the latency.
+    pub jitter: Duration,
+    /// Probability that a message is dropped.
+    pub drop_probability: f64,
+    /// Probability that a message is delivered after the messages which follow it.
+    pub reorder_probability: f64,
+    /// Bytes per second, zero if unlimited.
+    pub bandwidth: u64,
+    /// Seed of the faults.
+    pub seed: u64,
+}
+
+/// Faults of both directions of a connection, `None` if messages go through untouched.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PeerFaults {
+    pub send: Option<FaultConfig>,
+    pub receive: Option<FaultConfig>,
+}
+
+impl Message for PeerFaults {
+    type Result = ();
+}
+
+/// Messages held by the faults of one direction, until their delivery time.
+pub(crate) struct FaultInjector<T> {
+    config: FaultConfig,
+    rng: StdRng,
+    /// When the messages pushed so far went through the bandwidth cap.
+    busy_until: Option<Instant>,
+    /// Delivery time of the last message delivered in order.
+    last_delivery: Option<Instant>,
+    /// Messages by delivery time, then by the order they were pushed in.
+    pending: BTreeMap<(Instant, u64), T>,
+    pushed: u64,
+}
+
+impl<T> FaultInjector<T> {
+    pub fn new(config: FaultConfig) -> Self {
+        let rng = StdRng::seed_from_u64(config.seed);
+        Self {
+            config,
+            rng,
+            busy_until: None,
+            last_delivery: None,
+            pending: BTreeMap::new(),
+            pushed: 0,
+        }
+    }
+
+    /// Hold `msg` of `len` bytes until its delivery time, unless it's dropped. Returns whether it
+    /// was kept.
+    pub fn push(&mut self, msg: T, len: usize, now: Instant) -> bool {
+        if self.rng.gen_bool(self.config.drop_probability.clamp(0.0, 1.0)) {
+            return false;
+        }
+        let mut delivery = now;
+        if self.config.bandwidth > 0 {
+            let start = self.busy_until.map_or(now, |busy_until| busy_until.max(now));
+            delivery = start + Duration::from_secs_f64(len as f64 / self.config.bandwidth as f64);
+            self.busy_until = Some(delivery);
+        }
+        delivery += self.delay();
+        if self.rng.gen_bool(self.config.reorder_probability.clamp(0.0, 1.0)) {
+            delivery += self.delay();
+        } else {
+            if let Some(last_delivery) = self.last_delivery {
+                delivery = delivery.max(last_delivery);
+            }
+            self.last_delivery = Some(delivery);
+        }
+        self.pending.insert((delivery, self.pushed), msg);
+        self.pushed += 1;
+        true
+    }
+
+    fn delay(&mut self) -> Duration {
+        let jitter = self.config.jitter.as_nanos() as u64;
+        let jitter = if jitter > 0 { self.rng.gen_range(0, jitter + 1) } else { 0 };
+        self.config.latency + Duration::from_nanos(jitter)
+    }
+
+    /// Messages whose delivery time has come, in delivery order.
+    pub fn pop_ready(&mut self, now: Instant) -> Vec<T> {
+        let mut ready = vec![];
+        while let Some(&key) = self.pending.keys().next() {
+            if key.0 > now {
+                break;
+            }
+            ready.extend(self.pending.remove(&key));
+        }
+        ready
+    }
+
+    /// All the messages held, in delivery order.
+    pub fn into_pending(self) -> Vec<T> {
+        self.pending.into_values().collect()
+    }
+
+    /// Time until the next message is due, if messages are held.
+    pub fn next_delivery(&self, now: Instant) -> Option<Duration> {
+        self.pending.keys().next().map(|(delivery, _)| Time::duration_since(*delivery, now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{FaultConfig, FaultInjector};
+
+    fn deliveries(config: FaultConfig, messages: usize) -> Vec<(Duration, usize)> {
+        let start = Instant::now();
+        let mut injector = FaultInjector::new(config);
+        for i in 0..messages {
+            injector.push(i, 1000, start);
+        }
+        let mut deliveries = vec![];
+        while let Some(delay) = injector.next_delivery(start) {
+            deliveries
+                .extend(injector.pop_ready(start + delay).into_iter().map(|msg| (delay, msg)));
+        }
+        deliveries
+    }
+
+    #[test]
+    fn inject_faults() {
+        // Latency and bandwidth.
+        let config = FaultConfig {
+            latency: Duration::from_millis(100),
+            bandwidth: 10_000,
+            ..Default::default()
+        };
+        assert_eq!(
+            deliveries(config, 3),
+            vec![
+                (Duration::from_millis(200), 0),
+                (Duration::from_millis(300), 1),
+                (Duration::from_millis(400), 2)
+            ]
+        );
+
+        // Jitter doesn't reorder messages.
+        let config = FaultConfig {
+            latency: Duration::from_millis(100),
+            jitter: Duration::from_millis(100),
+            seed: 7,
+            ..Default::default()
+        };
+        let jittered = deliveries(config.clone(), 100);
+        assert!(jittered.iter().enumerate().all(|(i, (_, msg))| i == *msg));
+        assert!(jittered.iter().all(|(delay, _)| *delay >= Duration::from_millis(100)));
+        assert!(jittered.iter().all(|(delay, _)| *delay <= Duration::from_millis(200)));
+        // Same seed, same faults.
+        assert_eq!(deliveries(config, 100), jittered);
+
+        // Drops and reordering.
+        let config = FaultConfig { drop_probability: 1.0, ..Default::default() };
+        assert_eq!(deliveries(config, 10), vec![]);
+        let config = FaultConfig {
+            latency: Duration::from_millis(100),
+            drop_probability: 0.5,
+            reorder_probability: 0.2,
+            seed: 1,
+            ..Default::default()
+        };
+        let faulty = deliveries(config, 100);
+        assert!(faulty.len() < 100);
+        assert!(faulty.windows(2).any(|pair| pair[0].1 > pair[1].1));
+    }
+}
@@ -14,7 +14,7 @@ use near_rust_allocator_proxy::allocator::get_tid;
 use crate::metrics;
 use crate::types::{PeerMessage, ReasonForBan};
 
-const NETWORK_MESSAGE_MAX_SIZE: u32 = 512 * MIB as u32;
+pub(crate) const NETWORK_MESSAGE_MAX_SIZE: u32 = 512 * MIB as u32;
 const MAX_CAPACITY: u64 = GIB;
 
 pub struct Codec {
@@ -128,6 +128,15 @@ pub fn is_forward_tx(bytes: &[u8]) -> Option<bool> {
         return Some(false);
     }
 
+    let message_body_idx = routed_ttl_idx(bytes)? + 1;
+    let message_body_variant = *bytes.get(message_body_idx)?;
+
+    Some(message_body_variant == 1)
+}
+
+/// Index of the `ttl` field in the serialized `PeerMessage::Routed` message `bytes`. The message
+/// body follows it.
+pub fn routed_ttl_idx(bytes: &[u8]) -> Option<usize> {
     let target_field_variant = *bytes.get(1)?;
     let target_field_len = if target_field_variant == 0 {
         // PeerIdOrHash::PeerId
@@ -154,11 +163,7 @@ pub fn is_forward_tx(bytes: &[u8]) -> Option<bool> {
         }
     };
 
-    let ttl_idx = signature_variant_idx + signature_field_len;
-    let message_body_idx = ttl_idx + 1;
-    let message_body_variant = *bytes.get(message_body_idx)?;
-
-    Some(message_body_variant == 1)
+    Some(signature_variant_idx + signature_field_len)
 }
 
 #[cfg(test)]
@@ -179,6 +184,7 @@ mod test {
     };
 
     use super::*;
+    use crate::capabilities::PeerCapabilities;
     use crate::routing::EdgeInfo;
 
     fn test_codec(msg: PeerMessage) {
@@ -288,6 +294,8 @@ mod test {
                 archival: false,
             },
             edge_info: EdgeInfo::default(),
+            capabilities: PeerCapabilities::default(),
+            sub_key_delegation: None,
         };
         let msg = PeerMessage::Handshake(fake_handshake);
         test_codec(msg);
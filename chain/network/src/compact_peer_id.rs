@@ -0,0 +1,375 @@
+//! Compact on-the-wire representation of `PeerId`s.
+//!
+//! Both ends of a connection keep a table of the peer ids recently sent over it. A peer id is
+//! sent in full the first time and stored in the next slot of the table (evicting the oldest
+//! entry), afterwards it is referenced by its slot index. Messages on a connection are delivered
+//! in order, so the receiver replays the same insertions and its table mirrors the table of the
+//! sender. Only bandwidth heavy messages (routing table sync and routed messages) are compressed.
+use std::collections::HashMap;
+use std::fmt;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use near_crypto::Signature;
+use near_primitives::hash::CryptoHash;
+use near_primitives::network::{AnnounceAccount, PeerId};
+use near_primitives::types::{AccountId, EpochId};
+
+use crate::routing::{Edge, EdgeWithoutPeers};
+use crate::types::{PeerIdOrHash, PeerMessage, RoutedMessage, RoutedMessageBody, SyncData};
+
+/// Number of peer ids each side of a connection remembers.
+pub const PEER_ID_TABLE_SIZE: usize = 1024;
+
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Eq, Clone, Debug)]
+pub enum CompactPeerId {
+    /// Peer id which is not in the table of the receiver. It gets inserted upon receiving it.
+    Full(PeerId),
+    /// Slot of the table where the peer id is stored.
+    Index(u16),
+}
+
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Eq, Clone, Debug)]
+pub enum CompactPeerIdOrHash {
+    PeerId(CompactPeerId),
+    Hash(CryptoHash),
+}
+
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Eq, Clone, Debug)]
+pub struct CompactEdge {
+    pub peer0: CompactPeerId,
+    pub peer1: CompactPeerId,
+    pub rest: EdgeWithoutPeers,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Eq, Clone, Debug)]
+pub struct CompactAnnounceAccount {
+    pub account_id: AccountId,
+    pub peer_id: CompactPeerId,
+    pub epoch_id: EpochId,
+    pub signature: Signature,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Eq, Clone, Debug)]
+pub struct CompactSyncData {
+    pub edges: Vec<CompactEdge>,
+    pub accounts: Vec<CompactAnnounceAccount>,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Eq, Clone, Debug)]
+pub struct CompactRoutedMessage {
+    pub target: CompactPeerIdOrHash,
+    pub author: CompactPeerId,
+    pub signature: Signature,
+    pub ttl: u8,
+    pub body: RoutedMessageBody,
+}
+
+/// `PeerMessage`s which support sending peer ids in compact form.
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Eq, Clone, Debug)]
+#[allow(clippy::large_enum_variant)]
+pub enum CompactPeerMessage {
+    RoutingTableSync(CompactSyncData),
+    Routed(CompactRoutedMessage),
+}
+
+/// Received index which doesn't point to a peer id in the table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnknownPeerIdIndex(pub u16);
+
+impl fmt::Display for UnknownPeerIdIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown peer id index {}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownPeerIdIndex {}
+
+#[derive(Default)]
+struct PeerIdTable {
+    slots: Vec<PeerId>,
+    /// Slot which will be overwritten by the next insertion.
+    next: usize,
+    indices: HashMap<PeerId, u16>,
+}
+
+impl PeerIdTable {
+    fn insert(&mut self, peer_id: PeerId) {
+        let slot = self.next;
+        self.next = (self.next + 1) % PEER_ID_TABLE_SIZE;
+        if slot == self.slots.len() {
+            self.slots.push(peer_id.clone());
+        } else {
+            let evicted = std::mem::replace(&mut self.slots[slot], peer_id.clone());
+            // A misbehaving sender may send the same peer id in full twice.
+            if self.indices.get(&evicted) == Some(&(slot as u16)) {
+                self.indices.remove(&evicted);
+            }
+        }
+        self.indices.insert(peer_id, slot as u16);
+    }
+}
+
+/// Compresses peer ids of messages sent over a connection.
+#[derive(Default)]
+pub struct PeerIdEncoder {
+    table: PeerIdTable,
+    disabled: bool,
+}
+
+impl PeerIdEncoder {
+    /// Compact form of `msg`, or `None` if messages of its type are sent as is.
+    pub fn encode(&mut self, msg: &PeerMessage) -> Option<CompactPeerMessage> {
+        if self.disabled {
+            return None;
+        }
+        match msg {
+            PeerMessage::RoutingTableSync(sync_data) => {
+                let edges = sync_data
+                    .edges
+                    .iter()
+                    .map(|edge| {
+                        let (peer0, peer1, rest) = edge.split_peers();
+                        CompactEdge { peer0: self.peer_id(peer0), peer1: self.peer_id(peer1), rest }
+                    })
+                    .collect();
+                let accounts = sync_data
+                    .accounts
+                    .iter()
+                    .map(|account| CompactAnnounceAccount {
+                        account_id: account.account_id.clone(),
+                        peer_id: self.peer_id(&account.peer_id),
+                        epoch_id: account.epoch_id.clone(),
+                        signature: account.signature.clone(),
+                    })
+                    .collect();
+                Some(CompactPeerMessage::RoutingTableSync(CompactSyncData { edges, accounts }))
+            }
+            PeerMessage::Routed(msg) => {
+                let target = match &msg.target {
+                    PeerIdOrHash::PeerId(peer_id) => {
+                        CompactPeerIdOrHash::PeerId(self.peer_id(peer_id))
+                    }
+                    PeerIdOrHash::Hash(hash) => CompactPeerIdOrHash::Hash(*hash),
+                };
+                Some(CompactPeerMessage::Routed(CompactRoutedMessage {
+                    target,
+                    author: self.peer_id(&msg.author),
+                    signature: msg.signature.clone(),
+                    ttl: msg.ttl,
+                    body: msg.body.clone(),
+                }))
+            }
+            _ => None,
+        }
+    }
+
+    /// Stop compressing messages. Must be called if an encoded message was not sent, since the
+    /// table of the receiver won't contain peer ids inserted while encoding it.
+    pub fn disable(&mut self) {
+        self.disabled = true;
+    }
+
+    fn peer_id(&mut self, peer_id: &PeerId) -> CompactPeerId {
+        match self.table.indices.get(peer_id) {
+            Some(index) => CompactPeerId::Index(*index),
+            None => {
+                self.table.insert(peer_id.clone());
+                CompactPeerId::Full(peer_id.clone())
+            }
+        }
+    }
+}
+
+/// Restores peer ids of messages received over a connection.
+#[derive(Default)]
+pub struct PeerIdDecoder {
+    table: PeerIdTable,
+}
+
+impl PeerIdDecoder {
+    pub fn decode(&mut self, msg: CompactPeerMessage) -> Result<PeerMessage, UnknownPeerIdIndex> {
+        Ok(match msg {
+            CompactPeerMessage::RoutingTableSync(sync_data) => {
+                let mut edges = Vec::with_capacity(sync_data.edges.len());
+                for edge in sync_data.edges {
+                    let peer0 = self.peer_id(edge.peer0)?;
+                    let peer1 = self.peer_id(edge.peer1)?;
+                    edges.push(Edge::from_parts(peer0, peer1, edge.rest));
+                }
+                let mut accounts = Vec::with_capacity(sync_data.accounts.len());
+                for account in sync_data.accounts {
+                    accounts.push(AnnounceAccount {
+                        account_id: account.account_id,
+                        peer_id: self.peer_id(account.peer_id)?,
+                        epoch_id: account.epoch_id,
+                        signature: account.signature,
+                    });
+                }
+                PeerMessage::RoutingTableSync(SyncData { edges, accounts })
+            }
+            CompactPeerMessage::Routed(msg) => {
+                let target = match msg.target {
+                    CompactPeerIdOrHash::PeerId(peer_id) => {
+                        PeerIdOrHash::PeerId(self.peer_id(peer_id)?)
+                    }
+                    CompactPeerIdOrHash::Hash(hash) => PeerIdOrHash::Hash(hash),
+                };
+                PeerMessage::Routed(RoutedMessage {
+                    target,
+                    author: self.peer_id(msg.author)?,
+                    signature: msg.signature,
+                    ttl: msg.ttl,
+                    body: msg.body,
+                })
+            }
+        })
+    }
+
+    fn peer_id(&mut self, peer_id: CompactPeerId) -> Result<PeerId, UnknownPeerIdIndex> {
+        match peer_id {
+            CompactPeerId::Full(peer_id) => {
+                self.table.insert(peer_id.clone());
+                Ok(peer_id)
+            }
+            CompactPeerId::Index(index) => {
+                self.table.slots.get(index as usize).cloned().ok_or(UnknownPeerIdIndex(index))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use borsh::{BorshDeserialize, BorshSerialize};
+    use rand::rngs::StdRng;
+    use rand::{Rng, RngCore, SeedableRng};
+
+    use near_crypto::Signature;
+    use near_primitives::hash::hash;
+    use near_primitives::network::{AnnounceAccount, PeerId};
+    use near_primitives::types::EpochId;
+
+    use crate::routing::Edge;
+    use crate::test_utils::peer_id_from_seed;
+    use crate::types::{
+        PeerIdOrHash, PeerMessage, Ping, RoutedMessage, RoutedMessageBody, SyncData,
+    };
+
+    use super::{
+        CompactPeerId, CompactPeerMessage, PeerIdDecoder, PeerIdEncoder, UnknownPeerIdIndex,
+        PEER_ID_TABLE_SIZE,
+    };
+
+    fn routed(target: PeerIdOrHash, author: PeerId) -> PeerMessage {
+        PeerMessage::Routed(RoutedMessage {
+            target,
+            author: author.clone(),
+            signature: Signature::default(),
+            ttl: 10,
+            body: RoutedMessageBody::Ping(Ping { nonce: 0, source: author }),
+        })
+    }
+
+    fn pick(rng: &mut StdRng, peers: &[PeerId]) -> PeerId {
+        peers[rng.gen_range(0, peers.len())].clone()
+    }
+
+    fn random_message(rng: &mut StdRng, peers: &[PeerId]) -> PeerMessage {
+        if rng.gen_bool(0.5) {
+            let edges = (0..rng.gen_range(0, 10))
+                .map(|nonce| {
+                    let (peer0, peer1) = Edge::key(pick(rng, peers), pick(rng, peers));
+                    Edge::make_fake_edge(peer0, peer1, nonce)
+                })
+                .collect();
+            let accounts = (0..rng.gen_range(0, 3))
+                .map(|i| AnnounceAccount {
+                    account_id: format!("account{}", i).parse().unwrap(),
+                    peer_id: pick(rng, peers),
+                    epoch_id: EpochId::default(),
+                    signature: Signature::default(),
+                })
+                .collect();
+            PeerMessage::RoutingTableSync(SyncData { edges, accounts })
+        } else {
+            let target = if rng.gen_bool(0.8) {
+                PeerIdOrHash::PeerId(pick(rng, peers))
+            } else {
+                PeerIdOrHash::Hash(hash(&[1, 2, 3]))
+            };
+            routed(target, pick(rng, peers))
+        }
+    }
+
+    #[test]
+    fn roundtrip_with_eviction() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let peers: Vec<PeerId> = (0..PEER_ID_TABLE_SIZE + 100)
+            .map(|i| peer_id_from_seed(&format!("peer{}", i)))
+            .collect();
+        let mut encoder = PeerIdEncoder::default();
+        let mut decoder = PeerIdDecoder::default();
+        for _ in 0..2000 {
+            let msg = random_message(&mut rng, &peers);
+            let compact = encoder.encode(&msg).unwrap();
+            // Messages go through borsh on the wire.
+            let compact =
+                CompactPeerMessage::try_from_slice(&compact.try_to_vec().unwrap()).unwrap();
+            assert_eq!(decoder.decode(compact).unwrap(), msg);
+        }
+    }
+
+    #[test]
+    fn repeated_peer_ids_are_sent_as_indices() {
+        let peer = peer_id_from_seed("test");
+        let msg = routed(PeerIdOrHash::PeerId(peer.clone()), peer);
+        let mut encoder = PeerIdEncoder::default();
+        match encoder.encode(&msg).unwrap() {
+            CompactPeerMessage::Routed(routed) => {
+                assert_eq!(routed.author, CompactPeerId::Index(0));
+            }
+            _ => panic!("unexpected message"),
+        }
+        assert!(encoder.encode(&PeerMessage::Disconnect).is_none());
+        encoder.disable();
+        assert!(encoder.encode(&msg).is_none());
+    }
+
+    #[test]
+    fn unknown_index() {
+        let msg = routed(PeerIdOrHash::Hash(hash(&[1])), peer_id_from_seed("test"));
+        let mut compact = PeerIdEncoder::default().encode(&msg).unwrap();
+        if let CompactPeerMessage::Routed(routed) = &mut compact {
+            routed.author = CompactPeerId::Index(3);
+        }
+        assert_eq!(PeerIdDecoder::default().decode(compact), Err(UnknownPeerIdIndex(3)));
+    }
+
+    /// Decoding arbitrary data must never panic.
+    #[test]
+    fn fuzz_decode() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let peers: Vec<PeerId> =
+            (0..10).map(|i| peer_id_from_seed(&format!("peer{}", i))).collect();
+        let mut decoder = PeerIdDecoder::default();
+        for _ in 0..1000 {
+            let msg = random_message(&mut rng, &peers);
+            let mut bytes = PeerIdEncoder::default().encode(&msg).unwrap().try_to_vec().unwrap();
+            // Corrupt a few bytes of a valid message, so decoding gets past borsh more often.
+            for _ in 0..rng.gen_range(0, 4) {
+                let index = rng.gen_range(0, bytes.len());
+                bytes[index] = rng.gen();
+            }
+            if let Ok(compact) = CompactPeerMessage::try_from_slice(&bytes) {
+                let _ = decoder.decode(compact);
+            }
+
+            let mut random_bytes = vec![0; rng.gen_range(0, 200)];
+            rng.fill_bytes(&mut random_bytes);
+            if let Ok(compact) = CompactPeerMessage::try_from_slice(&random_bytes) {
+                let _ = decoder.decode(compact);
+            }
+        }
+    }
+}
@@ -0,0 +1,159 @@
+//! Compression of large messages to peers which negotiated `PeerCapabilities::COMPRESSION`.
+//!
+//! A compressed message starts with `COMPRESSED_MESSAGE_TAG`, which no `PeerMessage` variant
+//! starts with, then the algorithm, the length of the message once decompressed and the
+//! compressed message. Messages which don't get smaller are sent as they are, and so are those
+//! which are too large or compress too well to be accepted by the receiver, so a small compressed
+//! message can't make it allocate a lot.
+use std::io;
+
+use near_network_primitives::types::{CompressionAlgorithm, CompressionConfig};
+
+use crate::metrics;
+
+/// First byte of compressed messages.
+const COMPRESSED_MESSAGE_TAG: u8 = 0xff;
+/// Tag, algorithm and decompressed length.
+const HEADER_LEN: usize = 6;
+/// Largest message sent compressed, far below `NETWORK_MESSAGE_MAX_SIZE`.
+const MAX_DECOMPRESSED_LEN: usize = 64 * 1024 * 1024;
+/// Largest ratio of the length of a message to the length of its compressed form.
+const MAX_COMPRESSION_RATIO: usize = 64;
+
+fn algorithm_id(algorithm: CompressionAlgorithm) -> u8 {
+    match algorithm {
+        CompressionAlgorithm::Lz4 => 0,
+        CompressionAlgorithm::Zstd => 1,
+    }
+}
+
+fn algorithm_name(algorithm: CompressionAlgorithm) -> &'static str {
+    match algorithm {
+        CompressionAlgorithm::Lz4 => "lz4",
+        CompressionAlgorithm::Zstd => "zstd",
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+pub(crate) fn is_compressed(bytes: &[u8]) -> bool {
+    bytes.first() == Some(&COMPRESSED_MESSAGE_TAG)
+}
+
+/// Compress the serialized message `bytes` if it is larger than the threshold of `config`.
+pub(crate) fn compress(config: &CompressionConfig, bytes: Vec<u8>) -> Vec<u8> {
+    if bytes.len() < config.threshold_bytes || bytes.len() > MAX_DECOMPRESSED_LEN {
+        return bytes;
+    }
+    let name = algorithm_name(config.algorithm);
+    let timer = near_metrics::start_timer_vec(
+        &metrics::PEER_MESSAGE_COMPRESSION_SECONDS,
+        &[name, "compress"],
+    );
+    let compressed = match config.algorithm {
+        CompressionAlgorithm::Lz4 => Ok(lz4_flex::block::compress(&bytes)),
+        CompressionAlgorithm::Zstd => zstd::bulk::compress(&bytes, config.zstd_level),
+    };
+    near_metrics::stop_timer(timer);
+    let compressed = match compressed {
+        Ok(compressed)
+            if compressed.len() + HEADER_LEN < bytes.len()
+                && bytes.len() <= compressed.len() * MAX_COMPRESSION_RATIO =>
+        {
+            compressed
+        }
+        _ => return bytes,
+    };
+    near_metrics::inc_counter_vec_by(
+        &metrics::PEER_MESSAGE_COMPRESSION_BYTES,
+        &[name, "uncompressed"],
+        bytes.len() as u64,
+    );
+    near_metrics::inc_counter_vec_by(
+        &metrics::PEER_MESSAGE_COMPRESSION_BYTES,
+        &[name, "compressed"],
+        (compressed.len() + HEADER_LEN) as u64,
+    );
+    let mut message = Vec::with_capacity(compressed.len() + HEADER_LEN);
+    message.push(COMPRESSED_MESSAGE_TAG);
+    message.push(algorithm_id(config.algorithm));
+    message.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    message.extend_from_slice(&compressed);
+    message
+}
+
+/// Decompress a message for which `is_compressed` holds. Messages which `compress` wouldn't
+/// have compressed, because they are too large or compress too well, are rejected without
+/// decompressing them.
+pub(crate) fn decompress(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    if bytes.len() < HEADER_LEN {
+        return Err(invalid("truncated compressed message"));
+    }
+    let len = u32::from_le_bytes([bytes[2], bytes[3], bytes[4], bytes[5]]) as usize;
+    if len > MAX_DECOMPRESSED_LEN {
+        return Err(invalid("compressed message is too long"));
+    }
+    if len > (bytes.len() - HEADER_LEN) * MAX_COMPRESSION_RATIO {
+        return Err(invalid("compression ratio is too high"));
+    }
+    let algorithm = match bytes[1] {
+        0 => CompressionAlgorithm::Lz4,
+        1 => CompressionAlgorithm::Zstd,
+        _ => return Err(invalid("unknown compression algorithm")),
+    };
+    let timer = near_metrics::start_timer_vec(
+        &metrics::PEER_MESSAGE_COMPRESSION_SECONDS,
+        &[algorithm_name(algorithm), "decompress"],
+    );
+    let decompressed = match algorithm {
+        CompressionAlgorithm::Lz4 => lz4_flex::block::decompress(&bytes[HEADER_LEN..], len)
+            .map_err(|err| invalid(&err.to_string())),
+        CompressionAlgorithm::Zstd => zstd::bulk::decompress(&bytes[HEADER_LEN..], len),
+    };
+    near_metrics::stop_timer(timer);
+    let decompressed = decompressed?;
+    if decompressed.len() != len {
+        return Err(invalid("length of decompressed message doesn't match"));
+    }
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use near_network_primitives::types::{CompressionAlgorithm, CompressionConfig};
+
+    use super::{compress, decompress, is_compressed, MAX_DECOMPRESSED_LEN};
+
+    #[test]
+    fn compress_large_messages() {
+        let block: Vec<u8> = (0..2000).map(|_| rand::random()).collect();
+        let message = block.repeat(50);
+        for algorithm in [CompressionAlgorithm::Lz4, CompressionAlgorithm::Zstd] {
+            let config = CompressionConfig { enabled: true, algorithm, ..Default::default() };
+            let compressed = compress(&config, message.clone());
+            assert!(is_compressed(&compressed));
+            assert!(compressed.len() < message.len() / 10);
+            assert_eq!(decompress(&compressed).unwrap(), message);
+
+            // Messages compressing too well are sent as they are.
+            let zeros = vec![0u8; 100_000];
+            assert_eq!(compress(&config, zeros.clone()), zeros);
+        }
+
+        // Small or incompressible messages are sent as they are.
+        let config = CompressionConfig { enabled: true, ..Default::default() };
+        let small = vec![0u8; config.threshold_bytes - 1];
+        assert_eq!(compress(&config, small.clone()), small);
+        let random: Vec<u8> = (0..config.threshold_bytes).map(|_| rand::random()).collect();
+        assert_eq!(compress(&config, random.clone()), random);
+
+        assert!(decompress(&[0xff, 2, 0, 0, 0, 0]).is_err());
+        // Too long once decompressed, or compressed too well.
+        let mut header = vec![0xff, 0];
+        header.extend_from_slice(&(MAX_DECOMPRESSED_LEN as u32 + 1).to_le_bytes());
+        assert!(decompress(&[header, vec![0; MAX_DECOMPRESSED_LEN / 64 + 1]].concat()).is_err());
+        assert!(decompress(&[0xff, 0, 0x41, 0, 0, 0, 0]).is_err());
+    }
+}
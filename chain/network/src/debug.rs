@@ -0,0 +1,64 @@
+//! Snapshot of the state of the network layer, served as JSON by the debug endpoint of the RPC
+//! server when `enable_debug_rpc` is set.
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use serde::Serialize;
+
+use near_primitives::network::{AnnounceAccount, PeerId};
+use near_primitives::types::AccountId;
+
+use crate::capabilities::PeerCapabilities;
+use crate::routing::{Edge, EdgeType};
+use crate::types::{ListenAddr, PeerType};
+
+#[derive(Serialize, Clone, Debug)]
+pub struct EdgeView {
+    pub peer0: PeerId,
+    pub peer1: PeerId,
+    pub nonce: u64,
+    /// Whether the edge was removed. Removed edges are kept until pruned, so they can't be
+    /// added back with an older nonce.
+    pub removed: bool,
+}
+
+impl From<&Edge> for EdgeView {
+    fn from(edge: &Edge) -> Self {
+        Self {
+            peer0: edge.peer0.clone(),
+            peer1: edge.peer1.clone(),
+            nonce: edge.nonce,
+            removed: edge.edge_type() == EdgeType::Removed,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct PeerConnectionView {
+    pub peer_id: PeerId,
+    pub addr: Option<SocketAddr>,
+    pub account_id: Option<AccountId>,
+    pub peer_type: PeerType,
+    pub connected_secs: u64,
+    pub last_message_secs_ago: u64,
+    pub received_bytes_per_sec: u64,
+    pub sent_bytes_per_sec: u64,
+    /// Average round trip time of pings to the peer in milliseconds, none if it never answered.
+    pub ping_rtt_ms: Option<f64>,
+    /// Optional features negotiated in the handshake.
+    pub capabilities: PeerCapabilities,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct NetworkDebugInfo {
+    pub peer_id: PeerId,
+    pub peers: Vec<PeerConnectionView>,
+    /// Next hops on the shortest paths to every reachable peer.
+    pub peer_forwarding: HashMap<PeerId, Vec<PeerId>>,
+    pub account_announcements: Vec<AnnounceAccount>,
+    /// All known edges, as stored by the `RoutingTableActor`.
+    pub edges: Vec<EdgeView>,
+    /// Addresses the node listens at, with their advertisement policy. Peers only learn the
+    /// port of the address advertised to them, see `PeerInfo::addr_port`.
+    pub listen_addrs: Vec<ListenAddr>,
+}
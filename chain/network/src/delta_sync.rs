@@ -0,0 +1,136 @@
+//! Delta routing sync on reconnect.
+//!
+//! The routing table actor numbers every change of its edges with a sequence number. A peer
+//! receiving our edges remembers the `SyncWatermark` of the last delta we sent it, and when it
+//! reconnects it sends it back in a `RoutingTableDeltaRequest`, so we only send the edges
+//! changed since. If the watermark is stale (we restarted since, or so many edges changed that a
+//! delta isn't cheaper) we fall back to IBF or full sync.
+use std::collections::{BTreeMap, HashMap};
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use rand::{thread_rng, Rng};
+
+use near_primitives::network::PeerId;
+
+use crate::routing::Edge;
+use crate::routing_profile::{btree_map_bytes, hash_map_bytes};
+
+/// Position in the log of edge changes of a node.
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Eq, Clone, Copy, Debug)]
+pub struct SyncWatermark {
+    /// Random id of the log, changed on restart, since sequence numbers are only kept in memory.
+    pub session: u64,
+    /// Sequence number of the last change.
+    pub seq: u64,
+}
+
+/// Edges changed since the watermark the peer asked for. The peer asks for the next delta from
+/// `watermark`.
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Eq, Clone, Debug)]
+pub struct RoutingTableDelta {
+    pub watermark: SyncWatermark,
+    pub edges: Vec<Edge>,
+}
+
+/// Log of the last change of each edge, by sequence number.
+pub(crate) struct EdgeChangeLog {
+    session: u64,
+    seq: u64,
+    changes: BTreeMap<u64, (PeerId, PeerId)>,
+    seq_of: HashMap<(PeerId, PeerId), u64>,
+}
+
+impl Default for EdgeChangeLog {
+    fn default() -> Self {
+        Self {
+            session: thread_rng().gen(),
+            seq: 0,
+            changes: Default::default(),
+            seq_of: Default::default(),
+        }
+    }
+}
+
+impl EdgeChangeLog {
+    pub fn watermark(&self) -> SyncWatermark {
+        SyncWatermark { session: self.session, seq: self.seq }
+    }
+
+    pub fn record_change(&mut self, key: (PeerId, PeerId)) {
+        self.seq += 1;
+        if let Some(previous) = self.seq_of.insert(key.clone(), self.seq) {
+            self.changes.remove(&previous);
+        }
+        self.changes.insert(self.seq, key);
+    }
+
+    /// Forget an edge which was removed from the routing table. Peers keep it until they prune
+    /// it themselves.
+    pub fn record_removal(&mut self, key: &(PeerId, PeerId)) {
+        if let Some(previous) = self.seq_of.remove(key) {
+            self.changes.remove(&previous);
+        }
+    }
+
+    /// Approximate bytes used by the log.
+    pub fn memory_bytes(&self) -> usize {
+        btree_map_bytes(&self.changes) + hash_map_bytes(&self.seq_of)
+    }
+
+    /// Start a new log, e.g. after the routing table was replaced: no watermark is valid anymore.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Keys of the edges changed since `watermark`, or `None` if it is stale: it comes from
+    /// another session, or more than half of the `total_edges` changed since.
+    pub fn changed_since(
+        &self,
+        watermark: &SyncWatermark,
+        total_edges: usize,
+    ) -> Option<Vec<(PeerId, PeerId)>> {
+        if watermark.session != self.session || watermark.seq > self.seq {
+            return None;
+        }
+        let changed: Vec<_> = self.changes.range(watermark.seq + 1..).map(|(_, key)| key).collect();
+        if changed.len() * 2 > total_edges {
+            return None;
+        }
+        Some(changed.into_iter().cloned().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::random_peer_id;
+
+    use super::{EdgeChangeLog, SyncWatermark};
+
+    #[test]
+    fn changed_since_watermark() {
+        let mut log = EdgeChangeLog::default();
+        let keys: Vec<_> = (0..6).map(|_| (random_peer_id(), random_peer_id())).collect();
+        for key in keys.iter() {
+            log.record_change(key.clone());
+        }
+        let watermark = log.watermark();
+        assert_eq!(log.changed_since(&watermark, keys.len()), Some(vec![]));
+
+        log.record_change(keys[4].clone());
+        log.record_change(keys[1].clone());
+        log.record_change(keys[4].clone());
+        log.record_removal(&keys[1]);
+        assert_eq!(log.changed_since(&watermark, keys.len()), Some(vec![keys[4].clone()]));
+
+        // Too many changes for a delta.
+        for key in keys[..3].iter() {
+            log.record_change(key.clone());
+        }
+        assert_eq!(log.changed_since(&watermark, keys.len()), None);
+
+        let other_session = SyncWatermark { session: watermark.session.wrapping_add(1), seq: 0 };
+        assert_eq!(log.changed_since(&other_session, keys.len()), None);
+        let from_future = SyncWatermark { seq: log.watermark().seq + 1, ..watermark };
+        assert_eq!(log.changed_since(&from_future, keys.len()), None);
+    }
+}
@@ -0,0 +1,86 @@
+//! Operator-configured denylist of peer id prefixes, e.g. of keys of known attack infrastructure.
+//!
+//! Edges and account announcements referencing a denylisted peer, or a peer we banned, are
+//! rejected before their signatures are verified. Rejections are counted per rule.
+use near_primitives::network::PeerId;
+
+use crate::metrics;
+use crate::routing::Edge;
+
+/// Rule of the rejections of peers we banned, as opposed to denylisted prefixes.
+const BANNED_RULE: &str = "banned";
+
+#[derive(Debug, Default)]
+pub struct KeyPrefixDenylist {
+    /// Prefixes of the string representation of peer ids, e.g. `ed25519:Abc`.
+    prefixes: Vec<String>,
+}
+
+impl KeyPrefixDenylist {
+    pub fn new(prefixes: Vec<String>) -> Self {
+        Self { prefixes }
+    }
+
+    /// Denylisted prefix matching `peer_id`, if any.
+    pub fn matching_rule(&self, peer_id: &PeerId) -> Option<&str> {
+        if self.prefixes.is_empty() {
+            return None;
+        }
+        let peer_id = peer_id.to_string();
+        self.prefixes
+            .iter()
+            .find(|prefix| peer_id.starts_with(prefix.as_str()))
+            .map(|prefix| prefix.as_str())
+    }
+
+    /// Whether messages referencing `peer_id` are accepted: it isn't `is_banned` nor matches a
+    /// denylisted prefix. Rejections are counted in metrics.
+    pub fn allows(&self, peer_id: &PeerId, is_banned: impl Fn(&PeerId) -> bool) -> bool {
+        let rule = if is_banned(peer_id) { Some(BANNED_RULE) } else { self.matching_rule(peer_id) };
+        match rule {
+            Some(rule) => {
+                near_metrics::inc_counter_vec(&metrics::DENYLIST_REJECTIONS, &[rule]);
+                false
+            }
+            None => true,
+        }
+    }
+
+    /// Drop the edges which have a peer not `allows`-ed.
+    pub fn filter_edges(&self, edges: Vec<Edge>, is_banned: impl Fn(&PeerId) -> bool) -> Vec<Edge> {
+        edges
+            .into_iter()
+            .filter(|edge| {
+                self.allows(&edge.peer0, &is_banned) && self.allows(&edge.peer1, &is_banned)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::routing::Edge;
+    use crate::test_utils::random_peer_id;
+
+    use super::KeyPrefixDenylist;
+
+    #[test]
+    fn denylisted_prefixes() {
+        let (denied, banned, allowed) = (random_peer_id(), random_peer_id(), random_peer_id());
+        let prefix = denied.to_string()[..12].to_string();
+        let denylist = KeyPrefixDenylist::new(vec![prefix.clone()]);
+        assert_eq!(denylist.matching_rule(&denied), Some(prefix.as_str()));
+        assert_eq!(denylist.matching_rule(&allowed), None);
+
+        let is_banned = |peer_id: &_| peer_id == &banned;
+        let edges = vec![
+            Edge::make_fake_edge(allowed.clone(), denied.clone(), 1),
+            Edge::make_fake_edge(banned.clone(), allowed.clone(), 1),
+            Edge::make_fake_edge(allowed.clone(), random_peer_id(), 1),
+        ];
+        let edges = denylist.filter_edges(edges, is_banned);
+        assert_eq!(edges.len(), 1);
+        assert!(edges[0].contains_peer(&allowed));
+        assert!(!edges[0].contains_peer(&denied) && !edges[0].contains_peer(&banned));
+    }
+}
@@ -0,0 +1,102 @@
+//! Verbose routing diagnostics, turned on at runtime while investigating an incident: the route
+//! chosen for every routed message and the outcome of every received edge are logged under the
+//! `network::diagnostics` target.
+//!
+//! Diagnostics are turned off automatically once the requested duration elapses, so they need
+//! neither a restart nor a second request, and can't be left on by accident.
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+use tracing::info;
+
+use crate::metrics;
+use crate::time::{Clock, Time};
+
+/// Diagnostics can't be turned on for longer than this at once.
+pub const MAX_ROUTING_DIAGNOSTICS_DURATION: Duration = Duration::from_secs(60 * 60);
+
+/// Set while diagnostics are on, so the hot paths don't take the lock when they are off.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    /// Instant at which diagnostics are turned off again.
+    static ref ENABLED_UNTIL: Mutex<Option<Instant>> = Mutex::new(None);
+}
+
+/// Turn on routing diagnostics for `duration`, capped at `MAX_ROUTING_DIAGNOSTICS_DURATION`,
+/// or turn them off if `duration` is zero. Returns for how long they are on.
+pub fn enable_routing_diagnostics(duration: Duration, clock: &Clock) -> Duration {
+    enable_at(duration, clock.now())
+}
+
+fn enable_at(duration: Duration, now: Instant) -> Duration {
+    let duration = std::cmp::min(duration, MAX_ROUTING_DIAGNOSTICS_DURATION);
+    let mut enabled_until = ENABLED_UNTIL.lock().unwrap();
+    if duration == Duration::from_secs(0) {
+        if enabled_until.take().is_some() {
+            info!(target: "network", "Routing diagnostics turned off");
+        }
+    } else {
+        *enabled_until = Some(now + duration);
+        info!(target: "network", "Routing diagnostics turned on for {:?}", duration);
+    }
+    set_enabled(enabled_until.is_some());
+    duration
+}
+
+/// Whether routing diagnostics should be logged now.
+pub fn routing_diagnostics_enabled(clock: &Clock) -> bool {
+    ENABLED.load(Ordering::Relaxed) && routing_diagnostics_remaining(clock).is_some()
+}
+
+/// Time left until routing diagnostics are turned off, none if they are off.
+pub fn routing_diagnostics_remaining(clock: &Clock) -> Option<Duration> {
+    remaining_at(clock.now())
+}
+
+/// Turns diagnostics off if they expired at `now`.
+fn remaining_at(now: Instant) -> Option<Duration> {
+    let mut enabled_until = ENABLED_UNTIL.lock().unwrap();
+    let until = (*enabled_until)?;
+    if now >= until {
+        *enabled_until = None;
+        set_enabled(false);
+        info!(target: "network", "Routing diagnostics expired and were turned off");
+        return None;
+    }
+    Some(Time::duration_since(until, now))
+}
+
+fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+    near_metrics::set_gauge(&metrics::ROUTING_DIAGNOSTICS_ENABLED, enabled as i64);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{
+        enable_at, remaining_at, routing_diagnostics_enabled, MAX_ROUTING_DIAGNOSTICS_DURATION,
+    };
+    use crate::time::Clock;
+
+    #[test]
+    fn routing_diagnostics_expire() {
+        let now = Instant::now();
+        let duration = enable_at(Duration::from_secs(24 * 60 * 60), now);
+        assert_eq!(duration, MAX_ROUTING_DIAGNOSTICS_DURATION);
+
+        let half = MAX_ROUTING_DIAGNOSTICS_DURATION / 2;
+        assert_eq!(remaining_at(now + half), Some(half));
+        assert_eq!(remaining_at(now + MAX_ROUTING_DIAGNOSTICS_DURATION), None);
+        assert!(!routing_diagnostics_enabled(&Clock::real()));
+
+        enable_at(Duration::from_secs(60), now);
+        assert!(remaining_at(now).is_some());
+        enable_at(Duration::from_secs(0), now);
+        assert_eq!(remaining_at(now), None);
+    }
+}
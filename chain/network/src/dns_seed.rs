@@ -0,0 +1,101 @@
+//! Discovery of boot nodes from DNS seeds, so boot nodes can be rotated by updating DNS records
+//! instead of the config of every node.
+//!
+//! Each TXT record of a seed name is a peer in the format of boot nodes,
+//! `<peer id>@<ip or host>:<port>`. Each SRV record of `_near._tcp.<seed>` is the host and port of
+//! a peer, whose id is the TXT record of the host. Names are resolved with the system config.
+use std::io;
+
+use tracing::debug;
+use trust_dns_resolver::error::{ResolveError, ResolveErrorKind};
+use trust_dns_resolver::proto::rr::rdata::TXT;
+use trust_dns_resolver::TokioAsyncResolver;
+
+use near_network_primitives::types::{IpFamily, PeerInfo};
+
+/// Peers advertised by the DNS records of `seed`. Only addresses of `ip_family` are returned,
+/// IPv6 ones preferred if `prefer_v6`.
+pub(crate) async fn resolve_seed(
+    seed: &str,
+    ip_family: IpFamily,
+    prefer_v6: bool,
+) -> io::Result<Vec<PeerInfo>> {
+    let resolver = TokioAsyncResolver::tokio_from_system_conf()?;
+    let mut peers = vec![];
+    for txt in txt_records(&resolver, seed).await? {
+        match resolve_peer(&txt, ip_family, prefer_v6).await {
+            Ok(peer_info) => peers.push(peer_info),
+            Err(err) => {
+                debug!(target: "network", "Invalid peer {:?} of DNS seed {}: {}", txt, seed, err)
+            }
+        }
+    }
+    let srv_name = format!("_near._tcp.{}", seed);
+    let srvs = match resolver.srv_lookup(srv_name.as_str()).await {
+        Ok(srvs) => srvs.iter().map(|srv| (srv.target().to_utf8(), srv.port())).collect(),
+        Err(err) if is_missing(&err) => vec![],
+        Err(err) => return Err(err.into()),
+    };
+    for (target, port) in srvs {
+        let target = target.trim_end_matches('.');
+        for id in txt_records(&resolver, target).await? {
+            let peer = format!("{}@{}:{}", id, target, port);
+            match resolve_peer(&peer, ip_family, prefer_v6).await {
+                Ok(peer_info) => peers.push(peer_info),
+                Err(err) => {
+                    debug!(target: "network", "Invalid peer {:?} of DNS seed {}: {}", peer, seed, err)
+                }
+            }
+        }
+    }
+    Ok(peers)
+}
+
+/// TXT records of `name`. Empty if the name doesn't exist.
+async fn txt_records(resolver: &TokioAsyncResolver, name: &str) -> io::Result<Vec<String>> {
+    match resolver.txt_lookup(name).await {
+        Ok(txts) => Ok(txts.iter().map(txt_string).collect()),
+        Err(err) if is_missing(&err) => Ok(vec![]),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn is_missing(err: &ResolveError) -> bool {
+    matches!(err.kind(), ResolveErrorKind::NoRecordsFound { .. })
+}
+
+/// Long TXT records are split into strings of at most 255 bytes.
+fn txt_string(txt: &TXT) -> String {
+    String::from_utf8_lossy(&txt.txt_data().concat()).into_owned()
+}
+
+/// Parses a peer in the format of boot nodes, resolving its host name if it has one.
+async fn resolve_peer(
+    s: &str,
+    ip_family: IpFamily,
+    prefer_v6: bool,
+) -> Result<PeerInfo, Box<dyn std::error::Error>> {
+    let (mut peer_info, host) = PeerInfo::parse_with_host(s)?;
+    if let Some(host) = host {
+        let addrs = tokio::net::lookup_host(host.as_str()).await?;
+        peer_info.addr = ip_family.select_addr(addrs, prefer_v6);
+    }
+    match peer_info.addr {
+        Some(addr) if ip_family.allows(&addr) => Ok(peer_info),
+        _ => Err(format!("no {:?} address", ip_family).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use trust_dns_resolver::proto::rr::rdata::TXT;
+
+    use super::txt_string;
+
+    #[test]
+    fn txt_records() {
+        let long = "a".repeat(255);
+        let txt = TXT::new(vec![long.clone(), "bc".to_string()]);
+        assert_eq!(txt_string(&txt), format!("{}bc", long));
+    }
+}
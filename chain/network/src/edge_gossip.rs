@@ -0,0 +1,190 @@
+//! Bounded broadcast of edge announcements.
+//!
+//! Pushing every new edge to every peer sends it many times to each node on dense topologies.
+//! Instead we push it to `fanout` random peers which don't have it yet, and push the same edge at
+//! most `broadcast_budget` times, so a flapping connection can't flood the network. Every
+//! `digest_interval` each peer gets a digest of the recently changed edges it may miss
+//! (`EdgeDigest`), and pulls those it doesn't know (`EdgePull`).
+//!
+//! Only peers whose protocol version supports digests take part; the others still get every edge
+//! pushed to them, since they can't pull.
+use std::collections::{HashMap, HashSet};
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use cached::{Cached, SizedCache};
+use rand::seq::SliceRandom;
+use rand::thread_rng;
+
+use near_network_primitives::types::EdgeGossipConfig;
+use near_primitives::network::PeerId;
+
+use crate::routing::Edge;
+
+/// Number of recently changed edges we remember, and advertise in digests.
+pub const RECENT_EDGES_CACHE_SIZE: usize = 10_000;
+/// Maximum number of edges in a digest, or pulled at once.
+pub const MAX_DIGEST_EDGES: usize = 1_000;
+
+/// Edge advertised in a digest.
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Eq, Clone, Debug)]
+pub struct EdgeDigestEntry {
+    pub peer0: PeerId,
+    pub peer1: PeerId,
+    pub nonce: u64,
+}
+
+impl EdgeDigestEntry {
+    pub fn key(&self) -> (PeerId, PeerId) {
+        (self.peer0.clone(), self.peer1.clone())
+    }
+}
+
+/// Gossip state of a recently changed edge.
+struct RecentEdge {
+    nonce: u64,
+    /// Number of times the edge was pushed, with any nonce.
+    pushes: u32,
+    /// Peers which have the edge with `nonce`: they sent it to us, or we pushed or advertised it
+    /// to them.
+    holders: HashSet<PeerId>,
+}
+
+pub(crate) struct EdgeGossip {
+    config: EdgeGossipConfig,
+    recent: SizedCache<(PeerId, PeerId), RecentEdge>,
+}
+
+impl EdgeGossip {
+    pub fn new(config: EdgeGossipConfig) -> Self {
+        Self { config, recent: SizedCache::with_size(RECENT_EDGES_CACHE_SIZE) }
+    }
+
+    /// Gossip state of the edge `key` with `nonce`. Holders of an older nonce are forgotten.
+    /// Returns `None` if we already know a newer nonce.
+    fn entry(&mut self, key: &(PeerId, PeerId), nonce: u64) -> Option<&mut RecentEdge> {
+        if self.recent.cache_get(key).is_none() {
+            self.recent
+                .cache_set(key.clone(), RecentEdge { nonce, pushes: 0, holders: HashSet::new() });
+        }
+        let recent = self.recent.cache_get_mut(key)?;
+        if recent.nonce > nonce {
+            return None;
+        }
+        if recent.nonce < nonce {
+            recent.nonce = nonce;
+            recent.holders.clear();
+        }
+        Some(recent)
+    }
+
+    /// Remember that `peer_id` has the edge `key` with `nonce`, because it sent it to us or
+    /// advertised it.
+    pub fn record_holder(&mut self, peer_id: &PeerId, key: &(PeerId, PeerId), nonce: u64) {
+        if let Some(recent) = self.entry(key, nonce) {
+            recent.holders.insert(peer_id.clone());
+        }
+    }
+
+    /// Edges to push to each of `peers`. Each edge goes to at most `fanout` peers which don't
+    /// have it, unless its broadcast budget is exhausted; the other peers learn it from digests.
+    /// Returns the edges pushed to each peer and the number of edges over budget.
+    pub fn plan_broadcast(
+        &mut self,
+        edges: &[Edge],
+        peers: &[PeerId],
+    ) -> (HashMap<PeerId, Vec<Edge>>, usize) {
+        let mut plan: HashMap<PeerId, Vec<Edge>> = HashMap::new();
+        let mut over_budget = 0;
+        let (fanout, budget) = (self.config.fanout, self.config.broadcast_budget);
+        for edge in edges {
+            let recent = match self.entry(edge.key(), edge.nonce) {
+                Some(recent) => recent,
+                None => continue,
+            };
+            if budget > 0 && recent.pushes >= budget {
+                over_budget += 1;
+                continue;
+            }
+            let mut targets: Vec<_> =
+                peers.iter().filter(|peer_id| !recent.holders.contains(*peer_id)).collect();
+            if fanout > 0 && targets.len() > fanout {
+                targets.shuffle(&mut thread_rng());
+                targets.truncate(fanout);
+            }
+            if targets.is_empty() {
+                continue;
+            }
+            recent.pushes += 1;
+            for peer_id in targets {
+                recent.holders.insert(peer_id.clone());
+                plan.entry(peer_id.clone()).or_default().push(edge.clone());
+            }
+        }
+        (plan, over_budget)
+    }
+
+    /// Recently changed edges `peer_id` may miss, which are then considered known to it: it
+    /// pulls those it doesn't have.
+    pub fn digest(&mut self, peer_id: &PeerId) -> Vec<EdgeDigestEntry> {
+        let keys: Vec<_> = self.recent.key_order().cloned().collect();
+        let mut digest = Vec::new();
+        for key in keys {
+            if digest.len() >= MAX_DIGEST_EDGES {
+                break;
+            }
+            if let Some(recent) = self.recent.cache_get_mut(&key) {
+                if recent.holders.insert(peer_id.clone()) {
+                    digest.push(EdgeDigestEntry {
+                        peer0: key.0,
+                        peer1: key.1,
+                        nonce: recent.nonce,
+                    });
+                }
+            }
+        }
+        digest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use near_network_primitives::types::EdgeGossipConfig;
+
+    use crate::routing::Edge;
+    use crate::test_utils::random_peer_id;
+
+    use super::EdgeGossip;
+
+    #[test]
+    fn bounded_broadcast() {
+        let config =
+            EdgeGossipConfig { fanout: 2, broadcast_budget: 2, digest_interval: Duration::ZERO };
+        let mut gossip = EdgeGossip::new(config);
+        let peers: Vec<_> = (0..5).map(|_| random_peer_id()).collect();
+        let (peer0, peer1) = (random_peer_id(), random_peer_id());
+        let edge = |nonce| Edge::make_fake_edge(peer0.clone(), peer1.clone(), nonce);
+
+        // The peer which sent us the edge doesn't get it back.
+        gossip.record_holder(&peers[0], edge(1).key(), 1);
+        let (plan, over_budget) = gossip.plan_broadcast(&[edge(1)], &peers);
+        assert_eq!((plan.len(), over_budget), (2, 0));
+        assert!(!plan.contains_key(&peers[0]));
+
+        // The other peers get it from digests, once.
+        let digested = peers.iter().filter(|peer_id| !gossip.digest(peer_id).is_empty()).count();
+        assert_eq!(digested, 2);
+        assert!(peers.iter().all(|peer_id| gossip.digest(peer_id).is_empty()));
+
+        // A newer nonce is pushed again, until the budget is exhausted.
+        let (plan, _) = gossip.plan_broadcast(&[edge(2)], &peers);
+        assert_eq!(plan.len(), 2);
+        let (plan, over_budget) = gossip.plan_broadcast(&[edge(3)], &peers);
+        assert_eq!((plan.len(), over_budget), (0, 1));
+        assert_eq!(gossip.digest(&peers[0])[0].nonce, 3);
+        // Older nonces are ignored.
+        let (plan, over_budget) = gossip.plan_broadcast(&[edge(1)], &peers);
+        assert_eq!((plan.len(), over_budget), (0, 0));
+    }
+}
@@ -0,0 +1,112 @@
+use std::time::Instant;
+
+use cached::{Cached, SizedCache};
+
+use near_primitives::network::PeerId;
+
+use crate::time::{Clock, Time};
+
+/// Number of peers whose bucket is remembered, so reconnecting doesn't refill it.
+const EDGE_RATE_LIMITER_CACHE_SIZE: usize = 10_000;
+
+/// Token bucket holding up to `burst` tokens, refilled with `rate` tokens per second.
+#[derive(Clone, Debug)]
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(burst: u64, now: Instant) -> Self {
+        Self { tokens: burst as f64, updated: now }
+    }
+
+    /// Take up to `count` tokens, returns the number of tokens taken.
+    fn take(&mut self, count: u64, rate: u64, burst: u64, now: Instant) -> u64 {
+        let elapsed = Time::duration_since(now, self.updated);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * rate as f64).min(burst as f64);
+        self.updated = now;
+        let taken = (self.tokens.floor() as u64).min(count);
+        self.tokens -= taken as f64;
+        taken
+    }
+}
+
+/// Limits the number of edges each peer can make us verify and add to the routing table, so a
+/// peer flooding us with edges can't force constant recomputation of the routing table.
+pub struct EdgeRateLimiter {
+    /// Edges per second each peer is allowed to send. Zero disables the limit.
+    rate: u64,
+    /// Edges each peer can send at once, e.g. during a full routing table sync.
+    burst: u64,
+    buckets: SizedCache<PeerId, TokenBucket>,
+    clock: Clock,
+}
+
+impl EdgeRateLimiter {
+    pub fn new(rate: u64, burst: u64) -> Self {
+        Self {
+            rate,
+            burst,
+            buckets: SizedCache::with_size(EDGE_RATE_LIMITER_CACHE_SIZE),
+            clock: Clock::real(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Change the limits. Buckets of peers are kept, and refilled with the new limits.
+    pub fn set_limits(&mut self, rate: u64, burst: u64) {
+        self.rate = rate;
+        self.burst = burst;
+    }
+
+    /// Number of edges out of `count` received from `peer_id` which may be processed.
+    pub fn allow(&mut self, peer_id: &PeerId, count: usize) -> usize {
+        self.allow_at(peer_id, count, self.clock.now())
+    }
+
+    fn allow_at(&mut self, peer_id: &PeerId, count: usize, now: Instant) -> usize {
+        if self.rate == 0 {
+            return count;
+        }
+        let burst = self.burst;
+        if self.buckets.cache_get(peer_id).is_none() {
+            self.buckets.cache_set(peer_id.clone(), TokenBucket::new(burst, now));
+        }
+        let bucket = self.buckets.cache_get_mut(peer_id).unwrap();
+        bucket.take(count as u64, self.rate, burst, now) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::test_utils::random_peer_id;
+
+    use super::EdgeRateLimiter;
+
+    #[test]
+    fn token_bucket() {
+        let now = Instant::now();
+        let (peer0, peer1) = (random_peer_id(), random_peer_id());
+        let mut limiter = EdgeRateLimiter::new(10, 100);
+
+        assert_eq!(limiter.allow_at(&peer0, 80, now), 80);
+        assert_eq!(limiter.allow_at(&peer0, 80, now), 20);
+        assert_eq!(limiter.allow_at(&peer0, 1, now), 0);
+        // Other peers have their own bucket.
+        assert_eq!(limiter.allow_at(&peer1, 100, now), 100);
+
+        assert_eq!(limiter.allow_at(&peer0, 80, now + Duration::from_secs(2)), 20);
+        // Buckets don't fill above the burst size.
+        assert_eq!(limiter.allow_at(&peer0, 200, now + Duration::from_secs(100)), 100);
+
+        let mut unlimited = EdgeRateLimiter::new(0, 0);
+        assert_eq!(unlimited.allow_at(&peer0, 1_000_000, now), 1_000_000);
+    }
+}
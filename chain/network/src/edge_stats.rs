@@ -0,0 +1,83 @@
+//! Counters of the edges processed over the lifetime of the node, persisted on disk so they
+//! survive restarts. They show how efficient the routing gossip is over long periods, while
+//! the regular metrics reset on every restart.
+use std::io;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use tracing::warn;
+
+use near_store::{ColEdgeStats, Store};
+
+use crate::metrics;
+
+/// Key of the counters in `ColEdgeStats`.
+const EDGE_STATS_KEY: &[u8] = b"lifetime";
+
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct LifetimeEdgeStats {
+    /// Edges added to the routing table or discarded as duplicates.
+    pub processed: u64,
+    /// Edges discarded because we already knew them with the same or a newer nonce.
+    pub duplicates: u64,
+    /// Lists of edges received from peers which failed signature verification.
+    pub verification_failures: u64,
+}
+
+impl LifetimeEdgeStats {
+    /// Counters saved on disk, zero if none were saved yet.
+    pub fn load(store: &Store) -> Self {
+        match store.get_ser::<Self>(ColEdgeStats, EDGE_STATS_KEY) {
+            Ok(stats) => stats.unwrap_or_default(),
+            Err(err) => {
+                warn!(target: "network", "Failed to load lifetime edge stats: {}", err);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self, store: &Store) -> io::Result<()> {
+        let mut update = store.store_update();
+        update.set_ser(ColEdgeStats, EDGE_STATS_KEY, self)?;
+        update.commit()
+    }
+
+    /// Fraction of the processed edges which were duplicates.
+    pub fn duplicate_ratio(&self) -> f64 {
+        if self.processed == 0 {
+            return 0.0;
+        }
+        self.duplicates as f64 / self.processed as f64
+    }
+
+    pub fn export(&self) {
+        near_metrics::set_gauge(&metrics::EDGE_LIFETIME_PROCESSED, self.processed as i64);
+        near_metrics::set_gauge(&metrics::EDGE_LIFETIME_DUPLICATES, self.duplicates as i64);
+        near_metrics::set_gauge(
+            &metrics::EDGE_LIFETIME_VERIFICATION_FAILURES,
+            self.verification_failures as i64,
+        );
+        near_metrics::set_float_gauge(
+            &metrics::EDGE_LIFETIME_DUPLICATE_RATIO,
+            self.duplicate_ratio(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_store::test_utils::create_test_store;
+
+    use super::LifetimeEdgeStats;
+
+    #[test]
+    fn persisted() {
+        let store = create_test_store();
+        assert_eq!(LifetimeEdgeStats::load(&store), LifetimeEdgeStats::default());
+        assert_eq!(LifetimeEdgeStats::default().duplicate_ratio(), 0.0);
+
+        let stats = LifetimeEdgeStats { processed: 10, duplicates: 4, verification_failures: 1 };
+        stats.save(&store).unwrap();
+        assert_eq!(LifetimeEdgeStats::load(&store), stats);
+        assert_eq!(stats.duplicate_ratio(), 0.4);
+    }
+}
@@ -4,9 +4,21 @@ use actix::{Actor, Handler, SyncContext, System};
 
 use near_performance_metrics_macros::perf;
 
+use crate::failpoints::{self, FailpointsGuard, FailpointsHandle};
+use crate::metrics;
+use crate::routing::Edge;
 use crate::types::{EdgeList, StopMsg};
 
-pub(crate) struct EdgeVerifier {}
+pub(crate) struct EdgeVerifier {
+    /// Keeps the failpoints of the thread which started the pool installed on this worker thread.
+    _failpoints_guard: FailpointsGuard,
+}
+
+impl EdgeVerifier {
+    pub(crate) fn new(failpoints: FailpointsHandle) -> Self {
+        Self { _failpoints_guard: failpoints.install() }
+    }
+}
 
 impl Actor for EdgeVerifier {
     type Context = SyncContext<Self>;
@@ -24,7 +36,40 @@ impl Handler<EdgeList> for EdgeVerifier {
 
     #[perf]
     fn handle(&mut self, msg: EdgeList, _ctx: &mut Self::Context) -> Self::Result {
-        for edge in msg.edges {
+        let _batch_timer = near_metrics::start_timer(&metrics::EDGE_VERIFIER_BATCH_HISTOGRAM);
+        near_metrics::inc_counter(&metrics::EDGE_VERIFIER_BATCHES);
+        if failpoints::fail_point(failpoints::VERIFY_EDGES) {
+            return false;
+        }
+
+        #[cfg(feature = "test_features")]
+        let verify_signatures = !msg.adv_disable_edge_signature_verification;
+        #[cfg(not(feature = "test_features"))]
+        let verify_signatures = true;
+
+        // Only verify edges newer than the ones we already know.
+        let edges: Vec<Edge> = {
+            let guard = msg.edges_info_shared.lock().unwrap();
+            msg.edges
+                .into_iter()
+                .filter(|edge| {
+                    guard.get(&(edge.peer0.clone(), edge.peer1.clone())).cloned().unwrap_or(0u64)
+                        < edge.nonce
+                })
+                .collect()
+        };
+        if verify_signatures {
+            near_metrics::inc_counter_by(&metrics::EDGE_VERIFIER_EDGES, edges.len() as u64);
+        }
+
+        let sub_keys = msg.sub_keys.read().unwrap().clone();
+        // If the batch is valid skip verifying edges one by one.
+        let verified = !verify_signatures || Edge::verify_batch(&edges, &sub_keys);
+        if !verified {
+            near_metrics::inc_counter(&metrics::EDGE_VERIFIER_BATCH_FALLBACKS);
+        }
+
+        for edge in edges {
             let key = (edge.peer0.clone(), edge.peer1.clone());
             if msg.edges_info_shared.lock().unwrap().get(&key).cloned().unwrap_or(0u64)
                 >= edge.nonce
@@ -32,13 +77,7 @@ impl Handler<EdgeList> for EdgeVerifier {
                 continue;
             }
 
-            #[cfg(feature = "test_features")]
-            if !msg.adv_disable_edge_signature_verification && !edge.verify() {
-                return false;
-            }
-
-            #[cfg(not(feature = "test_features"))]
-            if !edge.verify() {
+            if !verified && !edge.verify_with_sub_keys(&sub_keys) {
                 return false;
             }
             {
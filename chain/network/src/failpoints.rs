@@ -0,0 +1,10 @@
+//! Failpoints of the network layer, see `near_store::failpoints`. They only fire in builds with
+//! the `failpoints` feature.
+pub use near_store::failpoints::{
+    fail_point, FailScenario, FailpointAction, FailpointsGuard, FailpointsHandle, STORE_COMMIT,
+};
+
+/// Drops a message the peer manager sends to a peer, as if the peer disconnected first.
+pub const SEND_MESSAGE: &str = "network::send_message";
+/// Fails the signature verification of a list of edges, as if one of them was invalid.
+pub const VERIFY_EDGES: &str = "network::verify_edges";
@@ -0,0 +1,142 @@
+//! Cache of the broadcast messages recently received from any peer, keyed by the hash of their
+//! content, so copies of the same block, transaction, approval or routing table sync received from
+//! other neighbors are dropped before being deserialized and verified again.
+//!
+//! Entries expire after `DEDUP_WINDOW`: a block received again much later, e.g. in response to a
+//! block request once we are done syncing headers, is processed again.
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use cached::{Cached, SizedCache};
+
+use near_primitives::hash::{hash, CryptoHash};
+
+use crate::codec;
+use crate::metrics;
+use crate::time::{Clock, Time};
+
+/// Number of message hashes remembered.
+pub const DEDUP_CACHE_SIZE: usize = 10_000;
+/// Copies received within this period after the first one are dropped.
+pub const DEDUP_WINDOW: Duration = Duration::from_millis(2_000);
+
+/// Variants of `PeerMessage` received unchanged from every neighbor which broadcasts them.
+const ROUTING_TABLE_SYNC: u8 = 3;
+const BLOCK: u8 = 11;
+const TRANSACTION: u8 = 12;
+const ROUTED: u8 = 13;
+const CHALLENGE: u8 = 15;
+/// Variant of `RoutedMessageBody::BlockApproval`.
+const BLOCK_APPROVAL: u8 = 0;
+
+/// Hash identifying the content of the serialized `PeerMessage` in `bytes`, if it is a broadcast
+/// message. Routed approvals are identified without their `ttl`, which each hop decrements.
+pub fn dedup_key(bytes: &[u8]) -> Option<(&'static str, CryptoHash)> {
+    match *bytes.get(0)? {
+        ROUTING_TABLE_SYNC => Some(("RoutingTableSync", hash(bytes))),
+        BLOCK => Some(("Block", hash(bytes))),
+        TRANSACTION => Some(("Transaction", hash(bytes))),
+        CHALLENGE => Some(("Challenge", hash(bytes))),
+        ROUTED => {
+            let ttl_idx = codec::routed_ttl_idx(bytes)?;
+            if *bytes.get(ttl_idx + 1)? != BLOCK_APPROVAL {
+                return None;
+            }
+            let without_ttl = [&bytes[..ttl_idx], &bytes[ttl_idx + 1..]].concat();
+            Some(("BlockApproval", hash(&without_ttl)))
+        }
+        _ => None,
+    }
+}
+
+/// Broadcast messages recently received from any peer, shared by all peer actors.
+#[derive(Clone)]
+pub struct SeenMessages {
+    cache: Arc<Mutex<SizedCache<CryptoHash, Instant>>>,
+    clock: Clock,
+}
+
+impl Default for SeenMessages {
+    fn default() -> Self {
+        Self::new(Clock::real())
+    }
+}
+
+impl SeenMessages {
+    pub fn new(clock: Clock) -> Self {
+        Self { cache: Arc::new(Mutex::new(SizedCache::with_size(DEDUP_CACHE_SIZE))), clock }
+    }
+
+    /// Whether the message in `bytes` is a copy of a broadcast message received recently.
+    /// Remembers it otherwise. Checks and drops are counted in metrics.
+    pub fn is_duplicate(&self, bytes: &[u8]) -> bool {
+        let (kind, key) = match dedup_key(bytes) {
+            Some(key) => key,
+            None => return false,
+        };
+        let duplicate = self.check_at(key, self.clock.now());
+        near_metrics::inc_counter_vec(&metrics::GOSSIP_DEDUP_CHECKED, &[kind]);
+        if duplicate {
+            near_metrics::inc_counter_vec(&metrics::GOSSIP_DEDUP_DROPPED, &[kind]);
+        }
+        duplicate
+    }
+
+    fn check_at(&self, key: CryptoHash, now: Instant) -> bool {
+        let mut cache = self.cache.lock().unwrap();
+        if let Some(first_seen) = cache.cache_get(&key) {
+            if Time::duration_since(now, *first_seen) <= DEDUP_WINDOW {
+                return true;
+            }
+        }
+        cache.cache_set(key, now);
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use borsh::BorshSerialize;
+    use near_crypto::{KeyType, SecretKey};
+    use near_primitives::block::{Approval, ApprovalInner};
+    use near_primitives::hash::CryptoHash;
+
+    use crate::types::{PeerIdOrHash, PeerMessage, RoutedMessage, RoutedMessageBody};
+
+    use super::{dedup_key, SeenMessages, DEDUP_WINDOW};
+
+    fn routed_approval(ttl: u8) -> Vec<u8> {
+        let sk = SecretKey::from_seed(KeyType::ED25519, "test");
+        let signature = sk.sign(CryptoHash::default().as_ref());
+        PeerMessage::Routed(RoutedMessage {
+            target: PeerIdOrHash::PeerId(sk.public_key().into()),
+            author: sk.public_key().into(),
+            signature: signature.clone(),
+            ttl,
+            body: RoutedMessageBody::BlockApproval(Approval {
+                account_id: "test".parse().unwrap(),
+                inner: ApprovalInner::Endorsement(CryptoHash::default()),
+                target_height: 1,
+                signature,
+            }),
+        })
+        .try_to_vec()
+        .unwrap()
+    }
+
+    #[test]
+    fn duplicates_dropped_within_window() {
+        let (approval, forwarded) = (routed_approval(100), routed_approval(99));
+        assert_ne!(approval, forwarded);
+        let (kind, key) = dedup_key(&approval).unwrap();
+        assert_eq!(kind, "BlockApproval");
+        assert_eq!(dedup_key(&forwarded), Some((kind, key)));
+        assert_eq!(dedup_key(&PeerMessage::PeersRequest.try_to_vec().unwrap()), None);
+
+        let seen = SeenMessages::default();
+        let now = std::time::Instant::now();
+        assert!(!seen.check_at(key, now));
+        assert!(seen.check_at(key, now + DEDUP_WINDOW));
+        assert!(!seen.check_at(key, now + DEDUP_WINDOW * 3));
+    }
+}
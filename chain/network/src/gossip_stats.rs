@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use actix::dev::MessageResponse;
+use cached::{Cached, SizedCache};
+#[cfg(feature = "test_features")]
+use serde::Serialize;
+
+use near_primitives::network::PeerId;
+
+use crate::routing::Edge;
+use crate::time::{Clock, Time};
+
+/// Window over which the cost of the routing gossip of each peer is accumulated.
+pub const GOSSIP_STATS_WINDOW: Duration = Duration::from_secs(60 * 60);
+/// Peers which sent at least this many bytes of edges in the last window...
+const REDUNDANT_GOSSIP_MIN_BYTES: u64 = 1_000_000;
+/// ...and for which at least this percentage of those bytes were edges we already knew
+/// are considered redundant gossipers.
+const REDUNDANT_GOSSIP_MIN_PERCENT: u64 = 90;
+/// Number of edges received from each redundant gossiper remembered to filter what we send back.
+const REDUNDANT_GOSSIP_EDGES_CACHE_SIZE: usize = 10_000;
+
+/// Cost of the routing gossip received from a single peer.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "test_features", derive(Serialize))]
+pub struct EdgeGossipCost {
+    /// Edges that updated our routing table.
+    pub new_edges: u64,
+    pub new_bytes: u64,
+    /// Edges we already knew with the same or a newer nonce.
+    pub redundant_edges: u64,
+    pub redundant_bytes: u64,
+}
+
+impl EdgeGossipCost {
+    fn merge(&self, other: &EdgeGossipCost) -> EdgeGossipCost {
+        EdgeGossipCost {
+            new_edges: self.new_edges + other.new_edges,
+            new_bytes: self.new_bytes + other.new_bytes,
+            redundant_edges: self.redundant_edges + other.redundant_edges,
+            redundant_bytes: self.redundant_bytes + other.redundant_bytes,
+        }
+    }
+
+    fn total_bytes(&self) -> u64 {
+        self.new_bytes + self.redundant_bytes
+    }
+}
+
+#[derive(MessageResponse, Debug)]
+#[cfg_attr(feature = "test_features", derive(Serialize))]
+pub struct GetEdgeGossipStatsResult {
+    /// Peers sorted by the amount of redundant bytes they sent us in the last hour.
+    pub top_contributors: Vec<(PeerId, EdgeGossipCost)>,
+}
+
+/// Tracks how many bytes the routing gossip of every peer costs us.
+/// Costs are accumulated for `GOSSIP_STATS_WINDOW`; the previous window is kept, so queries
+/// always cover between one and two windows.
+pub struct EdgeGossipStats {
+    clock: Clock,
+    window_start: Instant,
+    current: HashMap<PeerId, EdgeGossipCost>,
+    previous: HashMap<PeerId, EdgeGossipCost>,
+    /// Latest edges received from each redundant gossiper.
+    received_from: HashMap<PeerId, SizedCache<(PeerId, PeerId), u64>>,
+}
+
+impl Default for EdgeGossipStats {
+    fn default() -> Self {
+        Self::new(Clock::real())
+    }
+}
+
+impl EdgeGossipStats {
+    pub fn new(clock: Clock) -> Self {
+        Self {
+            window_start: clock.now(),
+            clock,
+            current: HashMap::new(),
+            previous: HashMap::new(),
+            received_from: HashMap::new(),
+        }
+    }
+
+    fn rotate(&mut self) {
+        let now = self.clock.now();
+        if Time::duration_since(now, self.window_start) >= GOSSIP_STATS_WINDOW {
+            self.previous = std::mem::take(&mut self.current);
+            self.window_start = now;
+        }
+    }
+
+    /// Record edges received from `peer_id`. `known_nonce` returns the nonce we have for
+    /// an edge before processing the message.
+    pub fn record_edges<F>(&mut self, peer_id: &PeerId, edges: &[Edge], known_nonce: F)
+    where
+        F: Fn(&(PeerId, PeerId)) -> u64,
+    {
+        self.rotate();
+        let is_redundant_gossiper = self.is_redundant_gossiper(peer_id);
+        let cost = self.current.entry(peer_id.clone()).or_default();
+        for edge in edges {
+            let key = (edge.peer0.clone(), edge.peer1.clone());
+            let bytes = borsh::BorshSerialize::try_to_vec(edge).map_or(0, |v| v.len() as u64);
+            if known_nonce(&key) >= edge.nonce {
+                cost.redundant_edges += 1;
+                cost.redundant_bytes += bytes;
+            } else {
+                cost.new_edges += 1;
+                cost.new_bytes += bytes;
+            }
+            if is_redundant_gossiper {
+                self.received_from
+                    .entry(peer_id.clone())
+                    .or_insert_with(|| SizedCache::with_size(REDUNDANT_GOSSIP_EDGES_CACHE_SIZE))
+                    .cache_set(key, edge.nonce);
+            }
+        }
+    }
+
+    /// Cost of the gossip of `peer_id` over the current and the previous window.
+    pub fn cost(&self, peer_id: &PeerId) -> EdgeGossipCost {
+        let current = self.current.get(peer_id).cloned().unwrap_or_default();
+        self.previous.get(peer_id).map_or(current.clone(), |previous| previous.merge(&current))
+    }
+
+    /// Whether most of the edges `peer_id` sends us are already known.
+    pub fn is_redundant_gossiper(&self, peer_id: &PeerId) -> bool {
+        let cost = self.cost(peer_id);
+        cost.total_bytes() >= REDUNDANT_GOSSIP_MIN_BYTES
+            && cost.redundant_bytes * 100 >= cost.total_bytes() * REDUNDANT_GOSSIP_MIN_PERCENT
+    }
+
+    /// Whether `peer_id` already sent us `edge` (or a newer version of it), so there is no
+    /// need to send it back. Only tracked for redundant gossipers.
+    pub fn was_received_from(&mut self, peer_id: &PeerId, edge: &Edge) -> bool {
+        self.received_from.get_mut(peer_id).map_or(false, |cache| {
+            cache
+                .cache_get(&(edge.peer0.clone(), edge.peer1.clone()))
+                .map_or(false, |nonce| *nonce >= edge.nonce)
+        })
+    }
+
+    pub fn remove_peer(&mut self, peer_id: &PeerId) {
+        self.received_from.remove(peer_id);
+    }
+
+    /// Up to `limit` peers which sent the most redundant bytes.
+    pub fn top_contributors(&self, limit: usize) -> Vec<(PeerId, EdgeGossipCost)> {
+        let mut result: Vec<_> = self
+            .current
+            .keys()
+            .chain(self.previous.keys())
+            .collect::<std::collections::HashSet<_>>()
+            .into_iter()
+            .map(|peer_id| (peer_id.clone(), self.cost(peer_id)))
+            .collect();
+        result.sort_by(|a, b| {
+            b.1.redundant_bytes.cmp(&a.1.redundant_bytes).then_with(|| a.0.cmp(&b.0))
+        });
+        result.truncate(limit);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::routing::Edge;
+    use crate::test_utils::random_peer_id;
+
+    use super::EdgeGossipStats;
+
+    #[test]
+    fn redundant_edges_are_counted() {
+        let (sender0, sender1) = (random_peer_id(), random_peer_id());
+        let edge = Edge::make_fake_edge(random_peer_id(), random_peer_id(), 3);
+        let mut stats = EdgeGossipStats::default();
+
+        stats.record_edges(&sender0, &[edge.clone()], |_| 1);
+        stats.record_edges(&sender1, &[edge.clone()], |_| 3);
+        stats.record_edges(&sender1, &[edge], |_| 5);
+
+        assert_eq!(stats.cost(&sender0).new_edges, 1);
+        assert_eq!(stats.cost(&sender0).redundant_edges, 0);
+        assert_eq!(stats.cost(&sender1).redundant_edges, 2);
+
+        let top = stats.top_contributors(1);
+        assert_eq!(top.len(), 1);
+        assert_eq!(top[0].0, sender1);
+        assert!(!stats.is_redundant_gossiper(&sender1));
+    }
+}
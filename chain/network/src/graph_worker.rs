@@ -0,0 +1,164 @@
+//! Computation of the routes of big routing graphs on worker threads, so the mailbox of the
+//! peer manager isn't blocked while shortest paths are computed from scratch.
+//!
+//! Workers compute routes from a snapshot of the graph. If the graph without us splits into
+//! several components, the routes of each group of components, see `Graph::partitions`, are
+//! computed by a different worker and merged afterwards.
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use actix::{Actor, Addr, Handler, MailboxError, Message, MessageResult, SyncContext, System};
+use futures::future::join_all;
+
+use near_performance_metrics_macros::perf;
+use near_primitives::network::PeerId;
+
+use crate::metrics;
+use crate::routing::{Graph, ShortestPaths};
+use crate::types::StopMsg;
+
+/// Routes of graphs with fewer nodes are computed by the peer manager, since copying the graph
+/// takes about as long.
+pub(crate) const MIN_GRAPH_NODES_FOR_WORKERS: usize = 1_000;
+
+pub(crate) struct GraphWorker;
+
+impl Actor for GraphWorker {
+    type Context = SyncContext<Self>;
+}
+
+impl Handler<StopMsg> for GraphWorker {
+    type Result = ();
+    fn handle(&mut self, _: StopMsg, _ctx: &mut Self::Context) -> Self::Result {
+        System::current().stop();
+    }
+}
+
+/// Split the neighbors of the source of `graph` in at most `max_partitions` groups whose routes
+/// can be computed separately.
+pub(crate) struct PartitionGraph {
+    pub graph: Arc<Graph>,
+    pub max_partitions: usize,
+}
+
+impl Message for PartitionGraph {
+    type Result = Vec<Vec<usize>>;
+}
+
+impl Handler<PartitionGraph> for GraphWorker {
+    type Result = MessageResult<PartitionGraph>;
+
+    #[perf]
+    fn handle(&mut self, msg: PartitionGraph, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(msg.graph.partitions(msg.max_partitions))
+    }
+}
+
+/// Compute the routes of `graph` through the neighbors of its source at positions `neighbors`
+/// of its adjacency list, all of them if `None`.
+pub(crate) struct ComputeRoutes {
+    pub graph: Arc<Graph>,
+    pub neighbors: Option<Vec<usize>>,
+}
+
+/// Routes computed from the snapshot of the graph at `version`, with their shortest paths.
+pub(crate) struct ComputedRoutes {
+    pub version: u64,
+    pub spf: ShortestPaths,
+    pub routes: HashMap<PeerId, Vec<PeerId>>,
+}
+
+impl Message for ComputeRoutes {
+    type Result = ComputedRoutes;
+}
+
+impl Handler<ComputeRoutes> for GraphWorker {
+    type Result = MessageResult<ComputeRoutes>;
+
+    #[perf]
+    fn handle(&mut self, msg: ComputeRoutes, _ctx: &mut Self::Context) -> Self::Result {
+        let _timer = near_metrics::start_timer(&metrics::ROUTING_GRAPH_WORKER_HISTOGRAM);
+        let spf = msg.graph.partial_shortest_paths(msg.neighbors.as_deref());
+        let routes = msg.graph.compute_result(&spf);
+        MessageResult(ComputedRoutes { version: msg.graph.version(), spf, routes })
+    }
+}
+
+/// Compute the routes of `graph` on the workers of `pool`, splitting the graph in at most
+/// `max_partitions` parts computed in parallel.
+pub(crate) async fn compute_routes(
+    pool: Addr<GraphWorker>,
+    graph: Arc<Graph>,
+    max_partitions: usize,
+) -> Result<ComputedRoutes, MailboxError> {
+    let partitions = if max_partitions > 1 {
+        pool.send(PartitionGraph { graph: graph.clone(), max_partitions }).await?
+    } else {
+        vec![]
+    };
+    if partitions.len() <= 1 {
+        return pool.send(ComputeRoutes { graph, neighbors: None }).await;
+    }
+    near_metrics::inc_counter(&metrics::ROUTING_GRAPH_PARTITIONED_RECOMPUTATIONS);
+    let parts = join_all(partitions.into_iter().map(|neighbors| {
+        pool.send(ComputeRoutes { graph: graph.clone(), neighbors: Some(neighbors) })
+    }))
+    .await
+    .into_iter()
+    .collect::<Result<Vec<_>, _>>()?;
+
+    let mut routes = HashMap::new();
+    let mut spfs = Vec::with_capacity(parts.len());
+    for part in parts {
+        routes.extend(part.routes);
+        spfs.push(part.spf);
+    }
+    let spf = ShortestPaths::merge(spfs).expect("at least two partitions");
+    Ok(ComputedRoutes { version: graph.version(), spf, routes })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix::{SyncArbiter, System};
+
+    use super::{compute_routes, GraphWorker};
+    use crate::routing::Graph;
+    use crate::test_utils::random_peer_id;
+
+    #[test]
+    fn compute_routes_on_workers() {
+        // Three components hanging from the source, one of them reached through two neighbors.
+        let source = random_peer_id();
+        let mut graph = Graph::new(source.clone());
+        for _ in 0..3 {
+            let (a, b, c) = (random_peer_id(), random_peer_id(), random_peer_id());
+            graph.add_edge(source.clone(), a.clone());
+            graph.add_edge(a.clone(), b.clone());
+            graph.add_edge(b, c);
+        }
+        let (a, b, c) = (random_peer_id(), random_peer_id(), random_peer_id());
+        graph.add_edge(source.clone(), a.clone());
+        graph.add_edge(source, b.clone());
+        graph.add_edge(a, c.clone());
+        graph.add_edge(b, c);
+        assert_eq!(graph.partitions(8).len(), 4);
+        assert_eq!(graph.partitions(2).len(), 2);
+        let expected = graph.calculate_distance();
+        let graph = Arc::new(graph);
+
+        near_actix_test_utils::run_actix(async move {
+            let pool = SyncArbiter::start(2, || GraphWorker);
+            actix::spawn(async move {
+                for max_partitions in [1, 2, 8] {
+                    let computed =
+                        compute_routes(pool.clone(), graph.clone(), max_partitions).await.unwrap();
+                    assert_eq!(computed.version, graph.version());
+                    assert_eq!(computed.routes, expected);
+                }
+                System::current().stop();
+            });
+        });
+    }
+}
@@ -5,6 +5,9 @@ use std::hash::Hasher;
 use borsh::{BorshDeserialize, BorshSerialize};
 use tracing::error;
 
+use near_primitives::checked_feature;
+use near_primitives::version::ProtocolVersion;
+
 /// Ibf consists of multiple boxes, this constant specified the number of unique boxes
 /// into which each element is inserted into IBF. According to https://www.ics.uci.edu/~eppstein/pubs/EppGooUye-SIGCOMM-11.pdf
 /// either number 3 or 4 is optimal.
@@ -33,7 +36,117 @@ impl IbfBox {
     }
 }
 
-type IbfHasher = DefaultHasher;
+/// Hash function used to pick boxes for an element and to verify that a box contains exactly one
+/// element. It doesn't need to be cryptographically strong, but both sides of the routing table
+/// exchange need to use the same function and seed.
+pub trait IbfHasher {
+    fn hash(&self, elem: u64) -> u64;
+}
+
+/// SipHash-1-3 (`std`'s `DefaultHasher`) keyed by seed. Used with peers which don't support
+/// `ProtocolFeature::FastIbfHasher`.
+#[derive(Clone)]
+pub struct SipIbfHasher(DefaultHasher);
+
+impl SipIbfHasher {
+    pub fn new(seed: u64) -> Self {
+        let mut hasher = DefaultHasher::default();
+        hasher.write_u64(seed);
+        Self(hasher)
+    }
+}
+
+impl IbfHasher for SipIbfHasher {
+    fn hash(&self, elem: u64) -> u64 {
+        let mut h = self.0.clone();
+        h.write_u64(elem);
+        h.finish()
+    }
+}
+
+/// XXH64 of a single `u64` keyed by seed.
+#[derive(Clone)]
+pub struct XxIbfHasher {
+    seed: u64,
+}
+
+impl XxIbfHasher {
+    const PRIME64_1: u64 = 0x9E3779B185EBCA87;
+    const PRIME64_2: u64 = 0xC2B2AE3D27D4EB4F;
+    const PRIME64_3: u64 = 0x165667B19E3779F9;
+    const PRIME64_4: u64 = 0x85EBCA77C2B2AE63;
+    const PRIME64_5: u64 = 0x27D4EB2F165667C5;
+
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+}
+
+impl IbfHasher for XxIbfHasher {
+    fn hash(&self, elem: u64) -> u64 {
+        let mut h = self.seed.wrapping_add(Self::PRIME64_5).wrapping_add(8);
+        let k = elem.wrapping_mul(Self::PRIME64_2).rotate_left(31).wrapping_mul(Self::PRIME64_1);
+        h ^= k;
+        h = h.rotate_left(27).wrapping_mul(Self::PRIME64_1).wrapping_add(Self::PRIME64_4);
+        h ^= h >> 33;
+        h = h.wrapping_mul(Self::PRIME64_2);
+        h ^= h >> 29;
+        h = h.wrapping_mul(Self::PRIME64_3);
+        h ^= h >> 32;
+        h
+    }
+}
+
+/// Hash function agreed on with a peer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IbfHasherKind {
+    Sip,
+    Xx,
+}
+
+impl Default for IbfHasherKind {
+    fn default() -> Self {
+        IbfHasherKind::Sip
+    }
+}
+
+impl IbfHasherKind {
+    /// Select hash function given protocol version negotiated during handshake.
+    /// Both sides of a connection use the same version, so they pick the same hasher.
+    pub fn negotiate(protocol_version: ProtocolVersion) -> Self {
+        checked_feature!(
+            "protocol_feature_fast_ibf_hasher",
+            FastIbfHasher,
+            protocol_version,
+            { IbfHasherKind::Xx },
+            { IbfHasherKind::Sip }
+        )
+    }
+}
+
+#[derive(Clone)]
+enum AnyIbfHasher {
+    Sip(SipIbfHasher),
+    Xx(XxIbfHasher),
+}
+
+impl AnyIbfHasher {
+    fn new(kind: IbfHasherKind, seed: u64) -> Self {
+        match kind {
+            IbfHasherKind::Sip => AnyIbfHasher::Sip(SipIbfHasher::new(seed)),
+            IbfHasherKind::Xx => AnyIbfHasher::Xx(XxIbfHasher::new(seed)),
+        }
+    }
+}
+
+impl IbfHasher for AnyIbfHasher {
+    fn hash(&self, elem: u64) -> u64 {
+        match self {
+            AnyIbfHasher::Sip(hasher) => hasher.hash(elem),
+            AnyIbfHasher::Xx(hasher) => hasher.hash(elem),
+        }
+    }
+}
 
 /// Data structure representing Inverse Bloom Filter (IBF). It's used to store elements of a set,
 /// in the current implementation they are of type 'u64', but it could be extended. Each `IbfSet`
@@ -55,28 +168,32 @@ pub struct Ibf {
     /// Vector containing all elements of IBF. Each one has xor of values stored, and xor of hashes stored.
     pub data: Vec<IbfBox>,
     /// Hashing object used to generate pseudo-random number
-    hasher: IbfHasher,
+    hasher: AnyIbfHasher,
+    /// Kind of `hasher`, both IBFs need to use the same one to be merged.
+    pub hasher_kind: IbfHasherKind,
     /// Hash seed used to generate IBF
     pub seed: u64,
 }
 
 impl Ibf {
     pub fn new(capacity: usize, seed: u64) -> Self {
+        Self::with_hasher(capacity, seed, IbfHasherKind::default())
+    }
+
+    pub fn with_hasher(capacity: usize, seed: u64, hasher_kind: IbfHasherKind) -> Self {
         let k = Ibf::calculate_k(capacity);
         let new_capacity = (1 << k) + NUM_HASHES - 1;
 
-        let mut hasher = IbfHasher::default();
-        hasher.write_u64(seed);
-        Self { data: vec![IbfBox::default(); new_capacity], hasher, k, seed }
+        let hasher = AnyIbfHasher::new(hasher_kind, seed);
+        Self { data: vec![IbfBox::default(); new_capacity], hasher, hasher_kind, k, seed }
     }
 
-    /// Create Ibf from vector of elements and seed
-    pub fn from_vec(data: &[IbfBox], seed: u64) -> Self {
+    /// Create Ibf from vector of elements, seed and hash function
+    pub fn from_vec(data: &[IbfBox], seed: u64, hasher_kind: IbfHasherKind) -> Self {
         let k = Ibf::calculate_k(data.len());
 
-        let mut hasher = IbfHasher::default();
-        hasher.write_u64(seed);
-        Self { data: data.into(), hasher, k, seed }
+        let hasher = AnyIbfHasher::new(hasher_kind, seed);
+        Self { data: data.into(), hasher, hasher_kind, k, seed }
     }
 
     /// Calculate minimum parameter 'k', such that given IBF has at least 'capacity` elements.
@@ -105,9 +222,7 @@ impl Ibf {
 
     /// Compute hash of element
     fn compute_hash(&self, elem: u64) -> u64 {
-        let mut h = self.hasher.clone();
-        h.write_u64(elem);
-        h.finish()
+        self.hasher.hash(elem)
     }
 
     /// Add element to IBF, which involves updating values of 3 boxes.
@@ -231,10 +346,14 @@ impl Ibf {
 
 #[cfg(test)]
 mod tests {
-    use crate::ibf::Ibf;
+    use crate::ibf::{Ibf, IbfHasher, IbfHasherKind, XxIbfHasher};
 
-    fn create_blt(elements: impl IntoIterator<Item = u64>, capacity: usize) -> Ibf {
-        let mut sketch = Ibf::new(capacity, 0);
+    fn create_blt(
+        elements: impl IntoIterator<Item = u64>,
+        capacity: usize,
+        hasher_kind: IbfHasherKind,
+    ) -> Ibf {
+        let mut sketch = Ibf::with_hasher(capacity, 0, hasher_kind);
         for item in elements.into_iter() {
             sketch.add(item);
         }
@@ -245,6 +364,17 @@ mod tests {
     fn create_blt_test() {
         let set = 1000000_3_00000u64..1000000_301000u64;
 
-        assert_eq!(1000, create_blt(set, 2048).recover().unwrap().len())
+        assert_eq!(
+            1000,
+            create_blt(set.clone(), 2048, IbfHasherKind::Sip).recover().unwrap().len()
+        );
+        assert_eq!(1000, create_blt(set, 2048, IbfHasherKind::Xx).recover().unwrap().len());
+    }
+
+    #[test]
+    fn xx_hasher_matches_reference() {
+        // XXH64 of the 8 little-endian bytes of the element.
+        assert_eq!(XxIbfHasher::new(0).hash(0), 0x34C96ACDCADB1BBB);
+        assert_ne!(XxIbfHasher::new(0).hash(1), XxIbfHasher::new(1).hash(1));
     }
 }
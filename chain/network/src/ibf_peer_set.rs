@@ -4,8 +4,10 @@ use rand::Rng;
 
 use near_primitives::network::PeerId;
 
+use crate::ibf::IbfHasherKind;
 use crate::ibf_set::IbfSet;
-use crate::routing::{Edge, SimpleEdge};
+use crate::routing::SimpleEdge;
+use crate::routing_profile::hash_map_bytes;
 
 pub type SlotMapId = u64;
 
@@ -52,78 +54,133 @@ impl SlotMap {
     }
 }
 
-/// IBfPeerSet contains collection of IbfSets, each for one connected peer.
+/// Number of peers we pick the same seed for before picking a new one. Sharing seeds spares
+/// building the IBFs of every new peer from all the edges, while peers which don't pick their
+/// seed can't learn the seed of all the others.
+pub const PEERS_PER_LOCAL_SEED: usize = 32;
+
+/// IBFs built with one seed and hash function, shared by all the peers using them.
+struct SharedIbfSet {
+    ibf_set: IbfSet<SimpleEdge>,
+    peers: usize,
+}
+
+/// Seed we pick for new peers using a hash function, and how many peers it was picked for.
+struct LocalSeed {
+    seed: u64,
+    peers: usize,
+}
+
+/// IBfPeerSet contains the IbfSets of the connected peers. Peers using the same seed and hash
+/// function share their IbfSet, which is updated once per added or removed edge, so only the
+/// first peer with a given seed pays for building it from all the edges.
 #[derive(Default)]
 pub struct IbfPeerSet {
-    peers: HashMap<PeerId, IbfSet<SimpleEdge>>,
+    peers: HashMap<PeerId, (u64, IbfHasherKind)>,
+    ibf_sets: HashMap<(u64, IbfHasherKind), SharedIbfSet>,
+    local_seeds: HashMap<IbfHasherKind, LocalSeed>,
     slot_map: SlotMap,
     edges: u64,
 }
 
 impl IbfPeerSet {
     pub fn get(&self, peer_id: &PeerId) -> Option<&IbfSet<SimpleEdge>> {
-        self.peers.get(peer_id)
+        let key = self.peers.get(peer_id)?;
+        self.ibf_sets.get(key).map(|shared| &shared.ibf_set)
     }
 
-    /// Add IbfSet assigned to given peer, defined by `seed`.
+    /// Add IbfSet assigned to given peer, defined by `seed` and `hasher_kind`. If `seed` is
+    /// `None`, the seed picked for the previous peers is used, unless `PEERS_PER_LOCAL_SEED`
+    /// peers use it already. Returns the seed.
     pub fn add_peer(
         &mut self,
         peer_id: PeerId,
         seed: Option<u64>,
-        edges_info: &mut HashMap<(PeerId, PeerId), Edge>,
+        hasher_kind: IbfHasherKind,
     ) -> u64 {
-        if let Some(ibf_set) = self.peers.get(&peer_id) {
-            return ibf_set.get_seed();
+        if let Some((seed, _)) = self.peers.get(&peer_id) {
+            return *seed;
         }
-        let seed = if let Some(seed) = seed {
-            seed
-        } else {
-            let mut rng = rand::thread_rng();
-            rng.gen()
-        };
-
-        let mut ibf_set = IbfSet::new(seed);
-        // Initialize IbfSet with edges
-        for (key, e) in edges_info.iter() {
-            let se = SimpleEdge::new(key.0.clone(), key.1.clone(), e.nonce);
-            if let Some(id) = self.slot_map.get(&se) {
-                ibf_set.add_edge(&se, id);
+        let seed = seed.unwrap_or_else(|| self.pick_local_seed(hasher_kind));
+
+        let slot_map = &self.slot_map;
+        let shared = self.ibf_sets.entry((seed, hasher_kind)).or_insert_with(|| {
+            let mut ibf_set = IbfSet::with_hasher(seed, hasher_kind);
+            // Initialize IbfSet with edges
+            for (id, edge) in slot_map.id2e.iter() {
+                ibf_set.add_edge(edge, *id);
             }
-        }
-        let seed = ibf_set.get_seed();
-        self.peers.insert(peer_id.clone(), ibf_set);
+            SharedIbfSet { ibf_set, peers: 0 }
+        });
+        shared.peers += 1;
+        self.peers.insert(peer_id, (seed, hasher_kind));
         seed
     }
 
-    /// Remove IbfSet associated with peer.
+    fn pick_local_seed(&mut self, hasher_kind: IbfHasherKind) -> u64 {
+        let local_seed = self
+            .local_seeds
+            .entry(hasher_kind)
+            .or_insert_with(|| LocalSeed { seed: rand::thread_rng().gen(), peers: 0 });
+        if local_seed.peers >= PEERS_PER_LOCAL_SEED {
+            *local_seed = LocalSeed { seed: rand::thread_rng().gen(), peers: 0 };
+        }
+        local_seed.peers += 1;
+        local_seed.seed
+    }
+
+    /// Remove IbfSet associated with peer, unless other peers share it.
     pub fn remove_peer(&mut self, peer_id: &PeerId) {
-        self.peers.remove(peer_id);
+        let key = match self.peers.remove(peer_id) {
+            Some(key) => key,
+            None => return,
+        };
+        if let Some(shared) = self.ibf_sets.get_mut(&key) {
+            shared.peers -= 1;
+            if shared.peers == 0 {
+                self.ibf_sets.remove(&key);
+            }
+        }
     }
 
-    /// Add edge to each IbfSet for each peer.
+    /// Add edge to each shared IbfSet.
     pub fn add_edge(&mut self, edge: &SimpleEdge) -> Option<SlotMapId> {
         let id = self.slot_map.insert(edge);
         if let Some(id) = id {
             self.edges += 1;
-            for (_, val) in self.peers.iter_mut() {
-                val.add_edge(edge, id);
+            for shared in self.ibf_sets.values_mut() {
+                shared.ibf_set.add_edge(edge, id);
             }
         }
         id
     }
 
-    /// Remove edge from each IbfSet for each peer.
+    /// Remove edge from each shared IbfSet.
     pub fn remove_edge(&mut self, edge: &SimpleEdge) -> bool {
         if let Some(_id) = self.slot_map.pop(edge) {
             self.edges -= 1;
-            for (_, val) in self.peers.iter_mut() {
-                val.remove_edge(&edge);
+            for shared in self.ibf_sets.values_mut() {
+                shared.ibf_set.remove_edge(&edge);
             }
             return true;
         }
         false
     }
 
+    /// Number of distinct IbfSets kept for the connected peers.
+    pub fn num_ibf_sets(&self) -> usize {
+        self.ibf_sets.len()
+    }
+
+    /// Approximate bytes used by the IbfSets of all peers and the ids of the edges.
+    pub fn memory_bytes(&self) -> usize {
+        hash_map_bytes(&self.peers)
+            + hash_map_bytes(&self.ibf_sets)
+            + self.ibf_sets.values().map(|shared| shared.ibf_set.memory_bytes()).sum::<usize>()
+            + hash_map_bytes(&self.slot_map.id2e)
+            + hash_map_bytes(&self.slot_map.e2id)
+    }
+
     /// Recover edges based on list of SlotMapId
     fn recover_edges(&self, edges: &[SlotMapId]) -> Vec<SimpleEdge> {
         edges.iter().filter_map(|v| self.slot_map.get_by_id(v)).collect()
@@ -145,12 +202,11 @@ impl IbfPeerSet {
 
 #[cfg(test)]
 mod test {
-    use crate::ibf_peer_set::{IbfPeerSet, SimpleEdge, SlotMap, SlotMapId};
+    use crate::ibf::IbfHasherKind;
+    use crate::ibf_peer_set::{IbfPeerSet, SimpleEdge, SlotMap, SlotMapId, PEERS_PER_LOCAL_SEED};
     use crate::ibf_set::IbfSet;
     use crate::routing::{Edge, ValidIBFLevel};
     use crate::test_utils::random_peer_id;
-    use near_primitives::network::PeerId;
-    use std::collections::HashMap;
 
     #[test]
     fn test_slot_map() {
@@ -200,11 +256,9 @@ mod test {
         let mut ibf_set = IbfSet::<SimpleEdge>::new(1111);
 
         let edge = Edge::make_fake_edge(peer_id.clone(), peer_id2.clone(), 111);
-        let mut edges_info: HashMap<(PeerId, PeerId), Edge> = Default::default();
-        edges_info.insert((peer_id.clone(), peer_id2.clone()), edge.clone());
 
         // Add Peer
-        ips.add_peer(peer_id.clone(), Some(1111), &mut edges_info);
+        ips.add_peer(peer_id.clone(), Some(1111), IbfHasherKind::Sip);
 
         // Remove Peer
         assert!(ips.get(&peer_id).is_some());
@@ -213,7 +267,7 @@ mod test {
         assert!(ips.get(&peer_id).is_none());
 
         // Add Peer again
-        ips.add_peer(peer_id.clone(), Some(1111), &mut edges_info);
+        ips.add_peer(peer_id.clone(), Some(1111), IbfHasherKind::Sip);
 
         // Add edge
         let e = SimpleEdge::new(peer_id.clone(), peer_id2.clone(), 111);
@@ -237,4 +291,60 @@ mod test {
         assert_eq!(4, ips.split_edges_for_peer(&peer_id, &hashes).1.len());
         assert_eq!(vec!(edge.to_simple_edge()), ips.split_edges_for_peer(&peer_id, &hashes).0);
     }
+
+    #[test]
+    fn test_shared_ibf_sets() {
+        let mut ips = IbfPeerSet::default();
+        let edges: Vec<_> = (0..10)
+            .map(|nonce| SimpleEdge::new(random_peer_id(), random_peer_id(), nonce))
+            .collect();
+        for edge in edges[..5].iter() {
+            ips.add_edge(edge);
+        }
+
+        // Peers we pick the seed for share an IbfSet, per hash function.
+        let peers: Vec<_> = (0..PEERS_PER_LOCAL_SEED + 1).map(|_| random_peer_id()).collect();
+        let seed = ips.add_peer(peers[0].clone(), None, IbfHasherKind::Sip);
+        for peer_id in peers[1..PEERS_PER_LOCAL_SEED].iter() {
+            assert_eq!(ips.add_peer(peer_id.clone(), None, IbfHasherKind::Sip), seed);
+        }
+        assert_eq!(ips.num_ibf_sets(), 1);
+        let xx_peer = random_peer_id();
+        ips.add_peer(xx_peer.clone(), None, IbfHasherKind::Xx);
+        assert_eq!(ips.num_ibf_sets(), 2);
+        // Until the seed was picked for enough peers.
+        let new_seed = ips.add_peer(peers[PEERS_PER_LOCAL_SEED].clone(), None, IbfHasherKind::Sip);
+        assert_ne!(new_seed, seed);
+        assert_eq!(ips.num_ibf_sets(), 3);
+
+        // Shared sets are updated incrementally, and match a set built from all the edges.
+        for edge in edges[5..].iter() {
+            ips.add_edge(edge);
+        }
+        ips.remove_edge(&edges[0]);
+        let late_peer = random_peer_id();
+        assert_eq!(ips.add_peer(late_peer.clone(), Some(seed), IbfHasherKind::Sip), seed);
+        assert_eq!(ips.num_ibf_sets(), 3);
+        let mut expected = IbfSet::<SimpleEdge>::with_hasher(seed, IbfHasherKind::Sip);
+        for edge in edges[1..].iter() {
+            expected.add_edge(edge, 0);
+        }
+        for peer_id in [&peers[0], &late_peer] {
+            let ibf_set = ips.get(peer_id).unwrap();
+            assert_eq!(
+                ibf_set.get_ibf_vec(ValidIBFLevel(10)),
+                expected.get_ibf_vec(ValidIBFLevel(10))
+            );
+        }
+
+        // Shared sets are dropped with their last peer.
+        ips.remove_peer(&xx_peer);
+        assert_eq!(ips.num_ibf_sets(), 2);
+        for peer_id in peers[..PEERS_PER_LOCAL_SEED].iter() {
+            ips.remove_peer(peer_id);
+        }
+        assert!(ips.get(&late_peer).is_some());
+        ips.remove_peer(&late_peer);
+        assert_eq!(ips.num_ibf_sets(), 1);
+    }
 }
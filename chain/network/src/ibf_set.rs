@@ -8,15 +8,17 @@ use tracing::{error, warn};
 
 use near_stable_hasher::StableHasher;
 
-use crate::ibf::{Ibf, IbfBox};
+use crate::ibf::{Ibf, IbfBox, IbfHasherKind};
 use crate::ibf_peer_set::SlotMapId;
 use crate::routing::{ValidIBFLevel, MAX_IBF_LEVEL, MIN_IBF_LEVEL};
+use crate::routing_profile::{hash_map_bytes, vec_bytes};
 
 /// Stores list of `Ibf` data structures of various sizes.
 /// In the current implementation we use sizes from 2^10+  2 ... 2^17 + 2.
 #[derive(Default)]
 pub struct IbfSet<T: Hash + Clone> {
     seed: u64,
+    hasher_kind: IbfHasherKind,
     ibf: Vec<Ibf>,
     h2e: HashMap<u64, SlotMapId>,
     hasher: StableHasher,
@@ -47,14 +49,37 @@ where
         self.ibf[(k.0 - MIN_IBF_LEVEL.0) as usize].clone()
     }
 
+    /// Approximate bytes used by the IBFs and the hashes of the edges.
+    pub fn memory_bytes(&self) -> usize {
+        vec_bytes(&self.ibf)
+            + self.ibf.iter().map(|ibf| vec_bytes(&ibf.data)).sum::<usize>()
+            + hash_map_bytes(&self.h2e)
+    }
+
+    /// Get hash function used by `Ibf`s of this IbfSet
+    pub fn get_hasher_kind(&self) -> IbfHasherKind {
+        self.hasher_kind
+    }
+
     pub fn new(seed: u64) -> Self {
+        Self::with_hasher(seed, IbfHasherKind::default())
+    }
+
+    pub fn with_hasher(seed: u64, hasher_kind: IbfHasherKind) -> Self {
         let mut ibf: Vec<Ibf> = Default::default();
         for i in MIN_IBF_LEVEL.0..=MAX_IBF_LEVEL.0 {
-            ibf.push(Ibf::new(1 << i, seed ^ i));
+            ibf.push(Ibf::with_hasher(1 << i, seed ^ i, hasher_kind));
         }
         let mut hasher = StableHasher::default();
         hasher.write_u64(seed);
-        Self { seed, ibf, h2e: Default::default(), hasher, pd: PhantomData::<T>::default() }
+        Self {
+            seed,
+            hasher_kind,
+            ibf,
+            h2e: Default::default(),
+            hasher,
+            pd: PhantomData::<T>::default(),
+        }
     }
 
     /// Get list of edges based on given list of hashes.
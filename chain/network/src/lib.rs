@@ -1,26 +1,67 @@
 pub use peer::{EPOCH_SYNC_PEER_TIMEOUT_MS, EPOCH_SYNC_REQUEST_TIMEOUT_MS};
 pub use peer_manager::PeerManagerActor;
 pub use routing_table_actor::{
-    RoutingTableActor, RoutingTableMessages, RoutingTableMessagesResponse,
+    RoutingTableActor, RoutingTableMessages, RoutingTableMessagesResponse, ROUTING_TABLE_PAGE_SIZE,
 };
 pub use types::{
     FullPeerInfo, NetworkAdapter, NetworkClientMessages, NetworkClientResponses, NetworkConfig,
     NetworkRecipient, NetworkRequests, NetworkResponses, PeerInfo,
 };
 
+pub mod anchors;
+pub mod audit_log;
+mod bandwidth;
 mod cache;
+pub mod capabilities;
+#[cfg(feature = "test_features")]
+pub mod chaos;
 mod codec;
+mod compression;
+pub mod compact_peer_id;
+pub mod debug;
+pub mod delta_sync;
+pub mod denylist;
+pub mod diagnostics;
+mod dns_seed;
+pub mod edge_gossip;
+mod edge_rate_limiter;
+pub mod edge_stats;
 mod edge_verifier;
-mod ibf;
+pub mod failpoints;
+mod gossip_dedup;
+mod graph_worker;
+pub mod gossip_stats;
+pub mod ibf;
 pub mod ibf_peer_set;
 pub mod ibf_set;
 pub mod metrics;
+mod nat;
 mod peer;
+mod peer_cache;
 mod peer_manager;
+pub mod peer_regions;
+mod peer_role;
+pub mod peer_score;
 pub mod peer_store;
+mod proxy;
 mod rate_counter;
+pub mod reachability_probe;
+pub mod routed_retrier;
 pub mod routing;
+pub mod routing_dump;
+pub mod routing_profile;
+pub mod routing_replay;
+mod routing_loops;
+mod routing_replica;
+pub mod routing_simulator;
 mod routing_table_actor;
+mod send_queue;
+pub mod simulation;
+mod sync_lru;
 pub mod test_utils;
+mod tier1;
+mod tls;
+pub mod time;
+pub mod topology_dataset;
 pub mod types;
 pub mod utils;
@@ -4,8 +4,10 @@ use lazy_static::lazy_static;
 use strum::VariantNames;
 
 use near_metrics::{
-    inc_counter_by_opt, inc_counter_opt, try_create_histogram, try_create_int_counter,
-    try_create_int_gauge, Histogram, IntCounter, IntGauge,
+    inc_counter_by_opt, inc_counter_opt, try_create_gauge, try_create_histogram,
+    try_create_histogram_vec, try_create_histogram_with_buckets, try_create_int_counter,
+    try_create_int_counter_vec, try_create_int_gauge, try_create_int_gauge_vec, Gauge, Histogram,
+    HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
 };
 
 use crate::types::{PeerMessage, RoutedMessageBody};
@@ -13,6 +15,11 @@ use crate::types::{PeerMessage, RoutedMessageBody};
 lazy_static! {
     pub static ref PEER_CONNECTIONS_TOTAL: near_metrics::Result<IntGauge> =
         try_create_int_gauge("near_peer_connections_total", "Number of connected peers");
+    pub static ref PEER_CONNECTION_MIGRATIONS: near_metrics::Result<IntCounter> =
+        try_create_int_counter(
+            "near_peer_connection_migrations_total",
+            "Number of connections replaced by a connection of the same peer from a new address"
+        );
     pub static ref PEER_DATA_RECEIVED_BYTES: near_metrics::Result<IntCounter> =
         try_create_int_counter("near_peer_data_received_bytes", "Total data received from peers");
     pub static ref PEER_MESSAGE_RECEIVED_TOTAL: near_metrics::Result<IntCounter> =
@@ -49,6 +56,38 @@ lazy_static! {
             "near_routing_table_recalculation_seconds",
             "Time spent recalculating routing table"
         );
+    pub static ref ROUTING_GRAPH_WORKER_HISTOGRAM: near_metrics::Result<Histogram> =
+        try_create_histogram(
+            "near_routing_graph_worker_seconds",
+            "Time spent computing routes of a part of the routing graph on a worker thread"
+        );
+    pub static ref ROUTING_GRAPH_PARTITIONED_RECOMPUTATIONS: near_metrics::Result<IntCounter> =
+        try_create_int_counter(
+            "near_routing_graph_partitioned_recomputations_total",
+            "Number of times routes of disjoint parts of the routing graph were computed in parallel"
+        );
+    pub static ref ROUTING_SHADOW_DECISIONS: near_metrics::Result<IntCounter> =
+        try_create_int_counter(
+            "near_routing_shadow_decisions_total",
+            "Number of next hops chosen by the shadow routing strategy"
+        );
+    pub static ref ROUTING_SHADOW_DIVERGENCES: near_metrics::Result<IntCounter> =
+        try_create_int_counter(
+            "near_routing_shadow_divergences_total",
+            "Number of next hops chosen by the shadow routing strategy which differ from the ones used"
+        );
+
+    pub static ref ROUTING_SHARD_AWARE_FALLBACKS: near_metrics::Result<IntCounter> =
+        try_create_int_counter(
+            "near_routing_shard_aware_fallbacks_total",
+            "Number of chunk requests routed through next hops which don't track the shard, because none of the next hops does"
+        );
+
+    pub static ref ROUTED_THROUGH_PROXY_RELAY: near_metrics::Result<IntCounter> =
+        try_create_int_counter(
+            "near_routed_through_proxy_relay_total",
+            "Number of messages routed to a relay of their target, because there is no known route to the target itself"
+        );
 
     pub static ref EDGE_UPDATES: near_metrics::Result<IntCounter> =
         try_create_int_counter(
@@ -65,17 +104,294 @@ lazy_static! {
             "near_peer_reachable",
             "Total peers such that there is a path potentially through other peers"
         );
+    pub static ref TIER1_CONNECTIONS: near_metrics::Result<IntGauge> =
+        try_create_int_gauge(
+            "near_tier1_connections",
+            "Number of direct connections to other validators of the epoch"
+        );
+    pub static ref ANCHOR_PEERS_DISCONNECTED: near_metrics::Result<IntGauge> =
+        try_create_int_gauge(
+            "near_anchor_peers_disconnected",
+            "Number of anchor peers which aren't connected"
+        );
+    pub static ref ANCHOR_PEERS_UNREACHABLE: near_metrics::Result<IntGauge> =
+        try_create_int_gauge(
+            "near_anchor_peers_unreachable",
+            "Number of anchor peers disconnected for longer than anchor_unreachable_timeout"
+        );
+    pub static ref ROUTING_GRAPH_NODES: near_metrics::Result<IntGauge> =
+        try_create_int_gauge(
+            "near_routing_graph_nodes",
+            "Number of peers in the routing graph, reachable or not"
+        );
+    pub static ref ROUTING_GRAPH_DIAMETER: near_metrics::Result<IntGauge> =
+        try_create_int_gauge(
+            "near_routing_graph_diameter",
+            "Largest number of hops on the shortest paths from this node to reachable peers"
+        );
+    pub static ref ROUTING_GRAPH_AVERAGE_HOPS: near_metrics::Result<Gauge> =
+        try_create_gauge(
+            "near_routing_graph_average_hops",
+            "Average number of hops on the shortest paths from this node to reachable peers"
+        );
+    pub static ref ROUTING_SINGLE_NEXT_HOP_FRACTION: near_metrics::Result<Gauge> =
+        try_create_gauge(
+            "near_routing_single_next_hop_fraction",
+            "Fraction of reachable peers which are only reachable through a single next hop"
+        );
+    pub static ref ROUTING_NEXT_HOPS: near_metrics::Result<Histogram> =
+        try_create_histogram_with_buckets(
+            "near_routing_next_hops",
+            "Number of next hops on shortest paths to each reachable peer, observed on every routing table update",
+            vec![1.0, 2.0, 3.0, 4.0, 6.0, 8.0, 12.0, 16.0, 24.0, 32.0]
+        );
+    pub static ref ROUTING_SINGLETON_COMPONENTS_SAVED: near_metrics::Result<IntCounter> =
+        try_create_int_counter(
+            "near_routing_singleton_components_saved_total",
+            "Number of components of a single unreachable peer saved to disk"
+        );
+    pub static ref ACCOUNT_ANNOUNCEMENT_VERIFICATIONS: near_metrics::Result<IntCounterVec> =
+        try_create_int_counter_vec(
+            "near_account_announcement_verifications_total",
+            "Pings sent to verify announcements which traveled too many hops, and the ones answered",
+            &["result"]
+        );
+    pub static ref ROUTING_SAVED_COMPONENTS_REMOVED: near_metrics::Result<IntCounter> =
+        try_create_int_counter(
+            "near_routing_saved_components_removed_total",
+            "Number of components of unreachable peers removed from disk to bound its size"
+        );
+    pub static ref ROUTING_GRAPH_NODES_EVICTED: near_metrics::Result<IntCounter> =
+        try_create_int_counter(
+            "near_routing_graph_nodes_evicted_total",
+            "Number of unreachable peers removed from the routing graph to stay within its budget"
+        );
+    pub static ref PEER_COMPONENT_CONFLICTS_REPAIRED: near_metrics::Result<IntCounter> =
+        try_create_int_counter(
+            "near_peer_component_conflicts_repaired_total",
+            "Peers found both reachable in memory and saved in a component on disk"
+        );
+    pub static ref EDGE_VERIFIER_BATCHES: near_metrics::Result<IntCounter> =
+        try_create_int_counter(
+            "near_edge_verifier_batches_total",
+            "Number of batches of edges verified"
+        );
+    pub static ref EDGE_VERIFIER_BATCH_FALLBACKS: near_metrics::Result<IntCounter> =
+        try_create_int_counter(
+            "near_edge_verifier_batch_fallbacks_total",
+            "Number of batches of edges which failed batch verification and were verified one by one"
+        );
+    pub static ref EDGE_VERIFIER_EDGES: near_metrics::Result<IntCounter> =
+        try_create_int_counter(
+            "near_edge_verifier_edges_total",
+            "Number of edges which signatures were verified"
+        );
+    pub static ref EDGE_VERIFIER_BATCH_HISTOGRAM: near_metrics::Result<Histogram> =
+        try_create_histogram(
+            "near_edge_verifier_batch_seconds",
+            "Time spent verifying a batch of edges"
+        );
+    pub static ref HEAVY_SYNCS_DEFERRED: near_metrics::Result<IntCounter> =
+        try_create_int_counter(
+            "near_heavy_syncs_deferred_total",
+            "Number of times sending a full routing table to a peer was postponed"
+        );
+    pub static ref ROUTING_DELTA_SYNCS: near_metrics::Result<IntCounterVec> =
+        try_create_int_counter_vec(
+            "near_routing_delta_syncs_total",
+            "Number of routing table delta requests answered, by result (delta or stale)",
+            &["result"]
+        );
+    pub static ref EDGE_GOSSIP_EDGES: near_metrics::Result<IntCounterVec> =
+        try_create_int_counter_vec(
+            "near_edge_gossip_edges_total",
+            "Number of edges pushed, pulled by peers, advertised in digests, or not pushed because over budget",
+            &["kind"]
+        );
+    pub static ref PEER_MISBEHAVIOR_PENALTIES: near_metrics::Result<IntCounter> =
+        try_create_int_counter(
+            "near_peer_misbehavior_penalties_total",
+            "Number of times peers were penalized for misbehaving"
+        );
+    pub static ref PEER_MESSAGE_NOT_ALLOWED: near_metrics::Result<IntCounter> =
+        try_create_int_counter(
+            "near_peer_message_not_allowed_total",
+            "Number of messages received from peers which are not allowed for their role"
+        );
+    pub static ref ROUTED_REQUEST_ATTEMPTS: near_metrics::Result<IntCounterVec> =
+        try_create_int_counter_vec(
+            "near_routed_request_attempts_total",
+            "Number of attempts to send requests retried by RoutedRequestRetrier, by kind",
+            &["kind"]
+        );
+    pub static ref ROUTED_REQUEST_SUCCEEDED: near_metrics::Result<IntCounterVec> =
+        try_create_int_counter_vec(
+            "near_routed_request_succeeded_total",
+            "Number of requests retried by RoutedRequestRetrier which got a response, by kind",
+            &["kind"]
+        );
+    pub static ref ROUTED_REQUEST_FAILED: near_metrics::Result<IntCounterVec> =
+        try_create_int_counter_vec(
+            "near_routed_request_failed_total",
+            "Number of requests retried by RoutedRequestRetrier which were given up, by kind",
+            &["kind"]
+        );
+    pub static ref EDGE_UPDATES_RATE_LIMITED: near_metrics::Result<IntCounterVec> =
+        try_create_int_counter_vec(
+            "near_edge_updates_rate_limited_total",
+            "Number of edge updates dropped because the peer exceeded its rate limit, by peer",
+            &["peer_id"]
+        );
+    pub static ref PEER_MESSAGES_QUEUED: near_metrics::Result<IntCounterVec> =
+        try_create_int_counter_vec(
+            "near_peer_messages_queued_total",
+            "Number of messages held back because the write buffer of the peer was congested, by priority",
+            &["priority"]
+        );
+    pub static ref PEER_MESSAGES_QUEUE_DROPPED: near_metrics::Result<IntCounterVec> =
+        try_create_int_counter_vec(
+            "near_peer_messages_queue_dropped_total",
+            "Number of messages dropped because the send queue of their priority was full, by priority",
+            &["priority"]
+        );
+    pub static ref PEER_SEND_QUEUE_SATURATED_DISCONNECTS: near_metrics::Result<IntCounter> =
+        try_create_int_counter(
+            "near_peer_send_queue_saturated_disconnects_total",
+            "Number of peers disconnected because their send queue stayed saturated"
+        );
+    pub static ref PEER_MESSAGES_DESTINATION_BUDGET_EXCEEDED: near_metrics::Result<IntCounterVec> =
+        try_create_int_counter_vec(
+            "near_peer_messages_destination_budget_exceeded_total",
+            "Number of messages dropped because too many bytes were queued for their destination, by priority",
+            &["priority"]
+        );
+    pub static ref PEER_MESSAGES_STARVED: near_metrics::Result<IntCounterVec> =
+        try_create_int_counter_vec(
+            "near_peer_messages_starved_total",
+            "Number of messages held back in the send queue for more than a second, by priority",
+            &["priority"]
+        );
+    pub static ref REACHABILITY_PROBE_DIVERGENT_TARGETS: near_metrics::Result<IntCounterVec> =
+        try_create_int_counter_vec(
+            "near_reachability_probe_divergent_targets",
+            "Targets of reachability probes only one of us and the probed peer can route to, by who can't",
+            &["direction"]
+        );
+    pub static ref PEER_MESSAGE_COMPRESSION_BYTES: near_metrics::Result<IntCounterVec> =
+        try_create_int_counter_vec(
+            "near_peer_message_compression_bytes_total",
+            "Bytes of the messages compressed for peers before and after compression, by algorithm and stage",
+            &["algorithm", "stage"]
+        );
+    pub static ref PEER_MESSAGE_COMPRESSION_SECONDS: near_metrics::Result<HistogramVec> =
+        try_create_histogram_vec(
+            "near_peer_message_compression_seconds",
+            "Time spent compressing and decompressing messages, by algorithm and operation",
+            &["algorithm", "operation"],
+            Some(vec![0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5]),
+        );
+    pub static ref PEER_BANDWIDTH_THROTTLED: near_metrics::Result<IntCounterVec> =
+        try_create_int_counter_vec(
+            "near_peer_bandwidth_throttled_total",
+            "Number of times reading from or writing to a peer was delayed by a bandwidth limit, by direction",
+            &["direction"]
+        );
+    pub static ref GOSSIP_DEDUP_CHECKED: near_metrics::Result<IntCounterVec> =
+        try_create_int_counter_vec(
+            "near_gossip_dedup_checked_total",
+            "Number of broadcast messages checked against the cache of recently received messages, by type",
+            &["type"]
+        );
+    pub static ref GOSSIP_DEDUP_DROPPED: near_metrics::Result<IntCounterVec> =
+        try_create_int_counter_vec(
+            "near_gossip_dedup_dropped_total",
+            "Number of broadcast messages dropped before deserialization because they were received recently, by type. The hit rate is the ratio to near_gossip_dedup_checked_total",
+            &["type"]
+        );
+    pub static ref DENYLIST_REJECTIONS: near_metrics::Result<IntCounterVec> =
+        try_create_int_counter_vec(
+            "near_denylist_rejections_total",
+            "Number of edges and account announcements rejected because they reference a denylisted or banned peer, by rule",
+            &["rule"]
+        );
+    pub static ref EDGE_LIFETIME_PROCESSED: near_metrics::Result<IntGauge> = try_create_int_gauge(
+        "near_edge_lifetime_processed",
+        "Number of edges processed by the routing table since the node's database was created"
+    );
+    pub static ref EDGE_LIFETIME_DUPLICATES: near_metrics::Result<IntGauge> = try_create_int_gauge(
+        "near_edge_lifetime_duplicates",
+        "Number of processed edges which were already known, since the node's database was created"
+    );
+    pub static ref EDGE_LIFETIME_VERIFICATION_FAILURES: near_metrics::Result<IntGauge> =
+        try_create_int_gauge(
+            "near_edge_lifetime_verification_failures",
+            "Number of lists of edges which failed verification, since the node's database was created"
+        );
+    pub static ref EDGE_LIFETIME_DUPLICATE_RATIO: near_metrics::Result<Gauge> = try_create_gauge(
+        "near_edge_lifetime_duplicate_ratio",
+        "Fraction of the edges processed since the node's database was created which were duplicates"
+    );
+    pub static ref REGION_REACHABLE_PEERS: near_metrics::Result<IntGaugeVec> =
+        try_create_int_gauge_vec(
+            "near_region_reachable_peers",
+            "Number of reachable peers with a known address, by region of the address",
+            &["region"]
+        );
+    pub static ref REGION_PEER_LATENCY_MS: near_metrics::Result<IntGaugeVec> =
+        try_create_int_gauge_vec(
+            "near_region_peer_latency_ms",
+            "Quantiles of the round trip time to active peers in milliseconds, by region of the address",
+            &["region", "quantile"]
+        );
+    pub static ref ACCOUNT_ANNOUNCEMENTS_EXPIRED: near_metrics::Result<IntCounter> =
+        try_create_int_counter(
+            "near_account_announcements_expired_total",
+            "Number of account announcements removed because their epoch is over"
+        );
     pub static ref DROP_MESSAGE_UNKNOWN_ACCOUNT: near_metrics::Result<IntCounter> =
         try_create_int_counter(
             "near_drop_message_unknown_account",
             "Total messages dropped because target account is not known"
         );
+    pub static ref ROUTE_NOT_FOUND_DROPPED: near_metrics::Result<IntCounterVec> =
+        try_create_int_counter_vec(
+            "near_route_not_found_dropped_total",
+            "Number of messages dropped because they couldn't be routed, by reason",
+            &["reason"]
+        );
+    pub static ref ROUTED_MESSAGE_TTL_EXPIRED: near_metrics::Result<IntCounterVec> =
+        try_create_int_counter_vec(
+            "near_routed_message_ttl_expired_total",
+            "Number of routed messages dropped because their TTL reached 0, by type",
+            &["type"]
+        );
+    pub static ref ROUTING_LOOPS_DETECTED: near_metrics::Result<IntCounter> =
+        try_create_int_counter(
+            "near_routing_loops_detected_total",
+            "Number of routed messages which came back to us through the next hop we sent them to"
+        );
+    pub static ref GOSSIP_PEERS_SKIPPED: near_metrics::Result<IntCounter> =
+        try_create_int_counter(
+            "near_gossip_peers_skipped_total",
+            "Number of active peers a gossiped message wasn't sent to, because of the gossip fanout"
+        );
     pub static ref RECEIVED_INFO_ABOUT_ITSELF: near_metrics::Result<IntCounter> = try_create_int_counter("received_info_about_itself", "Number of times a peer tried to connect to itself");
     pub static ref DROPPED_MESSAGES_COUNT: near_metrics::Result<IntCounter> =
         near_metrics::try_create_int_counter(
             "near_dropped_messages_count",
             "Total count of messages which were dropped, because write buffer was full"
         );
+    pub static ref ROUTING_DIAGNOSTICS_ENABLED: near_metrics::Result<IntGauge> =
+        try_create_int_gauge(
+            "near_routing_diagnostics_enabled",
+            "Whether verbose routing diagnostics are currently logged"
+        );
+    pub static ref ROUTE_BACK_EVICTED: near_metrics::Result<IntCounterVec> =
+        try_create_int_counter_vec(
+            "near_route_back_evicted_total",
+            "Number of route back entries evicted before their response, by peer the message came from and reason",
+            &["peer_id", "reason"]
+        );
 }
 
 #[derive(Clone)]
@@ -105,6 +421,12 @@ impl NetworkMetrics {
                 try_create_int_counter(counter_name.as_ref(), counter_name.as_ref()).ok(),
             );
 
+            let counter_name = NetworkMetrics::peer_message_bytes_tx(name.as_ref());
+            peer_messages.insert(
+                counter_name.clone(),
+                try_create_int_counter(counter_name.as_ref(), counter_name.as_ref()).ok(),
+            );
+
             let counter_name = NetworkMetrics::peer_message_dropped(name.as_ref());
             peer_messages.insert(
                 counter_name.clone(),
@@ -123,6 +445,10 @@ impl NetworkMetrics {
         format!("near_{}_bytes", message_name.to_lowercase())
     }
 
+    pub fn peer_message_bytes_tx(message_name: &str) -> String {
+        format!("near_{}_sent_bytes", message_name.to_lowercase())
+    }
+
     pub fn peer_message_dropped(message_name: &str) -> String {
         format!("near_{}_dropped", message_name.to_lowercase())
     }
@@ -0,0 +1,354 @@
+//! Port mapping on the NAT gateway with NAT-PMP (RFC 6886) or UPnP IGD, so nodes behind a NAT
+//! can accept inbound connections. The external address of the mapping is advertised to peers
+//! instead of the listening address.
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tracing::debug;
+
+/// Lifetime requested for port mappings. They are renewed halfway through.
+const PORT_MAPPING_LIFETIME: Duration = Duration::from_secs(60 * 60);
+const NAT_PMP_PORT: u16 = 5351;
+const NAT_PMP_OP_EXTERNAL_ADDR: u8 = 0;
+const NAT_PMP_OP_MAP_TCP: u8 = 2;
+/// Responses have the opcode of the request plus 128.
+const NAT_PMP_OP_RESPONSE: u8 = 128;
+/// Number of NAT-PMP requests sent before giving up, the timeout doubles every time.
+const NAT_PMP_ATTEMPTS: u32 = 4;
+const NAT_PMP_INITIAL_TIMEOUT: Duration = Duration::from_millis(250);
+const SSDP_ADDR: &str = "239.255.255.250:1900";
+const SSDP_TIMEOUT: Duration = Duration::from_secs(3);
+const UPNP_HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+const UPNP_SERVICE_TYPES: [&str; 2] = [
+    "urn:schemas-upnp-org:service:WANIPConnection:1",
+    "urn:schemas-upnp-org:service:WANPPPConnection:1",
+];
+/// Timeout of the connection to our own external address checking that it is reachable.
+const REACHABILITY_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum PortMappingProtocol {
+    NatPmp,
+    Upnp,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct PortMapping {
+    pub protocol: PortMappingProtocol,
+    pub external_addr: SocketAddr,
+    /// Time after which the gateway removes the mapping, unless it is renewed.
+    pub lifetime: Duration,
+}
+
+fn nat_error(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, msg)
+}
+
+/// Map `internal_port` on the NAT gateway to an external port, with NAT-PMP if the gateway
+/// supports it, with UPnP otherwise.
+pub(crate) async fn map_port(internal_port: u16) -> io::Result<PortMapping> {
+    match nat_pmp_map_port(internal_port).await {
+        Ok(mapping) => return Ok(mapping),
+        Err(err) => debug!(target: "network", "NAT-PMP port mapping failed: {}", err),
+    }
+    upnp_map_port(internal_port).await
+}
+
+/// Whether `addr` accepts connections from this host. Gateways which don't support hairpinning
+/// refuse them, even if the mapping works for connections from outside.
+pub(crate) async fn is_reachable(addr: SocketAddr) -> bool {
+    matches!(
+        tokio::time::timeout(REACHABILITY_CHECK_TIMEOUT, TcpStream::connect(addr)).await,
+        Ok(Ok(_))
+    )
+}
+
+async fn nat_pmp_map_port(internal_port: u16) -> io::Result<PortMapping> {
+    let gateway = default_gateway()?;
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    socket.connect((gateway, NAT_PMP_PORT)).await?;
+
+    let response = nat_pmp_request(&socket, &[0, NAT_PMP_OP_EXTERNAL_ADDR], 12).await?;
+    check_nat_pmp_response(&response, NAT_PMP_OP_EXTERNAL_ADDR)?;
+    let external_ip = Ipv4Addr::new(response[8], response[9], response[10], response[11]);
+
+    let mut request = vec![0, NAT_PMP_OP_MAP_TCP, 0, 0];
+    request.extend_from_slice(&internal_port.to_be_bytes());
+    // Suggest the same external port.
+    request.extend_from_slice(&internal_port.to_be_bytes());
+    request.extend_from_slice(&(PORT_MAPPING_LIFETIME.as_secs() as u32).to_be_bytes());
+    let response = nat_pmp_request(&socket, &request, 16).await?;
+    let (external_port, lifetime) = parse_nat_pmp_mapping(&response, internal_port)?;
+    Ok(PortMapping {
+        protocol: PortMappingProtocol::NatPmp,
+        external_addr: SocketAddr::new(external_ip.into(), external_port),
+        lifetime,
+    })
+}
+
+async fn nat_pmp_request(
+    socket: &UdpSocket,
+    request: &[u8],
+    response_len: usize,
+) -> io::Result<Vec<u8>> {
+    let mut timeout = NAT_PMP_INITIAL_TIMEOUT;
+    for _ in 0..NAT_PMP_ATTEMPTS {
+        socket.send(request).await?;
+        let mut response = vec![0u8; response_len];
+        match tokio::time::timeout(timeout, socket.recv(&mut response)).await {
+            Ok(Ok(len)) if len == response_len => return Ok(response),
+            Ok(Ok(len)) => {
+                return Err(nat_error(format!("NAT-PMP response of {} bytes", len)));
+            }
+            Ok(Err(err)) => return Err(err),
+            Err(_) => timeout *= 2,
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::TimedOut, "no NAT-PMP response"))
+}
+
+fn check_nat_pmp_response(response: &[u8], opcode: u8) -> io::Result<()> {
+    if response[0] != 0 || response[1] != NAT_PMP_OP_RESPONSE + opcode {
+        return Err(nat_error(format!("unexpected NAT-PMP response {:?}", &response[..2])));
+    }
+    match u16::from_be_bytes([response[2], response[3]]) {
+        0 => Ok(()),
+        result => Err(nat_error(format!("NAT-PMP gateway returned result code {}", result))),
+    }
+}
+
+/// Parses the response to a mapping request: external port and lifetime of the mapping.
+fn parse_nat_pmp_mapping(response: &[u8], internal_port: u16) -> io::Result<(u16, Duration)> {
+    check_nat_pmp_response(response, NAT_PMP_OP_MAP_TCP)?;
+    let mapped_port = u16::from_be_bytes([response[8], response[9]]);
+    if mapped_port != internal_port {
+        return Err(nat_error(format!("NAT-PMP gateway mapped port {}", mapped_port)));
+    }
+    let external_port = u16::from_be_bytes([response[10], response[11]]);
+    let lifetime = u32::from_be_bytes([response[12], response[13], response[14], response[15]]);
+    Ok((external_port, Duration::from_secs(lifetime as u64)))
+}
+
+/// Default IPv4 gateway, read from the routing table of the kernel. Only supported on Linux.
+fn default_gateway() -> io::Result<Ipv4Addr> {
+    parse_default_gateway(&std::fs::read_to_string("/proc/net/route")?)
+}
+
+fn parse_default_gateway(routes: &str) -> io::Result<Ipv4Addr> {
+    // Columns are interface, destination, gateway, ... with addresses in little endian hex.
+    for line in routes.lines().skip(1) {
+        let columns: Vec<&str> = line.split_whitespace().collect();
+        if columns.len() < 3 || columns[1] != "00000000" {
+            continue;
+        }
+        match u32::from_str_radix(columns[2], 16) {
+            Ok(gateway) if gateway != 0 => return Ok(Ipv4Addr::from(gateway.to_le_bytes())),
+            _ => {}
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::NotFound, "no default gateway"))
+}
+
+async fn upnp_map_port(internal_port: u16) -> io::Result<PortMapping> {
+    let location = ssdp_discover().await?;
+    let (host, path) = parse_http_url(&location)?;
+    let (internal_ip, description) =
+        http_request(host, &format!("GET {} HTTP/1.0\r\nHost: {}\r\n\r\n", path, host)).await?;
+    let (service_type, control_url) = find_wan_service(&description)
+        .ok_or_else(|| nat_error(format!("no WAN connection service in {}", location)))?;
+    let control_path = match control_url.strip_prefix("http://") {
+        Some(_) => parse_http_url(&control_url)?.1.to_string(),
+        None => control_url,
+    };
+
+    let lifetime = PORT_MAPPING_LIFETIME.as_secs().to_string();
+    soap_request(
+        host,
+        &control_path,
+        service_type,
+        "AddPortMapping",
+        &[
+            ("NewRemoteHost", ""),
+            ("NewExternalPort", &internal_port.to_string()),
+            ("NewProtocol", "TCP"),
+            ("NewInternalPort", &internal_port.to_string()),
+            ("NewInternalClient", &internal_ip.to_string()),
+            ("NewEnabled", "1"),
+            ("NewPortMappingDescription", "near"),
+            ("NewLeaseDuration", &lifetime),
+        ],
+    )
+    .await?;
+    let response =
+        soap_request(host, &control_path, service_type, "GetExternalIPAddress", &[]).await?;
+    let external_ip = xml_value(&response, "NewExternalIPAddress")
+        .and_then(|ip| ip.parse::<IpAddr>().ok())
+        .ok_or_else(|| nat_error("UPnP gateway didn't return its external address".into()))?;
+    Ok(PortMapping {
+        protocol: PortMappingProtocol::Upnp,
+        external_addr: SocketAddr::new(external_ip, internal_port),
+        lifetime: PORT_MAPPING_LIFETIME,
+    })
+}
+
+/// Location of the description of the first internet gateway device answering.
+async fn ssdp_discover() -> io::Result<String> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    let request = "M-SEARCH * HTTP/1.1\r\n\
+                   HOST: 239.255.255.250:1900\r\n\
+                   ST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\
+                   MAN: \"ssdp:discover\"\r\n\
+                   MX: 2\r\n\r\n";
+    socket.send_to(request.as_bytes(), SSDP_ADDR).await?;
+    let mut response = vec![0u8; 2048];
+    let (len, _) = tokio::time::timeout(SSDP_TIMEOUT, socket.recv_from(&mut response))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no UPnP gateway found"))??;
+    header_value(&String::from_utf8_lossy(&response[..len]), "location")
+        .map(|location| location.to_string())
+        .ok_or_else(|| nat_error("UPnP gateway didn't send its location".into()))
+}
+
+fn header_value<'a>(response: &'a str, name: &str) -> Option<&'a str> {
+    response.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim().eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+/// Splits `http://host:port/path` into `host:port` and `/path`.
+fn parse_http_url(url: &str) -> io::Result<(&str, &str)> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| nat_error(format!("unsupported UPnP URL {}", url)))?;
+    Ok(match rest.find('/') {
+        Some(index) => rest.split_at(index),
+        None => (rest, "/"),
+    })
+}
+
+/// Send `request` to `host` over HTTP/1.0, so the response isn't chunked. Returns the address
+/// of this host the gateway was connected from and the body of the response.
+async fn http_request(host: &str, request: &str) -> io::Result<(IpAddr, String)> {
+    let exchange = async {
+        let mut stream = if host.contains(':') {
+            TcpStream::connect(host).await?
+        } else {
+            TcpStream::connect((host, 80)).await?
+        };
+        let local_ip = stream.local_addr()?.ip();
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = vec![];
+        stream.read_to_end(&mut response).await?;
+        Ok::<_, io::Error>((local_ip, String::from_utf8_lossy(&response).into_owned()))
+    };
+    let (local_ip, response) = tokio::time::timeout(UPNP_HTTP_TIMEOUT, exchange)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "UPnP gateway timed out"))??;
+    let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+    let status_line = head.lines().next().unwrap_or_default();
+    match status_line.split_whitespace().nth(1) {
+        Some("200") => Ok((local_ip, body.to_string())),
+        _ => Err(nat_error(format!("UPnP gateway responded {}", status_line))),
+    }
+}
+
+async fn soap_request(
+    host: &str,
+    path: &str,
+    service_type: &str,
+    action: &str,
+    args: &[(&str, &str)],
+) -> io::Result<String> {
+    let args: String =
+        args.iter().map(|(name, value)| format!("<{0}>{1}</{0}>", name, value)).collect();
+    let body = format!(
+        "<?xml version=\"1.0\"?>\
+         <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+         s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+         <s:Body><u:{0} xmlns:u=\"{1}\">{2}</u:{0}></s:Body></s:Envelope>",
+        action, service_type, args
+    );
+    let request = format!(
+        "POST {} HTTP/1.0\r\nHost: {}\r\nContent-Type: text/xml; charset=\"utf-8\"\r\n\
+         Content-Length: {}\r\nSOAPAction: \"{}#{}\"\r\n\r\n{}",
+        path,
+        host,
+        body.len(),
+        service_type,
+        action,
+        body
+    );
+    Ok(http_request(host, &request).await?.1)
+}
+
+/// Type and control URL of the WAN connection service in a device description.
+fn find_wan_service(description: &str) -> Option<(&'static str, String)> {
+    UPNP_SERVICE_TYPES.iter().find_map(|service_type| {
+        let start = description.find(&format!("<serviceType>{}</serviceType>", service_type))?;
+        let service = &description[start..];
+        let service = &service[..service.find("</service>").unwrap_or(service.len())];
+        Some((*service_type, xml_value(service, "controlURL")?.to_string()))
+    })
+}
+
+fn xml_value<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{}>", tag))? + tag.len() + 2;
+    let end = start + xml[start..].find(&format!("</{}>", tag))?;
+    Some(xml[start..end].trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    use super::{find_wan_service, header_value, parse_default_gateway, parse_nat_pmp_mapping};
+
+    #[test]
+    fn default_gateway() {
+        let routes = "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\n\
+                      eth0\t0001A8C0\t00000000\t0001\t0\t0\t0\t00FFFFFF\n\
+                      eth0\t00000000\t0101A8C0\t0003\t0\t0\t0\t00000000\n";
+        assert_eq!(parse_default_gateway(routes).unwrap(), Ipv4Addr::new(192, 168, 1, 1));
+        assert!(parse_default_gateway("Iface\tDestination\tGateway\n").is_err());
+    }
+
+    #[test]
+    fn nat_pmp_mapping() {
+        let mut response = vec![0, 130, 0, 0, 0, 0, 0, 7];
+        response.extend_from_slice(&24567u16.to_be_bytes());
+        response.extend_from_slice(&34567u16.to_be_bytes());
+        response.extend_from_slice(&3600u32.to_be_bytes());
+        assert_eq!(
+            parse_nat_pmp_mapping(&response, 24567).unwrap(),
+            (34567, Duration::from_secs(3600))
+        );
+        assert!(parse_nat_pmp_mapping(&response, 24568).is_err());
+        // Result code 2: not authorized.
+        response[3] = 2;
+        assert!(parse_nat_pmp_mapping(&response, 24567).is_err());
+    }
+
+    #[test]
+    fn upnp_description() {
+        let ssdp = "HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age=120\r\n\
+                    Location: http://192.168.1.1:5000/rootDesc.xml\r\n\r\n";
+        assert_eq!(header_value(ssdp, "location"), Some("http://192.168.1.1:5000/rootDesc.xml"));
+
+        let description = "<root><device><serviceList>\
+            <service><serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType>\
+            <controlURL>/ctl/L3F</controlURL></service>\
+            <service><serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>\
+            <controlURL> /ctl/IPConn </controlURL></service>\
+            </serviceList></device></root>";
+        assert_eq!(
+            find_wan_service(description),
+            Some(("urn:schemas-upnp-org:service:WANIPConnection:1", "/ctl/IPConn".to_string()))
+        );
+        assert_eq!(find_wan_service("<root></root>"), None);
+    }
+}
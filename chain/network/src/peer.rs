@@ -3,7 +3,7 @@ use std::io;
 use std::net::SocketAddr;
 use std::sync::{
     atomic::{AtomicUsize, Ordering},
-    Arc,
+    Arc, RwLock,
 };
 use std::time::{Duration, Instant};
 
@@ -18,11 +18,12 @@ use tracing::{debug, error, info, trace, warn};
 use delay_detector::DelayDetector;
 use near_crypto::Signature;
 use near_metrics;
-use near_network_primitives::types::PeerIdOrHash;
+use near_network_primitives::types::{CompressionConfig, PeerIdOrHash, SendQueueConfig};
 use near_performance_metrics;
 use near_performance_metrics::framed_write::{FramedWrite, WriteHandler};
 use near_performance_metrics_macros::perf;
 use near_primitives::block::GenesisId;
+use near_primitives::checked_feature;
 use near_primitives::hash::CryptoHash;
 use near_primitives::logging;
 use near_primitives::network::PeerId;
@@ -34,17 +35,29 @@ use near_primitives::version::{
 };
 use near_rust_allocator_proxy::allocator::get_tid;
 
+use crate::bandwidth::Throttle;
+use crate::capabilities::PeerCapabilities;
+#[cfg(feature = "test_features")]
+use crate::chaos::{FaultInjector, PeerFaults};
 use crate::codec::{self, bytes_to_peer_message, peer_message_to_bytes, Codec};
+use crate::compact_peer_id::{PeerIdDecoder, PeerIdEncoder};
+use crate::compression;
+use crate::gossip_dedup::SeenMessages;
+use crate::peer_role::{PeerRole, MAX_DISALLOWED_MESSAGES};
+use crate::peer_score::Misbehavior;
 use crate::rate_counter::RateCounter;
-use crate::routing::{Edge, EdgeInfo};
+use crate::routing::{Edge, EdgeInfo, SubKeys};
+use crate::send_queue::{SendPriority, SendQueue, SEND_QUEUE_WATERMARK};
+use crate::time::{Clock, Time};
+use crate::tls::PeerStream;
 use crate::types::{
-    Ban, Consolidate, ConsolidateResponse, Handshake, HandshakeFailureReason, HandshakeV2,
-    NetworkClientMessages, NetworkClientResponses, NetworkRequests, NetworkViewClientMessages,
-    NetworkViewClientResponses, PeerChainInfo, PeerChainInfoV2, PeerInfo, PeerManagerRequest,
-    PeerMessage, PeerRequest, PeerResponse, PeerStatsResult, PeerStatus, PeerType, PeersRequest,
-    PeersResponse, QueryPeerStats, ReasonForBan, RoutedMessage, RoutedMessageBody,
-    RoutedMessageFrom, SendMessage, StateResponseInfo, Unregister,
-    UPDATE_INTERVAL_LAST_TIME_RECEIVED_MESSAGE,
+    Ban, CheckReachability, Consolidate, ConsolidateResponse, Handshake, HandshakeFailureReason,
+    HandshakeV2, NetworkClientMessages, NetworkClientResponses, NetworkRequests,
+    NetworkViewClientMessages, NetworkViewClientResponses, PeerChainInfo, PeerChainInfoV2,
+    PeerInfo, PeerManagerRequest, PeerMessage, PeerRequest, PeerResponse, PeerStatsResult,
+    PeerStatus, PeerType, PeersRequest, PeersResponse, QueryPeerStats, ReachabilityProbeResponse,
+    ReasonForBan, RoutedMessage, RoutedMessageBody, RoutedMessageFrom, RoutingSyncV2, SendMessage,
+    StateResponseInfo, SubKeyDelegation, Unregister, UPDATE_INTERVAL_LAST_TIME_RECEIVED_MESSAGE,
 };
 use crate::PeerManagerActor;
 use crate::{
@@ -52,7 +65,7 @@ use crate::{
     NetworkResponses,
 };
 
-type WriteHalf = tokio::io::WriteHalf<tokio::net::TcpStream>;
+type WriteHalf = tokio::io::WriteHalf<PeerStream>;
 
 /// Maximum number of requests and responses to track.
 const MAX_TRACK_SIZE: usize = 30;
@@ -73,8 +86,12 @@ pub const EPOCH_SYNC_REQUEST_TIMEOUT_MS: u64 = 1_000;
 pub const EPOCH_SYNC_PEER_TIMEOUT_MS: u64 = 10;
 /// Limit cache size of 1000 messages
 pub const ROUTED_MESSAGE_CACHE_SIZE: usize = 1000;
+/// How often messages held back in the send queue are moved to the write buffer.
+const SEND_QUEUE_FLUSH_INTERVAL: Duration = Duration::from_millis(20);
 /// Duplicated messages will be dropped if routed through the same peer multiple times.
 pub const DROP_DUPLICATED_MESSAGES_PERIOD: Duration = Duration::from_millis(50);
+/// Duplicated messages are reported to the peer manager in batches of this size.
+const DUPLICATE_MESSAGES_REPORT_BATCH: u64 = 10;
 
 /// Internal structure to keep a circular queue within a tracker with unique hashes.
 struct CircularUniqueQueue {
@@ -122,18 +139,16 @@ pub struct Tracker {
     received: CircularUniqueQueue,
 }
 
-impl Default for Tracker {
-    fn default() -> Self {
+impl Tracker {
+    fn new(clock: Clock) -> Self {
         Tracker {
-            sent_bytes: RateCounter::new(),
-            received_bytes: RateCounter::new(),
+            sent_bytes: RateCounter::new(clock.clone()),
+            received_bytes: RateCounter::new(clock),
             requested: CircularUniqueQueue::new(MAX_TRACK_SIZE),
             received: CircularUniqueQueue::new(MAX_TRACK_SIZE),
         }
     }
-}
 
-impl Tracker {
     fn increment_received(&mut self, size: u64) {
         self.received_bytes.increment(size);
     }
@@ -202,6 +217,44 @@ pub struct Peer {
     last_time_received_epoch_sync_request: Instant,
     /// Cache of recently routed messages, this allows us to drop duplicates
     routed_message_cache: SizedCache<(PeerId, PeerIdOrHash, Signature), Instant>,
+    /// Role negotiated during handshake. Set once the connection is ready.
+    role: Option<PeerRole>,
+    /// Number of received messages which are not allowed for the role of this peer.
+    disallowed_messages: usize,
+    /// Tables of peer ids sent and received in compact form over this connection.
+    peer_id_encoder: PeerIdEncoder,
+    peer_id_decoder: PeerIdDecoder,
+    /// Duplicated routed messages received and not reported to the peer manager yet.
+    unreported_duplicates: u64,
+    /// Messages held back with their encoding, by priority and target, while the write buffer is
+    /// congested or the upload limit is exceeded.
+    send_queue: SendQueue<PeerIdOrHash, (PeerMessage, Vec<u8>)>,
+    /// Limits of the bytes sent to this peer. Messages are queued while they are exceeded.
+    upload_throttle: Throttle,
+    /// Broadcast messages recently received from any peer.
+    seen_messages: SeenMessages,
+    /// Optional features we advertise in the handshake.
+    capabilities: PeerCapabilities,
+    /// Optional features negotiated in the handshake, empty until then.
+    negotiated_capabilities: PeerCapabilities,
+    /// Compression of large messages, used if negotiated.
+    compression: CompressionConfig,
+    /// PeerId the TLS certificate of the peer is pinned to, if the connection uses TLS.
+    tls_peer_id: Option<PeerId>,
+    /// Whether the peer manager replaced this connection by a newer one of the same peer from
+    /// another address, in which case the peer isn't unregistered when this one stops.
+    migrated: bool,
+    clock: Clock,
+    /// Delegation of the sub-key our edges with peers supporting sub-keys are signed with.
+    sub_key_delegation: Option<SubKeyDelegation>,
+    /// Network sub-keys of all nodes we know, shared with the peer manager.
+    sub_keys: Arc<RwLock<SubKeys>>,
+    /// Faults injected in the messages sent to the peer.
+    #[cfg(feature = "test_features")]
+    send_faults: Option<FaultInjector<PeerMessage>>,
+    /// Faults injected in the messages received from the peer.
+    #[cfg(feature = "test_features")]
+    receive_faults: Option<FaultInjector<Vec<u8>>>,
 }
 
 impl Peer {
@@ -219,7 +272,17 @@ impl Peer {
         network_metrics: NetworkMetrics,
         txns_since_last_block: Arc<AtomicUsize>,
         peer_counter: Arc<AtomicUsize>,
+        upload_throttle: Throttle,
+        seen_messages: SeenMessages,
+        send_queue_config: SendQueueConfig,
+        capabilities: PeerCapabilities,
+        compression: CompressionConfig,
+        tls_peer_id: Option<PeerId>,
+        clock: Clock,
+        sub_key_delegation: Option<SubKeyDelegation>,
+        sub_keys: Arc<RwLock<SubKeys>>,
     ) -> Self {
+        let now = clock.now();
         Peer {
             node_info,
             peer_addr,
@@ -232,17 +295,37 @@ impl Peer {
             peer_manager_addr,
             client_addr,
             view_client_addr,
-            tracker: Default::default(),
+            tracker: Tracker::new(clock.clone()),
             genesis_id: Default::default(),
             chain_info: Default::default(),
             edge_info,
-            last_time_received_message_update: Instant::now(),
+            last_time_received_message_update: now,
             network_metrics,
             txns_since_last_block,
             peer_counter,
-            last_time_received_epoch_sync_request: Instant::now()
+            last_time_received_epoch_sync_request: now
                 - Duration::from_millis(EPOCH_SYNC_PEER_TIMEOUT_MS),
             routed_message_cache: SizedCache::with_size(ROUTED_MESSAGE_CACHE_SIZE),
+            role: None,
+            disallowed_messages: 0,
+            peer_id_encoder: Default::default(),
+            peer_id_decoder: Default::default(),
+            unreported_duplicates: 0,
+            send_queue: SendQueue::new(send_queue_config).with_clock(clock.clone()),
+            upload_throttle,
+            seen_messages,
+            capabilities,
+            negotiated_capabilities: PeerCapabilities::empty(),
+            compression,
+            tls_peer_id,
+            migrated: false,
+            clock,
+            sub_key_delegation,
+            sub_keys,
+            #[cfg(feature = "test_features")]
+            send_faults: None,
+            #[cfg(feature = "test_features")]
+            receive_faults: None,
         }
     }
 
@@ -255,7 +338,129 @@ impl Peer {
             || self.tracker.sent_bytes.count_per_min() > MAX_PEER_MSG_PER_MIN
     }
 
+    /// Whether messages have to wait in the send queue, because the write buffer is congested or
+    /// the upload limit is exceeded.
+    fn is_send_blocked(&self) -> bool {
+        self.framed.buffer_len() >= SEND_QUEUE_WATERMARK || self.upload_throttle.is_exhausted()
+    }
+
+    /// Send `msg`, or queue it by priority if the write buffer is congested or the upload limit
+    /// is exceeded. High priority messages are always written right away, ahead of the queued
+    /// ones.
     fn send_message(&mut self, msg: &PeerMessage) {
+        self.flush_send_queue();
+        let priority = SendPriority::of(msg);
+        if priority != SendPriority::High && (!self.send_queue.is_empty() || self.is_send_blocked())
+        {
+            // Routed messages are scheduled fairly across their targets.
+            let destination = match msg {
+                PeerMessage::Routed(routed) => Some(routed.target.clone()),
+                _ => None,
+            };
+            // The encoded message is queued along with it, so it isn't encoded again when sent.
+            let bytes = match peer_message_to_bytes(msg) {
+                Ok(bytes) => bytes,
+                Err(err) => {
+                    error!(target: "network", "Error converting message to bytes: {}", err);
+                    return;
+                }
+            };
+            let size = bytes.len();
+            if let Some((dropped, _)) =
+                self.send_queue.push(priority, destination, size, (msg.clone(), bytes))
+            {
+                debug!(target: "network", "{} Dropped {} to {} from the send queue", get_tid(), strum::AsStaticRef::as_static(&dropped), self.peer_info);
+            }
+            return;
+        }
+        self.write_message(msg);
+    }
+
+    /// Move queued messages to the write buffer until it is congested or the upload limit is
+    /// exceeded again.
+    fn flush_send_queue(&mut self) {
+        while !self.is_send_blocked() {
+            match self.send_queue.pop() {
+                Some((msg, bytes)) => self.write_encoded_message(&msg, Some(bytes)),
+                None => break,
+            }
+        }
+    }
+
+    /// Send `msg` once the faults injected in the send path let it through.
+    #[cfg_attr(not(feature = "test_features"), allow(unused_variables))]
+    fn send_message_with_faults(&mut self, ctx: &mut Context<Peer>, msg: &PeerMessage) {
+        #[cfg(feature = "test_features")]
+        if let Some(faults) = self.send_faults.as_mut() {
+            let size = peer_message_to_bytes(msg).map_or(0, |bytes| bytes.len());
+            if faults.push(msg.clone(), size, self.clock.now()) {
+                self.deliver_send_faults(ctx);
+            }
+            return;
+        }
+        self.send_message(msg);
+    }
+
+    /// Send the messages held by the send faults whose delivery time has come.
+    #[cfg(feature = "test_features")]
+    fn deliver_send_faults(&mut self, ctx: &mut Context<Peer>) {
+        let faults = unwrap_option_or_return!(self.send_faults.as_mut());
+        let now = self.clock.now();
+        let ready = faults.pop_ready(now);
+        if let Some(delay) = faults.next_delivery(now) {
+            near_performance_metrics::actix::run_later(ctx, delay, move |act, ctx| {
+                act.deliver_send_faults(ctx);
+            });
+        }
+        for msg in ready {
+            self.send_message(&msg);
+        }
+    }
+
+    /// Process the messages held by the receive faults whose delivery time has come.
+    #[cfg(feature = "test_features")]
+    fn deliver_receive_faults(&mut self, ctx: &mut Context<Peer>) {
+        let faults = unwrap_option_or_return!(self.receive_faults.as_mut());
+        let now = self.clock.now();
+        let ready = faults.pop_ready(now);
+        if let Some(delay) = faults.next_delivery(now) {
+            near_performance_metrics::actix::run_later(ctx, delay, move |act, ctx| {
+                act.deliver_receive_faults(ctx);
+            });
+        }
+        for msg in ready {
+            self.receive_bytes(ctx, msg);
+        }
+    }
+
+    fn schedule_send_queue_flush(&mut self, ctx: &mut Context<Peer>) {
+        near_performance_metrics::actix::run_later(
+            ctx,
+            SEND_QUEUE_FLUSH_INTERVAL,
+            move |act, ctx| {
+                act.flush_send_queue();
+                if act.send_queue.is_saturated_too_long() {
+                    info!(target: "network", "Disconnecting {}: its send queue stayed saturated", act.peer_info);
+                    near_metrics::inc_counter(&metrics::PEER_SEND_QUEUE_SATURATED_DISCONNECTS);
+                    ctx.stop();
+                    return;
+                }
+                act.schedule_send_queue_flush(ctx);
+            },
+        );
+    }
+
+    fn write_message(&mut self, msg: &PeerMessage) {
+        self.write_encoded_message(msg, None);
+    }
+
+    /// Write `msg`, reusing `bytes`, its encoding if known already, unless another form of the
+    /// message is sent (compact, or without the edges signed with sub-keys).
+    fn write_encoded_message(&mut self, msg: &PeerMessage, bytes: Option<Vec<u8>>) {
+        let without_sub_key_edges = self.without_sub_key_edges(msg);
+        let bytes = if without_sub_key_edges.is_some() { None } else { bytes };
+        let msg = without_sub_key_edges.as_ref().unwrap_or(msg);
+
         // Skip sending block and headers if we received it or header from this peer.
         // Record block requests in tracker.
         match msg {
@@ -264,11 +469,35 @@ impl Peer {
             _ => (),
         };
 
-        match peer_message_to_bytes(msg) {
+        let compact =
+            if self.compact_peer_ids_enabled() { self.peer_id_encoder.encode(msg) } else { None };
+        let is_compact = compact.is_some();
+        let bytes = match (compact, bytes) {
+            (Some(compact), _) => peer_message_to_bytes(&PeerMessage::Compact(compact)),
+            (None, Some(bytes)) => Ok(bytes),
+            (None, None) => peer_message_to_bytes(msg),
+        };
+        let bytes = if self.negotiated_capabilities.contains(PeerCapabilities::COMPRESSION) {
+            bytes.map(|bytes| compression::compress(&self.compression, bytes))
+        } else {
+            bytes
+        };
+        match bytes {
             Ok(bytes) => {
                 self.tracker.increment_sent(bytes.len() as u64);
                 let bytes_len = bytes.len();
+                self.network_metrics.inc_by(
+                    NetworkMetrics::peer_message_bytes_tx(&msg.msg_variant()).as_ref(),
+                    bytes_len as u64,
+                );
+                if !self.upload_throttle.consume(bytes_len).is_zero() {
+                    near_metrics::inc_counter_vec(&metrics::PEER_BANDWIDTH_THROTTLED, &["upload"]);
+                }
                 if !self.framed.write(bytes) {
+                    if is_compact {
+                        // The other side won't learn the peer ids added to our table.
+                        self.peer_id_encoder.disable();
+                    }
                     error!(
                         "{} Failed to send message {} of size {}",
                         get_tid(),
@@ -281,6 +510,70 @@ impl Peer {
         };
     }
 
+    /// Whether peer ids of messages exchanged with this peer can be compressed, negotiated in the
+    /// handshake. Compact messages received otherwise are rejected.
+    fn compact_peer_ids_enabled(&self) -> bool {
+        if self.peer_status != PeerStatus::Ready {
+            return false;
+        }
+        checked_feature!(
+            "protocol_feature_compact_peer_id",
+            CompactPeerId,
+            self.protocol_version,
+            { true },
+            { false }
+        )
+    }
+
+    /// Whether the peer exchanges network sub-keys, and can verify edges signed with them.
+    fn sub_keys_enabled(&self) -> bool {
+        checked_feature!(
+            "protocol_feature_network_sub_keys",
+            NetworkSubKeys,
+            self.protocol_version,
+            { true },
+            { false }
+        )
+    }
+
+    /// `msg` without the edges of nodes with sub-keys if the peer doesn't support sub-keys and
+    /// `msg` has any, since the peer couldn't verify them and would ban us for relaying them.
+    /// Edges of the peer itself are kept, it signed them with its node key.
+    fn without_sub_key_edges(&self, msg: &PeerMessage) -> Option<PeerMessage> {
+        if self.sub_keys_enabled() {
+            return None;
+        }
+        let sub_keys = self.sub_keys.read().unwrap();
+        if sub_keys.is_empty() {
+            return None;
+        }
+        let receiver = self.peer_id()?;
+        let keep = |edge: &Edge| {
+            edge.contains_peer(&receiver)
+                || !(sub_keys.contains_peer(&edge.peer0) || sub_keys.contains_peer(&edge.peer1))
+        };
+        match msg {
+            PeerMessage::RoutingTableSync(sync) if !sync.edges.iter().all(keep) => {
+                let mut sync = sync.clone();
+                sync.edges.retain(keep);
+                Some(PeerMessage::RoutingTableSync(sync))
+            }
+            PeerMessage::RoutingTableSyncV2(RoutingSyncV2::Version2(sync))
+                if !sync.edges.iter().all(keep) =>
+            {
+                let mut sync = sync.clone();
+                sync.edges.retain(keep);
+                Some(PeerMessage::RoutingTableSyncV2(RoutingSyncV2::Version2(sync)))
+            }
+            PeerMessage::RoutingTableDelta(delta) if !delta.edges.iter().all(keep) => {
+                let mut delta = delta.clone();
+                delta.edges.retain(keep);
+                Some(PeerMessage::RoutingTableDelta(delta))
+            }
+            _ => None,
+        }
+    }
+
     fn fetch_client_chain_info(&mut self, ctx: &mut Context<Peer>) {
         ctx.wait(
             self.view_client_addr
@@ -324,6 +617,8 @@ impl Peer {
                             act.node_info.addr_port(),
                             PeerChainInfoV2 { genesis_id, height, tracked_shards, archival },
                             act.edge_info.as_ref().unwrap().clone(),
+                            act.capabilities,
+                            act.sub_key_delegation.clone(),
                         )),
                         34..=38 => PeerMessage::HandshakeV2(HandshakeV2::new(
                             act.protocol_version,
@@ -411,7 +706,7 @@ impl Peer {
                 NetworkViewClientMessages::BlockHeadersRequest(hashes)
             }
             PeerMessage::EpochSyncRequest(epoch_id) => {
-                self.last_time_received_epoch_sync_request = Instant::now();
+                self.last_time_received_epoch_sync_request = self.clock.now();
                 NetworkViewClientMessages::EpochSyncRequest { epoch_id }
             }
             PeerMessage::EpochSyncFinalizationRequest(epoch_id) => {
@@ -546,6 +841,7 @@ impl Peer {
                     }
                     RoutedMessageBody::Ping(_)
                     | RoutedMessageBody::Pong(_)
+                    | RoutedMessageBody::RouteNotFound(_)
                     | RoutedMessageBody::TxStatusRequest(_, _)
                     | RoutedMessageBody::TxStatusResponse(_)
                     | RoutedMessageBody::QueryRequest { .. }
@@ -573,6 +869,16 @@ impl Peer {
             | PeerMessage::PeersResponse(_)
             | PeerMessage::RoutingTableSync(_)
             | PeerMessage::RoutingTableSyncV2(_)
+            | PeerMessage::Compact(_)
+            | PeerMessage::ReachabilityRequest(_)
+            | PeerMessage::ReachabilityResponse(_)
+            | PeerMessage::RoutingTableDeltaRequest(_)
+            | PeerMessage::RoutingTableDelta(_)
+            | PeerMessage::EdgeDigest(_)
+            | PeerMessage::EdgePull(_)
+            | PeerMessage::AnnounceAccounts(_)
+            | PeerMessage::ProxyAnnouncement(_)
+            | PeerMessage::SubKeyDelegations(_)
             | PeerMessage::LastEdge(_)
             | PeerMessage::Disconnect
             | PeerMessage::RequestUpdateNonce(_)
@@ -617,10 +923,10 @@ impl Peer {
     /// Hook called on every valid message received from this peer from the network.
     fn on_receive_message(&mut self) {
         if let Some(peer_id) = self.peer_id() {
-            if self.last_time_received_message_update.elapsed()
+            if self.clock.elapsed(self.last_time_received_message_update)
                 > UPDATE_INTERVAL_LAST_TIME_RECEIVED_MESSAGE
             {
-                self.last_time_received_message_update = Instant::now();
+                self.last_time_received_message_update = self.clock.now();
                 self.peer_manager_addr.do_send(PeerRequest::ReceivedMessage(
                     peer_id,
                     self.last_time_received_message_update,
@@ -648,6 +954,8 @@ impl Actor for Peer {
             }
         });
 
+        self.schedule_send_queue_flush(ctx);
+
         // If outbound peer, initiate handshake.
         if self.peer_type == PeerType::Outbound {
             self.send_handshake(ctx);
@@ -659,7 +967,9 @@ impl Actor for Peer {
         near_metrics::dec_gauge(&metrics::PEER_CONNECTIONS_TOTAL);
         debug!(target: "network", "{:?}: Peer {} disconnected. {:?}", self.node_info.id, self.peer_info, self.peer_status);
         if let Some(peer_info) = self.peer_info.as_ref() {
-            if let PeerStatus::Banned(ban_reason) = self.peer_status {
+            if self.migrated {
+                debug!(target: "network", "Connection with {} replaced by a connection from a new address", peer_info);
+            } else if let PeerStatus::Banned(ban_reason) = self.peer_status {
                 self.peer_manager_addr.do_send(Ban { peer_id: peer_info.id.clone(), ban_reason });
             } else {
                 self.peer_manager_addr.do_send(Unregister {
@@ -696,16 +1006,52 @@ impl StreamHandler<Result<Vec<u8>, ReasonForBan>> for Peer {
             }
         };
 
+        #[cfg(feature = "test_features")]
+        if let Some(faults) = self.receive_faults.as_mut() {
+            let len = msg.len();
+            if faults.push(msg, len, self.clock.now()) {
+                self.deliver_receive_faults(ctx);
+            }
+            return;
+        }
+        self.receive_bytes(ctx, msg);
+    }
+}
+
+impl Peer {
+    /// Process a message received from the peer, once the injected faults let it through.
+    fn receive_bytes(&mut self, ctx: &mut Context<Peer>, msg: Vec<u8>) {
         near_metrics::inc_counter_by(&metrics::PEER_DATA_RECEIVED_BYTES, msg.len() as u64);
         near_metrics::inc_counter(&metrics::PEER_MESSAGE_RECEIVED_TOTAL);
 
         self.tracker.increment_received(msg.len() as u64);
+        let msg = if compression::is_compressed(&msg) {
+            if !self.negotiated_capabilities.contains(PeerCapabilities::COMPRESSION) {
+                debug!(target: "network", "Received compressed message from {}, which didn't negotiate compression", self.peer_info);
+                self.ban_peer(ctx, ReasonForBan::Abusive);
+                return;
+            }
+            match compression::decompress(&msg) {
+                Ok(msg) => msg,
+                Err(err) => {
+                    debug!(target: "network", "Received invalid compressed message from {}: {}", self.peer_info, err);
+                    self.ban_peer(ctx, ReasonForBan::Abusive);
+                    return;
+                }
+            }
+        } else {
+            msg
+        };
         if codec::is_forward_tx(&msg).unwrap_or(false) {
             let r = self.txns_since_last_block.load(Ordering::Acquire);
             if r > MAX_TXNS_PER_BLOCK_MESSAGE {
                 return;
             }
         }
+        if self.peer_status == PeerStatus::Ready && self.seen_messages.is_duplicate(&msg) {
+            trace!(target: "network", "Dropped duplicate broadcast message from {}", self.peer_info);
+            return;
+        }
         let mut peer_msg = match bytes_to_peer_message(&msg) {
             Ok(peer_msg) => peer_msg,
             Err(err) => {
@@ -737,13 +1083,50 @@ impl StreamHandler<Result<Vec<u8>, ReasonForBan>> for Peer {
             }
         };
 
+        if let PeerMessage::Compact(compact) = peer_msg {
+            if !self.compact_peer_ids_enabled() {
+                debug!(target: "network", "Received compact message from {}, which didn't negotiate compact peer ids", self.peer_info);
+                self.ban_peer(ctx, ReasonForBan::Abusive);
+                return;
+            }
+            peer_msg = match self.peer_id_decoder.decode(compact) {
+                Ok(peer_msg) => peer_msg,
+                Err(err) => {
+                    warn!(target: "network", "Received invalid compact message from {}: {}", self.peer_info, err);
+                    self.ban_peer(ctx, ReasonForBan::InvalidPeerId);
+                    return;
+                }
+            };
+            // Forwarded transactions in compact form are not seen by `codec::is_forward_tx`.
+            if let PeerMessage::Routed(RoutedMessage {
+                body: RoutedMessageBody::ForwardTx(_),
+                ..
+            }) = &peer_msg
+            {
+                if self.txns_since_last_block.load(Ordering::Acquire) > MAX_TXNS_PER_BLOCK_MESSAGE {
+                    return;
+                }
+            }
+        }
+
         // Drop duplicated messages routed within DROP_DUPLICATED_MESSAGES_PERIOD ms
         if let PeerMessage::Routed(msg) = &peer_msg {
             let key = (msg.author.clone(), msg.target.clone(), msg.signature.clone());
-            let now = Instant::now();
+            let now = self.clock.now();
             if let Some(time) = self.routed_message_cache.cache_get(&key) {
-                if now.duration_since(*time) <= DROP_DUPLICATED_MESSAGES_PERIOD {
+                if Time::duration_since(now, *time) <= DROP_DUPLICATED_MESSAGES_PERIOD {
                     debug!(target: "network", "Dropping duplicated message from {} to {:?}", msg.author, msg.target);
+                    self.unreported_duplicates += 1;
+                    if self.unreported_duplicates >= DUPLICATE_MESSAGES_REPORT_BATCH {
+                        if let Some(peer_id) = self.peer_id() {
+                            self.peer_manager_addr.do_send(PeerRequest::Misbehavior(
+                                peer_id,
+                                Misbehavior::DuplicateMessage,
+                                self.unreported_duplicates,
+                            ));
+                        }
+                        self.unreported_duplicates = 0;
+                    }
                     return;
                 }
             }
@@ -774,6 +1157,20 @@ impl StreamHandler<Result<Vec<u8>, ReasonForBan>> for Peer {
             peer_msg = PeerMessage::Handshake(handshake.into());
         }
 
+        if self.peer_status == PeerStatus::Ready {
+            if let Some(role) = self.role {
+                if !role.is_allowed(&peer_msg) {
+                    near_metrics::inc_counter(&metrics::PEER_MESSAGE_NOT_ALLOWED);
+                    self.disallowed_messages += 1;
+                    debug!(target: "network", "Received {} not allowed for {:?} from {}", peer_msg, role, self.peer_info);
+                    if self.disallowed_messages > MAX_DISALLOWED_MESSAGES {
+                        self.ban_peer(ctx, ReasonForBan::Abusive);
+                    }
+                    return;
+                }
+            }
+        }
+
         match (self.peer_type, self.peer_status, peer_msg) {
             (_, _, PeerMessage::HandshakeFailure(peer_info, reason)) => {
                 match reason {
@@ -847,11 +1244,27 @@ impl StreamHandler<Result<Vec<u8>, ReasonForBan>> for Peer {
                     // Connection will be closed by a handshake timeout
                 }
 
+                // The peer may sign its edges with a sub-key delegated by its node key.
+                let sub_key_delegation =
+                    handshake.sub_key_delegation.clone().filter(|_| self.sub_keys_enabled());
+                if let Some(delegation) = &sub_key_delegation {
+                    if delegation.peer_id != handshake.peer_id || !delegation.verify() {
+                        warn!(target: "network", "Received invalid sub-key delegation on handshake. Disconnecting peer {}", handshake.peer_id);
+                        self.ban_peer(ctx, ReasonForBan::InvalidSignature);
+                        return;
+                    }
+                }
+                let sub_keys: Vec<_> = sub_key_delegation
+                    .iter()
+                    .map(|delegation| delegation.sub_key.clone())
+                    .collect();
+
                 // Verify signature of the new edge in handshake.
                 if !Edge::partial_verify(
                     self.node_id(),
                     handshake.peer_id.clone(),
                     &handshake.edge_info,
+                    &sub_keys,
                 ) {
                     warn!(target: "network", "Received invalid signature on handshake. Disconnecting peer {}", handshake.peer_id);
                     self.ban_peer(ctx, ReasonForBan::InvalidSignature);
@@ -877,6 +1290,9 @@ impl StreamHandler<Result<Vec<u8>, ReasonForBan>> for Peer {
                     account_id: None,
                 };
                 self.chain_info = handshake.chain_info.clone();
+                self.negotiated_capabilities =
+                    self.capabilities.negotiate(handshake.capabilities, self.protocol_version);
+                let role = PeerRole::negotiate(self.protocol_version, handshake.capabilities);
                 self.peer_manager_addr
                     .send(Consolidate {
                         actor: ctx.address(),
@@ -886,6 +1302,9 @@ impl StreamHandler<Result<Vec<u8>, ReasonForBan>> for Peer {
                         this_edge_info: self.edge_info.clone(),
                         other_edge_info: handshake.edge_info.clone(),
                         peer_protocol_version: self.protocol_version,
+                        capabilities: self.negotiated_capabilities,
+                        tls_peer_id: self.tls_peer_id.clone(),
+                        sub_key_delegation,
                     })
                     .into_actor(self)
                     .then(move |res, act, ctx| {
@@ -893,6 +1312,7 @@ impl StreamHandler<Result<Vec<u8>, ReasonForBan>> for Peer {
                             Ok(ConsolidateResponse::Accept(edge_info)) => {
                                 act.peer_info = Some(peer_info).into();
                                 act.peer_status = PeerStatus::Ready;
+                                act.role = Some(role);
                                 // Respond to handshake if it's inbound and connection was consolidated.
                                 if act.peer_type == PeerType::Inbound {
                                     act.edge_info = edge_info;
@@ -923,7 +1343,7 @@ impl StreamHandler<Result<Vec<u8>, ReasonForBan>> for Peer {
                 }
 
                 // Disconnect if neighbor propose invalid edge.
-                if !edge.verify() {
+                if !edge.verify_with_sub_keys(&self.sub_keys.read().unwrap()) {
                     info!(target: "network", "{:?}: Peer {:?} sent invalid edge. Disconnect.", self.node_id(), self.peer_addr);
                     ctx.stop();
                     return;
@@ -967,6 +1387,24 @@ impl StreamHandler<Result<Vec<u8>, ReasonForBan>> for Peer {
                 debug!(target: "network", "Received peers from {}: {} peers.", self.peer_info, peers.len());
                 self.peer_manager_addr.do_send(PeersResponse { peers });
             }
+            (_, PeerStatus::Ready, PeerMessage::ReachabilityRequest(targets)) => {
+                self.peer_manager_addr
+                    .send(CheckReachability { targets })
+                    .into_actor(self)
+                    .then(|res, act, _ctx| {
+                        if let Ok(result) = res {
+                            act.send_message(&PeerMessage::ReachabilityResponse(result.reachable));
+                        }
+                        actix::fut::ready(())
+                    })
+                    .spawn(ctx);
+            }
+            (_, PeerStatus::Ready, PeerMessage::ReachabilityResponse(reachable)) => {
+                self.peer_manager_addr.do_send(ReachabilityProbeResponse {
+                    peer_id: self.peer_id().unwrap(),
+                    reachable,
+                });
+            }
             (_, PeerStatus::Ready, PeerMessage::RequestUpdateNonce(edge_info)) => self
                 .peer_manager_addr
                 .send(NetworkRequests::RequestUpdateNonce(self.peer_id().unwrap(), edge_info))
@@ -1002,13 +1440,62 @@ impl StreamHandler<Result<Vec<u8>, ReasonForBan>> for Peer {
                 self.peer_manager_addr
                     .do_send(NetworkRequests::Sync { peer_id: self.peer_id().unwrap(), sync_data });
             }
-            #[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
-            (_, _state, PeerMessage::RoutingTableSyncV2(ibf_message)) => {
+            (_, PeerStatus::Ready, PeerMessage::RoutingTableSyncV2(ibf_message)) => {
                 self.peer_manager_addr.do_send(NetworkRequests::IbfMessage {
                     peer_id: self.peer_id().unwrap(),
                     ibf_msg: ibf_message,
                 });
             }
+            (_, PeerStatus::Ready, PeerMessage::RoutingTableDeltaRequest(watermark)) => {
+                self.peer_manager_addr.do_send(NetworkRequests::DeltaSyncRequest {
+                    peer_id: self.peer_id().unwrap(),
+                    watermark,
+                });
+            }
+            (_, PeerStatus::Ready, PeerMessage::RoutingTableDelta(delta)) => {
+                self.peer_manager_addr.do_send(NetworkRequests::DeltaSync {
+                    peer_id: self.peer_id().unwrap(),
+                    delta,
+                });
+            }
+            (_, PeerStatus::Ready, PeerMessage::EdgeDigest(digest)) => {
+                self.peer_manager_addr.do_send(NetworkRequests::EdgeDigest {
+                    peer_id: self.peer_id().unwrap(),
+                    digest,
+                });
+            }
+            (_, PeerStatus::Ready, PeerMessage::EdgePull(keys)) => {
+                self.peer_manager_addr
+                    .do_send(NetworkRequests::EdgePull { peer_id: self.peer_id().unwrap(), keys });
+            }
+            (_, PeerStatus::Ready, PeerMessage::AnnounceAccounts(accounts)) => {
+                self.peer_manager_addr.do_send(NetworkRequests::AnnounceAccountsWithHops {
+                    peer_id: self.peer_id().unwrap(),
+                    accounts,
+                });
+            }
+            (_, PeerStatus::Ready, PeerMessage::ProxyAnnouncement(announcement)) => {
+                if !announcement.verify() {
+                    self.ban_peer(ctx, ReasonForBan::InvalidSignature);
+                } else {
+                    self.peer_manager_addr.do_send(NetworkRequests::ProxyAnnouncement {
+                        peer_id: self.peer_id().unwrap(),
+                        announcement,
+                    });
+                }
+            }
+            (_, PeerStatus::Ready, PeerMessage::SubKeyDelegations(delegations)) => {
+                if !self.sub_keys_enabled() {
+                    debug!(target: "network", "Ignoring sub-key delegations from {}, which didn't negotiate them", self.peer_info);
+                } else if !delegations.iter().all(SubKeyDelegation::verify) {
+                    self.ban_peer(ctx, ReasonForBan::InvalidSignature);
+                } else {
+                    self.peer_manager_addr.do_send(NetworkRequests::SubKeyDelegations {
+                        peer_id: self.peer_id().unwrap(),
+                        delegations,
+                    });
+                }
+            }
             (_, PeerStatus::Ready, PeerMessage::Routed(routed_message)) => {
                 trace!(target: "network", "Received routed message from {} to {:?}.", self.peer_info, routed_message.target);
 
@@ -1045,10 +1532,10 @@ impl Handler<SendMessage> for Peer {
     type Result = ();
 
     #[perf]
-    fn handle(&mut self, msg: SendMessage, _: &mut Self::Context) {
+    fn handle(&mut self, msg: SendMessage, ctx: &mut Self::Context) {
         #[cfg(feature = "delay_detector")]
         let _d = DelayDetector::new("send message".into());
-        self.send_message(&msg.message);
+        self.send_message_with_faults(ctx, &msg.message);
     }
 }
 
@@ -1056,10 +1543,10 @@ impl Handler<Arc<SendMessage>> for Peer {
     type Result = ();
 
     #[perf]
-    fn handle(&mut self, msg: Arc<SendMessage>, _: &mut Self::Context) {
+    fn handle(&mut self, msg: Arc<SendMessage>, ctx: &mut Self::Context) {
         #[cfg(feature = "delay_detector")]
         let _d = DelayDetector::new("send message".into());
-        self.send_message(&msg.as_ref().message);
+        self.send_message_with_faults(ctx, &msg.as_ref().message);
     }
 }
 
@@ -1097,7 +1584,35 @@ impl Handler<PeerManagerRequest> for Peer {
             PeerManagerRequest::UnregisterPeer => {
                 ctx.stop();
             }
+            PeerManagerRequest::ConnectionMigrated => {
+                // The peer likely can't receive it at its old address, but say goodbye in case.
+                self.send_message(&PeerMessage::Disconnect);
+                self.migrated = true;
+                ctx.stop();
+            }
+        }
+    }
+}
+
+#[cfg(feature = "test_features")]
+impl Handler<PeerFaults> for Peer {
+    type Result = ();
+
+    #[perf]
+    fn handle(&mut self, msg: PeerFaults, ctx: &mut Self::Context) {
+        // Messages held by the previous faults are delivered right away.
+        if let Some(faults) = self.send_faults.take() {
+            for msg in faults.into_pending() {
+                self.send_message(&msg);
+            }
+        }
+        if let Some(faults) = self.receive_faults.take() {
+            for msg in faults.into_pending() {
+                self.receive_bytes(ctx, msg);
+            }
         }
+        self.send_faults = msg.send.map(FaultInjector::new);
+        self.receive_faults = msg.receive.map(FaultInjector::new);
     }
 }
 
@@ -0,0 +1,122 @@
+//! Peers we were connected to and which behaved well, persisted on disk so that after a restart
+//! we dial them first, before the boot nodes. This shortens the time to the first connection and
+//! reduces the load on boot nodes.
+use std::io;
+use std::time::Duration;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use tracing::warn;
+
+use near_store::{ColPeerCache, Store};
+
+use crate::types::PeerInfo;
+
+/// Key of the cached peers in `ColPeerCache`.
+const PEER_CACHE_KEY: &[u8] = b"healthiest";
+
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+pub struct CachedPeer {
+    pub peer_info: PeerInfo,
+    /// Score of the peer when it was last seen, see `PeerScore`. Zero for peers which never
+    /// misbehaved.
+    pub score: f64,
+    /// Last time we were connected to the peer, as a timestamp in nanoseconds.
+    pub last_seen: u64,
+}
+
+/// Healthiest peers, with the highest score first and the most recently seen first among peers
+/// with the same score.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, Default, PartialEq)]
+pub struct PeerCache {
+    peers: Vec<CachedPeer>,
+}
+
+impl PeerCache {
+    /// Peers saved on disk, none if none were saved yet.
+    pub fn load(store: &Store) -> Self {
+        match store.get_ser::<Self>(ColPeerCache, PEER_CACHE_KEY) {
+            Ok(cache) => cache.unwrap_or_default(),
+            Err(err) => {
+                warn!(target: "network", "Failed to load cached peers: {}", err);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self, store: &Store) -> io::Result<()> {
+        let mut update = store.store_update();
+        update.set_ser(ColPeerCache, PEER_CACHE_KEY, self)?;
+        update.commit()
+    }
+
+    pub fn peers(&self) -> &[CachedPeer] {
+        &self.peers
+    }
+
+    /// Add the currently `connected` peers, replacing older entries of the same peers, drop
+    /// entries not seen for `expiration` since `now` and keep the `max_len` healthiest peers.
+    pub fn update(
+        &mut self,
+        connected: Vec<CachedPeer>,
+        max_len: usize,
+        expiration: Duration,
+        now: u64,
+    ) {
+        let oldest = now.saturating_sub(expiration.as_nanos() as u64);
+        self.peers.retain(|peer| {
+            peer.last_seen >= oldest
+                && !connected.iter().any(|other| other.peer_info.id == peer.peer_info.id)
+        });
+        self.peers.extend(connected);
+        self.peers.sort_by(|a, b| {
+            b.score.partial_cmp(&a.score).unwrap().then_with(|| b.last_seen.cmp(&a.last_seen))
+        });
+        self.peers.truncate(max_len);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use near_store::test_utils::create_test_store;
+
+    use crate::test_utils::random_peer_id;
+    use crate::types::PeerInfo;
+
+    use super::{CachedPeer, PeerCache};
+
+    fn cached_peer(port: u16, score: f64, last_seen: u64) -> CachedPeer {
+        let peer_info = PeerInfo {
+            id: random_peer_id(),
+            addr: Some(format!("127.0.0.1:{}", port).parse().unwrap()),
+            account_id: None,
+        };
+        CachedPeer { peer_info, score, last_seen }
+    }
+
+    #[test]
+    fn healthiest_peers_persisted() {
+        let store = create_test_store();
+        assert_eq!(PeerCache::load(&store), PeerCache::default());
+
+        let expiration = Duration::from_secs(100);
+        let now = 1_000 * 1_000_000_000;
+        let (recent, penalized, expired) = (
+            cached_peer(1, 0.0, now - 50 * 1_000_000_000),
+            cached_peer(2, -10.0, now),
+            cached_peer(3, 0.0, now - 200 * 1_000_000_000),
+        );
+        let mut cache = PeerCache::default();
+        cache.update(vec![recent.clone(), penalized, expired], 10, expiration, now);
+
+        let mut reconnected = recent.clone();
+        reconnected.last_seen = now;
+        let new = cached_peer(4, 0.0, now - 1);
+        cache.update(vec![reconnected.clone(), new.clone()], 2, expiration, now);
+        assert_eq!(cache.peers(), &[reconnected, new]);
+
+        cache.save(&store).unwrap();
+        assert_eq!(PeerCache::load(&store), cache);
+    }
+}
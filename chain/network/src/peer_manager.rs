@@ -1,21 +1,21 @@
 use rand::seq::{IteratorRandom, SliceRandom};
 use std::cmp;
 use std::collections::{HashMap, HashSet};
-#[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
 use std::mem::swap;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::pin::Pin;
 use std::sync::atomic::Ordering;
-use std::sync::{atomic::AtomicUsize, Arc};
+use std::sync::{atomic::AtomicUsize, Arc, RwLock};
 use std::time::{Duration, Instant};
 
 use actix::{
     Actor, ActorFuture, Addr, Arbiter, AsyncContext, Context, ContextFutureSpawner, Handler,
-    Recipient, Running, StreamHandler, SyncArbiter, WrapFuture,
+    MessageResult, Recipient, ResponseFuture, Running, StreamHandler, SyncArbiter, WrapFuture,
 };
-use chrono::Utc;
+use cached::{Cached, SizedCache};
 use futures::task::Poll;
-use futures::{future, Stream, StreamExt};
+use futures::{future, FutureExt, Stream, StreamExt};
 use tokio::net::{TcpListener, TcpStream};
 use tokio_util::codec::FramedRead;
 use tracing::{debug, error, info, trace, warn};
@@ -23,42 +23,95 @@ use tracing::{debug, error, info, trace, warn};
 #[cfg(feature = "delay_detector")]
 use delay_detector::DelayDetector;
 use metrics::NetworkMetrics;
+use near_crypto::SecretKey;
 use near_performance_metrics::framed_write::FramedWrite;
 use near_performance_metrics_macros::perf;
 use near_primitives::checked_feature;
 use near_primitives::hash::CryptoHash;
 use near_primitives::network::{AnnounceAccount, PeerId};
-use near_primitives::types::{AccountId, ProtocolVersion};
-use near_primitives::utils::from_timestamp;
+use near_primitives::types::{AccountId, ProtocolVersion, ShardId};
+use near_primitives::utils::{from_timestamp, to_timestamp};
 use near_store::Store;
 use rand::thread_rng;
 
+use crate::anchors::{AnchorPeers, GetAnchorPeersResult, ANCHOR_RETRY_INTERVAL};
+use crate::audit_log::{AuditLog, GetRoutingAuditLogResult};
+use crate::bandwidth::Throttle;
 use crate::codec::Codec;
+use crate::debug::{NetworkDebugInfo, PeerConnectionView};
+use crate::delta_sync::{RoutingTableDelta, SyncWatermark};
+use crate::denylist::KeyPrefixDenylist;
+use crate::diagnostics;
+use crate::dns_seed;
+use crate::edge_gossip::{EdgeDigestEntry, EdgeGossip, MAX_DIGEST_EDGES};
+use crate::gossip_dedup::SeenMessages;
+use crate::gossip_stats::{EdgeGossipStats, GetEdgeGossipStatsResult};
+use crate::nat;
 use crate::peer::Peer;
+use crate::peer_cache::{CachedPeer, PeerCache};
+use crate::peer_regions::{RegionMap, RegionStats};
+use crate::peer_score::{
+    GetPeerScoresResult, Misbehavior, PeerScore, BAN_PEER_SCORE, DEPRIORITIZE_PEER_SCORE,
+};
 use crate::peer_store::{PeerStore, TrustLevel};
+use crate::proxy::{self, ProxyTarget};
+#[cfg(feature = "protocol_feature_reachability_probe")]
+use crate::reachability_probe::REACHABILITY_PROBE_INTERVAL;
+use crate::reachability_probe::{
+    GetReachabilityProbesResult, ReachabilityProbes, MAX_REACHABILITY_PROBE_TARGETS,
+};
 #[cfg(feature = "test_features")]
-use crate::routing::SetAdvOptionsResult;
-use crate::{metrics, RoutingTableActor, RoutingTableMessages, RoutingTableMessagesResponse};
+use crate::routing::{RoutingTableSnapshot, SetAdvOptionsResult};
+use crate::routing_dump::RoutingTableDumpFormat;
+use crate::routing_loops::ForwardedMessages;
+use crate::routing_profile::RoutingProfile;
+use crate::routing_replay::RoutingEvent;
+use crate::routing_replica::{
+    self, ReplicaSubscribe, RoutingEventPublisher, REPLICA_RECONNECT_INTERVAL,
+};
+use crate::{
+    metrics, RoutingTableActor, RoutingTableMessages, RoutingTableMessagesResponse,
+    ROUTING_TABLE_PAGE_SIZE,
+};
 
 use crate::routing::{
-    Edge, EdgeInfo, EdgeType, EdgeVerifierHelper, GetRoutingTableResult, PeerRequestResult,
-    ProcessEdgeResult, RoutingTable, SimpleEdge, MAX_NUM_PEERS, SAVE_PEERS_AFTER_TIME,
+    AccountRouteExplanation, Edge, EdgeInfo, EdgeType, EdgeVerifierHelper, GetRoutingTableResult,
+    PeerRequestResult, ProcessEdgeResult, RouteExplanation, RoutingTable, SimpleEdge, SubKeys,
+    MAX_NUM_PEERS,
 };
 
+use crate::capabilities::PeerCapabilities;
+#[cfg(feature = "test_features")]
+use crate::chaos::PeerFaults;
+use crate::edge_rate_limiter::EdgeRateLimiter;
 use crate::edge_verifier::EdgeVerifier;
+use crate::failpoints::{self, FailpointsHandle};
+use crate::graph_worker::{self, GraphWorker, MIN_GRAPH_NODES_FOR_WORKERS};
+use crate::ibf::IbfHasherKind;
+use crate::routing_table_actor::prefer_full_sync;
+use crate::tier1::Tier1Connections;
+use crate::time::{Clock, Time};
+use crate::tls::{PeerStream, TlsContext};
 use crate::types::{
-    AccountOrPeerIdOrHash, Ban, BlockedPorts, Consolidate, ConsolidateResponse, EdgeList,
-    FullPeerInfo, GetRoutingTable, InboundTcpConnect, KnownPeerState, KnownPeerStatus,
-    KnownProducer, NetworkClientMessages, NetworkConfig, NetworkInfo, NetworkRequests,
-    NetworkResponses, NetworkViewClientMessages, NetworkViewClientResponses, OutboundTcpConnect,
-    PeerIdOrHash, PeerInfo, PeerManagerRequest, PeerMessage, PeerRequest, PeerResponse, PeerType,
-    PeersRequest, PeersResponse, Ping, Pong, QueryPeerStats, RawRoutedMessage, ReasonForBan,
-    RoutedMessage, RoutedMessageBody, RoutedMessageFrom, SendMessage, StateResponseInfo, StopMsg,
-    SyncData, Unregister,
+    canonical_addr, is_address_change, subnet_of, AccountOrPeerIdOrHash, Ban, BlockedPorts,
+    CheckReachability, CheckReachabilityResult, Consolidate, ConsolidateResponse, DumpRoutingTable,
+    EdgeList, ExplainAccountRoute, ExplainRoute, FindRouteError, Flush, FullPeerInfo,
+    GetAnchorPeers, GetEdgeGossipStats, GetNetworkDebugInfo, GetPeerScores, GetPingRtts,
+    GetReachabilityProbes, GetRoutingAuditLog, GetRoutingProfile, GetRoutingTable,
+    InboundTcpConnect, KnownPeerState, KnownPeerStatus, KnownProducer, ListenAddr,
+    NetworkClientMessages, NetworkConfig, NetworkInfo, NetworkRequests, NetworkResponses,
+    NetworkViewClientMessages, NetworkViewClientResponses, OutboundTcpConnect, PeerIdOrHash,
+    PeerInfo, PeerManagerRequest, PeerMessage, PeerRequest, PeerResponse, PeerType, PeersRequest,
+    PeersResponse, Ping, Pong, ProxyAnnouncement, PruneConfig, QueryPeerStats, RawRoutedMessage,
+    ReachabilityProbeResponse, ReasonForBan, ReloadNetworkConfig, RouteNotFound, RoutedMessage,
+    RoutedMessageBody, RoutedMessageFrom, SendMessage, SendPing, SentPing, StateResponseInfo,
+    StopMsg, SubKeyDelegation, SyncData, Unregister,
 };
 #[cfg(feature = "test_features")]
-use crate::types::{GetPeerId, GetPeerIdResult, SetAdvOptions};
-#[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
+use crate::types::{
+    GetPeerId, GetPeerIdResult, GetRoutingTableSnapshot, RestoreRoutingTableSnapshot,
+    SetAdvOptions, SetPeerFaults,
+};
 use crate::types::{RoutingState, RoutingSyncV2, RoutingVersion2};
 
 /// How often to request peers from active peers.
@@ -92,6 +145,28 @@ const BROAD_CAST_EDGES_MAX_WORK_ALLOWED: Duration = Duration::from_millis(50);
 const WAIT_FOR_SYNC_DELAY: Duration = Duration::from_millis(1_000);
 /// How often should we update the routing table
 const UPDATE_ROUTING_TABLE_INTERVAL: Duration = Duration::from_millis(1_000);
+/// How often should we check that no peer is both reachable in memory and saved on disk.
+const PEER_COMPONENT_SWEEP_INTERVAL: Duration = Duration::from_millis(600_000);
+/// Timeout of connections through a proxy, which include the handshake with the proxy and
+/// possibly resolving the host name of the peer.
+const PROXY_CONNECT_TIMEOUT: Duration = Duration::from_millis(10_000);
+/// Routes computed by the graph workers are dropped if the graph changed meanwhile. After this
+/// many times in a row, they are computed on the actor instead so the routing table is updated.
+const MAX_STALE_ROUTES_COMPUTATIONS: usize = 3;
+/// How often should we save the lifetime edge counters on disk.
+const SAVE_EDGE_STATS_INTERVAL: Duration = Duration::from_millis(60_000);
+/// How often should we save the healthiest peers on disk.
+const SAVE_PEER_CACHE_INTERVAL: Duration = Duration::from_millis(60_000);
+/// How long to wait before retrying a failed NAT port mapping. Also the minimum time between
+/// renewals of a mapping.
+const NAT_PORT_MAPPING_RETRY_INTERVAL: Duration = Duration::from_millis(60_000);
+/// Number of peers whose routing sync watermark we remember.
+const SYNC_WATERMARKS_CACHE_SIZE: usize = 1_000;
+/// Number of gossiped transactions remembered, so they aren't gossiped again.
+const GOSSIPED_TRANSACTIONS_CACHE_SIZE: usize = 10_000;
+/// How often nodes hiding their address behind relays sign a new `ProxyAnnouncement`, well
+/// within `PROXY_ANNOUNCEMENT_TTL`.
+const PROXY_ANNOUNCEMENT_PERIOD: Duration = Duration::from_millis(1_200_000);
 
 macro_rules! unwrap_or_error(($obj: expr, $error: expr) => (match $obj {
     Ok(result) => result,
@@ -101,6 +176,26 @@ macro_rules! unwrap_or_error(($obj: expr, $error: expr) => (match $obj {
     }
 }));
 
+/// Recalculation of the routing table, which prunes unreachable peers as configured by `prune`
+/// if set.
+#[derive(Clone, Copy, Debug)]
+struct RoutingTableUpdate {
+    prune: Option<PruneConfig>,
+}
+
+impl RoutingTableUpdate {
+    /// Update doing what both updates would: pruning if either of them does, forcibly if either
+    /// of them does.
+    fn merge(self, other: Self) -> Self {
+        let prune = match (self.prune, other.prune) {
+            (Some(prune), Some(other)) if other.force() && !prune.force() => Some(other),
+            (Some(prune), _) | (None, Some(prune)) => Some(prune),
+            (None, None) => None,
+        };
+        Self { prune }
+    }
+}
+
 /// Contains information relevant to an active peer.
 struct ActivePeer {
     addr: Addr<Peer>,
@@ -117,6 +212,17 @@ struct ActivePeer {
     connection_established_time: Instant,
     /// Who started connection. Inbound (other) or Outbound (us).
     peer_type: PeerType,
+    /// Protocol version agreed on with the peer during handshake.
+    protocol_version: ProtocolVersion,
+    /// Optional features agreed on with the peer during handshake.
+    capabilities: PeerCapabilities,
+}
+
+/// Whether a full routing table can be sent to a peer, see `heavy_sync_status`.
+enum HeavySyncStatus {
+    Ready,
+    Wait,
+    PeerGone,
 }
 
 /// Actor that manages peers connections.
@@ -148,12 +254,73 @@ pub struct PeerManagerActor {
     /// Dynamic Prometheus metrics
     network_metrics: NetworkMetrics,
     edge_verifier_pool: Addr<EdgeVerifier>,
+    /// Workers computing routes of big routing graphs, `None` if routes are computed here.
+    graph_worker_pool: Option<Addr<GraphWorker>>,
     routing_table_pool: Addr<RoutingTableActor>,
     txns_since_last_block: Arc<AtomicUsize>,
     pending_incoming_connections_counter: Arc<AtomicUsize>,
     peer_counter: Arc<AtomicUsize>,
     scheduled_routing_table_update: bool,
+    /// Whether routes are being computed by `graph_worker_pool`.
+    routes_computation_in_progress: bool,
+    /// Routing table update requested while routes were being computed.
+    pending_routing_table_update: Option<RoutingTableUpdate>,
+    /// Number of routes computations in a row whose graph changed before they finished.
+    stale_routes_computations: usize,
     edge_verifier_requests_in_progress: u64,
+    /// Number of full routing tables being sent to peers.
+    heavy_syncs_in_progress: usize,
+    /// Bytes of routing gossip received from each peer.
+    edge_gossip_stats: EdgeGossipStats,
+    /// Reputation of peers based on their misbehavior.
+    peer_score: PeerScore,
+    /// Limits the number of edges processed from each peer.
+    edge_rate_limiter: EdgeRateLimiter,
+    /// Direct connections to the other validators of the epoch.
+    tier1: Tier1Connections,
+    /// Peers we always try to stay connected to.
+    anchors: AnchorPeers,
+    /// Regions of peer addresses, if the operator provided a mapping.
+    peer_regions: Option<RegionMap>,
+    /// Probes comparing which peers we and our peers can route messages to.
+    reachability_probes: ReachabilityProbes,
+    /// Peers whose edges and account announcements are rejected before verification.
+    denylist: KeyPrefixDenylist,
+    /// Address of the port mapped on our NAT gateway, advertised to peers instead of the
+    /// listening address.
+    external_addr: Option<SocketAddr>,
+    /// Addresses we are listening at, with their advertisement policy.
+    listening: Vec<ListenAddr>,
+    /// Rate limits shared by all peers, each peer is also limited by its own.
+    upload_throttle: Throttle,
+    download_throttle: Throttle,
+    /// Broadcast messages recently received from any peer, shared by the peer actors.
+    seen_messages: SeenMessages,
+    /// Routed messages recently sent to each next hop, to detect routing loops.
+    forwarded_messages: ForwardedMessages,
+    /// Transactions recently gossiped because their validator couldn't be reached.
+    gossiped_transactions: SizedCache<CryptoHash, ()>,
+    /// Store the healthiest peers are saved in.
+    store: Arc<Store>,
+    /// Healthiest peers we were connected to, dialed first on startup.
+    peer_cache: PeerCache,
+    /// Adversarial and admin requests which mutated the routing state.
+    audit_log: AuditLog,
+    /// Whether we are waiting for a connection to a cached peer before connecting to the boot
+    /// nodes and other known peers.
+    cold_start_pending: bool,
+    /// Watermark of the last routing table delta each peer sent us, sent back on reconnect.
+    sync_watermarks: SizedCache<PeerId, SyncWatermark>,
+    /// Bounded broadcast of new edges to peers pulling the edges they miss from digests.
+    edge_gossip: EdgeGossip,
+    /// Routing events streamed to hot-standby replicas, if we publish them.
+    routing_replica: Option<RoutingEventPublisher>,
+    /// Certificates for mutual TLS with pinned peers, if configured.
+    tls: Option<Arc<TlsContext>>,
+    /// Network sub-keys of the nodes we know, including ours, shared with peer actors and edge
+    /// verifiers.
+    sub_keys: Arc<RwLock<SubKeys>>,
+    clock: Clock,
 
     #[cfg(feature = "test_features")]
     adv_disable_edge_propagation: bool,
@@ -161,6 +328,9 @@ pub struct PeerManagerActor {
     adv_disable_edge_signature_verification: bool,
     #[cfg(feature = "test_features")]
     adv_disable_edge_pruning: bool,
+    /// Faults injected in the connections with these peers.
+    #[cfg(feature = "test_features")]
+    adv_peer_faults: HashMap<PeerId, PeerFaults>,
 }
 
 impl PeerManagerActor {
@@ -170,21 +340,93 @@ impl PeerManagerActor {
         client_addr: Recipient<NetworkClientMessages>,
         view_client_addr: Recipient<NetworkViewClientMessages>,
         ibf_routing_pool: Addr<RoutingTableActor>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::new_with_clock(
+            store,
+            config,
+            client_addr,
+            view_client_addr,
+            ibf_routing_pool,
+            Clock::real(),
+        )
+    }
+
+    /// Same as `new`, with the time read from `clock` by the peer manager and its peers.
+    pub fn new_with_clock(
+        store: Arc<Store>,
+        config: NetworkConfig,
+        client_addr: Recipient<NetworkClientMessages>,
+        view_client_addr: Recipient<NetworkViewClientMessages>,
+        ibf_routing_pool: Addr<RoutingTableActor>,
+        clock: Clock,
     ) -> Result<Self, Box<dyn std::error::Error>> {
         if config.max_num_peers as usize > MAX_NUM_PEERS {
             panic!("Exceeded max peer limit: {}", MAX_NUM_PEERS);
         }
+        if let Err(err) = config.routing_table.verify() {
+            panic!("Invalid routing table config: {}", err);
+        }
 
-        let peer_store = PeerStore::new(store.clone(), &config.boot_nodes)?;
+        let peer_store =
+            PeerStore::new_with_clock(store.clone(), &config.boot_nodes, clock.clone())?;
         debug!(target: "network", "Found known peers: {} (boot nodes={})", peer_store.len(), config.boot_nodes.len());
         debug!(target: "network", "Blacklist: {:?}", config.blacklist);
+        let denylist = KeyPrefixDenylist::new(config.denylisted_key_prefixes.clone());
+        let upload_throttle = Throttle::new(config.bandwidth.upload_bytes_per_sec, clock.clone());
+        let download_throttle =
+            Throttle::new(config.bandwidth.download_bytes_per_sec, clock.clone());
+
+        let failpoints = FailpointsHandle::current();
+        let edge_verifier_pool =
+            SyncArbiter::start(std::cmp::max(1, config.edge_verifier_threads), move || {
+                EdgeVerifier::new(failpoints.clone())
+            });
 
-        let edge_verifier_pool = SyncArbiter::start(4, || EdgeVerifier {});
+        let graph_worker_pool = if config.routing_threads > 0 {
+            Some(SyncArbiter::start(config.routing_threads, || GraphWorker))
+        } else {
+            None
+        };
 
         let me: PeerId = config.public_key.clone().into();
-        let routing_table = RoutingTable::new(me.clone(), store);
+        let peer_cache = PeerCache::load(&store);
+        let audit_log = AuditLog::load(store.clone());
+        let mut routing_table = RoutingTable::with_config_and_clock(
+            me.clone(),
+            store.clone(),
+            config.routing_table.clone(),
+            clock.clone(),
+        );
+        for (peer_id, peer_state) in peer_store.iter() {
+            if peer_state.status.is_banned() {
+                routing_table.ban_peer(peer_id.clone());
+            }
+        }
 
         let txns_since_last_block = Arc::new(AtomicUsize::new(0));
+        let anchors = AnchorPeers::new(config.anchor_peers.clone(), clock.now());
+        let edge_rate_limiter =
+            EdgeRateLimiter::new(config.edge_rate_limit, config.edge_rate_limit_burst)
+                .with_clock(clock.clone());
+        let peer_regions = config.peer_regions_file.as_ref().and_then(|path| {
+            RegionMap::from_file(path)
+                .map_err(|err| {
+                    warn!(target: "network", "Failed to read peer regions from {}: {}", path.display(), err)
+                })
+                .ok()
+        });
+
+        let edge_gossip = EdgeGossip::new(config.edge_gossip.clone());
+        let routing_replica =
+            config.routing_replica.publish_socket.as_ref().map(|_| RoutingEventPublisher::new());
+        let tls = match &config.tls {
+            Some(tls) => Some(Arc::new(TlsContext::new(tls)?)),
+            None => None,
+        };
+        let mut sub_keys = SubKeys::default();
+        if let Some(sub_key) = &config.sub_key {
+            sub_keys.add(sub_key.delegation.clone());
+        }
 
         Ok(PeerManagerActor {
             peer_id: me,
@@ -201,29 +443,139 @@ impl PeerManagerActor {
             pending_update_nonce_request: HashMap::new(),
             network_metrics: NetworkMetrics::new(),
             edge_verifier_pool,
+            graph_worker_pool,
             routing_table_pool: ibf_routing_pool,
             txns_since_last_block,
             pending_incoming_connections_counter: Arc::new(AtomicUsize::new(0)),
             peer_counter: Arc::new(AtomicUsize::new(0)),
             scheduled_routing_table_update: false,
+            routes_computation_in_progress: false,
+            pending_routing_table_update: None,
+            stale_routes_computations: 0,
             edge_verifier_requests_in_progress: 0,
+            heavy_syncs_in_progress: 0,
+            edge_gossip_stats: EdgeGossipStats::new(clock.clone()),
+            peer_score: PeerScore::new(clock.clone()),
+            edge_rate_limiter,
+            tier1: Tier1Connections::new(clock.clone()),
+            anchors,
+            peer_regions,
+            reachability_probes: ReachabilityProbes::new(clock.clone()),
+            denylist,
+            external_addr: None,
+            listening: vec![],
+            upload_throttle,
+            download_throttle,
+            seen_messages: SeenMessages::new(clock.clone()),
+            forwarded_messages: ForwardedMessages::new(),
+            gossiped_transactions: SizedCache::with_size(GOSSIPED_TRANSACTIONS_CACHE_SIZE),
+            store,
+            peer_cache,
+            audit_log,
+            cold_start_pending: false,
+            sync_watermarks: SizedCache::with_size(SYNC_WATERMARKS_CACHE_SIZE),
+            edge_gossip,
+            routing_replica,
+            tls,
+            sub_keys: Arc::new(RwLock::new(sub_keys)),
+            clock,
             #[cfg(feature = "test_features")]
             adv_disable_edge_propagation: false,
             #[cfg(feature = "test_features")]
             adv_disable_edge_signature_verification: false,
             #[cfg(feature = "test_features")]
             adv_disable_edge_pruning: false,
+            #[cfg(feature = "test_features")]
+            adv_peer_faults: HashMap::new(),
         })
     }
 
+    /// Recalculate the routing table and remove pruned edges. Routes of big graphs which can't be
+    /// updated incrementally are computed by `graph_worker_pool`, and the update finishes once
+    /// they are; updates requested meanwhile are applied afterwards.
     fn update_and_remove_edges(
         &mut self,
         ctx: &mut Context<PeerManagerActor>,
-        can_save_edges: bool,
-        force_pruning: bool,
-        timeout: Duration,
+        update: RoutingTableUpdate,
+    ) {
+        if self.routes_computation_in_progress {
+            self.pending_routing_table_update = Some(match self.pending_routing_table_update {
+                Some(pending) => pending.merge(update),
+                None => update,
+            });
+            return;
+        }
+        let routing_table = &self.routing_table;
+        let offloaded = self.graph_worker_pool.as_ref().and_then(|pool| {
+            if self.stale_routes_computations >= MAX_STALE_ROUTES_COMPUTATIONS {
+                return None;
+            }
+            let graph = routing_table.graph_to_recompute(MIN_GRAPH_NODES_FOR_WORKERS)?;
+            Some((pool.clone(), Arc::new(graph)))
+        });
+        let (pool, graph) = match offloaded {
+            Some(offloaded) => offloaded,
+            None => {
+                self.stale_routes_computations = 0;
+                let edges_to_remove = self.routing_table.update(update.prune);
+                self.remove_pruned_edges(ctx, edges_to_remove);
+                return;
+            }
+        };
+
+        self.routes_computation_in_progress = true;
+        let started = self.clock.now();
+        graph_worker::compute_routes(pool, graph, self.config.routing_threads)
+            .into_actor(self)
+            .map(move |res, act, ctx| {
+                act.routes_computation_in_progress = false;
+                let edges_to_remove = match res {
+                    Ok(computed) => match act.routing_table.update_with_routes(
+                        started,
+                        computed.version,
+                        computed.spf,
+                        computed.routes,
+                        update.prune,
+                    ) {
+                        Some(edges_to_remove) => {
+                            act.stale_routes_computations = 0;
+                            edges_to_remove
+                        }
+                        None => {
+                            // Compute the routes of the changed graph, along with the updates
+                            // requested meanwhile.
+                            act.stale_routes_computations += 1;
+                            let update = match act.pending_routing_table_update.take() {
+                                Some(pending) => update.merge(pending),
+                                None => update,
+                            };
+                            act.update_and_remove_edges(ctx, update);
+                            return;
+                        }
+                    },
+                    Err(err) => {
+                        error!(target: "network", "Failed to compute routes on graph workers: {}", err);
+                        act.routing_table.update(update.prune)
+                    }
+                };
+                act.remove_pruned_edges(ctx, edges_to_remove);
+                if let Some(update) = act.pending_routing_table_update.take() {
+                    act.update_and_remove_edges(ctx, update);
+                }
+            })
+            .spawn(ctx);
+    }
+
+    fn remove_pruned_edges(
+        &mut self,
+        ctx: &mut Context<PeerManagerActor>,
+        edges_to_remove: Vec<Edge>,
     ) {
-        let edges_to_remove = self.routing_table.update(can_save_edges, force_pruning, timeout);
+        if let Some(publisher) = &self.routing_replica {
+            if !edges_to_remove.is_empty() {
+                publisher.publish(RoutingEvent::RemoveEdges(edges_to_remove.clone()));
+            }
+        }
         self.routing_table_pool
             .send(RoutingTableMessages::RemoveEdges(edges_to_remove))
             .into_actor(self)
@@ -242,15 +594,169 @@ impl PeerManagerActor {
         for account in accounts.iter() {
             self.routing_table.add_account(account.clone());
         }
+        if let Some(publisher) = &self.routing_replica {
+            if !accounts.is_empty() {
+                publisher.publish(RoutingEvent::AddAccounts(accounts.clone()));
+            }
+        }
+        self.broadcast_announcements(ctx, accounts);
+    }
 
-        let new_data = SyncData { edges: Default::default(), accounts };
+    /// Send `accounts` to all active peers. Peers which negotiated
+    /// `PeerCapabilities::ANNOUNCEMENT_HOPS` also get the number of hops the announcements
+    /// traveled, when we know it.
+    fn broadcast_announcements(
+        &mut self,
+        ctx: &mut Context<PeerManagerActor>,
+        accounts: Vec<AnnounceAccount>,
+    ) {
+        if accounts.is_empty() {
+            return;
+        }
+        let mut with_hops = vec![];
+        let mut without_hops = vec![];
+        for account in accounts.iter() {
+            match self.routing_table.announcement_hops(account) {
+                Some(hops) => with_hops.push((account.clone(), hops)),
+                None => without_hops.push(account.clone()),
+            }
+        }
+        let all = SendMessage {
+            message: PeerMessage::RoutingTableSync(SyncData {
+                edges: Default::default(),
+                accounts,
+            }),
+        };
+        if with_hops.is_empty() {
+            self.broadcast_message(ctx, all);
+            return;
+        }
 
-        if !new_data.is_empty() {
-            self.broadcast_message(
-                ctx,
-                SendMessage { message: PeerMessage::RoutingTableSync(new_data) },
-            )
+        let all = Arc::new(all);
+        let with_hops = Arc::new(SendMessage { message: PeerMessage::AnnounceAccounts(with_hops) });
+        let without_hops = if without_hops.is_empty() {
+            None
+        } else {
+            Some(Arc::new(SendMessage {
+                message: PeerMessage::RoutingTableSync(SyncData {
+                    edges: Default::default(),
+                    accounts: without_hops,
+                }),
+            }))
         };
+        for active_peer in self.active_peers.values() {
+            if active_peer.capabilities.contains(PeerCapabilities::ANNOUNCEMENT_HOPS) {
+                active_peer.addr.do_send(with_hops.clone());
+                if let Some(without_hops) = &without_hops {
+                    active_peer.addr.do_send(without_hops.clone());
+                }
+            } else {
+                active_peer.addr.do_send(all.clone());
+            }
+        }
+    }
+
+    /// Validate the announcements `peer_id` sent us, with the number of hops they traveled to it
+    /// if it told, then add the valid ones to the routing table and broadcast them.
+    fn receive_announcements(
+        &mut self,
+        ctx: &mut Context<Self>,
+        peer_id: PeerId,
+        accounts: Vec<(AnnounceAccount, Option<u32>)>,
+    ) {
+        let mut hops = HashMap::new();
+        let accounts = accounts
+            .into_iter()
+            .filter_map(|(announce_account, announce_hops)| {
+                if self.routing_table.contains_account(&announce_account)
+                    || self.routing_table.is_stale_announcement(&announce_account)
+                {
+                    return None;
+                }
+                let routing_table = &self.routing_table;
+                if !self
+                    .denylist
+                    .allows(&announce_account.peer_id, |peer_id| routing_table.is_banned(peer_id))
+                {
+                    return None;
+                }
+                hops.insert(
+                    (
+                        announce_account.account_id.clone(),
+                        announce_account.peer_id.clone(),
+                        announce_account.epoch_id.clone(),
+                    ),
+                    announce_hops,
+                );
+                match self.routing_table.get_announce(&announce_account.account_id) {
+                    // Announcements from a different epoch must be newer.
+                    Some(current_announce_account)
+                        if announce_account.epoch_id != current_announce_account.epoch_id =>
+                    {
+                        Some((announce_account, Some(current_announce_account.epoch_id)))
+                    }
+                    // Unknown account, or another peer of the account in the same epoch.
+                    _ => Some((announce_account, None)),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // Ask client to validate accounts before accepting them.
+        self.view_client_addr
+            .send(NetworkViewClientMessages::AnnounceAccount(accounts))
+            .into_actor(self)
+            .then(move |response, act, ctx| {
+                match response {
+                    Ok(NetworkViewClientResponses::Ban { ban_reason }) => {
+                        act.try_ban_peer(ctx, &peer_id, ban_reason);
+                    }
+                    Ok(NetworkViewClientResponses::AnnounceAccount { accounts, stale }) => {
+                        // Client sets apart announcements which are not newer than ours. Ones
+                        // dropped for other reasons, e.g. unknown epochs, are not the peer's fault.
+                        if !stale.is_empty() {
+                            act.penalize_peer(ctx, &peer_id, Misbehavior::StaleAnnounce, stale.len() as u64);
+                        }
+                        let mut to_verify = vec![];
+                        for account in accounts.iter() {
+                            let key = (
+                                account.account_id.clone(),
+                                account.peer_id.clone(),
+                                account.epoch_id.clone(),
+                            );
+                            // Announcements traveled one more hop to reach us.
+                            let hops =
+                                hops.get(&key).copied().flatten().map(|hops| hops.saturating_add(1));
+                            let source = Some(peer_id.clone());
+                            if act.routing_table.record_announcement_origin(account, source, hops) {
+                                to_verify.push(account.clone());
+                            }
+                        }
+                        act.broadcast_accounts(ctx, accounts);
+                        for account in to_verify.iter() {
+                            act.verify_announcement(ctx, account);
+                        }
+                    }
+                    _ => {
+                        debug!(target: "network", "Received invalid account confirmation from client.");
+                    }
+                }
+                actix::fut::ready(())
+            }).spawn(ctx);
+    }
+
+    /// Ping the peer which announced `announce_account`, which traveled suspiciously many hops,
+    /// to check that the peer is there. See `RoutingTable::record_announcement_origin`.
+    fn verify_announcement(&mut self, ctx: &mut Context<Self>, announce_account: &AnnounceAccount) {
+        if announce_account.peer_id == self.peer_id {
+            return;
+        }
+        debug!(target: "network", "Verifying announcement of {} by {}, which traveled {:?} hops",
+               announce_account.account_id, announce_account.peer_id,
+               self.routing_table.announcement_hops(announce_account));
+        let nonce = self.routing_table.get_ping(announce_account.peer_id.clone());
+        self.routing_table.verifying_announcement(announce_account, nonce);
+        near_metrics::inc_counter_vec(&metrics::ACCOUNT_ANNOUNCEMENT_VERIFICATIONS, &["sent"]);
+        self.send_ping(ctx, nonce, announce_account.peer_id.clone());
     }
 
     /// Receives list of edges that were verified, in a trigger every 20ms, and adds them to
@@ -258,7 +764,7 @@ impl PeerManagerActor {
     fn broadcast_edges(&mut self, ctx: &mut Context<PeerManagerActor>) {
         let me = self.peer_id.clone();
 
-        let start = Instant::now();
+        let start = self.clock.now();
         let mut new_edges = Vec::new();
         while let Some(edge) = self.routing_table_exchange_helper.edges_to_add_receiver.pop() {
             if let Some(cur_edge) =
@@ -294,7 +800,7 @@ impl PeerManagerActor {
                 }
             }
             new_edges.push(edge);
-            if start.elapsed() >= BROAD_CAST_EDGES_MAX_WORK_ALLOWED {
+            if self.clock.elapsed(start) >= BROAD_CAST_EDGES_MAX_WORK_ALLOWED {
                 break;
             }
         }
@@ -308,10 +814,7 @@ impl PeerManagerActor {
             let condition = !self.adv_disable_edge_propagation;
 
             if condition {
-                self.broadcast_message(
-                    ctx,
-                    SendMessage { message: PeerMessage::RoutingTableSync(new_data) },
-                )
+                self.broadcast_new_edges(ctx, new_data);
             }
         };
 
@@ -324,6 +827,73 @@ impl PeerManagerActor {
         );
     }
 
+    /// Broadcast new edges to active peers. Peers which can pull edges from digests only get
+    /// them from `edge_gossip`, within its fanout and budget. If `filter_redundant_gossip` is
+    /// enabled, edges are not sent back to redundant gossipers which already sent them to us.
+    fn broadcast_new_edges(&mut self, ctx: &mut Context<PeerManagerActor>, new_data: SyncData) {
+        let pulling_peers: Vec<PeerId> = self
+            .active_peers
+            .iter()
+            .filter(|(_, active_peer)| self.edge_digests_enabled(active_peer.protocol_version))
+            .map(|(peer_id, _)| peer_id.clone())
+            .collect();
+        if !pulling_peers.is_empty() {
+            let (plan, over_budget) =
+                self.edge_gossip.plan_broadcast(&new_data.edges, &pulling_peers);
+            near_metrics::inc_counter_vec_by(
+                &metrics::EDGE_GOSSIP_EDGES,
+                &["over_budget"],
+                over_budget as u64,
+            );
+            for (peer_id, edges) in plan {
+                near_metrics::inc_counter_vec_by(
+                    &metrics::EDGE_GOSSIP_EDGES,
+                    &["pushed"],
+                    edges.len() as u64,
+                );
+                if let Some(active_peer) = self.active_peers.get(&peer_id) {
+                    active_peer.addr.do_send(SendMessage {
+                        message: PeerMessage::RoutingTableSync(SyncData {
+                            edges,
+                            accounts: new_data.accounts.clone(),
+                        }),
+                    });
+                }
+            }
+        }
+
+        if !self.config.filter_redundant_gossip && pulling_peers.is_empty() {
+            self.broadcast_message(
+                ctx,
+                SendMessage { message: PeerMessage::RoutingTableSync(new_data) },
+            );
+            return;
+        }
+
+        for (peer_id, active_peer) in self.active_peers.iter() {
+            if self.edge_digests_enabled(active_peer.protocol_version) {
+                continue;
+            }
+            let edges: Vec<Edge> = new_data
+                .edges
+                .iter()
+                .filter(|edge| {
+                    !self.config.filter_redundant_gossip
+                        || !self.edge_gossip_stats.was_received_from(peer_id, edge)
+                })
+                .cloned()
+                .collect();
+            if !edges.is_empty() {
+                active_peer.addr.do_send(SendMessage {
+                    message: PeerMessage::RoutingTableSync(SyncData {
+                        edges,
+                        accounts: new_data.accounts.clone(),
+                    }),
+                });
+            }
+        }
+    }
+
     fn num_active_peers(&self) -> usize {
         self.active_peers.len()
     }
@@ -339,18 +909,18 @@ impl PeerManagerActor {
         }
     }
 
-    #[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
     fn initialize_routing_table_exchange(
         &mut self,
         peer_id: PeerId,
         peer_type: PeerType,
         addr: Addr<Peer>,
+        hasher_kind: IbfHasherKind,
         ctx: &mut Context<Self>,
     ) {
         near_performance_metrics::actix::run_later(ctx, WAIT_FOR_SYNC_DELAY, move |act, ctx2| {
             if peer_type == PeerType::Inbound {
                 act.routing_table_pool
-                    .send(RoutingTableMessages::AddPeerIfMissing(peer_id, None))
+                    .send(RoutingTableMessages::AddPeerIfMissing(peer_id, None, hasher_kind))
                     .into_actor(act)
                     .map(move |response, act2, _ctx| match response {
                         Ok(RoutingTableMessagesResponse::AddPeerResponse { seed }) => {
@@ -362,7 +932,6 @@ impl PeerManagerActor {
             }
         });
     }
-    #[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
     fn start_routing_table_syncv2(&self, addr: Addr<Peer>, seed: u64) {
         let _ = addr.do_send(SendMessage {
             message: PeerMessage::RoutingTableSyncV2(RoutingSyncV2::Version2(RoutingVersion2 {
@@ -387,17 +956,19 @@ impl PeerManagerActor {
         peer_type: PeerType,
         addr: Addr<Peer>,
         peer_protocol_version: ProtocolVersion,
+        capabilities: PeerCapabilities,
         ctx: &mut Context<Self>,
     ) {
-        #[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
         let peer_id = full_peer_info.peer_info.id.clone();
+        let now = self.clock.now();
         debug!(target: "network", "Consolidated connection with {:?}", full_peer_info);
 
         if self.outgoing_peers.contains(&full_peer_info.peer_info.id) {
             self.outgoing_peers.remove(&full_peer_info.peer_info.id);
         }
         unwrap_or_error!(
-            self.peer_store.peer_connected(&full_peer_info.peer_info),
+            self.peer_store
+                .peer_connected(&full_peer_info.peer_info, peer_type == PeerType::Outbound),
             "Failed to save peer data"
         );
 
@@ -411,6 +982,10 @@ impl PeerManagerActor {
             full_peer_info.edge_info.signature.clone(),
         );
 
+        self.routing_table.set_peer_shards(
+            target_peer_id.clone(),
+            full_peer_info.chain_info.tracked_shards.clone(),
+        );
         self.active_peers.insert(
             target_peer_id.clone(),
             ActivePeer {
@@ -418,83 +993,409 @@ impl PeerManagerActor {
                 full_peer_info,
                 sent_bytes_per_sec: 0,
                 received_bytes_per_sec: 0,
-                last_time_peer_requested: Instant::now(),
-                last_time_received_message: Instant::now(),
-                connection_established_time: Instant::now(),
+                last_time_peer_requested: now,
+                last_time_received_message: now,
+                connection_established_time: now,
                 peer_type,
+                protocol_version: peer_protocol_version,
+                capabilities,
             },
         );
+        #[cfg(feature = "test_features")]
+        if let Some(faults) = self.adv_peer_faults.get(&target_peer_id) {
+            addr.do_send(faults.clone());
+        }
 
         self.add_verified_edges_to_routing_table(ctx, vec![new_edge.clone()]);
+        // One connection is enough to learn about the rest of the network.
+        self.finish_cold_start(ctx);
 
+        if self.sub_keys_enabled(peer_protocol_version) {
+            let delegations = self.sub_keys.read().unwrap().delegations();
+            if !delegations.is_empty() {
+                addr.do_send(SendMessage { message: PeerMessage::SubKeyDelegations(delegations) });
+            }
+        }
+        if capabilities.contains(PeerCapabilities::PROXY_ANNOUNCEMENT) {
+            for announcement in self.routing_table.get_proxy_announcements() {
+                addr.do_send(SendMessage { message: PeerMessage::ProxyAnnouncement(announcement) });
+            }
+        }
+        if self.is_relay(&target_peer_id) {
+            self.announce_proxy();
+        }
+
+        if self.delta_sync_enabled(peer_protocol_version) {
+            // Both sides ask for the edges changed since they last synced. The other side falls
+            // back to IBF or full sync if our watermark is stale.
+            let watermark = self.sync_watermarks.cache_get(&target_peer_id).cloned();
+            self.send_sync(peer_type, addr.clone(), ctx, target_peer_id, new_edge, Vec::new());
+            near_performance_metrics::actix::run_later(ctx, WAIT_FOR_SYNC_DELAY, move |_, _| {
+                let _ = addr.do_send(SendMessage {
+                    message: PeerMessage::RoutingTableDeltaRequest(watermark),
+                });
+            });
+        } else if self.ibf_sync_enabled(capabilities) {
+            self.initialize_routing_table_exchange(
+                peer_id,
+                peer_type,
+                addr.clone(),
+                IbfHasherKind::negotiate(peer_protocol_version),
+                ctx,
+            );
+            self.send_sync(peer_type, addr, ctx, target_peer_id, new_edge, Vec::new());
+        } else {
+            self.start_full_sync(ctx, peer_type, addr, target_peer_id, new_edge);
+        }
+    }
+
+    /// Whether to exchange routing tables with a peer using IBF, based on the capabilities
+    /// negotiated in the handshake. Otherwise all edges are sent on connection.
+    fn ibf_sync_enabled(&self, capabilities: PeerCapabilities) -> bool {
+        capabilities.contains(PeerCapabilities::IBF_SYNC)
+    }
+
+    /// Optional features we advertise to peers in the handshake.
+    fn capabilities(&self) -> PeerCapabilities {
+        let mut capabilities = PeerCapabilities::empty();
+        if self.config.ibf_sync_enabled {
+            capabilities = capabilities.with(PeerCapabilities::IBF_SYNC);
+        }
+        if self.config.tier1_enabled {
+            capabilities = capabilities.with(PeerCapabilities::TIER1);
+        }
+        if self.config.compression.enabled {
+            capabilities = capabilities.with(PeerCapabilities::COMPRESSION);
+        }
+        capabilities
+            .with(PeerCapabilities::ANNOUNCEMENT_HOPS)
+            .with(PeerCapabilities::PROXY_ANNOUNCEMENT)
+    }
+
+    /// Whether the peer supports routing table deltas, in which case we don't exchange all
+    /// edges on every reconnect.
+    fn delta_sync_enabled(&self, protocol_version: ProtocolVersion) -> bool {
+        checked_feature!("protocol_feature_routing_delta_sync", RoutingDeltaSync, protocol_version)
+    }
+
+    /// Whether the peer pulls the edges it misses from our digests, in which case new edges are
+    /// only pushed to it within the bounds of `edge_gossip`.
+    fn edge_digests_enabled(&self, protocol_version: ProtocolVersion) -> bool {
         checked_feature!(
-            "protocol_feature_routing_exchange_algorithm",
-            RoutingExchangeAlgorithm,
-            peer_protocol_version,
+            "protocol_feature_edge_gossip_digests",
+            EdgeGossipDigests,
+            protocol_version
+        )
+    }
+
+    /// Whether the peer exchanges network sub-keys, and can verify edges signed with them.
+    fn sub_keys_enabled(&self, protocol_version: ProtocolVersion) -> bool {
+        checked_feature!("protocol_feature_network_sub_keys", NetworkSubKeys, protocol_version)
+    }
+
+    /// Key to sign our edges with a peer with: our sub-key if we have one and the peer supports
+    /// sub-keys, otherwise the node key. `None` if the protocol version of the peer isn't known.
+    fn edge_secret_key(&self, protocol_version: Option<ProtocolVersion>) -> &SecretKey {
+        match &self.config.sub_key {
+            Some(sub_key)
+                if protocol_version.map_or(false, |version| self.sub_keys_enabled(version)) =>
             {
-                self.initialize_routing_table_exchange(peer_id, peer_type, addr.clone(), ctx);
-                self.send_sync(peer_type, addr, ctx, target_peer_id.clone(), new_edge, Vec::new());
-                return;
+                &sub_key.secret_key
             }
-        );
+            _ => &self.config.secret_key,
+        }
+    }
+
+    /// Key to sign our edges with `peer_id` with, see `edge_secret_key`.
+    fn active_peer_edge_secret_key(&self, peer_id: &PeerId) -> &SecretKey {
+        self.edge_secret_key(
+            self.active_peers.get(peer_id).map(|active_peer| active_peer.protocol_version),
+        )
+    }
+
+    /// Add verified sub-key delegations, and send the new ones to the active peers which support
+    /// sub-keys, except `except` which sent them to us.
+    fn add_sub_key_delegations(
+        &mut self,
+        delegations: Vec<SubKeyDelegation>,
+        except: Option<&PeerId>,
+    ) {
+        let new: Vec<SubKeyDelegation> = {
+            let mut sub_keys = self.sub_keys.write().unwrap();
+            delegations.into_iter().filter(|delegation| sub_keys.add(delegation.clone())).collect()
+        };
+        if new.is_empty() {
+            return;
+        }
+        let msg = Arc::new(SendMessage { message: PeerMessage::SubKeyDelegations(new) });
+        for (peer_id, active_peer) in self.active_peers.iter() {
+            if Some(peer_id) != except && self.sub_keys_enabled(active_peer.protocol_version) {
+                active_peer.addr.do_send(msg.clone());
+            }
+        }
+    }
+
+    /// Whether we can send a full routing table to `peer_id` now.
+    ///
+    /// Sending it is expensive, so we only do it for peers which signed the edge with us (they are
+    /// active peers) at least `heavy_sync_min_connection_age` ago, and limit the number of such
+    /// syncs in progress, so cheap connections can't be used to amplify the load.
+    fn heavy_sync_status(&self, peer_id: &PeerId) -> HeavySyncStatus {
+        match self.active_peers.get(peer_id) {
+            None => HeavySyncStatus::PeerGone,
+            Some(active_peer)
+                if self.clock.elapsed(active_peer.connection_established_time)
+                    < self.config.heavy_sync_min_connection_age
+                    || self.heavy_syncs_in_progress >= self.config.max_concurrent_heavy_syncs =>
+            {
+                HeavySyncStatus::Wait
+            }
+            Some(_) => HeavySyncStatus::Ready,
+        }
+    }
+
+    /// Send all known edges to a newly connected peer, once `heavy_sync_status` allows it.
+    fn start_full_sync(
+        &mut self,
+        ctx: &mut Context<PeerManagerActor>,
+        peer_type: PeerType,
+        addr: Addr<Peer>,
+        target_peer_id: PeerId,
+        new_edge: Edge,
+    ) {
         near_performance_metrics::actix::run_later(ctx, WAIT_FOR_SYNC_DELAY, move |act, ctx2| {
+            match act.heavy_sync_status(&target_peer_id) {
+                HeavySyncStatus::PeerGone => return,
+                HeavySyncStatus::Wait => {
+                    near_metrics::inc_counter(&metrics::HEAVY_SYNCS_DEFERRED);
+                    act.start_full_sync(ctx2, peer_type, addr, target_peer_id, new_edge);
+                    return;
+                }
+                HeavySyncStatus::Ready => {}
+            }
+            act.heavy_syncs_in_progress += 1;
             act.routing_table_pool
-                .send(RoutingTableMessages::RequestRoutingTable)
+                .send(RoutingTableMessages::RequestRoutingTablePage {
+                    after: None,
+                    limit: ROUTING_TABLE_PAGE_SIZE,
+                })
                 .into_actor(act)
                 .map(move |response, act2, ctx3| match response {
-                    Ok(RoutingTableMessagesResponse::RequestRoutingTableResponse {
-                        edges_info: routing_table,
+                    Ok(RoutingTableMessagesResponse::RequestRoutingTablePageResponse {
+                        edges,
+                        next,
                     }) => {
                         act2.send_sync(
                             peer_type,
-                            addr,
+                            addr.clone(),
                             ctx3,
                             target_peer_id.clone(),
                             new_edge,
-                            routing_table,
+                            edges,
+                        );
+                        // The other pages follow the first one, which waits for the peer to be
+                        // ready.
+                        near_performance_metrics::actix::run_later(
+                            ctx3,
+                            WAIT_FOR_SYNC_DELAY,
+                            move |act, ctx| act.send_sync_pages(ctx, addr, next),
                         );
                     }
-                    _ => error!(target: "network", "expected AddIbfSetResponse"),
+                    _ => {
+                        act2.heavy_syncs_in_progress -= 1;
+                        error!(target: "network", "expected RequestRoutingTablePageResponse");
+                    }
                 })
                 .spawn(ctx2);
         });
     }
 
-    fn send_sync(
+    /// Send all known edges to a peer whose routing sync watermark is stale, once
+    /// `heavy_sync_status` allows it.
+    fn send_all_edges(
         &mut self,
-        peer_type: PeerType,
-        addr: Addr<Peer>,
         ctx: &mut Context<PeerManagerActor>,
-        target_peer_id: PeerId,
-        new_edge: Edge,
-        known_edges: Vec<Edge>,
+        peer_id: PeerId,
+        addr: Addr<Peer>,
     ) {
-        let known_accounts = self.routing_table.get_announce_accounts();
-
-        // Start syncing network point of view. Wait until both parties are connected before start
-        // sending messages.
-
-        near_performance_metrics::actix::run_later(ctx, WAIT_FOR_SYNC_DELAY, move |act, ctx| {
-            let _ = addr.do_send(SendMessage {
-                message: PeerMessage::RoutingTableSync(SyncData {
-                    edges: known_edges,
-                    accounts: known_accounts,
-                }),
-            });
-
-            // Ask for peers list on connection.
-            let _ = addr.do_send(SendMessage { message: PeerMessage::PeersRequest });
-            if let Some(active_peer) = act.active_peers.get_mut(&target_peer_id) {
-                active_peer.last_time_peer_requested = Instant::now();
-            }
-
-            if peer_type == PeerType::Outbound {
-                // Only broadcast new message from the outbound endpoint.
-                // Wait a time out before broadcasting this new edge to let the other party finish handshake.
-                act.broadcast_message(
+        match self.heavy_sync_status(&peer_id) {
+            HeavySyncStatus::PeerGone => return,
+            HeavySyncStatus::Wait => {
+                near_metrics::inc_counter(&metrics::HEAVY_SYNCS_DEFERRED);
+                near_performance_metrics::actix::run_later(
                     ctx,
-                    SendMessage {
-                        message: PeerMessage::RoutingTableSync(SyncData::edge(new_edge)),
+                    WAIT_FOR_SYNC_DELAY,
+                    move |act, ctx| act.send_all_edges(ctx, peer_id, addr),
+                );
+                return;
+            }
+            HeavySyncStatus::Ready => {}
+        }
+        self.heavy_syncs_in_progress += 1;
+        self.routing_table_pool
+            .send(RoutingTableMessages::RequestRoutingTablePage {
+                after: None,
+                limit: ROUTING_TABLE_PAGE_SIZE,
+            })
+            .into_actor(self)
+            .map(move |response, act, ctx| match response {
+                Ok(RoutingTableMessagesResponse::RequestRoutingTablePageResponse {
+                    edges,
+                    next,
+                }) => act.send_sync_page(ctx, addr, edges, next),
+                _ => {
+                    act.heavy_syncs_in_progress -= 1;
+                    error!(target: "network", "expected RequestRoutingTablePageResponse");
+                }
+            })
+            .spawn(ctx);
+    }
+
+    /// Answer a peer asking for the edges changed since `watermark`. If it is stale, the peer
+    /// gets our current watermark and all our edges, through IBF if possible.
+    fn process_delta_sync_request(
+        &mut self,
+        ctx: &mut Context<PeerManagerActor>,
+        peer_id: PeerId,
+        watermark: Option<SyncWatermark>,
+    ) {
+        let (addr, peer_type, protocol_version, capabilities) =
+            match self.active_peers.get(&peer_id) {
+                Some(active_peer) => (
+                    active_peer.addr.clone(),
+                    active_peer.peer_type,
+                    active_peer.protocol_version,
+                    active_peer.capabilities,
+                ),
+                None => return,
+            };
+        self.routing_table_pool
+            .send(RoutingTableMessages::RequestDelta(watermark))
+            .into_actor(self)
+            .map(move |response, act, ctx| match response {
+                Ok(RoutingTableMessagesResponse::RequestDeltaResponse { watermark, edges }) => {
+                    let stale = edges.is_none();
+                    near_metrics::inc_counter_vec(
+                        &metrics::ROUTING_DELTA_SYNCS,
+                        &[if stale { "stale" } else { "delta" }],
+                    );
+                    let _ = addr.do_send(SendMessage {
+                        message: PeerMessage::RoutingTableDelta(RoutingTableDelta {
+                            watermark,
+                            edges: edges.unwrap_or_default(),
+                        }),
+                    });
+                    if !stale {
+                        return;
+                    }
+                    debug!(target: "network", "Stale routing sync watermark from {}, sending all edges", peer_id);
+                    if act.ibf_sync_enabled(capabilities) && peer_type == PeerType::Inbound {
+                        act.initialize_routing_table_exchange(
+                            peer_id,
+                            peer_type,
+                            addr,
+                            IbfHasherKind::negotiate(protocol_version),
+                            ctx,
+                        );
+                    } else {
+                        act.send_all_edges(ctx, peer_id, addr);
+                    }
+                }
+                _ => error!(target: "network", "expected RequestDeltaResponse"),
+            })
+            .spawn(ctx);
+    }
+
+    /// Send the known edges starting after `after` to the peer, one page at a time, so the
+    /// whole routing table is never copied at once.
+    fn send_sync_pages(
+        &mut self,
+        ctx: &mut Context<PeerManagerActor>,
+        addr: Addr<Peer>,
+        after: Option<(PeerId, PeerId)>,
+    ) {
+        if after.is_none() || !addr.connected() {
+            self.heavy_syncs_in_progress -= 1;
+            return;
+        }
+        self.routing_table_pool
+            .send(RoutingTableMessages::RequestRoutingTablePage {
+                after,
+                limit: ROUTING_TABLE_PAGE_SIZE,
+            })
+            .into_actor(self)
+            .map(move |response, act, ctx| match response {
+                Ok(RoutingTableMessagesResponse::RequestRoutingTablePageResponse {
+                    edges,
+                    next,
+                }) => act.send_sync_page(ctx, addr, edges, next),
+                _ => {
+                    act.heavy_syncs_in_progress -= 1;
+                    error!(target: "network", "expected RequestRoutingTablePageResponse");
+                }
+            })
+            .spawn(ctx);
+    }
+
+    /// Send a page of edges to the peer, and the next pages once the peer actor encoded it. The
+    /// sync is in `heavy_syncs_in_progress` until the last page is encoded, not only while the
+    /// routing table actor reads it.
+    fn send_sync_page(
+        &mut self,
+        ctx: &mut Context<PeerManagerActor>,
+        addr: Addr<Peer>,
+        edges: Vec<Edge>,
+        next: Option<(PeerId, PeerId)>,
+    ) {
+        addr.send(SendMessage {
+            message: PeerMessage::RoutingTableSync(SyncData {
+                edges,
+                accounts: Default::default(),
+            }),
+        })
+        .into_actor(self)
+        .map(move |result, act, ctx| match result {
+            Ok(()) => act.send_sync_pages(ctx, addr, next),
+            Err(_) => act.heavy_syncs_in_progress -= 1,
+        })
+        .spawn(ctx);
+    }
+
+    fn send_sync(
+        &mut self,
+        peer_type: PeerType,
+        addr: Addr<Peer>,
+        ctx: &mut Context<PeerManagerActor>,
+        target_peer_id: PeerId,
+        new_edge: Edge,
+        known_edges: Vec<Edge>,
+    ) {
+        let known_accounts = self.routing_table.get_validator_announce_accounts();
+
+        // Start syncing network point of view. Wait until both parties are connected before start
+        // sending messages.
+
+        near_performance_metrics::actix::run_later(ctx, WAIT_FOR_SYNC_DELAY, move |act, ctx| {
+            let _ = addr.do_send(SendMessage {
+                message: PeerMessage::RoutingTableSync(SyncData {
+                    edges: known_edges,
+                    accounts: known_accounts,
+                }),
+            });
+
+            // Ask for peers list on connection.
+            let _ = addr.do_send(SendMessage { message: PeerMessage::PeersRequest });
+            if let Some(active_peer) = act.active_peers.get_mut(&target_peer_id) {
+                active_peer.last_time_peer_requested = act.clock.now();
+            }
+
+            if peer_type == PeerType::Outbound {
+                // Only broadcast new message from the outbound endpoint.
+                // Wait a time out before broadcasting this new edge to let the other party finish handshake.
+                act.broadcast_message(
+                    ctx,
+                    SendMessage {
+                        message: PeerMessage::RoutingTableSync(SyncData::edge(new_edge)),
                     },
                 );
             }
@@ -521,9 +1422,11 @@ impl PeerManagerActor {
 
         // If the last edge we have with this peer represent a connection addition, create the edge
         // update that represents the connection removal.
+        let secret_key = self.active_peer_edge_secret_key(peer_id).clone();
         self.active_peers.remove(&peer_id);
+        self.edge_gossip_stats.remove_peer(&peer_id);
+        self.routing_table.remove_peer_shards(&peer_id);
 
-        #[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
         self.routing_table_pool
             .send(RoutingTableMessages::RemovePeer(peer_id.clone()))
             .into_actor(self)
@@ -532,7 +1435,7 @@ impl PeerManagerActor {
 
         if let Some(edge) = self.routing_table.get_edge(self.peer_id.clone(), peer_id.clone()) {
             if edge.edge_type() == EdgeType::Added {
-                let edge_update = edge.remove_edge(self.peer_id.clone(), &self.config.secret_key);
+                let edge_update = edge.remove_edge(self.peer_id.clone(), &secret_key);
                 self.add_verified_edges_to_routing_table(ctx, vec![edge_update.clone()]);
                 self.broadcast_message(
                     ctx,
@@ -544,6 +1447,35 @@ impl PeerManagerActor {
         }
     }
 
+    /// Close the connection with the active peer `peer_info.id`, which connected again from
+    /// `peer_info.addr`. Its routing state and peer store entry are kept for the new connection:
+    /// the edge of the new connection replaces the edge of the stale one instead of the peer
+    /// being removed from the graph, and its quality history carries over to the new address.
+    fn migrate_connection(&mut self, ctx: &mut Context<Self>, peer_info: &PeerInfo) {
+        let active_peer = match self.active_peers.remove(&peer_info.id) {
+            Some(active_peer) => active_peer,
+            None => return,
+        };
+        info!(target: "network", "Peer {} moved from {:?} to {:?}, replacing its connection", peer_info.id, active_peer.full_peer_info.peer_info.addr, peer_info.addr);
+        near_metrics::inc_counter(&metrics::PEER_CONNECTION_MIGRATIONS);
+
+        let height = active_peer.full_peer_info.chain_info.height;
+        let rtt_ms = self.routing_table.peer_latency(&peer_info.id);
+        self.peer_store.update_peer_quality(&peer_info.id, rtt_ms, height);
+        unwrap_or_error!(
+            self.peer_store.peer_disconnected(&peer_info.id),
+            "Failed to save peer data"
+        );
+        self.edge_gossip_stats.remove_peer(&peer_info.id);
+        self.routing_table.remove_peer_shards(&peer_info.id);
+        self.routing_table_pool
+            .send(RoutingTableMessages::RemovePeer(peer_info.id.clone()))
+            .into_actor(self)
+            .map(|_, _, _| ())
+            .spawn(ctx);
+        active_peer.addr.do_send(PeerManagerRequest::ConnectionMigrated);
+    }
+
     /// Remove a peer from the active peer set. If the peer doesn't belong to the active peer set
     /// data from ongoing connection established is removed.
     fn unregister_peer(
@@ -557,10 +1489,19 @@ impl PeerManagerActor {
         // If this is an unconsolidated peer because failed / connected inbound, just delete it.
         if peer_type == PeerType::Outbound && self.outgoing_peers.contains(&peer_id) {
             self.outgoing_peers.remove(&peer_id);
+            unwrap_or_error!(
+                self.peer_store.peer_handshake_failed(&peer_id),
+                "Failed to save peer data"
+            );
             return;
         }
 
         if remove_from_peer_store {
+            if let Some(active_peer) = self.active_peers.get(&peer_id) {
+                let height = active_peer.full_peer_info.chain_info.height;
+                let rtt_ms = self.routing_table.peer_latency(&peer_id);
+                self.peer_store.update_peer_quality(&peer_id, rtt_ms, height);
+            }
             self.remove_active_peer(ctx, &peer_id, Some(peer_type));
             unwrap_or_error!(
                 self.peer_store.peer_disconnected(&peer_id),
@@ -576,6 +1517,14 @@ impl PeerManagerActor {
         warn!(target: "network", "Banning peer {:?} for {:?}", peer_id, ban_reason);
         self.remove_active_peer(ctx, peer_id, None);
         unwrap_or_error!(self.peer_store.peer_ban(peer_id, ban_reason), "Failed to save peer data");
+
+        // Stop routing through the banned peer without waiting for its edges to be removed.
+        let edges = self.routing_table.ban_peer(peer_id.clone());
+        self.routing_table_pool
+            .send(RoutingTableMessages::RemoveEdges(edges))
+            .into_actor(self)
+            .map(|_, _, _| ())
+            .spawn(ctx);
     }
 
     /// Ban peer. Stop peer instance if it is still active,
@@ -596,44 +1545,77 @@ impl PeerManagerActor {
         }
     }
 
-    /// Connects peer with given TcpStream and optional information if it's outbound.
+    /// Lower the score of `peer_id`. Peers with a low score are avoided when routing messages,
+    /// and banned if it gets even lower.
+    fn penalize_peer(
+        &mut self,
+        ctx: &mut Context<Self>,
+        peer_id: &PeerId,
+        misbehavior: Misbehavior,
+        count: u64,
+    ) {
+        near_metrics::inc_counter_by(&metrics::PEER_MISBEHAVIOR_PENALTIES, count);
+        let score = self.peer_score.penalize(peer_id, misbehavior, count);
+        debug!(target: "network", "Peer {} misbehaved {} times: {:?}. Score: {}", peer_id, count, misbehavior, score);
+        if score <= BAN_PEER_SCORE {
+            self.try_ban_peer(ctx, peer_id, misbehavior.ban_reason());
+        } else if score < DEPRIORITIZE_PEER_SCORE {
+            self.routing_table.deprioritize_peer(peer_id.clone());
+        }
+    }
+
+    /// Connects peer with given stream and optional information if it's outbound.
     /// This might fail if the other peers drop listener at its endpoint while establishing connection.
     fn try_connect_peer(
         &mut self,
         recipient: Addr<Self>,
-        stream: TcpStream,
+        stream: PeerStream,
         peer_type: PeerType,
         peer_info: Option<PeerInfo>,
         edge_info: Option<EdgeInfo>,
+        tls_peer_id: Option<PeerId>,
     ) {
         let peer_id = self.peer_id.clone();
         let account_id = self.config.account_id.clone();
-        let server_addr = self.config.addr;
         let handshake_timeout = self.config.handshake_timeout;
         let client_addr = self.client_addr.clone();
         let view_client_addr = self.view_client_addr.clone();
 
-        let server_addr = match server_addr {
-            Some(server_addr) => server_addr,
-            None => match stream.local_addr() {
-                Ok(server_addr) => server_addr,
-                _ => {
-                    warn!(target: "network", "Failed establishing connection with {:?}", peer_info);
-                    return;
-                }
-            },
-        };
-
         let remote_addr = match stream.peer_addr() {
-            Ok(remote_addr) => remote_addr,
+            Ok(remote_addr) => canonical_addr(remote_addr),
             _ => {
                 warn!(target: "network", "Failed establishing connection with {:?}", peer_info);
                 return;
             }
         };
 
+        let server_addr = if self.config.listen_addrs().is_empty() {
+            match stream.local_addr() {
+                Ok(server_addr) => Some(server_addr),
+                _ => {
+                    warn!(target: "network", "Failed establishing connection with {:?}", peer_info);
+                    return;
+                }
+            }
+        } else {
+            self.advertised_addr(peer_info.as_ref(), &remote_addr)
+        };
+
         let network_metrics = self.network_metrics.clone();
         let txns_since_last_block = Arc::clone(&self.txns_since_last_block);
+        let clock = self.clock.clone();
+        let upload_throttle =
+            Throttle::new(self.config.bandwidth.peer_upload_bytes_per_sec, clock.clone())
+                .and(&self.upload_throttle);
+        let download_throttle =
+            Throttle::new(self.config.bandwidth.peer_download_bytes_per_sec, clock.clone())
+                .and(&self.download_throttle);
+        let seen_messages = self.seen_messages.clone();
+        let send_queue_config = self.config.send_queue.clone();
+        let capabilities = self.capabilities();
+        let compression = self.config.compression.clone();
+        let sub_key_delegation = self.config.sub_key.as_ref().map(|key| key.delegation.clone());
+        let sub_keys = self.sub_keys.clone();
 
         // Start every peer actor on separate thread.
         let arbiter = Arbiter::new();
@@ -653,12 +1635,30 @@ impl PeerManagerActor {
                             future::ready(false)
                         }
                     })
-                    .map(Result::unwrap),
+                    .map(Result::unwrap)
+                    .then(move |msg| {
+                        // Delaying the next read slows down the peer through TCP flow control.
+                        let delay = match &msg {
+                            Ok(bytes) => download_throttle.consume(bytes.len()),
+                            Err(_) => Duration::ZERO,
+                        };
+                        let sleep = if delay.is_zero() { None } else { Some(clock.sleep(delay)) };
+                        async move {
+                            if let Some(sleep) = sleep {
+                                near_metrics::inc_counter_vec(
+                                    &metrics::PEER_BANDWIDTH_THROTTLED,
+                                    &["download"],
+                                );
+                                sleep.await;
+                            }
+                            msg
+                        }
+                    }),
                 ctx,
             );
 
             Peer::new(
-                PeerInfo { id: peer_id, addr: Some(server_addr), account_id },
+                PeerInfo { id: peer_id, addr: server_addr, account_id },
                 remote_addr,
                 peer_info,
                 peer_type,
@@ -671,10 +1671,51 @@ impl PeerManagerActor {
                 network_metrics,
                 txns_since_last_block,
                 peer_counter,
+                upload_throttle,
+                seen_messages,
+                send_queue_config,
+                capabilities,
+                compression,
+                tls_peer_id,
+                clock,
+                sub_key_delegation,
+                sub_keys,
             )
         });
     }
 
+    /// Start TLS on an inbound connection if it is configured and the peer starts it.
+    fn accept_inbound(&mut self, ctx: &mut Context<Self>, stream: TcpStream) {
+        let tls = match self.tls.clone() {
+            Some(tls) => tls,
+            None => {
+                let stream = PeerStream::Tcp(stream);
+                self.try_connect_peer(ctx.address(), stream, PeerType::Inbound, None, None, None);
+                return;
+            }
+        };
+        let handshake_timeout = self.config.handshake_timeout;
+        async move { tokio::time::timeout(handshake_timeout, tls.accept(stream)).await }
+            .into_actor(self)
+            .map(|res, act, ctx| match res {
+                Ok(Ok((stream, tls_peer_id))) => act.try_connect_peer(
+                    ctx.address(),
+                    stream,
+                    PeerType::Inbound,
+                    None,
+                    None,
+                    tls_peer_id,
+                ),
+                Ok(Err(err)) => {
+                    debug!(target: "network", "TLS handshake with inbound peer failed: {}", err)
+                }
+                Err(err) => {
+                    debug!(target: "network", "TLS handshake with inbound peer failed: {}", err)
+                }
+            })
+            .spawn(ctx);
+    }
+
     fn num_active_outgoing_peers(&self) -> usize {
         self.active_peers
             .values()
@@ -694,7 +1735,8 @@ impl PeerManagerActor {
     /// (the number of outgoing connections is less than `minimum_outbound_peers`
     ///     and the total connections is less than `max_num_peers`)
     fn is_outbound_bootstrap_needed(&self) -> bool {
-        let total_connections = self.active_peers.len() + self.outgoing_peers.len();
+        let total_connections =
+            self.active_peers.len() + self.outgoing_peers.len() + self.anchors.num_disconnected();
         let potential_outgoing_connections =
             self.num_active_outgoing_peers() + self.outgoing_peers.len();
 
@@ -704,8 +1746,67 @@ impl PeerManagerActor {
             && !self.config.outbound_disabled
     }
 
+    /// Whether there is room for another inbound connection. A slot is reserved for every anchor
+    /// peer which isn't connected.
     fn is_inbound_allowed(&self) -> bool {
-        self.active_peers.len() + self.outgoing_peers.len() < self.config.max_num_peers as usize
+        self.active_peers.len() + self.outgoing_peers.len() + self.anchors.num_disconnected()
+            < self.config.max_num_peers as usize
+    }
+
+    /// Whether `peer_info` is a whitelisted node, matched by peer id and IP address.
+    fn is_peer_whitelisted(&self, peer_info: &PeerInfo) -> bool {
+        let ip = match peer_info.addr {
+            Some(addr) => canonical_addr(addr).ip(),
+            None => return false,
+        };
+        self.config.whitelist_nodes.iter().any(|node| {
+            node.id == peer_info.id
+                && node.addr.map_or(false, |addr| canonical_addr(addr).ip() == ip)
+        })
+    }
+
+    /// Whether `addr` has the IP address of a whitelisted node. Used before the handshake, when
+    /// the peer id isn't known yet.
+    fn is_ip_whitelisted(&self, addr: &SocketAddr) -> bool {
+        let ip = canonical_addr(*addr).ip();
+        self.config
+            .whitelist_nodes
+            .iter()
+            .any(|node| node.addr.map_or(false, |addr| canonical_addr(addr).ip() == ip))
+    }
+
+    /// Address advertised in the handshake with the peer at `remote_addr`, `peer_info` being
+    /// known for outbound connections only, see `NetworkConfig::advertised_addr`.
+    ///
+    /// The handshake only carries the port: peers combine it with the IP they see us at.
+    fn advertised_addr(
+        &self,
+        peer_info: Option<&PeerInfo>,
+        remote_addr: &SocketAddr,
+    ) -> Option<SocketAddr> {
+        let whitelisted = match peer_info {
+            Some(peer_info) => self.is_peer_whitelisted(peer_info),
+            None => self.is_ip_whitelisted(remote_addr),
+        };
+        self.config.advertised_addr(whitelisted, self.external_addr)
+    }
+
+    /// Whether we have `max_inbound_peers_per_subnet` inbound peers in the subnet of `addr`
+    /// already. Loopback addresses aren't limited, local networks run all nodes on one host.
+    fn is_inbound_subnet_full(&self, addr: &SocketAddr) -> bool {
+        let limit = self.config.max_inbound_peers_per_subnet as usize;
+        let ip = canonical_addr(*addr).ip();
+        if limit == 0 || ip.is_loopback() {
+            return false;
+        }
+        let subnet = subnet_of(ip);
+        self.active_peers
+            .values()
+            .filter(|active_peer| active_peer.peer_type == PeerType::Inbound)
+            .filter_map(|active_peer| active_peer.full_peer_info.peer_info.addr)
+            .filter(|peer_addr| subnet_of(peer_addr.ip()) == subnet)
+            .count()
+            >= limit
     }
 
     /// Returns single random peer with close to the highest height
@@ -744,17 +1845,53 @@ impl PeerManagerActor {
 
     /// Get a random peer we are not connected to from the known list.
     fn sample_random_peer(&self, ignore_fn: impl Fn(&KnownPeerState) -> bool) -> Option<PeerInfo> {
-        let unconnected_peers = self.peer_store.unconnected_peers(ignore_fn);
-        unconnected_peers.choose(&mut rand::thread_rng()).cloned()
+        let ip_family = self.config.ip_family;
+        let peer_hosts = &self.config.peer_hosts;
+        let unconnected_peers = self.peer_store.unconnected_peers(|peer_state| {
+            ignore_fn(peer_state)
+                || match peer_state.peer_info.addr {
+                    Some(addr) => !ip_family.allows(&addr),
+                    None => !peer_hosts.contains_key(&peer_state.peer_info.id),
+                }
+        });
+
+        // Prefer peers in subnets we have no connection to, so a single operator can't take all
+        // our outbound connections.
+        let connected_subnets: HashSet<_> = self
+            .active_peers
+            .values()
+            .filter_map(|active_peer| active_peer.full_peer_info.peer_info.addr)
+            .chain(self.outgoing_peers.iter().filter_map(|peer_id| {
+                self.peer_store.get(peer_id).and_then(|state| state.peer_info.addr)
+            }))
+            .map(|addr| subnet_of(addr.ip()))
+            .collect();
+        let (diverse_peers, other_peers): (Vec<_>, Vec<_>) =
+            unconnected_peers.into_iter().partition(|peer_info| {
+                peer_info
+                    .addr
+                    .map_or(true, |addr| !connected_subnets.contains(&subnet_of(addr.ip())))
+            });
+        let candidates = if diverse_peers.is_empty() { other_peers } else { diverse_peers };
+        // Prefer peers which were reliable before, but still try the others.
+        let peer_store = &self.peer_store;
+        candidates
+            .choose_weighted(&mut rand::thread_rng(), |peer_info| {
+                peer_store.reliability(&peer_info.id)
+            })
+            .ok()
+            .cloned()
     }
 
     /// Query current peers for more peers.
     fn query_active_peers_for_more_peers(&mut self, ctx: &mut Context<Self>) {
         let mut requests = futures::stream::FuturesUnordered::new();
         let msg = SendMessage { message: PeerMessage::PeersRequest };
+        let now = self.clock.now();
         for (_, active_peer) in self.active_peers.iter_mut() {
-            if active_peer.last_time_peer_requested.elapsed() > REQUEST_PEERS_SECS {
-                active_peer.last_time_peer_requested = Instant::now();
+            if Time::duration_since(now, active_peer.last_time_peer_requested) > REQUEST_PEERS_SECS
+            {
+                active_peer.last_time_peer_requested = now;
                 requests.push(active_peer.addr.send(msg.clone()));
             }
         }
@@ -774,6 +1911,11 @@ impl PeerManagerActor {
         edges: Vec<Edge>,
     ) -> bool {
         let ProcessEdgeResult { new_edge, edges } = self.routing_table.process_edges(edges);
+        if let Some(publisher) = &self.routing_replica {
+            if !edges.is_empty() {
+                publisher.publish(RoutingEvent::AddVerifiedEdges(edges.clone()));
+            }
+        }
         self.routing_table_pool
             .send(RoutingTableMessages::AddEdges(edges))
             .into_actor(self)
@@ -795,7 +1937,9 @@ impl PeerManagerActor {
                     #[cfg(not(feature = "test_features"))]
                     let cond = act.edge_verifier_requests_in_progress == 0;
 
-                    act.update_and_remove_edges(ctx2, cond, false, SAVE_PEERS_AFTER_TIME);
+                    let prune =
+                        if cond { Some(act.config.routing_table.prune_config()) } else { None };
+                    act.update_and_remove_edges(ctx2, RoutingTableUpdate { prune });
                 },
             );
         }
@@ -803,7 +1947,17 @@ impl PeerManagerActor {
         new_edge
     }
 
-    #[cfg(all(feature = "test_features", feature = "protocol_feature_routing_exchange_algorithm"))]
+    /// Record in the audit log that `caller` mutated the routing state with `action`.
+    #[cfg(feature = "test_features")]
+    fn audit(&mut self, caller: String, action: String) {
+        info!(target: "network", "Routing mutation requested by {}: {}", caller, action);
+        let timestamp = to_timestamp(self.clock.now_utc());
+        if let Err(err) = self.audit_log.record(timestamp, caller, action) {
+            warn!(target: "network", "Failed to record routing mutation in the audit log: {}", err);
+        }
+    }
+
+    #[cfg(feature = "test_features")]
     fn adv_remove_edges_from_routing_table(
         &mut self,
         ctx: &mut Context<Self>,
@@ -822,7 +1976,15 @@ impl PeerManagerActor {
                 )
             })
             .collect();
+        self.remove_edges_from_routing_table(ctx, edges);
+    }
+
+    /// Remove edges from the routing table, e.g. the edges a primary we replicate removed.
+    fn remove_edges_from_routing_table(&mut self, ctx: &mut Context<Self>, edges: Vec<Edge>) {
         self.routing_table.remove_edges(&edges);
+        if let Some(publisher) = &self.routing_replica {
+            publisher.publish(RoutingEvent::RemoveEdges(edges.clone()));
+        }
         self.routing_table_pool
             .send(RoutingTableMessages::RemoveEdges(edges))
             .into_actor(self)
@@ -870,7 +2032,7 @@ impl PeerManagerActor {
                 self.peer_id.clone(),
                 other.clone(),
                 nonce,
-                &self.config.secret_key,
+                self.active_peer_edge_secret_key(&other),
             )),
         );
 
@@ -893,70 +2055,378 @@ impl PeerManagerActor {
         );
     }
 
-    /// Periodically query peer actors for latest weight and traffic info.
-    fn monitor_peer_stats(&mut self, ctx: &mut Context<Self>) {
-        for (peer_id, active_peer) in self.active_peers.iter() {
-            let peer_id1 = peer_id.clone();
+    /// Periodically ping active peers to measure the latency used to select next hops.
+    fn ping_active_peers(&mut self, ctx: &mut Context<Self>, period: Duration) {
+        let peer_ids = self.active_peers.keys().cloned().collect::<Vec<_>>();
+        for peer_id in peer_ids {
+            let nonce = self.routing_table.get_ping(peer_id.clone());
+            self.send_ping(ctx, nonce, peer_id);
+        }
+
+        near_performance_metrics::actix::run_later(ctx, period, move |act, ctx| {
+            act.ping_active_peers(ctx, period);
+        });
+    }
+
+    /// Periodically ask a random peer which of a sample of peers it can route messages to, to
+    /// detect its routing table diverging from ours.
+    #[cfg(feature = "protocol_feature_reachability_probe")]
+    fn probe_reachability(&mut self, ctx: &mut Context<Self>) {
+        let peer = self.active_peers.iter().filter(|(_, active_peer)| {
+            checked_feature!(
+                "protocol_feature_reachability_probe",
+                ReachabilityProbe,
+                active_peer.protocol_version
+            )
+        });
+        if let Some((peer_id, active_peer)) = peer.choose(&mut thread_rng()) {
+            let me = &self.peer_id;
+            let candidates = self
+                .routing_table
+                .reachable_peers()
+                .chain(self.peer_store.iter().map(|(peer_id, _)| peer_id))
+                .filter(|peer_id| *peer_id != me)
+                .cloned();
+            let targets = self.reachability_probes.start(peer_id.clone(), candidates);
+            debug!(target: "network", "Probing reachability of {} peers through {}", targets.len(), peer_id);
             active_peer
                 .addr
-                .send(QueryPeerStats {})
-                .into_actor(self)
-                .map(|result, _, _| result.map_err(|err| error!(target: "network", "Failed sending message(monitor_peer_stats): {}", err)))
-                .map(move |res, act, _| {
-                    let _ignore = res.map(|res| {
-                        if res.is_abusive {
-                            trace!(target: "network", "Banning peer {} for abuse ({} sent, {} recv)", peer_id1, res.message_counts.0, res.message_counts.1);
-                            // TODO(MarX, #1586): Ban peer if we found them abusive. Fix issue with heavy
-                            //  network traffic that flags honest peers.
-                            // Send ban signal to peer instance. It should send ban signal back and stop the instance.
-                            // if let Some(active_peer) = act.active_peers.get(&peer_id1) {
-                            //     active_peer.addr.do_send(PeerManagerRequest::BanPeer(ReasonForBan::Abusive));
-                            // }
-                        } else if let Some(active_peer) = act.active_peers.get_mut(&peer_id1) {
-                            active_peer.full_peer_info.chain_info = res.chain_info;
-                            active_peer.sent_bytes_per_sec = res.sent_bytes_per_sec;
-                            active_peer.received_bytes_per_sec = res.received_bytes_per_sec;
-                        }
-                    });
-                })
-                .spawn(ctx);
+                .do_send(SendMessage { message: PeerMessage::ReachabilityRequest(targets) });
         }
 
         near_performance_metrics::actix::run_later(
             ctx,
-            self.config.peer_stats_period,
+            REACHABILITY_PROBE_INTERVAL,
             move |act, ctx| {
-                act.monitor_peer_stats(ctx);
+                act.probe_reachability(ctx);
             },
         );
     }
 
-    /// Select one peer and send signal to stop connection to it gracefully.
-    /// Selection process:
-    ///     Create a safe set of peers, and among the remaining peers select one at random.
-    ///     If the number of outbound connections is less or equal than minimum_outbound_connections,
-    ///         add all outbound connections to the safe set.
-    ///     While the length of the safe set is less than safe_set_size:
-    ///         Among all the peers we have received a message within the last peer_recent_time_window,
-    ///             find the one we connected earlier and add it to the safe set.
-    ///         else break
-    fn try_stop_active_connection(&self) {
-        debug!(target: "network", "Trying to stop an active connection. Number of active connections: {}", self.active_peers.len());
-
-        // Build safe set
-        let mut safe_set = HashSet::new();
+    /// Map `listen_port` on our NAT gateway, and renew the mapping before it expires.
+    fn map_nat_port(&mut self, ctx: &mut Context<Self>, listen_port: u16) {
+        nat::map_port(listen_port)
+            .into_actor(self)
+            .map(move |res, act, ctx| {
+                let renew_after = match res {
+                    Ok(mapping) => {
+                        if act.external_addr != Some(mapping.external_addr) {
+                            info!(target: "network", "Mapped port {} to {} with {:?}", listen_port, mapping.external_addr, mapping.protocol);
+                            act.check_external_addr(ctx, mapping.external_addr);
+                        }
+                        act.external_addr = Some(mapping.external_addr);
+                        (mapping.lifetime / 2).max(NAT_PORT_MAPPING_RETRY_INTERVAL)
+                    }
+                    Err(err) => {
+                        warn!(target: "network", "Failed to map port {} on the NAT gateway: {}", listen_port, err);
+                        act.external_addr = None;
+                        NAT_PORT_MAPPING_RETRY_INTERVAL
+                    }
+                };
+                near_performance_metrics::actix::run_later(ctx, renew_after, move |act, ctx| {
+                    act.map_nat_port(ctx, listen_port);
+                });
+            })
+            .spawn(ctx);
+    }
 
-        if self.num_active_outgoing_peers() + self.outgoing_peers.len()
-            <= self.config.minimum_outbound_peers as usize
-        {
-            for (peer, active) in self.active_peers.iter() {
-                if active.peer_type == PeerType::Outbound {
-                    safe_set.insert(peer.clone());
+    /// Add the peers advertised by the DNS seeds to the known peers, and resolve the seeds again
+    /// after `dns_seeds_period`.
+    fn resolve_dns_seeds(&mut self, ctx: &mut Context<Self>) {
+        let seeds = self.config.dns_seeds.clone();
+        let ip_family = self.config.ip_family;
+        let prefer_v6 = self.config.addr.map_or(false, |addr| addr.is_ipv6());
+        async move {
+            let mut peers = vec![];
+            for seed in seeds {
+                match dns_seed::resolve_seed(&seed, ip_family, prefer_v6).await {
+                    Ok(seed_peers) => {
+                        debug!(target: "network", "DNS seed {} advertises {} peers", seed, seed_peers.len());
+                        peers.extend(seed_peers);
+                    }
+                    Err(err) => warn!(target: "network", "Failed to resolve DNS seed {}: {}", seed, err),
                 }
             }
+            peers
         }
+        .into_actor(self)
+        .map(|peers, act, ctx| {
+            let peers = peers.into_iter().filter(|peer_info| peer_info.id != act.peer_id).collect();
+            // Seeds are not verified by us, like peers learned from other peers.
+            if let Err(err) = act.peer_store.add_indirect_peers(peers) {
+                error!(target: "network", "Failed to save peer data: {}", err);
+            }
+            near_performance_metrics::actix::run_later(
+                ctx,
+                act.config.dns_seeds_period,
+                move |act, ctx| {
+                    act.resolve_dns_seeds(ctx);
+                },
+            );
+        })
+        .spawn(ctx);
+    }
 
-        if self.config.archive
+    /// Replicate the routing table of the primary node, subscribing again whenever the primary
+    /// disconnects.
+    fn follow_routing_primary(&mut self, ctx: &mut Context<Self>, socket: PathBuf) {
+        routing_replica::follow(socket.clone(), ctx.address().recipient())
+            .into_actor(self)
+            .map(move |result, _act, ctx| {
+                match result {
+                    Ok(()) => info!(target: "network", "Routing primary at {} disconnected", socket.display()),
+                    Err(err) => warn!(target: "network", "Failed to replicate routing primary at {}: {}", socket.display(), err),
+                }
+                near_performance_metrics::actix::run_later(
+                    ctx,
+                    REPLICA_RECONNECT_INTERVAL,
+                    move |act, ctx| {
+                        act.follow_routing_primary(ctx, socket);
+                    },
+                );
+            })
+            .spawn(ctx);
+    }
+
+    /// Check that peers can connect to us at `external_addr`. The address is advertised either
+    /// way: gateways which don't support hairpinning refuse connections from inside the NAT.
+    fn check_external_addr(&mut self, ctx: &mut Context<Self>, external_addr: SocketAddr) {
+        nat::is_reachable(external_addr)
+            .into_actor(self)
+            .map(move |reachable, _, _| {
+                if reachable {
+                    info!(target: "network", "External address {} is reachable", external_addr);
+                } else {
+                    warn!(target: "network", "External address {} isn't reachable from this host, inbound connections may fail", external_addr);
+                }
+            })
+            .spawn(ctx);
+    }
+
+    /// Dial the cached healthiest peers of the previous run. Returns whether any was dialed.
+    fn dial_cached_peers(&mut self, ctx: &mut Context<Self>) -> bool {
+        // Nodes behind relays don't connect to anyone else.
+        if !self.config.relays.is_empty() {
+            return false;
+        }
+        let mut dialed = false;
+        let cached_peers = self.peer_cache.peers().to_vec();
+        for cached_peer in cached_peers.into_iter().take(self.config.cold_start_peers) {
+            let peer_info = cached_peer.peer_info;
+            if peer_info.addr.is_none()
+                || peer_info.id == self.peer_id
+                || self.peer_store.is_banned(&peer_info.id)
+                || self.outgoing_peers.contains(&peer_info.id)
+            {
+                continue;
+            }
+            debug!(target: "network", "Dialing cached peer {} (score {}, last seen {})", peer_info, cached_peer.score, from_timestamp(cached_peer.last_seen));
+            self.outgoing_peers.insert(peer_info.id.clone());
+            ctx.notify(OutboundTcpConnect { peer_info });
+            dialed = true;
+        }
+        dialed
+    }
+
+    /// Start connecting to the boot nodes and other known peers, once we connected to a cached
+    /// peer or the cold start timed out.
+    fn finish_cold_start(&mut self, ctx: &mut Context<Self>) {
+        if !self.cold_start_pending {
+            return;
+        }
+        self.cold_start_pending = false;
+        if self.active_peers.is_empty() {
+            info!(target: "network", "No cached peer connected after {:?}, falling back to boot nodes", self.config.cold_start_timeout);
+        }
+        self.monitor_peers(ctx);
+    }
+
+    /// Add the active peers to the cache of healthiest peers and save it on disk.
+    fn update_peer_cache(&mut self) {
+        if self.config.cold_start_peers == 0 {
+            return;
+        }
+        let now = to_timestamp(self.clock.now_utc());
+        let peer_score = &mut self.peer_score;
+        let connected = self
+            .active_peers
+            .values()
+            .filter(|active_peer| active_peer.full_peer_info.peer_info.addr.is_some())
+            .map(|active_peer| {
+                let peer_info = active_peer.full_peer_info.peer_info.clone();
+                let score = peer_score.score(&peer_info.id);
+                CachedPeer { peer_info, score, last_seen: now }
+            })
+            .collect();
+        self.peer_cache.update(
+            connected,
+            self.config.cold_start_peers,
+            self.config.peer_expiration_duration,
+            now,
+        );
+        if let Err(err) = self.peer_cache.save(&self.store) {
+            warn!(target: "network", "Failed to save cached peers: {}", err);
+        }
+    }
+
+    /// Periodically save the healthiest peers on disk.
+    fn save_peer_cache(&mut self, ctx: &mut Context<Self>) {
+        self.update_peer_cache();
+        near_performance_metrics::actix::run_later(ctx, SAVE_PEER_CACHE_INTERVAL, |act, ctx| {
+            act.save_peer_cache(ctx);
+        });
+    }
+
+    /// Periodically send each peer pulling edges a digest of the recently changed edges it may
+    /// miss.
+    fn advertise_edge_digests(&mut self, ctx: &mut Context<Self>) {
+        for (peer_id, active_peer) in self.active_peers.iter() {
+            if !self.edge_digests_enabled(active_peer.protocol_version) {
+                continue;
+            }
+            let digest = self.edge_gossip.digest(peer_id);
+            if digest.is_empty() {
+                continue;
+            }
+            near_metrics::inc_counter_vec_by(
+                &metrics::EDGE_GOSSIP_EDGES,
+                &["advertised"],
+                digest.len() as u64,
+            );
+            active_peer.addr.do_send(SendMessage { message: PeerMessage::EdgeDigest(digest) });
+        }
+        near_performance_metrics::actix::run_later(
+            ctx,
+            self.config.edge_gossip.digest_interval,
+            |act, ctx| {
+                act.advertise_edge_digests(ctx);
+            },
+        );
+    }
+
+    /// Pull the edges of a digest which are newer than ours.
+    fn process_edge_digest(&mut self, peer_id: PeerId, digest: Vec<EdgeDigestEntry>) {
+        let addr = match self.active_peers.get(&peer_id) {
+            Some(active_peer) => active_peer.addr.clone(),
+            None => return,
+        };
+        let mut keys = Vec::new();
+        for entry in digest.into_iter().take(MAX_DIGEST_EDGES) {
+            let key = entry.key();
+            // The peer has the edge, we don't need to push it back.
+            self.edge_gossip.record_holder(&peer_id, &key, entry.nonce);
+            if self.routing_table.find_nonce(&key) < entry.nonce {
+                keys.push(key);
+            }
+        }
+        if !keys.is_empty() {
+            let _ = addr.do_send(SendMessage { message: PeerMessage::EdgePull(keys) });
+        }
+    }
+
+    /// Periodically save the lifetime edge counters on disk.
+    fn save_edge_stats(&mut self, ctx: &mut Context<Self>) {
+        self.routing_table.save_lifetime_edge_stats();
+        near_performance_metrics::actix::run_later(ctx, SAVE_EDGE_STATS_INTERVAL, |act, ctx| {
+            act.save_edge_stats(ctx);
+        });
+    }
+
+    /// Periodically query peer actors for latest weight and traffic info.
+    fn monitor_peer_stats(&mut self, ctx: &mut Context<Self>) {
+        for (peer_id, active_peer) in self.active_peers.iter() {
+            let peer_id1 = peer_id.clone();
+            active_peer
+                .addr
+                .send(QueryPeerStats {})
+                .into_actor(self)
+                .map(|result, _, _| result.map_err(|err| error!(target: "network", "Failed sending message(monitor_peer_stats): {}", err)))
+                .map(move |res, act, _| {
+                    let _ignore = res.map(|res| {
+                        if res.is_abusive {
+                            trace!(target: "network", "Banning peer {} for abuse ({} sent, {} recv)", peer_id1, res.message_counts.0, res.message_counts.1);
+                            // TODO(MarX, #1586): Ban peer if we found them abusive. Fix issue with heavy
+                            //  network traffic that flags honest peers.
+                            // Send ban signal to peer instance. It should send ban signal back and stop the instance.
+                            // if let Some(active_peer) = act.active_peers.get(&peer_id1) {
+                            //     active_peer.addr.do_send(PeerManagerRequest::BanPeer(ReasonForBan::Abusive));
+                            // }
+                        } else if let Some(active_peer) = act.active_peers.get_mut(&peer_id1) {
+                            act.routing_table.set_peer_shards(
+                                peer_id1.clone(),
+                                res.chain_info.tracked_shards.clone(),
+                            );
+                            active_peer.full_peer_info.chain_info = res.chain_info;
+                            active_peer.sent_bytes_per_sec = res.sent_bytes_per_sec;
+                            active_peer.received_bytes_per_sec = res.received_bytes_per_sec;
+                        }
+                    });
+                })
+                .spawn(ctx);
+        }
+        self.update_region_metrics();
+
+        near_performance_metrics::actix::run_later(
+            ctx,
+            self.config.peer_stats_period,
+            move |act, ctx| {
+                act.monitor_peer_stats(ctx);
+            },
+        );
+    }
+
+    /// Export the number of reachable peers and the latency of active peers per region, if the
+    /// operator provided regions of peer addresses.
+    fn update_region_metrics(&mut self) {
+        let peer_regions = match &self.peer_regions {
+            Some(peer_regions) => peer_regions,
+            None => return,
+        };
+        let mut stats = RegionStats::default();
+        for peer_id in self.routing_table.reachable_peers() {
+            if let Some(addr) = self.peer_store.get(peer_id).and_then(|state| state.peer_info.addr)
+            {
+                stats.add_reachable(peer_regions.region(addr.ip()));
+            }
+        }
+        for (peer_id, active_peer) in self.active_peers.iter() {
+            let addr = match active_peer.full_peer_info.peer_info.addr {
+                Some(addr) => addr,
+                None => continue,
+            };
+            if let Some(latency) = self.routing_table.peer_latency(peer_id) {
+                stats.add_latency(peer_regions.region(addr.ip()), latency);
+            }
+        }
+        stats.export();
+    }
+
+    /// Select one peer and send signal to stop connection to it gracefully.
+    /// Selection process:
+    ///     Create a safe set of peers, and among the remaining peers select one at random.
+    ///     If the number of outbound connections is less or equal than minimum_outbound_connections,
+    ///         add all outbound connections to the safe set.
+    ///     While the length of the safe set is less than safe_set_size:
+    ///         Among all the peers we have received a message within the last peer_recent_time_window,
+    ///             find the one we connected earlier and add it to the safe set.
+    ///         else break
+    fn try_stop_active_connection(&self) {
+        debug!(target: "network", "Trying to stop an active connection. Number of active connections: {}", self.active_peers.len());
+
+        // Build safe set
+        let mut safe_set = HashSet::new();
+
+        if self.num_active_outgoing_peers() + self.outgoing_peers.len()
+            <= self.config.minimum_outbound_peers as usize
+        {
+            for (peer, active) in self.active_peers.iter() {
+                if active.peer_type == PeerType::Outbound {
+                    safe_set.insert(peer.clone());
+                }
+            }
+        }
+
+        if self.config.archive
             && self.num_archival_peers()
                 <= self.config.archival_peer_connections_lower_bound as usize
         {
@@ -967,12 +2437,23 @@ impl PeerManagerActor {
             }
         }
 
+        // Direct connections to other validators, to whitelisted nodes and to anchors are kept.
+        for (peer, active) in self.active_peers.iter() {
+            if self.tier1.contains(peer)
+                || self.is_peer_whitelisted(&active.full_peer_info.peer_info)
+                || self.anchors.is_anchor(&active.full_peer_info.peer_info)
+            {
+                safe_set.insert(peer.clone());
+            }
+        }
+
         // Find all recent connections
         let mut recent_connections = self
             .active_peers
             .iter()
             .filter_map(|(peer_id, active)| {
-                if active.last_time_received_message.elapsed() < self.config.peer_recent_time_window
+                if self.clock.elapsed(active.last_time_received_message)
+                    < self.config.peer_recent_time_window
                 {
                     Some((peer_id.clone(), active.connection_established_time))
                 } else {
@@ -1027,7 +2508,7 @@ impl PeerManagerActor {
         for (peer_id, peer_state) in self.peer_store.iter() {
             if let KnownPeerStatus::Banned(_, last_banned) = peer_state.status {
                 let interval = unwrap_or_error!(
-                    (Utc::now() - from_timestamp(last_banned)).to_std(),
+                    (self.clock.now_utc() - from_timestamp(last_banned)).to_std(),
                     "Failed to convert time"
                 );
                 if interval > self.config.ban_window {
@@ -1039,15 +2520,27 @@ impl PeerManagerActor {
 
         for peer_id in to_unban {
             unwrap_or_error!(self.peer_store.peer_unban(&peer_id), "Failed to unban a peer");
+            self.routing_table.unban_peer(&peer_id);
+        }
+
+        // Penalties decay over time, so peers may stop being deprioritized.
+        self.routing_table.set_deprioritized_peers(self.peer_score.deprioritized_peers());
+
+        if self.config.tier1_enabled {
+            self.connect_tier1_peers(ctx);
         }
 
-        if self.is_outbound_bootstrap_needed() {
+        if !self.config.relays.is_empty() {
+            self.connect_relays(ctx);
+        } else if self.is_outbound_bootstrap_needed() {
             if let Some(peer_info) = self.sample_random_peer(|peer_state| {
                 // Ignore connecting to ourself
                 self.peer_id == peer_state.peer_info.id
                     || self.config.addr == peer_state.peer_info.addr
                     // Or to peers we are currently trying to connect to
                     || self.outgoing_peers.contains(&peer_state.peer_info.id)
+                    // Or to peers which recently failed to accept connections
+                    || self.peer_store.is_backing_off(&peer_state.peer_info.id)
             }) {
                 // Start monitor_peers_attempts from start after we discover the first healthy peer
                 if !self.started_connect_attempts {
@@ -1092,7 +2585,196 @@ impl PeerManagerActor {
         );
     }
 
+    /// Connect to our relays we are neither connected nor connecting to.
+    fn connect_relays(&mut self, ctx: &mut Context<Self>) {
+        for relay in self.config.relays.clone() {
+            if self.active_peers.contains_key(&relay.id)
+                || self.outgoing_peers.contains(&relay.id)
+                || self.peer_store.is_backing_off(&relay.id)
+            {
+                continue;
+            }
+            debug!(target: "network", "Connecting to relay {}", relay);
+            self.outgoing_peers.insert(relay.id.clone());
+            ctx.notify(OutboundTcpConnect { peer_info: relay });
+        }
+    }
+
+    /// Dial the anchor peers which aren't connected, and report the ones which stayed
+    /// disconnected for too long. Runs every `ANCHOR_RETRY_INTERVAL`, whatever the backoff of
+    /// `monitor_peers`.
+    fn maintain_anchors(&mut self, ctx: &mut Context<Self>) {
+        if !self.anchors.is_empty() {
+            let now = self.clock.now();
+            let (active_peers, outgoing_peers, peer_store) =
+                (&self.active_peers, &self.outgoing_peers, &self.peer_store);
+            let to_connect = self.anchors.peers_to_connect(
+                now,
+                |anchor| {
+                    active_peers
+                        .values()
+                        .find(|active| anchor.matches(&active.full_peer_info.peer_info))
+                        .map(|active| active.full_peer_info.peer_info.id.clone())
+                },
+                |peer_id| outgoing_peers.contains(peer_id),
+                |anchor| {
+                    peer_store
+                        .iter()
+                        .map(|(_, peer_state)| &peer_state.peer_info)
+                        .find(|peer_info| peer_info.addr.is_some() && anchor.matches(peer_info))
+                        .cloned()
+                },
+            );
+            for peer_info in to_connect {
+                debug!(target: "network", "Connecting to anchor peer {}", peer_info);
+                self.outgoing_peers.insert(peer_info.id.clone());
+                ctx.notify(OutboundTcpConnect { peer_info });
+            }
+
+            let timeout = self.config.anchor_unreachable_timeout;
+            for anchor in self.anchors.newly_unreachable(now, timeout) {
+                warn!(target: "network", "Anchor peer {} has been unreachable for more than {:?}", anchor, timeout);
+            }
+            near_metrics::set_gauge(
+                &metrics::ANCHOR_PEERS_DISCONNECTED,
+                self.anchors.num_disconnected() as i64,
+            );
+            near_metrics::set_gauge(
+                &metrics::ANCHOR_PEERS_UNREACHABLE,
+                self.anchors.num_unreachable(now, timeout) as i64,
+            );
+        }
+
+        near_performance_metrics::actix::run_later(ctx, ANCHOR_RETRY_INTERVAL, move |act, ctx| {
+            act.maintain_anchors(ctx);
+        });
+    }
+
+    /// Whether `peer_id` is one of the relays hiding our address.
+    fn is_relay(&self, peer_id: &PeerId) -> bool {
+        self.config.relays.iter().any(|relay| &relay.id == peer_id)
+    }
+
+    /// Sign the list of our relays and broadcast it, so nodes without a route to us route
+    /// messages to us through the relays.
+    fn announce_proxy(&mut self) {
+        let relays = self.config.relays.iter().map(|relay| relay.id.clone()).collect();
+        let announcement = ProxyAnnouncement::new(
+            &self.config.secret_key,
+            relays,
+            to_timestamp(self.clock.now_utc()),
+        );
+        if self.routing_table.add_proxy_announcement(announcement.clone()) {
+            self.broadcast_proxy_announcement(announcement, None);
+        }
+    }
+
+    /// Send `announcement` to the active peers which support it, except `except` which sent it
+    /// to us.
+    fn broadcast_proxy_announcement(
+        &self,
+        announcement: ProxyAnnouncement,
+        except: Option<&PeerId>,
+    ) {
+        let msg = Arc::new(SendMessage { message: PeerMessage::ProxyAnnouncement(announcement) });
+        for (peer_id, active_peer) in self.active_peers.iter() {
+            if Some(peer_id) != except
+                && active_peer.capabilities.contains(PeerCapabilities::PROXY_ANNOUNCEMENT)
+            {
+                active_peer.addr.do_send(msg.clone());
+            }
+        }
+    }
+
+    /// Periodically sign a new `ProxyAnnouncement` before the last one expires.
+    fn renew_proxy_announcement(&mut self, ctx: &mut Context<Self>) {
+        self.announce_proxy();
+        near_performance_metrics::actix::run_later(ctx, PROXY_ANNOUNCEMENT_PERIOD, |act, ctx| {
+            act.renew_proxy_announcement(ctx);
+        });
+    }
+
+    /// If we are a validator of the current epoch, connect directly to the peers the other
+    /// validators announced themselves from.
+    fn connect_tier1_peers(&mut self, ctx: &mut Context<Self>) {
+        let is_validator = self
+            .config
+            .account_id
+            .as_ref()
+            .map_or(false, |account_id| self.routing_table.is_epoch_validator(account_id));
+        let validator_peers = if is_validator {
+            let me = &self.peer_id;
+            self.routing_table
+                .epoch_validator_peers()
+                .into_iter()
+                .filter(|(_, peer_id)| peer_id != me)
+                .collect()
+        } else {
+            vec![]
+        };
+        self.tier1.set_validator_peers(validator_peers);
+
+        let (active_peers, outgoing_peers) = (&self.active_peers, &self.outgoing_peers);
+        let to_connect = self.tier1.peers_to_connect(|peer_id| {
+            active_peers.contains_key(peer_id) || outgoing_peers.contains(peer_id)
+        });
+        for peer_id in to_connect {
+            let peer_info = match self.peer_store.get(&peer_id) {
+                Some(peer_state)
+                    if peer_state.peer_info.addr.is_some() && !peer_state.status.is_banned() =>
+                {
+                    peer_state.peer_info.clone()
+                }
+                _ => {
+                    debug!(target: "network", "No address of validator {:?} peer {}", self.tier1.account_id(&peer_id), peer_id);
+                    continue;
+                }
+            };
+            debug!(target: "network", "Connecting to validator {:?} peer {}", self.tier1.account_id(&peer_id), peer_id);
+            self.outgoing_peers.insert(peer_id);
+            ctx.notify(OutboundTcpConnect { peer_info });
+        }
+
+        let tier1 = &self.tier1;
+        near_metrics::set_gauge(
+            &metrics::TIER1_CONNECTIONS,
+            self.active_peers.keys().filter(|peer_id| tier1.contains(peer_id)).count() as i64,
+        );
+    }
+
+    /// Send edges to `EdgeVerifier` pool. Large lists are split into batches verified in parallel.
+    /// Edges of denylisted or banned peers, and edges exceeding the rate limit of the peer are
+    /// dropped.
     fn verify_edges(&mut self, ctx: &mut Context<Self>, peer_id: PeerId, edges: Vec<Edge>) {
+        let received = edges.len();
+        let routing_table = &self.routing_table;
+        let mut edges =
+            self.denylist.filter_edges(edges, |peer_id| routing_table.is_banned(peer_id));
+        let allowed = self.edge_rate_limiter.allow(&peer_id, edges.len());
+        if allowed < edges.len() {
+            let dropped = edges.len() - allowed;
+            debug!(target: "network", "Dropping {} edges from {}: rate limit exceeded", dropped, peer_id);
+            near_metrics::inc_counter_vec_by(
+                &metrics::EDGE_UPDATES_RATE_LIMITED,
+                &[&peer_id.to_string()],
+                dropped as u64,
+            );
+            edges.truncate(allowed);
+        }
+        if diagnostics::routing_diagnostics_enabled(&self.clock) {
+            info!(target: "network::diagnostics", "Verifying {} edges from {} ({} received)",
+                  edges.len(), peer_id, received);
+        }
+        let batch_size = std::cmp::max(1, self.config.edge_verifier_batch_size);
+        while edges.len() > batch_size {
+            let rest = edges.split_off(batch_size);
+            self.verify_edges_batch(ctx, peer_id.clone(), edges);
+            edges = rest;
+        }
+        self.verify_edges_batch(ctx, peer_id, edges);
+    }
+
+    fn verify_edges_batch(&mut self, ctx: &mut Context<Self>, peer_id: PeerId, edges: Vec<Edge>) {
         if edges.is_empty() {
             return;
         }
@@ -1101,6 +2783,7 @@ impl PeerManagerActor {
             .send(EdgeList {
                 edges,
                 edges_info_shared: self.routing_table_exchange_helper.edges_info_shared.clone(),
+                sub_keys: self.sub_keys.clone(),
                 sender: self.routing_table_exchange_helper.edges_to_add_sender.clone(),
                 #[cfg(feature = "test_features")]
                 adv_disable_edge_signature_verification: self
@@ -1109,8 +2792,14 @@ impl PeerManagerActor {
             .into_actor(self)
             .then(move |response, act, ctx| {
                 act.edge_verifier_requests_in_progress -= 1;
+                if diagnostics::routing_diagnostics_enabled(&act.clock) {
+                    info!(target: "network::diagnostics", "Verified edges from {}: {:?}", peer_id, response);
+                }
                 match response {
-                    Ok(false) => act.try_ban_peer(ctx, &peer_id, ReasonForBan::InvalidEdge),
+                    Ok(false) => {
+                        act.routing_table.record_edge_verification_failure();
+                        act.penalize_peer(ctx, &peer_id, Misbehavior::InvalidEdges, 1)
+                    }
                     Ok(true) => {}
                     Err(err) => warn!(target: "network", "error validating edges: {}", err),
                 }
@@ -1121,12 +2810,37 @@ impl PeerManagerActor {
     /// Broadcast message to all active peers.
     fn broadcast_message(&self, ctx: &mut Context<Self>, msg: SendMessage) {
         // TODO(MarX, #1363): Implement smart broadcasting. (MST)
+        self.send_to_active_peers(ctx, self.active_peers.values(), msg);
+    }
+
+    /// Send message to `config.gossip_fanout` active peers sampled by the routing table, or to
+    /// all of them if the fanout is 0 or we don't have more active peers.
+    fn gossip_message(&mut self, ctx: &mut Context<Self>, msg: SendMessage) {
+        let fanout = self.config.gossip_fanout as usize;
+        if fanout == 0 || self.active_peers.len() <= fanout {
+            return self.broadcast_message(ctx, msg);
+        }
+        let candidates: Vec<_> = self.active_peers.keys().cloned().collect();
+        let sampled = self.routing_table.sample_peers(&candidates, fanout, &mut thread_rng());
+        near_metrics::inc_counter_by(
+            &metrics::GOSSIP_PEERS_SKIPPED,
+            (candidates.len() - sampled.len()) as u64,
+        );
+        let peers = sampled.iter().filter_map(|peer_id| self.active_peers.get(peer_id));
+        self.send_to_active_peers(ctx, peers, msg);
+    }
 
+    fn send_to_active_peers<'a>(
+        &self,
+        ctx: &mut Context<Self>,
+        peers: impl Iterator<Item = &'a ActivePeer>,
+        msg: SendMessage,
+    ) {
         // Change message to reference counted to allow sharing with all actors
         // without cloning.
         let msg = Arc::new(msg);
         let mut requests: futures::stream::FuturesUnordered<_> =
-            self.active_peers.values().map(|peer| peer.addr.send(Arc::clone(&msg))).collect();
+            peers.map(|peer| peer.addr.send(Arc::clone(&msg))).collect();
 
         ctx.spawn(async move {
             while let Some(response) = requests.next().await {
@@ -1141,12 +2855,11 @@ impl PeerManagerActor {
         debug!(target: "network", "{:?} Account announce: {:?}", self.config.account_id, announce_account);
         if !self.routing_table.contains_account(&announce_account) {
             self.routing_table.add_account(announce_account.clone());
-            self.broadcast_message(
-                ctx,
-                SendMessage {
-                    message: PeerMessage::RoutingTableSync(SyncData::account(announce_account)),
-                },
-            );
+            self.routing_table.record_announcement_origin(&announce_account, None, Some(0));
+            if let Some(publisher) = &self.routing_replica {
+                publisher.publish(RoutingEvent::AddAccounts(vec![announce_account.clone()]));
+            }
+            self.broadcast_announcements(ctx, vec![announce_account]);
         }
     }
 
@@ -1158,6 +2871,10 @@ impl PeerManagerActor {
         peer_id: PeerId,
         message: PeerMessage,
     ) -> bool {
+        if failpoints::fail_point(failpoints::SEND_MESSAGE) {
+            debug!(target: "network", "Failpoint dropped message to {}: {}", peer_id, message);
+            return false;
+        }
         if let Some(active_peer) = self.active_peers.get(&peer_id) {
             let msg_kind = message.msg_variant().to_string();
             trace!(target: "network", "Send message: {}", msg_kind);
@@ -1198,6 +2915,9 @@ impl PeerManagerActor {
             AccountOrPeerIdOrHash::AccountId(account_id) => {
                 self.send_message_to_account(ctx, &account_id, msg)
             }
+            AccountOrPeerIdOrHash::ShardValidator(shard_id) => {
+                self.send_message_to_shard_validator(ctx, *shard_id, msg)
+            }
             peer_or_hash @ AccountOrPeerIdOrHash::PeerId(_)
             | peer_or_hash @ AccountOrPeerIdOrHash::Hash(_) => self.send_message_to_peer(
                 ctx,
@@ -1209,15 +2929,67 @@ impl PeerManagerActor {
     /// Route signed message to target peer.
     /// Return whether the message is sent or not.
     fn send_signed_message_to_peer(&mut self, ctx: &mut Context<Self>, msg: RoutedMessage) -> bool {
+        self.route_signed_message(ctx, msg, None).is_some()
+    }
+
+    /// Route signed message to target peer, through next hops which track `shard_id` if any.
+    /// Return the next hop the message was sent to, none if it wasn't sent.
+    fn route_signed_message(
+        &mut self,
+        ctx: &mut Context<Self>,
+        msg: RoutedMessage,
+        shard_id: Option<ShardId>,
+    ) -> Option<PeerId> {
         // Check if the message is for myself and don't try to send it in that case.
         if let PeerIdOrHash::PeerId(target) = &msg.target {
             if target == &self.peer_id {
                 debug!(target: "network", "{:?} Drop signed message to myself ({:?}). Message: {:?}.", self.config.account_id, self.peer_id, msg);
-                return false;
+                return None;
             }
         }
 
-        match self.routing_table.find_route(&msg.target) {
+        let route = match &msg.target {
+            // Messages to validators we are directly connected to don't use the routing graph.
+            PeerIdOrHash::PeerId(target)
+                if self.tier1.contains(target) && self.active_peers.contains_key(target) =>
+            {
+                Ok(target.clone())
+            }
+            PeerIdOrHash::PeerId(target) if shard_id.is_some() => {
+                self.routing_table.find_route_via_shard(target, shard_id.unwrap())
+            }
+            target => self.routing_table.find_route(target),
+        };
+        if diagnostics::routing_diagnostics_enabled(&self.clock) {
+            info!(target: "network::diagnostics", "Route {} {} from {} to {:?}: {:?}",
+                  strum::AsStaticRef::as_static(&msg.body), msg.hash(), msg.author, msg.target, route);
+        }
+        let hash = msg.hash();
+        let route = match route {
+            Ok(next_hop) if self.forwarded_messages.is_loop(hash, &next_hop, msg.ttl) => {
+                near_metrics::inc_counter(&metrics::ROUTING_LOOPS_DETECTED);
+                debug!(target: "network", "Routing loop of {} to {:?} through {}", hash, msg.target, next_hop);
+                // Avoid the next hop the message came back from, and drop the message if there
+                // is no other one.
+                let target = match &msg.target {
+                    PeerIdOrHash::PeerId(target) => target,
+                    PeerIdOrHash::Hash(_) => return None,
+                };
+                self.routing_table.record_route_failure(
+                    target,
+                    Some(next_hop),
+                    "Routing loop".to_string(),
+                );
+                match self.routing_table.find_route(&msg.target) {
+                    Ok(next_hop) if !self.forwarded_messages.is_loop(hash, &next_hop, msg.ttl) => {
+                        Ok(next_hop)
+                    }
+                    _ => return None,
+                }
+            }
+            route => route,
+        };
+        match route {
             Ok(peer_id) => {
                 // Remember if we expect a response for this message.
                 if msg.author == self.peer_id && msg.expect_response() {
@@ -1225,14 +2997,38 @@ impl PeerManagerActor {
                     self.routing_table.add_route_back(msg.hash(), self.peer_id.clone());
                 }
 
-                self.send_message(ctx, peer_id, PeerMessage::Routed(msg))
+                let target = msg.target.clone();
+                let ttl = msg.ttl;
+                if self.send_message(ctx, peer_id.clone(), PeerMessage::Routed(msg)) {
+                    self.forwarded_messages.record(hash, peer_id.clone(), ttl);
+                    return Some(peer_id);
+                }
+                if let PeerIdOrHash::PeerId(target) = target {
+                    self.routing_table.record_route_failure(
+                        &target,
+                        Some(peer_id),
+                        "Next hop is not an active peer".to_string(),
+                    );
+                }
+                None
             }
             Err(find_route_error) => {
+                if let PeerIdOrHash::PeerId(target) = &msg.target {
+                    self.routing_table.record_route_failure(
+                        target,
+                        None,
+                        format!("{:?}", find_route_error),
+                    );
+                }
                 // TODO(MarX, #1369): Message is dropped here. Define policy for this case.
                 self.network_metrics.inc(
                     NetworkMetrics::peer_message_dropped(strum::AsStaticRef::as_static(&msg.body))
                         .as_str(),
                 );
+                near_metrics::inc_counter_vec(
+                    &metrics::ROUTE_NOT_FOUND_DROPPED,
+                    &[strum::AsStaticRef::as_static(&find_route_error)],
+                );
 
                 debug!(target: "network", "{:?} Drop signed message to {:?} Reason {:?}. Num known peers: {} Message {:?}",
                       self.config.account_id,
@@ -1241,7 +3037,21 @@ impl PeerManagerActor {
                       self.routing_table.peer_forwarding.len(),
                       msg.body,
                 );
-                false
+                // Responses can't be routed back to the author if their own route back is lost.
+                if self.config.send_route_not_found
+                    && msg.author != self.peer_id
+                    && msg.expect_response()
+                    && find_route_error != FindRouteError::RouteBackNotFound
+                {
+                    let body = RoutedMessageBody::RouteNotFound(RouteNotFound {
+                        request: msg.hash(),
+                        target: msg.target.clone(),
+                        reason: find_route_error,
+                    });
+                    let target = AccountOrPeerIdOrHash::Hash(msg.hash());
+                    self.send_message_to_peer(ctx, RawRoutedMessage { target, body });
+                }
+                None
             }
         }
     }
@@ -1260,12 +3070,28 @@ impl PeerManagerActor {
         ctx: &mut Context<Self>,
         account_id: &AccountId,
         msg: RoutedMessageBody,
+    ) -> bool {
+        self.send_message_to_account_via_shard(ctx, account_id, None, msg)
+    }
+
+    /// Send message to specific account, through next hops which track `shard_id` if any.
+    /// Return whether the message is sent or not.
+    fn send_message_to_account_via_shard(
+        &mut self,
+        ctx: &mut Context<Self>,
+        account_id: &AccountId,
+        shard_id: Option<ShardId>,
+        msg: RoutedMessageBody,
     ) -> bool {
         let target = match self.routing_table.account_owner(&account_id) {
             Ok(peer_id) => peer_id,
             Err(find_route_error) => {
                 // TODO(MarX, #1369): Message is dropped here. Define policy for this case.
                 near_metrics::inc_counter(&metrics::DROP_MESSAGE_UNKNOWN_ACCOUNT);
+                near_metrics::inc_counter_vec(
+                    &metrics::ROUTE_NOT_FOUND_DROPPED,
+                    &[strum::AsStaticRef::as_static(&find_route_error)],
+                );
                 debug!(target: "network", "{:?} Drop message to {} Reason {:?}. Message {:?}",
                        self.config.account_id,
                        account_id,
@@ -1278,7 +3104,36 @@ impl PeerManagerActor {
         };
 
         let msg = RawRoutedMessage { target: AccountOrPeerIdOrHash::PeerId(target), body: msg };
-        self.send_message_to_peer(ctx, msg)
+        let msg = self.sign_routed_message(msg);
+        self.route_signed_message(ctx, msg, shard_id).is_some()
+    }
+
+    /// Send message to any reachable validator of `shard_id` in the current epoch, through next
+    /// hops which track the shard if any. Return whether the message is sent or not.
+    fn send_message_to_shard_validator(
+        &mut self,
+        ctx: &mut Context<Self>,
+        shard_id: ShardId,
+        msg: RoutedMessageBody,
+    ) -> bool {
+        match self.routing_table.shard_validator(shard_id) {
+            Ok(account_id) => {
+                self.send_message_to_account_via_shard(ctx, &account_id, Some(shard_id), msg)
+            }
+            Err(find_route_error) => {
+                near_metrics::inc_counter_vec(
+                    &metrics::ROUTE_NOT_FOUND_DROPPED,
+                    &[strum::AsStaticRef::as_static(&find_route_error)],
+                );
+                debug!(target: "network", "{:?} Drop message to validators of shard {} Reason {:?}. Message {:?}",
+                       self.config.account_id,
+                       shard_id,
+                       find_route_error,
+                       msg,
+                );
+                false
+            }
+        }
     }
 
     fn sign_routed_message(&self, msg: RawRoutedMessage) -> RoutedMessage {
@@ -1295,7 +3150,13 @@ impl PeerManagerActor {
         }
     }
 
-    fn propose_edge(&self, peer1: PeerId, with_nonce: Option<u64>) -> EdgeInfo {
+    /// Sign our part of the edge with `peer1`, whose protocol version is given if known.
+    fn propose_edge(
+        &self,
+        peer1: PeerId,
+        with_nonce: Option<u64>,
+        peer_protocol_version: Option<ProtocolVersion>,
+    ) -> EdgeInfo {
         let key = Edge::key(self.peer_id.clone(), peer1.clone());
 
         // When we create a new edge we increase the latest nonce by 2 in case we miss a removal
@@ -1304,29 +3165,41 @@ impl PeerManagerActor {
             self.routing_table.get_edge(self.peer_id.clone(), peer1).map_or(1, |edge| edge.next())
         });
 
-        EdgeInfo::new(key.0, key.1, nonce, &self.config.secret_key)
+        EdgeInfo::new(key.0, key.1, nonce, self.edge_secret_key(peer_protocol_version))
     }
 
     // Ping pong useful functions.
 
-    // for unit tests
-    fn send_ping(&mut self, ctx: &mut Context<Self>, nonce: usize, target: PeerId) {
-        let body =
-            RoutedMessageBody::Ping(Ping { nonce: nonce as u64, source: self.peer_id.clone() });
+    /// Returns the next hop the ping was sent to, none if it wasn't sent.
+    fn send_ping(&mut self, ctx: &mut Context<Self>, nonce: u64, target: PeerId) -> Option<PeerId> {
+        let body = RoutedMessageBody::Ping(Ping { nonce, source: self.peer_id.clone() });
         self.routing_table.sending_ping(nonce, target.clone());
         let msg = RawRoutedMessage { target: AccountOrPeerIdOrHash::PeerId(target), body };
-        self.send_message_to_peer(ctx, msg);
+        let msg = self.sign_routed_message(msg);
+        self.route_signed_message(ctx, msg, None)
     }
 
-    fn send_pong(&mut self, ctx: &mut Context<Self>, nonce: usize, target: CryptoHash) {
-        let body =
-            RoutedMessageBody::Pong(Pong { nonce: nonce as u64, source: self.peer_id.clone() });
+    fn send_pong(&mut self, ctx: &mut Context<Self>, nonce: u64, target: CryptoHash) {
+        let body = RoutedMessageBody::Pong(Pong { nonce, source: self.peer_id.clone() });
         let msg = RawRoutedMessage { target: AccountOrPeerIdOrHash::Hash(target), body };
         self.send_message_to_peer(ctx, msg);
     }
 
+    /// `author` couldn't route our request to its target, remember it when choosing next hops.
+    fn handle_route_not_found(&mut self, author: &PeerId, response: RouteNotFound) {
+        debug!(target: "network", "{} couldn't route request {} to {:?}: {:?}",
+               author, response.request, response.target, response.reason);
+        if let PeerIdOrHash::PeerId(target) = &response.target {
+            self.routing_table.record_route_failure(
+                target,
+                None,
+                format!("{:?} at {}", response.reason, author),
+            );
+        }
+    }
+
     fn handle_ping(&mut self, ctx: &mut Context<Self>, ping: Ping, hash: CryptoHash) {
-        self.send_pong(ctx, ping.nonce as usize, hash);
+        self.send_pong(ctx, ping.nonce, hash);
         self.routing_table.add_ping(ping);
     }
 
@@ -1336,7 +3209,7 @@ impl PeerManagerActor {
         let latency = self.routing_table.add_pong(pong);
     }
 
-    pub(crate) fn get_network_info(&mut self) -> NetworkInfo {
+    pub(crate) fn get_network_info(&self) -> NetworkInfo {
         let (sent_bytes_per_sec, received_bytes_per_sec) = self.get_total_bytes_per_sec();
         NetworkInfo {
             active_peers: self
@@ -1358,12 +3231,46 @@ impl PeerManagerActor {
                     peer_id: announce_account.peer_id.clone(),
                     // TODO: fill in the address.
                     addr: None,
+                    next_hops: Some(
+                        self.routing_table
+                            .peer_forwarding
+                            .get(&announce_account.peer_id)
+                            .cloned()
+                            .unwrap_or_default(),
+                    ),
                 })
                 .collect(),
             peer_counter: self.peer_counter.load(Ordering::SeqCst),
+            routing_recalculation: self.routing_table.recalculation_view(
+                self.edge_verifier_requests_in_progress
+                    + self.pending_routing_table_update.is_some() as u64,
+            ),
         }
     }
 
+    /// Save the state which is otherwise only saved periodically or not at all.
+    fn flush(&mut self) {
+        self.routing_table.save_lifetime_edge_stats();
+        self.routing_table.save_route_back();
+        self.update_peer_cache();
+    }
+
+    /// Periodically remove stale `ColPeerComponent` entries of peers tracked in memory.
+    fn sweep_peer_components(&mut self, ctx: &mut Context<Self>) {
+        let repaired = self.routing_table.repair_peer_components();
+        if repaired > 0 {
+            debug!(target: "network", "Repaired {} peer components", repaired);
+        }
+
+        near_performance_metrics::actix::run_later(
+            ctx,
+            PEER_COMPONENT_SWEEP_INTERVAL,
+            move |act, ctx| {
+                act.sweep_peer_components(ctx);
+            },
+        );
+    }
+
     fn push_network_info(&mut self, ctx: &mut Context<Self>) {
         let network_info = self.get_network_info();
 
@@ -1384,6 +3291,23 @@ pub struct IncomingCrutch {
     listener: tokio_stream::wrappers::TcpListenerStream,
 }
 
+/// Binds a listener to the first of `addrs` which can be bound to.
+async fn bind_listener(addrs: Vec<SocketAddr>) -> std::io::Result<(TcpListener, SocketAddr)> {
+    let mut last_err = None;
+    for addr in addrs {
+        match TcpListener::bind(addr).await {
+            Ok(listener) => return Ok((listener, addr)),
+            Err(err) => {
+                warn!(target: "network", "Failed to listen at {}: {}", addr, err);
+                last_err = Some(err);
+            }
+        }
+    }
+    Err(last_err.unwrap_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "No address to listen at")
+    }))
+}
+
 impl Stream for IncomingCrutch {
     type Item = std::io::Result<TcpStream>;
 
@@ -1399,17 +3323,23 @@ impl Actor for PeerManagerActor {
     type Context = Context<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
-        // Start server if address provided.
-        if let Some(server_addr) = self.config.addr {
+        // Start a server at every address provided.
+        for listen in self.config.listen_addrs() {
             // TODO: for now crashes if server didn't start.
 
-            ctx.spawn(TcpListener::bind(server_addr).into_actor(self).then(
+            let listen_addrs = self.config.ip_family.listen_addrs(listen.addr);
+            ctx.spawn(bind_listener(listen_addrs).into_actor(self).then(
                 move |listener, act, ctx| {
-                    let listener = listener.unwrap();
+                    let (listener, listen_addr) = listener.unwrap();
                     let incoming = IncomingCrutch {
                         listener: tokio_stream::wrappers::TcpListenerStream::new(listener),
                     };
-                    info!(target: "stats", "Server listening at {}@{}", act.peer_id, server_addr);
+                    info!(target: "stats", "Server listening at {}@{} ({:?})", act.peer_id, listen_addr, listen.advertise);
+                    act.listening.push(ListenAddr { addr: listen_addr, advertise: listen.advertise });
+                    // Only the address advertised to every peer is mapped on the NAT gateway.
+                    if act.config.nat_port_mapping && act.config.addr == Some(listen.addr) {
+                        act.map_nat_port(ctx, listen_addr.port());
+                    }
                     let pending_incoming_connections_counter =
                         act.pending_incoming_connections_counter.clone();
                     let peer_counter = act.peer_counter.clone();
@@ -1436,17 +3366,80 @@ impl Actor for PeerManagerActor {
         // Periodically push network information to client
         self.push_network_info(ctx);
 
-        // Start peer monitoring.
-        self.monitor_peers(ctx);
+        // Start peer monitoring, after giving the healthiest peers of the previous run a chance
+        // to connect.
+        if self.dial_cached_peers(ctx) {
+            self.cold_start_pending = true;
+            near_performance_metrics::actix::run_later(
+                ctx,
+                self.config.cold_start_timeout,
+                move |act, ctx| {
+                    act.finish_cold_start(ctx);
+                },
+            );
+        } else {
+            self.monitor_peers(ctx);
+        }
 
         // Start active peer stats querying.
         self.monitor_peer_stats(ctx);
 
+        self.maintain_anchors(ctx);
+
+        // Start measuring latency to active peers.
+        if let Some(period) = self.config.latency_ping_period {
+            self.ping_active_peers(ctx, period);
+        }
+
         self.broadcast_edges(ctx);
+
+        #[cfg(feature = "protocol_feature_reachability_probe")]
+        self.probe_reachability(ctx);
+
+        self.save_edge_stats(ctx);
+        self.save_peer_cache(ctx);
+        self.advertise_edge_digests(ctx);
+        if !self.config.dns_seeds.is_empty() {
+            self.resolve_dns_seeds(ctx);
+        }
+        if let Some(socket) = self.config.routing_replica.publish_socket.clone() {
+            match routing_replica::bind(&socket) {
+                Ok(listener) => {
+                    info!(target: "network", "Publishing routing events at {}", socket.display());
+                    ctx.add_message_stream(
+                        tokio_stream::wrappers::UnixListenerStream::new(listener).filter_map(
+                            |stream| {
+                                future::ready(stream.ok().map(|stream| ReplicaSubscribe { stream }))
+                            },
+                        ),
+                    );
+                }
+                Err(err) => {
+                    error!(target: "network", "Failed to publish routing events at {}: {}", socket.display(), err)
+                }
+            }
+        }
+        if let Some(socket) = self.config.routing_replica.primary_socket.clone() {
+            self.follow_routing_primary(ctx, socket);
+        }
+        if !self.config.relays.is_empty() {
+            self.renew_proxy_announcement(ctx);
+        }
+
+        // Periodically check consistency of peer components saved on disk.
+        near_performance_metrics::actix::run_later(
+            ctx,
+            PEER_COMPONENT_SWEEP_INTERVAL,
+            move |act, ctx| {
+                act.sweep_peer_components(ctx);
+            },
+        );
     }
 
     /// Try to gracefully disconnect from active peers.
     fn stopping(&mut self, ctx: &mut Self::Context) -> Running {
+        self.flush();
+
         let msg = SendMessage { message: PeerMessage::Disconnect };
 
         for (_, active_peer) in self.active_peers.iter() {
@@ -1459,6 +3452,14 @@ impl Actor for PeerManagerActor {
             .then(move |_, _, _| actix::fut::ready(()))
             .spawn(ctx);
 
+        if let Some(graph_worker_pool) = &self.graph_worker_pool {
+            graph_worker_pool
+                .send(StopMsg {})
+                .into_actor(self)
+                .then(move |_, _, _| actix::fut::ready(()))
+                .spawn(ctx);
+        }
+
         self.routing_table_pool
             .send(StopMsg {})
             .into_actor(self)
@@ -1478,7 +3479,7 @@ impl Handler<NetworkRequests> for PeerManagerActor {
         let _d = DelayDetector::new(format!("network request {}", msg.as_ref()).into());
         match msg {
             NetworkRequests::Block { block } => {
-                self.broadcast_message(ctx, SendMessage { message: PeerMessage::Block(block) });
+                self.gossip_message(ctx, SendMessage { message: PeerMessage::Block(block) });
                 NetworkResponses::NoResponse
             }
             NetworkRequests::Approval { approval_message } => {
@@ -1575,9 +3576,10 @@ impl Handler<NetworkRequests> for PeerManagerActor {
                 for prefer_peer in &[target.prefer_peer, !target.prefer_peer] {
                     if !prefer_peer {
                         if let Some(account_id) = target.account_id.as_ref() {
-                            if self.send_message_to_account(
+                            if self.send_message_to_account_via_shard(
                                 ctx,
                                 &account_id,
+                                Some(target.shard_id),
                                 RoutedMessageBody::PartialEncodedChunkRequest(request.clone()),
                             ) {
                                 success = true;
@@ -1655,9 +3657,20 @@ impl Handler<NetworkRequests> for PeerManagerActor {
                 }
             }
             NetworkRequests::ForwardTx(account_id, tx) => {
+                let fallback = if self.config.gossip_fanout > 0 { Some(tx.clone()) } else { None };
                 if self.send_message_to_account(ctx, &account_id, RoutedMessageBody::ForwardTx(tx))
                 {
                     NetworkResponses::NoResponse
+                } else if let Some(tx) = fallback {
+                    // Peers receiving the transaction forward it to its validator in turn. Each
+                    // transaction is gossiped once, so it doesn't go around forever.
+                    if self.gossiped_transactions.cache_set(tx.get_hash(), ()).is_none() {
+                        self.gossip_message(
+                            ctx,
+                            SendMessage { message: PeerMessage::Transaction(tx) },
+                        );
+                    }
+                    NetworkResponses::NoResponse
                 } else {
                     NetworkResponses::RouteNotFound
                 }
@@ -1702,58 +3715,99 @@ impl Handler<NetworkRequests> for PeerManagerActor {
             NetworkRequests::Sync { peer_id, sync_data } => {
                 // Process edges and add new edges to the routing table. Also broadcast new edges.
                 let SyncData { edges, accounts } = sync_data;
-
-                // Filter known accounts before validating them.
-                let accounts = accounts
-                    .into_iter()
-                    .filter_map(|announce_account| {
-                        if let Some(current_announce_account) =
-                            self.routing_table.get_announce(&announce_account.account_id)
-                        {
-                            if announce_account.epoch_id == current_announce_account.epoch_id {
-                                None
-                            } else {
-                                Some((announce_account, Some(current_announce_account.epoch_id)))
-                            }
-                        } else {
-                            Some((announce_account, None))
-                        }
-                    })
-                    .collect();
-
-                // Ask client to validate accounts before accepting them.
-                let peer_id_clone = peer_id.clone();
-                self.view_client_addr
-                    .send(NetworkViewClientMessages::AnnounceAccount(accounts))
-                    .into_actor(self)
-                    .then(move |response, act, ctx| {
-                        match response {
-                            Ok(NetworkViewClientResponses::Ban { ban_reason }) => {
-                                act.try_ban_peer(ctx, &peer_id_clone, ban_reason);
-                            }
-                            Ok(NetworkViewClientResponses::AnnounceAccount(accounts)) => {
-                                act.broadcast_accounts(ctx, accounts);
-                            }
-                            _ => {
-                                debug!(target: "network", "Received invalid account confirmation from client.");
-                            }
-                        }
-                        actix::fut::ready(())
-                    }).spawn(ctx);
-
+                let accounts = accounts.into_iter().map(|account| (account, None)).collect();
+                self.receive_announcements(ctx, peer_id.clone(), accounts);
+
+                let routing_table = &self.routing_table;
+                self.edge_gossip_stats
+                    .record_edges(&peer_id, &edges, |key| routing_table.find_nonce(key));
+                for edge in edges.iter() {
+                    self.edge_gossip.record_holder(&peer_id, edge.key(), edge.nonce);
+                }
                 self.verify_edges(ctx, peer_id, edges);
 
                 NetworkResponses::NoResponse
             }
-            #[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
+            NetworkRequests::DeltaSyncRequest { peer_id, watermark } => {
+                self.process_delta_sync_request(ctx, peer_id, watermark);
+                NetworkResponses::NoResponse
+            }
+            NetworkRequests::DeltaSync { peer_id, delta } => {
+                if self.active_peers.contains_key(&peer_id) {
+                    self.sync_watermarks.cache_set(peer_id.clone(), delta.watermark);
+                    self.verify_edges(ctx, peer_id, delta.edges);
+                }
+                NetworkResponses::NoResponse
+            }
+            NetworkRequests::EdgeDigest { peer_id, digest } => {
+                self.process_edge_digest(peer_id, digest);
+                NetworkResponses::NoResponse
+            }
+            NetworkRequests::AnnounceAccountsWithHops { peer_id, accounts } => {
+                let accounts =
+                    accounts.into_iter().map(|(account, hops)| (account, Some(hops))).collect();
+                self.receive_announcements(ctx, peer_id, accounts);
+                NetworkResponses::NoResponse
+            }
+            NetworkRequests::SubKeyDelegations { peer_id, delegations } => {
+                self.add_sub_key_delegations(delegations, Some(&peer_id));
+                NetworkResponses::NoResponse
+            }
+            NetworkRequests::ProxyAnnouncement { peer_id, announcement } => {
+                if self.routing_table.add_proxy_announcement(announcement.clone()) {
+                    self.broadcast_proxy_announcement(announcement, Some(&peer_id));
+                }
+                NetworkResponses::NoResponse
+            }
+            NetworkRequests::EdgePull { peer_id, keys } => {
+                if let Some(active_peer) = self.active_peers.get(&peer_id) {
+                    let edges: Vec<Edge> = keys
+                        .into_iter()
+                        .take(MAX_DIGEST_EDGES)
+                        .filter_map(|(peer0, peer1)| self.routing_table.get_edge(peer0, peer1))
+                        .collect();
+                    near_metrics::inc_counter_vec_by(
+                        &metrics::EDGE_GOSSIP_EDGES,
+                        &["pulled"],
+                        edges.len() as u64,
+                    );
+                    active_peer.addr.do_send(SendMessage {
+                        message: PeerMessage::RoutingTableSync(SyncData {
+                            edges,
+                            accounts: Default::default(),
+                        }),
+                    });
+                }
+                NetworkResponses::NoResponse
+            }
             NetworkRequests::IbfMessage { peer_id, ibf_msg } => match ibf_msg {
                 RoutingSyncV2::Version2(ibf_msg) => {
-                    if let Some(addr) = self.active_peers.get(&peer_id).map(|p| p.addr.clone()) {
-                        self.process_ibf_msg(ctx, &peer_id, ibf_msg, addr)
+                    if let Some((addr, protocol_version)) = self
+                        .active_peers
+                        .get(&peer_id)
+                        .map(|p| (p.addr.clone(), p.protocol_version))
+                    {
+                        let hasher_kind = IbfHasherKind::negotiate(protocol_version);
+                        self.process_ibf_msg(ctx, &peer_id, ibf_msg, hasher_kind, addr, false)
                     }
                     NetworkResponses::NoResponse
                 }
             },
+            NetworkRequests::SetEpochValidators {
+                epoch_id,
+                next_epoch_id,
+                validators,
+                shard_validators,
+            } => {
+                debug!(target: "network", "Setting {} validators for epoch {:?}", validators.len(), epoch_id);
+                self.routing_table.set_epoch_validators(
+                    epoch_id,
+                    next_epoch_id,
+                    validators.into_iter().collect(),
+                );
+                self.routing_table.set_shard_validators(shard_validators);
+                NetworkResponses::NoResponse
+            }
             NetworkRequests::Challenge(challenge) => {
                 // TODO(illia): smarter routing?
                 self.broadcast_message(
@@ -1763,7 +3817,13 @@ impl Handler<NetworkRequests> for PeerManagerActor {
                 NetworkResponses::NoResponse
             }
             NetworkRequests::RequestUpdateNonce(peer_id, edge_info) => {
-                if Edge::partial_verify(self.peer_id.clone(), peer_id.clone(), &edge_info) {
+                let sub_keys = self.sub_keys.read().unwrap().keys(&peer_id);
+                if Edge::partial_verify(
+                    self.peer_id.clone(),
+                    peer_id.clone(),
+                    &edge_info,
+                    &sub_keys,
+                ) {
                     if let Some(cur_edge) =
                         self.routing_table.get_edge(self.peer_id.clone(), peer_id.clone())
                     {
@@ -1776,9 +3836,9 @@ impl Handler<NetworkRequests> for PeerManagerActor {
 
                     let new_edge = Edge::build_with_secret_key(
                         self.peer_id.clone(),
-                        peer_id,
+                        peer_id.clone(),
                         edge_info.nonce,
-                        &self.config.secret_key,
+                        self.active_peer_edge_secret_key(&peer_id),
                         edge_info.signature,
                     );
 
@@ -1789,7 +3849,9 @@ impl Handler<NetworkRequests> for PeerManagerActor {
                 }
             }
             NetworkRequests::ResponseUpdateNonce(edge) => {
-                if edge.contains_peer(&self.peer_id) && edge.verify() {
+                if edge.contains_peer(&self.peer_id)
+                    && edge.verify_with_sub_keys(&self.sub_keys.read().unwrap())
+                {
                     if self.add_verified_edges_to_routing_table(ctx, vec![edge.clone()]) {
                         let other = edge.other(&self.peer_id).unwrap();
                         if let Some(nonce) = self.pending_update_nonce_request.get(&other) {
@@ -1803,74 +3865,425 @@ impl Handler<NetworkRequests> for PeerManagerActor {
                     NetworkResponses::BanPeer(ReasonForBan::InvalidEdge)
                 }
             }
-            // For unit tests
-            NetworkRequests::PingTo(nonce, target) => {
-                self.send_ping(ctx, nonce, target);
-                NetworkResponses::NoResponse
+            // For unit tests
+            NetworkRequests::PingTo(nonce, target) => {
+                self.send_ping(ctx, nonce, target);
+                NetworkResponses::NoResponse
+            }
+            // For unit tests
+            NetworkRequests::FetchPingPongInfo => {
+                let (pings, pongs) = self.routing_table.fetch_ping_pong();
+                NetworkResponses::PingPongInfo { pings, pongs }
+            }
+        }
+    }
+}
+
+impl Handler<InboundTcpConnect> for PeerManagerActor {
+    type Result = ();
+
+    #[perf]
+    fn handle(&mut self, msg: InboundTcpConnect, ctx: &mut Self::Context) {
+        {
+            #[cfg(feature = "delay_detector")]
+            let _d = DelayDetector::new("inbound tcp connect".into());
+        }
+
+        let peer_addr = msg.stream.peer_addr();
+        let whitelisted = peer_addr
+            .as_ref()
+            .map_or(false, |addr| self.is_ip_whitelisted(addr) || self.anchors.is_anchor_ip(addr));
+        if !peer_addr.as_ref().map_or(false, |addr| self.config.ip_family.allows(addr)) {
+            debug!(target: "network", "Inbound connection dropped (address family not allowed).");
+        } else if !whitelisted
+            && peer_addr.as_ref().map_or(false, |addr| self.is_inbound_subnet_full(addr))
+        {
+            debug!(target: "network", "Inbound connection dropped (too many peers from subnet of {:?}).", peer_addr);
+        } else if whitelisted || self.is_inbound_allowed() {
+            self.accept_inbound(ctx, msg.stream);
+        } else {
+            // TODO(1896): Gracefully drop inbound connection for other peer.
+            debug!(target: "network", "Inbound connection dropped (network at max capacity).");
+        }
+        self.pending_incoming_connections_counter.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(feature = "test_features")]
+impl Handler<crate::types::StartRoutingTableSync> for PeerManagerActor {
+    type Result = ();
+
+    #[perf]
+    fn handle(&mut self, msg: crate::types::StartRoutingTableSync, ctx: &mut Self::Context) {
+        self.audit(msg.caller, format!("start routing table sync with {}", msg.peer_id));
+        if let Some(active_peer) = self.active_peers.get(&msg.peer_id) {
+            let addr = active_peer.addr.clone();
+            let hasher_kind = IbfHasherKind::negotiate(active_peer.protocol_version);
+            self.initialize_routing_table_exchange(
+                msg.peer_id,
+                PeerType::Inbound,
+                addr,
+                hasher_kind,
+                ctx,
+            );
+        }
+    }
+}
+
+#[cfg(feature = "test_features")]
+impl Handler<SetAdvOptions> for PeerManagerActor {
+    type Result = SetAdvOptionsResult;
+
+    #[perf]
+    fn handle(&mut self, msg: SetAdvOptions, _ctx: &mut Self::Context) -> SetAdvOptionsResult {
+        let action = format!(
+            "set adv options: disable_edge_propagation={:?} disable_edge_signature_verification={:?} disable_edge_pruning={:?} set_max_peers={:?}",
+            msg.disable_edge_propagation,
+            msg.disable_edge_signature_verification,
+            msg.disable_edge_pruning,
+            msg.set_max_peers
+        );
+        self.audit(msg.caller, action);
+        if let Some(disable_edge_propagation) = msg.disable_edge_propagation {
+            self.adv_disable_edge_propagation = disable_edge_propagation;
+        }
+        if let Some(disable_edge_signature_verification) = msg.disable_edge_signature_verification {
+            self.adv_disable_edge_signature_verification = disable_edge_signature_verification;
+        }
+        if let Some(disable_edge_pruning) = msg.disable_edge_pruning {
+            self.adv_disable_edge_pruning = disable_edge_pruning;
+        }
+        if let Some(set_max_peers) = msg.set_max_peers {
+            self.config.max_num_peers = set_max_peers as u32;
+        }
+        SetAdvOptionsResult {}
+    }
+}
+
+impl Handler<DumpRoutingTable> for PeerManagerActor {
+    type Result = ResponseFuture<Result<(), String>>;
+
+    #[perf]
+    fn handle(&mut self, msg: DumpRoutingTable, _ctx: &mut Self::Context) -> Self::Result {
+        let format = RoutingTableDumpFormat::from_path(&msg.path);
+        let request = self.routing_table_pool.send(RoutingTableMessages::DumpSnapshot {
+            dump: Box::new(self.routing_table.dump_without_edges()),
+            path: msg.path,
+            format,
+        });
+        Box::pin(async move {
+            match request.await {
+                Ok(RoutingTableMessagesResponse::DumpSnapshotResponse(result)) => {
+                    result.map_err(|err| err.to_string())
+                }
+                Ok(_) => Err("unexpected response from routing table actor".to_string()),
+                Err(err) => Err(err.to_string()),
+            }
+        })
+    }
+}
+
+impl Handler<Flush> for PeerManagerActor {
+    type Result = ();
+
+    #[perf]
+    fn handle(&mut self, _msg: Flush, _ctx: &mut Self::Context) {
+        self.flush();
+    }
+}
+
+impl Handler<GetNetworkDebugInfo> for PeerManagerActor {
+    type Result = ResponseFuture<Result<NetworkDebugInfo, String>>;
+
+    #[perf]
+    fn handle(&mut self, _msg: GetNetworkDebugInfo, _ctx: &mut Self::Context) -> Self::Result {
+        let peers = self
+            .active_peers
+            .values()
+            .map(|active_peer| {
+                let peer_info = &active_peer.full_peer_info.peer_info;
+                PeerConnectionView {
+                    peer_id: peer_info.id.clone(),
+                    addr: peer_info.addr,
+                    account_id: peer_info.account_id.clone(),
+                    peer_type: active_peer.peer_type,
+                    connected_secs: self
+                        .clock
+                        .elapsed(active_peer.connection_established_time)
+                        .as_secs(),
+                    last_message_secs_ago: self
+                        .clock
+                        .elapsed(active_peer.last_time_received_message)
+                        .as_secs(),
+                    received_bytes_per_sec: active_peer.received_bytes_per_sec,
+                    sent_bytes_per_sec: active_peer.sent_bytes_per_sec,
+                    ping_rtt_ms: self.routing_table.peer_latency(&peer_info.id),
+                    capabilities: active_peer.capabilities,
+                }
+            })
+            .collect();
+        let mut info = NetworkDebugInfo {
+            peer_id: self.peer_id.clone(),
+            peers,
+            peer_forwarding: self.routing_table.peer_forwarding.clone(),
+            account_announcements: self.routing_table.get_announce_accounts(),
+            edges: vec![],
+            listen_addrs: self.listening.clone(),
+        };
+        let request = self.routing_table_pool.send(RoutingTableMessages::GetDebugInfo);
+        Box::pin(async move {
+            match request.await {
+                Ok(RoutingTableMessagesResponse::GetDebugInfoResponse { edges }) => {
+                    info.edges = edges;
+                    Ok(info)
+                }
+                Ok(_) => Err("unexpected response from routing table actor".to_string()),
+                Err(err) => Err(err.to_string()),
+            }
+        })
+    }
+}
+
+impl Handler<GetRoutingProfile> for PeerManagerActor {
+    type Result = ResponseFuture<Result<RoutingProfile, String>>;
+
+    #[perf]
+    fn handle(&mut self, _msg: GetRoutingProfile, _ctx: &mut Self::Context) -> Self::Result {
+        let memory_bytes = self.routing_table.memory_bytes();
+        let request = self.routing_table_pool.send(RoutingTableMessages::GetProfile);
+        Box::pin(async move {
+            match request.await {
+                Ok(RoutingTableMessagesResponse::GetProfileResponse(mut profile)) => {
+                    profile.memory_bytes.extend(memory_bytes);
+                    Ok(profile)
+                }
+                Ok(_) => Err("unexpected response from routing table actor".to_string()),
+                Err(err) => Err(err.to_string()),
             }
-            // For unit tests
-            NetworkRequests::FetchPingPongInfo => {
-                let (pings, pongs) = self.routing_table.fetch_ping_pong();
-                NetworkResponses::PingPongInfo { pings, pongs }
+        })
+    }
+}
+
+impl Handler<ReloadNetworkConfig> for PeerManagerActor {
+    type Result = Result<Vec<&'static str>, String>;
+
+    #[perf]
+    fn handle(&mut self, msg: ReloadNetworkConfig, _ctx: &mut Self::Context) -> Self::Result {
+        let mut config = self.config.clone();
+        let changed = config.apply_reloadable(msg.config);
+        config.verify()?;
+
+        // Existing connections are kept, even if they are no longer allowed by the new limits.
+        for boot_node in config.boot_nodes.iter() {
+            if boot_node.addr.is_none() || self.config.boot_nodes.contains(boot_node) {
+                continue;
+            }
+            if let Err(err) =
+                self.peer_store.add_trusted_peer(boot_node.clone(), TrustLevel::Signed)
+            {
+                warn!(target: "network", "Failed to add boot node {}: {}", boot_node, err);
             }
         }
+        self.anchors.set_anchors(config.anchor_peers.clone(), self.clock.now());
+        self.edge_rate_limiter.set_limits(config.edge_rate_limit, config.edge_rate_limit_burst);
+        // Per peer bandwidth limits only apply to new connections.
+        self.upload_throttle.set_rate(config.bandwidth.upload_bytes_per_sec);
+        self.download_throttle.set_rate(config.bandwidth.download_bytes_per_sec);
+        self.config = config;
+
+        info!(target: "network", "Reloaded network config, changed: {:?}", changed);
+        Ok(changed)
     }
 }
 
-impl Handler<InboundTcpConnect> for PeerManagerActor {
-    type Result = ();
+impl Handler<GetPeerScores> for PeerManagerActor {
+    type Result = GetPeerScoresResult;
 
     #[perf]
-    fn handle(&mut self, msg: InboundTcpConnect, ctx: &mut Self::Context) {
-        {
-            #[cfg(feature = "delay_detector")]
-            let _d = DelayDetector::new("inbound tcp connect".into());
+    fn handle(&mut self, msg: GetPeerScores, _ctx: &mut Self::Context) -> GetPeerScoresResult {
+        GetPeerScoresResult { peers: self.peer_score.worst_peers(msg.limit) }
+    }
+}
+
+impl Handler<GetAnchorPeers> for PeerManagerActor {
+    type Result = GetAnchorPeersResult;
+
+    #[perf]
+    fn handle(&mut self, _msg: GetAnchorPeers, _ctx: &mut Self::Context) -> GetAnchorPeersResult {
+        GetAnchorPeersResult {
+            anchors: self
+                .anchors
+                .statuses(self.clock.now(), self.config.anchor_unreachable_timeout),
         }
+    }
+}
 
-        if self.is_inbound_allowed() {
-            self.try_connect_peer(ctx.address(), msg.stream, PeerType::Inbound, None, None);
-        } else {
-            // TODO(1896): Gracefully drop inbound connection for other peer.
-            debug!(target: "network", "Inbound connection dropped (network at max capacity).");
+impl Handler<GetReachabilityProbes> for PeerManagerActor {
+    type Result = GetReachabilityProbesResult;
+
+    #[perf]
+    fn handle(
+        &mut self,
+        _msg: GetReachabilityProbes,
+        _ctx: &mut Self::Context,
+    ) -> GetReachabilityProbesResult {
+        self.reachability_probes.results()
+    }
+}
+
+impl Handler<GetRoutingAuditLog> for PeerManagerActor {
+    type Result = GetRoutingAuditLogResult;
+
+    #[perf]
+    fn handle(
+        &mut self,
+        msg: GetRoutingAuditLog,
+        _ctx: &mut Self::Context,
+    ) -> GetRoutingAuditLogResult {
+        GetRoutingAuditLogResult { entries: self.audit_log.entries(msg.limit) }
+    }
+}
+
+impl Handler<GetEdgeGossipStats> for PeerManagerActor {
+    type Result = GetEdgeGossipStatsResult;
+
+    #[perf]
+    fn handle(
+        &mut self,
+        msg: GetEdgeGossipStats,
+        _ctx: &mut Self::Context,
+    ) -> GetEdgeGossipStatsResult {
+        GetEdgeGossipStatsResult {
+            top_contributors: self.edge_gossip_stats.top_contributors(msg.limit),
         }
-        self.pending_incoming_connections_counter.fetch_sub(1, Ordering::SeqCst);
     }
 }
 
-#[cfg(feature = "test_features")]
-#[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
-impl Handler<crate::types::StartRoutingTableSync> for PeerManagerActor {
+impl Handler<SendPing> for PeerManagerActor {
+    type Result = Result<SentPing, String>;
+
+    #[perf]
+    fn handle(&mut self, msg: SendPing, ctx: &mut Self::Context) -> Result<SentPing, String> {
+        let target = match msg.target {
+            AccountOrPeerIdOrHash::AccountId(account_id) => self
+                .routing_table
+                .account_owner(&account_id)
+                .map_err(|err| format!("Can't find peer of {}: {:?}", account_id, err))?,
+            AccountOrPeerIdOrHash::ShardValidator(shard_id) => {
+                let account_id = self.routing_table.shard_validator(shard_id).map_err(|err| {
+                    format!("Can't find validator of shard {}: {:?}", shard_id, err)
+                })?;
+                self.routing_table
+                    .account_owner(&account_id)
+                    .map_err(|err| format!("Can't find peer of {}: {:?}", account_id, err))?
+            }
+            AccountOrPeerIdOrHash::PeerId(peer_id) => peer_id,
+            AccountOrPeerIdOrHash::Hash(_) => return Err("Can't ping a route back".to_string()),
+        };
+        let nonce = self.routing_table.get_ping(target.clone());
+        let next_hop = self.send_ping(ctx, nonce, target.clone());
+        Ok(SentPing { target, nonce, next_hop })
+    }
+}
+
+impl Handler<GetPingRtts> for PeerManagerActor {
+    type Result = MessageResult<GetPingRtts>;
+
+    #[perf]
+    fn handle(&mut self, msg: GetPingRtts, _ctx: &mut Self::Context) -> Self::Result {
+        let rtts = msg
+            .nonces
+            .iter()
+            .filter_map(|nonce| self.routing_table.ping_rtt(&msg.target, *nonce))
+            .collect();
+        MessageResult(rtts)
+    }
+}
+
+impl Handler<ReplicaSubscribe> for PeerManagerActor {
     type Result = ();
 
-    #[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
     #[perf]
-    fn handle(&mut self, msg: crate::types::StartRoutingTableSync, ctx: &mut Self::Context) {
-        if let Some(active_peer) = self.active_peers.get(&msg.peer_id) {
-            let addr = active_peer.addr.clone();
-            self.initialize_routing_table_exchange(msg.peer_id, PeerType::Inbound, addr, ctx);
-        }
+    fn handle(&mut self, msg: ReplicaSubscribe, ctx: &mut Self::Context) {
+        let publisher = match &self.routing_replica {
+            Some(publisher) => publisher,
+            None => return,
+        };
+        let edges: Vec<Edge> = self.routing_table.edges_info.values().cloned().collect();
+        info!(target: "network", "Routing replica subscribed, sending {} edges", edges.len());
+        let snapshot = routing_replica::snapshot(edges, self.routing_table.get_announce_accounts());
+        publisher
+            .serve(msg.stream, snapshot)
+            .into_actor(self)
+            .map(|result, _, _| {
+                if let Err(err) = result {
+                    info!(target: "network", "Routing replica disconnected: {}", err);
+                }
+            })
+            .spawn(ctx);
     }
 }
 
-#[cfg(feature = "test_features")]
-impl Handler<SetAdvOptions> for PeerManagerActor {
-    type Result = SetAdvOptionsResult;
+/// Routing events of the primary node we replicate.
+impl Handler<RoutingEvent> for PeerManagerActor {
+    type Result = ();
 
     #[perf]
-    fn handle(&mut self, msg: SetAdvOptions, _ctx: &mut Self::Context) -> SetAdvOptionsResult {
-        if let Some(disable_edge_propagation) = msg.disable_edge_propagation {
-            self.adv_disable_edge_propagation = disable_edge_propagation;
-        }
-        if let Some(disable_edge_signature_verification) = msg.disable_edge_signature_verification {
-            self.adv_disable_edge_signature_verification = disable_edge_signature_verification;
-        }
-        if let Some(disable_edge_pruning) = msg.disable_edge_pruning {
-            self.adv_disable_edge_pruning = disable_edge_pruning;
-        }
-        if let Some(set_max_peers) = msg.set_max_peers {
-            self.config.max_num_peers = set_max_peers as u32;
+    fn handle(&mut self, msg: RoutingEvent, ctx: &mut Self::Context) {
+        match msg {
+            RoutingEvent::AddVerifiedEdges(edges) => {
+                self.add_verified_edges_to_routing_table(ctx, edges);
+            }
+            RoutingEvent::RemoveEdges(edges) => self.remove_edges_from_routing_table(ctx, edges),
+            RoutingEvent::AddAccounts(accounts) => {
+                let accounts = accounts
+                    .into_iter()
+                    .filter(|account| !self.routing_table.contains_account(account))
+                    .collect::<Vec<_>>();
+                for account in accounts.iter() {
+                    self.routing_table.add_account(account.clone());
+                }
+                if let Some(publisher) = &self.routing_replica {
+                    if !accounts.is_empty() {
+                        publisher.publish(RoutingEvent::AddAccounts(accounts));
+                    }
+                }
+            }
         }
-        SetAdvOptionsResult {}
+    }
+}
+
+impl Handler<ExplainAccountRoute> for PeerManagerActor {
+    type Result = AccountRouteExplanation;
+
+    #[perf]
+    fn handle(
+        &mut self,
+        msg: ExplainAccountRoute,
+        _ctx: &mut Self::Context,
+    ) -> AccountRouteExplanation {
+        self.routing_table.explain_account(&msg.account_id)
+    }
+}
+
+impl Handler<ExplainRoute> for PeerManagerActor {
+    type Result = ResponseFuture<Result<RouteExplanation, String>>;
+
+    #[perf]
+    fn handle(&mut self, msg: ExplainRoute, _ctx: &mut Self::Context) -> Self::Result {
+        let request = self.routing_table_pool.send(RoutingTableMessages::ExplainRoute {
+            source: self.peer_id.clone(),
+            explanation: Box::new(self.routing_table.explain_next_hops(&msg.target)),
+        });
+        Box::pin(async move {
+            match request.await {
+                Ok(RoutingTableMessagesResponse::ExplainRouteResponse(explanation)) => {
+                    Ok(*explanation)
+                }
+                Ok(_) => Err("unexpected response from routing table actor".to_string()),
+                Err(err) => Err(err.to_string()),
+            }
+        })
     }
 }
 
@@ -1891,12 +4304,18 @@ impl Handler<GetRoutingTable> for PeerManagerActor {
 }
 
 #[cfg(feature = "test_features")]
-#[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
 impl Handler<crate::types::SetRoutingTable> for PeerManagerActor {
     type Result = ();
 
     #[perf]
     fn handle(&mut self, msg: crate::types::SetRoutingTable, ctx: &mut Self::Context) {
+        let action = format!(
+            "set routing table: add {} edges, remove {} edges, prune edges {:?}",
+            msg.add_edges.as_ref().map_or(0, Vec::len),
+            msg.remove_edges.as_ref().map_or(0, Vec::len),
+            msg.prune_edges
+        );
+        self.audit(msg.caller, action);
         if let Some(add_edges) = msg.add_edges {
             debug!(target: "network", "test_features add_edges {}", add_edges.len());
             self.add_verified_edges_to_routing_table(ctx, add_edges);
@@ -1907,8 +4326,61 @@ impl Handler<crate::types::SetRoutingTable> for PeerManagerActor {
         }
         if let Some(true) = msg.prune_edges {
             debug!(target: "network", "test_features prune_edges");
-            self.update_and_remove_edges(ctx, true, true, Duration::from_secs(2));
+            let prune = PruneConfig::forced(Duration::from_secs(2));
+            self.update_and_remove_edges(ctx, RoutingTableUpdate { prune: Some(prune) });
+        }
+    }
+}
+
+#[cfg(feature = "test_features")]
+impl Handler<SetPeerFaults> for PeerManagerActor {
+    type Result = ();
+
+    #[perf]
+    fn handle(&mut self, msg: SetPeerFaults, _ctx: &mut Self::Context) {
+        debug!(target: "network", "test_features set faults of {}: {:?}", msg.peer_id, msg.faults);
+        if let Some(active_peer) = self.active_peers.get(&msg.peer_id) {
+            active_peer.addr.do_send(msg.faults.clone());
         }
+        self.adv_peer_faults.insert(msg.peer_id, msg.faults);
+    }
+}
+
+#[cfg(feature = "test_features")]
+impl Handler<GetRoutingTableSnapshot> for PeerManagerActor {
+    type Result = RoutingTableSnapshot;
+
+    #[perf]
+    fn handle(
+        &mut self,
+        _msg: GetRoutingTableSnapshot,
+        _ctx: &mut Self::Context,
+    ) -> RoutingTableSnapshot {
+        self.routing_table.snapshot()
+    }
+}
+
+#[cfg(feature = "test_features")]
+impl Handler<RestoreRoutingTableSnapshot> for PeerManagerActor {
+    type Result = ();
+
+    #[perf]
+    fn handle(&mut self, msg: RestoreRoutingTableSnapshot, ctx: &mut Self::Context) {
+        let num_edges = msg.snapshot.edges.len();
+        debug!(target: "network", "test_features restore routing table snapshot {}", num_edges);
+        self.audit(msg.caller, format!("restore routing table snapshot with {} edges", num_edges));
+        *self.routing_table_exchange_helper.edges_info_shared.lock().unwrap() = msg
+            .snapshot
+            .edges
+            .iter()
+            .map(|edge| ((edge.peer0.clone(), edge.peer1.clone()), edge.nonce))
+            .collect();
+        self.routing_table_pool
+            .send(RoutingTableMessages::ReplaceEdges(msg.snapshot.edges.clone()))
+            .into_actor(self)
+            .map(|_, _, _| ())
+            .spawn(ctx);
+        self.routing_table.restore(msg.snapshot);
     }
 }
 
@@ -1930,7 +4402,26 @@ impl Handler<OutboundTcpConnect> for PeerManagerActor {
         #[cfg(feature = "delay_detector")]
         let _d = DelayDetector::new("outbound tcp connect".into());
         debug!(target: "network", "Trying to connect to {}", msg.peer_info);
-        if let Some(addr) = msg.peer_info.addr {
+        let target = match (&self.config.proxy, msg.peer_info.addr) {
+            (Some(_), _) if self.config.peer_hosts.contains_key(&msg.peer_info.id) => {
+                ProxyTarget::Host(self.config.peer_hosts[&msg.peer_info.id].clone())
+            }
+            (_, Some(addr)) if !self.config.ip_family.allows(&addr) => {
+                debug!(target: "network", "Not connecting to {}, address family of {} not allowed", msg.peer_info.id, addr);
+                self.outgoing_peers.remove(&msg.peer_info.id);
+                return;
+            }
+            (_, Some(addr)) => ProxyTarget::Addr(addr),
+            (_, None) => {
+                warn!(target: "network", "Trying to connect to peer with no public address: {:?}", msg.peer_info);
+                return;
+            }
+        };
+        let connect = match (self.config.proxy.clone(), target.clone()) {
+            (Some(proxy), target) => async move {
+                tokio::time::timeout(PROXY_CONNECT_TIMEOUT, proxy::connect(&proxy, &target)).await
+            }
+            .boxed(),
             // The `connect` may take several minutes. This happens when the
             // `SYN` packet for establishing a TCP connection gets silently
             // dropped, in which case the default TCP timeout is applied. That's
@@ -1939,39 +4430,57 @@ impl Handler<OutboundTcpConnect> for PeerManagerActor {
             // Why exactly a second? It was hard-coded in a library we used
             // before, so we keep it to preserve behavior. Removing the timeout
             // completely was observed to break stuff for real on the testnet.
-            tokio::time::timeout(Duration::from_secs(1), TcpStream::connect(addr))
-                .into_actor(self)
-                .then(move |res, act, ctx| match res {
-                    Ok(res) => match res {
-                        Ok(stream) => {
-                            debug!(target: "network", "Connecting to {}", msg.peer_info);
-                            let edge_info = act.propose_edge(msg.peer_info.id.clone(), None);
-
-                            act.try_connect_peer(
-                                ctx.address(),
-                                stream,
-                                PeerType::Outbound,
-                                Some(msg.peer_info),
-                                Some(edge_info),
-                            );
-                            actix::fut::ready(())
-                        }
-                        Err(err) => {
-                            info!(target: "network", "Error connecting to {}: {}", addr, err);
-                            act.outgoing_peers.remove(&msg.peer_info.id);
-                            actix::fut::ready(())
-                        }
-                    },
-                    Err(err) => {
-                        info!(target: "network", "Error connecting to {}: {}", addr, err);
-                        act.outgoing_peers.remove(&msg.peer_info.id);
-                        actix::fut::ready(())
+            (None, ProxyTarget::Addr(addr)) => {
+                tokio::time::timeout(Duration::from_secs(1), TcpStream::connect(addr)).boxed()
+            }
+            (None, ProxyTarget::Host(_)) => {
+                unreachable!("peers are only connected to by host name through a proxy")
+            }
+        };
+        // Connections with pinned peers use TLS.
+        let tls = self.tls.clone();
+        let peer_id = msg.peer_info.id.clone();
+        let handshake_timeout = self.config.handshake_timeout;
+        let connect = async move {
+            let stream = connect.await??;
+            let stream = match tls {
+                Some(tls) => {
+                    tokio::time::timeout(handshake_timeout, tls.connect(stream, &peer_id)).await??
+                }
+                None => PeerStream::Tcp(stream),
+            };
+            Ok::<_, std::io::Error>(stream)
+        };
+        connect
+            .into_actor(self)
+            .then(move |res, act, ctx| match res {
+                Ok(stream) => {
+                    debug!(target: "network", "Connecting to {}", msg.peer_info);
+                    let edge_info = act.propose_edge(msg.peer_info.id.clone(), None, None);
+                    let tls_peer_id = matches!(stream, PeerStream::TlsClient(_))
+                        .then(|| msg.peer_info.id.clone());
+
+                    act.try_connect_peer(
+                        ctx.address(),
+                        stream,
+                        PeerType::Outbound,
+                        Some(msg.peer_info),
+                        Some(edge_info),
+                        tls_peer_id,
+                    );
+                    actix::fut::ready(())
+                }
+                Err(err) => {
+                    info!(target: "network", "Error connecting to {}: {}", target, err);
+                    act.outgoing_peers.remove(&msg.peer_info.id);
+                    if let Err(err) = act.peer_store.peer_handshake_failed(&msg.peer_info.id) {
+                        error!(target: "network", "Failed to save peer data: {}", err);
                     }
-                })
-                .wait(ctx);
-        } else {
-            warn!(target: "network", "Trying to connect to peer with no public address: {:?}", msg.peer_info);
-        }
+                    actix::fut::ready(())
+                }
+            })
+            // Proxied connects and TLS handshakes take a while, don't block the actor on them.
+            .spawn(ctx);
     }
 }
 
@@ -1994,12 +4503,27 @@ impl Handler<Consolidate> for PeerManagerActor {
             return ConsolidateResponse::Reject;
         }
 
-        // We already connected to this peer.
-        if self.active_peers.contains_key(&msg.peer_info.id) {
-            debug!(target: "network", "Dropping handshake (Active Peer). {:?} {:?}", self.peer_id, msg.peer_info.id);
-            return ConsolidateResponse::Reject;
+        if let Some(tls) = &self.tls {
+            if !tls.allows(&msg.peer_info.id, msg.tls_peer_id.as_ref()) {
+                debug!(target: "network", "Dropping connection from {} (TLS certificate not pinned to it)", msg.peer_info.id);
+                return ConsolidateResponse::Reject;
+            }
         }
 
+        // We already connected to this peer. If it connects again from another address, the
+        // connection we have is likely stale and is replaced by the new one.
+        let migrating = match self.active_peers.get(&msg.peer_info.id) {
+            Some(active_peer) => {
+                let old_addr = active_peer.full_peer_info.peer_info.addr;
+                if !is_address_change(old_addr, msg.peer_info.addr) {
+                    debug!(target: "network", "Dropping handshake (Active Peer). {:?} {:?}", self.peer_id, msg.peer_info.id);
+                    return ConsolidateResponse::Reject;
+                }
+                true
+            }
+            None => false,
+        };
+
         // This is incoming connection but we have this peer already in outgoing.
         // This only happens when both of us connect at the same time, break tie using higher peer id.
         if msg.peer_type == PeerType::Inbound && self.outgoing_peers.contains(&msg.peer_info.id) {
@@ -2010,12 +4534,36 @@ impl Handler<Consolidate> for PeerManagerActor {
             }
         }
 
-        if msg.peer_type == PeerType::Inbound && !self.is_inbound_allowed() {
+        // Nodes behind relays only talk to their relays.
+        if !self.config.relays.is_empty() && !self.is_relay(&msg.peer_info.id) {
+            debug!(target: "network", "Dropping connection from {} (not one of our relays)", msg.peer_info.id);
+            return ConsolidateResponse::Reject;
+        }
+
+        let whitelisted =
+            self.is_peer_whitelisted(&msg.peer_info) || self.anchors.is_anchor(&msg.peer_info);
+        if msg.peer_type == PeerType::Inbound
+            && !self.is_inbound_allowed()
+            && !self.tier1.contains(&msg.peer_info.id)
+            && !whitelisted
+            && !migrating
+        {
             // TODO(1896): Gracefully drop inbound connection for other peer.
             debug!(target: "network", "Inbound connection dropped (network at max capacity).");
             return ConsolidateResponse::Reject;
         }
 
+        // Connections accepted concurrently from the same subnet are only counted here.
+        if msg.peer_type == PeerType::Inbound
+            && msg.peer_info.addr.as_ref().map_or(false, |addr| self.is_inbound_subnet_full(addr))
+            && !self.tier1.contains(&msg.peer_info.id)
+            && !whitelisted
+            && !migrating
+        {
+            debug!(target: "network", "Inbound connection dropped (too many peers from subnet of {:?}).", msg.peer_info.addr);
+            return ConsolidateResponse::Reject;
+        }
+
         if msg.other_edge_info.nonce == 0 {
             debug!(target: "network", "Invalid nonce. It must be greater than 0. nonce={}", msg.other_edge_info.nonce);
             return ConsolidateResponse::Reject;
@@ -2039,11 +4587,26 @@ impl Handler<Consolidate> for PeerManagerActor {
         let require_response = msg.this_edge_info.is_none();
 
         let edge_info = msg.this_edge_info.clone().unwrap_or_else(|| {
-            self.propose_edge(msg.peer_info.id.clone(), Some(msg.other_edge_info.nonce))
+            self.propose_edge(
+                msg.peer_info.id.clone(),
+                Some(msg.other_edge_info.nonce),
+                Some(msg.peer_protocol_version),
+            )
         });
 
         let edge_info_response = if require_response { Some(edge_info.clone()) } else { None };
 
+        // The new connection has a newer edge, so it's the one the peer is using.
+        if migrating {
+            self.migrate_connection(ctx, &msg.peer_info);
+        }
+
+        // Peers learn the sub-key before the edge signed with it, which is broadcast once the
+        // peer is registered.
+        if let Some(delegation) = msg.sub_key_delegation.clone() {
+            self.add_sub_key_delegations(vec![delegation], Some(&msg.peer_info.id));
+        }
+
         // TODO: double check that address is connectable and add account id.
         self.register_peer(
             FullPeerInfo {
@@ -2055,6 +4618,7 @@ impl Handler<Consolidate> for PeerManagerActor {
             msg.peer_type,
             msg.actor,
             msg.peer_protocol_version,
+            msg.capabilities,
             ctx,
         );
 
@@ -2091,7 +4655,44 @@ impl Handler<PeersRequest> for PeerManagerActor {
     fn handle(&mut self, msg: PeersRequest, _ctx: &mut Self::Context) -> Self::Result {
         #[cfg(feature = "delay_detector")]
         let _d = DelayDetector::new("peers request".into());
-        PeerRequestResult { peers: self.peer_store.healthy_peers(self.config.max_send_peers) }
+        // Don't give away the address of nodes hiding it behind relays.
+        let mut peers = self.peer_store.healthy_peers(self.config.max_send_peers);
+        let routing_table = &mut self.routing_table;
+        peers.retain(|peer_info| routing_table.proxy_relays(&peer_info.id).is_none());
+        PeerRequestResult { peers }
+    }
+}
+
+impl Handler<CheckReachability> for PeerManagerActor {
+    type Result = CheckReachabilityResult;
+
+    #[perf]
+    fn handle(&mut self, msg: CheckReachability, _ctx: &mut Self::Context) -> Self::Result {
+        let routing_table = &self.routing_table;
+        let reachable = msg
+            .targets
+            .into_iter()
+            .take(MAX_REACHABILITY_PROBE_TARGETS)
+            .filter(|target| routing_table.peer_forwarding.contains_key(target))
+            .collect();
+        CheckReachabilityResult { reachable }
+    }
+}
+
+impl Handler<ReachabilityProbeResponse> for PeerManagerActor {
+    type Result = ();
+
+    #[perf]
+    fn handle(&mut self, msg: ReachabilityProbeResponse, _ctx: &mut Self::Context) {
+        let routing_table = &self.routing_table;
+        let result = self.reachability_probes.on_response(&msg.peer_id, msg.reachable, |target| {
+            routing_table.peer_forwarding.contains_key(target)
+        });
+        if let Some(result) = result {
+            if !result.unreachable_by_us.is_empty() || !result.unreachable_by_peer.is_empty() {
+                info!(target: "network", "Routing table diverges from the one of {}: {} of {} probed peers are only reachable by it, {} only by us", msg.peer_id, result.unreachable_by_us.len(), result.targets, result.unreachable_by_peer.len());
+            }
+        }
     }
 }
 
@@ -2135,6 +4736,9 @@ impl Handler<RoutedMessageFrom> for PeerManagerActor {
             match &msg.body {
                 RoutedMessageBody::Ping(ping) => self.handle_ping(ctx, ping.clone(), msg.hash()),
                 RoutedMessageBody::Pong(pong) => self.handle_pong(ctx, pong.clone()),
+                RoutedMessageBody::RouteNotFound(response) => {
+                    self.handle_route_not_found(&msg.author, response.clone())
+                }
                 _ => return true,
             }
 
@@ -2143,6 +4747,10 @@ impl Handler<RoutedMessageFrom> for PeerManagerActor {
             if msg.decrease_ttl() {
                 self.send_signed_message_to_peer(ctx, msg);
             } else {
+                near_metrics::inc_counter_vec(
+                    &metrics::ROUTED_MESSAGE_TTL_EXPIRED,
+                    &[strum::AsStaticRef::as_static(&msg.body)],
+                );
                 warn!(target: "network", "Message dropped because TTL reached 0. Message: {:?} From: {:?}", msg, from);
             }
             false
@@ -2159,10 +4767,16 @@ impl Handler<RawRoutedMessage> for PeerManagerActor {
         let _d = DelayDetector::new(
             format!("raw routed message {}", strum::AsStaticRef::as_static(&msg.body)).into(),
         );
-        if let AccountOrPeerIdOrHash::AccountId(target) = msg.target {
-            self.send_message_to_account(ctx, &target, msg.body);
-        } else {
-            self.send_message_to_peer(ctx, msg);
+        match msg.target {
+            AccountOrPeerIdOrHash::AccountId(target) => {
+                self.send_message_to_account(ctx, &target, msg.body);
+            }
+            AccountOrPeerIdOrHash::ShardValidator(shard_id) => {
+                self.send_message_to_shard_validator(ctx, shard_id, msg.body);
+            }
+            _ => {
+                self.send_message_to_peer(ctx, msg);
+            }
         }
     }
 }
@@ -2176,7 +4790,7 @@ impl Handler<PeerRequest> for PeerManagerActor {
         let _d = DelayDetector::new(format!("peer request {}", msg.as_ref()).into());
         match msg {
             PeerRequest::UpdateEdge((peer, nonce)) => {
-                PeerResponse::UpdatedEdge(self.propose_edge(peer, Some(nonce)))
+                PeerResponse::UpdatedEdge(self.propose_edge(peer, Some(nonce), None))
             }
             PeerRequest::RouteBack(body, target) => {
                 trace!(target: "network", "Sending message to route back: {:?}", target);
@@ -2198,38 +4812,130 @@ impl Handler<PeerRequest> for PeerManagerActor {
                 }
                 PeerResponse::NoResponse
             }
+            PeerRequest::Misbehavior(peer_id, misbehavior, count) => {
+                self.penalize_peer(ctx, &peer_id, misbehavior, count);
+                PeerResponse::NoResponse
+            }
         }
     }
 }
 
-#[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
 impl PeerManagerActor {
     fn process_ibf_msg(
         &mut self,
         ctx: &mut Context<PeerManagerActor>,
         peer_id: &PeerId,
         mut ibf_msg: RoutingVersion2,
+        hasher_kind: IbfHasherKind,
         addr: Addr<Peer>,
+        force_full_sync: bool,
     ) {
         let mut edges: Vec<Edge> = Vec::new();
         swap(&mut edges, &mut ibf_msg.edges);
         self.verify_edges(ctx, peer_id.clone(), edges);
+
+        // The peer initiating the sync tells how many edges it knows. If the tables are too small
+        // for IBF, send all our edges and ask for all of theirs instead. We do the same at any
+        // step of the IBF sync if it is disabled in our config, or if the routing table actor
+        // can't continue it (`force_full_sync`).
+        let full_sync = match ibf_msg.routing_state {
+            _ if force_full_sync => true,
+            RoutingState::RequestAllEdges | RoutingState::Done => false,
+            _ if !self.config.ibf_sync_enabled => true,
+            RoutingState::InitializeIbf => prefer_full_sync(
+                ibf_msg.known_edges,
+                self.routing_table.get_edges_len(),
+                self.config.ibf_sync_min_edges,
+            ),
+            _ => false,
+        };
+        let heavy_sync =
+            full_sync || matches!(ibf_msg.routing_state, RoutingState::RequestAllEdges);
+        if heavy_sync {
+            match self.heavy_sync_status(peer_id) {
+                HeavySyncStatus::PeerGone => return,
+                HeavySyncStatus::Wait => {
+                    near_metrics::inc_counter(&metrics::HEAVY_SYNCS_DEFERRED);
+                    let peer_id = peer_id.clone();
+                    near_performance_metrics::actix::run_later(
+                        ctx,
+                        WAIT_FOR_SYNC_DELAY,
+                        move |act, ctx| {
+                            act.process_ibf_msg(
+                                ctx,
+                                &peer_id,
+                                ibf_msg,
+                                hasher_kind,
+                                addr,
+                                force_full_sync,
+                            )
+                        },
+                    );
+                    return;
+                }
+                HeavySyncStatus::Ready => self.heavy_syncs_in_progress += 1,
+            }
+        }
+        if full_sync {
+            debug!(target: "network", "Exchanging all edges with {}: it knows {} edges", peer_id, ibf_msg.known_edges);
+            // The routing table actor answers with all our edges.
+            ibf_msg.routing_state = RoutingState::RequestAllEdges;
+        }
+        let (peer_id, seed, known_edges) = (peer_id.clone(), ibf_msg.seed, ibf_msg.known_edges);
         self.routing_table_pool
-            .send(RoutingTableMessages::ProcessIbfMessage { peer_id: peer_id.clone(), ibf_msg })
+            .send(RoutingTableMessages::ProcessIbfMessage {
+                peer_id: peer_id.clone(),
+                ibf_msg,
+                hasher_kind,
+            })
             .into_actor(self)
-            .map(move |response, _act2: &mut PeerManagerActor, _ctx2| match response {
-                Ok(RoutingTableMessagesResponse::ProcessIbfMessageResponse {
-                    ibf_msg: response_ibf_msg,
-                }) => {
-                    if let Some(response_ibf_msg) = response_ibf_msg {
-                        let _ = addr.do_send(SendMessage {
-                            message: PeerMessage::RoutingTableSyncV2(RoutingSyncV2::Version2(
-                                response_ibf_msg,
-                            )),
-                        });
+            .map(move |response, act2: &mut PeerManagerActor, ctx2| {
+                let mut response_ibf_msg = match response {
+                    Ok(RoutingTableMessagesResponse::ProcessIbfMessageResponse {
+                        ibf_msg: Some(response_ibf_msg),
+                    }) => response_ibf_msg,
+                    Ok(RoutingTableMessagesResponse::ProcessIbfMessageFullSync) if !full_sync => {
+                        // Sending all edges is a heavy sync like any other.
+                        debug!(target: "network", "Falling back to exchanging all edges with {}", peer_id);
+                        let ibf_msg = RoutingVersion2 {
+                            known_edges,
+                            seed,
+                            edges: Default::default(),
+                            routing_state: RoutingState::RequestAllEdges,
+                        };
+                        act2.process_ibf_msg(ctx2, &peer_id, ibf_msg, hasher_kind, addr, true);
+                        return;
                     }
+                    response => {
+                        if !matches!(
+                            response,
+                            Ok(RoutingTableMessagesResponse::ProcessIbfMessageResponse { .. })
+                        ) {
+                            error!(target: "network", "expected ProcessIbfMessageResponse");
+                        }
+                        if heavy_sync {
+                            act2.heavy_syncs_in_progress -= 1;
+                        }
+                        return;
+                    }
+                };
+                if full_sync {
+                    response_ibf_msg.routing_state = RoutingState::RequestAllEdges;
                 }
-                _ => error!(target: "network", "expected ProcessIbfMessageResponse"),
+                let message = SendMessage {
+                    message: PeerMessage::RoutingTableSyncV2(RoutingSyncV2::Version2(
+                        response_ibf_msg,
+                    )),
+                };
+                if !heavy_sync {
+                    let _ = addr.do_send(message);
+                    return;
+                }
+                // The sync is in progress until the peer actor encoded all our edges.
+                addr.send(message)
+                    .into_actor(act2)
+                    .map(|_, act3, _| act3.heavy_syncs_in_progress -= 1)
+                    .spawn(ctx2);
             })
             .spawn(ctx);
     }
@@ -0,0 +1,195 @@
+//! Coarse regions of peer addresses, read from a file provided by the operator, used to export
+//! the number of reachable peers and the latency of active peers per region.
+//!
+//! Every line of the file maps a network to a region, e.g. `10.0.0.0/8 eu-west`. Empty lines and
+//! lines starting with `#` are ignored. Addresses matching several networks get the region of the
+//! most specific one, addresses matching none are reported as `unknown`.
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+
+use near_network_primitives::types::canonical_addr;
+
+use crate::metrics;
+
+/// Region of addresses which don't match any network.
+const UNKNOWN_REGION: &str = "unknown";
+/// Quantiles of the latency exported per region, with their label.
+const LATENCY_QUANTILES: [(&str, f64); 3] = [("p50", 0.5), ("p90", 0.9), ("p99", 0.99)];
+
+#[derive(Debug, PartialEq, Eq)]
+struct Network {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl Network {
+    fn parse(s: &str) -> Result<Self, String> {
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|err| format!("invalid address {}: {}", s, err))?;
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix_len| *prefix_len <= max_len)
+                .ok_or_else(|| format!("invalid prefix length in {}", s))?,
+            None => max_len,
+        };
+        Ok(Self { addr, prefix_len })
+    }
+
+    fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix_len as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(*ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix_len as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(*ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Networks and the region they belong to.
+#[derive(Debug, Default)]
+pub struct RegionMap {
+    networks: Vec<(Network, String)>,
+}
+
+impl RegionMap {
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut networks = vec![];
+        for (line_number, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next(), words.next()) {
+                (Some(network), Some(region), None) => networks.push((
+                    Network::parse(network)
+                        .map_err(|err| format!("line {}: {}", line_number + 1, err))?,
+                    region.to_string(),
+                )),
+                _ => {
+                    return Err(format!(
+                        "line {}: expected a network and a region, got {:?}",
+                        line_number + 1,
+                        line
+                    ))
+                }
+            }
+        }
+        Ok(Self { networks })
+    }
+
+    /// Region of the most specific network containing `ip`.
+    pub fn region(&self, ip: IpAddr) -> &str {
+        let ip = canonical_addr((ip, 0).into()).ip();
+        self.networks
+            .iter()
+            .filter(|(network, _)| network.contains(&ip))
+            .max_by_key(|(network, _)| network.prefix_len)
+            .map_or(UNKNOWN_REGION, |(_, region)| region.as_str())
+    }
+}
+
+/// Number of reachable peers and latencies in milliseconds of active peers, by region.
+#[derive(Debug, Default)]
+pub(crate) struct RegionStats {
+    regions: BTreeMap<String, (i64, Vec<f64>)>,
+}
+
+impl RegionStats {
+    pub fn add_reachable(&mut self, region: &str) {
+        self.regions.entry(region.to_string()).or_default().0 += 1;
+    }
+
+    pub fn add_latency(&mut self, region: &str, latency: f64) {
+        self.regions.entry(region.to_string()).or_default().1.push(latency);
+    }
+
+    /// Replace the per region metrics with these stats.
+    pub fn export(self) {
+        for gauge in [&*metrics::REGION_REACHABLE_PEERS, &*metrics::REGION_PEER_LATENCY_MS] {
+            if let Ok(gauge) = gauge {
+                gauge.reset();
+            }
+        }
+        for (region, (reachable_peers, mut latencies)) in self.regions {
+            near_metrics::set_gauge_vec(
+                &metrics::REGION_REACHABLE_PEERS,
+                &[&region],
+                reachable_peers,
+            );
+            latencies.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            for (label, quantile) in LATENCY_QUANTILES.iter() {
+                if let Some(latency) = quantile_of_sorted(&latencies, *quantile) {
+                    near_metrics::set_gauge_vec(
+                        &metrics::REGION_PEER_LATENCY_MS,
+                        &[&region, label],
+                        latency as i64,
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Nearest-rank quantile of sorted `values`.
+fn quantile_of_sorted(values: &[f64], quantile: f64) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    let rank = (quantile * values.len() as f64).ceil() as usize;
+    Some(values[rank.saturating_sub(1).min(values.len() - 1)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{quantile_of_sorted, RegionMap};
+
+    #[test]
+    fn region_map() {
+        let map = RegionMap::parse(
+            "# Operator regions\n\
+             10.0.0.0/8 eu\n\
+             10.1.0.0/16 eu-west\n\
+             \n\
+             2001:db8::/32 us\n",
+        )
+        .unwrap();
+        assert_eq!(map.region("10.2.3.4".parse().unwrap()), "eu");
+        assert_eq!(map.region("10.1.3.4".parse().unwrap()), "eu-west");
+        // Addresses seen by dual stack listeners are mapped back to IPv4.
+        assert_eq!(map.region("::ffff:10.1.3.4".parse().unwrap()), "eu-west");
+        assert_eq!(map.region("2001:db8::1".parse().unwrap()), "us");
+        assert_eq!(map.region("192.168.0.1".parse().unwrap()), "unknown");
+
+        assert!(RegionMap::parse("10.0.0.0/33 eu").is_err());
+        assert!(RegionMap::parse("10.0.0.0/8").is_err());
+    }
+
+    #[test]
+    fn quantiles() {
+        let values: Vec<f64> = (1..=100).map(|x| x as f64).collect();
+        assert_eq!(quantile_of_sorted(&values, 0.5), Some(50.0));
+        assert_eq!(quantile_of_sorted(&values, 0.99), Some(99.0));
+        assert_eq!(quantile_of_sorted(&[7.0], 0.9), Some(7.0));
+        assert_eq!(quantile_of_sorted(&[], 0.5), None);
+    }
+}
@@ -0,0 +1,116 @@
+//! Role of a peer negotiated during handshake and the messages it may send once connected.
+use near_primitives::checked_feature;
+use near_primitives::version::ProtocolVersion;
+
+use crate::capabilities::PeerCapabilities;
+use crate::types::PeerMessage;
+
+/// Number of messages outside of its allowlist a peer may send before it gets banned.
+pub(crate) const MAX_DISALLOWED_MESSAGES: usize = 10;
+
+/// What a peer is allowed to do on a ready connection, derived from the handshake: the role the
+/// peer declared in its capabilities, or else the negotiated protocol version.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum PeerRole {
+    /// Peer speaks a protocol version without routing table exchange using IBF.
+    Legacy,
+    /// Peer negotiated routing table exchange using IBF (`RoutingTableSyncV2`).
+    RoutingV2,
+    /// Peer declared `PeerCapabilities::LIGHT_CLIENT`. It doesn't take part in routing, so it
+    /// may only request data, send transactions and route messages to nodes.
+    LightClient,
+}
+
+impl PeerRole {
+    /// Role of a peer speaking `protocol_version`, which advertised `capabilities` in its
+    /// handshake (empty if its version predates them).
+    pub(crate) fn negotiate(
+        protocol_version: ProtocolVersion,
+        capabilities: PeerCapabilities,
+    ) -> Self {
+        if capabilities.contains(PeerCapabilities::LIGHT_CLIENT) {
+            PeerRole::LightClient
+        } else if checked_feature!("stable", RoutingExchangeAlgorithm, protocol_version) {
+            PeerRole::RoutingV2
+        } else {
+            PeerRole::Legacy
+        }
+    }
+
+    /// Whether a peer with this role may send `msg` after the handshake is done.
+    pub(crate) fn is_allowed(&self, msg: &PeerMessage) -> bool {
+        match msg {
+            // Duplicate handshakes are harmless, they are ignored.
+            PeerMessage::Handshake(_) | PeerMessage::HandshakeV2(_) => true,
+            // Other handshake messages are only valid while the connection is being established.
+            PeerMessage::HandshakeFailure(_, _) | PeerMessage::LastEdge(_) => false,
+            PeerMessage::RoutingTableSyncV2(_) => *self == PeerRole::RoutingV2,
+            PeerMessage::PeersRequest
+            | PeerMessage::BlockHeadersRequest(_)
+            | PeerMessage::BlockRequest(_)
+            | PeerMessage::Transaction(_)
+            | PeerMessage::Routed(_)
+            | PeerMessage::Disconnect
+            | PeerMessage::EpochSyncRequest(_)
+            | PeerMessage::EpochSyncFinalizationRequest(_) => true,
+            // Compact messages are unwrapped before this check, the inner message is checked.
+            PeerMessage::Compact(_) => false,
+            // Routing and chain data only come from full nodes.
+            _ => *self != PeerRole::LightClient,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_primitives::version::ProtocolFeature;
+
+    use crate::capabilities::PeerCapabilities;
+    use crate::routing::Edge;
+    use crate::test_utils::random_peer_id;
+    use crate::types::{PeerMessage, RoutingState, RoutingSyncV2, RoutingVersion2, SyncData};
+
+    use super::PeerRole;
+
+    #[test]
+    fn allowlist_by_role() {
+        let sync_v2 = PeerMessage::RoutingTableSyncV2(RoutingSyncV2::Version2(RoutingVersion2 {
+            known_edges: 0,
+            seed: 0,
+            edges: vec![],
+            routing_state: RoutingState::Done,
+        }));
+        let last_edge =
+            PeerMessage::LastEdge(Edge::make_fake_edge(random_peer_id(), random_peer_id(), 1));
+
+        let sync = PeerMessage::RoutingTableSync(SyncData::edge(Edge::make_fake_edge(
+            random_peer_id(),
+            random_peer_id(),
+            1,
+        )));
+
+        for role in [PeerRole::Legacy, PeerRole::RoutingV2, PeerRole::LightClient].iter() {
+            assert!(role.is_allowed(&PeerMessage::PeersRequest));
+            assert!(role.is_allowed(&PeerMessage::Disconnect));
+            assert!(!role.is_allowed(&last_edge));
+        }
+        assert!(!PeerRole::Legacy.is_allowed(&sync_v2));
+        assert!(PeerRole::RoutingV2.is_allowed(&sync_v2));
+        assert!(PeerRole::RoutingV2.is_allowed(&sync));
+        assert!(!PeerRole::LightClient.is_allowed(&sync_v2));
+        assert!(!PeerRole::LightClient.is_allowed(&sync));
+        assert!(!PeerRole::LightClient.is_allowed(&PeerMessage::PeersResponse(vec![])));
+    }
+
+    #[test]
+    fn negotiate_by_protocol_version() {
+        let version = ProtocolFeature::RoutingExchangeAlgorithm.protocol_version();
+        let none = PeerCapabilities::empty();
+        assert_eq!(PeerRole::negotiate(version - 1, none), PeerRole::Legacy);
+        assert_eq!(PeerRole::negotiate(version, none), PeerRole::RoutingV2);
+        assert_eq!(
+            PeerRole::negotiate(version, PeerCapabilities::LIGHT_CLIENT),
+            PeerRole::LightClient
+        );
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use actix::dev::MessageResponse;
+use cached::{Cached, SizedCache};
+#[cfg(feature = "test_features")]
+use serde::Serialize;
+
+use near_primitives::network::PeerId;
+
+use crate::time::{Clock, Time};
+use crate::types::ReasonForBan;
+
+/// Penalties decay exponentially with this half-life, so peers recover from occasional mistakes.
+pub const PEER_SCORE_HALF_LIFE: Duration = Duration::from_secs(10 * 60);
+/// Peers with a lower score are only used as next hops if there is no other route.
+pub const DEPRIORITIZE_PEER_SCORE: f64 = -50.0;
+/// Peers with this score or lower get banned.
+pub const BAN_PEER_SCORE: f64 = -200.0;
+/// Number of peers whose score is remembered, so reconnecting doesn't reset it.
+const PEER_SCORE_CACHE_SIZE: usize = 10_000;
+
+/// Behavior which lowers the score of a peer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Misbehavior {
+    /// Sent edges which failed verification.
+    InvalidEdges,
+    /// Sent account announcements which are not newer than the ones we know.
+    StaleAnnounce,
+    /// Sent the same routed message several times in a short period.
+    DuplicateMessage,
+}
+
+impl Misbehavior {
+    fn penalty(self) -> f64 {
+        match self {
+            Misbehavior::InvalidEdges => -BAN_PEER_SCORE,
+            Misbehavior::StaleAnnounce => 5.0,
+            Misbehavior::DuplicateMessage => 1.0,
+        }
+    }
+
+    pub fn ban_reason(self) -> ReasonForBan {
+        match self {
+            Misbehavior::InvalidEdges => ReasonForBan::InvalidEdge,
+            Misbehavior::StaleAnnounce | Misbehavior::DuplicateMessage => ReasonForBan::Abusive,
+        }
+    }
+}
+
+/// Score of a single peer and how many times it misbehaved.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "test_features", derive(Serialize))]
+pub struct PeerScoreInfo {
+    pub peer_id: PeerId,
+    /// Zero for peers which never misbehaved, negative otherwise.
+    pub score: f64,
+    pub invalid_edges: u64,
+    pub stale_announces: u64,
+    pub duplicate_messages: u64,
+}
+
+#[derive(MessageResponse, Debug)]
+#[cfg_attr(feature = "test_features", derive(Serialize))]
+pub struct GetPeerScoresResult {
+    /// Peers with the lowest score first.
+    pub peers: Vec<PeerScoreInfo>,
+}
+
+#[derive(Clone)]
+struct ScoreEntry {
+    info: PeerScoreInfo,
+    /// Time `info.score` was computed at.
+    updated: Instant,
+}
+
+impl ScoreEntry {
+    fn decay(&mut self, now: Instant) {
+        let elapsed = Time::duration_since(now, self.updated);
+        self.info.score *= 0.5f64.powf(elapsed.as_secs_f64() / PEER_SCORE_HALF_LIFE.as_secs_f64());
+        self.updated = now;
+    }
+}
+
+/// Reputation of peers. Misbehaving peers accumulate penalties, which are used to avoid them
+/// when routing and eventually to ban them.
+pub struct PeerScore {
+    entries: SizedCache<PeerId, ScoreEntry>,
+    clock: Clock,
+}
+
+impl Default for PeerScore {
+    fn default() -> Self {
+        Self::new(Clock::real())
+    }
+}
+
+impl PeerScore {
+    pub fn new(clock: Clock) -> Self {
+        Self { entries: SizedCache::with_size(PEER_SCORE_CACHE_SIZE), clock }
+    }
+
+    /// Penalize `peer_id` for misbehaving `count` times. Returns its new score.
+    pub fn penalize(&mut self, peer_id: &PeerId, misbehavior: Misbehavior, count: u64) -> f64 {
+        self.penalize_at(peer_id, misbehavior, count, self.clock.now())
+    }
+
+    fn penalize_at(
+        &mut self,
+        peer_id: &PeerId,
+        misbehavior: Misbehavior,
+        count: u64,
+        now: Instant,
+    ) -> f64 {
+        if self.entries.cache_get(peer_id).is_none() {
+            self.entries.cache_set(
+                peer_id.clone(),
+                ScoreEntry {
+                    info: PeerScoreInfo {
+                        peer_id: peer_id.clone(),
+                        score: 0.0,
+                        invalid_edges: 0,
+                        stale_announces: 0,
+                        duplicate_messages: 0,
+                    },
+                    updated: now,
+                },
+            );
+        }
+        let entry = self.entries.cache_get_mut(peer_id).unwrap();
+        entry.decay(now);
+        entry.info.score -= misbehavior.penalty() * count as f64;
+        match misbehavior {
+            Misbehavior::InvalidEdges => entry.info.invalid_edges += count,
+            Misbehavior::StaleAnnounce => entry.info.stale_announces += count,
+            Misbehavior::DuplicateMessage => entry.info.duplicate_messages += count,
+        }
+        entry.info.score
+    }
+
+    pub fn score(&mut self, peer_id: &PeerId) -> f64 {
+        self.score_at(peer_id, self.clock.now())
+    }
+
+    fn score_at(&mut self, peer_id: &PeerId, now: Instant) -> f64 {
+        self.entries.cache_get_mut(peer_id).map_or(0.0, |entry| {
+            entry.decay(now);
+            entry.info.score
+        })
+    }
+
+    /// Peers which should be avoided when routing.
+    pub fn deprioritized_peers(&self) -> HashSet<PeerId> {
+        self.deprioritized_peers_at(self.clock.now())
+    }
+
+    fn deprioritized_peers_at(&self, now: Instant) -> HashSet<PeerId> {
+        self.snapshot(now)
+            .into_iter()
+            .filter(|info| info.score < DEPRIORITIZE_PEER_SCORE)
+            .map(|info| info.peer_id)
+            .collect()
+    }
+
+    /// Up to `limit` peers with the lowest score.
+    pub fn worst_peers(&self, limit: usize) -> Vec<PeerScoreInfo> {
+        let mut peers = self.snapshot(self.clock.now());
+        peers.sort_by(|a, b| {
+            a.score.partial_cmp(&b.score).unwrap().then_with(|| a.peer_id.cmp(&b.peer_id))
+        });
+        peers.truncate(limit);
+        peers
+    }
+
+    /// Scores of all known peers at `now`, without touching the order of the cache.
+    fn snapshot(&self, now: Instant) -> Vec<PeerScoreInfo> {
+        self.entries
+            .value_order()
+            .map(|entry| {
+                let mut entry = entry.clone();
+                entry.decay(now);
+                entry.info
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use crate::test_utils::random_peer_id;
+    use crate::types::ReasonForBan;
+
+    use super::{Misbehavior, PeerScore, BAN_PEER_SCORE, PEER_SCORE_HALF_LIFE};
+
+    #[test]
+    fn penalties_accumulate_and_decay() {
+        let now = Instant::now();
+        let (peer0, peer1) = (random_peer_id(), random_peer_id());
+        let mut scores = PeerScore::default();
+
+        assert_eq!(scores.score_at(&peer0, now), 0.0);
+        assert_eq!(scores.penalize_at(&peer0, Misbehavior::DuplicateMessage, 40, now), -40.0);
+        assert_eq!(scores.penalize_at(&peer0, Misbehavior::StaleAnnounce, 4, now), -60.0);
+        assert!(scores.deprioritized_peers_at(now).contains(&peer0));
+
+        let later = now + PEER_SCORE_HALF_LIFE;
+        assert!((scores.score_at(&peer0, later) + 30.0).abs() < 1e-9);
+        assert!(scores.deprioritized_peers_at(later).is_empty());
+
+        let score = scores.penalize_at(&peer1, Misbehavior::InvalidEdges, 1, later);
+        assert!(score <= BAN_PEER_SCORE);
+        assert_eq!(Misbehavior::InvalidEdges.ban_reason(), ReasonForBan::InvalidEdge);
+
+        let worst = scores.worst_peers(1);
+        assert_eq!(worst.len(), 1);
+        assert_eq!(worst[0].peer_id, peer1);
+        assert_eq!(worst[0].invalid_edges, 1);
+    }
+}
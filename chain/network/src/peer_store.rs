@@ -1,20 +1,23 @@
+use std::cmp;
 use std::collections::{
     hash_map::{Entry, Iter},
     HashMap,
 };
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 
-use borsh::BorshSerialize;
-use chrono::Utc;
+use borsh::{BorshDeserialize, BorshSerialize};
 use rand::seq::SliceRandom;
 use rand::thread_rng;
 use tracing::{debug, error};
 
 use near_primitives::network::PeerId;
+use near_primitives::types::BlockHeight;
 use near_primitives::utils::to_timestamp;
-use near_store::{ColPeers, Store};
+use near_store::{ColPeerQuality, ColPeers, Store};
 
+use crate::time::Clock;
 use crate::types::{KnownPeerState, KnownPeerStatus, NetworkConfig, PeerInfo, ReasonForBan};
 
 /// Level of trust we have about a new (PeerId, Addr) pair.
@@ -43,20 +46,167 @@ impl VerifiedPeer {
     }
 }
 
+/// Weight of a new round trip time in the average kept in `PeerQuality`.
+const RTT_EWMA_WEIGHT: f64 = 0.3;
+/// Round trip time in milliseconds which halves the reliability of a peer.
+const RELIABILITY_RTT_SCALE_MS: f64 = 200.0;
+/// Backoff after the first failed outbound connection to a peer, doubled on each further failure.
+const MIN_RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+/// Maximum backoff between outbound connection attempts to a peer.
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(30 * 60);
+/// Minimum number of attempts in an hour of the day for its own success rate to be used.
+const MIN_HOURLY_DIALS: u32 = 3;
+const NANOS_PER_HOUR: u64 = 3_600_000_000_000;
+
+/// Connection quality history of a peer, kept across restarts so we prefer peers which were
+/// reliable when choosing outbound connections.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PeerQuality {
+    /// Timestamp of the first connection attempt to or from the peer.
+    pub first_recorded: u64,
+    /// Total time we were connected to the peer in nanoseconds, excluding the current connection.
+    pub connected_ns: u64,
+    /// Number of connections established with the peer.
+    pub connections: u32,
+    /// Number of outbound connections to the peer which failed before the handshake completed.
+    pub handshake_failures: u32,
+    /// Moving average of the round trip time to the peer in microseconds, if it was measured.
+    pub avg_rtt_us: Option<u64>,
+    /// Height of the peer when we were last connected to it.
+    pub last_seen_height: Option<BlockHeight>,
+    /// Outbound connection attempts and successes by hour of the day (UTC), empty until the
+    /// first attempt.
+    pub hourly_dials: Vec<(u32, u32)>,
+    /// Outbound connection attempts which failed in a row.
+    pub consecutive_failures: u32,
+    /// Timestamp of the last failed outbound connection attempt.
+    pub last_failure: u64,
+}
+
+impl PeerQuality {
+    fn new(now: u64) -> Self {
+        Self {
+            first_recorded: now,
+            connected_ns: 0,
+            connections: 0,
+            handshake_failures: 0,
+            avg_rtt_us: None,
+            last_seen_height: None,
+            hourly_dials: vec![],
+            consecutive_failures: 0,
+            last_failure: 0,
+        }
+    }
+
+    /// Record an outbound connection attempt made at `now`.
+    fn record_dial(&mut self, now: u64, success: bool) {
+        if self.hourly_dials.is_empty() {
+            self.hourly_dials = vec![(0, 0); 24];
+        }
+        let (attempts, successes) = &mut self.hourly_dials[hour_of_day(now)];
+        *attempts += 1;
+        if success {
+            *successes += 1;
+            self.consecutive_failures = 0;
+        } else {
+            self.consecutive_failures += 1;
+            self.last_failure = now;
+        }
+    }
+
+    /// Estimated probability that an outbound connection made at `now` succeeds: the success
+    /// rate at this hour of the day if we dialed often enough then, otherwise overall.
+    pub fn dial_success_rate(&self, now: u64) -> f64 {
+        let rate =
+            |attempts: u32, successes: u32| (successes as f64 + 1.0) / (attempts as f64 + 2.0);
+        match self.hourly_dials.get(hour_of_day(now)) {
+            Some(&(attempts, successes)) if attempts >= MIN_HOURLY_DIALS => {
+                rate(attempts, successes)
+            }
+            _ => {
+                let (attempts, successes) = self
+                    .hourly_dials
+                    .iter()
+                    .fold((0, 0), |(a, s), (attempts, successes)| (a + attempts, s + successes));
+                rate(attempts, successes)
+            }
+        }
+    }
+
+    /// Time to wait after the last failed outbound connection before dialing the peer again.
+    /// Doubles with each failure in a row, and is shorter for peers which usually accept
+    /// connections at this time of the day.
+    pub fn reconnect_backoff(&self, now: u64) -> Duration {
+        if self.consecutive_failures == 0 {
+            return Duration::ZERO;
+        }
+        let base = MIN_RECONNECT_BACKOFF * 2u32.pow(cmp::min(self.consecutive_failures - 1, 16));
+        let factor = (2.0 * (1.0 - self.dial_success_rate(now))).max(0.1);
+        cmp::min(base.mul_f64(factor), MAX_RECONNECT_BACKOFF)
+    }
+
+    /// Whether the peer shouldn't be dialed at `now`, see `reconnect_backoff`.
+    pub fn is_backing_off(&self, now: u64) -> bool {
+        let backoff = self.reconnect_backoff(now).as_nanos() as u64;
+        now < self.last_failure.saturating_add(backoff)
+    }
+
+    /// Fraction of the time since `first_recorded` we were connected to the peer, counting the
+    /// current connection which started at `connected_since`. `None` if we never connected.
+    pub fn uptime_ratio(&self, now: u64, connected_since: Option<u64>) -> Option<f64> {
+        if self.connections == 0 || now <= self.first_recorded {
+            return None;
+        }
+        let current = connected_since.map_or(0, |since| now.saturating_sub(since));
+        let ratio = (self.connected_ns + current) as f64 / (now - self.first_recorded) as f64;
+        Some(ratio.min(1.0))
+    }
+
+    /// Score in `(0, 1]` of how reliable the peer was: the share of successful handshakes, the
+    /// uptime and the round trip time all count. Peers without history score 0.25.
+    pub fn reliability(&self, now: u64) -> f64 {
+        let attempts = self.connections + self.handshake_failures;
+        let handshakes = (self.connections as f64 + 1.0) / (attempts as f64 + 2.0);
+        let uptime = self.uptime_ratio(now, None).unwrap_or(0.0);
+        let rtt = self.avg_rtt_us.map_or(1.0, |rtt_us| {
+            RELIABILITY_RTT_SCALE_MS / (RELIABILITY_RTT_SCALE_MS + rtt_us as f64 / 1000.0)
+        });
+        handshakes * (0.5 + uptime / 2.0) * rtt
+    }
+}
+
+fn hour_of_day(timestamp: u64) -> usize {
+    ((timestamp / NANOS_PER_HOUR) % 24) as usize
+}
+
 /// Known peers store, maintaining cache of known peers and connection to storage to save/load them.
 pub struct PeerStore {
     store: Arc<Store>,
     peer_states: HashMap<PeerId, KnownPeerState>,
+    /// Connection quality history, see `PeerQuality`.
+    quality: HashMap<PeerId, PeerQuality>,
+    /// Start of the current connection of each connected peer.
+    connected_since: HashMap<PeerId, u64>,
     // This is a reverse index, from physical address to peer_id
     // It can happens that some peers don't have known address, so
     // they will not be present in this list, otherwise they will be present.
     addr_peers: HashMap<SocketAddr, VerifiedPeer>,
+    clock: Clock,
 }
 
 impl PeerStore {
     pub fn new(
         store: Arc<Store>,
         boot_nodes: &[PeerInfo],
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::new_with_clock(store, boot_nodes, Clock::real())
+    }
+
+    /// Like `new`, but reads the time from `clock` instead of the system clock.
+    pub fn new_with_clock(
+        store: Arc<Store>,
+        boot_nodes: &[PeerInfo],
+        clock: Clock,
     ) -> Result<Self, Box<dyn std::error::Error>> {
         let mut peer_states = HashMap::default();
         let mut addr_peers = HashMap::default();
@@ -87,7 +237,7 @@ impl PeerStore {
             let peer_id: PeerId = key.try_into()?;
             let mut peer_state: KnownPeerState = value.try_into()?;
             // Mark loaded node last seen to now, to avoid deleting them as soon as they are loaded.
-            peer_state.last_seen = to_timestamp(Utc::now());
+            peer_state.last_seen = to_timestamp(clock.now_utc());
             match peer_state.status {
                 KnownPeerStatus::Banned(_, _) => {}
                 _ => peer_state.status = KnownPeerStatus::NotConnected,
@@ -108,7 +258,19 @@ impl PeerStore {
                 }
             }
         }
-        Ok(PeerStore { store, peer_states, addr_peers })
+        let mut quality = HashMap::default();
+        for (key, value) in store.iter(ColPeerQuality) {
+            let peer_id = PeerId::try_from_slice(key.as_ref())?;
+            quality.insert(peer_id, PeerQuality::try_from_slice(value.as_ref())?);
+        }
+        Ok(PeerStore {
+            store,
+            peer_states,
+            quality,
+            connected_since: HashMap::default(),
+            addr_peers,
+            clock,
+        })
     }
 
     pub fn len(&self) -> usize {
@@ -119,34 +281,114 @@ impl PeerStore {
         self.peer_states.is_empty()
     }
 
+    pub fn get(&self, peer_id: &PeerId) -> Option<&KnownPeerState> {
+        self.peer_states.get(peer_id)
+    }
+
     pub fn is_banned(&self, peer_id: &PeerId) -> bool {
         self.peer_states
             .get(&peer_id)
             .map_or(false, |known_peer_state| known_peer_state.status.is_banned())
     }
 
+    /// Record a connection to `peer_info`, which we dialed if `outbound`.
     pub fn peer_connected(
         &mut self,
         peer_info: &PeerInfo,
+        outbound: bool,
     ) -> Result<(), Box<dyn std::error::Error>> {
         self.add_trusted_peer(peer_info.clone(), TrustLevel::Signed)?;
+        let now = to_timestamp(self.clock.now_utc());
         let entry = self.peer_states.get_mut(&peer_info.id).unwrap();
-        entry.last_seen = to_timestamp(Utc::now());
+        entry.last_seen = now;
         entry.status = KnownPeerStatus::Connected;
+        let quality =
+            self.quality.entry(peer_info.id.clone()).or_insert_with(|| PeerQuality::new(now));
+        quality.connections += 1;
+        if outbound {
+            quality.record_dial(now, true);
+        } else {
+            // The peer is up again, no need to wait before dialing it.
+            quality.consecutive_failures = 0;
+        }
+        self.connected_since.insert(peer_info.id.clone(), now);
         let mut store_update = self.store.store_update();
         store_update.set_ser(ColPeers, &peer_info.id.try_to_vec()?, entry)?;
+        store_update.set_ser(ColPeerQuality, &peer_info.id.try_to_vec()?, quality)?;
+        store_update.commit().map_err(|err| err.into())
+    }
+
+    /// Record the round trip time in milliseconds and height of a connected peer, if known.
+    pub fn update_peer_quality(
+        &mut self,
+        peer_id: &PeerId,
+        rtt_ms: Option<f64>,
+        height: BlockHeight,
+    ) {
+        if let Some(quality) = self.quality.get_mut(peer_id) {
+            if let Some(rtt_ms) = rtt_ms {
+                let rtt_us = rtt_ms * 1000.0;
+                let average = match quality.avg_rtt_us {
+                    Some(average) => {
+                        average as f64 * (1.0 - RTT_EWMA_WEIGHT) + rtt_us * RTT_EWMA_WEIGHT
+                    }
+                    None => rtt_us,
+                };
+                quality.avg_rtt_us = Some(average as u64);
+            }
+            quality.last_seen_height = Some(height);
+        }
+    }
+
+    /// Record an outbound connection to `peer_id` which failed before the handshake completed.
+    pub fn peer_handshake_failed(
+        &mut self,
+        peer_id: &PeerId,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let now = to_timestamp(self.clock.now_utc());
+        let quality = self.quality.entry(peer_id.clone()).or_insert_with(|| PeerQuality::new(now));
+        quality.handshake_failures += 1;
+        quality.record_dial(now, false);
+        let mut store_update = self.store.store_update();
+        store_update.set_ser(ColPeerQuality, &peer_id.try_to_vec()?, quality)?;
         store_update.commit().map_err(|err| err.into())
     }
 
+    /// Whether we should wait before dialing `peer_id` again, see `PeerQuality::reconnect_backoff`.
+    pub fn is_backing_off(&self, peer_id: &PeerId) -> bool {
+        let now = to_timestamp(self.clock.now_utc());
+        self.quality.get(peer_id).map_or(false, |quality| quality.is_backing_off(now))
+    }
+
+    pub fn peer_quality(&self, peer_id: &PeerId) -> Option<&PeerQuality> {
+        self.quality.get(peer_id)
+    }
+
+    /// Reliability of `peer_id`, see `PeerQuality::reliability`.
+    pub fn reliability(&self, peer_id: &PeerId) -> f64 {
+        let now = to_timestamp(self.clock.now_utc());
+        self.quality.get(peer_id).map_or_else(
+            || PeerQuality::new(now).reliability(now),
+            |quality| quality.reliability(now),
+        )
+    }
+
     pub fn peer_disconnected(
         &mut self,
         peer_id: &PeerId,
     ) -> Result<(), Box<dyn std::error::Error>> {
         if let Some(peer_state) = self.peer_states.get_mut(peer_id) {
-            peer_state.last_seen = to_timestamp(Utc::now());
+            let now = to_timestamp(self.clock.now_utc());
+            peer_state.last_seen = now;
             peer_state.status = KnownPeerStatus::NotConnected;
             let mut store_update = self.store.store_update();
             store_update.set_ser(ColPeers, &peer_id.try_to_vec()?, peer_state)?;
+            if let Some(quality) = self.quality.get_mut(peer_id) {
+                if let Some(since) = self.connected_since.remove(peer_id) {
+                    quality.connected_ns += now.saturating_sub(since);
+                }
+                store_update.set_ser(ColPeerQuality, &peer_id.try_to_vec()?, quality)?;
+            }
             store_update.commit().map_err(|err| err.into())
         } else {
             Err(format!("Peer {} is missing in the peer store", peer_id).into())
@@ -159,8 +401,9 @@ impl PeerStore {
         ban_reason: ReasonForBan,
     ) -> Result<(), Box<dyn std::error::Error>> {
         if let Some(peer_state) = self.peer_states.get_mut(peer_id) {
-            peer_state.last_seen = to_timestamp(Utc::now());
-            peer_state.status = KnownPeerStatus::Banned(ban_reason, to_timestamp(Utc::now()));
+            let now = to_timestamp(self.clock.now_utc());
+            peer_state.last_seen = now;
+            peer_state.status = KnownPeerStatus::Banned(ban_reason, now);
             let mut store_update = self.store.store_update();
             store_update.set_ser(ColPeers, &peer_id.try_to_vec()?, peer_state)?;
             store_update.commit().map_err(|err| err.into())
@@ -201,13 +444,13 @@ impl PeerStore {
     }
 
     /// Return unconnected or peers with unknown status that we can try to connect to.
-    /// Peers with unknown addresses are filtered out.
+    /// `ignore_fn` must filter out peers with unknown addresses, unless they can be connected to
+    /// otherwise, e.g. by host name through a proxy.
     pub fn unconnected_peers(&self, ignore_fn: impl Fn(&KnownPeerState) -> bool) -> Vec<PeerInfo> {
         self.find_peers(
             |p| {
                 (p.status == KnownPeerStatus::NotConnected || p.status == KnownPeerStatus::Unknown)
                     && !ignore_fn(p)
-                    && p.peer_info.addr.is_some()
             },
             0,
         )
@@ -238,7 +481,7 @@ impl PeerStore {
         &mut self,
         config: &NetworkConfig,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let now = Utc::now();
+        let now = self.clock.now_utc();
         let mut to_remove = vec![];
         for (peer_id, peer_status) in self.peer_states.iter() {
             let diff = (now - peer_status.last_seen()).to_std()?;
@@ -252,7 +495,9 @@ impl PeerStore {
         let mut store_update = self.store.store_update();
         for peer_id in to_remove {
             self.peer_states.remove(&peer_id);
+            self.quality.remove(&peer_id);
             store_update.delete(ColPeers, &peer_id.try_to_vec()?);
+            store_update.delete(ColPeerQuality, &peer_id.try_to_vec()?);
         }
         store_update.commit().map_err(|err| err.into())
     }
@@ -426,6 +671,58 @@ mod test {
         }
     }
 
+    #[test]
+    fn quality_history() {
+        let tmp_dir = tempfile::Builder::new().prefix("_test_store_quality").tempdir().unwrap();
+        let (reliable, failing, unknown) = (gen_peer_info(0), gen_peer_info(1), gen_peer_info(2));
+        {
+            let store = create_store(tmp_dir.path());
+            let mut peer_store = PeerStore::new(store, &[]).unwrap();
+            peer_store.peer_connected(&reliable, true).unwrap();
+            peer_store.update_peer_quality(&reliable.id, Some(20.0), 7);
+            peer_store.peer_disconnected(&reliable.id).unwrap();
+            peer_store.peer_handshake_failed(&failing.id).unwrap();
+            peer_store.peer_handshake_failed(&failing.id).unwrap();
+        }
+        let store = create_store(tmp_dir.path());
+        let peer_store = PeerStore::new(store, &[]).unwrap();
+        let quality = peer_store.peer_quality(&reliable.id).unwrap();
+        assert_eq!(
+            (quality.connections, quality.avg_rtt_us, quality.last_seen_height),
+            (1, Some(20_000), Some(7))
+        );
+        assert_eq!(peer_store.peer_quality(&failing.id).unwrap().handshake_failures, 2);
+        assert!(peer_store.reliability(&reliable.id) > peer_store.reliability(&unknown.id));
+        assert!(peer_store.reliability(&unknown.id) > peer_store.reliability(&failing.id));
+    }
+
+    #[test]
+    fn adaptive_reconnect_backoff() {
+        let hour = |h: u64| h * NANOS_PER_HOUR;
+        let mut flappy = PeerQuality::new(0);
+        for _ in 0..3 {
+            flappy.record_dial(hour(10), false);
+        }
+        let mut healthy = PeerQuality::new(0);
+        for _ in 0..10 {
+            healthy.record_dial(hour(10), true);
+        }
+        healthy.record_dial(hour(10), false);
+        assert!(healthy.reconnect_backoff(hour(10)) < MIN_RECONNECT_BACKOFF);
+        assert!(flappy.reconnect_backoff(hour(10)) > MIN_RECONNECT_BACKOFF * 4);
+        assert!(flappy.is_backing_off(hour(10)));
+        assert!(!flappy.is_backing_off(hour(10) + MAX_RECONNECT_BACKOFF.as_nanos() as u64));
+
+        // The peer usually refuses connections at night.
+        for _ in 0..MIN_HOURLY_DIALS {
+            healthy.record_dial(hour(46), false);
+        }
+        assert!(healthy.dial_success_rate(hour(22)) < 0.5);
+        assert!(healthy.dial_success_rate(hour(34)) > 0.8);
+        healthy.record_dial(hour(34), true);
+        assert_eq!(healthy.reconnect_backoff(hour(34)), Duration::ZERO);
+    }
+
     fn check_exist(
         peer_store: &PeerStore,
         peer_id: &PeerId,
@@ -474,7 +771,7 @@ mod test {
         let addr = get_addr(0);
 
         let peer_aa = get_peer_info(peers_id[0].clone(), Some(addr));
-        peer_store.peer_connected(&peer_aa).unwrap();
+        peer_store.peer_connected(&peer_aa, false).unwrap();
         assert!(check_exist(&peer_store, &peers_id[0], Some((addr, TrustLevel::Signed))));
 
         let peer_ba = get_peer_info(peers_id[1].clone(), Some(addr));
@@ -497,7 +794,7 @@ mod test {
         let addrs = (0..2).map(|ix| get_addr(ix)).collect::<Vec<_>>();
 
         let peer_aa = get_peer_info(peers_id[0].clone(), Some(addrs[0]));
-        peer_store.peer_connected(&peer_aa).unwrap();
+        peer_store.peer_connected(&peer_aa, false).unwrap();
         assert!(check_exist(&peer_store, &peers_id[0], Some((addrs[0], TrustLevel::Signed))));
 
         let peer_ba = get_peer_info(peers_id[0].clone(), Some(addrs[1]));
@@ -506,6 +803,28 @@ mod test {
         assert!(check_integrity(&peer_store));
     }
 
+    /// If peer_id A connects again from address #B, e.g. after a connection migration, the
+    /// signed connection moves it to #B and its quality history is kept.
+    #[test]
+    fn signed_address_change() {
+        let store = create_test_store();
+        let mut peer_store = PeerStore::new(store, &[]).unwrap();
+
+        let peer_id = get_peer_id("node0".to_string());
+        let addrs = (0..2).map(|ix| get_addr(ix)).collect::<Vec<_>>();
+
+        let peer_aa = get_peer_info(peer_id.clone(), Some(addrs[0]));
+        peer_store.peer_connected(&peer_aa, false).unwrap();
+        peer_store.peer_disconnected(&peer_id).unwrap();
+
+        let peer_ab = get_peer_info(peer_id.clone(), Some(addrs[1]));
+        peer_store.peer_connected(&peer_ab, false).unwrap();
+        assert!(check_exist(&peer_store, &peer_id, Some((addrs[1], TrustLevel::Signed))));
+        assert!(!peer_store.addr_peers.contains_key(&addrs[0]));
+        assert_eq!(peer_store.peer_quality(&peer_id).unwrap().connections, 2);
+        assert!(check_integrity(&peer_store));
+    }
+
     #[test]
     fn check_add_peers_overriding() {
         let store = create_test_store();
@@ -518,7 +837,7 @@ mod test {
 
         // Create signed connection A - #A
         let peer_00 = get_peer_info(peers_id[0].clone(), Some(addrs[0]));
-        peer_store.peer_connected(&peer_00).unwrap();
+        peer_store.peer_connected(&peer_00, false).unwrap();
         assert!(check_exist(&peer_store, &peers_id[0], Some((addrs[0], TrustLevel::Signed))));
         assert!(check_integrity(&peer_store));
 
@@ -529,7 +848,7 @@ mod test {
         assert!(check_integrity(&peer_store));
 
         // Create signed connection B - #B
-        peer_store.peer_connected(&peer_11).unwrap();
+        peer_store.peer_connected(&peer_11, false).unwrap();
         assert!(check_exist(&peer_store, &peers_id[1], Some((addrs[1], TrustLevel::Signed))));
         assert!(check_integrity(&peer_store));
 
@@ -540,14 +859,14 @@ mod test {
         assert!(check_integrity(&peer_store));
 
         // Create signed connection C - #C
-        peer_store.peer_connected(&peer_22).unwrap();
+        peer_store.peer_connected(&peer_22, false).unwrap();
         assert!(check_exist(&peer_store, &peers_id[2], Some((addrs[2], TrustLevel::Signed))));
         assert!(check_integrity(&peer_store));
 
         // Create signed connection C - #B
         // This overrides C - #C and B - #B
         let peer_21 = get_peer_info(peers_id[2].clone(), Some(addrs[1]));
-        peer_store.peer_connected(&peer_21).unwrap();
+        peer_store.peer_connected(&peer_21, false).unwrap();
         assert!(check_exist(&peer_store, &peers_id[1], None));
         assert!(check_exist(&peer_store, &peers_id[2], Some((addrs[1], TrustLevel::Signed))));
         assert!(check_integrity(&peer_store));
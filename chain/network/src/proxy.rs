@@ -0,0 +1,262 @@
+//! Outbound connections to peers through a SOCKS5 (RFC 1928) or HTTP `CONNECT` proxy.
+//!
+//! Peers given by host name are connected to without resolving the host name locally: the proxy
+//! resolves it, so nodes in restricted networks don't need a working resolver.
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use near_network_primitives::types::{ProxyConfig, ProxyProtocol};
+use near_primitives::serialize::to_base64;
+
+const SOCKS5_VERSION: u8 = 5;
+const SOCKS5_AUTH_NONE: u8 = 0;
+const SOCKS5_AUTH_PASSWORD: u8 = 2;
+/// Version of the username/password authentication subnegotiation, RFC 1929.
+const SOCKS5_PASSWORD_AUTH_VERSION: u8 = 1;
+const SOCKS5_CMD_CONNECT: u8 = 1;
+const SOCKS5_ATYP_IPV4: u8 = 1;
+const SOCKS5_ATYP_DOMAIN: u8 = 3;
+const SOCKS5_ATYP_IPV6: u8 = 4;
+/// Maximum size of the response of an HTTP proxy to a `CONNECT` request.
+const MAX_HTTP_RESPONSE_LEN: usize = 8 * 1024;
+
+/// Peer to connect to through the proxy.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum ProxyTarget {
+    Addr(SocketAddr),
+    /// `host:port`, resolved by the proxy.
+    Host(String),
+}
+
+impl fmt::Display for ProxyTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProxyTarget::Addr(addr) => write!(f, "{}", addr),
+            ProxyTarget::Host(host) => write!(f, "{}", host),
+        }
+    }
+}
+
+fn proxy_error(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, msg)
+}
+
+/// Connect to `target` through `proxy`. The returned stream is tunneled to `target`.
+pub(crate) async fn connect(proxy: &ProxyConfig, target: &ProxyTarget) -> io::Result<TcpStream> {
+    let mut stream = TcpStream::connect(proxy.addr.as_str()).await?;
+    let credentials = proxy.username.as_deref().zip(proxy.password.as_deref());
+    match proxy.protocol {
+        ProxyProtocol::Socks5 => socks5_handshake(&mut stream, target, credentials).await?,
+        ProxyProtocol::Http => http_handshake(&mut stream, target, credentials).await?,
+    }
+    Ok(stream)
+}
+
+async fn socks5_handshake<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    target: &ProxyTarget,
+    credentials: Option<(&str, &str)>,
+) -> io::Result<()> {
+    let method = if credentials.is_some() { SOCKS5_AUTH_PASSWORD } else { SOCKS5_AUTH_NONE };
+    stream.write_all(&[SOCKS5_VERSION, 1, method]).await?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply != [SOCKS5_VERSION, method] {
+        return Err(proxy_error(format!(
+            "SOCKS5 proxy doesn't support authentication method {}",
+            method
+        )));
+    }
+
+    if let Some((username, password)) = credentials {
+        let mut request = vec![SOCKS5_PASSWORD_AUTH_VERSION];
+        for field in [username, password] {
+            let len = u8::try_from(field.len()).map_err(|_| {
+                proxy_error("SOCKS5 proxy username and password are limited to 255 bytes".into())
+            })?;
+            request.push(len);
+            request.extend_from_slice(field.as_bytes());
+        }
+        stream.write_all(&request).await?;
+        stream.read_exact(&mut reply).await?;
+        if reply[1] != 0 {
+            return Err(proxy_error("SOCKS5 proxy rejected the credentials".into()));
+        }
+    }
+
+    let mut request = vec![SOCKS5_VERSION, SOCKS5_CMD_CONNECT, 0];
+    match target {
+        ProxyTarget::Addr(SocketAddr::V4(addr)) => {
+            request.push(SOCKS5_ATYP_IPV4);
+            request.extend_from_slice(&addr.ip().octets());
+            request.extend_from_slice(&addr.port().to_be_bytes());
+        }
+        ProxyTarget::Addr(SocketAddr::V6(addr)) => {
+            request.push(SOCKS5_ATYP_IPV6);
+            request.extend_from_slice(&addr.ip().octets());
+            request.extend_from_slice(&addr.port().to_be_bytes());
+        }
+        ProxyTarget::Host(host) => {
+            let (name, port) = host
+                .rsplit_once(':')
+                .and_then(|(name, port)| Some((name, port.parse::<u16>().ok()?)))
+                .filter(|(name, _)| !name.is_empty() && name.len() <= 255)
+                .ok_or_else(|| proxy_error(format!("invalid host {:?}", host)))?;
+            request.push(SOCKS5_ATYP_DOMAIN);
+            request.push(name.len() as u8);
+            request.extend_from_slice(name.as_bytes());
+            request.extend_from_slice(&port.to_be_bytes());
+        }
+    }
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[1] != 0 {
+        return Err(proxy_error(format!(
+            "SOCKS5 proxy failed to connect to {}: error {}",
+            target, reply[1]
+        )));
+    }
+    // Skip the address the proxy bound to, the tunnel starts right after it.
+    let addr_len = match reply[3] {
+        SOCKS5_ATYP_IPV4 => 4,
+        SOCKS5_ATYP_IPV6 => 16,
+        SOCKS5_ATYP_DOMAIN => stream.read_u8().await? as usize,
+        atyp => return Err(proxy_error(format!("SOCKS5 proxy sent address type {}", atyp))),
+    };
+    let mut bound_addr = vec![0u8; addr_len + 2];
+    stream.read_exact(&mut bound_addr).await?;
+    Ok(())
+}
+
+async fn http_handshake<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    target: &ProxyTarget,
+    credentials: Option<(&str, &str)>,
+) -> io::Result<()> {
+    let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", target);
+    if let Some((username, password)) = credentials {
+        request += &format!(
+            "Proxy-Authorization: Basic {}\r\n",
+            to_base64(format!("{}:{}", username, password))
+        );
+    }
+    request += "\r\n";
+    stream.write_all(request.as_bytes()).await?;
+
+    // Read byte by byte, so nothing sent by the peer after the response is consumed.
+    let mut response = vec![];
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() >= MAX_HTTP_RESPONSE_LEN {
+            return Err(proxy_error("HTTP proxy response is too long".into()));
+        }
+        response.push(stream.read_u8().await?);
+    }
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or_default();
+    match status_line.split_whitespace().nth(1) {
+        Some(status) if status.starts_with('2') => Ok(()),
+        _ => {
+            Err(proxy_error(format!("HTTP proxy failed to connect to {}: {}", target, status_line)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    use super::{http_handshake, socks5_handshake, ProxyTarget};
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(future)
+    }
+
+    #[test]
+    fn socks5() {
+        block_on(async {
+            let (mut client, mut proxy) = duplex(1024);
+            let target = ProxyTarget::Host("boot.near.org:24567".to_string());
+            let server = async {
+                let mut greeting = [0u8; 3];
+                proxy.read_exact(&mut greeting).await.unwrap();
+                assert_eq!(greeting, [5, 1, 2]);
+                proxy.write_all(&[5, 2]).await.unwrap();
+                let mut auth = [0u8; 11];
+                proxy.read_exact(&mut auth).await.unwrap();
+                assert_eq!(&auth, b"\x01\x04user\x04pass");
+                proxy.write_all(&[1, 0]).await.unwrap();
+
+                let mut request = vec![0u8; 5 + "boot.near.org".len() + 2];
+                proxy.read_exact(&mut request).await.unwrap();
+                assert_eq!(&request[..5], &[5, 1, 0, 3, 13]);
+                assert_eq!(&request[5..18], b"boot.near.org");
+                assert_eq!(&request[18..], &24567u16.to_be_bytes());
+                proxy.write_all(&[5, 0, 0, 1, 10, 0, 0, 1, 0, 80]).await.unwrap();
+                proxy.write_all(b"peer data").await.unwrap();
+            };
+            let (res, ()) = futures::join!(
+                socks5_handshake(&mut client, &target, Some(("user", "pass"))),
+                server
+            );
+            res.unwrap();
+            // The tunnel starts right after the reply.
+            let mut data = [0u8; 9];
+            client.read_exact(&mut data).await.unwrap();
+            assert_eq!(&data, b"peer data");
+
+            let (mut client, mut proxy) = duplex(1024);
+            let target = ProxyTarget::Addr("1.2.3.4:24567".parse().unwrap());
+            let server = async {
+                let mut greeting = [0u8; 3];
+                proxy.read_exact(&mut greeting).await.unwrap();
+                proxy.write_all(&[5, 0]).await.unwrap();
+                let mut request = [0u8; 10];
+                proxy.read_exact(&mut request).await.unwrap();
+                assert_eq!(request[3], 1);
+                // Connection refused.
+                proxy.write_all(&[5, 5, 0, 1]).await.unwrap();
+            };
+            let (res, ()) = futures::join!(socks5_handshake(&mut client, &target, None), server);
+            assert!(res.is_err());
+        });
+    }
+
+    #[test]
+    fn http() {
+        block_on(async {
+            let (mut client, mut proxy) = duplex(1024);
+            let target = ProxyTarget::Addr("[2001:db8::1]:24567".parse().unwrap());
+            let server = async {
+                let mut request = vec![0u8; 1024];
+                let len = proxy.read(&mut request).await.unwrap();
+                let request = String::from_utf8(request[..len].to_vec()).unwrap();
+                assert!(request.starts_with("CONNECT [2001:db8::1]:24567 HTTP/1.1\r\n"));
+                assert!(request.ends_with("\r\n\r\n"));
+                proxy.write_all(b"HTTP/1.1 200 Connection established\r\n\r\npeer").await.unwrap();
+            };
+            let (res, ()) = futures::join!(http_handshake(&mut client, &target, None), server);
+            res.unwrap();
+            let mut data = [0u8; 4];
+            client.read_exact(&mut data).await.unwrap();
+            assert_eq!(&data, b"peer");
+
+            let (mut client, mut proxy) = duplex(1024);
+            let server = async {
+                let mut request = vec![0u8; 1024];
+                proxy.read(&mut request).await.unwrap();
+                proxy
+                    .write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n")
+                    .await
+                    .unwrap();
+            };
+            let (res, ()) = futures::join!(http_handshake(&mut client, &target, None), server);
+            assert!(res.unwrap_err().to_string().contains("407"));
+        });
+    }
+}
@@ -13,13 +13,15 @@
 // limitations under the License.
 
 use std::collections::VecDeque;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant};
 
-const MINUTE_IN_MILLIS: u128 = 60_000;
+use crate::time::Clock;
+
+const MINUTE: Duration = Duration::from_secs(60);
 
 struct Entry {
     bytes: u64,
-    expiration_timestamp: u128,
+    expiration: Instant,
 }
 
 /// A rate counter tracks number of transfers, the amount of data exchanged and the rate of transfer
@@ -27,17 +29,18 @@ struct Entry {
 pub struct RateCounter {
     entries: VecDeque<Entry>,
     bytes_sum: u64,
+    clock: Clock,
 }
 
 impl RateCounter {
-    pub fn new() -> Self {
-        RateCounter { entries: VecDeque::new(), bytes_sum: 0 }
+    pub fn new(clock: Clock) -> Self {
+        RateCounter { entries: VecDeque::new(), bytes_sum: 0, clock }
     }
 
     /// Increment number of bytes transferred, updating counts and rates.
     pub fn increment(&mut self, bytes: u64) {
-        let now = millis_since_epoch();
-        self.entries.push_back(Entry { bytes, expiration_timestamp: now + MINUTE_IN_MILLIS });
+        let now = self.clock.now();
+        self.entries.push_back(Entry { bytes, expiration: now + MINUTE });
         self.bytes_sum += bytes;
         self.truncate(now);
     }
@@ -50,28 +53,22 @@ impl RateCounter {
         self.entries.len() as u64
     }
 
-    fn truncate(&mut self, now: u128) {
-        while !self.entries.is_empty() && self.entries.front().unwrap().expiration_timestamp < now {
+    fn truncate(&mut self, now: Instant) {
+        while !self.entries.is_empty() && self.entries.front().unwrap().expiration < now {
             self.bytes_sum -= self.entries.pop_front().unwrap().bytes;
         }
     }
 }
 
-/// Returns timestamp in milliseconds.
-fn millis_since_epoch() -> u128 {
-    let since_epoch = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap_or_else(|_| Duration::new(0, 0));
-    since_epoch.as_millis()
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::time::FakeClock;
 
     #[test]
     fn test_rate_counter() {
-        let mut rc = RateCounter::new();
+        let fake = FakeClock::new();
+        let mut rc = RateCounter::new(fake.clock());
 
         rc.increment(1000);
         assert_eq!(rc.bytes_per_min(), 1000);
@@ -82,8 +79,17 @@ mod tests {
         assert_eq!(rc.bytes_per_min(), 1123);
         assert_eq!(rc.count_per_min(), 2);
 
-        rc.truncate(millis_since_epoch() + MINUTE_IN_MILLIS + 1);
+        fake.advance(Duration::from_secs(30));
+        rc.increment(10);
+        assert_eq!(rc.bytes_per_min(), 1133);
+
+        // Only the bytes of the last minute are counted.
+        fake.advance(MINUTE);
+        rc.increment(1);
+        assert_eq!(rc.bytes_per_min(), 11);
+        assert_eq!(rc.count_per_min(), 2);
 
+        rc.truncate(fake.now() + MINUTE + Duration::from_millis(1));
         assert_eq!(rc.bytes_per_min(), 0);
         assert_eq!(rc.count_per_min(), 0);
     }
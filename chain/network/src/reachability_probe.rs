@@ -0,0 +1,213 @@
+//! Probes comparing which peers we and our direct peers can route messages to.
+//!
+//! Periodically we ask one of our peers which of a sampled set of targets it can route messages
+//! to. Targets it reaches but we don't, or the other way around, mean our routing tables
+//! diverged: e.g. the peer may route messages to us on behalf of a target we can't answer.
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use actix::dev::MessageResponse;
+use rand::seq::IteratorRandom;
+#[cfg(feature = "test_features")]
+use serde::Serialize;
+
+use near_primitives::network::PeerId;
+
+use crate::metrics;
+use crate::time::{Clock, Time};
+
+/// How often we probe one of our peers.
+pub(crate) const REACHABILITY_PROBE_INTERVAL: Duration = Duration::from_secs(60);
+/// Maximum number of targets of a probe, larger probes are truncated.
+pub const MAX_REACHABILITY_PROBE_TARGETS: usize = 32;
+/// Probes not answered within this time are forgotten.
+const REACHABILITY_PROBE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Number of probed peers whose last result is kept.
+const MAX_REACHABILITY_PROBE_RESULTS: usize = 100;
+
+/// Result of the last probe of a peer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "test_features", derive(Serialize))]
+pub struct ReachabilityProbeResult {
+    pub peer_id: PeerId,
+    /// Number of targets probed.
+    pub targets: usize,
+    /// Targets the peer can route messages to, but we can't.
+    pub unreachable_by_us: Vec<PeerId>,
+    /// Targets we can route messages to, but the peer can't.
+    pub unreachable_by_peer: Vec<PeerId>,
+}
+
+impl ReachabilityProbeResult {
+    fn divergent_targets(&self) -> usize {
+        self.unreachable_by_us.len() + self.unreachable_by_peer.len()
+    }
+}
+
+#[derive(MessageResponse, Debug)]
+#[cfg_attr(feature = "test_features", derive(Serialize))]
+pub struct GetReachabilityProbesResult {
+    /// Last result of each probed peer, the ones with the most divergent targets first.
+    pub probes: Vec<ReachabilityProbeResult>,
+}
+
+#[derive(Default)]
+pub(crate) struct ReachabilityProbes {
+    clock: Clock,
+    /// Targets of unanswered probes and when they were sent, by probed peer.
+    pending: HashMap<PeerId, (Vec<PeerId>, Instant)>,
+    /// Last result of each probed peer and when it was received.
+    results: HashMap<PeerId, (ReachabilityProbeResult, Instant)>,
+}
+
+impl ReachabilityProbes {
+    pub fn new(clock: Clock) -> Self {
+        Self { clock, ..Default::default() }
+    }
+
+    /// Start probing `peer_id`, returns the targets to ask it about, sampled from `candidates`.
+    pub fn start(
+        &mut self,
+        peer_id: PeerId,
+        candidates: impl Iterator<Item = PeerId>,
+    ) -> Vec<PeerId> {
+        self.start_at(peer_id, candidates, self.clock.now())
+    }
+
+    fn start_at(
+        &mut self,
+        peer_id: PeerId,
+        candidates: impl Iterator<Item = PeerId>,
+        now: Instant,
+    ) -> Vec<PeerId> {
+        let targets = candidates
+            .filter(|target| target != &peer_id)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .choose_multiple(&mut rand::thread_rng(), MAX_REACHABILITY_PROBE_TARGETS);
+        self.pending
+            .retain(|_, (_, sent)| Time::duration_since(now, *sent) < REACHABILITY_PROBE_TIMEOUT);
+        self.pending.insert(peer_id, (targets.clone(), now));
+        targets
+    }
+
+    /// Compare the targets `peer_id` can reach, its answer to our probe, with the ones
+    /// `is_reachable` by us. Unsolicited answers are ignored.
+    pub fn on_response(
+        &mut self,
+        peer_id: &PeerId,
+        reachable_by_peer: Vec<PeerId>,
+        is_reachable: impl Fn(&PeerId) -> bool,
+    ) -> Option<&ReachabilityProbeResult> {
+        self.on_response_at(peer_id, reachable_by_peer, is_reachable, self.clock.now())
+    }
+
+    fn on_response_at(
+        &mut self,
+        peer_id: &PeerId,
+        reachable_by_peer: Vec<PeerId>,
+        is_reachable: impl Fn(&PeerId) -> bool,
+        now: Instant,
+    ) -> Option<&ReachabilityProbeResult> {
+        let (targets, _) = self.pending.remove(peer_id)?;
+        let reachable_by_peer: HashSet<_> = reachable_by_peer.into_iter().collect();
+        let mut result = ReachabilityProbeResult {
+            peer_id: peer_id.clone(),
+            targets: targets.len(),
+            unreachable_by_us: vec![],
+            unreachable_by_peer: vec![],
+        };
+        for target in targets {
+            match (is_reachable(&target), reachable_by_peer.contains(&target)) {
+                (false, true) => result.unreachable_by_us.push(target),
+                (true, false) => result.unreachable_by_peer.push(target),
+                _ => {}
+            }
+        }
+        near_metrics::inc_counter_vec_by(
+            &metrics::REACHABILITY_PROBE_DIVERGENT_TARGETS,
+            &["unreachable_by_us"],
+            result.unreachable_by_us.len() as u64,
+        );
+        near_metrics::inc_counter_vec_by(
+            &metrics::REACHABILITY_PROBE_DIVERGENT_TARGETS,
+            &["unreachable_by_peer"],
+            result.unreachable_by_peer.len() as u64,
+        );
+
+        if self.results.len() >= MAX_REACHABILITY_PROBE_RESULTS
+            && !self.results.contains_key(peer_id)
+        {
+            let oldest = self
+                .results
+                .iter()
+                .min_by_key(|(_, (_, received))| *received)
+                .map(|(peer_id, _)| peer_id.clone());
+            if let Some(oldest) = oldest {
+                self.results.remove(&oldest);
+            }
+        }
+        self.results.insert(peer_id.clone(), (result, now));
+        self.results.get(peer_id).map(|(result, _)| result)
+    }
+
+    pub fn results(&self) -> GetReachabilityProbesResult {
+        let mut probes: Vec<_> = self.results.values().map(|(result, _)| result.clone()).collect();
+        probes.sort_by_key(|result| std::cmp::Reverse(result.divergent_targets()));
+        GetReachabilityProbesResult { probes }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use crate::test_utils::random_peer_id;
+
+    use super::{ReachabilityProbes, MAX_REACHABILITY_PROBE_TARGETS, REACHABILITY_PROBE_TIMEOUT};
+
+    #[test]
+    fn divergent_targets() {
+        let now = Instant::now();
+        let peer = random_peer_id();
+        let (both, only_us, only_peer, none) =
+            (random_peer_id(), random_peer_id(), random_peer_id(), random_peer_id());
+        let mut probes = ReachabilityProbes::default();
+
+        let candidates = vec![peer.clone(), both.clone(), only_us.clone(), only_peer.clone()];
+        let mut targets =
+            probes.start_at(peer.clone(), candidates.into_iter().chain(Some(none.clone())), now);
+        targets.sort();
+        let mut expected = vec![both.clone(), only_us.clone(), only_peer.clone(), none.clone()];
+        expected.sort();
+        // The probed peer is never a target.
+        assert_eq!(targets, expected);
+
+        let is_reachable = |target: &_| target == &both || target == &only_us;
+        let result = probes
+            .on_response_at(&peer, vec![both.clone(), only_peer.clone()], is_reachable, now)
+            .unwrap();
+        assert_eq!(result.targets, 4);
+        assert_eq!(result.unreachable_by_us, vec![only_peer.clone()]);
+        assert_eq!(result.unreachable_by_peer, vec![only_us.clone()]);
+        assert_eq!(probes.results().probes.len(), 1);
+
+        // Unsolicited answers are ignored.
+        assert!(probes.on_response_at(&peer, vec![none.clone()], is_reachable, now).is_none());
+    }
+
+    #[test]
+    fn limits() {
+        let now = Instant::now();
+        let mut probes = ReachabilityProbes::default();
+        let (peer0, peer1) = (random_peer_id(), random_peer_id());
+        let candidates = (0..2 * MAX_REACHABILITY_PROBE_TARGETS).map(|_| random_peer_id());
+        assert_eq!(
+            probes.start_at(peer0.clone(), candidates, now).len(),
+            MAX_REACHABILITY_PROBE_TARGETS
+        );
+        // Unanswered probes expire when the next one starts.
+        probes.start_at(peer1, vec![].into_iter(), now + REACHABILITY_PROBE_TIMEOUT);
+        assert!(probes.on_response_at(&peer0, vec![], |_| true, now).is_none());
+    }
+}
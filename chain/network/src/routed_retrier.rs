@@ -0,0 +1,213 @@
+//! Retry policy for requests routed through the network, shared by higher layers (state sync,
+//! chunk requests) instead of each of them running its own retry loop.
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+use rand::seq::SliceRandom;
+use rand::{thread_rng, Rng};
+
+use near_primitives::network::PeerId;
+
+use crate::metrics;
+use crate::time::Clock;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Number of attempts after which the request is given up.
+    pub max_attempts: usize,
+    /// Delay after the first attempt. It doubles after every attempt, up to `max_backoff`.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 10,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Target a request may be sent to, with the route to it as known by the routing table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetryCandidate<T> {
+    pub target: T,
+    /// Peers the request would be forwarded to. `None` if the route is not known to the caller,
+    /// an empty list if the target is unreachable.
+    pub next_hops: Option<Vec<PeerId>>,
+}
+
+impl<T> RetryCandidate<T> {
+    pub fn new(target: T, next_hops: Option<Vec<PeerId>>) -> Self {
+        Self { target, next_hops }
+    }
+}
+
+/// State of the retries of a single request.
+///
+/// Every attempt goes to a reachable target, preferring targets and next hops which were not
+/// tried yet, so a broken route doesn't swallow all attempts. Attempts are spaced out by an
+/// exponential backoff with jitter. The outcome of the request must be reported with
+/// `succeeded` or `failed`, which feed the `near_routed_request_*` metrics.
+pub struct RoutedRequestRetrier<T> {
+    /// Kind of request, used as the metrics label.
+    kind: &'static str,
+    policy: RetryPolicy,
+    attempts: usize,
+    tried_targets: HashSet<T>,
+    tried_next_hops: HashSet<PeerId>,
+    /// Earliest time of the next attempt.
+    next_attempt: Option<Instant>,
+    clock: Clock,
+}
+
+impl<T: Clone + Eq + Hash> RoutedRequestRetrier<T> {
+    pub fn new(kind: &'static str, policy: RetryPolicy) -> Self {
+        Self {
+            kind,
+            policy,
+            attempts: 0,
+            tried_targets: Default::default(),
+            tried_next_hops: Default::default(),
+            next_attempt: None,
+            clock: Clock::real(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn attempts(&self) -> usize {
+        self.attempts
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.attempts >= self.policy.max_attempts
+    }
+
+    /// Whether the backoff after the last attempt has passed.
+    pub fn is_ready(&self) -> bool {
+        !self.is_exhausted() && self.next_attempt.map_or(true, |at| self.clock.now() >= at)
+    }
+
+    /// Choose the target of the next attempt among `candidates` and start the backoff.
+    /// Returns `None` if it's too early to retry, retries are exhausted or no candidate is
+    /// reachable.
+    pub fn next_target(
+        &mut self,
+        candidates: impl IntoIterator<Item = RetryCandidate<T>>,
+    ) -> Option<T> {
+        if !self.is_ready() {
+            return None;
+        }
+        let reachable: Vec<_> = candidates
+            .into_iter()
+            .filter(|candidate| candidate.next_hops.as_ref().map_or(true, |hops| !hops.is_empty()))
+            .collect();
+        let best = reachable.iter().map(|candidate| self.preference(candidate)).min()?;
+        let candidate = reachable
+            .iter()
+            .filter(|candidate| self.preference(candidate) == best)
+            .collect::<Vec<_>>()
+            .choose(&mut thread_rng())
+            .cloned()?
+            .clone();
+
+        self.attempts += 1;
+        self.tried_targets.insert(candidate.target.clone());
+        self.tried_next_hops.extend(candidate.next_hops.into_iter().flatten());
+        self.next_attempt = Some(self.clock.now() + self.backoff());
+        near_metrics::inc_counter_vec(&metrics::ROUTED_REQUEST_ATTEMPTS, &[self.kind]);
+        Some(candidate.target)
+    }
+
+    /// Report that the last attempt timed out. Waiting for the timeout already spaced the
+    /// attempts out, so the next one is not delayed by the backoff on top of it.
+    pub fn timed_out(&mut self) {
+        self.next_attempt = None;
+    }
+
+    /// Report that the request got a response.
+    pub fn succeeded(self) {
+        near_metrics::inc_counter_vec(&metrics::ROUTED_REQUEST_SUCCEEDED, &[self.kind]);
+    }
+
+    /// Report that the request was given up.
+    pub fn failed(self) {
+        near_metrics::inc_counter_vec(&metrics::ROUTED_REQUEST_FAILED, &[self.kind]);
+    }
+
+    /// Lower is better: untried target over untried next hops over anything reachable.
+    fn preference(&self, candidate: &RetryCandidate<T>) -> u8 {
+        let new_next_hops = candidate
+            .next_hops
+            .as_ref()
+            .map_or(true, |hops| hops.iter().any(|hop| !self.tried_next_hops.contains(hop)));
+        match (self.tried_targets.contains(&candidate.target), new_next_hops) {
+            (false, true) => 0,
+            (false, false) | (true, true) => 1,
+            (true, false) => 2,
+        }
+    }
+
+    /// Delay before the next attempt, uniformly distributed between half and all of the
+    /// exponential backoff.
+    fn backoff(&self) -> Duration {
+        let exponent = self.attempts.saturating_sub(1).min(31) as u32;
+        let backoff = self
+            .policy
+            .initial_backoff
+            .checked_mul(1 << exponent)
+            .map_or(self.policy.max_backoff, |backoff| backoff.min(self.policy.max_backoff));
+        backoff.mul_f64(thread_rng().gen_range(0.5, 1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::test_utils::random_peer_id;
+    use crate::time::FakeClock;
+
+    use super::{RetryCandidate, RetryPolicy, RoutedRequestRetrier};
+
+    #[test]
+    fn distinct_next_hops_and_backoff() {
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(1),
+        };
+        let fake = FakeClock::new();
+        let mut retrier = RoutedRequestRetrier::new("test", policy).with_clock(fake.clock());
+        let (hop0, hop1) = (random_peer_id(), random_peer_id());
+        let candidates = vec![
+            RetryCandidate::new(0, Some(vec![hop0.clone()])),
+            RetryCandidate::new(1, Some(vec![hop1.clone()])),
+            RetryCandidate::new(2, Some(vec![])),
+        ];
+
+        let first = retrier.next_target(candidates.clone()).unwrap();
+        assert!(first < 2);
+
+        // Too early to retry.
+        assert_eq!(retrier.next_target(candidates.clone()), None);
+
+        // The other next hop is chosen, the unreachable target never is.
+        fake.advance(Duration::from_secs(1));
+        assert_eq!(retrier.next_target(candidates.clone()), Some(1 - first));
+
+        fake.advance(Duration::from_secs(1));
+        retrier.timed_out();
+        assert!(retrier.next_target(candidates.clone()).is_some());
+        assert!(retrier.is_exhausted());
+        assert_eq!(retrier.attempts(), 3);
+        retrier.failed();
+    }
+}
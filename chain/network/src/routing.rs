@@ -1,28 +1,41 @@
-use std::collections::{hash_map::Entry, HashMap, HashSet, VecDeque};
+use std::collections::{hash_map::Entry, BTreeMap, HashMap, HashSet, VecDeque};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 use cached::{Cached, SizedCache};
+use chrono::{DateTime, Utc};
 use conqueue::{QueueReceiver, QueueSender};
+use rand::seq::SliceRandom;
+use rand::{thread_rng, Rng};
 #[cfg(feature = "test_features")]
 use serde::{Deserialize, Serialize};
-use tracing::{debug, trace, warn};
+use tracing::{debug, info, trace, warn};
 
 #[cfg(feature = "delay_detector")]
 use delay_detector::DelayDetector;
 use near_metrics;
 use near_primitives::hash::{hash, CryptoHash};
 use near_primitives::network::{AnnounceAccount, PeerId};
-use near_primitives::types::AccountId;
-use near_primitives::utils::index_to_bytes;
+use near_primitives::types::{AccountId, EpochId, ShardId};
+use near_primitives::utils::{index_to_bytes, to_timestamp};
+use near_primitives::views::RoutingRecalculationView;
 use near_store::{
     ColAccountAnnouncements, ColComponentEdges, ColLastComponentNonce, ColPeerComponent, Store,
     StoreUpdate,
 };
 
+use crate::diagnostics;
+use crate::edge_stats::LifetimeEdgeStats;
 use crate::{
     cache::RouteBackCache,
-    types::{PeerIdOrHash, Ping, Pong},
+    routing_dump::{RoutingTableDump, ROUTING_TABLE_DUMP_VERSION},
+    routing_profile::{hash_map_bytes, vec_bytes},
+    sync_lru::SyncLruCache,
+    time::{Clock, Time},
+    types::{
+        next_ping_nonce, ping_nonce_precedes, PeerIdOrHash, Ping, Pong, ProxyAnnouncement,
+        PruneConfig, RoutingStrategy, RoutingTableConfig, SubKeyDelegation,
+    },
     utils::cache_to_hashmap,
 };
 use crate::{metrics, PeerInfo};
@@ -30,22 +43,43 @@ use actix::dev::{MessageResponse, ResponseChannel};
 use actix::{Actor, Message};
 use borsh::{BorshDeserialize, BorshSerialize};
 use byteorder::{LittleEndian, WriteBytesExt};
-use near_crypto::{KeyType, SecretKey, Signature};
+use near_crypto::{KeyType, PublicKey, SecretKey, Signature};
+pub use near_network_primitives::types::{
+    FindRouteError, MAX_NUM_PEERS, SAVE_PEERS_AFTER_TIME, SAVE_PEERS_MAX_TIME,
+};
 
-const ANNOUNCE_ACCOUNT_CACHE_SIZE: usize = 10_000;
-const ROUTE_BACK_CACHE_SIZE: u64 = 100_000;
-const ROUTE_BACK_CACHE_EVICT_TIMEOUT: Duration = Duration::from_millis(120_000);
-const ROUTE_BACK_CACHE_REMOVE_BATCH: u64 = 100;
+/// Maximum number of peers announced by the same account in the same epoch we keep track of.
+const MAX_PEERS_PER_ACCOUNT: usize = 4;
+/// Number of most recent epochs whose order is remembered to expire announcements.
+const MAX_KNOWN_EPOCHS: usize = 8;
 const PING_PONG_CACHE_SIZE: usize = 1_000;
 const ROUND_ROBIN_MAX_NONCE_DIFFERENCE_ALLOWED: usize = 10;
 const ROUND_ROBIN_NONCE_CACHE_SIZE: usize = 10_000;
-/// Routing table will clean edges if there is at least one node that is not reachable
-/// since `SAVE_PEERS_MAX_TIME` seconds. All peers disconnected since `SAVE_PEERS_AFTER_TIME`
-/// seconds will be removed from cache and persisted in disk.
-pub const SAVE_PEERS_MAX_TIME: Duration = Duration::from_secs(7_200);
-pub const SAVE_PEERS_AFTER_TIME: Duration = Duration::from_secs(3_600);
-/// Graph implementation supports up to 128 peers.
-pub const MAX_NUM_PEERS: usize = 128;
+/// Next hops whose round trip time differs by less than this are considered equally fast,
+/// and round robin is used among them.
+const ROUTE_LATENCY_BUCKET_MS: f64 = 20.0;
+/// Weight of a new round trip time measurement on the latency of a peer.
+const ROUTE_LATENCY_EWMA_WEIGHT: f64 = 0.2;
+const ROUTE_FAILURES_CACHE_SIZE: usize = 1_000;
+/// Number of recent failures kept for each target to explain routing decisions.
+const MAX_ROUTE_FAILURES_PER_TARGET: usize = 10;
+const AVOIDED_NEXT_HOPS_CACHE_SIZE: usize = 1_000;
+/// Time after which the penalty of a next hop which failed to deliver a message to some target
+/// is halved. The next hop is avoided for that target while its penalty is above
+/// `NEXT_HOP_AVOIDANCE_THRESHOLD`. Every failure adds one to the penalty, so repeated failures
+/// make the avoidance last longer.
+const NEXT_HOP_AVOIDANCE_HALF_LIFE: Duration = Duration::from_secs(10);
+const NEXT_HOP_AVOIDANCE_THRESHOLD: f64 = 0.5;
+/// Number of incremental routing table recalculations after which the shortest paths are
+/// recomputed from scratch, in case incremental updates diverged from the graph.
+const GRAPH_FULL_RECOMPUTE_PERIOD: usize = 100;
+/// A warning is logged when the fraction of reachable peers we only reach through a single next
+/// hop rises above this, since losing one of those next hops would partition us from them.
+const SINGLE_NEXT_HOP_WARNING_FRACTION: f64 = 0.5;
+/// Maximum number of critical next hops listed in the warning.
+const MAX_CRITICAL_NEXT_HOPS_LOGGED: usize = 10;
+/// Time after which a `ProxyAnnouncement` expires if the node doesn't sign a new one.
+pub const PROXY_ANNOUNCEMENT_TTL: Duration = Duration::from_secs(60 * 60);
 
 /// Information that will be ultimately used to create a new edge.
 /// It contains nonce proposed for the edge with signature from peer.
@@ -64,6 +98,64 @@ impl EdgeInfo {
     }
 }
 
+/// Maximum number of sub-keys we keep for each peer, see `SubKeys`.
+const MAX_SUB_KEYS_PER_PEER: usize = 4;
+
+/// Network sub-keys delegated by peers, see `SubKeyDelegation`. Edges of a peer with sub-keys
+/// may be signed by its node key or by any of them.
+#[derive(Default, Clone, Debug)]
+pub struct SubKeys(HashMap<PeerId, Vec<SubKeyDelegation>>);
+
+impl SubKeys {
+    /// Adds a delegation already verified with `SubKeyDelegation::verify`. Returns whether it
+    /// was new. Delegations above `MAX_SUB_KEYS_PER_PEER` for the same peer are ignored.
+    pub fn add(&mut self, delegation: SubKeyDelegation) -> bool {
+        let delegations = self.0.entry(delegation.peer_id.clone()).or_default();
+        if delegations.len() >= MAX_SUB_KEYS_PER_PEER
+            || delegations.iter().any(|known| known.sub_key == delegation.sub_key)
+        {
+            return false;
+        }
+        delegations.push(delegation);
+        true
+    }
+
+    /// Sub-keys of `peer_id`, oldest first.
+    pub fn keys(&self, peer_id: &PeerId) -> Vec<PublicKey> {
+        self.0.get(peer_id).map_or_else(Vec::new, |delegations| {
+            delegations.iter().map(|delegation| delegation.sub_key.clone()).collect()
+        })
+    }
+
+    pub fn contains_peer(&self, peer_id: &PeerId) -> bool {
+        self.0.contains_key(peer_id)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn delegations(&self) -> Vec<SubKeyDelegation> {
+        self.0.values().flatten().cloned().collect()
+    }
+
+    /// Key the edges of `peer_id` are most likely signed with: its newest sub-key, if any.
+    fn preferred_key(&self, peer_id: &PeerId) -> PublicKey {
+        self.0
+            .get(peer_id)
+            .and_then(|delegations| delegations.last())
+            .map_or_else(|| peer_id.public_key(), |delegation| delegation.sub_key.clone())
+    }
+
+    /// Whether `signature` of `data` is made by the node key of `peer_id` or one of its sub-keys.
+    fn verify(&self, peer_id: &PeerId, data: &[u8], signature: &Signature) -> bool {
+        signature.verify(data, &peer_id.public_key())
+            || self.0.get(peer_id).map_or(false, |delegations| {
+                delegations.iter().any(|delegation| signature.verify(data, &delegation.sub_key))
+            })
+    }
+}
+
 /// Status of the edge
 #[derive(BorshSerialize, BorshDeserialize, Clone, PartialEq, Eq, Debug, Hash)]
 pub enum EdgeType {
@@ -71,6 +163,53 @@ pub enum EdgeType {
     Removed,
 }
 
+/// Proof that one of the peers of an edge removed it.
+///
+/// Encoded in a versioned format readable by nodes which only know the original
+/// `(bool, Signature)` encoding: its first byte is the party for `SignedByPeer` (0 or 1), or the
+/// version of a later kind of proof followed by its length-prefixed payload. Proofs of versions
+/// this node doesn't know can still be decoded and relayed, but aren't valid.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "test_features", derive(Serialize, Deserialize))]
+pub enum EdgeRemovalProof {
+    /// Signature of the removal edge by `peer0` if `by_peer0`, otherwise by `peer1`.
+    SignedByPeer { by_peer0: bool, signature: Signature },
+    /// Proof of a kind introduced after this node was released.
+    Unknown { version: u8, payload: Vec<u8> },
+}
+
+/// First version of removal proofs which isn't `EdgeRemovalProof::SignedByPeer`.
+pub const MIN_EXTENDED_REMOVAL_PROOF_VERSION: u8 = 2;
+
+impl BorshSerialize for EdgeRemovalProof {
+    fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        match self {
+            EdgeRemovalProof::SignedByPeer { by_peer0, signature } => {
+                by_peer0.serialize(writer)?;
+                signature.serialize(writer)
+            }
+            EdgeRemovalProof::Unknown { version, payload } => {
+                version.serialize(writer)?;
+                payload.serialize(writer)
+            }
+        }
+    }
+}
+
+impl BorshDeserialize for EdgeRemovalProof {
+    fn deserialize(buf: &mut &[u8]) -> std::io::Result<Self> {
+        let version = u8::deserialize(buf)?;
+        if version < MIN_EXTENDED_REMOVAL_PROOF_VERSION {
+            Ok(EdgeRemovalProof::SignedByPeer {
+                by_peer0: version == 1,
+                signature: Signature::deserialize(buf)?,
+            })
+        } else {
+            Ok(EdgeRemovalProof::Unknown { version, payload: Vec::<u8>::deserialize(buf)? })
+        }
+    }
+}
+
 /// Edge object. Contains information relative to a new edge that is being added or removed
 /// from the network. This is the information that is required.
 #[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, Eq)]
@@ -86,12 +225,46 @@ pub struct Edge {
     signature0: Signature,
     signature1: Signature,
     /// Info necessary to declare an edge as removed.
-    /// The bool says which party is removing the edge: false for Peer0, true for Peer1
-    /// The signature from the party removing the edge.
-    removal_info: Option<(bool, Signature)>,
+    removal_proof: Option<EdgeRemovalProof>,
+}
+
+/// Everything an `Edge` contains except for the peer ids. Used to send edges with peer ids in a
+/// compact form, see `compact_peer_id`.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct EdgeWithoutPeers {
+    nonce: u64,
+    signature0: Signature,
+    signature1: Signature,
+    removal_proof: Option<EdgeRemovalProof>,
 }
 
 impl Edge {
+    /// Split the edge into its peer ids and the rest of it.
+    pub fn split_peers(&self) -> (&PeerId, &PeerId, EdgeWithoutPeers) {
+        (
+            &self.peer0,
+            &self.peer1,
+            EdgeWithoutPeers {
+                nonce: self.nonce,
+                signature0: self.signature0.clone(),
+                signature1: self.signature1.clone(),
+                removal_proof: self.removal_proof.clone(),
+            },
+        )
+    }
+
+    /// Inverse of `split_peers`.
+    pub fn from_parts(peer0: PeerId, peer1: PeerId, rest: EdgeWithoutPeers) -> Self {
+        Self {
+            peer0,
+            peer1,
+            nonce: rest.nonce,
+            signature0: rest.signature0,
+            signature1: rest.signature1,
+            removal_proof: rest.removal_proof,
+        }
+    }
+
     /// Create an addition edge.
     pub fn new(
         peer0: PeerId,
@@ -106,7 +279,7 @@ impl Edge {
             (peer1, signature1, peer0, signature0)
         };
 
-        Self { peer0, peer1, nonce, signature0, signature1, removal_info: None }
+        Self { peer0, peer1, nonce, signature0, signature1, removal_proof: None }
     }
 
     pub fn to_simple_edge(&self) -> SimpleEdge {
@@ -120,7 +293,7 @@ impl Edge {
             nonce,
             signature0: Signature::empty(KeyType::ED25519),
             signature1: Signature::empty(KeyType::ED25519),
-            removal_info: None,
+            removal_proof: None,
         }
     }
 
@@ -146,10 +319,9 @@ impl Edge {
         assert_eq!(self.edge_type(), EdgeType::Added);
         let mut edge = self.clone();
         edge.nonce += 1;
-        let me = edge.peer0 == me;
-        let hash = edge.hash();
-        let signature = sk.sign(hash.as_ref());
-        edge.removal_info = Some((me, signature));
+        let by_peer0 = edge.peer0 == me;
+        let signature = sk.sign(edge.hash().as_ref());
+        edge.removal_proof = Some(EdgeRemovalProof::SignedByPeer { by_peer0, signature });
         edge
     }
 
@@ -173,39 +345,77 @@ impl Edge {
         Edge::build_hash(&self.peer0, &self.peer1, self.nonce - 1)
     }
 
+    /// Whether the edge is signed by the node keys of its peers.
     pub fn verify(&self) -> bool {
+        self.verify_with_sub_keys(&SubKeys::default())
+    }
+
+    /// Whether the edge is signed by its peers, each with its node key or one of its `sub_keys`.
+    pub fn verify_with_sub_keys(&self, sub_keys: &SubKeys) -> bool {
+        self.signatures_to_verify().map_or(false, |signatures| {
+            signatures
+                .iter()
+                .all(|(data, signature, peer)| sub_keys.verify(peer, data.as_ref(), signature))
+        })
+    }
+
+    /// Verify all edges at once using batch signature verification, expecting peers with
+    /// `sub_keys` to sign with their newest one. Returns `true` only if all edges are valid; edges
+    /// signed with another key of their peers need to be verified one by one.
+    pub fn verify_batch(edges: &[Edge], sub_keys: &SubKeys) -> bool {
+        let mut signatures = Vec::with_capacity(3 * edges.len());
+        for edge in edges {
+            match edge.signatures_to_verify() {
+                Some(edge_signatures) => {
+                    signatures.extend(edge_signatures.into_iter().map(|(data, signature, peer)| {
+                        (data, signature, sub_keys.preferred_key(peer))
+                    }))
+                }
+                None => return false,
+            }
+        }
+        let items: Vec<(&[u8], &Signature, &PublicKey)> = signatures
+            .iter()
+            .map(|(data, signature, key)| (data.as_ref(), *signature, key))
+            .collect();
+        near_crypto::verify_batch(&items)
+    }
+
+    /// List of `(data, signature, signer)` which all need to be valid for the edge to be valid.
+    /// Returns `None` if the edge is invalid regardless of the signatures.
+    fn signatures_to_verify(&self) -> Option<Vec<(CryptoHash, &Signature, &PeerId)>> {
         if self.peer0 > self.peer1 {
-            return false;
+            return None;
         }
 
+        let (key0, key1) = (&self.peer0, &self.peer1);
+
         match self.edge_type() {
             EdgeType::Added => {
+                if self.removal_proof.is_some() {
+                    return None;
+                }
                 let data = self.hash();
-
-                self.removal_info.is_none()
-                    && self.signature0.verify(data.as_ref(), &self.peer0.public_key())
-                    && self.signature1.verify(data.as_ref(), &self.peer1.public_key())
+                Some(vec![(data, &self.signature0, key0), (data, &self.signature1, key1)])
             }
             EdgeType::Removed => {
                 // nonce should be an even positive number
                 if self.nonce == 0 {
-                    return false;
+                    return None;
                 }
 
                 // Check referring added edge is valid.
                 let add_hash = self.prev_hash();
-                if !self.signature0.verify(add_hash.as_ref(), &self.peer0.public_key())
-                    || !self.signature1.verify(add_hash.as_ref(), &self.peer1.public_key())
-                {
-                    return false;
-                }
-
-                if let Some((party, signature)) = &self.removal_info {
-                    let peer = if *party { &self.peer0 } else { &self.peer1 };
-                    let del_hash = self.hash();
-                    signature.verify(del_hash.as_ref(), &peer.public_key())
-                } else {
-                    false
+                match &self.removal_proof {
+                    Some(EdgeRemovalProof::SignedByPeer { by_peer0, signature }) => {
+                        let key = if *by_peer0 { key0 } else { key1 };
+                        Some(vec![
+                            (add_hash, &self.signature0, key0),
+                            (add_hash, &self.signature1, key1),
+                            (self.hash(), signature, key),
+                        ])
+                    }
+                    Some(EdgeRemovalProof::Unknown { .. }) | None => None,
                 }
             }
         }
@@ -220,12 +430,19 @@ impl Edge {
     }
 
     /// Helper function when adding a new edge and we receive information from new potential peer
-    /// to verify the signature.
-    pub fn partial_verify(peer0: PeerId, peer1: PeerId, edge_info: &EdgeInfo) -> bool {
+    /// to verify the signature, made by the node key of `peer1` or one of its `sub_keys`.
+    pub fn partial_verify(
+        peer0: PeerId,
+        peer1: PeerId,
+        edge_info: &EdgeInfo,
+        sub_keys: &[PublicKey],
+    ) -> bool {
         let pk = peer1.public_key();
         let (peer0, peer1) = Edge::key(peer0, peer1);
         let data = Edge::build_hash(&peer0, &peer1, edge_info.nonce);
-        edge_info.signature.verify(data.as_ref(), &pk)
+        std::iter::once(&pk)
+            .chain(sub_keys)
+            .any(|key| edge_info.signature.verify(data.as_ref(), key))
     }
 
     pub fn get_pair(&self) -> (PeerId, PeerId) {
@@ -352,6 +569,19 @@ pub struct GetRoutingTableResult {
     pub edges_info: Vec<SimpleEdge>,
 }
 
+/// Internal state of the routing table, so integration tests can start from a precise state
+/// instead of replaying the traffic that led to it.
+#[cfg(feature = "test_features")]
+#[derive(MessageResponse, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RoutingTableSnapshot {
+    /// All known edges, both added and removed. The graph is rebuilt from the added ones.
+    pub edges: Vec<Edge>,
+    /// Milliseconds since each peer was last reachable.
+    pub peer_last_time_reachable: Vec<(PeerId, u64)>,
+    /// Last nonce used to store edges on disk.
+    pub component_nonce: u64,
+}
+
 pub struct EdgeVerifierHelper {
     /// Shared version of edges_info used by multiple threads
     pub edges_info_shared: Arc<Mutex<HashMap<(PeerId, PeerId), u64>>>,
@@ -372,8 +602,23 @@ impl Default for EdgeVerifierHelper {
 }
 
 pub struct RoutingTable {
-    /// PeerId associated for every known account id.
-    account_peers: SizedCache<AccountId, AnnounceAccount>,
+    /// Most recent announcement of every known account id.
+    account_peers: SyncLruCache<AccountId, AnnounceAccount>,
+    /// Announcements of every known account id from different peers in the latest known epoch
+    /// of the account, most recent first.
+    account_announcements: SyncLruCache<AccountId, Vec<AnnounceAccount>>,
+    /// Validators of the current and next epoch, as reported by the client.
+    /// `None` until the client reports them for the first time.
+    epoch_validators: Option<HashSet<AccountId>>,
+    /// Validators of each shard in the current epoch, as reported by the client.
+    shard_validators: HashMap<ShardId, Vec<AccountId>>,
+    /// Accounts in `account_peers` which are not validators. Their announcements are evicted
+    /// first from the cache and are not sent to new peers.
+    low_priority_accounts: HashSet<AccountId>,
+    /// Most recent epochs reported by the client, oldest first.
+    known_epochs: VecDeque<EpochId>,
+    /// Current epoch, as reported by the client. Announcements from older epochs expire.
+    current_epoch: Option<EpochId>,
     /// Active PeerId that are part of the shortest path to each PeerId.
     pub peer_forwarding: HashMap<PeerId, Vec<PeerId>>,
     /// Store last update for known edges.
@@ -391,54 +636,141 @@ pub struct RoutingTable {
     /// New routes are added with minimum nonce.
     route_nonce: SizedCache<PeerId, usize>,
     /// Ping received by nonce.
-    ping_info: SizedCache<usize, (Ping, usize)>,
+    ping_info: SizedCache<u64, (Ping, usize)>,
     /// Ping received by nonce.
-    pong_info: SizedCache<usize, (Pong, usize)>,
+    pong_info: SizedCache<u64, (Pong, usize)>,
     /// List of pings sent for which we haven't received any pong yet.
-    waiting_pong: SizedCache<PeerId, SizedCache<usize, Instant>>,
-    /// Last nonce sent to each peer through pings.
-    last_ping_nonce: SizedCache<PeerId, usize>,
+    waiting_pong: SizedCache<PeerId, SizedCache<u64, Instant>>,
+    /// Nonce of the next ping sent to each peer.
+    last_ping_nonce: SizedCache<PeerId, u64>,
+    /// Moving average of the round trip time in milliseconds of pings sent to each peer.
+    peer_latency: SizedCache<PeerId, f64>,
+    /// Round trip time in milliseconds of recently answered pings, by target and nonce.
+    ping_rtts: SizedCache<(PeerId, u64), f64>,
+    /// Where and when announcements were first learned, by account, peer and epoch.
+    announcement_origins: SizedCache<(AccountId, PeerId, EpochId), AnnouncementOrigin>,
+    /// Announcements being verified, by the peer and nonce of the ping sent to verify them.
+    announcement_verifications: SizedCache<(PeerId, u64), (AccountId, PeerId, EpochId)>,
+    /// Latest relays announced by nodes hiding their address, by node.
+    proxies: SizedCache<PeerId, ProxyAnnouncement>,
+    /// Recent failures to deliver messages to each target: time, next hop and reason.
+    route_failures: SizedCache<PeerId, VecDeque<(Instant, Option<PeerId>, String)>>,
+    /// Penalty of next hops which recently failed to deliver messages to a target, by target
+    /// and next hop, with the time it was last updated. Penalties decay exponentially.
+    avoided_next_hops: SizedCache<(PeerId, PeerId), (f64, Instant)>,
+    /// Peers with a low score, only used as next hops if there is no other route.
+    deprioritized_peers: HashSet<PeerId>,
+    /// Peers banned by us. Their edges are ignored and they are never used as next hops.
+    banned_peers: HashSet<PeerId>,
+    /// Shards tracked by each active peer, as advertised in its handshake and chain info.
+    peer_shards: HashMap<PeerId, Vec<ShardId>>,
     /// Last nonce used to store edges on disk.
     pub component_nonce: u64,
-}
-
-#[derive(Debug)]
-pub enum FindRouteError {
-    Disconnected,
-    PeerNotFound,
-    AccountNotFound,
-    RouteBackNotFound,
+    /// Edges processed since the database was created, saved with `save_lifetime_edge_stats`.
+    lifetime_edge_stats: LifetimeEdgeStats,
+    /// Decisions of `config.shadow_strategy` compared with the ones used.
+    shadow_stats: ShadowRoutingStats,
+    /// Whether the fraction of peers reached through a single next hop was above
+    /// `SINGLE_NEXT_HOP_WARNING_FRACTION` at the last recalculation.
+    single_next_hop_warning: bool,
+    /// When the last recalculation finished and how long it took.
+    last_recalculation: Option<(DateTime<Utc>, Duration)>,
+    /// Edges added to or removed from the graph since the last recalculation.
+    edges_since_recalculation: u64,
+    /// Sizes of caches and timeouts.
+    config: RoutingTableConfig,
+    clock: Clock,
 }
 
 impl RoutingTable {
     pub fn new(peer_id: PeerId, store: Arc<Store>) -> Self {
+        Self::with_config(peer_id, store, RoutingTableConfig::default())
+    }
+
+    pub fn with_config(peer_id: PeerId, store: Arc<Store>, config: RoutingTableConfig) -> Self {
+        Self::with_config_and_clock(peer_id, store, config, Clock::real())
+    }
+
+    /// Same as `with_config`, with the time read from `clock`, also when restoring the route
+    /// back entries saved on disk.
+    pub fn with_config_and_clock(
+        peer_id: PeerId,
+        store: Arc<Store>,
+        config: RoutingTableConfig,
+        clock: Clock,
+    ) -> Self {
         // Find greater nonce on disk and set `component_nonce` to this value.
         let component_nonce = store
             .get_ser::<u64>(ColLastComponentNonce, &[])
             .unwrap_or(None)
             .map_or(0, |nonce| nonce + 1);
+        let lifetime_edge_stats = LifetimeEdgeStats::load(&store);
+        lifetime_edge_stats.export();
+        let mut route_back = RouteBackCache::new(
+            config.route_back_cache_size,
+            config.route_back_cache_evict_timeout,
+            config.route_back_cache_remove_batch,
+        )
+        .with_peer_quota(config.route_back_cache_peer_quota)
+        .with_clock(clock.clone());
+        if config.persist_route_back {
+            match route_back.load(&store, to_timestamp(clock.now_utc())) {
+                Ok(restored) => {
+                    debug!(target: "network", "Restored {} route back entries", restored)
+                }
+                Err(err) => {
+                    warn!(target: "network", "Failed to restore route back entries: {}", err)
+                }
+            }
+        }
 
         Self {
-            account_peers: SizedCache::with_size(ANNOUNCE_ACCOUNT_CACHE_SIZE),
+            account_peers: SyncLruCache::new(config.announce_account_cache_size),
+            account_announcements: SyncLruCache::new(config.announce_account_cache_size),
+            epoch_validators: None,
+            shard_validators: HashMap::new(),
+            low_priority_accounts: Default::default(),
+            known_epochs: Default::default(),
+            current_epoch: None,
             peer_forwarding: Default::default(),
             edges_info: Default::default(),
-            route_back: RouteBackCache::new(
-                ROUTE_BACK_CACHE_SIZE,
-                ROUTE_BACK_CACHE_EVICT_TIMEOUT,
-                ROUTE_BACK_CACHE_REMOVE_BATCH,
-            ),
+            route_back,
             peer_last_time_reachable: Default::default(),
             store,
-            raw_graph: Graph::new(peer_id),
+            raw_graph: Graph::new(peer_id).with_incremental(GRAPH_FULL_RECOMPUTE_PERIOD),
             route_nonce: SizedCache::with_size(ROUND_ROBIN_NONCE_CACHE_SIZE),
             ping_info: SizedCache::with_size(PING_PONG_CACHE_SIZE),
             pong_info: SizedCache::with_size(PING_PONG_CACHE_SIZE),
             waiting_pong: SizedCache::with_size(PING_PONG_CACHE_SIZE),
             last_ping_nonce: SizedCache::with_size(PING_PONG_CACHE_SIZE),
+            peer_latency: SizedCache::with_size(PING_PONG_CACHE_SIZE),
+            ping_rtts: SizedCache::with_size(PING_PONG_CACHE_SIZE),
+            announcement_origins: SizedCache::with_size(config.announce_account_cache_size),
+            announcement_verifications: SizedCache::with_size(PING_PONG_CACHE_SIZE),
+            proxies: SizedCache::with_size(config.announce_account_cache_size),
+            route_failures: SizedCache::with_size(ROUTE_FAILURES_CACHE_SIZE),
+            avoided_next_hops: SizedCache::with_size(AVOIDED_NEXT_HOPS_CACHE_SIZE),
+            deprioritized_peers: Default::default(),
+            banned_peers: Default::default(),
+            peer_shards: Default::default(),
             component_nonce,
+            lifetime_edge_stats,
+            shadow_stats: Default::default(),
+            single_next_hop_warning: false,
+            last_recalculation: None,
+            edges_since_recalculation: 0,
+            config,
+            clock,
         }
     }
 
+    /// Read the time from `clock` instead of the system clock, also in the route back cache.
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.route_back = self.route_back.with_clock(clock.clone());
+        self.clock = clock;
+        self
+    }
+
     fn peer_id(&self) -> &PeerId {
         &self.raw_graph.source
     }
@@ -450,20 +782,51 @@ impl RoutingTable {
     /// Find peer that is connected to `source` and belong to the shortest path
     /// from `source` to `peer_id`.
     pub fn find_route_from_peer_id(&mut self, peer_id: &PeerId) -> Result<PeerId, FindRouteError> {
-        if let Some(routes) = self.peer_forwarding.get(&peer_id).cloned() {
-            if routes.is_empty() {
+        self.find_route_preferring_shard(peer_id, None)
+    }
+
+    /// Same as `find_route_from_peer_id`, but only next hops which track `shard_id` are used if
+    /// there are any, so requests for data of the shard go through nodes which can serve it.
+    pub fn find_route_via_shard(
+        &mut self,
+        peer_id: &PeerId,
+        shard_id: ShardId,
+    ) -> Result<PeerId, FindRouteError> {
+        self.find_route_preferring_shard(peer_id, Some(shard_id))
+    }
+
+    fn find_route_preferring_shard(
+        &mut self,
+        peer_id: &PeerId,
+        shard_id: Option<ShardId>,
+    ) -> Result<PeerId, FindRouteError> {
+        if let Some(mut routes) = self.peer_forwarding.get(&peer_id).cloned() {
+            if let Some(shard_id) = shard_id {
+                let tracking: Vec<_> = routes
+                    .iter()
+                    .filter(|peer_id| {
+                        !self.banned_peers.contains(peer_id) && self.tracks_shard(peer_id, shard_id)
+                    })
+                    .cloned()
+                    .collect();
+                if tracking.is_empty() {
+                    near_metrics::inc_counter(&metrics::ROUTING_SHARD_AWARE_FALLBACKS);
+                } else {
+                    routes = tracking;
+                }
+            }
+            let candidates = self.next_hop_candidates(peer_id, &routes);
+            if candidates.is_empty() {
                 return Err(FindRouteError::Disconnected);
             }
 
-            // Strategy similar to Round Robin. Select node with least nonce and send it. Increase its
-            // nonce by one. Additionally if the difference between the highest nonce and the lowest
-            // nonce is greater than some threshold increase the lowest nonce to be at least
-            // max nonce - threshold.
-            let nonce_peer = routes
-                .iter()
-                .map(|peer_id| {
-                    (self.route_nonce.cache_get(&peer_id).cloned().unwrap_or(0), peer_id)
-                })
+            // Strategy similar to Round Robin among the next hops eligible under the configured
+            // strategy. Select node with least nonce and send it. Increase its nonce by one.
+            // Additionally if the difference between the highest nonce and the lowest nonce is
+            // greater than some threshold increase the lowest nonce to be at least max nonce -
+            // threshold.
+            let nonce_peer = Self::eligible_next_hops(self.config.strategy, &candidates)
+                .map(|candidate| (candidate.nonce, candidate.peer_id.clone()))
                 .collect::<Vec<_>>();
 
             // Neighbor with minimum and maximum nonce respectively.
@@ -478,171 +841,835 @@ impl RoutingTable {
             let next_hop = min_v.1;
             let nonce = self.route_nonce.cache_get(&next_hop).cloned();
             self.route_nonce.cache_set(next_hop.clone(), nonce.map_or(1, |nonce| nonce + 1));
-            Ok(next_hop.clone())
+            if let Some(shadow_strategy) = self.config.shadow_strategy {
+                self.record_shadow_decision(peer_id, shadow_strategy, &candidates, &next_hop);
+            }
+            Ok(next_hop)
+        } else if let Some(relay) = self.reachable_relay(peer_id) {
+            near_metrics::inc_counter(&metrics::ROUTED_THROUGH_PROXY_RELAY);
+            self.find_route_preferring_shard(&relay, None)
         } else {
             Err(FindRouteError::PeerNotFound)
         }
     }
 
-    pub fn find_route(&mut self, target: &PeerIdOrHash) -> Result<PeerId, FindRouteError> {
-        match target {
-            PeerIdOrHash::PeerId(peer_id) => self.find_route_from_peer_id(&peer_id),
-            PeerIdOrHash::Hash(hash) => {
-                self.fetch_route_back(hash.clone()).ok_or(FindRouteError::RouteBackNotFound)
+    /// First relay announced by `peer_id` we have a route to, if it hides its address behind
+    /// relays.
+    fn reachable_relay(&mut self, peer_id: &PeerId) -> Option<PeerId> {
+        let relays = self.proxy_relays(peer_id)?;
+        relays.into_iter().find(|relay| {
+            self.peer_forwarding.contains_key(relay) && !self.banned_peers.contains(relay)
+        })
+    }
+
+    /// Add the relays announced by a node hiding its address, unless we know a newer
+    /// announcement of the node or it expired. Returns whether it was added, in which case it
+    /// should be broadcast.
+    pub fn add_proxy_announcement(&mut self, announcement: ProxyAnnouncement) -> bool {
+        if proxy_announcement_expired(&announcement, to_timestamp(self.clock.now_utc())) {
+            return false;
+        }
+        if let Some(known) = self.proxies.cache_get(&announcement.peer_id) {
+            if known.timestamp >= announcement.timestamp {
+                return false;
             }
         }
+        self.proxies.cache_set(announcement.peer_id.clone(), announcement);
+        true
     }
 
-    /// Find peer that owns this AccountId.
-    pub fn account_owner(&mut self, account_id: &AccountId) -> Result<PeerId, FindRouteError> {
-        self.get_announce(account_id)
-            .map(|announce_account| announce_account.peer_id)
-            .ok_or_else(|| FindRouteError::AccountNotFound)
+    /// Relays `peer_id` announced it's reached through, if its announcement didn't expire.
+    pub fn proxy_relays(&mut self, peer_id: &PeerId) -> Option<Vec<PeerId>> {
+        let now = to_timestamp(self.clock.now_utc());
+        match self.proxies.cache_get(peer_id) {
+            Some(announcement) if !proxy_announcement_expired(announcement, now) => {
+                Some(announcement.relays.clone())
+            }
+            Some(_) => {
+                self.proxies.cache_remove(peer_id);
+                None
+            }
+            None => None,
+        }
     }
 
-    /// Add (account id, peer id) to routing table.
-    /// Note: There is at most on peer id per account id.
-    pub fn add_account(&mut self, announce_account: AnnounceAccount) {
-        let account_id = announce_account.account_id.clone();
-        self.account_peers.cache_set(account_id.clone(), announce_account.clone());
+    /// Announcements of relays which didn't expire, sent to new peers.
+    pub fn get_proxy_announcements(&self) -> Vec<ProxyAnnouncement> {
+        let now = to_timestamp(self.clock.now_utc());
+        self.proxies
+            .value_order()
+            .filter(|announcement| !proxy_announcement_expired(announcement, now))
+            .cloned()
+            .collect()
+    }
 
-        // Add account to store
-        let mut update = self.store.store_update();
-        if let Err(e) = update
-            .set_ser(ColAccountAnnouncements, account_id.as_ref().as_bytes(), &announce_account)
-            .and_then(|_| update.commit())
-        {
-            warn!(target: "network", "Error saving announce account to store: {:?}", e);
-        }
+    /// Label active peer `peer_id` with the shards it tracks.
+    pub fn set_peer_shards(&mut self, peer_id: PeerId, shards: Vec<ShardId>) {
+        self.peer_shards.insert(peer_id, shards);
     }
 
-    // TODO(MarX, #1694): Allow one account id to be routed to several peer id.
-    pub fn contains_account(&mut self, announce_account: &AnnounceAccount) -> bool {
-        self.get_announce(&announce_account.account_id).map_or(false, |current_announce_account| {
-            current_announce_account.epoch_id == announce_account.epoch_id
-        })
+    pub fn remove_peer_shards(&mut self, peer_id: &PeerId) {
+        self.peer_shards.remove(peer_id);
     }
 
-    /// Get the nonce of the component where the peer was stored
-    fn component_nonce_from_peer(&mut self, peer_id: PeerId) -> Result<u64, ()> {
-        match self.store.get_ser::<u64>(ColPeerComponent, Vec::from(peer_id).as_ref()) {
-            Ok(Some(nonce)) => Ok(nonce),
-            _ => Err(()),
-        }
+    /// Whether active peer `peer_id` tracks `shard_id`.
+    pub fn tracks_shard(&self, peer_id: &PeerId, shard_id: ShardId) -> bool {
+        self.peer_shards.get(peer_id).map_or(false, |shards| shards.contains(&shard_id))
     }
 
-    /// Get all edges in the component with `nonce`
-    /// Remove those edges from the store.
-    fn get_component_edges(
-        &mut self,
-        nonce: u64,
-        update: &mut StoreUpdate,
-    ) -> Result<Vec<Edge>, ()> {
-        let enc_nonce = index_to_bytes(nonce);
+    /// Next hops on the shortest paths to `target` which track `shard_id`.
+    pub fn next_hops_tracking_shard(&self, target: &PeerId, shard_id: ShardId) -> Vec<PeerId> {
+        self.peer_forwarding
+            .get(target)
+            .map(|routes| {
+                routes
+                    .iter()
+                    .filter(|peer_id| self.tracks_shard(peer_id, shard_id))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
 
-        let res = match self.store.get_ser::<Vec<Edge>>(ColComponentEdges, enc_nonce.as_ref()) {
-            Ok(Some(edges)) => Ok(edges),
-            _ => Err(()),
-        };
+    /// Round robin nonce and latency of each of `routes` to `target`, except banned peers.
+    /// Peers with unknown latency are as good as the fastest one, so they keep getting traffic
+    /// until they are measured. Deprioritized peers and next hops avoided for `target` are
+    /// ignored unless all routes go through them.
+    fn next_hop_candidates(&mut self, target: &PeerId, routes: &[PeerId]) -> Vec<NextHopCandidate> {
+        let now = self.clock.now();
+        let mut candidates = routes
+            .iter()
+            .filter(|peer_id| !self.banned_peers.contains(peer_id))
+            .map(|peer_id| NextHopCandidate {
+                peer_id: peer_id.clone(),
+                nonce: self.route_nonce.cache_get(peer_id).cloned().unwrap_or(0),
+                latency: self.peer_latency.cache_get(peer_id).cloned(),
+                deprioritized: self.deprioritized_peers.contains(peer_id),
+                avoided: Self::next_hop_penalty(&mut self.avoided_next_hops, target, peer_id, now)
+                    > NEXT_HOP_AVOIDANCE_THRESHOLD,
+                fastest: false,
+            })
+            .collect::<Vec<_>>();
+        let only_penalized = candidates.iter().all(NextHopCandidate::penalized);
+        let usable = |c: &NextHopCandidate| only_penalized || !c.penalized();
+        let bucket = |latency: f64| (latency / ROUTE_LATENCY_BUCKET_MS) as u64;
+        let best_bucket =
+            candidates.iter().filter(|c| usable(c)).filter_map(|c| c.latency.map(bucket)).min();
+        for candidate in candidates.iter_mut() {
+            candidate.fastest = usable(candidate)
+                && candidate.latency.map(bucket).map_or(true, |b| Some(b) == best_bucket);
+        }
+        candidates
+    }
 
-        update.delete(ColComponentEdges, enc_nonce.as_ref());
+    /// Candidates `strategy` chooses the next hop from. Never empty if `candidates` isn't.
+    fn eligible_next_hops(
+        strategy: RoutingStrategy,
+        candidates: &[NextHopCandidate],
+    ) -> impl Iterator<Item = &NextHopCandidate> {
+        let only_penalized = candidates.iter().all(NextHopCandidate::penalized);
+        candidates.iter().filter(move |c| match strategy {
+            RoutingStrategy::RoundRobin => only_penalized || !c.penalized(),
+            RoutingStrategy::LatencyAware => c.fastest,
+        })
+    }
 
-        res
+    /// Next hop `strategy` would choose, without updating round robin nonces.
+    fn select_next_hop(
+        strategy: RoutingStrategy,
+        candidates: &[NextHopCandidate],
+    ) -> Option<&NextHopCandidate> {
+        Self::eligible_next_hops(strategy, candidates)
+            .min_by(|a, b| a.nonce.cmp(&b.nonce).then_with(|| a.peer_id.cmp(&b.peer_id)))
     }
 
-    /// If peer_id is not on memory check if it is on disk in bring it back on memory.
-    fn touch(&mut self, peer_id: &PeerId) {
-        if peer_id == self.peer_id() || self.peer_last_time_reachable.contains_key(peer_id) {
+    /// Compare the next hop `shadow_strategy` would have chosen with `next_hop`, which was used.
+    /// `candidates` are the ones `next_hop` was chosen from, before its nonce was increased.
+    fn record_shadow_decision(
+        &mut self,
+        target: &PeerId,
+        shadow_strategy: RoutingStrategy,
+        candidates: &[NextHopCandidate],
+        next_hop: &PeerId,
+    ) {
+        let shadow = match Self::select_next_hop(shadow_strategy, candidates) {
+            Some(shadow) => shadow,
+            None => return,
+        };
+        near_metrics::inc_counter(&metrics::ROUTING_SHADOW_DECISIONS);
+        self.shadow_stats.decisions += 1;
+        if &shadow.peer_id == next_hop {
             return;
         }
+        near_metrics::inc_counter(&metrics::ROUTING_SHADOW_DIVERGENCES);
+        self.shadow_stats.divergences += 1;
+        let latency = candidates.iter().find(|c| &c.peer_id == next_hop).and_then(|c| c.latency);
+        if let (Some(latency), Some(shadow_latency)) = (latency, shadow.latency) {
+            self.shadow_stats.latency_difference_ms += shadow_latency - latency;
+        }
+        debug!(target: "network", "Shadow routing strategy {:?} diverged for {}: used {} ({:?}ms), would use {} ({:?}ms)",
+               shadow_strategy, target, next_hop, latency, shadow.peer_id, shadow.latency);
+    }
 
-        let me = self.peer_id().clone();
+    /// Decisions of the shadow routing strategy so far.
+    pub fn shadow_stats(&self) -> ShadowRoutingStats {
+        self.shadow_stats
+    }
 
-        if let Ok(nonce) = self.component_nonce_from_peer(peer_id.clone()) {
-            let mut update = self.store.store_update();
+    /// Replace the set of peers with a low score.
+    pub fn set_deprioritized_peers(&mut self, peers: HashSet<PeerId>) {
+        self.deprioritized_peers = peers;
+    }
 
-            if let Ok(edges) = self.get_component_edges(nonce, &mut update) {
-                for edge in edges {
-                    for &peer_id in vec![&edge.peer0, &edge.peer1].iter() {
-                        if peer_id == &me || self.peer_last_time_reachable.contains_key(peer_id) {
-                            continue;
-                        }
+    /// Avoid `peer_id` as next hop until the next call to `set_deprioritized_peers`.
+    pub fn deprioritize_peer(&mut self, peer_id: PeerId) {
+        self.deprioritized_peers.insert(peer_id);
+    }
 
-                        if let Ok(cur_nonce) = self.component_nonce_from_peer(peer_id.clone()) {
-                            if cur_nonce == nonce {
-                                self.peer_last_time_reachable
-                                    .insert(peer_id.clone(), Instant::now() - SAVE_PEERS_MAX_TIME);
-                                update
-                                    .delete(ColPeerComponent, Vec::from(peer_id.clone()).as_ref());
-                            }
-                        }
-                    }
-                    self.add_edge(edge);
-                }
-            }
+    /// Remove the active edges of `peer_id` from the graph and ignore its edges until
+    /// `unban_peer` is called, so routes stop going through it right away. Removal edges are
+    /// kept, so older active edges can't be accepted again once it is unbanned.
+    /// Returns the removed edges.
+    pub fn ban_peer(&mut self, peer_id: PeerId) -> Vec<Edge> {
+        let edges = self
+            .edges_info
+            .values()
+            .filter(|edge| edge.edge_type() == EdgeType::Added && edge.contains_peer(&peer_id))
+            .cloned()
+            .collect::<Vec<_>>();
+        self.remove_edges(&edges);
+        self.banned_peers.insert(peer_id);
+        edges
+    }
 
-            if let Err(e) = update.commit() {
-                warn!(target: "network", "Error removing network component from store. {:?}", e);
-            }
-        } else {
-            self.peer_last_time_reachable.insert(peer_id.clone(), Instant::now());
-        }
+    pub fn unban_peer(&mut self, peer_id: &PeerId) {
+        self.banned_peers.remove(peer_id);
     }
 
-    pub fn remove_edges(&mut self, edges: &Vec<Edge>) {
-        for edge in edges.iter() {
-            let key = (edge.peer0.clone(), edge.peer1.clone());
-            if self.edges_info.remove(&key).is_some() {
-                self.raw_graph.remove_edge(&edge.peer0, &edge.peer1);
-            }
-        }
+    pub fn is_banned(&self, peer_id: &PeerId) -> bool {
+        self.banned_peers.contains(peer_id)
     }
 
-    fn add_edge(&mut self, edge: Edge) -> bool {
-        let key = edge.get_pair();
+    /// Explain which next hop would be used to route a message to `target` and why.
+    /// Unlike `find_route_from_peer_id`, round robin nonces are not updated.
+    pub fn explain_route(&mut self, target: &PeerId) -> RouteExplanation {
+        let mut explanation = self.explain_next_hops(target);
+        explanation.distance = self.raw_graph.distance(target);
+        explanation
+    }
 
-        if self.find_nonce(&key) >= edge.nonce {
-            // We already have a newer information about this edge. Discard this information.
-            false
-        } else {
-            match edge.edge_type() {
-                EdgeType::Added => {
-                    self.raw_graph.add_edge(key.0.clone(), key.1.clone());
-                }
-                EdgeType::Removed => {
-                    self.raw_graph.remove_edge(&key.0, &key.1);
+    /// `explain_route` without the distance to `target`, which may take a shortest path search.
+    /// The routing table actor fills it in, see `RoutingTableMessages::ExplainRoute`.
+    pub fn explain_next_hops(&mut self, target: &PeerId) -> RouteExplanation {
+        let now = self.clock.now();
+        let routes = self.peer_forwarding.get(target).cloned().unwrap_or_default();
+        let candidates = self.next_hop_candidates(target, &routes);
+        let recent_failures = self
+            .route_failures
+            .cache_get(target)
+            .map(|failures| {
+                failures
+                    .iter()
+                    .map(|(time, next_hop, reason)| RouteFailure {
+                        millis_ago: Time::duration_since(now, *time).as_millis() as u64,
+                        next_hop: next_hop.clone(),
+                        reason: reason.clone(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let selected = Self::select_next_hop(self.config.strategy, &candidates)
+            .map(|candidate| candidate.peer_id.clone());
+        let shadow_selected = self.config.shadow_strategy.and_then(|strategy| {
+            Self::select_next_hop(strategy, &candidates).map(|candidate| candidate.peer_id.clone())
+        });
+        let rationale = match (&selected, self.peer_forwarding.contains_key(target)) {
+            (_, false) => format!("{:?}", FindRouteError::PeerNotFound),
+            (None, true) => format!("{:?}", FindRouteError::Disconnected),
+            (Some(_), true) => {
+                let eligible = Self::eligible_next_hops(self.config.strategy, &candidates).count();
+                match self.config.strategy {
+                    RoutingStrategy::RoundRobin => format!(
+                        "{} of {} next hops are usable, \
+                         selected the one with the lowest round robin nonce",
+                        eligible,
+                        candidates.len(),
+                    ),
+                    RoutingStrategy::LatencyAware => format!(
+                        "{} of {} next hops are in the lowest latency bucket of {}ms, \
+                         selected the one with the lowest round robin nonce",
+                        eligible,
+                        candidates.len(),
+                        ROUTE_LATENCY_BUCKET_MS
+                    ),
                 }
             }
-            self.edges_info.insert(key, edge);
-            true
+        };
+
+        RouteExplanation {
+            target: target.clone(),
+            distance: None,
+            candidates,
+            recent_failures,
+            selected,
+            shadow_selected,
+            rationale,
         }
     }
 
-    /// Add several edges to the current view of the network.
-    /// These edges are assumed to be valid at this point.
-    /// Return true if some of the edges contains new information to the network.
-    pub fn process_edges(&mut self, edges: Vec<Edge>) -> ProcessEdgeResult {
-        let mut new_edge = false;
-        let total = edges.len();
-        let mut result = Vec::with_capacity(edges.len() as usize);
+    /// Explain each stage of routing a message to `account_id`, see `account_owners`.
+    pub fn explain_account(&mut self, account_id: &AccountId) -> AccountRouteExplanation {
+        let cached = self.account_peers.contains(account_id);
+        let announce_account = self.get_announce(account_id);
+        let announcement = match (&announce_account, cached) {
+            (None, _) => None,
+            (Some(_), true) => Some(AnnouncementSource::Cache),
+            (Some(_), false) => Some(AnnouncementSource::Store),
+        };
+        let owners = self.account_owners(account_id).unwrap_or_default();
+        let me = self.peer_id().clone();
+        let routes: Vec<_> = owners
+            .iter()
+            .filter(|peer_id| **peer_id != me)
+            .map(|peer_id| self.explain_route(peer_id))
+            .collect();
 
-        for edge in edges {
-            let key = edge.get_pair();
+        let diagnosis = if announce_account.is_none() {
+            "no announcement of the account is known".to_string()
+        } else if owners.contains(&me) {
+            "the account is announced by this node".to_string()
+        } else if let Some(route) = routes.iter().find(|route| route.selected.is_some()) {
+            format!("routable to {} through {}", route.target, route.selected.as_ref().unwrap())
+        } else if routes.iter().all(|route| route.distance.is_none()) {
+            "none of the peers which announced the account are reachable".to_string()
+        } else {
+            "the peers which announced the account are reachable, \
+             but all next hops are disconnected"
+                .to_string()
+        };
 
-            self.touch(&key.0);
-            self.touch(&key.1);
+        let origins = match &announce_account {
+            Some(announce_account) => owners
+                .iter()
+                .filter_map(|peer_id| {
+                    let key =
+                        (account_id.clone(), peer_id.clone(), announce_account.epoch_id.clone());
+                    let origin = self.announcement_origins.cache_get(&key)?;
+                    Some((peer_id.clone(), origin.clone()))
+                })
+                .collect(),
+            None => vec![],
+        };
 
-            if self.add_edge(edge.clone()) {
-                new_edge = true;
-                result.push(edge);
-            }
+        AccountRouteExplanation {
+            account_id: account_id.clone(),
+            announcement,
+            epoch_id: announce_account.map(|announce_account| announce_account.epoch_id),
+            origins,
+            routes,
+            diagnosis,
+        }
+    }
+
+    /// Remember that a message to `target` couldn't be delivered, e.g. because sending it failed
+    /// or no response arrived in time. `next_hop` is then avoided for `target` for a while, longer
+    /// after repeated failures, so further messages take another route if there is one.
+    pub fn record_route_failure(
+        &mut self,
+        target: &PeerId,
+        next_hop: Option<PeerId>,
+        reason: String,
+    ) {
+        let now = self.clock.now();
+        if let Some(next_hop) = next_hop.as_ref() {
+            let penalty =
+                Self::next_hop_penalty(&mut self.avoided_next_hops, target, next_hop, now);
+            self.avoided_next_hops
+                .cache_set((target.clone(), next_hop.clone()), (penalty + 1.0, now));
+        }
+        if let Some(failures) = self.route_failures.cache_get_mut(target) {
+            if failures.len() >= MAX_ROUTE_FAILURES_PER_TARGET {
+                failures.pop_front();
+            }
+            failures.push_back((now, next_hop, reason));
+        } else {
+            self.route_failures.cache_set(target.clone(), vec![(now, next_hop, reason)].into());
+        }
+    }
+
+    /// Penalty of `next_hop` for `target` at `now`, zero if it never failed.
+    fn next_hop_penalty(
+        avoided_next_hops: &mut SizedCache<(PeerId, PeerId), (f64, Instant)>,
+        target: &PeerId,
+        next_hop: &PeerId,
+        now: Instant,
+    ) -> f64 {
+        match avoided_next_hops.cache_get(&(target.clone(), next_hop.clone())) {
+            Some((penalty, updated)) => {
+                let half_lives = Time::duration_since(now, *updated).as_secs_f64()
+                    / NEXT_HOP_AVOIDANCE_HALF_LIFE.as_secs_f64();
+                penalty * 0.5f64.powf(half_lives)
+            }
+            None => 0.0,
+        }
+    }
+
+    pub fn find_route(&mut self, target: &PeerIdOrHash) -> Result<PeerId, FindRouteError> {
+        match target {
+            PeerIdOrHash::PeerId(peer_id) => self.find_route_from_peer_id(&peer_id),
+            PeerIdOrHash::Hash(hash) => {
+                self.fetch_route_back(hash.clone()).ok_or(FindRouteError::RouteBackNotFound)
+            }
+        }
+    }
+
+    /// Find peer that owns this AccountId. See `account_owners`.
+    pub fn account_owner(&mut self, account_id: &AccountId) -> Result<PeerId, FindRouteError> {
+        self.account_owners(account_id).map(|mut owners| owners.swap_remove(0))
+    }
+
+    /// Remember that `announce_account` was first received from `source`, `None` if it's ours,
+    /// after traveling `hops` hops if the sender told. Later copies of the announcement don't
+    /// change where it was first learned. Returns whether the announcement should be verified
+    /// with the peer which announced it, because it traveled more than `max_announcement_hops`.
+    pub fn record_announcement_origin(
+        &mut self,
+        announce_account: &AnnounceAccount,
+        source: Option<PeerId>,
+        hops: Option<u32>,
+    ) -> bool {
+        let key = announcement_key(announce_account);
+        if self.announcement_origins.cache_get(&key).is_some() {
+            return false;
+        }
+        let origin =
+            AnnouncementOrigin { source, hops, verified: false, first_seen: self.clock.now() };
+        self.announcement_origins.cache_set(key, origin);
+        hops.map_or(false, |hops| hops > self.config.max_announcement_hops)
+    }
+
+    /// Where and when `announce_account` was first learned, if it still is remembered.
+    pub fn announcement_origin(
+        &mut self,
+        announce_account: &AnnounceAccount,
+    ) -> Option<AnnouncementOrigin> {
+        self.announcement_origins.cache_get(&announcement_key(announce_account)).cloned()
+    }
+
+    /// Number of hops `announce_account` traveled to us, if known.
+    pub fn announcement_hops(&mut self, announce_account: &AnnounceAccount) -> Option<u32> {
+        self.announcement_origin(announce_account).and_then(|origin| origin.hops)
+    }
+
+    /// Remember that a pong to the ping with `nonce` sent to the peer which announced
+    /// `announce_account` verifies the announcement.
+    pub fn verifying_announcement(&mut self, announce_account: &AnnounceAccount, nonce: u64) {
+        self.announcement_verifications.cache_set(
+            (announce_account.peer_id.clone(), nonce),
+            announcement_key(announce_account),
+        );
+    }
+
+    /// Find all peers that announced this AccountId in its latest known epoch. Reachable peers
+    /// come first, and among those the most recently announced ones, so messages to the
+    /// account fail over to an alternate peer if the preferred one is not reachable.
+    pub fn account_owners(
+        &mut self,
+        account_id: &AccountId,
+    ) -> Result<Vec<PeerId>, FindRouteError> {
+        let announce_account =
+            self.get_announce(account_id).ok_or_else(|| FindRouteError::AccountNotFound)?;
+        let mut owners = self
+            .account_announcements
+            .get(account_id)
+            .map(|announcements| {
+                announcements.iter().map(|announce| announce.peer_id.clone()).collect::<Vec<_>>()
+            })
+            .unwrap_or_else(|| vec![announce_account.peer_id]);
+        // Stable sort keeps the most recent announcements first.
+        owners.sort_by_key(|peer_id| !self.peer_forwarding.contains_key(peer_id));
+        Ok(owners)
+    }
+
+    /// Add (account id, peer id) to routing table.
+    /// Announcements of the same account from a different epoch replace all previous ones,
+    /// unless they are stale, see `is_stale_announcement`.
+    pub fn add_account(&mut self, announce_account: AnnounceAccount) {
+        if self.is_stale_announcement(&announce_account) {
+            return;
+        }
+        let account_id = announce_account.account_id.clone();
+        if self.account_peers.len() >= self.config.announce_account_cache_size
+            && !self.account_peers.contains(&account_id)
+        {
+            // Evict a non-validator before the least recently used account.
+            while let Some(evicted) = self.low_priority_accounts.iter().next().cloned() {
+                self.low_priority_accounts.remove(&evicted);
+                self.account_announcements.remove(&evicted);
+                if self.account_peers.remove(&evicted).is_some() {
+                    break;
+                }
+            }
+        }
+        self.account_peers.put(account_id.clone(), announce_account.clone());
+        let announcements = match self.account_announcements.remove(&account_id) {
+            Some(mut announcements)
+                if announcements
+                    .first()
+                    .map_or(false, |announce| announce.epoch_id == announce_account.epoch_id) =>
+            {
+                announcements.retain(|announce| announce.peer_id != announce_account.peer_id);
+                announcements.insert(0, announce_account.clone());
+                announcements.truncate(MAX_PEERS_PER_ACCOUNT);
+                announcements
+            }
+            _ => vec![announce_account.clone()],
+        };
+        self.account_announcements.put(account_id.clone(), announcements);
+        if self.is_low_priority_account(&account_id) {
+            self.low_priority_accounts.insert(account_id.clone());
+        } else {
+            self.low_priority_accounts.remove(&account_id);
+        }
+
+        // Add account to store
+        let mut update = self.store.store_update();
+        if let Err(e) = update
+            .set_ser(ColAccountAnnouncements, account_id.as_ref().as_bytes(), &announce_account)
+            .and_then(|_| update.commit())
+        {
+            warn!(target: "network", "Error saving announce account to store: {:?}", e);
+        }
+    }
+
+    /// Position of `epoch_id` among the known epochs, higher is newer.
+    fn epoch_index(&self, epoch_id: &EpochId) -> Option<usize> {
+        self.known_epochs.iter().position(|known| known == epoch_id)
+    }
+
+    /// Whether `epoch_id` is known to be older than the current epoch.
+    fn is_expired_epoch(&self, epoch_id: &EpochId) -> bool {
+        match (self.epoch_index(epoch_id), self.current_epoch.as_ref()) {
+            (Some(index), Some(current_epoch)) => {
+                self.epoch_index(current_epoch).map_or(false, |current| index < current)
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the announcement is from an epoch older than the current one, or older than the
+    /// epoch of the announcement we have for the account. Epochs we don't know the order of
+    /// are never considered older.
+    pub fn is_stale_announcement(&self, announce_account: &AnnounceAccount) -> bool {
+        if self.is_expired_epoch(&announce_account.epoch_id) {
+            return true;
+        }
+        let current_epoch = match self.account_peers.get(&announce_account.account_id) {
+            Some(current) => current.epoch_id.clone(),
+            None => return false,
+        };
+        match (self.epoch_index(&announce_account.epoch_id), self.epoch_index(&current_epoch)) {
+            (Some(index), Some(current)) => index < current,
+            _ => false,
+        }
+    }
+
+    /// Remember the order of `epoch_id` and `next_epoch_id`, and remove announcements from
+    /// epochs older than `epoch_id`.
+    fn advance_epoch(&mut self, epoch_id: EpochId, next_epoch_id: EpochId) {
+        for epoch_id in [&epoch_id, &next_epoch_id] {
+            if self.epoch_index(epoch_id).is_none() {
+                self.known_epochs.push_back(epoch_id.clone());
+            }
+        }
+        while self.known_epochs.len() > MAX_KNOWN_EPOCHS {
+            self.known_epochs.pop_front();
+        }
+        if self.current_epoch.as_ref() == Some(&epoch_id) {
+            return;
+        }
+        self.current_epoch = Some(epoch_id);
+
+        let expired: Vec<_> = self
+            .account_peers
+            .values()
+            .into_iter()
+            .filter(|announce_account| self.is_expired_epoch(&announce_account.epoch_id))
+            .map(|announce_account| announce_account.account_id.clone())
+            .collect();
+        if expired.is_empty() {
+            return;
+        }
+        let mut update = self.store.store_update();
+        for account_id in expired.iter() {
+            self.account_peers.remove(account_id);
+            self.account_announcements.remove(account_id);
+            self.low_priority_accounts.remove(account_id);
+            update.delete(ColAccountAnnouncements, account_id.as_ref().as_bytes());
+        }
+        if let Err(e) = update.commit() {
+            warn!(target: "network", "Error removing expired announce accounts from store: {:?}", e);
+        }
+        near_metrics::inc_counter_by(&metrics::ACCOUNT_ANNOUNCEMENTS_EXPIRED, expired.len() as u64);
+        debug!(target: "network", "Expired announcements of {} accounts", expired.len());
+    }
+
+    fn is_low_priority_account(&self, account_id: &AccountId) -> bool {
+        self.epoch_validators.as_ref().map_or(false, |validators| !validators.contains(account_id))
+    }
+
+    /// Set validators of the current and next epoch. Announcements of every other account
+    /// become low priority.
+    pub fn is_epoch_validator(&self, account_id: &AccountId) -> bool {
+        self.epoch_validators.as_ref().map_or(false, |validators| validators.contains(account_id))
+    }
+
+    /// Validators of the current epoch with the peer of their latest announcement, if any.
+    pub fn epoch_validator_peers(&mut self) -> Vec<(AccountId, PeerId)> {
+        let validators = match &self.epoch_validators {
+            Some(validators) => validators.iter().cloned().collect::<Vec<_>>(),
+            None => return vec![],
+        };
+        validators
+            .into_iter()
+            .filter_map(|account_id| {
+                let peer_id = self.get_announce(&account_id)?.peer_id;
+                Some((account_id, peer_id))
+            })
+            .collect()
+    }
+
+    /// Set validators of the current epoch `epoch_id` and the next one. Announcements from
+    /// epochs before `epoch_id` expire.
+    pub fn set_epoch_validators(
+        &mut self,
+        epoch_id: EpochId,
+        next_epoch_id: EpochId,
+        validators: HashSet<AccountId>,
+    ) {
+        self.advance_epoch(epoch_id, next_epoch_id);
+        self.epoch_validators = Some(validators);
+        self.low_priority_accounts = self
+            .account_peers
+            .keys()
+            .into_iter()
+            .filter(|account_id| self.is_low_priority_account(account_id))
+            .collect();
+    }
+
+    /// Set validators of each shard in the current epoch.
+    pub fn set_shard_validators(&mut self, shard_validators: HashMap<ShardId, Vec<AccountId>>) {
+        self.shard_validators = shard_validators;
+    }
+
+    /// Validator of `shard_id` in the current epoch to send messages for the shard to: the
+    /// closest reachable one, preferring the ones whose peer isn't deprioritized. Ties are
+    /// broken randomly, so load is spread between validators.
+    pub fn shard_validator(&mut self, shard_id: ShardId) -> Result<AccountId, FindRouteError> {
+        let mut validators = self.shard_validators.get(&shard_id).cloned().unwrap_or_default();
+        validators.shuffle(&mut thread_rng());
+        let owners: Vec<_> = validators
+            .into_iter()
+            .filter_map(|account_id| {
+                let peer_id = self.get_announce(&account_id)?.peer_id;
+                let reachable = self.peer_forwarding.contains_key(&peer_id)
+                    && !self.banned_peers.contains(&peer_id);
+                if reachable {
+                    Some((account_id, peer_id))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        let peers: Vec<_> = owners.iter().map(|(_, peer_id)| peer_id.clone()).collect();
+        let distances = self.raw_graph.distances(&peers);
+        owners
+            .into_iter()
+            .zip(distances)
+            .filter_map(|((account_id, peer_id), distance)| {
+                Some((self.deprioritized_peers.contains(&peer_id), distance?, account_id))
+            })
+            .min_by_key(|(deprioritized, distance, _)| (*deprioritized, *distance))
+            .map(|(_, _, account_id)| account_id)
+            .ok_or(FindRouteError::AccountNotFound)
+    }
+
+    /// Whether we already know the announcement of this account from the same peer and epoch.
+    pub fn contains_account(&mut self, announce_account: &AnnounceAccount) -> bool {
+        let is_same = |announce: &AnnounceAccount| {
+            announce.peer_id == announce_account.peer_id
+                && announce.epoch_id == announce_account.epoch_id
+        };
+        match self.get_announce(&announce_account.account_id) {
+            Some(current_announce_account) => self
+                .account_announcements
+                .get(&announce_account.account_id)
+                .map_or(is_same(&current_announce_account), |announcements| {
+                    announcements.iter().any(is_same)
+                }),
+            None => false,
+        }
+    }
+
+    /// Get the nonce of the component where the peer was stored
+    fn component_nonce_from_peer(&mut self, peer_id: PeerId) -> Result<u64, ()> {
+        match self.store.get_ser::<u64>(ColPeerComponent, Vec::from(peer_id).as_ref()) {
+            Ok(Some(nonce)) => Ok(nonce),
+            _ => Err(()),
+        }
+    }
+
+    /// Get all edges in the component with `nonce`
+    /// Remove those edges from the store.
+    fn get_component_edges(
+        &mut self,
+        nonce: u64,
+        update: &mut StoreUpdate,
+    ) -> Result<Vec<Edge>, ()> {
+        let enc_nonce = index_to_bytes(nonce);
+
+        let res = match self.store.get_ser::<Vec<Edge>>(ColComponentEdges, enc_nonce.as_ref()) {
+            Ok(Some(edges)) => Ok(edges),
+            _ => Err(()),
+        };
+
+        update.delete(ColComponentEdges, enc_nonce.as_ref());
+
+        res
+    }
+
+    /// If peer_id is not on memory check if it is on disk in bring it back on memory.
+    fn touch(&mut self, peer_id: &PeerId) {
+        if peer_id == self.peer_id() || self.peer_last_time_reachable.contains_key(peer_id) {
+            return;
+        }
+
+        let me = self.peer_id().clone();
+
+        if let Ok(nonce) = self.component_nonce_from_peer(peer_id.clone()) {
+            let mut update = self.store.store_update();
+
+            if let Ok(edges) = self.get_component_edges(nonce, &mut update) {
+                for edge in edges {
+                    for &peer_id in vec![&edge.peer0, &edge.peer1].iter() {
+                        if peer_id == &me || self.peer_last_time_reachable.contains_key(peer_id) {
+                            continue;
+                        }
+
+                        if let Ok(cur_nonce) = self.component_nonce_from_peer(peer_id.clone()) {
+                            if cur_nonce == nonce {
+                                self.peer_last_time_reachable.insert(
+                                    peer_id.clone(),
+                                    self.clock.now() - self.config.save_peers_max_time,
+                                );
+                                update
+                                    .delete(ColPeerComponent, Vec::from(peer_id.clone()).as_ref());
+                            }
+                        }
+                    }
+                    self.add_edge(edge);
+                }
+            }
+
+            if let Err(e) = update.commit() {
+                warn!(target: "network", "Error removing network component from store. {:?}", e);
+            }
+        } else {
+            self.peer_last_time_reachable.insert(peer_id.clone(), self.clock.now());
+        }
+    }
+
+    pub fn remove_edges(&mut self, edges: &Vec<Edge>) {
+        for edge in edges.iter() {
+            let key = (edge.peer0.clone(), edge.peer1.clone());
+            if self.edges_info.remove(&key).is_some() {
+                self.raw_graph.remove_edge(&edge.peer0, &edge.peer1);
+            }
+        }
+    }
+
+    fn add_edge(&mut self, edge: Edge) -> bool {
+        let key = edge.get_pair();
+
+        if self.find_nonce(&key) >= edge.nonce {
+            // We already have a newer information about this edge. Discard this information.
+            false
+        } else if self.banned_peers.contains(&key.0) || self.banned_peers.contains(&key.1) {
+            false
+        } else {
+            match edge.edge_type() {
+                EdgeType::Added => {
+                    self.raw_graph.add_edge(key.0.clone(), key.1.clone());
+                }
+                EdgeType::Removed => {
+                    self.raw_graph.remove_edge(&key.0, &key.1);
+                }
+            }
+            self.edges_info.insert(key, edge);
+            self.edges_since_recalculation += 1;
+            true
+        }
+    }
+
+    /// Add several edges to the current view of the network.
+    /// These edges are assumed to be valid at this point.
+    /// Return true if some of the edges contains new information to the network.
+    pub fn process_edges(&mut self, edges: Vec<Edge>) -> ProcessEdgeResult {
+        let mut new_edge = false;
+        let total = edges.len();
+        let mut result = Vec::with_capacity(edges.len() as usize);
+
+        for edge in edges {
+            let key = edge.get_pair();
+
+            self.touch(&key.0);
+            self.touch(&key.1);
+
+            let added = self.add_edge(edge.clone());
+            if diagnostics::routing_diagnostics_enabled(&self.clock) {
+                info!(target: "network::diagnostics", "Edge {} - {} nonce {} {:?}: {}",
+                      key.0, key.1, edge.nonce, edge.edge_type(),
+                      if added { "added" } else { "ignored, not newer than the known edge" });
+            }
+            if added {
+                new_edge = true;
+                result.push(edge);
+            }
+        }
+
+        // Update metrics after edge update
+        near_metrics::inc_counter_by(&metrics::EDGE_UPDATES, total as u64);
+        near_metrics::set_gauge(&metrics::EDGE_ACTIVE, self.raw_graph.total_active_edges as i64);
+        self.lifetime_edge_stats.processed += total as u64;
+        self.lifetime_edge_stats.duplicates += (total - result.len()) as u64;
+        self.lifetime_edge_stats.export();
+
+        ProcessEdgeResult { new_edge, edges: result }
+    }
+
+    /// Count a list of edges received from a peer which failed signature verification.
+    pub fn record_edge_verification_failure(&mut self) {
+        self.lifetime_edge_stats.verification_failures += 1;
+        self.lifetime_edge_stats.export();
+    }
+
+    /// Persist the lifetime edge counters, so they survive restarts.
+    pub fn save_lifetime_edge_stats(&self) {
+        if let Err(err) = self.lifetime_edge_stats.save(&self.store) {
+            warn!(target: "network", "Failed to save lifetime edge stats: {}", err);
+        }
+    }
+
+    /// Save the route back cache if `config.persist_route_back` is set, so it's restored on the
+    /// next start.
+    pub fn save_route_back(&self) {
+        if !self.config.persist_route_back {
+            return;
+        }
+        if let Err(err) = self.route_back.save(&self.store, to_timestamp(self.clock.now_utc())) {
+            warn!(target: "network", "Failed to save route back entries: {}", err);
         }
-
-        // Update metrics after edge update
-        near_metrics::inc_counter_by(&metrics::EDGE_UPDATES, total as u64);
-        near_metrics::set_gauge(&metrics::EDGE_ACTIVE, self.raw_graph.total_active_edges as i64);
-
-        ProcessEdgeResult { new_edge, edges: result }
     }
 
     pub fn find_nonce(&self, edge: &(PeerId, PeerId)) -> u64 {
@@ -662,6 +1689,21 @@ impl RoutingTable {
         self.edges_info.len() as u64
     }
 
+    /// Approximate bytes used by the largest data structures, see `routing_profile`.
+    pub fn memory_bytes(&self) -> BTreeMap<&'static str, usize> {
+        let mut memory_bytes = BTreeMap::new();
+        memory_bytes.insert("routing_table.edges_info", hash_map_bytes(&self.edges_info));
+        memory_bytes.insert("routing_table.graph", self.raw_graph.memory_bytes());
+        memory_bytes
+            .insert("routing_table.reachability", hash_map_bytes(&self.peer_last_time_reachable));
+        memory_bytes.insert(
+            "routing_table.peer_forwarding",
+            hash_map_bytes(&self.peer_forwarding)
+                + self.peer_forwarding.values().map(vec_bytes).sum::<usize>(),
+        );
+        memory_bytes
+    }
+
     pub fn add_route_back(&mut self, hash: CryptoHash, peer_id: PeerId) {
         self.route_back.insert(hash, peer_id);
     }
@@ -676,30 +1718,77 @@ impl RoutingTable {
     }
 
     pub fn add_ping(&mut self, ping: Ping) {
-        let cnt = self.ping_info.cache_get(&(ping.nonce as usize)).map(|v| v.1).unwrap_or(0);
+        let cnt = self.ping_info.cache_get(&ping.nonce).map(|v| v.1).unwrap_or(0);
 
-        self.ping_info.cache_set(ping.nonce as usize, (ping, cnt + 1));
+        self.ping_info.cache_set(ping.nonce, (ping, cnt + 1));
     }
 
-    /// Return time of the round trip of ping + pong
+    /// Return time of the round trip of ping + pong. Pongs for nonces which weren't sent yet
+    /// don't count.
     pub fn add_pong(&mut self, pong: Pong) -> Option<f64> {
         let mut res = None;
 
-        if let Some(nonces) = self.waiting_pong.cache_get_mut(&pong.source) {
+        let sent = self
+            .last_ping_nonce
+            .cache_get(&pong.source)
+            .map_or(false, |next| ping_nonce_precedes(pong.nonce, *next));
+        if let (true, Some(nonces)) = (sent, self.waiting_pong.cache_get_mut(&pong.source)) {
             res = nonces
-                .cache_remove(&(pong.nonce as usize))
-                .and_then(|sent| Some(Instant::now().duration_since(sent).as_secs_f64() * 1000f64));
+                .cache_remove(&pong.nonce)
+                .map(|sent| self.clock.elapsed(sent).as_secs_f64() * 1000f64);
+        }
+        if let Some(latency) = res {
+            self.update_latency(&pong.source, latency);
+            self.ping_rtts.cache_set((pong.source.clone(), pong.nonce), latency);
+            if let Some(key) =
+                self.announcement_verifications.cache_remove(&(pong.source.clone(), pong.nonce))
+            {
+                if let Some(origin) = self.announcement_origins.cache_get_mut(&key) {
+                    origin.verified = true;
+                    near_metrics::inc_counter_vec(
+                        &metrics::ACCOUNT_ANNOUNCEMENT_VERIFICATIONS,
+                        &["verified"],
+                    );
+                }
+            }
         }
 
-        let cnt = self.pong_info.cache_get(&(pong.nonce as usize)).map(|v| v.1).unwrap_or(0);
+        let cnt = self.pong_info.cache_get(&pong.nonce).map(|v| v.1).unwrap_or(0);
 
-        self.pong_info.cache_set(pong.nonce as usize, (pong, (cnt + 1)));
+        self.pong_info.cache_set(pong.nonce, (pong, (cnt + 1)));
 
         res
     }
 
+    /// Add a round trip time measurement in milliseconds to `peer_id`.
+    pub fn update_latency(&mut self, peer_id: &PeerId, latency: f64) {
+        let latency = match self.peer_latency.cache_get(peer_id) {
+            Some(average) => {
+                average * (1f64 - ROUTE_LATENCY_EWMA_WEIGHT) + latency * ROUTE_LATENCY_EWMA_WEIGHT
+            }
+            None => latency,
+        };
+        self.peer_latency.cache_set(peer_id.clone(), latency);
+    }
+
+    /// Average round trip time in milliseconds to `peer_id`, if it was measured.
+    pub fn peer_latency(&mut self, peer_id: &PeerId) -> Option<f64> {
+        self.peer_latency.cache_get(peer_id).cloned()
+    }
+
+    /// Round trip time in milliseconds of the ping to `peer_id` with `nonce`, if it was answered.
+    pub fn ping_rtt(&mut self, peer_id: &PeerId, nonce: u64) -> Option<f64> {
+        self.ping_rtts.cache_get(&(peer_id.clone(), nonce)).cloned()
+    }
+
     // for unit tests
-    pub fn sending_ping(&mut self, nonce: usize, target: PeerId) {
+    pub fn sending_ping(&mut self, nonce: u64, target: PeerId) {
+        // Nonces picked by the caller rather than `get_ping` move the next nonce forward.
+        match self.last_ping_nonce.cache_get_mut(&target) {
+            Some(next) if !ping_nonce_precedes(nonce, *next) => *next = next_ping_nonce(nonce),
+            Some(_) => {}
+            None => self.last_ping_nonce.cache_set(target.clone(), next_ping_nonce(nonce)),
+        }
         let entry = if let Some(entry) = self.waiting_pong.cache_get_mut(&target) {
             entry
         } else {
@@ -707,44 +1796,48 @@ impl RoutingTable {
             self.waiting_pong.cache_get_mut(&target).unwrap()
         };
 
-        entry.cache_set(nonce, Instant::now());
+        entry.cache_set(nonce, self.clock.now());
     }
 
-    pub fn get_ping(&mut self, peer_id: PeerId) -> usize {
+    /// Nonce of the next ping to `peer_id`.
+    pub fn get_ping(&mut self, peer_id: PeerId) -> u64 {
         if let Some(entry) = self.last_ping_nonce.cache_get_mut(&peer_id) {
-            *entry += 1;
-            *entry - 1
+            let nonce = *entry;
+            *entry = next_ping_nonce(nonce);
+            nonce
         } else {
-            self.last_ping_nonce.cache_set(peer_id, 1);
+            self.last_ping_nonce.cache_set(peer_id, next_ping_nonce(0));
             0
         }
     }
 
     // for unit tests
-    pub fn fetch_ping_pong(
-        &self,
-    ) -> (HashMap<usize, (Ping, usize)>, HashMap<usize, (Pong, usize)>) {
+    pub fn fetch_ping_pong(&self) -> (HashMap<u64, (Ping, usize)>, HashMap<u64, (Pong, usize)>) {
         (cache_to_hashmap(&self.ping_info), cache_to_hashmap(&self.pong_info))
     }
 
-    pub fn info(&mut self) -> RoutingTableInfo {
+    pub fn info(&self) -> RoutingTableInfo {
         let account_peers = self
             .get_announce_accounts()
             .into_iter()
             .map(|announce_account| (announce_account.account_id, announce_account.peer_id))
             .collect();
-        RoutingTableInfo { account_peers, peer_forwarding: self.peer_forwarding.clone() }
+        RoutingTableInfo {
+            account_peers,
+            peer_forwarding: self.peer_forwarding.clone(),
+            peer_latency: cache_to_hashmap(&self.peer_latency),
+        }
     }
 
-    fn try_save_edges(&mut self, force_pruning: bool, timeout: Duration) -> Vec<Edge> {
-        let now = Instant::now();
+    fn try_save_edges(&mut self, prune: PruneConfig) -> Vec<Edge> {
+        let now = self.clock.now();
         let mut oldest_time = now;
         let to_save = self
             .peer_last_time_reachable
             .iter()
             .filter_map(|(peer_id, last_time)| {
                 oldest_time = std::cmp::min(oldest_time, *last_time);
-                if now.duration_since(*last_time) >= timeout {
+                if prune.should_save(Time::duration_since(now, *last_time)) {
                     Some(peer_id.clone())
                 } else {
                     None
@@ -753,14 +1846,22 @@ impl RoutingTable {
             .collect::<HashSet<_>>();
 
         // Save nodes on disk and remove from memory only if elapsed time from oldest peer
-        // is greater than `SAVE_PEERS_MAX_TIME`
-        if !force_pruning && now.duration_since(oldest_time) < SAVE_PEERS_MAX_TIME {
+        // is greater than `prune_after`
+        if !prune.should_prune(Time::duration_since(now, oldest_time)) {
             return Vec::new();
         }
         debug!(target: "network", "try_save_edges: We are going to remove {} peers", to_save.len());
+        self.save_component(&to_save)
+    }
 
+    /// Save `to_save` and all their edges to disk as a new component, and remove them from
+    /// memory. Returns the removed edges.
+    fn save_component(&mut self, to_save: &HashSet<PeerId>) -> Vec<Edge> {
         let component_nonce = self.component_nonce;
         self.component_nonce += 1;
+        if to_save.len() == 1 {
+            near_metrics::inc_counter(&metrics::ROUTING_SINGLETON_COMPONENTS_SAVED);
+        }
 
         let mut update = self.store.store_update();
         let _ = update.set_ser(ColLastComponentNonce, &[], &component_nonce);
@@ -794,16 +1895,179 @@ impl RoutingTable {
         if let Err(e) = update.commit() {
             warn!(target: "network", "Error storing network component to store. {:?}", e);
         }
+        self.remove_expired_component();
         edges_to_remove
     }
 
-    /// Recalculate routing table.
-    pub fn update(
-        &mut self,
-        can_save_edges: bool,
-        force_pruning: bool,
-        timeout: Duration,
-    ) -> Vec<Edge> {
+    /// Remove from disk the component saved `max_saved_components` components before the last
+    /// one, so components of peers which never come back don't accumulate forever. This runs in
+    /// its own store update, as deleting the mappings of its peers in the update saving the last
+    /// component would also delete the mappings of the peers saved again since.
+    fn remove_expired_component(&mut self) {
+        let expired = match self.component_nonce.checked_sub(self.config.max_saved_components + 1) {
+            Some(expired) => expired,
+            None => return,
+        };
+        let key = index_to_bytes(expired);
+        let edges = match self.store.get_ser::<Vec<Edge>>(ColComponentEdges, &key) {
+            Ok(Some(edges)) => edges,
+            _ => return,
+        };
+        let mut update = self.store.store_update();
+        update.delete(ColComponentEdges, &key);
+        for edge in edges {
+            for peer_id in [edge.peer0, edge.peer1] {
+                let key = Vec::from(peer_id);
+                if self.store.get_ser::<u64>(ColPeerComponent, &key).unwrap_or(None)
+                    == Some(expired)
+                {
+                    update.delete(ColPeerComponent, &key);
+                }
+            }
+        }
+        match update.commit() {
+            Ok(()) => near_metrics::inc_counter(&metrics::ROUTING_SAVED_COMPONENTS_REMOVED),
+            Err(e) => {
+                warn!(target: "network", "Error removing network component from store. {:?}", e)
+            }
+        }
+    }
+
+    /// Remove from `store` the components saved before the last `max_saved_components` ones,
+    /// and the mappings of peers to components which aren't on disk anymore. Stores written
+    /// before components were removed can have many of both. Returns the number of components
+    /// and mappings removed.
+    pub fn gc_saved_components(
+        store: &Store,
+        max_saved_components: u64,
+    ) -> std::io::Result<(u64, u64)> {
+        let last_nonce = match store.get_ser::<u64>(ColLastComponentNonce, &[])? {
+            Some(last_nonce) => last_nonce,
+            None => return Ok((0, 0)),
+        };
+        let first_kept = (last_nonce + 1).saturating_sub(max_saved_components);
+        let mut update = store.store_update();
+
+        let mut kept = HashSet::new();
+        let mut removed_components = 0;
+        for (key, _) in store.iter(ColComponentEdges) {
+            match <[u8; 8]>::try_from(key.as_ref()).map(u64::from_le_bytes) {
+                Ok(nonce) if nonce >= first_kept => {
+                    kept.insert(nonce);
+                }
+                _ => {
+                    update.delete(ColComponentEdges, &key);
+                    removed_components += 1;
+                }
+            }
+        }
+        let mut removed_peers = 0;
+        for (key, value) in store.iter(ColPeerComponent) {
+            if !u64::try_from_slice(&value).map_or(false, |nonce| kept.contains(&nonce)) {
+                update.delete(ColPeerComponent, &key);
+                removed_peers += 1;
+            }
+        }
+        update.commit()?;
+        Ok((removed_components, removed_peers))
+    }
+
+    /// Save to disk the peers unreachable for the longest time, so that at most
+    /// `max_unreachable_graph_nodes` unreachable peers stay in memory. Peers which were never
+    /// reachable count from the time they were first seen. Returns the removed edges.
+    fn evict_unreachable_nodes(&mut self) -> Vec<Edge> {
+        let mut unreachable = self.raw_graph.unreachable_nodes();
+        if unreachable.len() <= self.config.max_unreachable_graph_nodes {
+            return Vec::new();
+        }
+        let excess = unreachable.len() - self.config.max_unreachable_graph_nodes;
+        unreachable.sort_by_key(|peer_id| self.peer_last_time_reachable.get(peer_id).cloned());
+        let to_evict = unreachable.into_iter().take(excess).collect::<HashSet<_>>();
+        debug!(target: "network", "Evicting {} of {} unreachable peers from the routing graph",
+               excess, excess + self.config.max_unreachable_graph_nodes);
+
+        let edges_to_remove = self.save_component(&to_evict);
+        self.raw_graph.compact();
+        near_metrics::inc_counter_by(&metrics::ROUTING_GRAPH_NODES_EVICTED, excess as u64);
+        edges_to_remove
+    }
+
+    /// Save to disk the peers unreachable for the longest time until at most
+    /// `max_edges_in_memory` edges stay in memory, or no unreachable peer is left. Edges between
+    /// reachable peers are never evicted. Returns the removed edges.
+    fn evict_over_edge_budget(&mut self) -> Vec<Edge> {
+        let max_edges = self.config.max_edges_in_memory;
+        if self.edges_info.len() <= max_edges {
+            return Vec::new();
+        }
+        let mut unreachable = self.raw_graph.unreachable_nodes();
+        unreachable.sort_by_key(|peer_id| self.peer_last_time_reachable.get(peer_id).cloned());
+
+        let mut neighbors: HashMap<&PeerId, Vec<&PeerId>> = HashMap::new();
+        for (peer0, peer1) in self.edges_info.keys() {
+            neighbors.entry(peer0).or_default().push(peer1);
+            neighbors.entry(peer1).or_default().push(peer0);
+        }
+        let mut to_evict = HashSet::new();
+        let mut remaining = self.edges_info.len();
+        for peer_id in unreachable {
+            if remaining <= max_edges {
+                break;
+            }
+            // Edges to peers already evicted were counted with them.
+            remaining -= neighbors
+                .get(&peer_id)
+                .map_or(0, |peers| peers.iter().filter(|peer| !to_evict.contains(**peer)).count());
+            to_evict.insert(peer_id);
+        }
+        if remaining > max_edges {
+            warn!(target: "network", "{} edges in memory after evicting all unreachable peers, above the budget of {}",
+                  remaining, max_edges);
+        }
+        if to_evict.is_empty() {
+            return Vec::new();
+        }
+        debug!(target: "network", "Evicting {} unreachable peers to keep {} edges in memory",
+               to_evict.len(), remaining);
+
+        let evicted = to_evict.len();
+        let edges_to_remove = self.save_component(&to_evict);
+        self.raw_graph.compact();
+        near_metrics::inc_counter_by(&metrics::ROUTING_GRAPH_NODES_EVICTED, evicted as u64);
+        edges_to_remove
+    }
+
+    /// Remove the `ColPeerComponent` entry of every peer that is also tracked in memory.
+    /// A peer can end up in both places if the node crashed while saving or loading a
+    /// component; the in-memory view is the most recent one, so the disk pointer is dropped.
+    /// Returns the number of conflicts repaired.
+    pub fn repair_peer_components(&self) -> usize {
+        let mut update = self.store.store_update();
+        let mut conflicts = 0;
+        for peer_id in self.peer_last_time_reachable.keys() {
+            let key = Vec::from(peer_id.clone());
+            if let Ok(Some(_)) = self.store.get_ser::<u64>(ColPeerComponent, key.as_ref()) {
+                update.delete(ColPeerComponent, key.as_ref());
+                conflicts += 1;
+            }
+        }
+
+        if conflicts > 0 {
+            warn!(target: "network", "Found {} peers both in memory and on disk", conflicts);
+            if let Err(e) = update.commit() {
+                warn!(target: "network", "Error removing peer components from store. {:?}", e);
+                return 0;
+            }
+            near_metrics::inc_counter_by(
+                &metrics::PEER_COMPONENT_CONFLICTS_REPAIRED,
+                conflicts as u64,
+            );
+        }
+        conflicts
+    }
+
+    /// Recalculate routing table, and prune unreachable peers as configured by `prune` if set.
+    pub fn update(&mut self, prune: Option<PruneConfig>) -> Vec<Edge> {
         #[cfg(feature = "delay_detector")]
         let _d = DelayDetector::new("routing table update".into());
         let _routing_table_recalculation =
@@ -811,48 +2075,306 @@ impl RoutingTable {
 
         trace!(target: "network", "Update routing table.");
 
-        self.peer_forwarding = self.raw_graph.calculate_distance();
+        let started = self.clock.now();
+        self.peer_forwarding = self.raw_graph.recalculate_distance();
+        self.finish_update(started, prune)
+    }
 
-        let now = Instant::now();
+    /// Snapshot of the graph to recompute routes from on another thread, if routes of the
+    /// graph can't be updated incrementally and it has at least `min_nodes` nodes. Otherwise
+    /// `update` is about as fast as copying the graph.
+    pub fn graph_to_recompute(&self, min_nodes: usize) -> Option<Graph> {
+        if self.raw_graph.node_count() < min_nodes || !self.raw_graph.needs_full_recompute() {
+            return None;
+        }
+        Some(self.raw_graph.snapshot())
+    }
+
+    /// Recalculate routing table with `routes` and their shortest paths `spf`, computed from
+    /// the snapshot of the graph at `version` taken at `started`. Returns `None` without
+    /// updating anything if the graph changed since, as the routes may use removed edges and
+    /// pruning would go by reachability in the old graph.
+    pub(crate) fn update_with_routes(
+        &mut self,
+        started: Instant,
+        version: u64,
+        spf: ShortestPaths,
+        routes: HashMap<PeerId, Vec<PeerId>>,
+        prune: Option<PruneConfig>,
+    ) -> Option<Vec<Edge>> {
+        if version != self.raw_graph.version() {
+            trace!(target: "network", "Routing graph changed while its routes were computed");
+            return None;
+        }
+        self.raw_graph.install_shortest_paths(version, spf);
+        self.peer_forwarding = routes;
+        Some(self.finish_update(started, prune))
+    }
+
+    fn finish_update(&mut self, started: Instant, prune: Option<PruneConfig>) -> Vec<Edge> {
+        let now = self.clock.now();
+        self.last_recalculation = Some((self.clock.now_utc(), Time::duration_since(now, started)));
+        self.edges_since_recalculation = 0;
         for peer in self.peer_forwarding.keys() {
             self.peer_last_time_reachable.insert(peer.clone(), now);
         }
 
         let mut edges_to_remove = Vec::new();
-        if can_save_edges {
-            edges_to_remove = self.try_save_edges(force_pruning, timeout);
+        if let Some(prune) = prune {
+            edges_to_remove = self.try_save_edges(prune);
+            edges_to_remove.extend(self.evict_unreachable_nodes());
+            edges_to_remove.extend(self.evict_over_edge_budget());
         }
 
         near_metrics::inc_counter_by(&metrics::ROUTING_TABLE_RECALCULATIONS, 1);
         near_metrics::set_gauge(&metrics::PEER_REACHABLE, self.peer_forwarding.len() as i64);
+        near_metrics::set_gauge(&metrics::ROUTING_GRAPH_NODES, self.raw_graph.node_count() as i64);
+        self.export_topology_metrics();
         edges_to_remove
     }
 
+    /// Weight of active peer `peer_id` in `sample_peers`: one plus the number of peers reached
+    /// through it, divided by one plus its recent failures to deliver messages as a next hop.
+    /// Deprioritized peers weigh ten times less.
+    fn gossip_weight(&self, peer_id: &PeerId, reached: &HashMap<&PeerId, usize>) -> f64 {
+        let failures = self
+            .route_failures
+            .value_order()
+            .flatten()
+            .filter(|(_, next_hop, _)| next_hop.as_ref() == Some(peer_id))
+            .count();
+        let reach = reached.get(peer_id).cloned().unwrap_or(0);
+        let weight = (1 + reach) as f64 / (1 + failures) as f64;
+        if self.deprioritized_peers.contains(peer_id) {
+            weight / 10.0
+        } else {
+            weight
+        }
+    }
+
+    /// Sample `k` of the active peers `candidates` to gossip to, instead of sending to all of
+    /// them. Peers reaching more of the network and delivering messages reliably are more
+    /// likely to be sampled. Banned peers are never sampled.
+    pub fn sample_peers<R: Rng>(
+        &self,
+        candidates: &[PeerId],
+        k: usize,
+        rng: &mut R,
+    ) -> Vec<PeerId> {
+        let candidates = candidates.iter().filter(|peer_id| !self.banned_peers.contains(peer_id));
+        let mut reached: HashMap<&PeerId, usize> = HashMap::new();
+        for next_hop in self.peer_forwarding.values().flatten() {
+            *reached.entry(next_hop).or_default() += 1;
+        }
+        // Weighted sampling without replacement: keep the `k` peers with the highest
+        // `u ^ (1 / weight)`, `u` uniform in (0, 1).
+        let mut keyed: Vec<_> = candidates
+            .map(|peer_id| {
+                let u: f64 = rng.gen_range(f64::EPSILON, 1.0);
+                (u.powf(1.0 / self.gossip_weight(peer_id, &reached)), peer_id)
+            })
+            .collect();
+        keyed.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+        keyed.into_iter().take(k).map(|(_, peer_id)| peer_id.clone()).collect()
+    }
+
+    /// State of the recalculations, with `queue_depth` updates waiting to be applied.
+    pub fn recalculation_view(&self, queue_depth: u64) -> RoutingRecalculationView {
+        RoutingRecalculationView {
+            last_recalculation_time: self.last_recalculation.map(|(time, _)| time),
+            last_recalculation_duration_ms: self
+                .last_recalculation
+                .map(|(_, duration)| duration.as_millis() as u64),
+            edges_since_last_recalculation: self.edges_since_recalculation,
+            queue_depth,
+        }
+    }
+
+    /// Hops to reachable peers and number of next hops to each of them, to follow the health of
+    /// the network over time.
+    fn export_topology_metrics(&mut self) {
+        let (diameter, average_hops) = self.raw_graph.hop_stats();
+        near_metrics::set_gauge(&metrics::ROUTING_GRAPH_DIAMETER, diameter as i64);
+        near_metrics::set_float_gauge(&metrics::ROUTING_GRAPH_AVERAGE_HOPS, average_hops);
+        for next_hops in self.peer_forwarding.values() {
+            near_metrics::observe(&metrics::ROUTING_NEXT_HOPS, next_hops.len() as f64);
+        }
+
+        let (fraction, critical_next_hops) = self.single_next_hop_stats();
+        near_metrics::set_float_gauge(&metrics::ROUTING_SINGLE_NEXT_HOP_FRACTION, fraction);
+        let warning = fraction > SINGLE_NEXT_HOP_WARNING_FRACTION;
+        if warning && !self.single_next_hop_warning {
+            let critical_next_hops: Vec<_> = critical_next_hops
+                .iter()
+                .take(MAX_CRITICAL_NEXT_HOPS_LOGGED)
+                .map(|(peer_id, targets)| format!("{} ({} peers)", peer_id, targets))
+                .collect();
+            warn!(target: "network", "{:.0}% of reachable peers are only reachable through a single next hop. Critical next hops: {}",
+                  fraction * 100.0, critical_next_hops.join(", "));
+        }
+        self.single_next_hop_warning = warning;
+    }
+
+    /// Fraction of reachable peers, other than the ones we are connected to, which are
+    /// reachable through a single next hop, and those next hops with the number of peers only
+    /// reachable through them, most critical first.
+    pub fn single_next_hop_stats(&self) -> (f64, Vec<(PeerId, usize)>) {
+        let mut targets = 0;
+        let mut critical_next_hops: HashMap<PeerId, usize> = HashMap::new();
+        for (target, next_hops) in self.peer_forwarding.iter() {
+            match next_hops.as_slice() {
+                [next_hop] if next_hop == target => continue,
+                [next_hop] => *critical_next_hops.entry(next_hop.clone()).or_default() += 1,
+                _ => {}
+            }
+            targets += 1;
+        }
+        if targets == 0 {
+            return (0.0, vec![]);
+        }
+        let single = critical_next_hops.values().sum::<usize>();
+        let mut critical_next_hops: Vec<_> = critical_next_hops.into_iter().collect();
+        critical_next_hops.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        (single as f64 / targets as f64, critical_next_hops)
+    }
+
+    /// Capture the state of the routing table.
+    #[cfg(feature = "test_features")]
+    pub fn snapshot(&self) -> RoutingTableSnapshot {
+        let now = self.clock.now();
+        RoutingTableSnapshot {
+            edges: self.edges_info.values().cloned().collect(),
+            peer_last_time_reachable: self
+                .peer_last_time_reachable
+                .iter()
+                .map(|(peer_id, time)| {
+                    (peer_id.clone(), Time::duration_since(now, *time).as_millis() as u64)
+                })
+                .collect(),
+            component_nonce: self.component_nonce,
+        }
+    }
+
+    /// Full state of the routing table, including routes and components stored on disk.
+    pub fn dump(&self) -> RoutingTableDump {
+        let mut dump = self.dump_without_edges();
+        dump.edges = self.edges_info.values().cloned().collect();
+        dump.edges.sort_by(|a, b| (&a.peer0, &a.peer1).cmp(&(&b.peer0, &b.peer1)));
+        dump
+    }
+
+    /// `dump` without the edges, which the routing table actor adds from its own copy, see
+    /// `RoutingTableMessages::DumpSnapshot`.
+    pub fn dump_without_edges(&self) -> RoutingTableDump {
+        let now = self.clock.now();
+        let mut peer_forwarding: Vec<_> = self
+            .peer_forwarding
+            .iter()
+            .map(|(peer_id, hops)| (peer_id.clone(), hops.clone()))
+            .collect();
+        peer_forwarding.sort();
+        let mut peer_last_time_reachable: Vec<_> = self
+            .peer_last_time_reachable
+            .iter()
+            .map(|(peer_id, time)| {
+                (peer_id.clone(), Time::duration_since(now, *time).as_millis() as u64)
+            })
+            .collect();
+        peer_last_time_reachable.sort();
+        let mut peer_components: Vec<_> = self
+            .store
+            .iter(ColPeerComponent)
+            .filter_map(|(key, value)| {
+                let peer_id = PeerId::try_from(key.to_vec()).ok()?;
+                let nonce = u64::try_from_slice(value.as_ref()).ok()?;
+                Some((peer_id, nonce))
+            })
+            .collect();
+        peer_components.sort();
+        let mut peer_latency_us: Vec<_> = cache_to_hashmap(&self.peer_latency)
+            .into_iter()
+            .map(|(peer_id, latency_ms)| (peer_id, (latency_ms * 1000.0) as u64))
+            .collect();
+        peer_latency_us.sort();
+        RoutingTableDump {
+            version: ROUTING_TABLE_DUMP_VERSION,
+            peer_id: self.peer_id().clone(),
+            edges: vec![],
+            peer_forwarding,
+            peer_last_time_reachable,
+            component_nonce: self.component_nonce,
+            peer_components,
+            peer_latency_us,
+        }
+    }
+
+    /// Replace the state of the routing table with `snapshot` and recompute routes.
+    #[cfg(feature = "test_features")]
+    pub fn restore(&mut self, snapshot: RoutingTableSnapshot) {
+        let now = self.clock.now();
+        self.raw_graph =
+            Graph::new(self.peer_id().clone()).with_incremental(GRAPH_FULL_RECOMPUTE_PERIOD);
+        self.edges_info.clear();
+        for edge in snapshot.edges {
+            if edge.edge_type() == EdgeType::Added {
+                self.raw_graph.add_edge(edge.peer0.clone(), edge.peer1.clone());
+            }
+            self.edges_info.insert((edge.peer0.clone(), edge.peer1.clone()), edge);
+        }
+        self.peer_last_time_reachable = snapshot
+            .peer_last_time_reachable
+            .into_iter()
+            .map(|(peer_id, millis)| {
+                (peer_id, now.checked_sub(Duration::from_millis(millis)).unwrap_or(now))
+            })
+            .collect();
+        self.component_nonce = snapshot.component_nonce;
+        self.peer_forwarding = self.raw_graph.recalculate_distance();
+
+        near_metrics::set_gauge(&metrics::EDGE_ACTIVE, self.raw_graph.total_active_edges as i64);
+        near_metrics::set_gauge(&metrics::PEER_REACHABLE, self.peer_forwarding.len() as i64);
+    }
+
     /// Public interface for `account_peers`
     ///
     /// Get keys currently on cache.
-    pub fn get_accounts_keys(&mut self) -> Vec<AccountId> {
-        self.account_peers.key_order().cloned().collect()
+    pub fn get_accounts_keys(&self) -> Vec<AccountId> {
+        self.account_peers.keys()
     }
 
     /// Get announce accounts on cache.
-    pub fn get_announce_accounts(&mut self) -> Vec<AnnounceAccount> {
-        self.account_peers.value_order().cloned().collect()
+    pub fn get_announce_accounts(&self) -> Vec<AnnounceAccount> {
+        self.account_peers.values()
+    }
+
+    /// Get announce accounts on cache of current and next epoch validators.
+    /// These are the accounts sent to new peers.
+    pub fn get_validator_announce_accounts(&self) -> Vec<AnnounceAccount> {
+        self.account_peers
+            .values()
+            .into_iter()
+            .filter(|announce_account| {
+                !self.low_priority_accounts.contains(&announce_account.account_id)
+            })
+            .collect()
     }
 
     /// Get number of accounts
-    pub fn get_announce_accounts_size(&mut self) -> usize {
-        self.account_peers.cache_size()
+    pub fn get_announce_accounts_size(&self) -> usize {
+        self.account_peers.len()
     }
 
     /// Get account announce from
     pub fn get_announce(&mut self, account_id: &AccountId) -> Option<AnnounceAccount> {
-        if let Some(announce_account) = self.account_peers.cache_get(&account_id) {
-            Some(announce_account.clone())
+        if let Some(announce_account) = self.account_peers.get(&account_id) {
+            Some(announce_account)
         } else {
             self.store
                 .get_ser(ColAccountAnnouncements, account_id.as_ref().as_bytes())
                 .and_then(|res: Option<AnnounceAccount>| {
+                    let res = res.filter(|announce_account| {
+                        !self.is_expired_epoch(&announce_account.epoch_id)
+                    });
                     if let Some(announce_account) = res {
                         self.add_account(announce_account.clone());
                         Ok(Some(announce_account))
@@ -877,6 +2399,118 @@ pub struct ProcessEdgeResult {
 pub struct RoutingTableInfo {
     pub account_peers: HashMap<AccountId, PeerId>,
     pub peer_forwarding: HashMap<PeerId, Vec<PeerId>>,
+    /// Average round trip time in milliseconds to peers, used to select next hops.
+    pub peer_latency: HashMap<PeerId, f64>,
+}
+
+/// Next hop on a shortest path to some target.
+#[derive(Serialize, Clone, Debug)]
+pub struct NextHopCandidate {
+    pub peer_id: PeerId,
+    /// Round robin counter of messages routed through this peer.
+    pub nonce: usize,
+    /// Average round trip time in milliseconds, if measured.
+    pub latency: Option<f64>,
+    /// Whether this peer has a low score, see `PeerScore`.
+    pub deprioritized: bool,
+    /// Whether this peer recently failed to deliver messages to the target, see
+    /// `RoutingTable::record_route_failure`.
+    pub avoided: bool,
+    /// Whether this peer is among the lowest latency next hops, which are the only ones used by
+    /// `RoutingStrategy::LatencyAware`.
+    pub fastest: bool,
+}
+
+impl NextHopCandidate {
+    /// Whether this peer is only used if all next hops are.
+    fn penalized(&self) -> bool {
+        self.deprioritized || self.avoided
+    }
+}
+
+/// Decisions of the shadow routing strategy, see `RoutingTableConfig::shadow_strategy`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ShadowRoutingStats {
+    /// Number of messages the shadow strategy chose a next hop for.
+    pub decisions: u64,
+    /// Number of those where it chose a different next hop than the one used.
+    pub divergences: u64,
+    /// Sum over divergences of the latency of the shadow next hop minus the latency of the one
+    /// used, if both are known. Negative if the shadow strategy would have been faster.
+    pub latency_difference_ms: f64,
+}
+
+/// Failure to deliver a message to some target.
+#[derive(Serialize, Clone, Debug)]
+pub struct RouteFailure {
+    pub millis_ago: u64,
+    /// Next hop selected, if any.
+    pub next_hop: Option<PeerId>,
+    pub reason: String,
+}
+
+/// Why messages to `target` are routed through `selected`.
+#[derive(MessageResponse, Serialize, Clone, Debug)]
+pub struct RouteExplanation {
+    pub target: PeerId,
+    /// Number of hops from us to `target` in the current graph.
+    pub distance: Option<u32>,
+    pub candidates: Vec<NextHopCandidate>,
+    pub recent_failures: Vec<RouteFailure>,
+    pub selected: Option<PeerId>,
+    /// Next hop the shadow routing strategy would select, if one is configured.
+    pub shadow_selected: Option<PeerId>,
+    pub rationale: String,
+}
+
+/// Where the announcement of an account was found.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnnouncementSource {
+    Cache,
+    Store,
+}
+
+fn announcement_key(announce_account: &AnnounceAccount) -> (AccountId, PeerId, EpochId) {
+    (
+        announce_account.account_id.clone(),
+        announce_account.peer_id.clone(),
+        announce_account.epoch_id.clone(),
+    )
+}
+
+/// Whether `announcement` was signed more than `PROXY_ANNOUNCEMENT_TTL` before `now`, or as far
+/// in the future, so a skewed clock can't pin it forever.
+fn proxy_announcement_expired(announcement: &ProxyAnnouncement, now: u64) -> bool {
+    let ttl = PROXY_ANNOUNCEMENT_TTL.as_nanos() as u64;
+    announcement.timestamp.saturating_add(ttl) < now || announcement.timestamp > now + ttl
+}
+
+/// How an announcement reached us, see `RoutingTable::record_announcement_origin`.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct AnnouncementOrigin {
+    /// Peer we first received the announcement from, `None` if it's ours.
+    pub source: Option<PeerId>,
+    /// Number of hops the announcement traveled, if the peer which sent it told.
+    pub hops: Option<u32>,
+    /// Whether the peer which announced it answered a ping sent to verify the announcement.
+    pub verified: bool,
+    #[serde(skip)]
+    pub first_seen: Instant,
+}
+
+/// Each stage of routing messages to an account: finding its announcement, then routing to the
+/// peers which announced it. `diagnosis` tells the first stage which failed, if any.
+#[derive(MessageResponse, Serialize, Clone, Debug)]
+pub struct AccountRouteExplanation {
+    pub account_id: AccountId,
+    /// `None` if no announcement of the account is known.
+    pub announcement: Option<AnnouncementSource>,
+    pub epoch_id: Option<EpochId>,
+    /// How the announcements of the peers which announced the account reached us.
+    pub origins: Vec<(PeerId, AnnouncementOrigin)>,
+    /// Routes to the peers which announced the account, in the order they are tried.
+    pub routes: Vec<RouteExplanation>,
+    pub diagnosis: String,
 }
 
 #[derive(Clone)]
@@ -887,9 +2521,151 @@ pub struct Graph {
     id2p: Vec<PeerId>,
     pub used: Vec<bool>,
     pub unused: Vec<u32>,
+    /// Neighbors of every node, sorted so edges of high degree nodes can be found with a binary
+    /// search.
     adjacency: Vec<Vec<u32>>,
 
     pub total_active_edges: u64,
+    /// Shortest paths kept up to date on every `add_edge` / `remove_edge`, if incremental mode
+    /// is enabled.
+    incremental: Option<ShortestPaths>,
+    /// Number of incremental recalculations before a full one.
+    full_recompute_period: usize,
+    /// Incremented on every change of the nodes or edges, to tell whether shortest paths
+    /// computed from a snapshot of the graph are still up to date.
+    version: u64,
+}
+
+/// Distance and routes from `source` to every node of the graph.
+#[derive(Clone)]
+pub(crate) struct ShortestPaths {
+    /// Number of u64 words of the routes of every node.
+    words: usize,
+    distance: Vec<i32>,
+    /// Bitset of the neighbors of `source` on a shortest path to each node. Every node
+    /// takes `words` consecutive u64, one bit per neighbor of `source`.
+    routes: Vec<u64>,
+    /// Set when a change couldn't be applied incrementally.
+    dirty: bool,
+    /// Incremental recalculations since the last full one.
+    incremental_updates: usize,
+}
+
+impl ShortestPaths {
+    fn resize(&mut self, nodes: usize) {
+        if self.distance.len() < nodes {
+            self.distance.resize(nodes, -1);
+            self.routes.resize(nodes * self.words, 0);
+        }
+    }
+
+    fn route(&self, id: u32) -> &[u64] {
+        &self.routes[id as usize * self.words..(id as usize + 1) * self.words]
+    }
+
+    /// Shortest paths of the whole graph, from the ones computed separately for each of its
+    /// partitions, see `Graph::partitions`.
+    pub(crate) fn merge(parts: Vec<ShortestPaths>) -> Option<ShortestPaths> {
+        let mut parts = parts.into_iter();
+        let mut merged = parts.next()?;
+        for part in parts {
+            for (distance, part_distance) in merged.distance.iter_mut().zip(part.distance) {
+                *distance = std::cmp::max(*distance, part_distance);
+            }
+            for (route, part_route) in merged.routes.iter_mut().zip(part.routes) {
+                *route |= part_route;
+            }
+        }
+        Some(merged)
+    }
+
+    fn clear(&mut self, id: u32) {
+        self.distance[id as usize] = -1;
+        let words = self.words;
+        self.routes[id as usize * words..(id as usize + 1) * words].iter_mut().for_each(|w| *w = 0);
+    }
+
+    /// Whether `id` has a neighbor one step closer to `source`.
+    fn has_parent(&self, adjacency: &[Vec<u32>], id: u32) -> bool {
+        let distance = self.distance[id as usize];
+        adjacency[id as usize].iter().any(|&p| self.distance[p as usize] == distance - 1)
+    }
+
+    /// Update shortest paths after edge `id0 - id1` was added. Neither is `source`.
+    fn on_add_edge(&mut self, adjacency: &[Vec<u32>], id0: u32, id1: u32) {
+        let (d0, d1) = (self.distance[id0 as usize], self.distance[id1 as usize]);
+        if d0 == d1 {
+            // Either both nodes are unreachable or the edge is not part of any shortest path.
+        } else if d0 != -1 && d1 != -1 && (d0 - d1).abs() == 1 {
+            self.propagate(adjacency, if d0 > d1 { id0 } else { id1 });
+        } else {
+            // A new leaf attached to a reachable node only adds a single node to the tree.
+            let (parent, leaf) = if d0 == -1 { (id1, id0) } else { (id0, id1) };
+            if self.distance[parent as usize] != -1 && adjacency[leaf as usize].len() == 1 {
+                self.distance[leaf as usize] = self.distance[parent as usize] + 1;
+                let words = self.words;
+                for word in 0..words {
+                    self.routes[leaf as usize * words + word] =
+                        self.routes[parent as usize * words + word];
+                }
+            } else {
+                self.dirty = true;
+            }
+        }
+    }
+
+    /// Update shortest paths after edge `id0 - id1` was removed. Neither is `source`.
+    fn on_remove_edge(&mut self, adjacency: &[Vec<u32>], id0: u32, id1: u32) {
+        let (d0, d1) = (self.distance[id0 as usize], self.distance[id1 as usize]);
+        if d0 == d1 {
+            // Either both nodes are unreachable or the edge is not part of any shortest path.
+        } else if d0 != -1 && d1 != -1 && (d0 - d1).abs() == 1 {
+            let child = if d0 > d1 { id0 } else { id1 };
+            if self.has_parent(adjacency, child) {
+                self.propagate(adjacency, child);
+            } else if adjacency[child as usize].is_empty() {
+                self.clear(child);
+            } else {
+                // Distance to the subtree of `child` increased.
+                self.dirty = true;
+            }
+        } else {
+            self.dirty = true;
+        }
+    }
+
+    /// Recompute routes of `start` from its parents, and of every node below it whose routes
+    /// changed as a result. Distances must be up to date.
+    fn propagate(&mut self, adjacency: &[Vec<u32>], start: u32) {
+        let words = self.words;
+        let mut queue = VecDeque::new();
+        let mut queued = HashSet::new();
+        queue.push_back(start);
+        queued.insert(start);
+
+        // Nodes are processed by increasing distance, so all parents of a node are updated
+        // before the node itself.
+        while let Some(cur) = queue.pop_front() {
+            let distance = self.distance[cur as usize];
+            let mut route = vec![0u64; words];
+            for &parent in &adjacency[cur as usize] {
+                if self.distance[parent as usize] == distance - 1 {
+                    for (word, value) in route.iter_mut().zip(self.route(parent)) {
+                        *word |= value;
+                    }
+                }
+            }
+            if route.as_slice() == self.route(cur) {
+                continue;
+            }
+            self.routes[cur as usize * words..(cur as usize + 1) * words].copy_from_slice(&route);
+            for &child in &adjacency[cur as usize] {
+                if self.distance[child as usize] == distance + 1 && queued.insert(child) {
+                    queue.push_back(child);
+                }
+            }
+        }
+    }
 }
 
 impl Graph {
@@ -903,6 +2679,9 @@ impl Graph {
             unused: Vec::default(),
             adjacency: Vec::default(),
             total_active_edges: 0,
+            incremental: None,
+            full_recompute_period: 0,
+            version: 0,
         };
         res.id2p.push(source.clone());
         res.adjacency.push(Vec::default());
@@ -912,10 +2691,102 @@ impl Graph {
         res
     }
 
+    /// Approximate bytes used by the nodes, their adjacency lists and the incremental shortest
+    /// paths.
+    pub fn memory_bytes(&self) -> usize {
+        let incremental = self
+            .incremental
+            .as_ref()
+            .map_or(0, |spf| vec_bytes(&spf.distance) + vec_bytes(&spf.routes));
+        hash_map_bytes(&self.p2id)
+            + vec_bytes(&self.id2p)
+            + vec_bytes(&self.used)
+            + vec_bytes(&self.unused)
+            + vec_bytes(&self.adjacency)
+            + self.adjacency.iter().map(vec_bytes).sum::<usize>()
+            + incremental
+    }
+
+    /// Keep shortest paths up to date on every change of the graph, so `recalculate_distance`
+    /// only needs to walk the affected subtrees. Every `full_recompute_period` recalculations
+    /// shortest paths are computed from scratch.
+    pub fn with_incremental(mut self, full_recompute_period: usize) -> Self {
+        self.full_recompute_period = full_recompute_period;
+        self.incremental = Some(ShortestPaths {
+            words: 0,
+            distance: vec![],
+            routes: vec![],
+            dirty: true,
+            incremental_updates: 0,
+        });
+        self
+    }
+
+    /// Copy of the graph without the incremental shortest paths, to compute shortest paths on
+    /// another thread.
+    pub fn snapshot(&self) -> Graph {
+        Graph {
+            source: self.source.clone(),
+            source_id: self.source_id,
+            p2id: self.p2id.clone(),
+            id2p: self.id2p.clone(),
+            used: self.used.clone(),
+            unused: self.unused.clone(),
+            adjacency: self.adjacency.clone(),
+            total_active_edges: self.total_active_edges,
+            incremental: None,
+            full_recompute_period: 0,
+            version: self.version,
+        }
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Whether the next `recalculate_distance` computes shortest paths from scratch.
+    pub fn needs_full_recompute(&self) -> bool {
+        match &self.incremental {
+            Some(spf) => spf.dirty || spf.incremental_updates + 1 >= self.full_recompute_period,
+            None => true,
+        }
+    }
+
+    /// Use `spf`, computed from a snapshot of the graph at `version`, as the incremental
+    /// shortest paths if the graph didn't change since. Returns whether they were used.
+    pub(crate) fn install_shortest_paths(&mut self, version: u64, spf: ShortestPaths) -> bool {
+        if version != self.version || self.incremental.is_none() {
+            return false;
+        }
+        self.incremental = Some(spf);
+        true
+    }
+
+    /// Apply change of edge `id0 - id1` to the incremental shortest paths.
+    fn update_shortest_paths(&mut self, id0: u32, id1: u32, added: bool) {
+        self.version += 1;
+        if let Some(spf) = self.incremental.as_mut() {
+            if spf.dirty {
+                return;
+            }
+            if id0 == self.source_id || id1 == self.source_id {
+                // Neighbors of `source` are the bits of the routes.
+                spf.dirty = true;
+                return;
+            }
+            spf.resize(self.id2p.len());
+            if added {
+                spf.on_add_edge(&self.adjacency, id0, id1);
+            } else {
+                spf.on_remove_edge(&self.adjacency, id0, id1);
+            }
+        }
+    }
+
     fn contains_edge(&self, peer0: &PeerId, peer1: &PeerId) -> bool {
         if let Some(&id0) = self.p2id.get(&peer0) {
             if let Some(&id1) = self.p2id.get(&peer1) {
-                return self.adjacency[id0 as usize].contains(&id1);
+                return self.adjacency[id0 as usize].binary_search(&id1).is_ok();
             }
         }
         false
@@ -931,6 +2802,31 @@ impl Graph {
         }
     }
 
+    /// Number of peers in the graph, including `source`.
+    pub fn node_count(&self) -> usize {
+        self.p2id.len()
+    }
+
+    /// Release the memory of removed nodes with the highest ids. Ids of other removed nodes are
+    /// kept to be reused.
+    pub fn compact(&mut self) {
+        let len = self.id2p.len();
+        while !self.used[self.id2p.len() - 1] {
+            self.id2p.pop();
+            self.used.pop();
+            self.adjacency.pop();
+        }
+        if self.id2p.len() < len {
+            self.version += 1;
+            let len = self.id2p.len() as u32;
+            self.unused.retain(|&id| id < len);
+            if let Some(spf) = self.incremental.as_mut() {
+                spf.dirty = true;
+            }
+        }
+        self.p2id.shrink_to_fit();
+    }
+
     fn get_id(&mut self, peer: &PeerId) -> u32 {
         match self.p2id.entry(peer.clone()) {
             Entry::Occupied(occupied) => *occupied.get(),
@@ -955,14 +2851,27 @@ impl Graph {
         }
     }
 
+    fn insert_neighbor(neighbors: &mut Vec<u32>, id: u32) {
+        if let Err(pos) = neighbors.binary_search(&id) {
+            neighbors.insert(pos, id);
+        }
+    }
+
+    fn remove_neighbor(neighbors: &mut Vec<u32>, id: u32) {
+        if let Ok(pos) = neighbors.binary_search(&id) {
+            neighbors.remove(pos);
+        }
+    }
+
     pub fn add_edge(&mut self, peer0: PeerId, peer1: PeerId) {
         assert_ne!(peer0, peer1);
         if !self.contains_edge(&peer0, &peer1) {
             let id0 = self.get_id(&peer0);
             let id1 = self.get_id(&peer1);
 
-            self.adjacency[id0 as usize].push(id1);
-            self.adjacency[id1 as usize].push(id0);
+            Self::insert_neighbor(&mut self.adjacency[id0 as usize], id1);
+            Self::insert_neighbor(&mut self.adjacency[id1 as usize], id0);
+            self.update_shortest_paths(id0, id1, true);
 
             self.total_active_edges += 1;
         }
@@ -974,8 +2883,9 @@ impl Graph {
             let id0 = self.get_id(&peer0);
             let id1 = self.get_id(&peer1);
 
-            self.adjacency[id0 as usize].retain(|&x| x != id1);
-            self.adjacency[id1 as usize].retain(|&x| x != id0);
+            Self::remove_neighbor(&mut self.adjacency[id0 as usize], id1);
+            Self::remove_neighbor(&mut self.adjacency[id1 as usize], id0);
+            self.update_shortest_paths(id0, id1, false);
 
             self.remove_if_unused(id0);
             self.remove_if_unused(id1);
@@ -988,22 +2898,109 @@ impl Graph {
     /// `sources` which belong to the shortest path from `source` to `u`. Nodes that are
     /// not connected to `source` will not appear in the result.
     pub fn calculate_distance(&self) -> HashMap<PeerId, Vec<PeerId>> {
-        // TODO add removal of unreachable nodes
+        self.compute_result(&self.shortest_paths())
+    }
+
+    /// Same as `calculate_distance`, but if incremental mode is enabled reuses the shortest
+    /// paths maintained on every change of the graph instead of running a full BFS.
+    pub fn recalculate_distance(&mut self) -> HashMap<PeerId, Vec<PeerId>> {
+        let full_recompute_period = self.full_recompute_period;
+        let needs_full_recompute = match self.incremental.as_mut() {
+            Some(spf) => {
+                spf.incremental_updates += 1;
+                spf.dirty || spf.incremental_updates >= full_recompute_period
+            }
+            None => true,
+        };
+        if needs_full_recompute {
+            let spf = self.shortest_paths();
+            if self.incremental.is_none() {
+                return self.compute_result(&spf);
+            }
+            self.incremental = Some(spf);
+        }
+        let spf = self.incremental.as_ref().unwrap();
+        self.compute_result(spf)
+    }
+
+    fn shortest_paths(&self) -> ShortestPaths {
+        self.partial_shortest_paths(None)
+    }
+
+    /// Neighbors of `source`, by their position in its adjacency list, in at most
+    /// `max_partitions` groups of about the same number of nodes. Every path between
+    /// neighbors of different groups goes through `source`, so shortest paths starting with the
+    /// neighbors of each group can be computed separately, see `partial_shortest_paths`.
+    pub fn partitions(&self, max_partitions: usize) -> Vec<Vec<usize>> {
+        let neighbors = &self.adjacency[self.source_id as usize];
+        let mut component = vec![usize::MAX; self.id2p.len()];
+        component[self.source_id as usize] = 0;
+        // Neighbors of `source` and number of nodes of each component of the graph without it.
+        let mut components: Vec<(Vec<usize>, usize)> = vec![];
+        for (index, &neighbor) in neighbors.iter().enumerate() {
+            if component[neighbor as usize] != usize::MAX {
+                components[component[neighbor as usize]].0.push(index);
+                continue;
+            }
+            let id = components.len();
+            let mut size = 0;
+            let mut queue = VecDeque::new();
+            component[neighbor as usize] = id;
+            queue.push_back(neighbor);
+            while let Some(cur) = queue.pop_front() {
+                size += 1;
+                for &next in &self.adjacency[cur as usize] {
+                    if component[next as usize] == usize::MAX {
+                        component[next as usize] = id;
+                        queue.push_back(next);
+                    }
+                }
+            }
+            components.push((vec![index], size));
+        }
+
+        // Largest components first, each to the group with the fewest nodes so far.
+        components.sort_by(|a, b| b.1.cmp(&a.1));
+        let mut groups: Vec<(Vec<usize>, usize)> = vec![];
+        for (indices, size) in components {
+            if groups.len() < std::cmp::max(1, max_partitions) {
+                groups.push((indices, size));
+            } else {
+                let group = groups.iter_mut().min_by_key(|(_, group_size)| *group_size).unwrap();
+                group.0.extend(indices);
+                group.1 += size;
+            }
+        }
+        groups
+            .into_iter()
+            .map(|(mut indices, _)| {
+                indices.sort();
+                indices
+            })
+            .collect()
+    }
 
+    /// Shortest paths starting with the neighbors of `source` at positions `neighbors` of its
+    /// adjacency list, all of them if `None`.
+    pub(crate) fn partial_shortest_paths(&self, neighbors: Option<&[usize]>) -> ShortestPaths {
         let mut queue = VecDeque::new();
 
         let nodes = self.id2p.len();
         let mut distance: Vec<i32> = vec![-1; nodes];
-        let mut routes: Vec<u128> = vec![0; nodes];
+        let words = Self::route_words(self.adjacency[self.source_id as usize].len());
+        let mut routes: Vec<u64> = vec![0; nodes * words];
 
         distance[self.source_id as usize] = 0;
 
         {
-            let neighbors = &self.adjacency[self.source_id as usize];
-            for (id, &neighbor) in neighbors.iter().enumerate().take(MAX_NUM_PEERS) {
+            let all_neighbors = &self.adjacency[self.source_id as usize];
+            let start =
+                neighbors.map_or_else(|| (0..all_neighbors.len()).collect(), |ids| ids.to_vec());
+            for id in start {
+                let neighbor = all_neighbors[id];
                 queue.push_back(neighbor);
                 distance[neighbor as usize] = 1;
-                routes[neighbor as usize] = 1u128 << id;
+                routes[neighbor as usize * words + id / 64] |= 1u64 << (id % 64);
             }
         }
 
@@ -1018,51 +3015,164 @@ impl Graph {
                 // If this edge belong to a shortest path, all paths to
                 // the closer nodes are also valid for the current node.
                 if distance[neighbor as usize] == cur_distance + 1 {
-                    routes[neighbor as usize] |= routes[cur_peer as usize];
+                    let (cur, next) = (cur_peer as usize * words, neighbor as usize * words);
+                    for word in 0..words {
+                        routes[next + word] |= routes[cur + word];
+                    }
                 }
             }
         }
 
-        self.compute_result(&mut routes, &distance)
+        ShortestPaths { words, distance, routes, dirty: false, incremental_updates: 0 }
     }
 
-    fn compute_result(&self, routes: &[u128], distance: &[i32]) -> HashMap<PeerId, Vec<PeerId>> {
-        let mut res = HashMap::with_capacity(routes.len());
+    /// Number of hops from `source` to `peer_id`, or `None` if it is not reachable.
+    pub fn distance(&self, peer_id: &PeerId) -> Option<u32> {
+        let id = *self.p2id.get(peer_id)? as usize;
+        let distance = match &self.incremental {
+            Some(spf) if !spf.dirty && id < spf.distance.len() => spf.distance[id],
+            _ => self.shortest_paths().distance[id],
+        };
+        if distance < 0 {
+            None
+        } else {
+            Some(distance as u32)
+        }
+    }
 
-        let neighbors = &self.adjacency[self.source_id as usize];
-        let mut unreachable_nodes = 0;
+    /// Number of hops from `source` to each of `peers`, computing the shortest paths at most once.
+    pub fn distances(&self, peers: &[PeerId]) -> Vec<Option<u32>> {
+        if peers.is_empty() {
+            return vec![];
+        }
+        self.with_shortest_paths(|spf| {
+            peers
+                .iter()
+                .map(|peer_id| {
+                    let distance = spf.distance[*self.p2id.get(peer_id)? as usize];
+                    if distance < 0 {
+                        None
+                    } else {
+                        Some(distance as u32)
+                    }
+                })
+                .collect()
+        })
+    }
+
+    /// Run `f` on the up to date shortest paths, computing them only if they are not maintained
+    /// incrementally.
+    fn with_shortest_paths<T>(&self, f: impl FnOnce(&ShortestPaths) -> T) -> T {
+        match &self.incremental {
+            Some(spf) if !spf.dirty && spf.distance.len() >= self.id2p.len() => f(spf),
+            _ => f(&self.shortest_paths()),
+        }
+    }
+
+    /// Peers of the graph not connected to `source`.
+    pub fn unreachable_nodes(&self) -> Vec<PeerId> {
+        self.with_shortest_paths(|spf| {
+            (0..self.id2p.len())
+                .filter(|&id| self.used[id] && spf.distance[id] == -1)
+                .map(|id| self.id2p[id].clone())
+                .collect()
+        })
+    }
 
-        for (key, &cur_route) in routes.iter().enumerate() {
-            if distance[key] == -1 && self.used[key] {
-                unreachable_nodes += 1;
+    /// Largest and average number of hops from `source` to the peers reachable from it, zeros
+    /// if there are none.
+    pub fn hop_stats(&self) -> (u32, f64) {
+        self.with_shortest_paths(|spf| {
+            let hops = (0..self.id2p.len())
+                .filter(|&id| self.used[id] && spf.distance[id] > 0)
+                .map(|id| spf.distance[id] as u32);
+            let (max, sum, count) = hops.fold((0, 0u64, 0u64), |(max, sum, count), hops| {
+                (std::cmp::max(max, hops), sum + hops as u64, count + 1)
+            });
+            if count == 0 {
+                (0, 0.0)
+            } else {
+                (max, sum as f64 / count as f64)
             }
+        })
+    }
+
+    /// Number of u64 words needed to store one bit per neighbor of `source`.
+    fn route_words(num_neighbors: usize) -> usize {
+        std::cmp::max(1, (num_neighbors + 63) / 64)
+    }
+
+    pub(crate) fn compute_result(&self, spf: &ShortestPaths) -> HashMap<PeerId, Vec<PeerId>> {
+        let distance = &spf.distance;
+        let mut res = HashMap::with_capacity(distance.len());
+
+        let neighbors = &self.adjacency[self.source_id as usize];
+
+        for (key, cur_route) in spf.routes.chunks(spf.words).enumerate() {
             if key as u32 == self.source_id
                 || distance[key] == -1
-                || cur_route == 0u128
+                || cur_route.iter().all(|&word| word == 0)
                 || !self.used[key]
             {
                 continue;
             }
-            let mut peer_set: Vec<PeerId> = Vec::with_capacity(cur_route.count_ones() as usize);
+            let num_routes: u32 = cur_route.iter().map(|word| word.count_ones()).sum();
+            let mut peer_set: Vec<PeerId> = Vec::with_capacity(num_routes as usize);
 
-            for (id, &neighbor) in neighbors.iter().enumerate().take(MAX_NUM_PEERS) {
-                if (cur_route & (1u128 << id)) != 0 {
+            for (id, &neighbor) in neighbors.iter().enumerate() {
+                if (cur_route[id / 64] & (1u64 << (id % 64))) != 0 {
                     peer_set.push(self.id2p[neighbor as usize].clone());
                 };
             }
             res.insert(self.id2p[key].clone(), peer_set);
         }
-        if unreachable_nodes > 1000 {
-            warn!("We store more than 1000 unreachable nodes: {}", unreachable_nodes);
-        }
         res
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::routing::Graph;
+    use std::collections::HashSet;
+    use std::time::{Duration, Instant};
+
+    use chrono::Utc;
+    use near_crypto::{KeyType, SecretKey, Signature};
+    use near_primitives::hash::hash;
+    use near_primitives::network::{AnnounceAccount, PeerId};
+    use near_primitives::types::{AccountId, EpochId};
+    use near_primitives::utils::{index_to_bytes, to_timestamp};
+    use near_store::test_utils::create_test_store;
+    use near_store::{ColComponentEdges, ColPeerComponent};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use crate::routing::{
+        AnnouncementSource, Edge, FindRouteError, Graph, RoutingTable, SubKeys,
+        PROXY_ANNOUNCEMENT_TTL,
+    };
     use crate::test_utils::{expected_routing_tables, random_peer_id};
+    use crate::time::FakeClock;
+    use crate::types::{Pong, ProxyAnnouncement, RoutingStrategy, RoutingTableConfig};
+
+    #[test]
+    fn install_shortest_paths_of_snapshot() {
+        let source = random_peer_id();
+        let (node0, node1) = (random_peer_id(), random_peer_id());
+        let mut graph = Graph::new(source.clone()).with_incremental(100);
+        graph.add_edge(source.clone(), node0.clone());
+        graph.add_edge(node0.clone(), node1.clone());
+        assert!(graph.needs_full_recompute());
+
+        let snapshot = graph.snapshot();
+        let spf = snapshot.partial_shortest_paths(None);
+        assert!(graph.install_shortest_paths(snapshot.version(), spf.clone()));
+        assert!(!graph.needs_full_recompute());
+        assert_eq!(graph.recalculate_distance(), snapshot.compute_result(&spf));
+
+        // Shortest paths of a graph which changed since are not used.
+        graph.add_edge(source, node1);
+        assert!(!graph.install_shortest_paths(snapshot.version(), spf));
+    }
 
     #[test]
     fn graph_contains_edge() {
@@ -1143,6 +3253,66 @@ mod test {
         ));
     }
 
+    #[test]
+    fn graph_distance_many_direct_peers() {
+        let source = random_peer_id();
+        let nodes: Vec<_> = (0..500).map(|_| random_peer_id()).collect();
+        let target = random_peer_id();
+
+        let mut graph = Graph::new(source.clone());
+        for node in nodes.iter() {
+            graph.add_edge(source.clone(), node.clone());
+            graph.add_edge(node.clone(), target.clone());
+        }
+
+        let mut expected: Vec<_> =
+            nodes.iter().map(|node| (node.clone(), vec![node.clone()])).collect();
+        expected.push((target, nodes.clone()));
+        assert!(expected_routing_tables(graph.calculate_distance(), expected));
+    }
+
+    #[test]
+    fn graph_incremental_distance() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let source = random_peer_id();
+        let nodes: Vec<_> = (0..20).map(|_| random_peer_id()).collect();
+
+        // Never run a full recompute unless an update can't be applied incrementally.
+        let mut graph = Graph::new(source.clone()).with_incremental(usize::MAX);
+        for node in nodes.iter().take(3) {
+            graph.add_edge(source.clone(), node.clone());
+        }
+
+        for _ in 0..1000 {
+            let u = rng.gen_range(0, nodes.len());
+            let v = rng.gen_range(0, nodes.len());
+            if u == v {
+                continue;
+            }
+            if rng.gen_bool(0.6) {
+                graph.add_edge(nodes[u].clone(), nodes[v].clone());
+            } else {
+                graph.remove_edge(&nodes[u], &nodes[v]);
+            }
+            assert_eq!(graph.recalculate_distance(), graph.calculate_distance());
+        }
+    }
+
+    #[test]
+    fn graph_hop_stats() {
+        let source = random_peer_id();
+        let nodes: Vec<_> = (0..4).map(|_| random_peer_id()).collect();
+
+        let mut graph = Graph::new(source.clone());
+        assert_eq!(graph.hop_stats(), (0, 0.0));
+        // source - 0 - 1 - 2, and 3 unreachable.
+        graph.add_edge(source.clone(), nodes[0].clone());
+        graph.add_edge(nodes[0].clone(), nodes[1].clone());
+        graph.add_edge(nodes[1].clone(), nodes[2].clone());
+        graph.add_edge(nodes[3].clone(), random_peer_id());
+        assert_eq!(graph.hop_stats(), (3, 2.0));
+    }
+
     #[test]
     fn graph_distance3() {
         let source = random_peer_id();
@@ -1208,4 +3378,861 @@ mod test {
 
         assert!(expected_routing_tables(graph.calculate_distance(), next_hops));
     }
+
+    #[test]
+    fn add_pong_without_delay() {
+        let other = random_peer_id();
+        let fake = FakeClock::new();
+        let mut routing_table =
+            RoutingTable::new(random_peer_id(), create_test_store()).with_clock(fake.clock());
+
+        routing_table.sending_ping(0, other.clone());
+        // Pong arrives at the instant the ping was sent.
+        assert_eq!(routing_table.add_pong(Pong { nonce: 0, source: other }), Some(0f64));
+    }
+
+    #[test]
+    fn ping_rtt_by_nonce() {
+        let other = random_peer_id();
+        let fake = FakeClock::new();
+        let mut routing_table =
+            RoutingTable::new(random_peer_id(), create_test_store()).with_clock(fake.clock());
+
+        routing_table.sending_ping(0, other.clone());
+        routing_table.sending_ping(1, other.clone());
+        fake.advance(Duration::from_millis(20));
+        routing_table.add_pong(Pong { nonce: 1, source: other.clone() });
+
+        assert_eq!(routing_table.ping_rtt(&other, 0), None);
+        assert_eq!(routing_table.ping_rtt(&other, 1), Some(20f64));
+    }
+
+    #[test]
+    fn ping_nonces_wrap_around() {
+        let other = random_peer_id();
+        let mut routing_table = RoutingTable::new(random_peer_id(), create_test_store());
+
+        routing_table.sending_ping(u64::MAX, other.clone());
+        assert_eq!(routing_table.get_ping(other.clone()), 0);
+        routing_table.sending_ping(0, other.clone());
+        assert!(routing_table.add_pong(Pong { nonce: u64::MAX, source: other.clone() }).is_some());
+        assert!(routing_table.add_pong(Pong { nonce: 0, source: other.clone() }).is_some());
+        // Pong for a ping which wasn't sent yet.
+        assert_eq!(routing_table.add_pong(Pong { nonce: 1, source: other }), None);
+    }
+
+    #[cfg(feature = "test_features")]
+    #[test]
+    fn restore_routing_table_snapshot() {
+        let me = random_peer_id();
+        let nodes: Vec<_> = (0..3).map(|_| random_peer_id()).collect();
+        let mut routing_table = RoutingTable::new(me.clone(), create_test_store());
+        routing_table.process_edges(vec![
+            Edge::make_fake_edge(me.clone(), nodes[0].clone(), 1),
+            Edge::make_fake_edge(nodes[0].clone(), nodes[1].clone(), 1),
+            Edge::make_fake_edge(nodes[1].clone(), nodes[2].clone(), 2),
+        ]);
+        routing_table.update(None);
+        let snapshot = routing_table.snapshot();
+
+        let mut restored = RoutingTable::new(me, create_test_store());
+        restored.restore(snapshot.clone());
+        assert_eq!(restored.edges_info, routing_table.edges_info);
+        assert_eq!(restored.peer_forwarding, routing_table.peer_forwarding);
+        assert_eq!(restored.peer_last_time_reachable.len(), nodes.len());
+        assert_eq!(restored.component_nonce, snapshot.component_nonce);
+    }
+
+    #[test]
+    fn repair_peer_components() {
+        let me = random_peer_id();
+        let other = random_peer_id();
+        let store = create_test_store();
+        let mut routing_table = RoutingTable::new(me.clone(), store.clone());
+        routing_table.process_edges(vec![Edge::make_fake_edge(me, other.clone(), 1)]);
+
+        // Simulate a crash after `other` was brought back in memory, but before its
+        // component pointer was removed from disk.
+        let mut update = store.store_update();
+        update.set_ser(ColPeerComponent, Vec::from(other.clone()).as_ref(), &0u64).unwrap();
+        update.commit().unwrap();
+
+        assert_eq!(routing_table.repair_peer_components(), 1);
+        assert_eq!(
+            store.get_ser::<u64>(ColPeerComponent, Vec::from(other).as_ref()).unwrap(),
+            None
+        );
+        assert_eq!(routing_table.repair_peer_components(), 0);
+    }
+
+    #[test]
+    fn evict_unreachable_nodes() {
+        let me = random_peer_id();
+        let nodes: Vec<_> = (0..4).map(|_| random_peer_id()).collect();
+        let config = RoutingTableConfig { max_unreachable_graph_nodes: 2, ..Default::default() };
+        let store = create_test_store();
+        let mut routing_table = RoutingTable::with_config(me.clone(), store.clone(), config);
+        routing_table.process_edges(vec![
+            Edge::make_fake_edge(me.clone(), nodes[0].clone(), 1),
+            Edge::make_fake_edge(nodes[0].clone(), nodes[1].clone(), 1),
+        ]);
+        routing_table.update(Some(RoutingTableConfig::default().prune_config()));
+
+        // nodes[0] and nodes[1] become unreachable, nodes[2] and nodes[3] never were.
+        routing_table.process_edges(vec![
+            Edge::make_fake_edge(me.clone(), nodes[0].clone(), 2),
+            Edge::make_fake_edge(nodes[2].clone(), nodes[3].clone(), 1),
+        ]);
+        for peer_id in nodes[2..].iter() {
+            routing_table
+                .peer_last_time_reachable
+                .insert(peer_id.clone(), Instant::now() - Duration::from_secs(60));
+        }
+        assert_eq!(routing_table.raw_graph.node_count(), 5);
+        let removed = routing_table.update(Some(RoutingTableConfig::default().prune_config()));
+        assert_eq!(removed, vec![Edge::make_fake_edge(nodes[2].clone(), nodes[3].clone(), 1)]);
+        assert_eq!(routing_table.raw_graph.node_count(), 3);
+        assert_eq!(routing_table.raw_graph.id2p.len(), 3);
+        assert!(store
+            .get_ser::<u64>(ColPeerComponent, &Vec::from(nodes[2].clone()))
+            .unwrap()
+            .is_some());
+
+        // Evicted peers are loaded back from disk once they are seen again.
+        routing_table.process_edges(vec![Edge::make_fake_edge(me, nodes[2].clone(), 1)]);
+        routing_table.update(Some(RoutingTableConfig::default().prune_config()));
+        assert!(routing_table.peer_forwarding.contains_key(&nodes[3]));
+    }
+
+    #[test]
+    fn evict_over_edge_budget() {
+        let me = random_peer_id();
+        let nodes: Vec<_> = (0..5).map(|_| random_peer_id()).collect();
+        let config = RoutingTableConfig { max_edges_in_memory: 2, ..Default::default() };
+        let store = create_test_store();
+        let mut routing_table = RoutingTable::with_config(me.clone(), store.clone(), config);
+        let unreachable_edge = Edge::make_fake_edge(nodes[1].clone(), nodes[2].clone(), 1);
+        routing_table.process_edges(vec![
+            Edge::make_fake_edge(me, nodes[0].clone(), 1),
+            unreachable_edge.clone(),
+            Edge::make_fake_edge(nodes[3].clone(), nodes[4].clone(), 1),
+        ]);
+        for (peer_id, secs) in nodes[1..].iter().zip([60, 60, 30, 30].iter()) {
+            routing_table
+                .peer_last_time_reachable
+                .insert(peer_id.clone(), Instant::now() - Duration::from_secs(*secs));
+        }
+
+        // Only the peers unreachable for the longest time are evicted, until the budget is met.
+        let removed = routing_table.update(Some(RoutingTableConfig::default().prune_config()));
+        assert_eq!(removed, vec![unreachable_edge]);
+        assert_eq!(routing_table.get_edges_len(), 2);
+        let saved = |peer_id: &PeerId| {
+            store.get_ser::<u64>(ColPeerComponent, &Vec::from(peer_id.clone())).unwrap().is_some()
+        };
+        assert!(saved(&nodes[1]) || saved(&nodes[2]));
+        assert!(!saved(&nodes[3]) && !saved(&nodes[4]));
+    }
+
+    #[test]
+    fn remove_old_saved_components() {
+        let config = RoutingTableConfig { max_saved_components: 2, ..Default::default() };
+        let store = create_test_store();
+        let mut routing_table = RoutingTable::with_config(random_peer_id(), store.clone(), config);
+        let mut components = vec![];
+        for _ in 0..3 {
+            let (a, b) = (random_peer_id(), random_peer_id());
+            routing_table.process_edges(vec![Edge::make_fake_edge(a.clone(), b.clone(), 1)]);
+            routing_table.save_component(&vec![a.clone(), b.clone()].into_iter().collect());
+            components.push((a, b));
+        }
+        let component = |peer_id: &PeerId| {
+            store.get_ser::<u64>(ColPeerComponent, &Vec::from(peer_id.clone())).unwrap()
+        };
+        let saved = |nonce: u64| {
+            store.get_ser::<Vec<Edge>>(ColComponentEdges, &index_to_bytes(nonce)).unwrap().is_some()
+        };
+        assert!(!saved(0) && saved(1) && saved(2));
+        assert_eq!(component(&components[0].0), None);
+        assert_eq!(component(&components[0].1), None);
+        assert_eq!(component(&components[1].0), Some(1));
+
+        // Stale mappings are removed along with the components beyond the limit.
+        let stale = random_peer_id();
+        let mut update = store.store_update();
+        update.set_ser(ColPeerComponent, &Vec::from(stale.clone()), &7u64).unwrap();
+        update.commit().unwrap();
+        assert_eq!(RoutingTable::gc_saved_components(&store, 1).unwrap(), (1, 3));
+        assert!(!saved(1) && saved(2));
+        assert_eq!(component(&stale), None);
+        assert_eq!(component(&components[2].0), Some(2));
+    }
+
+    #[test]
+    fn single_next_hop_stats() {
+        let me = random_peer_id();
+        let (a, b, c, d, e) = (
+            random_peer_id(),
+            random_peer_id(),
+            random_peer_id(),
+            random_peer_id(),
+            random_peer_id(),
+        );
+        let mut routing_table = RoutingTable::new(me.clone(), create_test_store());
+        assert_eq!(routing_table.single_next_hop_stats(), (0.0, vec![]));
+
+        // `c` is reachable through both `a` and `b`, `d` and `e` only through `a`.
+        routing_table.process_edges(vec![
+            Edge::make_fake_edge(me.clone(), a.clone(), 1),
+            Edge::make_fake_edge(me, b.clone(), 1),
+            Edge::make_fake_edge(a.clone(), c.clone(), 1),
+            Edge::make_fake_edge(b, c.clone(), 1),
+            Edge::make_fake_edge(a.clone(), d.clone(), 1),
+            Edge::make_fake_edge(d, e, 1),
+        ]);
+        routing_table.update(None);
+        let (fraction, critical_next_hops) = routing_table.single_next_hop_stats();
+        assert!((fraction - 2.0 / 3.0).abs() < 1e-9);
+        assert_eq!(critical_next_hops, vec![(a, 2)]);
+        assert!(routing_table.single_next_hop_warning);
+    }
+
+    #[test]
+    fn find_route_prefers_low_latency() {
+        let me = random_peer_id();
+        let (fast, slow, target) = (random_peer_id(), random_peer_id(), random_peer_id());
+        let mut routing_table = RoutingTable::new(me.clone(), create_test_store());
+        routing_table.process_edges(vec![
+            Edge::make_fake_edge(me.clone(), fast.clone(), 1),
+            Edge::make_fake_edge(me, slow.clone(), 1),
+            Edge::make_fake_edge(fast.clone(), target.clone(), 1),
+            Edge::make_fake_edge(slow.clone(), target.clone(), 1),
+        ]);
+        routing_table.update(None);
+
+        // Without latency information round robin is used.
+        let hops: HashSet<_> =
+            (0..2).map(|_| routing_table.find_route_from_peer_id(&target).unwrap()).collect();
+        assert_eq!(hops.len(), 2);
+
+        routing_table.update_latency(&fast, 10.0);
+        routing_table.update_latency(&slow, 200.0);
+        for _ in 0..20 {
+            assert_eq!(routing_table.find_route_from_peer_id(&target).unwrap(), fast);
+        }
+        assert_eq!(routing_table.info().peer_latency.get(&slow), Some(&200.0));
+    }
+
+    #[test]
+    fn find_route_prefers_next_hops_tracking_shard() {
+        let me = random_peer_id();
+        let (tracking, other, target) = (random_peer_id(), random_peer_id(), random_peer_id());
+        let mut routing_table = RoutingTable::new(me.clone(), create_test_store());
+        routing_table.process_edges(vec![
+            Edge::make_fake_edge(me.clone(), tracking.clone(), 1),
+            Edge::make_fake_edge(me, other.clone(), 1),
+            Edge::make_fake_edge(tracking.clone(), target.clone(), 1),
+            Edge::make_fake_edge(other.clone(), target.clone(), 1),
+        ]);
+        routing_table.update(None);
+        routing_table.set_peer_shards(tracking.clone(), vec![0, 2]);
+        routing_table.set_peer_shards(other.clone(), vec![1]);
+
+        assert_eq!(routing_table.next_hops_tracking_shard(&target, 2), vec![tracking.clone()]);
+        for _ in 0..10 {
+            assert_eq!(routing_table.find_route_via_shard(&target, 2).unwrap(), tracking);
+        }
+        // No next hop tracks shard 3, so any of them is used.
+        let hops: HashSet<_> =
+            (0..2).map(|_| routing_table.find_route_via_shard(&target, 3).unwrap()).collect();
+        assert_eq!(hops.len(), 2);
+
+        routing_table.remove_peer_shards(&tracking);
+        assert!(!routing_table.tracks_shard(&tracking, 2));
+        assert!(routing_table.next_hops_tracking_shard(&target, 2).is_empty());
+    }
+
+    #[test]
+    fn find_route_avoids_deprioritized_peers() {
+        let me = random_peer_id();
+        let (good, bad, target) = (random_peer_id(), random_peer_id(), random_peer_id());
+        let mut routing_table = RoutingTable::new(me.clone(), create_test_store());
+        routing_table.process_edges(vec![
+            Edge::make_fake_edge(me, bad.clone(), 1),
+            Edge::make_fake_edge(bad.clone(), target.clone(), 1),
+        ]);
+        routing_table.update(None);
+        routing_table.deprioritize_peer(bad.clone());
+
+        // A deprioritized peer is still used if it is the only route.
+        assert_eq!(routing_table.find_route_from_peer_id(&target).unwrap(), bad);
+
+        routing_table.process_edges(vec![
+            Edge::make_fake_edge(routing_table.peer_id().clone(), good.clone(), 1),
+            Edge::make_fake_edge(good.clone(), target.clone(), 1),
+        ]);
+        routing_table.update(None);
+        // Even if it is faster.
+        routing_table.update_latency(&bad, 10.0);
+        routing_table.update_latency(&good, 200.0);
+        for _ in 0..20 {
+            assert_eq!(routing_table.find_route_from_peer_id(&target).unwrap(), good);
+        }
+
+        routing_table.set_deprioritized_peers(HashSet::new());
+        assert_eq!(routing_table.find_route_from_peer_id(&target).unwrap(), bad);
+    }
+
+    #[test]
+    fn find_route_avoids_failed_next_hops() {
+        let me = random_peer_id();
+        let (hop1, hop2, target) = (random_peer_id(), random_peer_id(), random_peer_id());
+        let fake = FakeClock::new();
+        let mut routing_table =
+            RoutingTable::new(me.clone(), create_test_store()).with_clock(fake.clock());
+        routing_table.process_edges(vec![
+            Edge::make_fake_edge(me.clone(), hop1.clone(), 1),
+            Edge::make_fake_edge(me, hop2.clone(), 1),
+            Edge::make_fake_edge(hop1.clone(), target.clone(), 1),
+            Edge::make_fake_edge(hop2.clone(), target.clone(), 1),
+        ]);
+        routing_table.update(None);
+
+        routing_table.record_route_failure(&target, Some(hop1.clone()), "test".to_string());
+        for _ in 0..5 {
+            assert_eq!(routing_table.find_route_from_peer_id(&target).unwrap(), hop2);
+        }
+        // The penalty drops to a quarter after two half-lives, and the next hop is used again.
+        fake.advance(NEXT_HOP_AVOIDANCE_HALF_LIFE * 2);
+        assert_eq!(routing_table.find_route_from_peer_id(&target).unwrap(), hop1);
+
+        // Repeated failures make the avoidance last longer.
+        for _ in 0..3 {
+            routing_table.record_route_failure(&target, Some(hop1.clone()), "test".to_string());
+        }
+        fake.advance(NEXT_HOP_AVOIDANCE_HALF_LIFE * 2);
+        assert_eq!(routing_table.find_route_from_peer_id(&target).unwrap(), hop2);
+        assert_eq!(routing_table.find_route_from_peer_id(&target).unwrap(), hop2);
+
+        // A failed next hop is still used if all routes failed.
+        routing_table.record_route_failure(&target, Some(hop2.clone()), "test".to_string());
+        assert!(routing_table.explain_route(&target).candidates.iter().all(|c| c.avoided));
+        routing_table.find_route_from_peer_id(&target).unwrap();
+    }
+
+    #[test]
+    fn ban_peer() {
+        let me = random_peer_id();
+        let (good, bad, target) = (random_peer_id(), random_peer_id(), random_peer_id());
+        let mut routing_table = RoutingTable::new(me.clone(), create_test_store());
+        routing_table.process_edges(vec![
+            Edge::make_fake_edge(me.clone(), good.clone(), 1),
+            Edge::make_fake_edge(me.clone(), bad.clone(), 1),
+            Edge::make_fake_edge(good.clone(), target.clone(), 1),
+            Edge::make_fake_edge(bad.clone(), target.clone(), 1),
+        ]);
+        routing_table.update(None);
+        routing_table.process_edges(vec![Edge::make_fake_edge(me.clone(), bad.clone(), 2)]);
+
+        // Only active edges are removed.
+        let removed = routing_table.ban_peer(bad.clone());
+        assert_eq!(removed, vec![Edge::make_fake_edge(bad.clone(), target.clone(), 1)]);
+        assert_eq!(routing_table.find_nonce(&(me.clone(), bad.clone())), 2);
+        // The banned peer is skipped even before routes are recomputed.
+        for _ in 0..10 {
+            assert_eq!(routing_table.find_route_from_peer_id(&target).unwrap(), good);
+        }
+
+        let edge = Edge::make_fake_edge(bad.clone(), target.clone(), 3);
+        assert!(!routing_table.process_edges(vec![edge.clone()]).new_edge);
+        routing_table.update(None);
+        assert!(!routing_table.peer_forwarding.contains_key(&bad));
+
+        routing_table.unban_peer(&bad);
+        assert!(routing_table.process_edges(vec![edge]).new_edge);
+    }
+
+    #[test]
+    fn explain_route() {
+        let me = random_peer_id();
+        let (fast, slow, target) = (random_peer_id(), random_peer_id(), random_peer_id());
+        let mut routing_table = RoutingTable::new(me.clone(), create_test_store());
+        routing_table.process_edges(vec![
+            Edge::make_fake_edge(me.clone(), fast.clone(), 1),
+            Edge::make_fake_edge(me, slow.clone(), 1),
+            Edge::make_fake_edge(fast.clone(), target.clone(), 1),
+            Edge::make_fake_edge(slow.clone(), target.clone(), 1),
+        ]);
+        routing_table.update(None);
+        routing_table.update_latency(&fast, 10.0);
+        routing_table.update_latency(&slow, 200.0);
+        routing_table.record_route_failure(&target, Some(slow.clone()), "test".to_string());
+
+        let explanation = routing_table.explain_route(&target);
+        assert_eq!(explanation.distance, Some(2));
+        assert_eq!(explanation.candidates.len(), 2);
+        assert_eq!(explanation.selected, Some(fast.clone()));
+        assert_eq!(explanation.recent_failures.len(), 1);
+        assert_eq!(explanation.recent_failures[0].next_hop, Some(slow));
+        // Explaining doesn't affect round robin.
+        assert!(explanation.candidates.iter().all(|candidate| candidate.nonce == 0));
+        assert_eq!(routing_table.explain_route(&target).selected, Some(fast));
+
+        let unknown = routing_table.explain_route(&random_peer_id());
+        assert_eq!(unknown.distance, None);
+        assert_eq!(unknown.selected, None);
+    }
+
+    #[test]
+    fn explain_account() {
+        let me = random_peer_id();
+        let (hop, owner) = (random_peer_id(), random_peer_id());
+        let mut routing_table = RoutingTable::new(me.clone(), create_test_store());
+        let account_id: AccountId = "test1".parse().unwrap();
+        let announce = |peer_id: &PeerId| AnnounceAccount {
+            account_id: account_id.clone(),
+            peer_id: peer_id.clone(),
+            epoch_id: EpochId::default(),
+            signature: Signature::empty(KeyType::ED25519),
+        };
+
+        let explanation = routing_table.explain_account(&account_id);
+        assert_eq!(explanation.announcement, None);
+        assert!(explanation.routes.is_empty());
+
+        routing_table.add_account(announce(&owner));
+        let explanation = routing_table.explain_account(&account_id);
+        assert_eq!(explanation.announcement, Some(AnnouncementSource::Cache));
+        assert_eq!(explanation.routes.len(), 1);
+        assert_eq!(explanation.routes[0].distance, None);
+
+        routing_table.process_edges(vec![
+            Edge::make_fake_edge(me.clone(), hop.clone(), 1),
+            Edge::make_fake_edge(hop.clone(), owner.clone(), 1),
+        ]);
+        routing_table.update(None);
+        let explanation = routing_table.explain_account(&account_id);
+        assert_eq!(explanation.routes[0].selected, Some(hop));
+
+        // Announcements evicted from the cache are loaded from the store.
+        let mut restarted = RoutingTable::new(me, routing_table.store.clone());
+        let explanation = restarted.explain_account(&account_id);
+        assert_eq!(explanation.announcement, Some(AnnouncementSource::Store));
+        assert_eq!(explanation.epoch_id, Some(EpochId::default()));
+    }
+
+    #[test]
+    fn shadow_routing_strategy() {
+        let me = random_peer_id();
+        let (fast, slow, target) = (random_peer_id(), random_peer_id(), random_peer_id());
+        let config = RoutingTableConfig {
+            strategy: RoutingStrategy::RoundRobin,
+            shadow_strategy: Some(RoutingStrategy::LatencyAware),
+            ..Default::default()
+        };
+        let mut routing_table = RoutingTable::with_config(me.clone(), create_test_store(), config);
+        routing_table.process_edges(vec![
+            Edge::make_fake_edge(me.clone(), fast.clone(), 1),
+            Edge::make_fake_edge(me, slow.clone(), 1),
+            Edge::make_fake_edge(fast.clone(), target.clone(), 1),
+            Edge::make_fake_edge(slow.clone(), target.clone(), 1),
+        ]);
+        routing_table.update(None);
+        routing_table.update_latency(&fast, 10.0);
+        routing_table.update_latency(&slow, 200.0);
+
+        let explanation = routing_table.explain_route(&target);
+        assert_eq!(explanation.shadow_selected, Some(fast.clone()));
+
+        // The shadow strategy doesn't affect routing: round robin keeps using the slow peer.
+        let hops: Vec<_> =
+            (0..4).map(|_| routing_table.find_route_from_peer_id(&target).unwrap()).collect();
+        assert_eq!(hops.iter().filter(|hop| **hop == slow).count(), 2);
+        let stats = routing_table.shadow_stats();
+        assert_eq!(stats.decisions, 4);
+        assert_eq!(stats.divergences, 2);
+        assert_eq!(stats.latency_difference_ms, -2.0 * 190.0);
+    }
+
+    #[test]
+    fn account_owners_fail_over() {
+        let me = random_peer_id();
+        let (reachable, unreachable) = (random_peer_id(), random_peer_id());
+        let mut routing_table = RoutingTable::new(me.clone(), create_test_store());
+        routing_table.process_edges(vec![Edge::make_fake_edge(me, reachable.clone(), 1)]);
+        routing_table.update(None);
+
+        let account_id: AccountId = "validator".parse().unwrap();
+        let announce = |peer_id: &PeerId, epoch_id: EpochId| AnnounceAccount {
+            account_id: account_id.clone(),
+            peer_id: peer_id.clone(),
+            epoch_id,
+            signature: Signature::empty(KeyType::ED25519),
+        };
+        routing_table.add_account(announce(&reachable, EpochId::default()));
+        assert!(!routing_table.contains_account(&announce(&unreachable, EpochId::default())));
+        routing_table.add_account(announce(&unreachable, EpochId::default()));
+        assert!(routing_table.contains_account(&announce(&reachable, EpochId::default())));
+
+        // The most recent announcement is not reachable, so the other peer is preferred.
+        assert_eq!(
+            routing_table.account_owners(&account_id).unwrap(),
+            vec![reachable.clone(), unreachable.clone()]
+        );
+        assert_eq!(routing_table.account_owner(&account_id).unwrap(), reachable);
+
+        // Announcement from a new epoch replaces previous ones.
+        let new_epoch = EpochId(hash(&[1]));
+        routing_table.add_account(announce(&unreachable, new_epoch));
+        assert_eq!(routing_table.account_owners(&account_id).unwrap(), vec![unreachable]);
+    }
+
+    #[test]
+    fn announcements_expire_with_their_epoch() {
+        let mut routing_table = RoutingTable::new(random_peer_id(), create_test_store());
+        let (old_peer, new_peer) = (random_peer_id(), random_peer_id());
+        let account_id: AccountId = "validator".parse().unwrap();
+        let announce = |peer_id: &PeerId, epoch_id: &EpochId| AnnounceAccount {
+            account_id: account_id.clone(),
+            peer_id: peer_id.clone(),
+            epoch_id: epoch_id.clone(),
+            signature: Signature::empty(KeyType::ED25519),
+        };
+        let epochs: Vec<_> = (0..4u8).map(|i| EpochId(hash(&[i]))).collect();
+        routing_table.set_epoch_validators(epochs[0].clone(), epochs[1].clone(), HashSet::new());
+        routing_table.add_account(announce(&old_peer, &epochs[1]));
+
+        // The validator moved to a new peer in the next epoch.
+        routing_table.set_epoch_validators(epochs[1].clone(), epochs[2].clone(), HashSet::new());
+        routing_table.add_account(announce(&new_peer, &epochs[2]));
+        assert_eq!(routing_table.account_owner(&account_id).unwrap(), new_peer);
+        // Announcements from an older epoch don't shadow the newest one.
+        assert!(routing_table.is_stale_announcement(&announce(&old_peer, &epochs[1])));
+        routing_table.add_account(announce(&old_peer, &epochs[1]));
+        assert_eq!(routing_table.account_owners(&account_id).unwrap(), vec![new_peer.clone()]);
+        // Announcements from epochs we don't know the order of are accepted.
+        let unknown_epoch = EpochId(hash(&[100]));
+        assert!(!routing_table.is_stale_announcement(&announce(&old_peer, &unknown_epoch)));
+
+        // Once the epoch of the announcement is over, it expires, also from the store.
+        routing_table.set_epoch_validators(epochs[3].clone(), EpochId(hash(&[4])), HashSet::new());
+        assert!(routing_table.is_stale_announcement(&announce(&new_peer, &epochs[2])));
+        assert!(matches!(
+            routing_table.account_owner(&account_id),
+            Err(FindRouteError::AccountNotFound)
+        ));
+        assert!(routing_table.get_announce_accounts().is_empty());
+    }
+
+    #[test]
+    fn announcement_origins() {
+        let config = RoutingTableConfig { max_announcement_hops: 3, ..Default::default() };
+        let mut routing_table =
+            RoutingTable::with_config(random_peer_id(), create_test_store(), config);
+        let (source, owner) = (random_peer_id(), random_peer_id());
+        let account_id: AccountId = "test1".parse().unwrap();
+        let announce = AnnounceAccount {
+            account_id: account_id.clone(),
+            peer_id: owner.clone(),
+            epoch_id: EpochId::default(),
+            signature: Signature::empty(KeyType::ED25519),
+        };
+        routing_table.add_account(announce.clone());
+
+        // Only the first copy of the announcement counts.
+        assert!(routing_table.record_announcement_origin(&announce, Some(source.clone()), Some(4)));
+        assert!(!routing_table.record_announcement_origin(&announce, None, Some(1)));
+        assert_eq!(routing_table.announcement_hops(&announce), Some(4));
+        let explanation = routing_table.explain_account(&account_id);
+        assert_eq!(explanation.origins.len(), 1);
+        assert_eq!(explanation.origins[0].1.source, Some(source));
+        assert!(!explanation.origins[0].1.verified);
+
+        // The owner answers the ping sent to verify the announcement.
+        let nonce = routing_table.get_ping(owner.clone());
+        routing_table.verifying_announcement(&announce, nonce);
+        routing_table.sending_ping(nonce, owner.clone());
+        routing_table.add_pong(Pong { nonce, source: owner });
+        assert!(routing_table.announcement_origin(&announce).unwrap().verified);
+    }
+
+    #[test]
+    fn route_through_proxy_relays() {
+        let me = random_peer_id();
+        let (next_hop, relay) = (random_peer_id(), random_peer_id());
+        let secret_key = SecretKey::from_random(KeyType::ED25519);
+        let hidden = PeerId::new(secret_key.public_key());
+        let mut routing_table = RoutingTable::new(me.clone(), create_test_store());
+        routing_table.process_edges(vec![
+            Edge::make_fake_edge(me, next_hop.clone(), 1),
+            Edge::make_fake_edge(next_hop.clone(), relay.clone(), 1),
+        ]);
+        routing_table.update(None);
+        assert_eq!(
+            routing_table.find_route_from_peer_id(&hidden),
+            Err(FindRouteError::PeerNotFound)
+        );
+
+        // Relays we can't reach are skipped.
+        let now = to_timestamp(Utc::now());
+        let relays = vec![random_peer_id(), relay.clone()];
+        let announcement = ProxyAnnouncement::new(&secret_key, relays.clone(), now);
+        assert!(routing_table.add_proxy_announcement(announcement.clone()));
+        assert!(!routing_table.add_proxy_announcement(announcement));
+        assert_eq!(routing_table.find_route_from_peer_id(&hidden), Ok(next_hop));
+        assert_eq!(routing_table.get_proxy_announcements().len(), 1);
+
+        // Older and expired announcements are ignored.
+        let older = ProxyAnnouncement::new(&secret_key, vec![random_peer_id()], now - 1);
+        assert!(!routing_table.add_proxy_announcement(older));
+        assert_eq!(routing_table.proxy_relays(&hidden), Some(relays));
+        let ttl = PROXY_ANNOUNCEMENT_TTL.as_nanos() as u64;
+        let other_key = SecretKey::from_random(KeyType::ED25519);
+        let expired = ProxyAnnouncement::new(&other_key, vec![relay], now - 2 * ttl);
+        assert!(!routing_table.add_proxy_announcement(expired));
+    }
+
+    #[test]
+    fn recalculation_view() {
+        let me = random_peer_id();
+        let (a, b) = (random_peer_id(), random_peer_id());
+        let mut routing_table = RoutingTable::new(me.clone(), create_test_store());
+        let view = routing_table.recalculation_view(0);
+        assert_eq!(view.last_recalculation_time, None);
+        assert_eq!(view.last_recalculation_duration_ms, None);
+
+        routing_table.process_edges(vec![
+            Edge::make_fake_edge(me.clone(), a.clone(), 1),
+            Edge::make_fake_edge(a.clone(), b, 1),
+        ]);
+        // Edges with stale nonces aren't processed.
+        routing_table.process_edges(vec![Edge::make_fake_edge(me, a, 1)]);
+        let view = routing_table.recalculation_view(3);
+        assert_eq!(view.edges_since_last_recalculation, 2);
+        assert_eq!(view.queue_depth, 3);
+
+        let before = Utc::now();
+        routing_table.update(None);
+        let view = routing_table.recalculation_view(0);
+        assert!(view.last_recalculation_time.unwrap() >= before);
+        assert!(view.last_recalculation_duration_ms.is_some());
+        assert_eq!(view.edges_since_last_recalculation, 0);
+    }
+
+    #[test]
+    fn sample_peers_by_weight() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let (a, b, c) = (random_peer_id(), random_peer_id(), random_peer_id());
+        let mut routing_table = RoutingTable::new(random_peer_id(), create_test_store());
+        for _ in 0..9 {
+            routing_table.peer_forwarding.insert(random_peer_id(), vec![a.clone()]);
+        }
+        routing_table.ban_peer(c.clone());
+        let candidates = vec![a.clone(), b.clone(), c];
+        let mut sampled = routing_table.sample_peers(&candidates, 5, &mut rng);
+        sampled.sort();
+        let mut expected = vec![a.clone(), b.clone()];
+        expected.sort();
+        assert_eq!(sampled, expected);
+
+        // a reaches ten times more peers than b.
+        let count = |routing_table: &RoutingTable, rng: &mut StdRng, peer_id: &PeerId| {
+            (0..1000)
+                .filter(|_| &routing_table.sample_peers(&candidates, 1, rng)[0] == peer_id)
+                .count()
+        };
+        assert!(count(&routing_table, &mut rng, &a) > 800);
+
+        // Unless it keeps failing to deliver messages.
+        for _ in 0..20 {
+            routing_table.record_route_failure(&random_peer_id(), Some(a.clone()), "test".into());
+        }
+        assert!(count(&routing_table, &mut rng, &b) > 500);
+    }
+
+    #[test]
+    fn route_to_shard_validator() {
+        let me = random_peer_id();
+        let (a, b, c) = (random_peer_id(), random_peer_id(), random_peer_id());
+        let mut routing_table = RoutingTable::new(me.clone(), create_test_store());
+        routing_table.process_edges(vec![
+            Edge::make_fake_edge(me, a.clone(), 1),
+            Edge::make_fake_edge(a.clone(), b.clone(), 1),
+        ]);
+        routing_table.update(None);
+        let (near, far, unreachable): (AccountId, AccountId, AccountId) =
+            ("near".parse().unwrap(), "far".parse().unwrap(), "unreachable".parse().unwrap());
+        for (account_id, peer_id) in [(&near, &a), (&far, &b), (&unreachable, &c)] {
+            routing_table.add_account(AnnounceAccount {
+                account_id: account_id.clone(),
+                peer_id: peer_id.clone(),
+                epoch_id: EpochId::default(),
+                signature: Signature::empty(KeyType::ED25519),
+            });
+        }
+        routing_table.set_shard_validators(
+            vec![(0, vec![far.clone(), near.clone()]), (1, vec![unreachable])]
+                .into_iter()
+                .collect(),
+        );
+
+        assert_eq!(routing_table.shard_validator(0), Ok(near));
+        routing_table.deprioritize_peer(a);
+        assert_eq!(routing_table.shard_validator(0), Ok(far));
+        assert_eq!(routing_table.shard_validator(1), Err(FindRouteError::AccountNotFound));
+        assert_eq!(routing_table.shard_validator(2), Err(FindRouteError::AccountNotFound));
+    }
+
+    #[test]
+    fn non_validator_announcements_are_not_synced() {
+        let mut routing_table = RoutingTable::new(random_peer_id(), create_test_store());
+        let announce = |account_id: &str| AnnounceAccount {
+            account_id: account_id.parse().unwrap(),
+            peer_id: random_peer_id(),
+            epoch_id: EpochId::default(),
+            signature: Signature::empty(KeyType::ED25519),
+        };
+        routing_table.add_account(announce("validator"));
+        routing_table.add_account(announce("kicked_out"));
+        assert_eq!(routing_table.get_validator_announce_accounts().len(), 2);
+
+        let validator: AccountId = "validator".parse().unwrap();
+        routing_table.set_epoch_validators(
+            EpochId::default(),
+            EpochId(hash(&[1])),
+            vec![validator.clone()].into_iter().collect(),
+        );
+        let synced = routing_table.get_validator_announce_accounts();
+        assert_eq!(synced.len(), 1);
+        assert_eq!(synced[0].account_id, validator);
+        // Low priority accounts are still routable.
+        assert!(routing_table.account_owner(&"kicked_out".parse().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn verify_edges_in_batch() {
+        use crate::routing::EdgeInfo;
+        use near_crypto::SecretKey;
+
+        let keys: Vec<SecretKey> =
+            (0..6).map(|i| SecretKey::from_seed(KeyType::ED25519, &format!("node{}", i))).collect();
+        let peers: Vec<PeerId> = keys.iter().map(|key| key.public_key().into()).collect();
+        let mut edges = vec![];
+        for i in 1..keys.len() {
+            let (peer0, key0, peer1, key1) = if peers[0] < peers[i] {
+                (&peers[0], &keys[0], &peers[i], &keys[i])
+            } else {
+                (&peers[i], &keys[i], &peers[0], &keys[0])
+            };
+            let edge_info = EdgeInfo::new(peer0.clone(), peer1.clone(), 1, key1);
+            let edge = Edge::build_with_secret_key(
+                peer0.clone(),
+                peer1.clone(),
+                1,
+                key0,
+                edge_info.signature,
+            );
+            edges.push(edge.remove_edge(peer0.clone(), key0));
+            edges.push(edge);
+        }
+        assert!(edges.iter().all(|edge| edge.verify()));
+        assert!(Edge::verify_batch(&edges, &SubKeys::default()));
+
+        let invalid = Edge::make_fake_edge(peers[0].clone(), peers[1].clone(), 1);
+        edges.insert(3, invalid);
+        assert!(!Edge::verify_batch(&edges, &SubKeys::default()));
+    }
+
+    #[test]
+    fn removal_proof_encoding() {
+        use crate::routing::{EdgeInfo, EdgeRemovalProof};
+        use borsh::{BorshDeserialize, BorshSerialize};
+        use near_crypto::SecretKey;
+
+        let mut keys: Vec<SecretKey> =
+            (0..2).map(|i| SecretKey::from_seed(KeyType::ED25519, &format!("node{}", i))).collect();
+        keys.sort_by_key(|key| PeerId::from(key.public_key()));
+        let peers: Vec<PeerId> = keys.iter().map(|key| key.public_key().into()).collect();
+        let edge_info = EdgeInfo::new(peers[0].clone(), peers[1].clone(), 1, &keys[1]);
+        let added = Edge::build_with_secret_key(
+            peers[0].clone(),
+            peers[1].clone(),
+            1,
+            &keys[0],
+            edge_info.signature,
+        );
+        let removed = added.remove_edge(peers[0].clone(), &keys[0]);
+        assert!(removed.verify());
+
+        // Same encoding as the original `Option<(bool, Signature)>`.
+        let signature = match removed.removal_proof.clone() {
+            Some(EdgeRemovalProof::SignedByPeer { by_peer0: true, signature }) => signature,
+            proof => panic!("unexpected removal proof {:?}", proof),
+        };
+        let legacy: Option<(bool, Signature)> = Some((true, signature));
+        assert_eq!(removed.removal_proof.try_to_vec().unwrap(), legacy.try_to_vec().unwrap());
+
+        // Proofs of unknown versions are decoded, but not valid.
+        let mut extended = removed.clone();
+        extended.removal_proof =
+            Some(EdgeRemovalProof::Unknown { version: 7, payload: vec![1, 2, 3] });
+        let decoded = Edge::try_from_slice(&extended.try_to_vec().unwrap()).unwrap();
+        assert_eq!(decoded, extended);
+        assert!(!decoded.verify());
+    }
+
+    #[test]
+    fn edge_signed_with_sub_key() {
+        use crate::routing::EdgeInfo;
+        use crate::types::SubKeyDelegation;
+        use near_crypto::SecretKey;
+
+        let node_key0 = SecretKey::from_random(KeyType::ED25519);
+        let node_key1 = SecretKey::from_random(KeyType::ED25519);
+        let sub_key0 = SecretKey::from_random(KeyType::ED25519);
+        let peer0: PeerId = node_key0.public_key().into();
+        let peer1: PeerId = node_key1.public_key().into();
+
+        let edge_info = EdgeInfo::new(peer0.clone(), peer1.clone(), 1, &sub_key0);
+        assert!(!Edge::partial_verify(peer1.clone(), peer0.clone(), &edge_info, &[]));
+        assert!(Edge::partial_verify(
+            peer1.clone(),
+            peer0.clone(),
+            &edge_info,
+            &[sub_key0.public_key()]
+        ));
+
+        let edge_info = EdgeInfo::new(peer0.clone(), peer1.clone(), 1, &node_key1);
+        let edge = Edge::build_with_secret_key(
+            peer0.clone(),
+            peer1.clone(),
+            1,
+            &sub_key0,
+            edge_info.signature,
+        );
+        assert!(!edge.verify());
+
+        // Delegation not signed by the node key.
+        let mut forged = SubKeyDelegation::new(&sub_key0, sub_key0.public_key());
+        forged.peer_id = peer0.clone();
+        assert!(!forged.verify());
+
+        let delegation = SubKeyDelegation::new(&node_key0, sub_key0.public_key());
+        assert!(delegation.verify());
+        let mut sub_keys = SubKeys::default();
+        assert!(sub_keys.add(delegation.clone()));
+        assert!(!sub_keys.add(delegation));
+        assert!(edge.verify_with_sub_keys(&sub_keys));
+        assert!(Edge::verify_batch(&[edge.clone()], &sub_keys));
+
+        // Edges signed with the node key stay valid, but aren't verified in batch.
+        let removed = edge.remove_edge(peer0, &node_key0);
+        assert!(removed.verify_with_sub_keys(&sub_keys));
+        assert!(!Edge::verify_batch(&[removed], &sub_keys));
+
+        // Edges and delegations keep the same encoding in all builds.
+        let decoded = Edge::try_from_slice(&edge.try_to_vec().unwrap()).unwrap();
+        assert_eq!(decoded, edge);
+        assert_eq!(edge.try_to_vec().unwrap().len(), 2 * 33 + 8 + 2 * 65 + 1);
+    }
 }
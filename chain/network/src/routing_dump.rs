@@ -0,0 +1,289 @@
+//! Export of the routing table to a file, so it can be attached to bug reports and replayed in
+//! tests.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+#[cfg(feature = "test_features")]
+use serde::{Deserialize, Serialize};
+
+use near_primitives::network::PeerId;
+
+use crate::routing::Edge;
+#[cfg(feature = "test_features")]
+use crate::routing::RoutingTableSnapshot;
+
+/// Version of `RoutingTableDump`. Must be increased on every incompatible change.
+pub const ROUTING_TABLE_DUMP_VERSION: u32 = 2;
+
+/// Full state of the routing table of a node.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "test_features", derive(Serialize, Deserialize))]
+pub struct RoutingTableDump {
+    pub version: u32,
+    /// Node which produced the dump.
+    pub peer_id: PeerId,
+    /// All known edges, both added and removed.
+    pub edges: Vec<Edge>,
+    /// Next hops on the shortest paths to each reachable peer.
+    pub peer_forwarding: Vec<(PeerId, Vec<PeerId>)>,
+    /// Milliseconds since each peer was last reachable.
+    pub peer_last_time_reachable: Vec<(PeerId, u64)>,
+    /// Last nonce used to store edges on disk.
+    pub component_nonce: u64,
+    /// Nonce of the component each peer removed from memory was stored on disk with.
+    pub peer_components: Vec<(PeerId, u64)>,
+    /// Moving average of the round trip time to peers we pinged, in microseconds.
+    pub peer_latency_us: Vec<(PeerId, u64)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoutingTableDumpFormat {
+    Borsh,
+    #[cfg(feature = "test_features")]
+    Json,
+}
+
+impl RoutingTableDumpFormat {
+    /// JSON for paths with a `.json` extension if supported, Borsh otherwise.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension() {
+            #[cfg(feature = "test_features")]
+            Some(ext) if ext == "json" => RoutingTableDumpFormat::Json,
+            _ => RoutingTableDumpFormat::Borsh,
+        }
+    }
+}
+
+impl RoutingTableDump {
+    pub fn write_to_file(&self, path: &Path, format: RoutingTableDumpFormat) -> io::Result<()> {
+        let data = match format {
+            RoutingTableDumpFormat::Borsh => self.try_to_vec()?,
+            #[cfg(feature = "test_features")]
+            RoutingTableDumpFormat::Json => serde_json::to_vec_pretty(self)?,
+        };
+        fs::write(path, data)
+    }
+
+    /// Read a dump written by `write_to_file` in any format.
+    pub fn read_from_file(path: &Path) -> io::Result<Self> {
+        let data = fs::read(path)?;
+        // Borsh dumps start with the version, so they never start with `{`.
+        let is_json = data.iter().find(|byte| !byte.is_ascii_whitespace()) == Some(&b'{');
+        let dump = if is_json { Self::from_json(&data)? } else { Self::try_from_slice(&data)? };
+        if dump.version != ROUTING_TABLE_DUMP_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "unsupported routing table dump version {}, expected {}",
+                    dump.version, ROUTING_TABLE_DUMP_VERSION
+                ),
+            ));
+        }
+        Ok(dump)
+    }
+
+    #[cfg(feature = "test_features")]
+    fn from_json(data: &[u8]) -> io::Result<Self> {
+        Ok(serde_json::from_slice(data)?)
+    }
+
+    #[cfg(not(feature = "test_features"))]
+    fn from_json(_data: &[u8]) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "reading JSON routing table dumps requires test_features",
+        ))
+    }
+}
+
+/// Differences between the routing tables of two nodes, `a` and `b`.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RoutingTableDumpDiff {
+    /// Edges known to `a` but not to `b`.
+    pub edges_only_in_a: Vec<(PeerId, PeerId)>,
+    /// Edges known to `b` but not to `a`.
+    pub edges_only_in_b: Vec<(PeerId, PeerId)>,
+    /// Edges known to both with different nonces, as `(peer0, peer1, nonce_a, nonce_b)`.
+    pub nonce_mismatches: Vec<(PeerId, PeerId, u64, u64)>,
+    /// Peers reachable from `a` but not from `b`.
+    pub reachable_only_from_a: Vec<PeerId>,
+    /// Peers reachable from `b` but not from `a`.
+    pub reachable_only_from_b: Vec<PeerId>,
+}
+
+impl RoutingTableDumpDiff {
+    pub fn new(a: &RoutingTableDump, b: &RoutingTableDump) -> Self {
+        let edges_a = Self::edge_nonces(a);
+        let edges_b = Self::edge_nonces(b);
+        let mut diff = RoutingTableDumpDiff::default();
+        for (key, nonce_a) in edges_a.iter() {
+            match edges_b.get(key) {
+                None => diff.edges_only_in_a.push(key.clone()),
+                Some(nonce_b) if nonce_a != nonce_b => {
+                    diff.nonce_mismatches.push((key.0.clone(), key.1.clone(), *nonce_a, *nonce_b))
+                }
+                Some(_) => {}
+            }
+        }
+        diff.edges_only_in_b =
+            edges_b.keys().filter(|key| !edges_a.contains_key(key)).cloned().collect();
+
+        let reachable_a = Self::reachable(a);
+        let reachable_b = Self::reachable(b);
+        diff.reachable_only_from_a = reachable_a.difference(&reachable_b).cloned().collect();
+        diff.reachable_only_from_b = reachable_b.difference(&reachable_a).cloned().collect();
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == RoutingTableDumpDiff::default()
+    }
+
+    fn edge_nonces(dump: &RoutingTableDump) -> BTreeMap<(PeerId, PeerId), u64> {
+        dump.edges
+            .iter()
+            .map(|edge| ((edge.peer0.clone(), edge.peer1.clone()), edge.nonce))
+            .collect()
+    }
+
+    /// Peers a node has a route to. The node itself counts as reachable, so that two nodes
+    /// reaching each other don't show up as a difference.
+    fn reachable(dump: &RoutingTableDump) -> BTreeSet<PeerId> {
+        dump.peer_forwarding
+            .iter()
+            .filter(|(_, next_hops)| !next_hops.is_empty())
+            .map(|(peer_id, _)| peer_id.clone())
+            .chain(std::iter::once(dump.peer_id.clone()))
+            .collect()
+    }
+}
+
+impl fmt::Display for RoutingTableDumpDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Edges only in A: {}", self.edges_only_in_a.len())?;
+        for (peer0, peer1) in self.edges_only_in_a.iter() {
+            writeln!(f, "  {} - {}", peer0, peer1)?;
+        }
+        writeln!(f, "Edges only in B: {}", self.edges_only_in_b.len())?;
+        for (peer0, peer1) in self.edges_only_in_b.iter() {
+            writeln!(f, "  {} - {}", peer0, peer1)?;
+        }
+        writeln!(f, "Edge nonce mismatches: {}", self.nonce_mismatches.len())?;
+        for (peer0, peer1, nonce_a, nonce_b) in self.nonce_mismatches.iter() {
+            writeln!(f, "  {} - {}: A {}, B {}", peer0, peer1, nonce_a, nonce_b)?;
+        }
+        writeln!(f, "Peers reachable only from A: {}", self.reachable_only_from_a.len())?;
+        for peer_id in self.reachable_only_from_a.iter() {
+            writeln!(f, "  {}", peer_id)?;
+        }
+        writeln!(f, "Peers reachable only from B: {}", self.reachable_only_from_b.len())?;
+        for peer_id in self.reachable_only_from_b.iter() {
+            writeln!(f, "  {}", peer_id)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "test_features")]
+impl From<RoutingTableDump> for RoutingTableSnapshot {
+    fn from(dump: RoutingTableDump) -> Self {
+        RoutingTableSnapshot {
+            edges: dump.edges,
+            peer_last_time_reachable: dump.peer_last_time_reachable,
+            component_nonce: dump.component_nonce,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use borsh::BorshSerialize;
+
+    use crate::routing::Edge;
+    use crate::test_utils::random_peer_id;
+
+    use super::{
+        RoutingTableDump, RoutingTableDumpDiff, RoutingTableDumpFormat, ROUTING_TABLE_DUMP_VERSION,
+    };
+
+    fn dump() -> RoutingTableDump {
+        let (me, other) = (random_peer_id(), random_peer_id());
+        RoutingTableDump {
+            version: ROUTING_TABLE_DUMP_VERSION,
+            peer_id: me.clone(),
+            edges: vec![Edge::make_fake_edge(me, other.clone(), 1)],
+            peer_forwarding: vec![(other.clone(), vec![other.clone()])],
+            peer_last_time_reachable: vec![(other.clone(), 0)],
+            component_nonce: 3,
+            peer_components: vec![(random_peer_id(), 2)],
+            peer_latency_us: vec![(other, 1_500)],
+        }
+    }
+
+    #[test]
+    fn write_and_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let dump = dump();
+        for name in ["routing.bin", "routing.json"].iter() {
+            let path = dir.path().join(name);
+            dump.write_to_file(&path, RoutingTableDumpFormat::from_path(&path)).unwrap();
+            assert_eq!(RoutingTableDump::read_from_file(&path).unwrap(), dump);
+        }
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("routing.bin");
+        let dump = RoutingTableDump { version: ROUTING_TABLE_DUMP_VERSION + 1, ..dump() };
+        std::fs::write(&path, dump.try_to_vec().unwrap()).unwrap();
+        assert!(RoutingTableDump::read_from_file(&path).is_err());
+    }
+
+    #[test]
+    fn diff() {
+        let (peer0, peer1, peer2, peer3) =
+            (random_peer_id(), random_peer_id(), random_peer_id(), random_peer_id());
+        let a = RoutingTableDump {
+            version: ROUTING_TABLE_DUMP_VERSION,
+            peer_id: peer0.clone(),
+            edges: vec![
+                Edge::make_fake_edge(peer0.clone(), peer1.clone(), 1),
+                Edge::make_fake_edge(peer1.clone(), peer2.clone(), 3),
+            ],
+            peer_forwarding: vec![
+                (peer1.clone(), vec![peer1.clone()]),
+                (peer2.clone(), vec![peer1.clone()]),
+            ],
+            peer_last_time_reachable: vec![],
+            component_nonce: 0,
+            peer_components: vec![],
+            peer_latency_us: vec![],
+        };
+        let b = RoutingTableDump {
+            peer_id: peer1.clone(),
+            edges: vec![
+                Edge::make_fake_edge(peer0.clone(), peer1.clone(), 1),
+                Edge::make_fake_edge(peer1.clone(), peer2.clone(), 5),
+                Edge::make_fake_edge(peer1.clone(), peer3.clone(), 1),
+            ],
+            peer_forwarding: vec![
+                (peer0.clone(), vec![peer0.clone()]),
+                (peer3.clone(), vec![peer3.clone()]),
+            ],
+            ..a.clone()
+        };
+
+        assert!(RoutingTableDumpDiff::new(&a, &a).is_empty());
+        let diff = RoutingTableDumpDiff::new(&a, &b);
+        assert!(diff.edges_only_in_a.is_empty());
+        assert_eq!(diff.edges_only_in_b, vec![(peer1.clone(), peer3.clone())]);
+        assert_eq!(diff.nonce_mismatches, vec![(peer1, peer2.clone(), 3, 5)]);
+        assert_eq!(diff.reachable_only_from_a, vec![peer2]);
+        assert_eq!(diff.reachable_only_from_b, vec![peer3]);
+    }
+}
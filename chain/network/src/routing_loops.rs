@@ -0,0 +1,57 @@
+//! Detection of routing loops, caused by routing tables which transiently disagree on the next
+//! hop towards a target.
+//!
+//! We remember the TTL of routed messages when they were last sent to each next hop. A message
+//! about to be sent again to the same next hop with a lower TTL went around a loop back to us,
+//! while a message resent by its author reaches us with the same TTL as before.
+use cached::{Cached, SizedCache};
+
+use near_primitives::hash::CryptoHash;
+use near_primitives::network::PeerId;
+
+/// Number of (message, next hop) pairs remembered.
+const FORWARDED_MESSAGES_CACHE_SIZE: usize = 10_000;
+
+pub(crate) struct ForwardedMessages {
+    ttls: SizedCache<(CryptoHash, PeerId), u8>,
+}
+
+impl ForwardedMessages {
+    pub fn new() -> Self {
+        Self { ttls: SizedCache::with_size(FORWARDED_MESSAGES_CACHE_SIZE) }
+    }
+
+    /// Whether sending the message with `hash` and `ttl` to `next_hop` closes a loop.
+    pub fn is_loop(&mut self, hash: CryptoHash, next_hop: &PeerId, ttl: u8) -> bool {
+        self.ttls.cache_get(&(hash, next_hop.clone())).map_or(false, |&sent_ttl| ttl < sent_ttl)
+    }
+
+    /// Remember that the message with `hash` and `ttl` was sent to `next_hop`.
+    pub fn record(&mut self, hash: CryptoHash, next_hop: PeerId, ttl: u8) {
+        self.ttls.cache_set((hash, next_hop), ttl);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_primitives::hash::hash;
+
+    use super::ForwardedMessages;
+    use crate::test_utils::random_peer_id;
+
+    #[test]
+    fn detect_routing_loops() {
+        let (a, b) = (random_peer_id(), random_peer_id());
+        let msg = hash(b"msg");
+        let mut forwarded = ForwardedMessages::new();
+        assert!(!forwarded.is_loop(msg, &a, 100));
+        forwarded.record(msg, a.clone(), 100);
+
+        // Resent by its author, the message has the same TTL.
+        assert!(!forwarded.is_loop(msg, &a, 100));
+        // The message came back after going through a and other peers.
+        assert!(forwarded.is_loop(msg, &a, 97));
+        assert!(!forwarded.is_loop(msg, &b, 97));
+        assert!(!forwarded.is_loop(hash(b"other"), &a, 97));
+    }
+}
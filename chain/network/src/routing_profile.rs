@@ -0,0 +1,113 @@
+//! Approximate memory used by the routing table and the `RoutingTableActor`, and the time the
+//! actor recently spent handling each kind of message. Served by `GetRoutingProfile`, to diagnose
+//! capacity issues on production nodes where heap profilers can't run.
+//!
+//! Sizes are the inline size of entries times the capacity of their containers, plus the heap
+//! buffers of nested vectors. Allocator and hash table overhead are ignored.
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::mem::size_of;
+use std::time::Duration;
+
+use serde::Serialize;
+
+/// Number of recent handling times kept for each kind of message.
+pub const HANDLER_TIMING_SAMPLES: usize = 100;
+
+/// Bytes used by the entries of `map`.
+pub(crate) fn hash_map_bytes<K, V>(map: &HashMap<K, V>) -> usize {
+    map.capacity() * (size_of::<K>() + size_of::<V>())
+}
+
+/// Bytes used by the entries of `map`.
+pub(crate) fn btree_map_bytes<K, V>(map: &BTreeMap<K, V>) -> usize {
+    map.len() * (size_of::<K>() + size_of::<V>())
+}
+
+/// Bytes used by the buffer of `vec`, including its spare capacity.
+#[allow(clippy::ptr_arg)]
+pub(crate) fn vec_bytes<T>(vec: &Vec<T>) -> usize {
+    vec.capacity() * size_of::<T>()
+}
+
+/// Recent handling times of one kind of message.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct HandlerTimingView {
+    pub message: &'static str,
+    /// Number of messages handled since start.
+    pub handled: u64,
+    /// Over the last `HANDLER_TIMING_SAMPLES` messages.
+    pub mean_us: u64,
+    pub max_us: u64,
+}
+
+/// Handling times of the last `HANDLER_TIMING_SAMPLES` messages of each kind.
+#[derive(Default)]
+pub(crate) struct HandlerTimings {
+    timings: BTreeMap<&'static str, (u64, VecDeque<Duration>)>,
+}
+
+impl HandlerTimings {
+    pub fn record(&mut self, message: &'static str, elapsed: Duration) {
+        let (handled, samples) = self.timings.entry(message).or_default();
+        *handled += 1;
+        if samples.len() == HANDLER_TIMING_SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back(elapsed);
+    }
+
+    pub fn views(&self) -> Vec<HandlerTimingView> {
+        self.timings
+            .iter()
+            .map(|(message, (handled, samples))| {
+                let total: Duration = samples.iter().sum();
+                HandlerTimingView {
+                    message: *message,
+                    handled: *handled,
+                    mean_us: (total / samples.len() as u32).as_micros() as u64,
+                    max_us: samples.iter().max().map_or(0, |max| max.as_micros() as u64),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Self-report of the routing table and its actor.
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct RoutingProfile {
+    /// Approximate bytes used by each data structure.
+    pub memory_bytes: BTreeMap<&'static str, usize>,
+    /// Recent handling times of the messages of the `RoutingTableActor`, by kind.
+    pub handler_timings: Vec<HandlerTimingView>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{HandlerTimingView, HandlerTimings, HANDLER_TIMING_SAMPLES};
+
+    #[test]
+    fn recent_handler_timings() {
+        let mut timings = HandlerTimings::default();
+        timings.record("AddEdges", Duration::from_millis(50));
+        for _ in 0..HANDLER_TIMING_SAMPLES {
+            timings.record("AddEdges", Duration::from_micros(10));
+        }
+        timings.record("RemovePeer", Duration::from_micros(4));
+        timings.record("RemovePeer", Duration::from_micros(8));
+        assert_eq!(
+            timings.views(),
+            vec![
+                // The slow message is out of the window.
+                HandlerTimingView {
+                    message: "AddEdges",
+                    handled: HANDLER_TIMING_SAMPLES as u64 + 1,
+                    mean_us: 10,
+                    max_us: 10
+                },
+                HandlerTimingView { message: "RemovePeer", handled: 2, mean_us: 6, max_us: 8 },
+            ]
+        );
+    }
+}
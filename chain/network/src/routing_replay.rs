@@ -0,0 +1,139 @@
+//! Replay of recorded edge updates against the routing table, used to turn routing bugs seen in
+//! production into regression tests.
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use near_primitives::network::{AnnounceAccount, PeerId};
+use near_store::test_utils::create_test_store;
+
+use crate::routing::{Edge, ProcessEdgeResult, RoutingTable};
+use crate::routing_dump::RoutingTableDump;
+use crate::RoutingTableActor;
+
+/// Version of `RoutingEventLog`. Must be increased on every incompatible change.
+pub const ROUTING_EVENT_LOG_VERSION: u32 = 1;
+
+/// Routing table update, as handled by `PeerManagerActor` and forwarded to `RoutingTableActor`.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, Eq)]
+pub enum RoutingEvent {
+    /// Edges which passed signature verification.
+    AddVerifiedEdges(Vec<Edge>),
+    RemoveEdges(Vec<Edge>),
+    /// Account announcements which passed signature verification.
+    AddAccounts(Vec<AnnounceAccount>),
+}
+
+/// Sequence of edge updates received by a node.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RoutingEventLog {
+    pub version: u32,
+    /// Node which received the updates.
+    pub peer_id: PeerId,
+    pub events: Vec<RoutingEvent>,
+}
+
+impl RoutingEventLog {
+    pub fn new(peer_id: PeerId) -> Self {
+        Self { version: ROUTING_EVENT_LOG_VERSION, peer_id, events: vec![] }
+    }
+
+    /// Log which rebuilds the graph of `dump`. More events can be appended to it.
+    pub fn from_dump(dump: &RoutingTableDump) -> Self {
+        let mut log = Self::new(dump.peer_id.clone());
+        log.events.push(RoutingEvent::AddVerifiedEdges(dump.edges.clone()));
+        log
+    }
+
+    pub fn write_to_file(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.try_to_vec()?)
+    }
+
+    pub fn read_from_file(path: &Path) -> io::Result<Self> {
+        let log = Self::try_from_slice(&fs::read(path)?)?;
+        if log.version != ROUTING_EVENT_LOG_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "unsupported routing event log version {}, expected {}",
+                    log.version, ROUTING_EVENT_LOG_VERSION
+                ),
+            ));
+        }
+        Ok(log)
+    }
+}
+
+/// Routing table of a node fed with recorded events.
+///
+/// Events are applied to both `RoutingTable` and `RoutingTableActor` the same way
+/// `PeerManagerActor` does, but synchronously, so the result is deterministic.
+pub struct RoutingReplay {
+    routing_table: RoutingTable,
+    routing_table_actor: RoutingTableActor,
+}
+
+impl RoutingReplay {
+    pub fn new(peer_id: PeerId) -> Self {
+        Self {
+            routing_table: RoutingTable::new(peer_id, create_test_store()),
+            routing_table_actor: RoutingTableActor::default(),
+        }
+    }
+
+    /// Replay all events of `log` on a fresh routing table.
+    pub fn from_log(log: &RoutingEventLog) -> Self {
+        let mut replay = Self::new(log.peer_id.clone());
+        for event in log.events.iter() {
+            replay.apply(event.clone());
+        }
+        replay
+    }
+
+    pub fn apply(&mut self, event: RoutingEvent) {
+        match event {
+            RoutingEvent::AddVerifiedEdges(edges) => {
+                let ProcessEdgeResult { edges, .. } = self.routing_table.process_edges(edges);
+                self.routing_table_actor.add_edges(edges);
+            }
+            RoutingEvent::RemoveEdges(edges) => {
+                self.routing_table.remove_edges(&edges);
+                self.routing_table_actor.remove_edges(&edges);
+            }
+            RoutingEvent::AddAccounts(accounts) => {
+                for account in accounts {
+                    self.routing_table.add_account(account);
+                }
+            }
+        }
+    }
+
+    /// Recompute routes and return the next hops to every reachable peer. Edges are never
+    /// pruned, so the result only depends on the replayed events.
+    pub fn peer_forwarding(&mut self) -> &HashMap<PeerId, Vec<PeerId>> {
+        self.routing_table.update(None);
+        &self.routing_table.peer_forwarding
+    }
+
+    /// Whether the edges of `RoutingTableActor` match the edges of `RoutingTable`.
+    pub fn is_consistent(&self) -> bool {
+        let actor_edges: HashMap<_, _> = self
+            .routing_table_actor
+            .edges()
+            .map(|edge| ((&edge.peer0, &edge.peer1), edge))
+            .collect();
+        actor_edges.len() == self.routing_table.edges_info.len()
+            && self
+                .routing_table
+                .edges_info
+                .iter()
+                .all(|((peer0, peer1), edge)| actor_edges.get(&(peer0, peer1)) == Some(&edge))
+    }
+
+    pub fn routing_table(&self) -> &RoutingTable {
+        &self.routing_table
+    }
+}
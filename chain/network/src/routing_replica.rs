@@ -0,0 +1,209 @@
+//! Streaming of routing events from a primary node to hot-standby replicas on the same host, so
+//! RPC front-ends can fail over to a replica without waiting for it to sync with the network.
+//!
+//! Replicas connect to the Unix socket of the primary, which sends them its edges and account
+//! announcements, then every `RoutingEvent` it applies from then on. Events are framed by their
+//! length as a little endian `u32`. Replicas which fall too far behind are disconnected, and get
+//! a fresh snapshot when they subscribe again.
+use std::fs;
+use std::io;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix::{Message, Recipient};
+use borsh::{BorshDeserialize, BorshSerialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::broadcast;
+use tracing::error;
+
+use near_primitives::network::AnnounceAccount;
+
+use crate::codec::NETWORK_MESSAGE_MAX_SIZE;
+use crate::routing::Edge;
+use crate::routing_replay::RoutingEvent;
+
+/// Number of events a replica can be behind before it's disconnected.
+const REPLICA_EVENTS_BUFFER: usize = 4096;
+/// Number of edges per event of the snapshot sent to new replicas.
+const SNAPSHOT_EDGES_BATCH: usize = 1000;
+/// How long replicas wait before subscribing again after losing the primary.
+pub(crate) const REPLICA_RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+impl Message for RoutingEvent {
+    type Result = ();
+}
+
+/// Connection of a replica to the publish socket.
+pub(crate) struct ReplicaSubscribe {
+    pub stream: UnixStream,
+}
+
+impl Message for ReplicaSubscribe {
+    type Result = ();
+}
+
+fn encode(event: &RoutingEvent) -> io::Result<Arc<Vec<u8>>> {
+    let body = event.try_to_vec()?;
+    let mut frame = Vec::with_capacity(4 + body.len());
+    frame.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&body);
+    Ok(Arc::new(frame))
+}
+
+/// Listen for replicas at `path`, replacing the socket left by a previous run.
+pub(crate) fn bind(path: &Path) -> io::Result<UnixListener> {
+    if fs::symlink_metadata(path).map_or(false, |metadata| metadata.file_type().is_socket()) {
+        fs::remove_file(path)?;
+    }
+    UnixListener::bind(path)
+}
+
+/// Events of a routing table with `edges` and `accounts`, sent to new replicas.
+pub(crate) fn snapshot(edges: Vec<Edge>, accounts: Vec<AnnounceAccount>) -> Vec<RoutingEvent> {
+    let mut events: Vec<_> = edges
+        .chunks(SNAPSHOT_EDGES_BATCH)
+        .map(|batch| RoutingEvent::AddVerifiedEdges(batch.to_vec()))
+        .collect();
+    if !accounts.is_empty() {
+        events.push(RoutingEvent::AddAccounts(accounts));
+    }
+    events
+}
+
+/// Routing events of the primary, fanned out to every subscribed replica.
+pub(crate) struct RoutingEventPublisher {
+    sender: broadcast::Sender<Arc<Vec<u8>>>,
+}
+
+impl RoutingEventPublisher {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(REPLICA_EVENTS_BUFFER);
+        Self { sender }
+    }
+
+    pub fn publish(&self, event: RoutingEvent) {
+        if self.sender.receiver_count() == 0 {
+            return;
+        }
+        match encode(&event) {
+            Ok(frame) => {
+                let _ = self.sender.send(frame);
+            }
+            Err(err) => error!(target: "network", "Failed to encode routing event: {}", err),
+        }
+    }
+
+    /// Send `snapshot` to the replica on `stream`, then the events published from now on, until
+    /// the replica disconnects, falls behind or the publisher is dropped.
+    pub fn serve(
+        &self,
+        mut stream: UnixStream,
+        snapshot: Vec<RoutingEvent>,
+    ) -> impl std::future::Future<Output = io::Result<()>> {
+        // Subscribe right away, so no event published after the snapshot is missed.
+        let mut receiver = self.sender.subscribe();
+        async move {
+            for event in snapshot.iter() {
+                stream.write_all(&encode(event)?).await?;
+            }
+            loop {
+                match receiver.recv().await {
+                    Ok(frame) => stream.write_all(&frame).await?,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::Other,
+                            format!("replica fell {} events behind", skipped),
+                        ))
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return Ok(()),
+                }
+            }
+        }
+    }
+}
+
+/// Next event sent by the primary on `stream`, `None` once it disconnects.
+async fn read_event(stream: &mut UnixStream) -> io::Result<Option<RoutingEvent>> {
+    let mut len = [0u8; 4];
+    match stream.read_exact(&mut len).await {
+        Ok(_) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+    let len = u32::from_le_bytes(len);
+    if len > NETWORK_MESSAGE_MAX_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("routing event of {} bytes is too long", len),
+        ));
+    }
+    let mut body = vec![0u8; len as usize];
+    stream.read_exact(&mut body).await?;
+    Ok(Some(RoutingEvent::try_from_slice(&body)?))
+}
+
+/// Subscribe to the primary listening at `socket` and forward its events to `recipient`, until
+/// the primary disconnects.
+pub(crate) async fn follow(socket: PathBuf, recipient: Recipient<RoutingEvent>) -> io::Result<()> {
+    let mut stream = UnixStream::connect(&socket).await?;
+    while let Some(event) = read_event(&mut stream).await? {
+        recipient
+            .send(event)
+            .await
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use near_crypto::Signature;
+    use near_primitives::network::PeerId;
+    use tokio::net::UnixStream;
+
+    use super::{read_event, snapshot, RoutingEventPublisher, SNAPSHOT_EDGES_BATCH};
+    use crate::routing::Edge;
+    use crate::routing_replay::RoutingEvent;
+
+    fn edge(nonce: u64) -> Edge {
+        Edge::new(
+            PeerId::random(),
+            PeerId::random(),
+            nonce,
+            Signature::default(),
+            Signature::default(),
+        )
+    }
+
+    #[test]
+    fn stream_routing_events() {
+        tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(
+            async {
+                let publisher = RoutingEventPublisher::new();
+                // Nobody listens yet.
+                publisher.publish(RoutingEvent::AddVerifiedEdges(vec![edge(1)]));
+
+                let edges: Vec<_> = (0..SNAPSHOT_EDGES_BATCH as u64 + 1).map(edge).collect();
+                let snapshot = snapshot(edges.clone(), vec![]);
+                assert_eq!(snapshot.len(), 2);
+                let (primary, mut replica) = UnixStream::pair().unwrap();
+                let serve = tokio::spawn(publisher.serve(primary, snapshot.clone()));
+                let removed = RoutingEvent::RemoveEdges(vec![edges[0].clone()]);
+                publisher.publish(removed.clone());
+
+                for event in snapshot {
+                    assert_eq!(read_event(&mut replica).await.unwrap(), Some(event));
+                }
+                assert_eq!(read_event(&mut replica).await.unwrap(), Some(removed));
+
+                // The replica is done once the primary stops.
+                drop(publisher);
+                serve.await.unwrap().unwrap();
+                assert_eq!(read_event(&mut replica).await.unwrap(), None);
+            },
+        );
+    }
+}
@@ -0,0 +1,374 @@
+//! Deterministic simulator of the routing tables of `N` nodes.
+//!
+//! Unlike `simulation`, which only models the edge gossip, every simulated node runs the
+//! `RoutingTable` and `RoutingTableActor` of a real node, driven synchronously the way
+//! `PeerManagerActor` drives them: nodes sync their routing tables when they connect and
+//! broadcast the edges they learn to their neighbors on every step. Connections follow a
+//! scripted `Schedule` of joins, leaves and partitions, and time is simulated with a `FakeClock`
+//! shared by all nodes, advanced at the start of every step. After every step the `peer_forwarding` of every node is
+//! compared with the shortest paths over the connections active at that time.
+//!
+//! ```
+//! use std::time::Duration;
+//! use near_network::routing_simulator::{RoutingSimulator, Schedule, ScheduledEvent};
+//! use near_network::simulation::Topology;
+//!
+//! let schedule = Schedule::new()
+//!     .at(Duration::from_secs(1), ScheduledEvent::Partition(vec![0, 1]))
+//!     .at(Duration::from_secs(2), ScheduledEvent::Heal);
+//! let report = RoutingSimulator::new(Topology::ring(6), schedule).run_for(Duration::from_secs(3));
+//! assert!(report.converged_at_end);
+//! ```
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::time::Duration;
+
+use near_primitives::network::PeerId;
+use near_store::test_utils::create_test_store;
+
+use crate::routing::{Edge, ProcessEdgeResult, RoutingTable};
+use crate::simulation::Topology;
+use crate::test_utils::peer_id_from_seed;
+use crate::time::{Clock, FakeClock};
+use crate::RoutingTableActor;
+
+/// Default duration of a single simulation step.
+const DEFAULT_TICK: Duration = Duration::from_millis(100);
+
+/// Change of the connections between simulated nodes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScheduledEvent {
+    Connect(usize, usize),
+    Disconnect(usize, usize),
+    /// Node connects to each of the given nodes.
+    Join {
+        node: usize,
+        peers: Vec<usize>,
+    },
+    /// Node drops all its connections.
+    Leave(usize),
+    /// Connections between the given nodes and the other nodes are dropped.
+    Partition(Vec<usize>),
+    /// Connections dropped by partitions are established again.
+    Heal,
+}
+
+/// Events to apply at given times since the start of the simulation.
+#[derive(Clone, Debug, Default)]
+pub struct Schedule {
+    events: Vec<(Duration, ScheduledEvent)>,
+}
+
+impl Schedule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply `event` at `time`. Events at the same time are applied in the order they were added.
+    pub fn at(mut self, time: Duration, event: ScheduledEvent) -> Self {
+        let index = self.events.partition_point(|(other, _)| *other <= time);
+        self.events.insert(index, (time, event));
+        self
+    }
+}
+
+/// Convergence of the routing tables after a change of the connections.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Convergence {
+    /// When the connections changed, zero for the initial topology.
+    pub time: Duration,
+    /// How long after the change every `peer_forwarding` was correct. `None` if that didn't
+    /// happen before the next change or the end of the simulation.
+    pub after: Option<Duration>,
+}
+
+/// Result of a simulation run.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SimulatorReport {
+    /// Convergence after the initial topology, then after every step with scheduled events.
+    pub convergence: Vec<Convergence>,
+    /// Whether every `peer_forwarding` was correct at the end of the run.
+    pub converged_at_end: bool,
+    /// Number of simulation steps executed.
+    pub ticks: u64,
+}
+
+struct SimulatedNode {
+    routing_table: RoutingTable,
+    routing_table_actor: RoutingTableActor,
+    /// Edges learned since the last broadcast.
+    pending: Vec<Edge>,
+}
+
+impl SimulatedNode {
+    fn new(peer_id: PeerId, clock: Clock) -> Self {
+        Self {
+            routing_table: RoutingTable::new(peer_id, create_test_store())
+                .with_clock(clock.clone()),
+            routing_table_actor: RoutingTableActor::default().with_clock(clock),
+            pending: vec![],
+        }
+    }
+
+    fn add_edges(&mut self, edges: Vec<Edge>) {
+        let ProcessEdgeResult { edges, .. } = self.routing_table.process_edges(edges);
+        self.routing_table_actor.add_edges(edges.clone());
+        self.pending.extend(edges);
+    }
+}
+
+/// Deterministic simulation of the routing tables of the nodes of a `Topology`.
+pub struct RoutingSimulator {
+    topology: Topology,
+    schedule: Schedule,
+    tick: Duration,
+}
+
+impl RoutingSimulator {
+    pub fn new(topology: Topology, schedule: Schedule) -> Self {
+        Self { topology, schedule, tick: DEFAULT_TICK }
+    }
+
+    /// Duration of a single step. Every step each node broadcasts the edges learned on the
+    /// previous step to its neighbors.
+    pub fn with_tick(mut self, tick: Duration) -> Self {
+        assert!(tick > Duration::ZERO);
+        self.tick = tick;
+        self
+    }
+
+    /// Run the simulation for `duration` of simulated time.
+    pub fn run_for(self, duration: Duration) -> SimulatorReport {
+        let fake = FakeClock::new();
+        let start = fake.now();
+        let mut state = SimulationState::new(self.topology.num_nodes(), fake.clock());
+        for &(u, v) in self.topology.edges() {
+            state.connect(u, v);
+        }
+
+        let mut report = SimulatorReport::default();
+        let mut last_change = Duration::ZERO;
+        report.convergence.push(Convergence { time: last_change, after: None });
+        let mut events = self.schedule.events.into_iter().peekable();
+        let total_ticks = (duration.as_nanos() / self.tick.as_nanos()) as u64;
+        for tick in 1..=total_ticks {
+            let now = self.tick * tick as u32;
+            fake.advance_until(start + now);
+            let mut changed = false;
+            while let Some((_, event)) = events.next_if(|(time, _)| *time <= now) {
+                state.apply(event);
+                changed = true;
+            }
+            if changed {
+                last_change = now;
+                report.convergence.push(Convergence { time: now, after: None });
+            }
+            state.gossip();
+            let converged = state.is_converged();
+            let convergence = report.convergence.last_mut().unwrap();
+            if converged && convergence.after.is_none() {
+                convergence.after = Some(now - last_change);
+            }
+            report.converged_at_end = converged;
+            report.ticks = tick;
+        }
+        report
+    }
+}
+
+struct SimulationState {
+    peer_ids: Vec<PeerId>,
+    nodes: Vec<SimulatedNode>,
+    /// Nonce of the last edge of every pair of nodes which were ever connected. Odd if connected.
+    nonces: BTreeMap<(usize, usize), u64>,
+    /// Connections dropped by partitions.
+    partitioned: Vec<(usize, usize)>,
+}
+
+impl SimulationState {
+    fn new(num_nodes: usize, clock: Clock) -> Self {
+        let peer_ids: Vec<PeerId> =
+            (0..num_nodes).map(|i| peer_id_from_seed(&format!("simulator{}", i))).collect();
+        let nodes = peer_ids
+            .iter()
+            .map(|peer_id| SimulatedNode::new(peer_id.clone(), clock.clone()))
+            .collect();
+        Self { peer_ids, nodes, nonces: BTreeMap::new(), partitioned: vec![] }
+    }
+
+    fn is_connected(&self, u: usize, v: usize) -> bool {
+        self.nonces.get(&(u.min(v), u.max(v))).map_or(false, |nonce| nonce % 2 == 1)
+    }
+
+    fn neighbors(&self, u: usize) -> Vec<usize> {
+        (0..self.nodes.len()).filter(|&v| v != u && self.is_connected(u, v)).collect()
+    }
+
+    /// Change the state of the connection between `u` and `v`. Both nodes learn the new edge.
+    fn set_connected(&mut self, u: usize, v: usize, connected: bool) -> bool {
+        assert!(u != v && u < self.nodes.len() && v < self.nodes.len());
+        if self.is_connected(u, v) == connected {
+            return false;
+        }
+        let nonce = self.nonces.entry((u.min(v), u.max(v))).or_insert(0);
+        *nonce += 1;
+        let (peer0, peer1) = Edge::key(self.peer_ids[u].clone(), self.peer_ids[v].clone());
+        let edge = Edge::make_fake_edge(peer0, peer1, *nonce);
+        self.nodes[u].add_edges(vec![edge.clone()]);
+        self.nodes[v].add_edges(vec![edge]);
+        true
+    }
+
+    /// Connect `u` and `v`, which then send each other their whole routing table.
+    fn connect(&mut self, u: usize, v: usize) {
+        if !self.set_connected(u, v, true) {
+            return;
+        }
+        let edges_of = |node: &SimulatedNode| node.routing_table_actor.edges().cloned().collect();
+        let (edges_u, edges_v): (Vec<Edge>, Vec<Edge>) =
+            (edges_of(&self.nodes[u]), edges_of(&self.nodes[v]));
+        self.nodes[u].add_edges(edges_v);
+        self.nodes[v].add_edges(edges_u);
+    }
+
+    fn apply(&mut self, event: ScheduledEvent) {
+        match event {
+            ScheduledEvent::Connect(u, v) => self.connect(u, v),
+            ScheduledEvent::Disconnect(u, v) => {
+                self.set_connected(u, v, false);
+            }
+            ScheduledEvent::Join { node, peers } => {
+                for peer in peers {
+                    self.connect(node, peer);
+                }
+            }
+            ScheduledEvent::Leave(node) => {
+                for peer in self.neighbors(node) {
+                    self.set_connected(node, peer, false);
+                }
+            }
+            ScheduledEvent::Partition(side) => {
+                let side: HashSet<usize> = side.into_iter().collect();
+                for &u in side.iter() {
+                    for v in self.neighbors(u) {
+                        if !side.contains(&v) && self.set_connected(u, v, false) {
+                            self.partitioned.push((u, v));
+                        }
+                    }
+                }
+            }
+            ScheduledEvent::Heal => {
+                for (u, v) in std::mem::take(&mut self.partitioned) {
+                    self.connect(u, v);
+                }
+            }
+        }
+    }
+
+    /// Every node broadcasts the edges it learned since the previous step to its neighbors,
+    /// then recomputes its routes.
+    fn gossip(&mut self) {
+        let mut messages = vec![];
+        for u in 0..self.nodes.len() {
+            let pending = std::mem::take(&mut self.nodes[u].pending);
+            if !pending.is_empty() {
+                messages.extend(self.neighbors(u).into_iter().map(|v| (v, pending.clone())));
+            }
+        }
+        for (v, edges) in messages {
+            self.nodes[v].add_edges(edges);
+        }
+        for node in self.nodes.iter_mut() {
+            node.routing_table.update(None);
+        }
+    }
+
+    /// Distance of every node to `target` over the active connections, `None` if unreachable.
+    fn distances_to(&self, target: usize) -> Vec<Option<usize>> {
+        let mut distance = vec![None; self.nodes.len()];
+        distance[target] = Some(0);
+        let mut queue = VecDeque::from(vec![target]);
+        while let Some(u) = queue.pop_front() {
+            for v in self.neighbors(u) {
+                if distance[v].is_none() {
+                    distance[v] = Some(distance[u].unwrap() + 1);
+                    queue.push_back(v);
+                }
+            }
+        }
+        distance
+    }
+
+    /// Whether every node routes to every node reachable from it through all the neighbors on
+    /// its shortest paths, and only to them.
+    fn is_converged(&self) -> bool {
+        let mut expected: Vec<HashMap<PeerId, HashSet<PeerId>>> =
+            vec![HashMap::new(); self.nodes.len()];
+        for target in 0..self.nodes.len() {
+            let distance = self.distances_to(target);
+            for source in (0..self.nodes.len()).filter(|&source| source != target) {
+                let hops = match distance[source] {
+                    Some(hops) => hops,
+                    None => continue,
+                };
+                let next_hops = self
+                    .neighbors(source)
+                    .into_iter()
+                    .filter(|&v| distance[v] == Some(hops - 1))
+                    .map(|v| self.peer_ids[v].clone())
+                    .collect();
+                expected[source].insert(self.peer_ids[target].clone(), next_hops);
+            }
+        }
+        self.nodes.iter().zip(expected.iter()).all(|(node, expected)| {
+            let actual: HashMap<PeerId, HashSet<PeerId>> = node
+                .routing_table
+                .peer_forwarding
+                .iter()
+                .map(|(target, next_hops)| (target.clone(), next_hops.iter().cloned().collect()))
+                .collect();
+            actual == *expected
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{RoutingSimulator, Schedule, ScheduledEvent};
+    use crate::simulation::Topology;
+
+    #[test]
+    fn partition_and_heal() {
+        let schedule = Schedule::new()
+            .at(Duration::from_secs(1), ScheduledEvent::Partition(vec![0, 1, 2]))
+            .at(Duration::from_secs(2), ScheduledEvent::Heal)
+            .at(Duration::from_secs(3), ScheduledEvent::Leave(3))
+            .at(Duration::from_secs(4), ScheduledEvent::Join { node: 3, peers: vec![0] });
+        let run = || {
+            RoutingSimulator::new(Topology::ring(6), schedule.clone())
+                .run_for(Duration::from_secs(5))
+        };
+        let report = run();
+        assert_eq!(report.ticks, 50);
+        assert!(report.converged_at_end);
+        let times: Vec<_> = report.convergence.iter().map(|convergence| convergence.time).collect();
+        assert_eq!(times, (0..5).map(Duration::from_secs).collect::<Vec<_>>());
+        // Edges travel one hop per step, the ring of 6 nodes has a diameter of 3.
+        for convergence in report.convergence.iter() {
+            assert!(convergence.after.unwrap() <= Duration::from_millis(400), "{:?}", report);
+        }
+        assert_eq!(run(), report);
+    }
+
+    #[test]
+    fn unreachable_nodes_have_no_routes() {
+        let schedule =
+            Schedule::new().at(Duration::from_millis(500), ScheduledEvent::Disconnect(1, 2));
+        let report = RoutingSimulator::new(Topology::line(4), schedule)
+            .with_tick(Duration::from_millis(50))
+            .run_for(Duration::from_secs(1));
+        assert!(report.converged_at_end);
+        assert_eq!(report.convergence.len(), 2);
+    }
+}
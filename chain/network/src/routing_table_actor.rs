@@ -1,39 +1,64 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io;
+use std::ops::Bound;
+use std::path::PathBuf;
 
 use actix::dev::MessageResponse;
 use actix::{Actor, Handler, Message, SyncContext, System};
-#[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
-use tracing::error;
+use tracing::field::Empty;
+use tracing::{debug, debug_span, error, Span};
 
 use near_performance_metrics_macros::perf;
 use near_primitives::network::PeerId;
 
-#[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
-use crate::ibf::{Ibf, IbfBox};
-#[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
+use crate::debug::EdgeView;
+use crate::delta_sync::{EdgeChangeLog, SyncWatermark};
+use crate::ibf::{Ibf, IbfBox, IbfHasherKind};
 use crate::ibf_peer_set::IbfPeerSet;
-#[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
 use crate::ibf_set::IbfSet;
-use crate::routing::Edge;
-#[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
+use crate::routing::{Edge, EdgeType, RouteExplanation};
 use crate::routing::{SimpleEdge, ValidIBFLevel, MIN_IBF_LEVEL};
+use crate::routing_dump::{RoutingTableDump, RoutingTableDumpFormat};
+use crate::routing_profile::{btree_map_bytes, HandlerTimings, RoutingProfile};
+use crate::time::Clock;
 use crate::types::StopMsg;
-#[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
 use crate::types::{PartialSync, RoutingState, RoutingVersion2};
 
+/// Number of edges in a page of `RequestRoutingTablePage`.
+pub const ROUTING_TABLE_PAGE_SIZE: usize = 10_000;
+/// Number of characters of peer ids recorded in tracing spans.
+const SHORT_PEER_ID_LEN: usize = 8;
+
+/// Whether peers syncing routing tables should exchange all their edges instead of using IBF.
+/// `their_edges` is the edge count the peer sent when initiating the sync. If either side knows
+/// few edges, the IBF overhead isn't worth it: the tables are tiny, or one side has to learn
+/// almost all edges of the other, which IBF can't recover anyway.
+pub(crate) fn prefer_full_sync(their_edges: u64, our_edges: u64, min_edges: u64) -> bool {
+    their_edges.min(our_edges) < min_edges
+}
+
 /// Actor that maintains routing table information.
 /// TODO (PIOTR, #4859) Finish moving routing table computation to new thread.
 #[derive(Default)]
 pub struct RoutingTableActor {
-    /// Data structures with all edges.
-    edges: HashMap<(PeerId, PeerId), Edge>,
+    /// Data structures with all edges, ordered by key so they can be read in pages.
+    edges: BTreeMap<(PeerId, PeerId), Edge>,
     /// Data structure used for exchanging routing tables.
-    #[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
     pub peer_ibf_set: IbfPeerSet,
+    /// Changes of `edges`, to send peers only the edges they missed, see `delta_sync`.
+    change_log: EdgeChangeLog,
+    /// Recent handling times of each kind of message, see `routing_profile`.
+    handler_timings: HandlerTimings,
+    clock: Clock,
 }
 
-#[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
 impl RoutingTableActor {
+    /// Read the time from `clock` instead of the system clock.
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
     pub fn split_edges_for_peer(
         &self,
         peer_id: &PeerId,
@@ -43,6 +68,98 @@ impl RoutingTableActor {
     }
 }
 
+impl RoutingTableActor {
+    pub(crate) fn add_edges(&mut self, edges: Vec<Edge>) {
+        for edge in edges {
+            {
+                let se = edge.to_simple_edge();
+                self.peer_ibf_set.add_edge(&se);
+            }
+            let key = (edge.peer0.clone(), edge.peer1.clone());
+            self.change_log.record_change(key.clone());
+            self.edges.insert(key, edge);
+        }
+    }
+
+    pub(crate) fn remove_edges(&mut self, edges: &[Edge]) {
+        for edge in edges.iter() {
+            self.peer_ibf_set.remove_edge(&edge.to_simple_edge());
+
+            let key = (edge.peer0.clone(), edge.peer1.clone());
+            self.change_log.record_removal(&key);
+            self.edges.remove(&key);
+        }
+    }
+
+    pub(crate) fn edges(&self) -> impl Iterator<Item = &Edge> {
+        self.edges.values()
+    }
+
+    /// Number of hops from `source` to `target` over the added edges, or `None` if it is not
+    /// reachable.
+    pub(crate) fn distance(&self, source: &PeerId, target: &PeerId) -> Option<u32> {
+        let mut neighbors: HashMap<&PeerId, Vec<&PeerId>> = HashMap::new();
+        for edge in self.edges.values().filter(|edge| edge.edge_type() == EdgeType::Added) {
+            neighbors.entry(&edge.peer0).or_default().push(&edge.peer1);
+            neighbors.entry(&edge.peer1).or_default().push(&edge.peer0);
+        }
+        let mut distances: HashMap<&PeerId, u32> = HashMap::new();
+        let mut queue = VecDeque::new();
+        distances.insert(source, 0);
+        queue.push_back(source);
+        while let Some(peer_id) = queue.pop_front() {
+            let distance = distances[peer_id];
+            if peer_id == target {
+                return Some(distance);
+            }
+            for &next in neighbors.get(peer_id).into_iter().flatten() {
+                if !distances.contains_key(next) {
+                    distances.insert(next, distance + 1);
+                    queue.push_back(next);
+                }
+            }
+        }
+        None
+    }
+
+    /// Up to `limit` edges whose key comes after `after`, and the key to request the next page
+    /// with. There is no next page if fewer than `limit` edges are returned.
+    pub(crate) fn edges_page(
+        &self,
+        after: Option<&(PeerId, PeerId)>,
+        limit: usize,
+    ) -> (Vec<Edge>, Option<(PeerId, PeerId)>) {
+        let start = after.map_or(Bound::Unbounded, Bound::Excluded);
+        let edges: Vec<Edge> = self
+            .edges
+            .range((start, Bound::Unbounded))
+            .take(limit)
+            .map(|(_, edge)| edge.clone())
+            .collect();
+        let next = if edges.len() == limit {
+            edges.last().map(|edge| (edge.peer0.clone(), edge.peer1.clone()))
+        } else {
+            None
+        };
+        (edges, next)
+    }
+
+    /// Approximate memory used by the actor and its recent handling times.
+    pub(crate) fn profile(&self) -> RoutingProfile {
+        let mut memory_bytes = BTreeMap::new();
+        memory_bytes.insert("routing_table_actor.edges", btree_map_bytes(&self.edges));
+        memory_bytes.insert("routing_table_actor.ibf_sets", self.peer_ibf_set.memory_bytes());
+        memory_bytes.insert("routing_table_actor.change_log", self.change_log.memory_bytes());
+        RoutingProfile { memory_bytes, handler_timings: self.handler_timings.views() }
+    }
+
+    /// Edges changed since `watermark`, or `None` if it is missing or stale.
+    pub(crate) fn edges_since(&self, watermark: Option<&SyncWatermark>) -> Option<Vec<Edge>> {
+        let keys = self.change_log.changed_since(watermark?, self.edges.len())?;
+        Some(keys.iter().filter_map(|key| self.edges.get(key).cloned()).collect())
+    }
+}
+
 impl Handler<StopMsg> for RoutingTableActor {
     type Result = ();
     fn handle(&mut self, _: StopMsg, _ctx: &mut Self::Context) -> Self::Result {
@@ -54,19 +171,47 @@ impl Actor for RoutingTableActor {
     type Context = SyncContext<Self>;
 }
 
-#[derive(Debug)]
+#[derive(Debug, strum::AsStaticStr)]
 pub enum RoutingTableMessages {
     AddEdges(Vec<Edge>),
     RemoveEdges(Vec<Edge>),
+    /// Request all known edges at once. Prefer `RequestRoutingTablePage` for large tables.
     RequestRoutingTable,
-    #[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
-    AddPeerIfMissing(PeerId, Option<u64>),
-    #[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
+    /// Request a page of at most `limit` known edges, starting after the key `after`. Edges
+    /// added or removed while reading pages may or may not be returned.
+    RequestRoutingTablePage {
+        after: Option<(PeerId, PeerId)>,
+        limit: usize,
+    },
+    /// Request the edges changed since the watermark a peer received from us, see
+    /// `delta_sync`.
+    RequestDelta(Option<SyncWatermark>),
+    /// Request all known edges in the form served by the debug endpoint.
+    GetDebugInfo,
+    /// Request the approximate memory used by the actor and its recent handling times.
+    GetProfile,
+    /// Write `dump` of the routing table to `path`, with the edges known here. Copying the
+    /// edges and file I/O are done here, so they don't block the peer manager.
+    DumpSnapshot {
+        dump: Box<RoutingTableDump>,
+        path: PathBuf,
+        format: RoutingTableDumpFormat,
+    },
+    /// Replace all known edges, used to restore a `RoutingTableSnapshot`.
+    #[cfg(feature = "test_features")]
+    ReplaceEdges(Vec<Edge>),
+    /// Fill in the distance from `source` to the target of `explanation`, whose next hops were
+    /// explained by the peer manager, see `RoutingTable::explain_next_hops`.
+    ExplainRoute {
+        source: PeerId,
+        explanation: Box<RouteExplanation>,
+    },
+    AddPeerIfMissing(PeerId, Option<u64>, IbfHasherKind),
     RemovePeer(PeerId),
-    #[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
     ProcessIbfMessage {
         peer_id: PeerId,
         ibf_msg: RoutingVersion2,
+        hasher_kind: IbfHasherKind,
     },
 }
 
@@ -74,23 +219,124 @@ impl Message for RoutingTableMessages {
     type Result = RoutingTableMessagesResponse;
 }
 
+/// First characters of the base58 encoded key of `peer_id`, enough to tell peers apart in
+/// traces.
+fn short_peer_id(peer_id: &PeerId) -> String {
+    let peer_id = peer_id.to_string();
+    let key = peer_id.split_once(':').map_or(peer_id.as_str(), |(_, key)| key);
+    key.chars().take(SHORT_PEER_ID_LEN).collect()
+}
+
+impl RoutingTableMessages {
+    /// Span the handling of this message is traced in, named after the variant. Spans are at
+    /// debug level, so they cost nothing unless the `network` target is logged at debug or
+    /// trace level. `elapsed_us` is recorded once the message is handled.
+    fn span(&self) -> Span {
+        match self {
+            RoutingTableMessages::AddEdges(edges) => {
+                debug_span!(target: "network", "AddEdges", edges = edges.len(), elapsed_us = Empty)
+            }
+            RoutingTableMessages::RemoveEdges(edges) => {
+                debug_span!(target: "network", "RemoveEdges", edges = edges.len(), elapsed_us = Empty)
+            }
+            RoutingTableMessages::RequestRoutingTable => {
+                debug_span!(target: "network", "RequestRoutingTable", elapsed_us = Empty)
+            }
+            RoutingTableMessages::RequestRoutingTablePage { after, limit } => debug_span!(
+                target: "network",
+                "RequestRoutingTablePage",
+                first_page = after.is_none(),
+                limit = *limit,
+                elapsed_us = Empty
+            ),
+            RoutingTableMessages::RequestDelta(watermark) => debug_span!(
+                target: "network",
+                "RequestDelta",
+                watermark = ?watermark,
+                elapsed_us = Empty
+            ),
+            RoutingTableMessages::GetDebugInfo => {
+                debug_span!(target: "network", "GetDebugInfo", elapsed_us = Empty)
+            }
+            RoutingTableMessages::GetProfile => {
+                debug_span!(target: "network", "GetProfile", elapsed_us = Empty)
+            }
+            RoutingTableMessages::DumpSnapshot { path, format, .. } => debug_span!(
+                target: "network",
+                "DumpSnapshot",
+                path = %path.display(),
+                format = ?format,
+                elapsed_us = Empty
+            ),
+            #[cfg(feature = "test_features")]
+            RoutingTableMessages::ReplaceEdges(edges) => {
+                debug_span!(target: "network", "ReplaceEdges", edges = edges.len(), elapsed_us = Empty)
+            }
+            RoutingTableMessages::ExplainRoute { explanation, .. } => debug_span!(
+                target: "network",
+                "ExplainRoute",
+                target_peer_id = %short_peer_id(&explanation.target),
+                elapsed_us = Empty
+            ),
+            RoutingTableMessages::AddPeerIfMissing(peer_id, _, hasher_kind) => debug_span!(
+                target: "network",
+                "AddPeerIfMissing",
+                peer_id = %short_peer_id(peer_id),
+                hasher_kind = ?hasher_kind,
+                elapsed_us = Empty
+            ),
+            RoutingTableMessages::RemovePeer(peer_id) => debug_span!(
+                target: "network",
+                "RemovePeer",
+                peer_id = %short_peer_id(peer_id),
+                elapsed_us = Empty
+            ),
+            RoutingTableMessages::ProcessIbfMessage { peer_id, ibf_msg, .. } => debug_span!(
+                target: "network",
+                "ProcessIbfMessage",
+                peer_id = %short_peer_id(peer_id),
+                edges = ibf_msg.edges.len(),
+                known_edges = ibf_msg.known_edges,
+                elapsed_us = Empty
+            ),
+        }
+    }
+}
+
 #[derive(MessageResponse, Debug)]
 pub enum RoutingTableMessagesResponse {
-    #[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
     AddPeerResponse {
         seed: u64,
     },
     Empty,
-    #[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
     ProcessIbfMessageResponse {
         ibf_msg: Option<RoutingVersion2>,
     },
+    /// The IBF sync can't go on, the peer manager falls back to exchanging all edges within
+    /// the limits of heavy syncs.
+    ProcessIbfMessageFullSync,
     RequestRoutingTableResponse {
         edges_info: Vec<Edge>,
     },
+    RequestRoutingTablePageResponse {
+        edges: Vec<Edge>,
+        /// Key to request the next page with, `None` if this is the last page.
+        next: Option<(PeerId, PeerId)>,
+    },
+    /// Edges changed since the requested watermark, `None` if it is stale, and the watermark
+    /// to request the next delta from.
+    RequestDeltaResponse {
+        watermark: SyncWatermark,
+        edges: Option<Vec<Edge>>,
+    },
+    DumpSnapshotResponse(io::Result<()>),
+    GetDebugInfoResponse {
+        edges: Vec<EdgeView>,
+    },
+    GetProfileResponse(RoutingProfile),
+    ExplainRouteResponse(Box<RouteExplanation>),
 }
 
-#[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
 impl RoutingTableActor {
     pub fn exchange_routing_tables_using_ibf(
         &self,
@@ -102,7 +348,8 @@ impl RoutingTableActor {
     ) -> (Vec<SimpleEdge>, Vec<u64>, u64) {
         let ibf = ibf_set.get_ibf(ibf_level);
 
-        let mut new_ibf = Ibf::from_vec(ibf_vec.clone(), seed ^ (ibf_level.0 as u64));
+        let mut new_ibf =
+            Ibf::from_vec(ibf_vec.clone(), seed ^ (ibf_level.0 as u64), ibf_set.get_hasher_kind());
 
         if !new_ibf.merge(&ibf.data, seed ^ (ibf_level.0 as u64)) {
             error!(target: "network", "exchange routing tables failed with peer {}", peer_id);
@@ -121,25 +368,27 @@ impl Handler<RoutingTableMessages> for RoutingTableActor {
 
     #[perf]
     fn handle(&mut self, msg: RoutingTableMessages, _ctx: &mut Self::Context) -> Self::Result {
+        let span = msg.span();
+        let _entered = span.enter();
+        let name = strum::AsStaticRef::as_static(&msg);
+        let start = self.clock.now();
+        let response = self.handle_message(msg);
+        let elapsed = self.clock.elapsed(start);
+        span.record("elapsed_us", &(elapsed.as_micros() as u64));
+        self.handler_timings.record(name, elapsed);
+        response
+    }
+}
+
+impl RoutingTableActor {
+    fn handle_message(&mut self, msg: RoutingTableMessages) -> RoutingTableMessagesResponse {
         match msg {
             RoutingTableMessages::AddEdges(edges) => {
-                for edge in edges.iter() {
-                    #[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
-                    {
-                        let se = edge.to_simple_edge();
-                        self.peer_ibf_set.add_edge(&se);
-                    }
-                    self.edges.insert((edge.peer0.clone(), edge.peer1.clone()), edge.clone());
-                }
+                self.add_edges(edges);
                 RoutingTableMessagesResponse::Empty
             }
             RoutingTableMessages::RemoveEdges(edges) => {
-                for edge in edges.iter() {
-                    #[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
-                    self.peer_ibf_set.remove_edge(&edge.to_simple_edge());
-
-                    self.edges.remove(&(edge.peer0.clone(), edge.peer1.clone()));
-                }
+                self.remove_edges(&edges);
                 RoutingTableMessagesResponse::Empty
             }
             RoutingTableMessages::RequestRoutingTable => {
@@ -147,18 +396,54 @@ impl Handler<RoutingTableMessages> for RoutingTableActor {
                     edges_info: self.edges.iter().map(|(_k, v)| v.clone()).collect(),
                 }
             }
-            #[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
-            RoutingTableMessages::AddPeerIfMissing(peer_id, ibf_set) => {
-                let seed = self.peer_ibf_set.add_peer(peer_id.clone(), ibf_set, &mut self.edges);
+            RoutingTableMessages::RequestRoutingTablePage { after, limit } => {
+                let (edges, next) = self.edges_page(after.as_ref(), limit);
+                RoutingTableMessagesResponse::RequestRoutingTablePageResponse { edges, next }
+            }
+            RoutingTableMessages::RequestDelta(watermark) => {
+                RoutingTableMessagesResponse::RequestDeltaResponse {
+                    watermark: self.change_log.watermark(),
+                    edges: self.edges_since(watermark.as_ref()),
+                }
+            }
+            RoutingTableMessages::GetDebugInfo => {
+                RoutingTableMessagesResponse::GetDebugInfoResponse {
+                    edges: self.edges.values().map(EdgeView::from).collect(),
+                }
+            }
+            RoutingTableMessages::GetProfile => {
+                RoutingTableMessagesResponse::GetProfileResponse(self.profile())
+            }
+            RoutingTableMessages::DumpSnapshot { mut dump, path, format } => {
+                // Ordered by key like the edges of `RoutingTable::dump`.
+                dump.edges = self.edges.values().cloned().collect();
+                RoutingTableMessagesResponse::DumpSnapshotResponse(
+                    dump.write_to_file(&path, format),
+                )
+            }
+            #[cfg(feature = "test_features")]
+            RoutingTableMessages::ReplaceEdges(edges) => {
+                for edge in self.edges.values() {
+                    self.peer_ibf_set.remove_edge(&edge.to_simple_edge());
+                }
+                self.edges.clear();
+                self.change_log.reset();
+                self.add_edges(edges);
+                RoutingTableMessagesResponse::Empty
+            }
+            RoutingTableMessages::ExplainRoute { source, mut explanation } => {
+                explanation.distance = self.distance(&source, &explanation.target);
+                RoutingTableMessagesResponse::ExplainRouteResponse(explanation)
+            }
+            RoutingTableMessages::AddPeerIfMissing(peer_id, ibf_set, hasher_kind) => {
+                let seed = self.peer_ibf_set.add_peer(peer_id.clone(), ibf_set, hasher_kind);
                 RoutingTableMessagesResponse::AddPeerResponse { seed }
             }
-            #[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
             RoutingTableMessages::RemovePeer(peer_id) => {
                 self.peer_ibf_set.remove_peer(&peer_id);
                 RoutingTableMessagesResponse::Empty
             }
-            #[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
-            RoutingTableMessages::ProcessIbfMessage { peer_id, ibf_msg } => {
+            RoutingTableMessages::ProcessIbfMessage { peer_id, ibf_msg, hasher_kind } => {
                 match ibf_msg.routing_state {
                     RoutingState::PartialSync(partial_sync) => {
                         if let Some(ibf_set) = self.peer_ibf_set.get(&peer_id) {
@@ -208,27 +493,24 @@ impl Handler<RoutingTableMessages> for RoutingTableActor {
                                         }),
                                     }
                                 } else {
-                                    RoutingVersion2 {
-                                        known_edges: self.edges.len() as u64,
-                                        seed,
-                                        edges: self.edges.iter().map(|x| x.1.clone()).collect(),
-                                        routing_state: RoutingState::RequestAllEdges,
-                                    }
+                                    debug!(target: "network", "IBF sync with {} failed at the highest level", peer_id);
+                                    return RoutingTableMessagesResponse::ProcessIbfMessageFullSync;
                                 }
                             };
                             RoutingTableMessagesResponse::ProcessIbfMessageResponse {
                                 ibf_msg: Some(ibf_msg),
                             }
                         } else {
-                            error!(target: "network", "Peer not found {}", peer_id);
-                            RoutingTableMessagesResponse::Empty
+                            // We have no IBF for the peer, e.g. it was removed on a reconnect.
+                            debug!(target: "network", "No IBF for {}", peer_id);
+                            RoutingTableMessagesResponse::ProcessIbfMessageFullSync
                         }
                     }
                     RoutingState::InitializeIbf => {
                         self.peer_ibf_set.add_peer(
                             peer_id.clone(),
                             Some(ibf_msg.seed),
-                            &mut self.edges,
+                            hasher_kind,
                         );
                         if let Some(ibf_set) = self.peer_ibf_set.get(&peer_id) {
                             let seed = ibf_set.get_seed();
@@ -287,3 +569,97 @@ impl Handler<RoutingTableMessages> for RoutingTableActor {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::routing::Edge;
+    use crate::test_utils::random_peer_id;
+
+    use super::RoutingTableActor;
+
+    #[test]
+    fn edges_page() {
+        let mut actor = RoutingTableActor::default();
+        let peers: Vec<_> = (0..6).map(|_| random_peer_id()).collect();
+        let edges: Vec<_> = peers
+            .windows(2)
+            .map(|pair| {
+                let (peer0, peer1) = Edge::key(pair[0].clone(), pair[1].clone());
+                Edge::make_fake_edge(peer0, peer1, 1)
+            })
+            .collect();
+        actor.add_edges(edges.clone());
+
+        let mut read = vec![];
+        let mut after = None;
+        loop {
+            let (page, next) = actor.edges_page(after.as_ref(), 2);
+            assert!(page.len() <= 2);
+            read.extend(page);
+            match next {
+                Some(next) => after = Some(next),
+                None => break,
+            }
+        }
+        let mut expected = edges;
+        expected.sort_by(|edge0, edge1| {
+            (&edge0.peer0, &edge0.peer1).cmp(&(&edge1.peer0, &edge1.peer1))
+        });
+        assert_eq!(read, expected);
+    }
+
+    #[test]
+    fn distance() {
+        let mut actor = RoutingTableActor::default();
+        let peers: Vec<_> = (0..4).map(|_| random_peer_id()).collect();
+        let edge = |a: usize, b: usize, nonce: u64| {
+            let (peer0, peer1) = Edge::key(peers[a].clone(), peers[b].clone());
+            Edge::make_fake_edge(peer0, peer1, nonce)
+        };
+        actor.add_edges(vec![edge(0, 1, 1), edge(1, 2, 1), edge(0, 2, 1), edge(2, 3, 2)]);
+
+        assert_eq!(actor.distance(&peers[0], &peers[0]), Some(0));
+        assert_eq!(actor.distance(&peers[0], &peers[2]), Some(1));
+        // Removed edges don't count.
+        assert_eq!(actor.distance(&peers[0], &peers[3]), None);
+        actor.add_edges(vec![edge(2, 3, 3)]);
+        assert_eq!(actor.distance(&peers[0], &peers[3]), Some(2));
+    }
+
+    #[test]
+    fn no_ibf_falls_back_to_full_sync() {
+        use super::{RoutingTableMessages, RoutingTableMessagesResponse};
+        use crate::ibf::IbfHasherKind;
+        use crate::routing::MIN_IBF_LEVEL;
+        use crate::types::{PartialSync, RoutingState, RoutingVersion2};
+
+        let mut actor = RoutingTableActor::default();
+        let ibf_msg = RoutingVersion2 {
+            known_edges: 0,
+            seed: 0,
+            edges: vec![],
+            routing_state: RoutingState::PartialSync(PartialSync {
+                ibf_level: MIN_IBF_LEVEL,
+                ibf: vec![],
+            }),
+        };
+        // The edges are not sent right away, bypassing the limits of heavy syncs.
+        let response = actor.handle_message(RoutingTableMessages::ProcessIbfMessage {
+            peer_id: random_peer_id(),
+            ibf_msg,
+            hasher_kind: IbfHasherKind::default(),
+        });
+        assert!(matches!(response, RoutingTableMessagesResponse::ProcessIbfMessageFullSync));
+    }
+
+    #[test]
+    fn full_sync_below_min_edges() {
+        use super::prefer_full_sync;
+
+        assert!(prefer_full_sync(10, 20, 100));
+        // A new node learns all edges of the peer at once.
+        assert!(prefer_full_sync(0, 50_000, 100));
+        assert!(!prefer_full_sync(100, 50_000, 100));
+        assert!(!prefer_full_sync(0, 0, 0));
+    }
+}
@@ -0,0 +1,381 @@
+//! Priority classes of messages sent to a peer.
+//!
+//! While the write buffer of a connection is congested, messages are held back in per-class
+//! queues instead of being appended behind bulk traffic. High priority messages (consensus and
+//! connection control) are never held back, so they overtake everything queued.
+//!
+//! Within a class, messages are scheduled fairly across their destinations (the target of routed
+//! messages, or the peer itself) by deficit round robin, and no routed destination can have more
+//! than `DESTINATION_BYTES_BUDGET` queued. A single overloaded destination, e.g. a validator which
+//! stopped reading, can't use up the capacity of the connection.
+//!
+//! The size of each class and what to drop when it is full are set by `SendQueueConfig`. A queue
+//! which stays saturated for `max_saturation` means the peer can't keep up, and it is
+//! disconnected.
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+use near_network_primitives::types::{DropPolicy, RoutedMessageBody, SendQueueConfig};
+
+use crate::metrics;
+use crate::time::Clock;
+use crate::types::PeerMessage;
+
+/// Bytes in the write buffer of a connection above which messages are queued by priority.
+pub(crate) const SEND_QUEUE_WATERMARK: usize = 1024 * 1024;
+/// Maximum number of bytes queued for a single routed destination in each class. A larger
+/// message is still queued if nothing else is queued for its destination.
+const DESTINATION_BYTES_BUDGET: usize = 256 * 1024;
+/// Bytes a destination may send at each of its turns.
+const QUANTUM: usize = 16 * 1024;
+/// Messages held back for longer than this are counted as starved.
+const STARVATION_THRESHOLD: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SendPriority {
+    /// Block approvals, chunk parts and connection control messages. Never queued nor dropped.
+    High,
+    /// Everything which isn't high priority or bulk.
+    Normal,
+    /// Transactions, state sync and epoch sync responses.
+    Bulk,
+}
+
+impl SendPriority {
+    pub fn of(msg: &PeerMessage) -> Self {
+        match msg {
+            PeerMessage::Handshake(_)
+            | PeerMessage::HandshakeV2(_)
+            | PeerMessage::HandshakeFailure(_, _)
+            | PeerMessage::LastEdge(_)
+            | PeerMessage::RequestUpdateNonce(_)
+            | PeerMessage::ResponseUpdateNonce(_)
+            | PeerMessage::Disconnect => SendPriority::High,
+            PeerMessage::Transaction(_)
+            | PeerMessage::EpochSyncResponse(_)
+            | PeerMessage::EpochSyncFinalizationResponse(_) => SendPriority::Bulk,
+            PeerMessage::Routed(routed) => match routed.body {
+                RoutedMessageBody::BlockApproval(_)
+                | RoutedMessageBody::PartialEncodedChunk(_)
+                | RoutedMessageBody::VersionedPartialEncodedChunk(_)
+                | RoutedMessageBody::PartialEncodedChunkForward(_) => SendPriority::High,
+                RoutedMessageBody::ForwardTx(_)
+                | RoutedMessageBody::StateRequestHeader(_, _)
+                | RoutedMessageBody::StateRequestPart(_, _, _)
+                | RoutedMessageBody::StateResponse(_)
+                | RoutedMessageBody::VersionedStateResponse(_) => SendPriority::Bulk,
+                _ => SendPriority::Normal,
+            },
+            _ => SendPriority::Normal,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            SendPriority::High => "high",
+            SendPriority::Normal => "normal",
+            SendPriority::Bulk => "bulk",
+        }
+    }
+}
+
+struct Queued<T> {
+    item: T,
+    size: usize,
+    queued_at: Instant,
+}
+
+/// Messages queued for a single destination.
+struct DestinationQueue<T> {
+    items: VecDeque<Queued<T>>,
+    bytes: usize,
+    /// Bytes the destination may still send before yielding its turn.
+    deficit: usize,
+}
+
+impl<T> Default for DestinationQueue<T> {
+    fn default() -> Self {
+        Self { items: VecDeque::new(), bytes: 0, deficit: 0 }
+    }
+}
+
+/// Messages of one class, scheduled by deficit round robin across their destinations. Messages
+/// for the peer itself have no destination.
+struct FairQueue<K, T> {
+    destinations: HashMap<Option<K>, DestinationQueue<T>>,
+    /// Destinations with queued messages, the one whose turn it is first.
+    order: VecDeque<Option<K>>,
+    len: usize,
+}
+
+impl<K, T> Default for FairQueue<K, T> {
+    fn default() -> Self {
+        Self { destinations: HashMap::new(), order: VecDeque::new(), len: 0 }
+    }
+}
+
+impl<K: Hash + Eq + Clone, T> FairQueue<K, T> {
+    /// Whether `destination` can queue `size` more bytes within its budget. Messages for the peer
+    /// itself have no budget.
+    fn fits(&self, destination: &Option<K>, size: usize) -> bool {
+        destination.is_none()
+            || self
+                .destinations
+                .get(destination)
+                .map_or(true, |queue| queue.bytes + size <= DESTINATION_BYTES_BUDGET)
+    }
+
+    fn push(&mut self, destination: Option<K>, item: T, size: usize, now: Instant) {
+        let queue = match self.destinations.entry(destination) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                self.order.push_back(entry.key().clone());
+                entry.insert(DestinationQueue::default())
+            }
+        };
+        queue.items.push_back(Queued { item, size, queued_at: now });
+        queue.bytes += size;
+        self.len += 1;
+    }
+
+    /// Next message of the destination whose turn it is. A destination keeps its turn until
+    /// it sent `QUANTUM` bytes, plus whatever it didn't use of its previous turns.
+    fn pop(&mut self) -> Option<Queued<T>> {
+        loop {
+            let destination = self.order.front()?.clone();
+            let queue = self.destinations.get_mut(&destination).unwrap();
+            let size = queue.items.front().unwrap().size;
+            if queue.deficit < size {
+                queue.deficit += QUANTUM;
+                self.order.rotate_left(1);
+                continue;
+            }
+            queue.deficit -= size;
+            return Some(self.remove_front(&destination));
+        }
+    }
+
+    /// Oldest message of the destination with the most queued bytes.
+    fn pop_largest(&mut self) -> Option<Queued<T>> {
+        let destination = self
+            .destinations
+            .iter()
+            .max_by_key(|(_, queue)| queue.bytes)
+            .map(|(destination, _)| destination.clone())?;
+        Some(self.remove_front(&destination))
+    }
+
+    fn remove_front(&mut self, destination: &Option<K>) -> Queued<T> {
+        let queue = self.destinations.get_mut(destination).unwrap();
+        let queued = queue.items.pop_front().unwrap();
+        queue.bytes -= queued.size;
+        if queue.items.is_empty() {
+            self.destinations.remove(destination);
+            self.order.retain(|other| other != destination);
+        }
+        self.len -= 1;
+        queued
+    }
+}
+
+/// Messages held back while the write buffer of a connection is congested.
+pub(crate) struct SendQueue<K, T> {
+    config: SendQueueConfig,
+    normal: FairQueue<K, T>,
+    bulk: FairQueue<K, T>,
+    /// When a message was first dropped because a class was full, since the queue last drained
+    /// below half of its limits.
+    saturated_since: Option<Instant>,
+    clock: Clock,
+}
+
+impl<K, T> Default for SendQueue<K, T> {
+    fn default() -> Self {
+        Self::new(SendQueueConfig::default())
+    }
+}
+
+impl<K, T> SendQueue<K, T> {
+    pub fn new(config: SendQueueConfig) -> Self {
+        Self {
+            config,
+            normal: FairQueue::default(),
+            bulk: FairQueue::default(),
+            saturated_since: None,
+            clock: Clock::real(),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+}
+
+impl<K: Hash + Eq + Clone, T> SendQueue<K, T> {
+    pub fn is_empty(&self) -> bool {
+        self.normal.len == 0 && self.bulk.len == 0
+    }
+
+    /// Whether the queue kept dropping messages for longer than `max_saturation`, without
+    /// draining in between.
+    pub fn is_saturated_too_long(&self) -> bool {
+        self.saturated_since
+            .map_or(false, |since| self.clock.elapsed(since) > self.config.max_saturation)
+    }
+
+    /// Queue `item` of class `priority` for `destination`, none for the peer itself. Returns the
+    /// message dropped to make room, or `item` itself if its destination used up its budget. High priority messages must
+    /// be sent right away and are returned as is.
+    pub fn push(
+        &mut self,
+        priority: SendPriority,
+        destination: Option<K>,
+        size: usize,
+        item: T,
+    ) -> Option<T> {
+        let (queue, limit, policy) = match priority {
+            SendPriority::High => return Some(item),
+            SendPriority::Normal => {
+                (&mut self.normal, self.config.normal_limit, self.config.normal_drop_policy)
+            }
+            SendPriority::Bulk => {
+                (&mut self.bulk, self.config.bulk_limit, self.config.bulk_drop_policy)
+            }
+        };
+        near_metrics::inc_counter_vec(&metrics::PEER_MESSAGES_QUEUED, &[priority.as_str()]);
+        if !queue.fits(&destination, size) {
+            near_metrics::inc_counter_vec(
+                &metrics::PEER_MESSAGES_DESTINATION_BUDGET_EXCEEDED,
+                &[priority.as_str()],
+            );
+            return Some(item);
+        }
+        let now = self.clock.now();
+        let mut dropped = None;
+        if queue.len >= limit {
+            self.saturated_since.get_or_insert(now);
+            near_metrics::inc_counter_vec(
+                &metrics::PEER_MESSAGES_QUEUE_DROPPED,
+                &[priority.as_str()],
+            );
+            match policy {
+                // The destination using the most of the queue makes room.
+                DropPolicy::DropOldest => dropped = queue.pop_largest().map(|queued| queued.item),
+                DropPolicy::DropNewest => return Some(item),
+            }
+        }
+        queue.push(destination, item, size, now);
+        dropped
+    }
+
+    /// Next message to send: normal messages before bulk ones, fairly across destinations.
+    pub fn pop(&mut self) -> Option<T> {
+        let (priority, queued) = match self.normal.pop() {
+            Some(queued) => (SendPriority::Normal, queued),
+            None => (SendPriority::Bulk, self.bulk.pop()?),
+        };
+        if self.clock.elapsed(queued.queued_at) > STARVATION_THRESHOLD {
+            near_metrics::inc_counter_vec(&metrics::PEER_MESSAGES_STARVED, &[priority.as_str()]);
+        }
+        if self.normal.len <= self.config.normal_limit / 2
+            && self.bulk.len <= self.config.bulk_limit / 2
+        {
+            self.saturated_since = None;
+        }
+        Some(queued.item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use near_network_primitives::types::{DropPolicy, SendQueueConfig};
+
+    use crate::time::FakeClock;
+
+    use super::{SendPriority, SendQueue, DESTINATION_BYTES_BUDGET, QUANTUM};
+
+    #[test]
+    fn priorities_and_drop_policies() {
+        let config = SendQueueConfig {
+            normal_limit: 1_000,
+            normal_drop_policy: DropPolicy::DropOldest,
+            bulk_limit: 100,
+            bulk_drop_policy: DropPolicy::DropNewest,
+            ..Default::default()
+        };
+        let mut queue = SendQueue::new(config.clone());
+        assert_eq!(queue.push(SendPriority::High, None::<()>, 1, 0), Some(0));
+        assert!(queue.is_empty());
+
+        let bulk = 10_000..10_000 + config.bulk_limit;
+        for i in bulk.clone() {
+            assert_eq!(queue.push(SendPriority::Bulk, None::<()>, 1, i), None);
+        }
+        // A full bulk queue drops the arriving message.
+        assert_eq!(queue.push(SendPriority::Bulk, None::<()>, 1, 20_000), Some(20_000));
+        for i in 0..config.normal_limit {
+            assert_eq!(queue.push(SendPriority::Normal, None::<()>, 1, i), None);
+        }
+        // A full normal queue drops its oldest message.
+        assert_eq!(queue.push(SendPriority::Normal, None::<()>, 1, config.normal_limit), Some(0));
+
+        // Normal messages overtake bulk ones.
+        let sent: Vec<_> = std::iter::from_fn(|| queue.pop()).collect();
+        assert_eq!(sent, (1..=config.normal_limit).chain(bulk).collect::<Vec<_>>());
+        assert!(queue.is_empty());
+
+        // By default old bulk messages are dropped too.
+        let mut queue = SendQueue::default();
+        for i in 0..=SendQueueConfig::default().bulk_limit {
+            queue.push(SendPriority::Bulk, None::<()>, 1, i);
+        }
+        assert_eq!(queue.pop(), Some(1));
+    }
+
+    #[test]
+    fn saturated_queue() {
+        let config = SendQueueConfig { normal_limit: 4, ..Default::default() };
+        let fake = FakeClock::new();
+        let mut queue = SendQueue::new(config.clone()).with_clock(fake.clock());
+        for i in 0..=config.normal_limit {
+            queue.push(SendPriority::Normal, None::<()>, 1, i);
+        }
+        assert!(!queue.is_saturated_too_long());
+        fake.advance(config.max_saturation + Duration::from_secs(1));
+        assert!(queue.is_saturated_too_long());
+
+        // Draining below half of the limit ends the saturation.
+        queue.pop();
+        assert!(queue.saturated_since.is_some());
+        queue.pop();
+        assert!(!queue.is_saturated_too_long());
+    }
+
+    #[test]
+    fn destinations_scheduled_fairly_within_budget() {
+        let mut queue = SendQueue::default();
+        let budget = DESTINATION_BYTES_BUDGET / QUANTUM;
+        for i in 0..budget {
+            assert_eq!(queue.push(SendPriority::Normal, Some("busy"), QUANTUM, ("busy", i)), None);
+        }
+        // The busy destination used up its budget, other destinations still get queued.
+        let over_budget = ("busy", budget);
+        assert_eq!(
+            queue.push(SendPriority::Normal, Some("busy"), QUANTUM, over_budget),
+            Some(over_budget)
+        );
+        for i in 0..2 {
+            assert_eq!(queue.push(SendPriority::Normal, Some("idle"), QUANTUM, ("idle", i)), None);
+        }
+
+        // Destinations take turns instead of waiting behind everything queued earlier.
+        let sent: Vec<_> = std::iter::from_fn(|| queue.pop()).collect();
+        assert_eq!(&sent[..4], &[("busy", 0), ("idle", 0), ("busy", 1), ("idle", 1)]);
+        assert_eq!(sent.len(), budget + 2);
+    }
+}
@@ -0,0 +1,476 @@
+//! Deterministic routing simulator.
+//!
+//! Runs an in-memory gossip of edges between `N` nodes (each node re-broadcasts every edge it
+//! learns to all its active neighbors, the same way `PeerManagerActor::broadcast_edges` does)
+//! and reports how long it takes until every node computes a complete routing table.
+//!
+//! ```
+//! use std::time::Duration;
+//! use near_network::simulation::{Churn, Simulation, Topology};
+//!
+//! let metrics = Simulation::new(Topology::ring(8))
+//!     .with_churn(Churn::new(Duration::from_secs(1), 1))
+//!     .run_for(Duration::from_secs(10));
+//! assert!(metrics.messages_exchanged > 0);
+//! ```
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Duration;
+
+use borsh::BorshSerialize;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use near_crypto::{KeyType, SecretKey};
+use near_primitives::network::PeerId;
+
+use crate::routing::{Edge, EdgeInfo, EdgeType, Graph};
+use crate::test_utils::peer_id_from_seed;
+
+/// Default duration of a single simulation step.
+const DEFAULT_TICK: Duration = Duration::from_millis(100);
+
+/// Undirected graph of direct connections between simulated nodes.
+#[derive(Clone, Debug, Default)]
+pub struct Topology {
+    num_nodes: usize,
+    edges: Vec<(usize, usize)>,
+    /// Same as `edges`, to find duplicated connections of large topologies quickly.
+    edge_set: HashSet<(usize, usize)>,
+}
+
+impl Topology {
+    pub fn new(num_nodes: usize) -> Self {
+        Self { num_nodes, edges: vec![], edge_set: HashSet::new() }
+    }
+
+    /// Add a connection between nodes `u` and `v`. Duplicated connections are ignored.
+    pub fn add_edge(mut self, u: usize, v: usize) -> Self {
+        self.insert_edge(u, v);
+        self
+    }
+
+    /// Add a connection between nodes `u` and `v`, returns whether it's new.
+    fn insert_edge(&mut self, u: usize, v: usize) -> bool {
+        assert!(u < self.num_nodes && v < self.num_nodes && u != v);
+        let key = (u.min(v), u.max(v));
+        if !self.edge_set.insert(key) {
+            return false;
+        }
+        self.edges.push(key);
+        true
+    }
+
+    /// Nodes `0 - 1 - ... - (n - 1)`.
+    pub fn line(num_nodes: usize) -> Self {
+        (1..num_nodes).fold(Self::new(num_nodes), |topology, u| topology.add_edge(u - 1, u))
+    }
+
+    /// Line topology where the last node is also connected to the first one.
+    pub fn ring(num_nodes: usize) -> Self {
+        let topology = Self::line(num_nodes);
+        if num_nodes > 2 {
+            topology.add_edge(0, num_nodes - 1)
+        } else {
+            topology
+        }
+    }
+
+    /// Every pair of nodes is connected.
+    pub fn complete(num_nodes: usize) -> Self {
+        let mut topology = Self::new(num_nodes);
+        for u in 0..num_nodes {
+            for v in u + 1..num_nodes {
+                topology = topology.add_edge(u, v);
+            }
+        }
+        topology
+    }
+
+    /// Random graph where each pair of nodes is connected with probability `probability`
+    /// (Erdős–Rényi).
+    pub fn erdos_renyi(num_nodes: usize, probability: f64, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut topology = Self::new(num_nodes);
+        for u in 0..num_nodes {
+            for v in u + 1..num_nodes {
+                if rng.gen_bool(probability.clamp(0.0, 1.0)) {
+                    topology.insert_edge(u, v);
+                }
+            }
+        }
+        topology
+    }
+
+    /// Random graph with a power-law degree distribution, like networks where new peers prefer
+    /// connecting to well connected ones (Barabási–Albert). Starts with `edges_per_node + 1`
+    /// connected nodes, then each node connects to `edges_per_node` earlier nodes picked with
+    /// probability proportional to their degree.
+    pub fn barabasi_albert(num_nodes: usize, edges_per_node: usize, seed: u64) -> Self {
+        assert!(edges_per_node > 0);
+        let mut rng = StdRng::seed_from_u64(seed);
+        let initial = (edges_per_node + 1).min(num_nodes);
+        let mut topology = Self::complete(initial);
+        topology.num_nodes = num_nodes;
+        // Every node appears once per connection, so uniform picks are proportional to degree.
+        let mut endpoints: Vec<usize> =
+            topology.edges.iter().flat_map(|&(u, v)| vec![u, v]).collect();
+        for u in initial..num_nodes {
+            let mut targets = HashSet::new();
+            while targets.len() < edges_per_node {
+                targets.insert(endpoints[rng.gen_range(0, endpoints.len())]);
+            }
+            let mut targets: Vec<_> = targets.into_iter().collect();
+            // Iteration order of hash sets isn't deterministic.
+            targets.sort();
+            for v in targets {
+                topology.insert_edge(u, v);
+                endpoints.push(u);
+                endpoints.push(v);
+            }
+        }
+        topology
+    }
+
+    /// Nodes on a `width` by `height` grid connected to their horizontal and vertical neighbors,
+    /// like peers connecting to nearby peers, plus `shortcuts` random long distance connections.
+    /// Node `x + y * width` is at column `x` and row `y`.
+    pub fn geographic_lattice(width: usize, height: usize, shortcuts: usize, seed: u64) -> Self {
+        let num_nodes = width * height;
+        let mut topology = Self::new(num_nodes);
+        for y in 0..height {
+            for x in 0..width {
+                let u = x + y * width;
+                if x + 1 < width {
+                    topology.insert_edge(u, u + 1);
+                }
+                if y + 1 < height {
+                    topology.insert_edge(u, u + width);
+                }
+            }
+        }
+        let mut rng = StdRng::seed_from_u64(seed);
+        let max_edges = num_nodes * num_nodes.saturating_sub(1) / 2;
+        let mut added = 0;
+        while added < shortcuts && topology.edges.len() < max_edges {
+            let (u, v) = (rng.gen_range(0, num_nodes), rng.gen_range(0, num_nodes));
+            if u != v && topology.insert_edge(u, v) {
+                added += 1;
+            }
+        }
+        topology
+    }
+
+    pub fn num_nodes(&self) -> usize {
+        self.num_nodes
+    }
+
+    pub fn edges(&self) -> &[(usize, usize)] {
+        &self.edges
+    }
+
+    /// Number of connections of each node.
+    pub fn degrees(&self) -> Vec<usize> {
+        let mut degrees = vec![0; self.num_nodes];
+        for &(u, v) in self.edges.iter() {
+            degrees[u] += 1;
+            degrees[v] += 1;
+        }
+        degrees
+    }
+
+    /// Peer ids of the nodes and edges of the connections with nonce 1, signed by both nodes.
+    /// Keys are derived from `seed`, so the same topology and seed give the same edges.
+    pub fn signed_edges(&self, seed: u64) -> (Vec<PeerId>, Vec<Edge>) {
+        let keys: Vec<SecretKey> = (0..self.num_nodes)
+            .map(|i| SecretKey::from_seed(KeyType::ED25519, &format!("topology{}-{}", seed, i)))
+            .collect();
+        let peer_ids: Vec<PeerId> = keys.iter().map(|key| key.public_key().into()).collect();
+        let edges = self
+            .edges
+            .iter()
+            .map(|&(u, v)| {
+                let nonce = 1;
+                let info0 =
+                    EdgeInfo::new(peer_ids[u].clone(), peer_ids[v].clone(), nonce, &keys[u]);
+                let info1 =
+                    EdgeInfo::new(peer_ids[u].clone(), peer_ids[v].clone(), nonce, &keys[v]);
+                Edge::new(
+                    peer_ids[u].clone(),
+                    peer_ids[v].clone(),
+                    nonce,
+                    info0.signature,
+                    info1.signature,
+                )
+            })
+            .collect();
+        (peer_ids, edges)
+    }
+}
+
+/// Periodically toggles connections of the topology: active connections are dropped and dropped
+/// connections are established again.
+#[derive(Clone, Debug)]
+pub struct Churn {
+    /// How often connections are toggled.
+    pub period: Duration,
+    /// Number of connections toggled every period.
+    pub edges_per_period: usize,
+    /// Seed used to select connections, so runs are reproducible.
+    pub seed: u64,
+}
+
+impl Churn {
+    pub fn new(period: Duration, edges_per_period: usize) -> Self {
+        Self { period, edges_per_period, seed: 0 }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+}
+
+/// Result of a simulation run.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SimulationMetrics {
+    /// Simulated time until every node could route to every node reachable from it.
+    /// `None` if that didn't happen within the simulated duration.
+    pub time_to_full_reachability: Option<Duration>,
+    /// Whether all routing tables were complete at the end of the run.
+    pub converged_at_end: bool,
+    /// Number of edge gossip messages sent between nodes.
+    pub messages_exchanged: u64,
+    /// Number of bytes (borsh encoded edges) sent between nodes.
+    pub bytes_exchanged: u64,
+    /// Number of simulation steps executed.
+    pub ticks: u64,
+}
+
+struct SimulatedNode {
+    peer_id: PeerId,
+    /// Latest version known of each edge.
+    edges: HashMap<(PeerId, PeerId), Edge>,
+    /// Edges learned since the last broadcast.
+    pending: Vec<Edge>,
+}
+
+impl SimulatedNode {
+    fn process_edge(&mut self, edge: &Edge) -> bool {
+        let key = (edge.peer0.clone(), edge.peer1.clone());
+        if self.edges.get(&key).map_or(false, |known| known.nonce >= edge.nonce) {
+            return false;
+        }
+        self.edges.insert(key, edge.clone());
+        self.pending.push(edge.clone());
+        true
+    }
+
+    fn reachable_peers(&self) -> HashSet<PeerId> {
+        let mut graph = Graph::new(self.peer_id.clone());
+        for edge in self.edges.values() {
+            if edge.edge_type() == EdgeType::Added {
+                graph.add_edge(edge.peer0.clone(), edge.peer1.clone());
+            }
+        }
+        graph.calculate_distance().into_iter().map(|(peer_id, _)| peer_id).collect()
+    }
+}
+
+/// Deterministic simulation of the edge gossip protocol between `N` nodes.
+pub struct Simulation {
+    topology: Topology,
+    churn: Option<Churn>,
+    tick: Duration,
+}
+
+impl Simulation {
+    pub fn new(topology: Topology) -> Self {
+        Self { topology, churn: None, tick: DEFAULT_TICK }
+    }
+
+    pub fn with_churn(mut self, churn: Churn) -> Self {
+        self.churn = Some(churn);
+        self
+    }
+
+    /// Duration of a single step. Every step each node broadcasts the edges learned on the
+    /// previous step to its neighbors.
+    pub fn with_tick(mut self, tick: Duration) -> Self {
+        assert!(tick > Duration::from_millis(0));
+        self.tick = tick;
+        self
+    }
+
+    /// Run the simulation for `duration` of simulated time.
+    pub fn run_for(self, duration: Duration) -> SimulationMetrics {
+        let num_nodes = self.topology.num_nodes;
+        let peer_ids: Vec<PeerId> =
+            (0..num_nodes).map(|i| peer_id_from_seed(&format!("simulation{}", i))).collect();
+        let mut nodes: Vec<SimulatedNode> = peer_ids
+            .iter()
+            .map(|peer_id| SimulatedNode {
+                peer_id: peer_id.clone(),
+                edges: HashMap::new(),
+                pending: vec![],
+            })
+            .collect();
+        // Current nonce of every connection of the topology.
+        let mut nonces: Vec<u64> = vec![1; self.topology.edges.len()];
+        for (index, &(u, v)) in self.topology.edges.iter().enumerate() {
+            let edge = Self::make_edge(&peer_ids, u, v, nonces[index]);
+            nodes[u].process_edge(&edge);
+            nodes[v].process_edge(&edge);
+        }
+
+        let mut rng = StdRng::seed_from_u64(self.churn.as_ref().map_or(0, |churn| churn.seed));
+        let mut metrics = SimulationMetrics::default();
+        let total_ticks = (duration.as_nanos() / self.tick.as_nanos()) as u64;
+        let churn_every = self
+            .churn
+            .as_ref()
+            .map(|churn| std::cmp::max(1, (churn.period.as_nanos() / self.tick.as_nanos()) as u64));
+
+        for tick in 1..=total_ticks {
+            if let (Some(churn), Some(every)) = (self.churn.as_ref(), churn_every) {
+                if tick % every == 0 && !nonces.is_empty() {
+                    for _ in 0..churn.edges_per_period {
+                        let index = rng.gen_range(0, nonces.len());
+                        nonces[index] += 1;
+                        let (u, v) = self.topology.edges[index];
+                        let edge = Self::make_edge(&peer_ids, u, v, nonces[index]);
+                        nodes[u].process_edge(&edge);
+                        nodes[v].process_edge(&edge);
+                    }
+                }
+            }
+
+            let messages: Vec<(usize, Vec<Edge>)> = self
+                .topology
+                .edges
+                .iter()
+                .zip(nonces.iter())
+                .filter(|(_, nonce)| *nonce % 2 == 1)
+                .flat_map(|(&(u, v), _)| vec![(u, v), (v, u)])
+                .filter(|&(from, _)| !nodes[from].pending.is_empty())
+                .map(|(from, to)| (to, nodes[from].pending.clone()))
+                .collect();
+            for node in nodes.iter_mut() {
+                node.pending.clear();
+            }
+            for (to, edges) in messages {
+                metrics.messages_exchanged += 1;
+                metrics.bytes_exchanged += edges.try_to_vec().map_or(0, |bytes| bytes.len() as u64);
+                for edge in edges.iter() {
+                    nodes[to].process_edge(edge);
+                }
+            }
+
+            metrics.ticks = tick;
+            let converged = Self::is_converged(&self.topology, &nonces, &nodes);
+            if converged && metrics.time_to_full_reachability.is_none() {
+                metrics.time_to_full_reachability = Some(self.tick * tick as u32);
+            }
+            metrics.converged_at_end = converged;
+        }
+
+        metrics
+    }
+
+    fn make_edge(peer_ids: &[PeerId], u: usize, v: usize, nonce: u64) -> Edge {
+        let (peer0, peer1) = Edge::key(peer_ids[u].clone(), peer_ids[v].clone());
+        Edge::make_fake_edge(peer0, peer1, nonce)
+    }
+
+    /// Every node can route to exactly the set of nodes connected to it in the active topology.
+    fn is_converged(topology: &Topology, nonces: &[u64], nodes: &[SimulatedNode]) -> bool {
+        let mut adjacency = vec![vec![]; topology.num_nodes];
+        for (&(u, v), nonce) in topology.edges.iter().zip(nonces.iter()) {
+            if nonce % 2 == 1 {
+                adjacency[u].push(v);
+                adjacency[v].push(u);
+            }
+        }
+
+        nodes.iter().enumerate().all(|(source, node)| {
+            let mut visited = vec![false; topology.num_nodes];
+            let mut queue = VecDeque::new();
+            visited[source] = true;
+            queue.push_back(source);
+            while let Some(u) = queue.pop_front() {
+                for &v in adjacency[u].iter() {
+                    if !visited[v] {
+                        visited[v] = true;
+                        queue.push_back(v);
+                    }
+                }
+            }
+            let expected: HashSet<PeerId> = (0..topology.num_nodes)
+                .filter(|&u| u != source && visited[u])
+                .map(|u| nodes[u].peer_id.clone())
+                .collect();
+            node.reachable_peers() == expected
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Churn, Simulation, Topology};
+
+    #[test]
+    fn random_topologies() {
+        // Same seed, same graph.
+        let graph = Topology::erdos_renyi(50, 0.1, 3);
+        assert_eq!(graph.edges(), Topology::erdos_renyi(50, 0.1, 3).edges());
+        assert_ne!(graph.edges(), Topology::erdos_renyi(50, 0.1, 4).edges());
+        assert!(graph.edges().len() > 50 && graph.edges().len() < 200);
+
+        // Preferential attachment gives a few hubs much better connected than most nodes.
+        let graph = Topology::barabasi_albert(2_000, 2, 5);
+        assert_eq!(graph.edges(), Topology::barabasi_albert(2_000, 2, 5).edges());
+        assert_eq!(graph.edges().len(), 3 + 2 * 1_997);
+        let mut degrees = graph.degrees();
+        degrees.sort();
+        assert_eq!(degrees[0], 2);
+        assert!(degrees[degrees.len() / 2] <= 4);
+        assert!(degrees[degrees.len() - 1] >= 30);
+
+        let graph = Topology::geographic_lattice(4, 3, 2, 1);
+        assert_eq!(graph.num_nodes(), 12);
+        assert_eq!(graph.edges().len(), 3 * 3 + 4 * 2 + 2);
+        assert_eq!(Topology::geographic_lattice(2, 1, 10, 1).edges().len(), 1);
+    }
+
+    #[test]
+    fn signed_topology_edges() {
+        let topology = Topology::barabasi_albert(20, 2, 1);
+        let (peer_ids, edges) = topology.signed_edges(9);
+        assert_eq!(peer_ids.len(), 20);
+        assert_eq!(edges.len(), topology.edges().len());
+        assert!(edges.iter().all(|edge| edge.verify()));
+        assert_eq!(topology.signed_edges(9).1, edges);
+    }
+
+    #[test]
+    fn line_converges() {
+        let metrics = Simulation::new(Topology::line(4)).run_for(Duration::from_secs(1));
+        assert_eq!(metrics.time_to_full_reachability, Some(Duration::from_millis(200)));
+        assert!(metrics.converged_at_end);
+        // On the first step every node sends its edges to each neighbor.
+        assert!(metrics.messages_exchanged >= 6);
+        assert!(metrics.bytes_exchanged > 0);
+        assert_eq!(metrics.ticks, 10);
+    }
+
+    #[test]
+    fn churn_is_deterministic() {
+        let run = || {
+            Simulation::new(Topology::complete(6))
+                .with_churn(Churn::new(Duration::from_millis(300), 2).with_seed(7))
+                .run_for(Duration::from_secs(3))
+        };
+        assert_eq!(run(), run());
+    }
+}
@@ -0,0 +1,119 @@
+//! Least recently used cache which synchronizes access internally, so it can be read through a
+//! shared reference, unlike `cached::SizedCache` whose reads update the order of the entries.
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::sync::{Mutex, MutexGuard};
+
+struct Inner<K, V> {
+    /// Values with the tick of their last use.
+    entries: HashMap<K, (V, u64)>,
+    /// Keys by the tick of their last use, least recently used first.
+    order: BTreeMap<u64, K>,
+    tick: u64,
+}
+
+pub(crate) struct SyncLruCache<K, V> {
+    capacity: usize,
+    inner: Mutex<Inner<K, V>>,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> SyncLruCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "LRU cache capacity must be positive");
+        Self {
+            capacity,
+            inner: Mutex::new(Inner { entries: HashMap::new(), order: BTreeMap::new(), tick: 0 }),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner<K, V>> {
+        self.inner.lock().unwrap()
+    }
+
+    /// Value of `key`, which becomes the most recently used.
+    pub fn get(&self, key: &K) -> Option<V> {
+        let mut inner = self.lock();
+        let inner = &mut *inner;
+        let (value, last_use) = inner.entries.get_mut(key)?;
+        inner.order.remove(last_use);
+        inner.tick += 1;
+        *last_use = inner.tick;
+        inner.order.insert(inner.tick, key.clone());
+        Some(value.clone())
+    }
+
+    /// Whether `key` is cached, without changing the order of the entries.
+    pub fn contains(&self, key: &K) -> bool {
+        self.lock().entries.contains_key(key)
+    }
+
+    /// Set the value of `key`, evicting the least recently used entry if the cache is full.
+    /// Returns the previous value of `key`.
+    pub fn put(&self, key: K, value: V) -> Option<V> {
+        let mut inner = self.lock();
+        let inner = &mut *inner;
+        inner.tick += 1;
+        let tick = inner.tick;
+        if let Some((previous, last_use)) = inner.entries.remove(&key) {
+            inner.order.remove(&last_use);
+            inner.order.insert(tick, key.clone());
+            inner.entries.insert(key, (value, tick));
+            return Some(previous);
+        }
+        if inner.entries.len() >= self.capacity {
+            if let Some(&oldest) = inner.order.keys().next() {
+                let evicted = inner.order.remove(&oldest).unwrap();
+                inner.entries.remove(&evicted);
+            }
+        }
+        inner.order.insert(tick, key.clone());
+        inner.entries.insert(key, (value, tick));
+        None
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        let mut inner = self.lock();
+        let (value, last_use) = inner.entries.remove(key)?;
+        inner.order.remove(&last_use);
+        Some(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    /// Keys, most recently used first.
+    pub fn keys(&self) -> Vec<K> {
+        self.lock().order.values().rev().cloned().collect()
+    }
+
+    /// Values, most recently used first.
+    pub fn values(&self) -> Vec<V> {
+        let inner = self.lock();
+        inner.order.values().rev().map(|key| inner.entries[key].0.clone()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SyncLruCache;
+
+    #[test]
+    fn evict_least_recently_used() {
+        let cache = SyncLruCache::new(2);
+        assert_eq!(cache.put(1, "a"), None);
+        assert_eq!(cache.put(2, "b"), None);
+        assert_eq!(cache.get(&1), Some("a"));
+        // 2 is the least recently used.
+        assert_eq!(cache.put(3, "c"), None);
+        assert!(!cache.contains(&2));
+        assert_eq!(cache.keys(), vec![3, 1]);
+        assert_eq!(cache.values(), vec!["c", "a"]);
+
+        assert_eq!(cache.put(1, "d"), Some("a"));
+        assert_eq!(cache.keys(), vec![1, 3]);
+        assert_eq!(cache.remove(&3), Some("c"));
+        assert_eq!(cache.remove(&3), None);
+        assert_eq!(cache.len(), 1);
+    }
+}
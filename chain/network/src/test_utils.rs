@@ -309,9 +309,10 @@ pub fn make_peer_manager(
                 if !accounts.is_empty() {
                     counter1.fetch_add(1, Ordering::SeqCst);
                 }
-                Box::new(Some(NetworkViewClientResponses::AnnounceAccount(
-                    accounts.clone().into_iter().map(|obj| obj.0).collect(),
-                )))
+                Box::new(Some(NetworkViewClientResponses::AnnounceAccount {
+                    accounts: accounts.clone().into_iter().map(|obj| obj.0).collect(),
+                    stale: vec![],
+                }))
             }
             NetworkViewClientMessages::GetChainInfo => {
                 Box::new(Some(NetworkViewClientResponses::ChainInfo {
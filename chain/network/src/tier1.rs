@@ -0,0 +1,110 @@
+//! Direct connections between validators of the current epoch ("TIER1").
+//!
+//! Validators connect directly to the peers the other validators of the epoch announced
+//! themselves from, so that messages between them don't depend on the routing graph.
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use near_primitives::network::PeerId;
+use near_primitives::types::AccountId;
+
+use crate::time::{Clock, Time};
+
+/// Minimum time between two connection attempts to the same validator.
+const TIER1_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Default)]
+pub(crate) struct Tier1Connections {
+    clock: Clock,
+    /// Peers the validators of the current epoch announced themselves from, with their account.
+    validator_peers: HashMap<PeerId, AccountId>,
+    /// Last connection attempt to each peer of `validator_peers`.
+    last_attempt: HashMap<PeerId, Instant>,
+}
+
+impl Tier1Connections {
+    pub fn new(clock: Clock) -> Self {
+        Self { clock, ..Default::default() }
+    }
+
+    /// Replace the peers of the validators of the current epoch.
+    pub fn set_validator_peers(&mut self, peers: Vec<(AccountId, PeerId)>) {
+        self.validator_peers =
+            peers.into_iter().map(|(account_id, peer_id)| (peer_id, account_id)).collect();
+        let validator_peers = &self.validator_peers;
+        self.last_attempt.retain(|peer_id, _| validator_peers.contains_key(peer_id));
+    }
+
+    /// Whether `peer_id` is the peer of a validator of the current epoch.
+    pub fn contains(&self, peer_id: &PeerId) -> bool {
+        self.validator_peers.contains_key(peer_id)
+    }
+
+    pub fn account_id(&self, peer_id: &PeerId) -> Option<&AccountId> {
+        self.validator_peers.get(peer_id)
+    }
+
+    /// Validator peers we should try to connect to now: the ones `is_connected` is false for,
+    /// and which weren't tried recently.
+    pub fn peers_to_connect(&mut self, is_connected: impl Fn(&PeerId) -> bool) -> Vec<PeerId> {
+        self.peers_to_connect_at(is_connected, self.clock.now())
+    }
+
+    fn peers_to_connect_at(
+        &mut self,
+        is_connected: impl Fn(&PeerId) -> bool,
+        now: Instant,
+    ) -> Vec<PeerId> {
+        let last_attempt = &self.last_attempt;
+        let peers = self
+            .validator_peers
+            .keys()
+            .filter(|peer_id| !is_connected(peer_id))
+            .filter(|peer_id| {
+                last_attempt
+                    .get(peer_id)
+                    .map_or(true, |last| Time::duration_since(now, *last) >= TIER1_RETRY_INTERVAL)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        for peer_id in peers.iter() {
+            self.last_attempt.insert(peer_id.clone(), now);
+        }
+        peers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use crate::test_utils::random_peer_id;
+
+    use super::{Tier1Connections, TIER1_RETRY_INTERVAL};
+
+    #[test]
+    fn peers_to_connect() {
+        let now = Instant::now();
+        let (connected, disconnected) = (random_peer_id(), random_peer_id());
+        let mut tier1 = Tier1Connections::default();
+        tier1.set_validator_peers(vec![
+            ("test0".parse().unwrap(), connected.clone()),
+            ("test1".parse().unwrap(), disconnected.clone()),
+        ]);
+        assert!(tier1.contains(&connected));
+        assert_eq!(tier1.account_id(&disconnected), Some(&"test1".parse().unwrap()));
+
+        let is_connected = |peer_id: &_| peer_id == &connected;
+        assert_eq!(tier1.peers_to_connect_at(is_connected, now), vec![disconnected.clone()]);
+        // Recently tried peers are not tried again.
+        assert!(tier1.peers_to_connect_at(is_connected, now).is_empty());
+        assert_eq!(
+            tier1.peers_to_connect_at(is_connected, now + TIER1_RETRY_INTERVAL),
+            vec![disconnected.clone()]
+        );
+
+        tier1.set_validator_peers(vec![]);
+        assert!(!tier1.contains(&disconnected));
+        assert!(tier1.peers_to_connect_at(is_connected, now).is_empty());
+    }
+}
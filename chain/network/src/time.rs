@@ -0,0 +1,310 @@
+//! Source of time for the network crate.
+//!
+//! Components which read the time hold a `Clock`, given to them when they are created, instead of
+//! reading the system clock. The real clock is used unless a test injects the clock of a
+//! `FakeClock`: its instants and UTC times only change when the test advances it, whatever the
+//! thread the components run on, and futures from `Clock::sleep_until` complete once it reaches
+//! their deadline. Clones of a clock share the same time, so a clock can be handed to actors on
+//! other threads (e.g. peers, each running on its own arbiter) like any other value.
+//!
+//! A `FakeClock` also records which threads use it: tests can check how many times a thread read
+//! the time and how many of its sleeps are pending, with `FakeClock::thread_state`, after giving
+//! worker threads names with `FakeClock::register_thread`.
+//!
+//! Fake instants don't need to be monotonic between clocks, so every arithmetic between instants
+//! goes through the saturating helpers here instead of `Instant::duration_since`, which panics on
+//! older toolchains if `earlier` is later than `self`.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, TimeZone, Utc};
+use futures::channel::oneshot;
+use futures::future::{self, BoxFuture, FutureExt};
+
+pub struct Time;
+
+impl Time {
+    /// Duration from `earlier` to `later`, or zero if `earlier` is after `later`.
+    pub fn duration_since(later: Instant, earlier: Instant) -> Duration {
+        later.saturating_duration_since(earlier)
+    }
+}
+
+#[derive(Debug)]
+struct FakeClockState {
+    instant: Instant,
+    utc: DateTime<Utc>,
+    /// Futures from `Clock::sleep_until` waiting for the fake time to reach their deadline, with
+    /// the thread which started them.
+    wakeups: Vec<(Instant, ThreadId, oneshot::Sender<()>)>,
+    /// Number of times each thread read the time.
+    reads: HashMap<ThreadId, usize>,
+    /// Threads registered by the test, by name.
+    threads: HashMap<String, ThreadId>,
+}
+
+impl FakeClockState {
+    fn record_read(&mut self) {
+        *self.reads.entry(thread::current().id()).or_default() += 1;
+    }
+
+    fn advance(&mut self, duration: Duration) {
+        self.instant += duration;
+        self.utc = self.utc + chrono::Duration::from_std(duration).unwrap();
+        let now = self.instant;
+        let (due, pending): (Vec<_>, Vec<_>) =
+            std::mem::take(&mut self.wakeups).into_iter().partition(|(at, _, _)| *at <= now);
+        self.wakeups = pending;
+        for (_, _, wakeup) in due {
+            let _ = wakeup.send(());
+        }
+    }
+}
+
+/// Source of `Instant`s and UTC times: the system clock, or a `FakeClock` shared with a test.
+#[derive(Clone, Debug, Default)]
+pub struct Clock(Option<Arc<Mutex<FakeClockState>>>);
+
+impl Clock {
+    pub fn real() -> Self {
+        Self(None)
+    }
+
+    /// Current instant.
+    pub fn now(&self) -> Instant {
+        match &self.0 {
+            Some(fake) => {
+                let mut state = fake.lock().unwrap();
+                state.record_read();
+                state.instant
+            }
+            None => Instant::now(),
+        }
+    }
+
+    /// Current UTC time.
+    pub fn now_utc(&self) -> DateTime<Utc> {
+        match &self.0 {
+            Some(fake) => {
+                let mut state = fake.lock().unwrap();
+                state.record_read();
+                state.utc
+            }
+            None => Utc::now(),
+        }
+    }
+
+    /// Time elapsed since `earlier`, or zero if `earlier` is in the future.
+    pub fn elapsed(&self, earlier: Instant) -> Duration {
+        Time::duration_since(self.now(), earlier)
+    }
+
+    /// Complete once the clock reaches `deadline`. Needs a tokio runtime with the real clock.
+    pub fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()> {
+        let fake = match &self.0 {
+            Some(fake) => fake,
+            None => {
+                return tokio::time::sleep_until(tokio::time::Instant::from_std(deadline)).boxed()
+            }
+        };
+        let mut state = fake.lock().unwrap();
+        if deadline <= state.instant {
+            return future::ready(()).boxed();
+        }
+        let (wakeup, woken) = oneshot::channel();
+        state.wakeups.push((deadline, thread::current().id(), wakeup));
+        // If the fake clock is dropped, nothing will ever advance it: complete right away.
+        woken.map(|_| ()).boxed()
+    }
+
+    pub fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        self.sleep_until(self.now() + duration)
+    }
+}
+
+/// Clock controlled by a test. Its time only moves with `advance`, and every `Clock` created
+/// with `clock` sees the same time.
+pub struct FakeClock(Arc<Mutex<FakeClockState>>);
+
+impl FakeClock {
+    /// Fake clock starting at the current instant and at a fixed UTC time, so tests reading
+    /// UTC times are deterministic.
+    pub fn new() -> Self {
+        Self(Arc::new(Mutex::new(FakeClockState {
+            instant: Instant::now(),
+            utc: Utc.ymd(2021, 1, 1).and_hms(0, 0, 0),
+            wakeups: vec![],
+            reads: HashMap::new(),
+            threads: HashMap::new(),
+        })))
+    }
+
+    pub fn clock(&self) -> Clock {
+        Clock(Some(self.0.clone()))
+    }
+
+    pub fn now(&self) -> Instant {
+        self.0.lock().unwrap().instant
+    }
+
+    pub fn now_utc(&self) -> DateTime<Utc> {
+        self.0.lock().unwrap().utc
+    }
+
+    /// Move the time forward by `duration`, completing the sleeps which are due.
+    pub fn advance(&self, duration: Duration) {
+        self.0.lock().unwrap().advance(duration);
+    }
+
+    /// Move the time forward to `instant`, if it's in the future.
+    pub fn advance_until(&self, instant: Instant) {
+        let mut state = self.0.lock().unwrap();
+        let duration = Time::duration_since(instant, state.instant);
+        state.advance(duration);
+    }
+
+    /// Number of sleeps which are not due yet.
+    pub fn num_scheduled_wakeups(&self) -> usize {
+        self.0.lock().unwrap().wakeups.len()
+    }
+
+    /// Give `thread` a name, e.g. a worker spawned by the code under test, to find it later with
+    /// `registered_thread`. Registering a name again replaces its thread.
+    pub fn register_thread(&self, name: &str, thread: ThreadId) {
+        self.0.lock().unwrap().threads.insert(name.to_string(), thread);
+    }
+
+    /// Thread registered as `name`, if any.
+    pub fn registered_thread(&self, name: &str) -> Option<ThreadId> {
+        self.0.lock().unwrap().threads.get(name).copied()
+    }
+
+    /// Use of this clock by `thread` since it was created or since `reset_thread`.
+    pub fn thread_state(&self, thread: ThreadId) -> ThreadClockState {
+        let state = self.0.lock().unwrap();
+        ThreadClockState {
+            reads: state.reads.get(&thread).copied().unwrap_or(0),
+            scheduled_wakeups: state.wakeups.iter().filter(|(_, t, _)| *t == thread).count(),
+        }
+    }
+
+    /// Forget the time reads of `thread`. Its pending sleeps are kept.
+    pub fn reset_thread(&self, thread: ThreadId) {
+        self.0.lock().unwrap().reads.remove(&thread);
+    }
+}
+
+/// Use of a `FakeClock` by one thread, see `FakeClock::thread_state`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ThreadClockState {
+    /// Number of times the thread read the time with `Clock::now` or `Clock::now_utc`.
+    pub reads: usize,
+    /// Number of sleeps started on the thread which are not due yet.
+    pub scheduled_wakeups: usize,
+}
+
+impl Default for FakeClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use futures::FutureExt;
+
+    use super::{Clock, FakeClock, ThreadClockState, Time};
+
+    #[test]
+    fn reversed_instants_saturate() {
+        let now = Instant::now();
+        let later = now + Duration::from_secs(5);
+        assert_eq!(Time::duration_since(now, later), Duration::from_secs(0));
+        assert_eq!(Time::duration_since(later, now), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn fake_clock() {
+        let fake = FakeClock::new();
+        let clock = fake.clock();
+        let (start, start_utc) = (clock.now(), clock.now_utc());
+        assert_eq!(clock.now(), start);
+        assert_eq!(clock.elapsed(start + Duration::from_secs(1)), Duration::from_secs(0));
+
+        fake.advance(Duration::from_secs(10));
+        assert_eq!(clock.elapsed(start), Duration::from_secs(10));
+        assert_eq!(clock.now_utc() - start_utc, chrono::Duration::seconds(10));
+        // Going back in time is ignored.
+        fake.advance_until(start);
+        assert_eq!(fake.now(), start + Duration::from_secs(10));
+
+        // Clones of the clock on other threads see the same time.
+        let other = clock.clone();
+        assert_eq!(thread::spawn(move || other.now()).join().unwrap(), fake.now());
+        assert!(Clock::real().now() < fake.now());
+    }
+
+    #[test]
+    fn fake_sleeps_complete_when_due() {
+        let fake = FakeClock::new();
+        let clock = fake.clock();
+        let mut short = clock.sleep(Duration::from_secs(1));
+        let mut long = clock.sleep(Duration::from_secs(5));
+        assert!(clock.sleep(Duration::from_secs(0)).now_or_never().is_some());
+        assert_eq!(fake.num_scheduled_wakeups(), 2);
+
+        fake.advance(Duration::from_millis(999));
+        assert!((&mut short).now_or_never().is_none());
+        fake.advance(Duration::from_millis(1));
+        assert!((&mut short).now_or_never().is_some());
+        assert!((&mut long).now_or_never().is_none());
+        assert_eq!(fake.num_scheduled_wakeups(), 1);
+
+        fake.advance_until(fake.now() + Duration::from_secs(4));
+        assert!(long.now_or_never().is_some());
+        assert_eq!(fake.num_scheduled_wakeups(), 0);
+    }
+
+    #[test]
+    fn thread_state() {
+        let fake = FakeClock::new();
+        let clock = fake.clock();
+        let worker = thread::spawn(move || {
+            let start = clock.now();
+            clock.elapsed(start);
+            clock.now_utc();
+            clock.sleep(Duration::from_secs(1))
+        });
+        let worker_id = worker.thread().id();
+        let sleep = worker.join().unwrap();
+        fake.register_thread("worker", worker_id);
+        assert_eq!(fake.registered_thread("worker"), Some(worker_id));
+        assert_eq!(fake.registered_thread("other"), None);
+        // `sleep` reads the time to compute its deadline.
+        assert_eq!(
+            fake.thread_state(worker_id),
+            ThreadClockState { reads: 4, scheduled_wakeups: 1 }
+        );
+
+        // Reads on the test thread are counted separately, and reads of the `FakeClock` itself
+        // aren't counted.
+        fake.clock().now();
+        fake.now();
+        assert_eq!(fake.thread_state(thread::current().id()).reads, 1);
+        assert_eq!(fake.thread_state(worker_id).reads, 4);
+
+        fake.reset_thread(worker_id);
+        assert_eq!(
+            fake.thread_state(worker_id),
+            ThreadClockState { reads: 0, scheduled_wakeups: 1 }
+        );
+        fake.advance(Duration::from_secs(1));
+        assert!(sleep.now_or_never().is_some());
+        assert_eq!(fake.thread_state(worker_id), ThreadClockState::default());
+    }
+}
@@ -0,0 +1,176 @@
+//! Dataset of the network topology seen by a node, scrubbed so it can be shared publicly, e.g.
+//! with researchers studying peer-to-peer networks.
+//!
+//! Peer ids are replaced with pseudonyms computed with HMAC-SHA256 under a secret key. Nodes
+//! using the same key produce the same pseudonyms, so their datasets can be joined, while without
+//! the key pseudonyms can't be linked back to peers. Edge nonces, which tell when connections
+//! were made, and reachability times are dropped. Routing table dumps contain no addresses.
+//! Degrees and latencies are kept.
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use near_primitives::hash::{hash, CryptoHash};
+use near_primitives::network::PeerId;
+
+use crate::routing::EdgeType;
+use crate::routing_dump::RoutingTableDump;
+
+/// Version of `TopologyDataset`. Must be increased on every incompatible change.
+pub const TOPOLOGY_DATASET_VERSION: u32 = 1;
+
+/// Block size of SHA-256 in bytes.
+const HMAC_BLOCK_SIZE: usize = 64;
+
+/// HMAC-SHA256 of `data` under `key`, as defined in RFC 2104.
+fn hmac_sha256(key: &[u8], data: &[u8]) -> CryptoHash {
+    let mut block = [0u8; HMAC_BLOCK_SIZE];
+    if key.len() > HMAC_BLOCK_SIZE {
+        block[..32].copy_from_slice(hash(key).as_ref());
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let pad = |byte: u8| block.iter().map(move |b| b ^ byte);
+    let inner: Vec<u8> = pad(0x36).chain(data.iter().cloned()).collect();
+    let outer: Vec<u8> = pad(0x5c).chain(hash(&inner).as_ref().iter().cloned()).collect();
+    hash(&outer)
+}
+
+/// Replaces peer ids with pseudonyms.
+pub struct Scrubber {
+    key: Vec<u8>,
+}
+
+impl Scrubber {
+    /// Nodes whose datasets should be joined must use the same key, which must stay secret.
+    pub fn new(key: &[u8]) -> Self {
+        Self { key: key.to_vec() }
+    }
+
+    pub fn pseudonym(&self, peer_id: &PeerId) -> String {
+        hmac_sha256(&self.key, &Vec::from(peer_id.clone())).to_string()
+    }
+}
+
+/// Network topology seen by a node, with pseudonyms instead of peer ids.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct TopologyDataset {
+    pub version: u32,
+    /// Node which produced the dataset.
+    pub source: String,
+    /// Active edges, each once with the smaller pseudonym first, sorted.
+    pub edges: Vec<(String, String)>,
+    /// Number of active edges of each peer.
+    pub degrees: BTreeMap<String, usize>,
+    /// Moving average of the round trip time from `source` to the peers it pinged, in
+    /// milliseconds.
+    pub latencies_ms: BTreeMap<String, f64>,
+}
+
+impl TopologyDataset {
+    pub fn new(dump: &RoutingTableDump, scrubber: &Scrubber) -> Self {
+        let mut edges: Vec<_> = dump
+            .edges
+            .iter()
+            .filter(|edge| edge.edge_type() == EdgeType::Added)
+            .map(|edge| {
+                let peer0 = scrubber.pseudonym(&edge.peer0);
+                let peer1 = scrubber.pseudonym(&edge.peer1);
+                if peer0 <= peer1 {
+                    (peer0, peer1)
+                } else {
+                    (peer1, peer0)
+                }
+            })
+            .collect();
+        edges.sort();
+        edges.dedup();
+
+        let mut degrees = BTreeMap::new();
+        for (peer0, peer1) in edges.iter() {
+            *degrees.entry(peer0.clone()).or_default() += 1;
+            *degrees.entry(peer1.clone()).or_default() += 1;
+        }
+        let latencies_ms = dump
+            .peer_latency_us
+            .iter()
+            .map(|(peer_id, latency_us)| (scrubber.pseudonym(peer_id), *latency_us as f64 / 1000.0))
+            .collect();
+
+        Self {
+            version: TOPOLOGY_DATASET_VERSION,
+            source: scrubber.pseudonym(&dump.peer_id),
+            edges,
+            degrees,
+            latencies_ms,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::routing::Edge;
+    use crate::routing_dump::{RoutingTableDump, ROUTING_TABLE_DUMP_VERSION};
+    use crate::test_utils::random_peer_id;
+
+    use super::{hmac_sha256, Scrubber, TopologyDataset};
+
+    /// Test cases 1 and 6 of RFC 4231, the latter with a key longer than a block.
+    #[test]
+    fn hmac_test_vectors() {
+        let mac = hmac_sha256(&[0x0b; 20], b"Hi There");
+        assert_eq!(
+            mac.as_ref(),
+            &[
+                0xb0, 0x34, 0x4c, 0x61, 0xd8, 0xdb, 0x38, 0x53, 0x5c, 0xa8, 0xaf, 0xce, 0xaf, 0x0b,
+                0xf1, 0x2b, 0x88, 0x1d, 0xc2, 0x00, 0xc9, 0x83, 0x3d, 0xa7, 0x26, 0xe9, 0x37, 0x6c,
+                0x2e, 0x32, 0xcf, 0xf7
+            ]
+        );
+        let mac =
+            hmac_sha256(&[0xaa; 131], b"Test Using Larger Than Block-Size Key - Hash Key First");
+        assert_eq!(
+            mac.as_ref(),
+            &[
+                0x60, 0xe4, 0x31, 0x59, 0x1e, 0xe0, 0xb6, 0x7f, 0x0d, 0x8a, 0x26, 0xaa, 0xcb, 0xf5,
+                0xb7, 0x7f, 0x8e, 0x0b, 0xc6, 0x21, 0x37, 0x28, 0xc5, 0x14, 0x05, 0x46, 0x04, 0x0f,
+                0x0e, 0xe3, 0x7f, 0x54
+            ]
+        );
+    }
+
+    #[test]
+    fn scrubbed_dataset() {
+        let (me, peer1, peer2) = (random_peer_id(), random_peer_id(), random_peer_id());
+        let removed = Edge::make_fake_edge(me.clone(), peer2.clone(), 2);
+        let dump = RoutingTableDump {
+            version: ROUTING_TABLE_DUMP_VERSION,
+            peer_id: me.clone(),
+            edges: vec![
+                Edge::make_fake_edge(me.clone(), peer1.clone(), 1),
+                Edge::make_fake_edge(peer1.clone(), peer2.clone(), 7),
+                removed,
+            ],
+            peer_forwarding: vec![],
+            peer_last_time_reachable: vec![],
+            component_nonce: 0,
+            peer_components: vec![],
+            peer_latency_us: vec![(peer1.clone(), 2_500)],
+        };
+        let scrubber = Scrubber::new(b"secret");
+        let dataset = TopologyDataset::new(&dump, &scrubber);
+
+        let (me, peer1, peer2) =
+            (scrubber.pseudonym(&me), scrubber.pseudonym(&peer1), scrubber.pseudonym(&peer2));
+        assert_eq!(dataset.source, me);
+        assert_eq!(dataset.edges.len(), 2);
+        assert_eq!(dataset.degrees[&peer1], 2);
+        assert_eq!(dataset.degrees[&peer2], 1);
+        assert_eq!(dataset.degrees[&me], 1);
+        assert_eq!(dataset.latencies_ms[&peer1], 2.5);
+
+        // Pseudonyms only depend on the key.
+        assert_eq!(Scrubber::new(b"secret").pseudonym(&dump.peer_id), me);
+        assert_ne!(Scrubber::new(b"other").pseudonym(&dump.peer_id), me);
+    }
+}
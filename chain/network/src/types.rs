@@ -2,6 +2,7 @@ use std::collections::HashMap;
 use std::fmt;
 use std::fmt::{Debug, Formatter};
 use std::io;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex, RwLock};
 use std::time::Instant;
 
@@ -18,6 +19,7 @@ pub use near_network_primitives::types::*;
 
 use near_primitives::block::{Approval, ApprovalMessage, Block, BlockHeader, GenesisId};
 use near_primitives::challenge::Challenge;
+use near_primitives::checked_feature;
 use near_primitives::errors::InvalidTxError;
 use near_primitives::hash::CryptoHash;
 use near_primitives::network::{AnnounceAccount, PeerId};
@@ -28,16 +30,29 @@ use near_primitives::types::{AccountId, BlockReference, EpochId, ShardId};
 use near_primitives::version::{
     ProtocolVersion, OLDEST_BACKWARD_COMPATIBLE_PROTOCOL_VERSION, PROTOCOL_VERSION,
 };
-use near_primitives::views::QueryRequest;
+use near_primitives::views::{QueryRequest, RoutingRecalculationView};
 
+use crate::anchors::GetAnchorPeersResult;
+use crate::audit_log::GetRoutingAuditLogResult;
+use crate::capabilities::PeerCapabilities;
+#[cfg(feature = "test_features")]
+use crate::chaos::PeerFaults;
+use crate::compact_peer_id::CompactPeerMessage;
+use crate::debug::NetworkDebugInfo;
+use crate::delta_sync::{RoutingTableDelta, SyncWatermark};
+use crate::edge_gossip::EdgeDigestEntry;
+use crate::gossip_stats::GetEdgeGossipStatsResult;
 use crate::ibf::IbfBox;
 use crate::peer::Peer;
-#[cfg(feature = "test_features")]
-use crate::routing::SetAdvOptionsResult;
+use crate::peer_score::{GetPeerScoresResult, Misbehavior};
+use crate::reachability_probe::GetReachabilityProbesResult;
 use crate::routing::{
-    Edge, EdgeInfo, GetRoutingTableResult, PeerRequestResult, RoutingTableInfo, SimpleEdge,
-    ValidIBFLevel,
+    AccountRouteExplanation, Edge, EdgeInfo, GetRoutingTableResult, PeerRequestResult,
+    RouteExplanation, RoutingTableInfo, SimpleEdge, SubKeys, ValidIBFLevel,
 };
+#[cfg(feature = "test_features")]
+use crate::routing::{RoutingTableSnapshot, SetAdvOptionsResult};
+use crate::routing_profile::RoutingProfile;
 
 const ERROR_UNEXPECTED_LENGTH_OF_INPUT: &str = "Unexpected length of input";
 
@@ -56,7 +71,7 @@ impl fmt::Display for HandshakeFailureReason {
 
 impl std::error::Error for HandshakeFailureReason {}
 
-#[derive(BorshSerialize, PartialEq, Eq, Clone, Debug)]
+#[derive(PartialEq, Eq, Clone, Debug)]
 pub struct Handshake {
     pub version: u32,
     /// Oldest supported protocol version.
@@ -71,10 +86,14 @@ pub struct Handshake {
     pub chain_info: PeerChainInfoV2,
     /// Info for new edge.
     pub edge_info: EdgeInfo,
+    /// Optional features the sender supports. Only sent if `version` supports negotiating them,
+    /// after the fields of `HandshakeAutoDes`.
+    pub capabilities: PeerCapabilities,
+    /// Delegation of the network sub-key the sender signs its edges with. Only sent if `version`
+    /// supports sub-keys, after `capabilities`.
+    pub sub_key_delegation: Option<SubKeyDelegation>,
 }
 
-/// Struct describing the layout for Handshake.
-/// It is used to automatically derive BorshDeserialize.
 /// Struct describing the layout for Handshake.
 /// It is used to automatically derive BorshDeserialize.
 #[derive(BorshSerialize, BorshDeserialize, PartialEq, Eq, Clone, Debug)]
@@ -103,6 +122,8 @@ impl Handshake {
         listen_port: Option<u16>,
         chain_info: PeerChainInfoV2,
         edge_info: EdgeInfo,
+        capabilities: PeerCapabilities,
+        sub_key_delegation: Option<SubKeyDelegation>,
     ) -> Self {
         Handshake {
             version,
@@ -112,8 +133,37 @@ impl Handshake {
             listen_port,
             chain_info,
             edge_info,
+            capabilities,
+            sub_key_delegation,
         }
     }
+
+    fn has_capabilities(version: ProtocolVersion) -> bool {
+        checked_feature!("protocol_feature_handshake_capabilities", HandshakeCapabilities, version)
+    }
+
+    fn has_sub_key_delegation(version: ProtocolVersion) -> bool {
+        checked_feature!("protocol_feature_network_sub_keys", NetworkSubKeys, version)
+    }
+}
+
+impl BorshSerialize for Handshake {
+    fn serialize<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.version.serialize(writer)?;
+        self.oldest_supported_version.serialize(writer)?;
+        self.peer_id.serialize(writer)?;
+        self.target_peer_id.serialize(writer)?;
+        self.listen_port.serialize(writer)?;
+        self.chain_info.serialize(writer)?;
+        self.edge_info.serialize(writer)?;
+        if Self::has_capabilities(self.version) {
+            self.capabilities.serialize(writer)?;
+        }
+        if Self::has_sub_key_delegation(self.version) {
+            self.sub_key_delegation.serialize(writer)?;
+        }
+        Ok(())
+    }
 }
 
 // Use custom deserializer for HandshakeV2. Try to read version of the other peer from the header.
@@ -132,7 +182,14 @@ impl BorshDeserialize for Handshake {
 
         if OLDEST_BACKWARD_COMPATIBLE_PROTOCOL_VERSION <= version && version <= PROTOCOL_VERSION {
             // If we support this version, then try to deserialize with custom deserializer
-            HandshakeAutoDes::deserialize(buf).map(Into::into)
+            let mut handshake: Handshake = HandshakeAutoDes::deserialize(buf)?.into();
+            if Self::has_capabilities(version) {
+                handshake.capabilities = PeerCapabilities::deserialize(buf)?;
+            }
+            if Self::has_sub_key_delegation(version) {
+                handshake.sub_key_delegation = Option::<SubKeyDelegation>::deserialize(buf)?;
+            }
+            Ok(handshake)
         } else {
             Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidData,
@@ -155,6 +212,8 @@ impl From<HandshakeAutoDes> for Handshake {
             listen_port: handshake.listen_port,
             chain_info: handshake.chain_info,
             edge_info: handshake.edge_info,
+            capabilities: PeerCapabilities::empty(),
+            sub_key_delegation: None,
         }
     }
 }
@@ -261,6 +320,8 @@ impl From<HandshakeV2> for Handshake {
             listen_port: handshake.listen_port,
             chain_info: handshake.chain_info.into(),
             edge_info: handshake.edge_info,
+            capabilities: PeerCapabilities::empty(),
+            sub_key_delegation: None,
         }
     }
 }
@@ -333,6 +394,36 @@ pub enum PeerMessage {
     EpochSyncFinalizationResponse(EpochSyncFinalizationResponse),
 
     RoutingTableSyncV2(RoutingSyncV2),
+
+    /// One of the messages above with peer ids in compact form, see `compact_peer_id`.
+    Compact(CompactPeerMessage),
+
+    /// Asks which of these peers the receiver can route messages to, see `reachability_probe`.
+    ReachabilityRequest(Vec<PeerId>),
+    /// Peers of a `ReachabilityRequest` the sender can route messages to.
+    ReachabilityResponse(Vec<PeerId>),
+
+    /// Asks for the edges changed since the watermark of the last `RoutingTableDelta` received
+    /// from the receiver, or for a new watermark if there is none, see `delta_sync`.
+    RoutingTableDeltaRequest(Option<SyncWatermark>),
+    RoutingTableDelta(RoutingTableDelta),
+
+    /// Recently changed edges the receiver may miss, see `edge_gossip`.
+    EdgeDigest(Vec<EdgeDigestEntry>),
+    /// Asks for the edges of a digest the sender doesn't know, sent back as `RoutingTableSync`.
+    EdgePull(Vec<(PeerId, PeerId)>),
+
+    /// Account announcements with the number of hops they traveled to the sender, zero for its
+    /// own, sent to peers which negotiated `PeerCapabilities::ANNOUNCEMENT_HOPS`.
+    AnnounceAccounts(Vec<(AnnounceAccount, u32)>),
+
+    /// Relays of a node hiding its address, sent to peers which negotiated
+    /// `PeerCapabilities::PROXY_ANNOUNCEMENT`.
+    ProxyAnnouncement(ProxyAnnouncement),
+
+    /// Network sub-keys of other nodes, sent to peers whose negotiated protocol version has
+    /// `ProtocolFeature::NetworkSubKeys` so they can verify the edges signed with them.
+    SubKeyDelegations(Vec<SubKeyDelegation>),
 }
 
 #[derive(BorshSerialize, BorshDeserialize, PartialEq, Eq, Clone, Debug)]
@@ -445,6 +536,13 @@ pub struct Consolidate {
     pub other_edge_info: EdgeInfo,
     // Protocol version of new peer. May be higher than ours.
     pub peer_protocol_version: ProtocolVersion,
+    /// Optional features negotiated in the handshake.
+    pub capabilities: PeerCapabilities,
+    /// PeerId the TLS certificate of the peer is pinned to, if the connection uses TLS.
+    pub tls_peer_id: Option<PeerId>,
+    /// Verified delegation of the sub-key the peer signs its edges with, if it has one and the
+    /// negotiated protocol version supports sub-keys.
+    pub sub_key_delegation: Option<SubKeyDelegation>,
 }
 
 impl Message for Consolidate {
@@ -469,9 +567,142 @@ impl Message for GetRoutingTable {
     type Result = GetRoutingTableResult;
 }
 
+/// Persist the state of the peer manager which is otherwise only saved periodically or on
+/// shutdown. The response is a barrier: every message sent to the peer manager before was
+/// handled, and what it stored was committed.
+pub struct Flush {}
+
+impl Message for Flush {
+    type Result = ();
+}
+
+/// State of the network layer served by the debug endpoint.
+pub struct GetNetworkDebugInfo {}
+
+impl Message for GetNetworkDebugInfo {
+    type Result = Result<NetworkDebugInfo, String>;
+}
+
+/// Approximate memory used by the routing table and recent handling times of the routing table
+/// actor, served by the debug endpoint.
+pub struct GetRoutingProfile {}
+
+impl Message for GetRoutingProfile {
+    type Result = Result<RoutingProfile, String>;
+}
+
+/// Send a ping to `target`, either a peer or the peer which announced an account. Fails if the
+/// account is not known.
+pub struct SendPing {
+    pub target: AccountOrPeerIdOrHash,
+}
+
+impl Message for SendPing {
+    type Result = Result<SentPing, String>;
+}
+
+#[derive(Debug)]
+pub struct SentPing {
+    pub target: PeerId,
+    pub nonce: u64,
+    /// Peer the ping was sent to, none if there is no route to `target`.
+    pub next_hop: Option<PeerId>,
+}
+
+/// Round trip times in milliseconds of the pings to `target` with `nonces` which were answered.
+pub struct GetPingRtts {
+    pub target: PeerId,
+    pub nonces: Vec<u64>,
+}
+
+impl Message for GetPingRtts {
+    type Result = Vec<f64>;
+}
+
+/// Explain how messages to `target` are routed.
+pub struct ExplainRoute {
+    pub target: PeerId,
+}
+
+impl Message for ExplainRoute {
+    type Result = Result<RouteExplanation, String>;
+}
+
+/// Explain how messages to `account_id` are routed, or why they can't be.
+pub struct ExplainAccountRoute {
+    pub account_id: AccountId,
+}
+
+impl Message for ExplainAccountRoute {
+    type Result = AccountRouteExplanation;
+}
+
+/// Peers with the lowest reputation.
+pub struct GetPeerScores {
+    pub limit: usize,
+}
+
+impl Message for GetPeerScores {
+    type Result = GetPeerScoresResult;
+}
+
+/// Anchor peers and whether they are connected.
+pub struct GetAnchorPeers {}
+
+impl Message for GetAnchorPeers {
+    type Result = GetAnchorPeersResult;
+}
+
+/// Last results of reachability probes of our peers.
+pub struct GetReachabilityProbes {}
+
+impl Message for GetReachabilityProbes {
+    type Result = GetReachabilityProbesResult;
+}
+
+/// Write the full state of the routing table to `path`, as JSON if the path has a `.json`
+/// extension and JSON is supported, as Borsh otherwise.
+pub struct DumpRoutingTable {
+    pub path: PathBuf,
+}
+
+impl Message for DumpRoutingTable {
+    type Result = Result<(), String>;
+}
+
+/// Apply the settings of the network config which can be changed while the node is running.
+/// Returns the names of the settings which changed, or why the new settings are invalid.
+pub struct ReloadNetworkConfig {
+    pub config: ReloadableNetworkConfig,
+}
+
+impl Message for ReloadNetworkConfig {
+    type Result = Result<Vec<&'static str>, String>;
+}
+
+/// Peers whose routing gossip costs us the most bytes.
+pub struct GetEdgeGossipStats {
+    pub limit: usize,
+}
+
+impl Message for GetEdgeGossipStats {
+    type Result = GetEdgeGossipStatsResult;
+}
+
+/// Most recent entries of the audit log of routing mutations.
+pub struct GetRoutingAuditLog {
+    pub limit: usize,
+}
+
+impl Message for GetRoutingAuditLog {
+    type Result = GetRoutingAuditLogResult;
+}
+
 #[cfg(feature = "test_features")]
 pub struct StartRoutingTableSync {
     pub peer_id: PeerId,
+    /// Who sent the request, recorded in the audit log.
+    pub caller: String,
 }
 
 #[cfg(feature = "test_features")]
@@ -480,6 +711,8 @@ pub struct SetAdvOptions {
     pub disable_edge_propagation: Option<bool>,
     pub disable_edge_pruning: Option<bool>,
     pub set_max_peers: Option<u64>,
+    /// Who sent the request, recorded in the audit log.
+    pub caller: String,
 }
 
 #[cfg(feature = "test_features")]
@@ -487,11 +720,43 @@ impl Message for SetAdvOptions {
     type Result = SetAdvOptionsResult;
 }
 
+/// Inject faults in the connection with `peer_id`, now and after reconnecting.
+#[cfg(feature = "test_features")]
+pub struct SetPeerFaults {
+    pub peer_id: PeerId,
+    pub faults: PeerFaults,
+}
+
+#[cfg(feature = "test_features")]
+impl Message for SetPeerFaults {
+    type Result = ();
+}
+
 #[cfg(feature = "test_features")]
 impl Message for StartRoutingTableSync {
     type Result = ();
 }
 
+#[cfg(feature = "test_features")]
+pub struct GetRoutingTableSnapshot {}
+
+#[cfg(feature = "test_features")]
+impl Message for GetRoutingTableSnapshot {
+    type Result = RoutingTableSnapshot;
+}
+
+#[cfg(feature = "test_features")]
+pub struct RestoreRoutingTableSnapshot {
+    pub snapshot: RoutingTableSnapshot,
+    /// Who sent the request, recorded in the audit log.
+    pub caller: String,
+}
+
+#[cfg(feature = "test_features")]
+impl Message for RestoreRoutingTableSnapshot {
+    type Result = ();
+}
+
 #[derive(MessageResponse, Debug)]
 pub enum ConsolidateResponse {
     Accept(Option<EdgeInfo>),
@@ -519,6 +784,8 @@ pub enum PeerRequest {
     RouteBack(Box<RoutedMessageBody>, CryptoHash),
     UpdatePeerInfo(PeerInfo),
     ReceivedMessage(PeerId, Instant),
+    /// Peer misbehaved this many times.
+    Misbehavior(PeerId, Misbehavior, u64),
 }
 
 impl Message for PeerRequest {
@@ -546,6 +813,28 @@ pub struct PeersResponse {
     pub peers: Vec<PeerInfo>,
 }
 
+/// A peer asks which of `targets` we can route messages to.
+pub struct CheckReachability {
+    pub targets: Vec<PeerId>,
+}
+
+impl Message for CheckReachability {
+    type Result = CheckReachabilityResult;
+}
+
+#[derive(MessageResponse, Debug)]
+pub struct CheckReachabilityResult {
+    pub reachable: Vec<PeerId>,
+}
+
+/// Answer of `peer_id` to our reachability probe: the targets it can route messages to.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ReachabilityProbeResponse {
+    pub peer_id: PeerId,
+    pub reachable: Vec<PeerId>,
+}
+
 // TODO(#1313): Use Box
 #[derive(Clone, strum::AsRefStr, Debug, Eq, PartialEq)]
 #[allow(clippy::large_enum_variant)]
@@ -650,24 +939,73 @@ pub enum NetworkRequests {
     ResponseUpdateNonce(Edge),
 
     /// (Unit tests) Start ping to `PeerId` with `nonce`.
-    PingTo(usize, PeerId),
+    PingTo(u64, PeerId),
     /// (Unit tests) Fetch all received ping and pong so far.
     FetchPingPongInfo,
 
+    /// Validators of the current and next epoch. Announcements of other accounts are evicted
+    /// first from the routing table and are not sent to new peers, and announcements from
+    /// epochs before `epoch_id` expire.
+    SetEpochValidators {
+        epoch_id: EpochId,
+        next_epoch_id: EpochId,
+        validators: Vec<AccountId>,
+        /// Validators of each shard in the current epoch, for messages to any validator of a
+        /// shard.
+        shard_validators: HashMap<ShardId, Vec<AccountId>>,
+    },
+
     /// A challenge to invalidate a block.
     Challenge(Challenge),
 
     // IbfMessage
-    #[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
     IbfMessage {
         peer_id: PeerId,
         ibf_msg: RoutingSyncV2,
     },
+
+    /// A peer asked for the edges changed since `watermark`.
+    DeltaSyncRequest {
+        peer_id: PeerId,
+        watermark: Option<SyncWatermark>,
+    },
+    /// A peer sent the edges changed since the watermark we asked for.
+    DeltaSync {
+        peer_id: PeerId,
+        delta: RoutingTableDelta,
+    },
+    /// A peer advertised recently changed edges.
+    EdgeDigest {
+        peer_id: PeerId,
+        digest: Vec<EdgeDigestEntry>,
+    },
+    /// A peer asked for edges of our digest.
+    EdgePull {
+        peer_id: PeerId,
+        keys: Vec<(PeerId, PeerId)>,
+    },
+    /// A peer sent account announcements with the number of hops they traveled to it.
+    AnnounceAccountsWithHops {
+        peer_id: PeerId,
+        accounts: Vec<(AnnounceAccount, u32)>,
+    },
+    /// A peer sent the relays of a node hiding its address, with a valid signature.
+    ProxyAnnouncement {
+        peer_id: PeerId,
+        announcement: ProxyAnnouncement,
+    },
+    /// A peer sent network sub-keys of other nodes, with valid delegations.
+    SubKeyDelegations {
+        peer_id: PeerId,
+        delegations: Vec<SubKeyDelegation>,
+    },
 }
 
 pub struct EdgeList {
     pub edges: Vec<Edge>,
     pub edges_info_shared: Arc<Mutex<HashMap<(PeerId, PeerId), u64>>>,
+    /// Network sub-keys edges may be signed with.
+    pub sub_keys: Arc<RwLock<SubKeys>>,
     pub sender: QueueSender<Edge>,
     #[cfg(feature = "test_features")]
     pub adv_disable_edge_signature_verification: bool,
@@ -696,6 +1034,8 @@ pub struct NetworkInfo {
     /// Accounts of known block and chunk producers from routing table.
     pub known_producers: Vec<KnownProducer>,
     pub peer_counter: usize,
+    /// Recalculation of the routing table.
+    pub routing_recalculation: RoutingRecalculationView,
 }
 
 impl<A, M> MessageResponse<A, M> for NetworkInfo
@@ -714,7 +1054,7 @@ where
 pub enum NetworkResponses {
     NoResponse,
     RoutingTableInfo(RoutingTableInfo),
-    PingPongInfo { pings: HashMap<usize, (Ping, usize)>, pongs: HashMap<usize, (Pong, usize)> },
+    PingPongInfo { pings: HashMap<u64, (Ping, usize)>, pongs: HashMap<u64, (Pong, usize)> },
     BanPeer(ReasonForBan),
     EdgeUpdate(Box<Edge>),
     RouteNotFound,
@@ -889,6 +1229,8 @@ pub struct SetRoutingTable {
     pub add_edges: Option<Vec<Edge>>,
     pub remove_edges: Option<Vec<SimpleEdge>>,
     pub prune_edges: Option<bool>,
+    /// Who sent the request, recorded in the audit log.
+    pub caller: String,
 }
 
 #[cfg(test)]
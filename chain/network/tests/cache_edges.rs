@@ -9,6 +9,7 @@ use near_network::routing::{
     Edge, EdgeType, RoutingTable, SAVE_PEERS_AFTER_TIME, SAVE_PEERS_MAX_TIME,
 };
 use near_network::test_utils::random_peer_id;
+use near_network::types::RoutingTableConfig;
 use near_primitives::network::PeerId;
 use near_store::test_utils::create_test_store;
 use near_store::{ColComponentEdges, ColPeerComponent, Store};
@@ -160,7 +161,7 @@ impl RoutingTableTest {
     }
 
     fn update(&mut self) {
-        self.routing_table.update(true, false, SAVE_PEERS_AFTER_TIME);
+        self.routing_table.update(Some(RoutingTableConfig::default().prune_config()));
     }
 }
 
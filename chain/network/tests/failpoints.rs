@@ -0,0 +1,61 @@
+//! Failed store commits in the routing table must degrade gracefully: the in-memory state stays
+//! usable, and inconsistencies left on disk are repaired once commits succeed again.
+#![cfg(feature = "failpoints")]
+use std::time::Duration;
+
+use near_crypto::Signature;
+use near_network::failpoints::{FailScenario, FailpointAction, STORE_COMMIT};
+use near_network::routing::{Edge, RoutingTable};
+use near_network::test_utils::{random_epoch_id, random_peer_id};
+use near_network::types::PruneConfig;
+use near_primitives::network::{AnnounceAccount, PeerId};
+use near_store::test_utils::create_test_store;
+use near_store::ColPeerComponent;
+
+fn edge(peer0: &PeerId, peer1: &PeerId, nonce: u64) -> Edge {
+    Edge::new(peer0.clone(), peer1.clone(), nonce, Signature::default(), Signature::default())
+}
+
+#[test]
+fn failed_announcement_commit_keeps_cache() {
+    let scenario = FailScenario::setup();
+    let store = create_test_store();
+    let me = random_peer_id();
+    let mut routing_table = RoutingTable::new(me.clone(), store.clone());
+    let announce = AnnounceAccount {
+        account_id: "near0".parse().unwrap(),
+        peer_id: me.clone(),
+        epoch_id: random_epoch_id(),
+        signature: Signature::default(),
+    };
+
+    scenario.configure(STORE_COMMIT, FailpointAction::Always);
+    routing_table.add_account(announce.clone());
+    assert_eq!(routing_table.get_announce(&announce.account_id), Some(announce.clone()));
+
+    // The announcement was never saved, so it's forgotten on restart.
+    scenario.remove(STORE_COMMIT);
+    let mut routing_table = RoutingTable::new(me, store);
+    assert_eq!(routing_table.get_announce(&announce.account_id), None);
+}
+
+#[test]
+fn failed_component_load_is_repaired() {
+    let scenario = FailScenario::setup();
+    let store = create_test_store();
+    let (me, peer1, peer2) = (random_peer_id(), random_peer_id(), random_peer_id());
+    let mut routing_table = RoutingTable::new(me.clone(), store.clone());
+    routing_table.process_edges(vec![edge(&me, &peer1, 2)]);
+    routing_table.update(Some(PruneConfig::forced(Duration::from_secs(0))));
+    let key = Vec::from(peer1.clone());
+    assert!(store.get_ser::<u64>(ColPeerComponent, &key).unwrap().is_some());
+
+    // Loading the component back fails to remove it from disk, but the edge is loaded anyway.
+    scenario.configure(STORE_COMMIT, FailpointAction::Times(1));
+    routing_table.process_edges(vec![edge(&peer1, &peer2, 1)]);
+    assert_eq!(routing_table.get_edge(me, peer1).map(|edge| edge.nonce), Some(2));
+    assert!(store.get_ser::<u64>(ColPeerComponent, &key).unwrap().is_some());
+
+    assert_eq!(routing_table.repair_peer_components(), 1);
+    assert!(store.get_ser::<u64>(ColPeerComponent, &key).unwrap().is_none());
+}
@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+
+use near_network::routing::Edge;
+use near_network::routing_replay::{RoutingEvent, RoutingEventLog, RoutingReplay};
+use near_network::test_utils::random_peer_id;
+use near_primitives::network::PeerId;
+
+fn edge(peer0: &PeerId, peer1: &PeerId, nonce: u64) -> Edge {
+    let (peer0, peer1) = Edge::key(peer0.clone(), peer1.clone());
+    Edge::make_fake_edge(peer0, peer1, nonce)
+}
+
+fn routes(replay: &mut RoutingReplay) -> HashMap<PeerId, Vec<PeerId>> {
+    replay
+        .peer_forwarding()
+        .iter()
+        .map(|(peer_id, next_hops)| {
+            let mut next_hops = next_hops.clone();
+            next_hops.sort();
+            (peer_id.clone(), next_hops)
+        })
+        .collect()
+}
+
+#[test]
+fn replay_edge_stream() {
+    let (me, a, b, c) = (random_peer_id(), random_peer_id(), random_peer_id(), random_peer_id());
+    let mut log = RoutingEventLog::new(me.clone());
+    log.events.push(RoutingEvent::AddVerifiedEdges(vec![
+        edge(&me, &a, 1),
+        edge(&a, &b, 1),
+        edge(&b, &c, 1),
+    ]));
+
+    let mut replay = RoutingReplay::from_log(&log);
+    assert!(replay.is_consistent());
+    let via_a = vec![a.clone()];
+    assert_eq!(
+        routes(&mut replay),
+        vec![(a.clone(), via_a.clone()), (b.clone(), via_a.clone()), (c.clone(), via_a.clone())]
+            .into_iter()
+            .collect()
+    );
+
+    // A removal edge with a newer nonce makes `c` unreachable, a stale one is ignored.
+    replay.apply(RoutingEvent::AddVerifiedEdges(vec![edge(&b, &c, 2)]));
+    replay.apply(RoutingEvent::AddVerifiedEdges(vec![edge(&a, &b, 0)]));
+    assert!(replay.is_consistent());
+    assert_eq!(
+        routes(&mut replay),
+        vec![(a.clone(), via_a.clone()), (b.clone(), via_a.clone())].into_iter().collect()
+    );
+
+    // Pruned edges are forgotten by both the routing table and the routing table actor.
+    replay.apply(RoutingEvent::RemoveEdges(vec![edge(&a, &b, 1)]));
+    assert!(replay.is_consistent());
+    assert_eq!(routes(&mut replay), vec![(a, via_a)].into_iter().collect());
+}
+
+#[test]
+fn replay_from_file() {
+    let (me, a, b) = (random_peer_id(), random_peer_id(), random_peer_id());
+    let mut log = RoutingEventLog::new(me.clone());
+    log.events.push(RoutingEvent::AddVerifiedEdges(vec![edge(&me, &a, 1), edge(&me, &b, 1)]));
+    log.events.push(RoutingEvent::AddVerifiedEdges(vec![edge(&a, &b, 1)]));
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("events.bin");
+    log.write_to_file(&path).unwrap();
+    let read = RoutingEventLog::read_from_file(&path).unwrap();
+    assert_eq!(read, log);
+
+    // Replaying the dump of the replayed routing table leads to the same routes.
+    let mut replay = RoutingReplay::from_log(&read);
+    let expected = routes(&mut replay);
+    let mut from_dump =
+        RoutingReplay::from_log(&RoutingEventLog::from_dump(&replay.routing_table().dump()));
+    assert_eq!(routes(&mut from_dump), expected);
+}
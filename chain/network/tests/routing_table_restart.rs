@@ -0,0 +1,171 @@
+//! The component nonce, the components saved on disk and the account announcements must survive
+//! a restart of the routing table, including after a crash in the middle of any of its commits.
+//! Route back entries survive it too when `persist_route_back` is set.
+use std::sync::Arc;
+use std::time::Duration;
+
+use borsh::BorshDeserialize;
+use near_crypto::Signature;
+use near_network::routing::{Edge, RoutingTable};
+use near_network::test_utils::{random_epoch_id, random_peer_id};
+use near_network::types::{PruneConfig, RoutingTableConfig};
+use near_primitives::hash::hash;
+use near_primitives::network::{AnnounceAccount, PeerId};
+use near_primitives::utils::index_to_bytes;
+use near_store::test_utils::{create_crashing_store, create_test_store};
+use near_store::{ColComponentEdges, ColPeerComponent, Store};
+
+/// Writes done by `run`, in order.
+const ANNOUNCE_0: usize = 0;
+const SAVE_PEER_1: usize = 1;
+const ANNOUNCE_1: usize = 2;
+const SAVE_PEER_2: usize = 3;
+const LOAD_PEER_1: usize = 4;
+const TOTAL_WRITES: usize = 5;
+
+struct Peers {
+    me: PeerId,
+    peer1: PeerId,
+    peer2: PeerId,
+    peer3: PeerId,
+    announcements: Vec<AnnounceAccount>,
+}
+
+impl Peers {
+    fn new() -> Self {
+        let me = random_peer_id();
+        let announcements = ["near0", "near1"]
+            .iter()
+            .map(|account_id| AnnounceAccount {
+                account_id: account_id.parse().unwrap(),
+                peer_id: me.clone(),
+                epoch_id: random_epoch_id(),
+                signature: Signature::default(),
+            })
+            .collect();
+        Self {
+            me,
+            peer1: random_peer_id(),
+            peer2: random_peer_id(),
+            peer3: random_peer_id(),
+            announcements,
+        }
+    }
+}
+
+/// Remove the edge to `peer_id` and force pruning, which saves it to disk in a new component.
+fn save_peer(routing_table: &mut RoutingTable, me: &PeerId, peer_id: &PeerId) {
+    let edge =
+        Edge::new(me.clone(), peer_id.clone(), 2, Signature::default(), Signature::default());
+    routing_table.process_edges(vec![edge]);
+    routing_table.update(Some(PruneConfig::forced(Duration::from_secs(0))));
+}
+
+/// Edge making `peer_id` known again, which loads its component from disk.
+fn load_peer(routing_table: &mut RoutingTable, peer_id: &PeerId, other: &PeerId) {
+    let edge =
+        Edge::new(peer_id.clone(), other.clone(), 1, Signature::default(), Signature::default());
+    routing_table.process_edges(vec![edge]);
+}
+
+fn run(routing_table: &mut RoutingTable, peers: &Peers) {
+    routing_table.add_account(peers.announcements[0].clone());
+    save_peer(routing_table, &peers.me, &peers.peer1);
+    routing_table.add_account(peers.announcements[1].clone());
+    save_peer(routing_table, &peers.me, &peers.peer2);
+    load_peer(routing_table, &peers.peer1, &peers.peer3);
+}
+
+/// Every peer saved on disk points to a component which is saved too, and older than the next
+/// component nonce.
+fn check_components(store: &Store, component_nonce: u64) {
+    for (peer_id, nonce) in store.iter(ColPeerComponent) {
+        let nonce = u64::try_from_slice(nonce.as_ref()).unwrap();
+        assert!(nonce < component_nonce, "{:?} in component {}", peer_id, nonce);
+        let edges = store.get_ser::<Vec<Edge>>(ColComponentEdges, &index_to_bytes(nonce)).unwrap();
+        assert!(edges.is_some(), "component {} is missing", nonce);
+    }
+}
+
+fn is_saved(store: &Arc<Store>, peer_id: &PeerId) -> bool {
+    store.get_ser::<u64>(ColPeerComponent, &Vec::from(peer_id.clone())).unwrap().is_some()
+}
+
+#[test]
+fn restart_after_each_write() {
+    for crash_after in 0..=TOTAL_WRITES {
+        let peers = Peers::new();
+        let (db, store) = create_crashing_store();
+        db.crash_after_writes(crash_after);
+        let mut routing_table = RoutingTable::new(peers.me.clone(), store.clone());
+        run(&mut routing_table, &peers);
+        drop(routing_table);
+
+        db.restart();
+        let mut routing_table = RoutingTable::new(peers.me.clone(), store.clone());
+        let applied = |write: usize| write < crash_after;
+        let saved_components = applied(SAVE_PEER_1) as u64 + applied(SAVE_PEER_2) as u64;
+        assert_eq!(routing_table.component_nonce, saved_components, "crash after {}", crash_after);
+        check_components(&store, routing_table.component_nonce);
+
+        for (write, announce) in [ANNOUNCE_0, ANNOUNCE_1].iter().zip(&peers.announcements) {
+            let restored = routing_table.get_announce(&announce.account_id);
+            assert_eq!(restored.as_ref(), Some(announce).filter(|_| applied(*write)));
+        }
+
+        let peer1_saved = applied(SAVE_PEER_1) && !applied(LOAD_PEER_1);
+        assert_eq!(is_saved(&store, &peers.peer1), peer1_saved);
+        assert_eq!(is_saved(&store, &peers.peer2), applied(SAVE_PEER_2));
+        if peer1_saved {
+            // The removed edge is loaded back with its component.
+            load_peer(&mut routing_table, &peers.peer1, &peers.peer3);
+            let edge = routing_table.get_edge(peers.me.clone(), peers.peer1.clone()).unwrap();
+            assert_eq!(edge.nonce, 2);
+            assert!(!is_saved(&store, &peers.peer1));
+            check_components(&store, routing_table.component_nonce);
+        }
+    }
+}
+
+#[test]
+fn component_nonce_increases_across_restarts() {
+    let peers = Peers::new();
+    let store = create_test_store();
+    let mut routing_table = RoutingTable::new(peers.me.clone(), store.clone());
+    save_peer(&mut routing_table, &peers.me, &peers.peer1);
+    drop(routing_table);
+
+    let mut routing_table = RoutingTable::new(peers.me.clone(), store.clone());
+    assert_eq!(routing_table.component_nonce, 1);
+    save_peer(&mut routing_table, &peers.me, &peers.peer2);
+    drop(routing_table);
+
+    let routing_table = RoutingTable::new(peers.me.clone(), store.clone());
+    assert_eq!(routing_table.component_nonce, 2);
+    check_components(&store, routing_table.component_nonce);
+    assert!(is_saved(&store, &peers.peer1) && is_saved(&store, &peers.peer2));
+}
+
+#[test]
+fn route_back_survives_restart() {
+    let peers = Peers::new();
+    let store = create_test_store();
+    let config = RoutingTableConfig { persist_route_back: true, ..Default::default() };
+    let mut routing_table =
+        RoutingTable::with_config(peers.me.clone(), store.clone(), config.clone());
+    routing_table.add_route_back(hash(&[0]), peers.peer1.clone());
+    routing_table.save_route_back();
+    drop(routing_table);
+
+    let routing_table = RoutingTable::with_config(peers.me.clone(), store.clone(), config);
+    assert_eq!(routing_table.route_back.get(&hash(&[0])), Some(&peers.peer1));
+
+    // Without persistence nothing is saved.
+    let mut routing_table = RoutingTable::new(peers.me.clone(), store.clone());
+    routing_table.add_route_back(hash(&[1]), peers.peer2.clone());
+    routing_table.save_route_back();
+    drop(routing_table);
+    let config = RoutingTableConfig { persist_route_back: true, ..Default::default() };
+    let routing_table = RoutingTable::with_config(peers.me.clone(), store, config);
+    assert_eq!(routing_table.route_back.get(&hash(&[1])), None);
+}
@@ -101,6 +101,10 @@ pub struct ClientConfig {
     /// genesis file.  The value only affects the RPCs without influencing the
     /// protocol thus changing it per-node doesn’t affect the blockchain.
     pub max_gas_burnt_view: Option<Gas>,
+    /// Drop account announcements of accounts which don't exist on chain or aren't validators of
+    /// the announced epoch, and remember them for a while. Can be disabled on light deployments
+    /// to save the state lookups.
+    pub check_announce_account_existence: bool,
 }
 
 impl ClientConfig {
@@ -159,6 +163,7 @@ impl ClientConfig {
             view_client_throttle_period: Duration::from_secs(1),
             trie_viewer_state_size_limit: None,
             max_gas_burnt_view: None,
+            check_announce_account_existence: true,
         }
     }
 }
@@ -1,8 +1,8 @@
 pub use errors::{ParseKeyError, ParseKeyTypeError, ParseSignatureError};
 pub use key_file::KeyFile;
 pub use signature::{
-    ED25519PublicKey, KeyType, PublicKey, Secp256K1PublicKey, Secp256K1Signature, SecretKey,
-    Signature,
+    verify_batch, ED25519PublicKey, KeyType, PublicKey, Secp256K1PublicKey, Secp256K1Signature,
+    SecretKey, Signature,
 };
 pub use signer::{EmptySigner, InMemorySigner, Signer};
 
@@ -745,6 +745,83 @@ impl Signature {
     }
 }
 
+/// Verifies a batch of signatures, returns `true` only if all of them are valid.
+///
+/// ED25519 signatures are checked together using a single multiscalar multiplication, which is
+/// about twice as fast as checking them one by one. If the combined check fails (or the batch
+/// contains other kinds of signatures) they are verified one by one, so this never rejects a
+/// signature accepted by `Signature::verify`.
+pub fn verify_batch(items: &[(&[u8], &Signature, &PublicKey)]) -> bool {
+    let ed25519_items: Vec<_> = items
+        .iter()
+        .filter_map(|(data, signature, public_key)| match (signature, public_key) {
+            (Signature::ED25519(signature), PublicKey::ED25519(public_key)) => {
+                Some((*data, signature, public_key))
+            }
+            _ => None,
+        })
+        .collect();
+    if ed25519_items.len() > 1 && ed25519_items.len() == items.len() {
+        if ed25519_verify_batch(&ed25519_items) {
+            return true;
+        }
+    }
+    items.iter().all(|(data, signature, public_key)| signature.verify(data, public_key))
+}
+
+/// Checks `sum(z_i * (R_i + k_i * A_i - s_i * B)) == 0` for random 128-bit `z_i`.
+///
+/// The check is cofactorless, so it only matches `Signature::verify` if no `R_i` or `A_i` has a
+/// small order component: random `z_i` could cancel it out. Such batches are rejected, and left
+/// to the checks one by one.
+fn ed25519_verify_batch(items: &[(&[u8], &ed25519_dalek::Signature, &ED25519PublicKey)]) -> bool {
+    use curve25519_dalek::constants::ED25519_BASEPOINT_POINT;
+    use curve25519_dalek::edwards::{CompressedEdwardsY, EdwardsPoint};
+    use curve25519_dalek::scalar::Scalar;
+    use curve25519_dalek::traits::{IsIdentity, VartimeMultiscalarMul};
+    use rand::Rng;
+    use sha2::Digest;
+
+    let mut rng = rand::rngs::OsRng;
+    let mut basepoint_scalar = Scalar::zero();
+    let mut scalars = Vec::with_capacity(2 * items.len() + 1);
+    let mut points = Vec::with_capacity(2 * items.len() + 1);
+    for (data, signature, public_key) in items {
+        let bytes = signature.to_bytes();
+        let mut r_bytes = [0u8; 32];
+        r_bytes.copy_from_slice(&bytes[..32]);
+        let mut s_bytes = [0u8; 32];
+        s_bytes.copy_from_slice(&bytes[32..]);
+
+        // Reject non canonical encodings and points with a torsion component here,
+        // `Signature::verify` will decide about them.
+        let r = match CompressedEdwardsY(r_bytes).decompress() {
+            Some(r) if r.compress().to_bytes() == r_bytes && r.is_torsion_free() => r,
+            _ => return false,
+        };
+        let s = match Scalar::from_canonical_bytes(s_bytes) {
+            Some(s) => s,
+            None => return false,
+        };
+        let a = match CompressedEdwardsY(public_key.0).decompress() {
+            Some(a) if !a.is_small_order() && a.is_torsion_free() => a,
+            _ => return false,
+        };
+        let k =
+            Scalar::from_hash(sha2::Sha512::new().chain(&r_bytes).chain(&public_key.0).chain(data));
+        let z = Scalar::from(rng.gen::<u128>());
+
+        basepoint_scalar -= z * s;
+        scalars.push(z);
+        points.push(r);
+        scalars.push(z * k);
+        points.push(a);
+    }
+    scalars.push(basepoint_scalar);
+    points.push(ED25519_BASEPOINT_POINT);
+    EdwardsPoint::vartime_multiscalar_mul(scalars, points).is_identity()
+}
+
 impl Default for Signature {
     fn default() -> Self {
         Signature::empty(KeyType::ED25519)
@@ -885,6 +962,51 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_verify_batch() {
+        let data: Vec<Vec<u8>> = (0..10u8).map(|i| vec![i; 32]).collect();
+        let keys: Vec<SecretKey> = (0..10)
+            .map(|i| SecretKey::from_seed(KeyType::ED25519, &format!("test{}", i)))
+            .collect();
+        let public_keys: Vec<PublicKey> = keys.iter().map(|key| key.public_key()).collect();
+        let mut signatures: Vec<Signature> =
+            keys.iter().zip(data.iter()).map(|(key, data)| key.sign(data)).collect();
+        let verify_all = |signatures: &[Signature]| -> bool {
+            let items: Vec<_> = data
+                .iter()
+                .zip(signatures.iter())
+                .zip(public_keys.iter())
+                .map(|((data, signature), public_key)| (data.as_slice(), signature, public_key))
+                .collect();
+            verify_batch(&items)
+        };
+        assert!(verify_all(&signatures));
+
+        signatures.swap(3, 4);
+        assert!(!verify_all(&signatures));
+    }
+
+    #[test]
+    fn test_verify_batch_small_order_key() {
+        // `y = 0` is a point of order 4, and the identity is `R`. `R == k * A + s * B` only
+        // holds for some messages, so a cofactorless batch would accept the others by chance.
+        let public_key = PublicKey::ED25519(ED25519PublicKey([0; 32]));
+        let mut signature_bytes = [0; ed25519_dalek::SIGNATURE_LENGTH];
+        signature_bytes[0] = 1;
+        let signature = Signature::from_parts(KeyType::ED25519, &signature_bytes).unwrap();
+        let data: Vec<Vec<u8>> = (0..=u8::MAX)
+            .map(|i| vec![i; 32])
+            .filter(|data| !signature.verify(data, &public_key))
+            .take(2)
+            .collect();
+        assert_eq!(data.len(), 2);
+        let items: Vec<_> =
+            data.iter().map(|data| (data.as_slice(), &signature, &public_key)).collect();
+        for _ in 0..32 {
+            assert!(!verify_batch(&items));
+        }
+    }
+
     #[test]
     fn test_json_serialize_ed25519() {
         let sk = SecretKey::from_seed(KeyType::ED25519, "test");
@@ -13,7 +13,7 @@ pub struct Version {
 pub type DbVersion = u32;
 
 /// Current version of the database.
-pub const DB_VERSION: DbVersion = 28;
+pub const DB_VERSION: DbVersion = 34;
 
 /// Protocol version type.
 pub use near_primitives_core::types::ProtocolVersion;
@@ -115,6 +115,12 @@ pub enum ProtocolFeature {
     /// Lowers the cost of wasm instruction due to switch to wasmer2.
     LowerRegularOpCost,
 
+    // network features
+    /// Exchange routing tables using IBF (`RoutingTableSyncV2`). It doesn't change the chain, so
+    /// the network layer enables it for each connection based on the protocol version negotiated
+    /// in the handshake, falling back to sending the full routing table with older peers.
+    RoutingExchangeAlgorithm,
+
     // nightly features
     #[cfg(feature = "protocol_feature_block_header_v3")]
     BlockHeaderV3,
@@ -122,12 +128,37 @@ pub enum ProtocolFeature {
     AltBn128,
     #[cfg(feature = "protocol_feature_chunk_only_producers")]
     ChunkOnlyProducers,
-    #[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
-    RoutingExchangeAlgorithm,
     /// Limit number of wasm functions in one contract. See
     /// <https://github.com/near/nearcore/pull/4954> for more details.
     #[cfg(feature = "protocol_feature_limit_contract_functions_number")]
     LimitContractFunctionsNumber,
+    /// Use XXH64 instead of SipHash to hash elements of IBFs used to exchange routing tables.
+    #[cfg(feature = "protocol_feature_fast_ibf_hasher")]
+    FastIbfHasher,
+    /// Send peer ids of routing table syncs and routed messages as indices into a per connection
+    /// table of recently used peer ids.
+    #[cfg(feature = "protocol_feature_compact_peer_id")]
+    CompactPeerId,
+    /// Ask peers which of a sample of peers they can route messages to, to detect diverging
+    /// routing tables.
+    #[cfg(feature = "protocol_feature_reachability_probe")]
+    ReachabilityProbe,
+    /// Nodes may sign their edges with a "network sub-key" delegated by their node key, and
+    /// announce the delegation in the handshake and with `SubKeyDelegations`.
+    #[cfg(feature = "protocol_feature_network_sub_keys")]
+    NetworkSubKeys,
+    /// On reconnect, only send the edges changed since the watermark the peer received from us
+    /// (`RoutingTableDeltaRequest`).
+    #[cfg(feature = "protocol_feature_routing_delta_sync")]
+    RoutingDeltaSync,
+    /// Peers advertise digests of recently changed edges, and pull the ones they miss, so new
+    /// edges don't need to be pushed to every peer (`EdgeDigest`, `EdgePull`).
+    #[cfg(feature = "protocol_feature_edge_gossip_digests")]
+    EdgeGossipDigests,
+    /// Peers advertise the optional features they support in the handshake, and connections use
+    /// those both sides advertised (`PeerCapabilities`).
+    #[cfg(feature = "protocol_feature_handshake_capabilities")]
+    HandshakeCapabilities,
 }
 
 /// Current latest stable version of the protocol.
@@ -138,7 +169,7 @@ pub const PROTOCOL_VERSION: ProtocolVersion = 48;
 
 /// Current latest nightly version of the protocol.
 #[cfg(feature = "nightly_protocol")]
-pub const PROTOCOL_VERSION: ProtocolVersion = 123;
+pub const PROTOCOL_VERSION: ProtocolVersion = 130;
 
 impl ProtocolFeature {
     pub const fn protocol_version(self) -> ProtocolVersion {
@@ -162,6 +193,9 @@ impl ProtocolFeature {
             | ProtocolFeature::LowerRegularOpCost
             | ProtocolFeature::SimpleNightshade => 48,
 
+            // Network features
+            ProtocolFeature::RoutingExchangeAlgorithm => 117,
+
             // Nightly features
             #[cfg(feature = "protocol_feature_alt_bn128")]
             ProtocolFeature::AltBn128 => 105,
@@ -169,10 +203,22 @@ impl ProtocolFeature {
             ProtocolFeature::BlockHeaderV3 => 109,
             #[cfg(feature = "protocol_feature_chunk_only_producers")]
             ProtocolFeature::ChunkOnlyProducers => 115,
-            #[cfg(feature = "protocol_feature_routing_exchange_algorithm")]
-            ProtocolFeature::RoutingExchangeAlgorithm => 117,
             #[cfg(feature = "protocol_feature_limit_contract_functions_number")]
             ProtocolFeature::LimitContractFunctionsNumber => 123,
+            #[cfg(feature = "protocol_feature_fast_ibf_hasher")]
+            ProtocolFeature::FastIbfHasher => 124,
+            #[cfg(feature = "protocol_feature_compact_peer_id")]
+            ProtocolFeature::CompactPeerId => 125,
+            #[cfg(feature = "protocol_feature_reachability_probe")]
+            ProtocolFeature::ReachabilityProbe => 126,
+            #[cfg(feature = "protocol_feature_network_sub_keys")]
+            ProtocolFeature::NetworkSubKeys => 130,
+            #[cfg(feature = "protocol_feature_routing_delta_sync")]
+            ProtocolFeature::RoutingDeltaSync => 127,
+            #[cfg(feature = "protocol_feature_edge_gossip_digests")]
+            ProtocolFeature::EdgeGossipDigests => 128,
+            #[cfg(feature = "protocol_feature_handshake_capabilities")]
+            ProtocolFeature::HandshakeCapabilities => 129,
         }
     }
 }
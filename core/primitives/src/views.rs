@@ -308,6 +308,21 @@ pub struct ValidatorInfo {
     pub is_slashed: bool,
 }
 
+/// State of the recalculation of the routing table, to tell stale routes caused by missing
+/// edges from a stuck recalculation loop.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct RoutingRecalculationView {
+    /// When the last recalculation finished, `None` if there was none yet.
+    pub last_recalculation_time: Option<DateTime<Utc>>,
+    /// How long the last recalculation took, in milliseconds.
+    pub last_recalculation_duration_ms: Option<u64>,
+    /// Edges added to or removed from the graph since the last recalculation.
+    pub edges_since_last_recalculation: u64,
+    /// Batches of edges waiting for verification and recalculations waiting for the one in
+    /// progress.
+    pub queue_depth: u64,
+}
+
 // TODO: add more information to status.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct StatusResponse {
@@ -328,6 +343,9 @@ pub struct StatusResponse {
     pub sync_info: StatusSyncInfo,
     /// Validator id of the node
     pub validator_account_id: Option<AccountId>,
+    /// Recalculation of the routing table.
+    #[serde(default)]
+    pub routing_recalculation: RoutingRecalculationView,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -121,10 +121,20 @@ pub enum DBCol {
     ColHeaderHashesByHeight = 48,
     /// State changes made by a chunk, used for splitting states
     ColStateChangesForSplitStates = 49,
+    /// Lifetime counters of the edges processed by the routing table
+    ColEdgeStats = 50,
+    /// Healthiest peers we were connected to, dialed first on startup
+    ColPeerCache = 51,
+    /// Previous hops of routed messages waiting for a response, saved on shutdown
+    ColRouteBack = 52,
+    /// Connection quality history of known peers, used to choose outbound connections
+    ColPeerQuality = 53,
+    /// Adversarial and admin requests which mutated the routing state, in order
+    ColRoutingAuditLog = 54,
 }
 
 // Do not move this line from enum DBCol
-pub const NUM_COLS: usize = 50;
+pub const NUM_COLS: usize = 55;
 
 impl std::fmt::Display for DBCol {
     fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
@@ -181,6 +191,11 @@ impl std::fmt::Display for DBCol {
             Self::ColStateChangesForSplitStates => {
                 "state changes indexed by block hash and shard id"
             }
+            Self::ColEdgeStats => "edge statistics",
+            Self::ColPeerCache => "healthiest peers",
+            Self::ColRouteBack => "previous hops of routed messages",
+            Self::ColPeerQuality => "connection quality history of peers",
+            Self::ColRoutingAuditLog => "audit log of routing mutations",
         };
         write!(formatter, "{}", desc)
     }
@@ -209,6 +224,11 @@ lazy_static! {
         col_gc[DBCol::ColPeerComponent as usize] = false; // Peer related info doesn't GC
         col_gc[DBCol::ColLastComponentNonce as usize] = false;
         col_gc[DBCol::ColComponentEdges as usize] = false;
+        col_gc[DBCol::ColEdgeStats as usize] = false;
+        col_gc[DBCol::ColPeerCache as usize] = false;
+        col_gc[DBCol::ColRouteBack as usize] = false;
+        col_gc[DBCol::ColPeerQuality as usize] = false;
+        col_gc[DBCol::ColRoutingAuditLog as usize] = false;
         col_gc[DBCol::ColBlockOrdinal as usize] = false;
         col_gc[DBCol::ColEpochInfo as usize] = false; // https://github.com/nearprotocol/nearcore/pull/2952
         col_gc[DBCol::ColEpochValidatorInfo as usize] = false; // https://github.com/nearprotocol/nearcore/pull/2952
@@ -0,0 +1,180 @@
+//! Failpoints: named places in the code where tests can deterministically inject failures, e.g.
+//! a store commit returning an error or a message being dropped, to check that the error branches
+//! degrade gracefully.
+//!
+//! Failpoints only fire in builds with the `failpoints` feature. Without it `fail_point` always
+//! returns false, and the compiler removes the failure branches.
+//!
+//! Like mocked time in the network crate, failpoints are configured per thread, so tests running
+//! concurrently don't affect each other. Work moved to other threads must capture a
+//! `FailpointsHandle` on the spawning thread and install it in the worker.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Fails `StoreUpdate::commit` with an IO error, before anything is written.
+pub const STORE_COMMIT: &str = "store::commit";
+
+/// When a configured failpoint fires.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailpointAction {
+    /// Fire every time the failpoint is reached.
+    Always,
+    /// Fire the next `n` times the failpoint is reached, then never again.
+    Times(usize),
+    /// Don't fire the next `n` times the failpoint is reached, then fire every time.
+    After(usize),
+}
+
+type Failpoints = Arc<Mutex<HashMap<String, FailpointAction>>>;
+
+thread_local! {
+    /// Failpoints configured on this thread while a `FailScenario` is installed.
+    static FAILPOINTS: RefCell<Option<Failpoints>> = RefCell::new(None);
+}
+
+/// Whether the failpoint `name` fires now, in which case the caller should take its failure
+/// branch.
+#[cfg(feature = "failpoints")]
+pub fn fail_point(name: &str) -> bool {
+    FAILPOINTS.with(|failpoints| {
+        let failpoints = failpoints.borrow();
+        let mut failpoints = match failpoints.as_ref() {
+            Some(failpoints) => failpoints.lock().unwrap(),
+            None => return false,
+        };
+        match failpoints.get_mut(name) {
+            None => false,
+            Some(FailpointAction::Always) => true,
+            Some(FailpointAction::Times(0)) => false,
+            Some(FailpointAction::Times(n)) => {
+                *n -= 1;
+                true
+            }
+            Some(FailpointAction::After(0)) => true,
+            Some(FailpointAction::After(n)) => {
+                *n -= 1;
+                false
+            }
+        }
+    })
+}
+
+#[cfg(not(feature = "failpoints"))]
+#[inline(always)]
+pub fn fail_point(_name: &str) -> bool {
+    false
+}
+
+/// Failpoints configured by a test on the current thread, removed when dropped.
+pub struct FailScenario {
+    failpoints: Failpoints,
+    _guard: FailpointsGuard,
+}
+
+impl FailScenario {
+    pub fn setup() -> Self {
+        let failpoints = Failpoints::default();
+        Self { _guard: FailpointsHandle(Some(failpoints.clone())).install(), failpoints }
+    }
+
+    /// Configure when the failpoint `name` fires, replacing its previous action.
+    pub fn configure(&self, name: &str, action: FailpointAction) {
+        self.failpoints.lock().unwrap().insert(name.to_string(), action);
+    }
+
+    /// The failpoint `name` never fires again.
+    pub fn remove(&self, name: &str) {
+        self.failpoints.lock().unwrap().remove(name);
+    }
+}
+
+/// Failpoints used by a thread, if any.
+#[derive(Clone, Default)]
+pub struct FailpointsHandle(Option<Failpoints>);
+
+impl FailpointsHandle {
+    /// Failpoints of the current thread. Capture them before moving work to another thread.
+    pub fn current() -> Self {
+        Self(FAILPOINTS.with(|failpoints| failpoints.borrow().clone()))
+    }
+
+    /// Use these failpoints on the current thread until the returned guard is dropped.
+    pub fn install(self) -> FailpointsGuard {
+        let previous = FAILPOINTS.with(|failpoints| failpoints.replace(self.0));
+        FailpointsGuard { previous }
+    }
+}
+
+/// Restores the previous failpoints of the thread on drop.
+pub struct FailpointsGuard {
+    previous: Option<Failpoints>,
+}
+
+impl Drop for FailpointsGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        FAILPOINTS.with(|failpoints| *failpoints.borrow_mut() = previous);
+    }
+}
+
+#[cfg(all(test, feature = "failpoints"))]
+mod tests {
+    use std::thread;
+
+    use crate::test_utils::create_test_store;
+    use crate::DBCol::ColBlockMisc;
+
+    use super::{fail_point, FailScenario, FailpointAction, FailpointsHandle, STORE_COMMIT};
+
+    #[test]
+    fn failpoint_actions() {
+        let scenario = FailScenario::setup();
+        assert!(!fail_point("test::always"));
+        scenario.configure("test::always", FailpointAction::Always);
+        scenario.configure("test::times", FailpointAction::Times(2));
+        scenario.configure("test::after", FailpointAction::After(2));
+
+        let fired = |name| (0..4).map(|_| fail_point(name)).collect::<Vec<_>>();
+        assert_eq!(fired("test::always"), vec![true; 4]);
+        assert_eq!(fired("test::times"), vec![true, true, false, false]);
+        assert_eq!(fired("test::after"), vec![false, false, true, true]);
+
+        scenario.remove("test::always");
+        assert!(!fail_point("test::always"));
+        drop(scenario);
+        assert!(!fail_point("test::after"));
+    }
+
+    #[test]
+    fn failpoints_follow_worker_thread() {
+        let scenario = FailScenario::setup();
+        scenario.configure("test::worker", FailpointAction::Always);
+
+        let handle = FailpointsHandle::current();
+        let worker = move || {
+            let _guard = handle.install();
+            fail_point("test::worker")
+        };
+        assert!(thread::spawn(worker).join().unwrap());
+        // Threads without the handle don't see the failpoints.
+        assert!(!thread::spawn(|| fail_point("test::worker")).join().unwrap());
+    }
+
+    #[test]
+    fn store_commit_failpoint() {
+        let scenario = FailScenario::setup();
+        let store = create_test_store();
+        scenario.configure(STORE_COMMIT, FailpointAction::Times(1));
+
+        let mut update = store.store_update();
+        update.set(ColBlockMisc, b"key", b"value");
+        assert!(update.commit().is_err());
+        assert_eq!(store.get(ColBlockMisc, b"key").unwrap(), None);
+
+        let mut update = store.store_update();
+        update.set(ColBlockMisc, b"key", b"value");
+        update.commit().unwrap();
+        assert_eq!(store.get(ColBlockMisc, b"key").unwrap(), Some(b"value".to_vec()));
+    }
+}
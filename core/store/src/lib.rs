@@ -41,6 +41,7 @@ pub use crate::trie::{
 };
 
 pub mod db;
+pub mod failpoints;
 pub mod migrations;
 pub mod test_utils;
 mod trie;
@@ -253,6 +254,9 @@ impl StoreUpdate {
             "Transaction overwrites itself: {:?}",
             self
         );
+        if failpoints::fail_point(failpoints::STORE_COMMIT) {
+            return Err(io::Error::new(io::ErrorKind::Other, "Injected commit failure"));
+        }
         if let Some(tries) = self.tries {
             assert_eq!(
                 tries.get_store().storage.deref() as *const _,
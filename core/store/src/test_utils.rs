@@ -1,11 +1,12 @@
 use std::collections::{HashMap, HashSet};
-use std::sync::Arc;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
 
 use rand::seq::SliceRandom;
 use rand::Rng;
 
-use crate::db::TestDB;
-use crate::{ShardTries, Store};
+use crate::db::{DBError, DBTransaction, Database, TestDB};
+use crate::{DBCol, ShardTries, Store};
 use near_primitives::account::id::AccountId;
 use near_primitives::hash::CryptoHash;
 use near_primitives::receipt::{DataReceipt, Receipt, ReceiptEnum};
@@ -19,6 +20,73 @@ pub fn create_test_store() -> Arc<Store> {
     Arc::new(Store::new(db))
 }
 
+/// In-memory database simulating a crash of the node: once `crash_after_writes` more writes were
+/// applied, every later write is dropped, as if the process died while committing it. Reads keep
+/// seeing only what was written before the crash, like after a restart.
+pub struct CrashingDB {
+    db: TestDB,
+    /// Writes left until the crash, none if no crash is scheduled.
+    writes_until_crash: Mutex<Option<usize>>,
+}
+
+impl Default for CrashingDB {
+    fn default() -> Self {
+        Self { db: TestDB::new(), writes_until_crash: Mutex::new(None) }
+    }
+}
+
+impl CrashingDB {
+    /// Apply the next `writes` writes, and drop every write after them.
+    pub fn crash_after_writes(&self, writes: usize) {
+        *self.writes_until_crash.lock().unwrap() = Some(writes);
+    }
+
+    /// Apply writes again, like a node restarted after a crash.
+    pub fn restart(&self) {
+        *self.writes_until_crash.lock().unwrap() = None;
+    }
+}
+
+impl Database for CrashingDB {
+    fn get(&self, col: DBCol, key: &[u8]) -> Result<Option<Vec<u8>>, DBError> {
+        self.db.get(col, key)
+    }
+
+    fn iter<'a>(&'a self, col: DBCol) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+        self.db.iter(col)
+    }
+
+    fn iter_without_rc_logic<'a>(
+        &'a self,
+        col: DBCol,
+    ) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+        self.db.iter_without_rc_logic(col)
+    }
+
+    fn iter_prefix<'a>(
+        &'a self,
+        col: DBCol,
+        key_prefix: &'a [u8],
+    ) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+        self.db.iter_prefix(col, key_prefix)
+    }
+
+    fn write(&self, transaction: DBTransaction) -> Result<(), DBError> {
+        match self.writes_until_crash.lock().unwrap().as_mut() {
+            Some(0) => return Ok(()),
+            Some(writes) => *writes -= 1,
+            None => {}
+        }
+        self.db.write(transaction)
+    }
+}
+
+/// Creates a store backed by a `CrashingDB`, returned to schedule crashes.
+pub fn create_crashing_store() -> (Pin<Arc<CrashingDB>>, Arc<Store>) {
+    let db = Arc::pin(CrashingDB::default());
+    (db.clone(), Arc::new(Store::new(db)))
+}
+
 /// Creates a Trie using an in-memory database.
 pub fn create_tries() -> ShardTries {
     let store = create_test_store();
@@ -927,6 +927,7 @@ fn client_sync_headers() {
             received_bytes_per_sec: 0,
             known_producers: vec![],
             peer_counter: 0,
+            routing_recalculation: Default::default(),
         }));
         wait_or_panic(2000);
     });
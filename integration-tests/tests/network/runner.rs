@@ -116,9 +116,9 @@ pub enum Action {
     CheckRoutingTable(usize, Vec<(usize, Vec<usize>)>),
     CheckAccountId(usize, Vec<usize>),
     // Send ping from `source` with `nonce` to `target`
-    PingTo(usize, usize, usize),
+    PingTo(usize, u64, usize),
     // Check for `source` received pings and pongs.
-    CheckPingPong(usize, Vec<(usize, usize, Option<usize>)>, Vec<(usize, usize, Option<usize>)>),
+    CheckPingPong(usize, Vec<(u64, usize, Option<usize>)>, Vec<(u64, usize, Option<usize>)>),
     // Send stop signal to some node.
     Stop(usize),
     // Wait time in milliseconds
@@ -165,6 +165,7 @@ impl StateMachine {
                                 disable_edge_propagation: None,
                                 disable_edge_pruning: None,
                                 set_max_peers: max_num_peers,
+                                caller: "test runner".to_string(),
                             })
                             .then(move |res| match res {
                                 Ok(_) => {
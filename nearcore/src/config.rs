@@ -1,7 +1,8 @@
+use std::collections::HashMap;
 use std::fs;
 use std::fs::File;
 use std::io::{Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -9,7 +10,7 @@ use actix;
 use chrono::Utc;
 use num_rational::Rational;
 use serde::{Deserialize, Serialize};
-use tracing::info;
+use tracing::{info, warn};
 
 use near_chain_configs::{
     get_initial_supply, ClientConfig, Genesis, GenesisConfig, LogSummaryStyle,
@@ -18,11 +19,16 @@ use near_crypto::{InMemorySigner, KeyFile, KeyType, PublicKey, Signer};
 #[cfg(feature = "json_rpc")]
 use near_jsonrpc::RpcConfig;
 use near_network::test_utils::open_port;
-use near_network::types::ROUTED_MESSAGE_TTL;
+use near_network::types::{
+    BandwidthConfig, CompressionConfig, EdgeGossipConfig, IpFamily, ListenAddr, NetworkSubKey,
+    PeerInfo, ProxyConfig, ReloadableNetworkConfig, RoutingReplicaConfig, RoutingStrategy,
+    RoutingTableConfig, SendQueueConfig, TlsConfig, ROUTED_MESSAGE_TTL,
+};
 use near_network::utils::blacklist_from_iter;
 use near_network::NetworkConfig;
 use near_primitives::account::{AccessKey, Account};
 use near_primitives::hash::CryptoHash;
+use near_primitives::network::PeerId;
 use near_primitives::shard_layout::ShardLayout;
 use near_primitives::state_record::StateRecord;
 use near_primitives::types::{
@@ -189,6 +195,97 @@ fn default_ttl_account_id_router() -> Duration {
 fn default_peer_stats_period() -> Duration {
     Duration::from_secs(5)
 }
+/// Period between latency pings sent to every active peer
+fn default_latency_ping_period() -> Option<Duration> {
+    Some(Duration::from_secs(30))
+}
+/// Number of threads verifying edge signatures
+fn default_edge_verifier_threads() -> usize {
+    4
+}
+/// Number of threads computing routes of big routing graphs
+fn default_routing_threads() -> usize {
+    2
+}
+/// Maximum number of edges verified in one batch
+fn default_edge_verifier_batch_size() -> usize {
+    256
+}
+/// Minimum age of a connection before sending all known edges to the peer
+fn default_heavy_sync_min_connection_age() -> Duration {
+    Duration::from_secs(3)
+}
+/// Maximum number of peers receiving all known edges at the same time
+fn default_max_concurrent_heavy_syncs() -> usize {
+    8
+}
+/// Whether routing tables are exchanged with IBF when the peer supports it
+fn default_ibf_sync_enabled() -> bool {
+    true
+}
+/// Number of edges below which peers exchange all edges instead of syncing with IBF
+fn default_ibf_sync_min_edges() -> u64 {
+    100
+}
+/// Edges per second accepted from each peer
+fn default_edge_rate_limit() -> u64 {
+    2_000
+}
+/// Edges accepted from each peer at once
+fn default_edge_rate_limit_burst() -> u64 {
+    100_000
+}
+fn default_tier1_enabled() -> bool {
+    true
+}
+fn default_max_inbound_peers_per_subnet() -> u32 {
+    8
+}
+fn default_cold_start_peers() -> usize {
+    10
+}
+fn default_cold_start_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+fn default_anchor_unreachable_timeout() -> Duration {
+    Duration::from_secs(300)
+}
+fn default_dns_seeds_period() -> Duration {
+    Duration::from_secs(30 * 60)
+}
+fn default_announce_account_cache_size() -> usize {
+    RoutingTableConfig::default().announce_account_cache_size
+}
+fn default_route_back_cache_size() -> u64 {
+    RoutingTableConfig::default().route_back_cache_size
+}
+fn default_route_back_cache_evict_timeout() -> Duration {
+    RoutingTableConfig::default().route_back_cache_evict_timeout
+}
+fn default_route_back_cache_remove_batch() -> u64 {
+    RoutingTableConfig::default().route_back_cache_remove_batch
+}
+fn default_route_back_cache_peer_quota() -> u64 {
+    RoutingTableConfig::default().route_back_cache_peer_quota
+}
+fn default_save_peers_max_time() -> Duration {
+    RoutingTableConfig::default().save_peers_max_time
+}
+fn default_save_peers_after_time() -> Duration {
+    RoutingTableConfig::default().save_peers_after_time
+}
+fn default_max_unreachable_graph_nodes() -> usize {
+    RoutingTableConfig::default().max_unreachable_graph_nodes
+}
+fn default_max_edges_in_memory() -> usize {
+    RoutingTableConfig::default().max_edges_in_memory
+}
+fn default_max_saved_components() -> u64 {
+    RoutingTableConfig::default().max_saved_components
+}
+fn default_max_announcement_hops() -> u32 {
+    RoutingTableConfig::default().max_announcement_hops
+}
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Network {
@@ -234,12 +331,208 @@ pub struct Network {
     /// It can be IP:Port or IP (to blacklist all connections coming from this address).
     #[serde(default)]
     pub blacklist: Vec<String>,
+    /// Nodes always accepted, even when at `max_num_peers` or when their subnet is full, e.g. a
+    /// validator's own relays. Each one is `peer_id@ip:port`.
+    #[serde(default)]
+    pub whitelist_nodes: Vec<String>,
+    /// Prefixes of peer ids of known attack infrastructure, e.g. `ed25519:Abc`. Edges and account
+    /// announcements referencing a matching peer are rejected.
+    #[serde(default)]
+    pub denylisted_key_prefixes: Vec<String>,
     /// Time to persist Accounts Id in the router without removing them in seconds.
     #[serde(default = "default_ttl_account_id_router")]
     pub ttl_account_id_router: Duration,
     /// Period to check on peer status
     #[serde(default = "default_peer_stats_period")]
     pub peer_stats_period: Duration,
+    /// Don't send edges back to peers which mostly gossip edges we already know.
+    #[serde(default)]
+    pub filter_redundant_gossip: bool,
+    /// Period between pings sent to every active peer to measure the latency used to select next
+    /// hops. `null` disables latency measurement.
+    #[serde(default = "default_latency_ping_period")]
+    pub latency_ping_period: Option<Duration>,
+    /// Number of threads verifying edge signatures.
+    #[serde(default = "default_edge_verifier_threads")]
+    pub edge_verifier_threads: usize,
+    /// Number of threads computing the routes of big routing graphs. Zero computes them in the
+    /// peer manager.
+    #[serde(default = "default_routing_threads")]
+    pub routing_threads: usize,
+    /// Whether to answer requests we can't route, so their authors don't wait for responses
+    /// that never come. Peers running older versions can't decode the answer.
+    #[serde(default)]
+    pub send_route_not_found: bool,
+    /// Maximum number of edges verified together with batch signature verification.
+    #[serde(default = "default_edge_verifier_batch_size")]
+    pub edge_verifier_batch_size: usize,
+    /// Minimum time a peer needs to stay connected before we send it all edges we know.
+    #[serde(default = "default_heavy_sync_min_connection_age")]
+    pub heavy_sync_min_connection_age: Duration,
+    /// Maximum number of peers we send all edges we know to at the same time.
+    #[serde(default = "default_max_concurrent_heavy_syncs")]
+    pub max_concurrent_heavy_syncs: usize,
+    /// Whether to exchange routing tables using IBF with peers whose protocol version supports
+    /// it, instead of sending all edges.
+    #[serde(default = "default_ibf_sync_enabled")]
+    pub ibf_sync_enabled: bool,
+    /// Peers supporting IBF sync exchange all their edges instead if either side knows fewer
+    /// edges than this.
+    #[serde(default = "default_ibf_sync_min_edges")]
+    pub ibf_sync_min_edges: u64,
+    /// Edges per second each peer may send us, excess edges are dropped. Zero disables the limit.
+    #[serde(default = "default_edge_rate_limit")]
+    pub edge_rate_limit: u64,
+    /// Edges each peer may send at once before being rate limited.
+    #[serde(default = "default_edge_rate_limit_burst")]
+    pub edge_rate_limit_burst: u64,
+    /// Whether validators keep direct connections to the other validators of the epoch.
+    #[serde(default = "default_tier1_enabled")]
+    pub tier1_enabled: bool,
+    /// IP families to listen and connect to peers with: `dual`, `v4` or `v6`.
+    #[serde(default)]
+    pub ip_family: IpFamily,
+    /// Proxy to connect to peers through, e.g.
+    /// `{"protocol": "socks5", "addr": "127.0.0.1:1080"}`. Boot nodes given by host name are
+    /// then resolved by the proxy.
+    #[serde(default)]
+    pub proxy: Option<ProxyConfig>,
+    /// Relays hiding the address of this node, each one `peer_id@ip:port`. If any, the node only
+    /// connects to them and announces that messages to it are routed through them.
+    #[serde(default)]
+    pub relays: Vec<String>,
+    /// Peers this node always tries to stay connected to, each one `peer_id@ip:port`, `peer_id`
+    /// or `ip:port`. They're redialed as soon as they disconnect and never evicted.
+    #[serde(default)]
+    pub anchor_peers: Vec<String>,
+    /// How long an anchor peer can stay disconnected before it's reported as unreachable.
+    #[serde(default = "default_anchor_unreachable_timeout")]
+    pub anchor_unreachable_timeout: Duration,
+    /// Number of peers blocks are sent to, and transactions are gossiped to when their validator
+    /// can't be reached, sampled favoring peers which reach more of the network reliably.
+    /// 0 sends blocks to every peer and never gossips transactions.
+    #[serde(default)]
+    pub gossip_fanout: u32,
+    /// Addresses to listen at in addition to `addr`, e.g.
+    /// `[{"addr": "10.0.0.1:24567", "advertise": "whitelisted"}]`. `advertise` is `public`
+    /// (default), `whitelisted` to only advertise the address to whitelisted nodes, or `hidden`.
+    /// Each peer is advertised a single address.
+    #[serde(default)]
+    pub extra_listen_addrs: Vec<ListenAddr>,
+    /// Mutual TLS with the peers whose certificate is pinned, e.g. `{"cert_file": "tls.crt",
+    /// "key_file": "tls.key", "pinned_peers": {"ed25519:...": "AB:CD:..."}}`. Connections with
+    /// other peers don't use TLS.
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    /// File mapping networks to regions, one `<network>/<prefix length> <region>` per line, e.g.
+    /// `10.0.0.0/8 eu-west`. Enables the number of reachable peers and the latency of active peers
+    /// per region in metrics.
+    #[serde(default)]
+    pub peer_regions_file: Option<String>,
+    /// File with a network sub-key delegated by the node key, written by
+    /// `neard network_sub_key`. Edges with peers supporting sub-keys are signed with it instead of
+    /// the node key.
+    #[serde(default)]
+    pub sub_key_file: Option<String>,
+    /// Maximum number of inbound peers from the same /24 IPv4 or /48 IPv6 subnet. Zero disables
+    /// the limit. Peers connecting from loopback addresses aren't limited.
+    #[serde(default = "default_max_inbound_peers_per_subnet")]
+    pub max_inbound_peers_per_subnet: u32,
+    /// Number of the healthiest peers saved on disk and dialed first on startup, before the boot
+    /// nodes. Zero disables the cache.
+    #[serde(default = "default_cold_start_peers")]
+    pub cold_start_peers: usize,
+    /// How long to wait for a connection to a cached peer before falling back to the boot nodes.
+    #[serde(default = "default_cold_start_timeout")]
+    pub cold_start_timeout: Duration,
+    /// Map the listening port on the NAT gateway with NAT-PMP or UPnP at startup, and advertise
+    /// the external address to peers. For nodes behind a home router.
+    #[serde(default)]
+    pub nat_port_mapping: bool,
+    /// Names whose DNS records advertise boot nodes, e.g. `["seed.example.org"]`. Each TXT record
+    /// of a name is a peer in the format of `boot_nodes`, each SRV record of `_near._tcp.<name>`
+    /// the host and port of a peer whose id is the TXT record of the host. Discovered peers are
+    /// added to the known peers.
+    #[serde(default)]
+    pub dns_seeds: Vec<String>,
+    /// How often DNS seeds are resolved again.
+    #[serde(default = "default_dns_seeds_period")]
+    pub dns_seeds_period: Duration,
+    /// Upload and download limits in bytes per second, e.g.
+    /// `{"upload_bytes_per_sec": 10000000}`. Keys are `peer_upload_bytes_per_sec`,
+    /// `peer_download_bytes_per_sec`, `upload_bytes_per_sec` and `download_bytes_per_sec`; zero
+    /// or missing disables a limit. For nodes on metered links.
+    #[serde(default)]
+    pub bandwidth: BandwidthConfig,
+    /// Bounded broadcast of new edges, e.g. `{"fanout": 8, "broadcast_budget": 4}`. Peers which
+    /// don't get an edge pushed pull it after the next digest, sent every `digest_interval`. Zero
+    /// or missing fanout and budget push every edge to every peer.
+    #[serde(default)]
+    pub edge_gossip: EdgeGossipConfig,
+    /// Messages queued for each peer while its connection is congested, e.g.
+    /// `{"bulk_limit": 200, "bulk_drop_policy": "drop_newest"}`. Peers whose queue stays full for
+    /// `max_saturation` are disconnected.
+    #[serde(default)]
+    pub send_queue: SendQueueConfig,
+    /// Compression of messages larger than `threshold_bytes` to peers which enabled it too, e.g.
+    /// `{"enabled": true, "algorithm": "zstd", "zstd_level": 3}`. Algorithms are `lz4` and
+    /// `zstd`.
+    #[serde(default)]
+    pub compression: CompressionConfig,
+    /// Unix sockets to stream the routing table to hot-standby nodes on the same host, e.g.
+    /// `{"publish_socket": "routing.sock"}` on the primary and
+    /// `{"primary_socket": "routing.sock"}` on the standby.
+    #[serde(default)]
+    pub routing_replica: RoutingReplicaConfig,
+    /// Maximum number of accounts we keep announcements of.
+    #[serde(default = "default_announce_account_cache_size")]
+    pub announce_account_cache_size: usize,
+    /// Maximum number of routed messages we remember the previous hop of, to route responses back.
+    /// Relay nodes routing a lot of traffic may need to raise it.
+    #[serde(default = "default_route_back_cache_size")]
+    pub route_back_cache_size: u64,
+    /// Entries of the route back cache older than this are evicted first when it's full.
+    #[serde(default = "default_route_back_cache_evict_timeout")]
+    pub route_back_cache_evict_timeout: Duration,
+    /// Number of entries evicted from the route back cache at once when it's full.
+    #[serde(default = "default_route_back_cache_remove_batch")]
+    pub route_back_cache_remove_batch: u64,
+    /// Number of entries of the route back cache each peer can hold even if many peers use it.
+    /// Peers above it and their fair share of the cache have their oldest entries evicted first.
+    #[serde(default = "default_route_back_cache_peer_quota")]
+    pub route_back_cache_peer_quota: u64,
+    /// Save the route back cache on shutdown and restore it on startup, so that responses to
+    /// state sync and view client requests in flight during a restart aren't lost.
+    #[serde(default)]
+    pub persist_route_back: bool,
+    /// Edges are only pruned if some peer is unreachable for at least this long.
+    #[serde(default = "default_save_peers_max_time")]
+    pub save_peers_max_time: Duration,
+    /// Peers unreachable for this long are removed from memory and saved to disk when pruning.
+    #[serde(default = "default_save_peers_after_time")]
+    pub save_peers_after_time: Duration,
+    /// Maximum number of unreachable peers kept in memory, the ones unreachable for the longest
+    /// time are saved to disk above it.
+    #[serde(default = "default_max_unreachable_graph_nodes")]
+    pub max_unreachable_graph_nodes: usize,
+    /// Maximum number of edges kept in memory, the peers unreachable for the longest time are
+    /// saved to disk above it. Supernodes knowing many edges may need to lower it.
+    #[serde(default = "default_max_edges_in_memory")]
+    pub max_edges_in_memory: usize,
+    /// Number of components of unreachable peers kept on disk, older ones are removed.
+    #[serde(default = "default_max_saved_components")]
+    pub max_saved_components: u64,
+    /// Announcements of accounts which traveled more hops are verified with the peer which
+    /// announced them.
+    #[serde(default = "default_max_announcement_hops")]
+    pub max_announcement_hops: u32,
+    /// Strategy used to choose the next hop of routed messages.
+    #[serde(default)]
+    pub routing_strategy: RoutingStrategy,
+    /// Strategy run alongside `routing_strategy` on canary nodes. Its decisions don't affect
+    /// routing, they are only compared with the ones of `routing_strategy`.
+    #[serde(default)]
+    pub shadow_routing_strategy: Option<RoutingStrategy>,
 }
 
 impl Default for Network {
@@ -260,8 +553,58 @@ impl Default for Network {
             skip_sync_wait: false,
             ban_window: Duration::from_secs(3 * 60 * 60),
             blacklist: vec![],
+            whitelist_nodes: vec![],
+            denylisted_key_prefixes: vec![],
             ttl_account_id_router: default_ttl_account_id_router(),
             peer_stats_period: default_peer_stats_period(),
+            filter_redundant_gossip: false,
+            latency_ping_period: default_latency_ping_period(),
+            edge_verifier_threads: default_edge_verifier_threads(),
+            routing_threads: default_routing_threads(),
+            send_route_not_found: false,
+            edge_verifier_batch_size: default_edge_verifier_batch_size(),
+            heavy_sync_min_connection_age: default_heavy_sync_min_connection_age(),
+            max_concurrent_heavy_syncs: default_max_concurrent_heavy_syncs(),
+            ibf_sync_enabled: default_ibf_sync_enabled(),
+            ibf_sync_min_edges: default_ibf_sync_min_edges(),
+            edge_rate_limit: default_edge_rate_limit(),
+            edge_rate_limit_burst: default_edge_rate_limit_burst(),
+            tier1_enabled: default_tier1_enabled(),
+            ip_family: IpFamily::default(),
+            proxy: None,
+            relays: vec![],
+            anchor_peers: vec![],
+            anchor_unreachable_timeout: default_anchor_unreachable_timeout(),
+            gossip_fanout: 0,
+            extra_listen_addrs: vec![],
+            tls: None,
+            peer_regions_file: None,
+            sub_key_file: None,
+            max_inbound_peers_per_subnet: default_max_inbound_peers_per_subnet(),
+            cold_start_peers: default_cold_start_peers(),
+            cold_start_timeout: default_cold_start_timeout(),
+            nat_port_mapping: false,
+            dns_seeds: vec![],
+            dns_seeds_period: default_dns_seeds_period(),
+            bandwidth: BandwidthConfig::default(),
+            edge_gossip: EdgeGossipConfig::default(),
+            send_queue: SendQueueConfig::default(),
+            compression: CompressionConfig::default(),
+            routing_replica: RoutingReplicaConfig::default(),
+            announce_account_cache_size: default_announce_account_cache_size(),
+            route_back_cache_size: default_route_back_cache_size(),
+            route_back_cache_evict_timeout: default_route_back_cache_evict_timeout(),
+            route_back_cache_remove_batch: default_route_back_cache_remove_batch(),
+            route_back_cache_peer_quota: default_route_back_cache_peer_quota(),
+            persist_route_back: false,
+            save_peers_max_time: default_save_peers_max_time(),
+            save_peers_after_time: default_save_peers_after_time(),
+            max_unreachable_graph_nodes: default_max_unreachable_graph_nodes(),
+            max_edges_in_memory: default_max_edges_in_memory(),
+            max_saved_components: default_max_saved_components(),
+            max_announcement_hops: default_max_announcement_hops(),
+            routing_strategy: RoutingStrategy::default(),
+            shadow_routing_strategy: None,
         }
     }
 }
@@ -319,6 +662,10 @@ fn default_trie_viewer_state_size_limit() -> Option<u64> {
     Some(50_000)
 }
 
+fn default_check_announce_account_existence() -> bool {
+    true
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Consensus {
     /// Minimum number of peers to start syncing.
@@ -432,6 +779,9 @@ pub struct Config {
     /// If set, overrides value in genesis configuration.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub max_gas_burnt_view: Option<Gas>,
+    /// Drop account announcements of accounts which don't exist on chain or aren't validators.
+    #[serde(default = "default_check_announce_account_existence")]
+    pub check_announce_account_existence: bool,
 }
 
 impl Default for Config {
@@ -458,6 +808,7 @@ impl Default for Config {
             view_client_throttle_period: default_view_client_throttle_period(),
             trie_viewer_state_size_limit: default_trie_viewer_state_size_limit(),
             max_gas_burnt_view: None,
+            check_announce_account_existence: default_check_announce_account_existence(),
         }
     }
 }
@@ -622,6 +973,8 @@ impl NearConfig {
         network_key_pair: KeyFile,
         validator_signer: Option<Arc<dyn ValidatorSigner>>,
     ) -> Self {
+        let (boot_nodes, peer_hosts) =
+            parse_boot_nodes(&config.network).expect("Failed to parse PeerInfo");
         NearConfig {
             config: config.clone(),
             client_config: ClientConfig {
@@ -668,6 +1021,7 @@ impl NearConfig {
                 view_client_throttle_period: config.view_client_throttle_period,
                 trie_viewer_state_size_limit: config.trie_viewer_state_size_limit,
                 max_gas_burnt_view: config.max_gas_burnt_view,
+                check_announce_account_existence: config.check_announce_account_existence,
             },
             network_config: NetworkConfig {
                 public_key: network_key_pair.public_key,
@@ -678,16 +1032,7 @@ impl NearConfig {
                 } else {
                     Some(config.network.addr.parse().unwrap())
                 },
-                boot_nodes: if config.network.boot_nodes.is_empty() {
-                    vec![]
-                } else {
-                    config
-                        .network
-                        .boot_nodes
-                        .split(',')
-                        .map(|chunk| chunk.try_into().expect("Failed to parse PeerInfo"))
-                        .collect()
-                },
+                boot_nodes,
                 handshake_timeout: config.network.handshake_timeout,
                 reconnect_delay: config.network.reconnect_delay,
                 bootstrap_peers_period: Duration::from_secs(60),
@@ -710,8 +1055,76 @@ impl NearConfig {
                 highest_peer_horizon: HIGHEST_PEER_HORIZON,
                 push_info_period: Duration::from_millis(100),
                 blacklist: blacklist_from_iter(config.network.blacklist),
+                whitelist_nodes: config
+                    .network
+                    .whitelist_nodes
+                    .iter()
+                    .map(|node| node.parse().expect("Failed to parse whitelisted PeerInfo"))
+                    .collect(),
+                denylisted_key_prefixes: config.network.denylisted_key_prefixes,
                 outbound_disabled: false,
                 archive: config.archive,
+                filter_redundant_gossip: config.network.filter_redundant_gossip,
+                latency_ping_period: config.network.latency_ping_period,
+                edge_verifier_threads: config.network.edge_verifier_threads,
+                routing_threads: config.network.routing_threads,
+                send_route_not_found: config.network.send_route_not_found,
+                edge_verifier_batch_size: config.network.edge_verifier_batch_size,
+                heavy_sync_min_connection_age: config.network.heavy_sync_min_connection_age,
+                max_concurrent_heavy_syncs: config.network.max_concurrent_heavy_syncs,
+                ibf_sync_enabled: config.network.ibf_sync_enabled,
+                ibf_sync_min_edges: config.network.ibf_sync_min_edges,
+                edge_rate_limit: config.network.edge_rate_limit,
+                edge_rate_limit_burst: config.network.edge_rate_limit_burst,
+                tier1_enabled: config.network.tier1_enabled,
+                ip_family: config.network.ip_family,
+                proxy: config.network.proxy,
+                relays: config
+                    .network
+                    .relays
+                    .iter()
+                    .map(|relay| relay.parse().expect("Failed to parse relay PeerInfo"))
+                    .collect(),
+                anchor_peers: config
+                    .network
+                    .anchor_peers
+                    .iter()
+                    .map(|anchor| anchor.parse().expect("Failed to parse anchor peer"))
+                    .collect(),
+                anchor_unreachable_timeout: config.network.anchor_unreachable_timeout,
+                gossip_fanout: config.network.gossip_fanout,
+                extra_listen_addrs: config.network.extra_listen_addrs,
+                peer_hosts,
+                tls: config.network.tls,
+                peer_regions_file: config.network.peer_regions_file.map(PathBuf::from),
+                max_inbound_peers_per_subnet: config.network.max_inbound_peers_per_subnet,
+                cold_start_peers: config.network.cold_start_peers,
+                cold_start_timeout: config.network.cold_start_timeout,
+                nat_port_mapping: config.network.nat_port_mapping,
+                dns_seeds: config.network.dns_seeds,
+                dns_seeds_period: config.network.dns_seeds_period,
+                bandwidth: config.network.bandwidth,
+                edge_gossip: config.network.edge_gossip,
+                send_queue: config.network.send_queue,
+                compression: config.network.compression,
+                routing_replica: config.network.routing_replica,
+                routing_table: RoutingTableConfig {
+                    announce_account_cache_size: config.network.announce_account_cache_size,
+                    route_back_cache_size: config.network.route_back_cache_size,
+                    route_back_cache_evict_timeout: config.network.route_back_cache_evict_timeout,
+                    route_back_cache_remove_batch: config.network.route_back_cache_remove_batch,
+                    route_back_cache_peer_quota: config.network.route_back_cache_peer_quota,
+                    persist_route_back: config.network.persist_route_back,
+                    save_peers_max_time: config.network.save_peers_max_time,
+                    save_peers_after_time: config.network.save_peers_after_time,
+                    max_unreachable_graph_nodes: config.network.max_unreachable_graph_nodes,
+                    max_edges_in_memory: config.network.max_edges_in_memory,
+                    max_saved_components: config.network.max_saved_components,
+                    max_announcement_hops: config.network.max_announcement_hops,
+                    strategy: config.network.routing_strategy,
+                    shadow_strategy: config.network.shadow_routing_strategy,
+                },
+                sub_key: None,
             },
             telemetry_config: config.telemetry,
             #[cfg(feature = "json_rpc")]
@@ -1159,6 +1572,75 @@ impl From<NodeKeyFile> for KeyFile {
     }
 }
 
+/// Boot nodes of the network section of the config file, and the host names of the ones
+/// connected to through the proxy.
+fn parse_boot_nodes(
+    network: &Network,
+) -> Result<(Vec<PeerInfo>, HashMap<PeerId, String>), Box<dyn std::error::Error>> {
+    // Boot nodes given by host name are resolved to the family we listen on.
+    let prefer_v6 =
+        network.addr.parse::<std::net::SocketAddr>().map_or(false, |addr| addr.is_ipv6());
+    let mut peer_hosts = HashMap::new();
+    let mut boot_nodes = vec![];
+    for chunk in network.boot_nodes.split(',').filter(|chunk| !chunk.is_empty()) {
+        let peer_info = if network.proxy.is_none() {
+            PeerInfo::resolve(chunk, network.ip_family, prefer_v6)?
+        } else {
+            // Host names are resolved by the proxy.
+            let (peer_info, host) = PeerInfo::parse_with_host(chunk)?;
+            if let Some(host) = host {
+                peer_hosts.insert(peer_info.id.clone(), host);
+            }
+            peer_info
+        };
+        if !peer_info.addr.map_or(true, |addr| network.ip_family.allows(&addr)) {
+            warn!(target: "near", "Ignoring boot node {}, address family not allowed", peer_info);
+            continue;
+        }
+        boot_nodes.push(peer_info);
+    }
+    Ok((boot_nodes, peer_hosts))
+}
+
+/// Settings of the network section of the config file in `dir` which can be changed while the
+/// node is running. Unlike `load_config`, invalid settings are returned as errors.
+pub fn load_reloadable_network_config(dir: &Path) -> Result<ReloadableNetworkConfig, String> {
+    let path = dir.join(CONFIG_FILENAME);
+    let content = fs::read_to_string(&path)
+        .map_err(|err| format!("Failed to read {}: {}", path.display(), err))?;
+    let config: Config = serde_json::from_str(&content)
+        .map_err(|err| format!("Failed to parse {}: {}", path.display(), err))?;
+    let network = config.network;
+    let (boot_nodes, peer_hosts) =
+        parse_boot_nodes(&network).map_err(|err| format!("Failed to parse boot nodes: {}", err))?;
+    let whitelist_nodes = network
+        .whitelist_nodes
+        .iter()
+        .map(|node| {
+            node.parse()
+                .map_err(|err| format!("Failed to parse whitelisted node {}: {}", node, err))
+        })
+        .collect::<Result<_, _>>()?;
+    let anchor_peers = network
+        .anchor_peers
+        .iter()
+        .map(|anchor| {
+            anchor.parse().map_err(|err| format!("Failed to parse anchor peer {}: {}", anchor, err))
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(ReloadableNetworkConfig {
+        boot_nodes,
+        peer_hosts,
+        max_num_peers: network.max_num_peers,
+        whitelist_nodes,
+        blacklist: blacklist_from_iter(network.blacklist),
+        edge_rate_limit: network.edge_rate_limit,
+        edge_rate_limit_burst: network.edge_rate_limit_burst,
+        bandwidth: network.bandwidth,
+        anchor_peers,
+    })
+}
+
 pub fn load_config_without_genesis_records(dir: &Path) -> NearConfig {
     let config = Config::from_file(&dir.join(CONFIG_FILENAME));
     let genesis_config = GenesisConfig::from_file(&dir.join(&config.genesis_file));
@@ -1176,12 +1658,42 @@ pub fn load_config_without_genesis_records(dir: &Path) -> NearConfig {
         None
     };
     let network_signer = NodeKeyFile::from_file(&dir.join(&config.node_key_file));
-    NearConfig::new(
+    let sub_key = config.network.sub_key_file.as_ref().map(|file| {
+        let path = dir.join(file);
+        let content = fs::read_to_string(&path).unwrap_or_else(|err| {
+            panic!("Failed to read network sub-key {}: {}", path.display(), err)
+        });
+        serde_json::from_str::<NetworkSubKey>(&content).unwrap_or_else(|err| {
+            panic!("Failed to parse network sub-key {}: {}", path.display(), err)
+        })
+    });
+    let mut near_config = NearConfig::new(
         config,
         Genesis::new_with_path(genesis_config, genesis_records_file),
         network_signer.into(),
         validator_signer,
-    )
+    );
+    near_config.network_config.sub_key = sub_key;
+    near_config
+}
+
+/// Generate a network sub-key delegated by the node key in `dir`, and write it to `sub_key_file`
+/// in `dir`, readable only by the owner like key files.
+pub fn init_network_sub_key(dir: &Path, sub_key_file: &str) -> NetworkSubKey {
+    use std::os::unix::fs::PermissionsExt;
+
+    let config = Config::from_file(&dir.join(CONFIG_FILENAME));
+    let node_key: KeyFile = NodeKeyFile::from_file(&dir.join(&config.node_key_file)).into();
+    let sub_key = NetworkSubKey::generate(&node_key.secret_key);
+    let path = dir.join(sub_key_file);
+    let mut file = File::create(&path).expect("Failed to create the network sub-key file");
+    let mut perm =
+        file.metadata().expect("Failed to retrieve network sub-key file metadata").permissions();
+    perm.set_mode(0o600);
+    file.set_permissions(perm).expect("Failed to set permissions of the network sub-key file");
+    let content = serde_json::to_string_pretty(&sub_key).expect("Failed to serialize sub-key");
+    file.write_all(content.as_bytes()).expect("Failed to write the network sub-key file");
+    sub_key
 }
 
 pub fn load_config(dir: &Path) -> NearConfig {
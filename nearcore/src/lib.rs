@@ -13,6 +13,7 @@ use near_chain::ChainGenesis;
 #[cfg(feature = "test_features")]
 use near_client::AdversarialControls;
 use near_client::{start_client, start_view_client, ClientActor, ViewClientActor};
+use near_network::types::ReloadNetworkConfig;
 use near_network::{NetworkRecipient, PeerManagerActor};
 #[cfg(feature = "rosetta_rpc")]
 use near_rosetta_rpc::start_rosetta_rpc;
@@ -28,10 +29,13 @@ use near_store::migrations::{migrate_20_to_21, migrate_26_to_27};
 use near_store::{create_store, Store};
 use near_telemetry::TelemetryActor;
 
-pub use crate::config::{init_configs, load_config, load_test_config, NearConfig, NEAR_BASE};
+pub use crate::config::{
+    init_configs, init_network_sub_key, load_config, load_reloadable_network_config,
+    load_test_config, NearConfig, NEAR_BASE,
+};
 use crate::migrations::{
     migrate_12_to_13, migrate_18_to_19, migrate_19_to_20, migrate_22_to_23, migrate_23_to_24,
-    migrate_24_to_25,
+    migrate_24_to_25, migrate_32_to_33,
 };
 pub use crate::runtime::NightshadeRuntime;
 pub use crate::shard_tracker::TrackedConfig;
@@ -244,6 +248,45 @@ pub fn apply_store_migrations(path: &Path, near_config: &NearConfig) {
         let store = create_store(&path);
         set_store_version(&store, 28);
     }
+    if db_version <= 28 {
+        // version 28 => 29: add ColEdgeStats
+        // Does not need to do anything since open db with option `create_missing_column_families`
+        info!(target: "near", "Migrate DB from version 28 to 29");
+        let store = create_store(&path);
+        set_store_version(&store, 29);
+    }
+    if db_version <= 29 {
+        // version 29 => 30: add ColPeerCache
+        // Does not need to do anything since open db with option `create_missing_column_families`
+        info!(target: "near", "Migrate DB from version 29 to 30");
+        let store = create_store(&path);
+        set_store_version(&store, 30);
+    }
+    if db_version <= 30 {
+        // version 30 => 31: add ColRouteBack
+        // Does not need to do anything since open db with option `create_missing_column_families`
+        info!(target: "near", "Migrate DB from version 30 to 31");
+        let store = create_store(&path);
+        set_store_version(&store, 31);
+    }
+    if db_version <= 31 {
+        // version 31 => 32: add ColPeerQuality
+        // Does not need to do anything since open db with option `create_missing_column_families`
+        info!(target: "near", "Migrate DB from version 31 to 32");
+        let store = create_store(&path);
+        set_store_version(&store, 32);
+    }
+    if db_version <= 32 {
+        info!(target: "near", "Migrate DB from version 32 to 33");
+        migrate_32_to_33(&path, near_config);
+    }
+    if db_version <= 33 {
+        // version 33 => 34: add ColRoutingAuditLog
+        // Does not need to do anything since open db with option `create_missing_column_families`
+        info!(target: "near", "Migrate DB from version 33 to 34");
+        let store = create_store(&path);
+        set_store_version(&store, 34);
+    }
     #[cfg(feature = "nightly_protocol")]
     {
         let store = create_store(&path);
@@ -280,11 +323,15 @@ pub fn init_and_migrate_store(home_dir: &Path, near_config: &NearConfig) -> Arc<
 pub struct NearNode {
     pub client: Addr<ClientActor>,
     pub view_client: Addr<ViewClientActor>,
+    pub network: Addr<PeerManagerActor>,
     pub arbiters: Vec<ArbiterHandle>,
     pub rpc_servers: Vec<(&'static str, actix_web::dev::Server)>,
 }
 
 pub fn start_with_config(home_dir: &Path, config: NearConfig) -> NearNode {
+    if let Err(err) = config.network_config.verify() {
+        panic!("Invalid network config: {}", err);
+    }
     let store = init_and_migrate_store(home_dir, &config);
 
     let runtime = Arc::new(NightshadeRuntime::with_config(
@@ -342,7 +389,6 @@ pub fn start_with_config(home_dir: &Path, config: NearConfig) -> NearNode {
     let arbiter = Arbiter::new();
     let client_actor1 = client_actor.clone().recipient();
     let view_client1 = view_client.clone().recipient();
-    config.network_config.verify();
     let network_config = config.network_config;
     let ibf_routing_pool = make_ibf_routing_pool();
     #[cfg(all(feature = "json_rpc", feature = "test_features"))]
@@ -359,7 +405,6 @@ pub fn start_with_config(home_dir: &Path, config: NearConfig) -> NearNode {
             config.genesis.config.clone(),
             client_actor.clone(),
             view_client.clone(),
-            #[cfg(feature = "test_features")]
             network_actor.clone(),
             #[cfg(feature = "test_features")]
             ibf_routing_pool2,
@@ -379,7 +424,7 @@ pub fn start_with_config(home_dir: &Path, config: NearConfig) -> NearNode {
         ));
     }
 
-    network_adapter.set_recipient(network_actor.recipient());
+    network_adapter.set_recipient(network_actor.clone().recipient());
 
     rpc_servers.shrink_to_fit();
 
@@ -392,7 +437,25 @@ pub fn start_with_config(home_dir: &Path, config: NearConfig) -> NearNode {
     NearNode {
         client: client_actor,
         view_client,
+        network: network_actor,
         rpc_servers,
         arbiters: vec![client_arbiter_handle, arbiter.handle()],
     }
 }
+
+/// Re-read the network section of the config file in `home_dir` and apply the settings which can
+/// be changed while the node is running. Invalid settings are logged and ignored.
+pub async fn reload_network_config(home_dir: &Path, network: &Addr<PeerManagerActor>) {
+    let config = match load_reloadable_network_config(home_dir) {
+        Ok(config) => config,
+        Err(err) => {
+            error!(target: "near", "Not reloading network config: {}", err);
+            return;
+        }
+    };
+    match network.send(ReloadNetworkConfig { config }).await {
+        Ok(Ok(changed)) => info!(target: "near", "Reloaded network config, changed: {:?}", changed),
+        Ok(Err(err)) => error!(target: "near", "Not reloading invalid network config: {}", err),
+        Err(err) => error!(target: "near", "Failed to reload network config: {}", err),
+    }
+}
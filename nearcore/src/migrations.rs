@@ -5,6 +5,7 @@ use near_chain::migrations::check_if_block_is_first_with_chunk_of_version;
 use near_chain::types::{ApplyTransactionResult, BlockHeaderInfo};
 use near_chain::{ChainStore, ChainStoreAccess, ChainStoreUpdate, RuntimeAdapter};
 use near_epoch_manager::{EpochManager, RewardCalculator};
+use near_network::routing::RoutingTable;
 use near_primitives::epoch_manager::{AllEpochConfig, EpochConfig};
 use near_primitives::hash::CryptoHash;
 use near_primitives::merkle::MerklePath;
@@ -22,6 +23,7 @@ use near_store::db::DBCol::ColReceipts;
 use near_store::migrations::{set_store_version, BatchedStoreUpdate};
 use near_store::{create_store, DBCol, StoreUpdate};
 use std::path::Path;
+use tracing::info;
 
 fn get_chunk(chain_store: &ChainStore, chunk_hash: ChunkHash) -> ShardChunkV1 {
     let store = chain_store.store();
@@ -478,6 +480,17 @@ pub fn migrate_24_to_25(path: &Path) {
     set_store_version(&store, 25);
 }
 
+/// Remove the components of unreachable peers saved on disk by the routing table beyond the
+/// configured number, which used to accumulate forever.
+pub fn migrate_32_to_33(path: &Path, near_config: &NearConfig) {
+    let store = create_store(path);
+    let max_saved_components = near_config.network_config.routing_table.max_saved_components;
+    let (components, peers) =
+        RoutingTable::gc_saved_components(&store, max_saved_components).unwrap();
+    info!(target: "near", "Removed {} saved network components and {} peer mappings", components, peers);
+    set_store_version(&store, 33);
+}
+
 lazy_static_include::lazy_static_include_bytes! {
     /// File with account ids and deltas that need to be applied in order to fix storage usage
     /// difference between actual and stored usage, introduced due to bug in access key deletion,
@@ -1,10 +1,16 @@
 use super::{DEFAULT_HOME, NEARD_VERSION, NEARD_VERSION_STRING, PROTOCOL_VERSION};
 use clap::{AppSettings, Clap};
 use futures::future::FutureExt;
+use near_crypto::PublicKey;
+use near_jsonrpc_primitives::types::network_info::RpcPingRequest;
+use near_network::routing_dump::{RoutingTableDump, RoutingTableDumpDiff};
+use near_network::topology_dataset::{Scrubber, TopologyDataset};
+use near_primitives::network::PeerId;
 use near_primitives::types::{Gas, NumSeats, NumShards};
 use nearcore::get_store_path;
 use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::{env, fs, io};
 use tracing::debug;
 #[cfg(feature = "test_features")]
@@ -47,6 +53,10 @@ impl NeardCmd {
             NeardSubCommand::Init(cmd) => cmd.run(&home_dir),
             NeardSubCommand::Testnet(cmd) => cmd.run(&home_dir),
             NeardSubCommand::Run(cmd) => cmd.run(&home_dir),
+            NeardSubCommand::RoutingDiff(cmd) => cmd.run(),
+            NeardSubCommand::TopologyDataset(cmd) => cmd.run(),
+            NeardSubCommand::Ping(cmd) => cmd.run(),
+            NeardSubCommand::NetworkSubKey(cmd) => cmd.run(&home_dir),
 
             NeardSubCommand::UnsafeResetData => {
                 let store_path = get_store_path(&home_dir);
@@ -98,6 +108,19 @@ pub(super) enum NeardSubCommand {
     /// config)
     #[clap(name = "unsafe_reset_data")]
     UnsafeResetData,
+    /// Compares routing table dumps of two nodes (written by `adv_dump_routing_table`)
+    #[clap(name = "routing_diff")]
+    RoutingDiff(RoutingDiffCmd),
+    /// Converts a routing table dump into a topology dataset which can be shared publicly: peer
+    /// ids are pseudonymized and nonces dropped
+    #[clap(name = "topology_dataset")]
+    TopologyDataset(TopologyDatasetCmd),
+    /// Pings an account or peer through the routed network of a running node
+    #[clap(name = "ping")]
+    Ping(PingCmd),
+    /// Generates a network sub-key delegated by the node key, to sign edges without the node key
+    #[clap(name = "network_sub_key")]
+    NetworkSubKey(NetworkSubKeyCmd),
 }
 
 #[derive(Clap)]
@@ -276,16 +299,25 @@ impl RunCmd {
 
         let sys = actix::System::new();
         sys.block_on(async move {
-            let nearcore::NearNode { rpc_servers, .. } =
+            let nearcore::NearNode { rpc_servers, network, .. } =
                 nearcore::start_with_config(home_dir, near_config);
 
             let sig = if cfg!(unix) {
                 use tokio::signal::unix::{signal, SignalKind};
                 let mut sigint = signal(SignalKind::interrupt()).unwrap();
                 let mut sigterm = signal(SignalKind::terminate()).unwrap();
-                futures::select! {
-                    _ = sigint .recv().fuse() => "SIGINT",
-                    _ = sigterm.recv().fuse() => "SIGTERM"
+                let mut sighup = signal(SignalKind::hangup()).unwrap();
+                loop {
+                    let sig = futures::select! {
+                        _ = sigint .recv().fuse() => "SIGINT",
+                        _ = sigterm.recv().fuse() => "SIGTERM",
+                        _ = sighup .recv().fuse() => "SIGHUP"
+                    };
+                    if sig != "SIGHUP" {
+                        break sig;
+                    }
+                    info!(target: "neard", "Got SIGHUP, reloading network config");
+                    nearcore::reload_network_config(home_dir, &network).await;
                 }
             } else {
                 tokio::signal::ctrl_c().await.unwrap();
@@ -378,6 +410,136 @@ fn init_logging(verbose: Option<&str>) {
         .init();
 }
 
+#[derive(Clap)]
+pub(super) struct RoutingDiffCmd {
+    /// Routing table dump of the first node.
+    #[clap(parse(from_os_str))]
+    a: PathBuf,
+    /// Routing table dump of the second node.
+    #[clap(parse(from_os_str))]
+    b: PathBuf,
+}
+
+impl RoutingDiffCmd {
+    pub(super) fn run(self) {
+        let read = |path: &Path| {
+            RoutingTableDump::read_from_file(path).unwrap_or_else(|err| {
+                panic!("Failed to read routing table dump {}: {}", path.display(), err)
+            })
+        };
+        let (a, b) = (read(&self.a), read(&self.b));
+        println!("A: {} ({})", a.peer_id, self.a.display());
+        println!("B: {} ({})", b.peer_id, self.b.display());
+        print!("{}", RoutingTableDumpDiff::new(&a, &b));
+    }
+}
+
+#[derive(Clap)]
+pub(super) struct TopologyDatasetCmd {
+    /// Routing table dump (written by `adv_dump_routing_table`).
+    #[clap(parse(from_os_str))]
+    dump: PathBuf,
+    /// File with the secret key of the pseudonyms. Use the same key on all nodes whose datasets
+    /// should be joined, and don't share it.
+    #[clap(long, parse(from_os_str))]
+    key_file: PathBuf,
+    /// Where to write the dataset, as JSON.
+    #[clap(long, parse(from_os_str))]
+    output: PathBuf,
+}
+
+impl TopologyDatasetCmd {
+    pub(super) fn run(self) {
+        let dump = RoutingTableDump::read_from_file(&self.dump).unwrap_or_else(|err| {
+            panic!("Failed to read routing table dump {}: {}", self.dump.display(), err)
+        });
+        let key = fs::read(&self.key_file).unwrap_or_else(|err| {
+            panic!("Failed to read key file {}: {}", self.key_file.display(), err)
+        });
+        assert!(!key.is_empty(), "Key file {} is empty", self.key_file.display());
+        let dataset = TopologyDataset::new(&dump, &Scrubber::new(&key));
+        let data = serde_json::to_vec_pretty(&dataset).expect("Failed to serialize the dataset");
+        fs::write(&self.output, data).unwrap_or_else(|err| {
+            panic!("Failed to write dataset {}: {}", self.output.display(), err)
+        });
+        println!(
+            "Wrote {} peers and {} edges to {}",
+            dataset.degrees.len(),
+            dataset.edges.len(),
+            self.output.display()
+        );
+    }
+}
+
+#[derive(Clap)]
+pub(super) struct PingCmd {
+    /// Account or peer id (`ed25519:...`) to ping. Pings to an account are sent to the peer which
+    /// announced it.
+    target: String,
+    /// JSON RPC address of the node sending the pings. It must have `enable_admin_methods` set in
+    /// the `rpc` section of its config.json.
+    #[clap(long, default_value = "http://localhost:3030")]
+    rpc_addr: String,
+    /// Number of pings to send.
+    #[clap(long, default_value = "10")]
+    count: usize,
+    /// Milliseconds between consecutive pings.
+    #[clap(long, default_value = "1000")]
+    interval_ms: u64,
+    /// Milliseconds to wait for pongs after the last ping is sent.
+    #[clap(long, default_value = "2000")]
+    timeout_ms: u64,
+}
+
+impl PingCmd {
+    pub(super) fn run(self) {
+        let (account_id, peer_id) = match PublicKey::from_str(&self.target) {
+            Ok(public_key) => (None, Some(PeerId::new(public_key))),
+            Err(_) => {
+                let account_id = self.target.parse().unwrap_or_else(|err| {
+                    panic!("{} is neither an account nor a peer id: {}", self.target, err)
+                });
+                (Some(account_id), None)
+            }
+        };
+        let request = RpcPingRequest {
+            account_id,
+            peer_id,
+            count: self.count,
+            interval_ms: self.interval_ms,
+            timeout_ms: self.timeout_ms,
+        };
+        let rpc_addr = self.rpc_addr;
+        let response = actix::System::new()
+            .block_on(async move {
+                near_jsonrpc_client::new_client(&rpc_addr).EXPERIMENTAL_ping(request).await
+            })
+            .unwrap_or_else(|err| panic!("Failed to ping {}: {}", self.target, err));
+        println!("PING {} ({})", self.target, response.peer_id);
+        print!("{}", response);
+    }
+}
+
+#[derive(Clap)]
+pub(super) struct NetworkSubKeyCmd {
+    /// File in the home directory the sub-key is written to.
+    #[clap(long, default_value = "network_sub_key.json")]
+    file: String,
+}
+
+impl NetworkSubKeyCmd {
+    pub(super) fn run(self, home_dir: &Path) {
+        let sub_key = nearcore::init_network_sub_key(home_dir, &self.file);
+        println!(
+            "Wrote network sub-key {} delegated by {} to {}",
+            sub_key.delegation.sub_key,
+            sub_key.delegation.peer_id,
+            home_dir.join(&self.file).display()
+        );
+        println!("Set \"sub_key_file\": \"{}\" in the network section of config.json", self.file);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;